@@ -17,7 +17,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         loop {
             rudp2.tick().await;
             
-            if let Some(received) = rudp2.recv().await {
+            if let Some(received) = rudp2.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         println!("Server received from {}: {:?}", 
@@ -68,7 +68,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         for _ in 0..100 { // Wait up to 100ms
             rudp1.tick().await;
             
-            if let Some(received) = rudp1.recv().await {
+            if let Some(received) = rudp1.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         println!("Client received from {}: {:?}", 
@@ -10,7 +10,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut sender = Rudpbase::new("127.0.0.1:9001".parse().unwrap()).await?;
     let mut receiver = Rudpbase::new("127.0.0.1:9002".parse().unwrap()).await?;
 
-    let sender_addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
     let receiver_addr: SocketAddr = "127.0.0.1:9002".parse().unwrap();
 
     // 显示初始内存池状态
@@ -47,7 +46,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let received_data = tokio::spawn(async move {
         loop {
             receiver.tick().await;
-            if let Some(received) = receiver.recv().await {
+            if let Some(received) = receiver.recv_timeout(Duration::from_millis(1)).await {
                 return received;
             }
             sleep(Duration::from_millis(10)).await;
@@ -19,7 +19,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         loop {
             rudp2.tick().await;
             
-            if let Some(received) = rudp2.recv().await {
+            if let Some(received) = rudp2.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         received_count += 1;
@@ -87,7 +87,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     while response_count < 1000 && response_start.elapsed() < Duration::from_secs(10) {
         rudp1.tick().await;
         
-        if let Some(received) = rudp1.recv().await {
+        if let Some(received) = rudp1.recv_timeout(Duration::from_millis(1)).await {
             match received.result {
                 Ok(_buffer) => {
                     response_count += 1;
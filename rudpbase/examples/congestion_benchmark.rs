@@ -0,0 +1,40 @@
+//! Compare congestion control configurations on a simulated bottleneck link
+//!
+//! Runs two competing flows per configuration through a drop-tail and a RED queue,
+//! on a virtual clock, and prints throughput, utilization and fairness.
+
+use rudpbase::testing::{Bottleneck, BottleneckScenario, Flow};
+use rudpbase::{RudpConfig, RudpError};
+use std::time::Duration;
+
+fn main() -> Result<(), RudpError> {
+    let configs = [
+        ("default", RudpConfig::default()),
+        ("initial_cwnd 4", RudpConfig::default().with_initial_cwnd(4)),
+        ("max_cwnd 32", RudpConfig::default().with_max_cwnd(32)),
+    ];
+    let link = Bottleneck::new(1_000_000, 64 * 1024, Duration::from_millis(20));
+    let queues = [("drop-tail", link), ("red", link.with_red())];
+
+    println!("{:<16} {:<10} {:>12} {:>12} {:>9} {:>8}", "config", "queue", "throughput", "retransmits", "utilized", "fairness");
+    for (name, config) in &configs {
+        for (queue, bottleneck) in queues {
+            let outcome = BottleneckScenario::new(bottleneck)
+                .flow(Flow::new(config.clone()))
+                .flow(Flow::new(config.clone()).starting_at(Duration::from_secs(1)))
+                .duration(Duration::from_secs(30))
+                .run()?;
+            let retransmissions: u64 = outcome.flows.iter().filter_map(|flow| flow.stats.as_ref()).map(|stats| stats.retransmissions).sum();
+            println!(
+                "{:<16} {:<10} {:>10.0}/s {:>12} {:>8.1}% {:>8.3}",
+                name,
+                queue,
+                outcome.throughput(),
+                retransmissions,
+                outcome.utilization() * 100.0,
+                outcome.fairness()
+            );
+        }
+    }
+    Ok(())
+}
@@ -9,9 +9,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // 创建两个Rudpbase实例
     let mut rudp1 = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
-    let mut rudp2 = Rudpbase::new("127.0.0.1:8081".parse().unwrap()).await?;
+    let rudp2 = Rudpbase::new("127.0.0.1:8081".parse().unwrap()).await?;
 
-    let addr1: SocketAddr = "127.0.0.1:8080".parse().unwrap();
     let addr2: SocketAddr = "127.0.0.1:8081".parse().unwrap();
 
     println!("🚀 开始拥塞控制测试...\n");
@@ -20,7 +19,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut rudp2_clone = rudp2;
     let receiver_task = tokio::spawn(async move {
         loop {
-            if let Some(received) = rudp2_clone.recv().await {
+            if let Some(received) = rudp2_clone.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         let data = &buffer.data()[..buffer.data_len()];
@@ -53,8 +52,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         buffer.data_mut()[..data.len()].copy_from_slice(data);
         buffer.set_data_len(data.len())?;
 
-        match rudp1.send(buffer, addr2).await {
-            Ok(()) => {
+        match rudp1.try_send(buffer, addr2).await {
+            Ok(_) => {
                 sent_count += 1;
                 println!("✅ 发送成功 #{}: {}", i, message);
             }
@@ -73,8 +72,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 buffer.data_mut()[..data.len()].copy_from_slice(data);
                 buffer.set_data_len(data.len())?;
                 
-                match rudp1.send(buffer, addr2).await {
-                    Ok(()) => {
+                match rudp1.try_send(buffer, addr2).await {
+                    Ok(_) => {
                         sent_count += 1;
                         println!("✅ 重试成功 #{}: {}", i, message);
                     }
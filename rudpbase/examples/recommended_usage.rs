@@ -1,3 +1,4 @@
+use rudpbase::report::StatsJsonWriter;
 use rudpbase::Rudpbase;
 use std::net::SocketAddr;
 use tokio::time::{sleep, Duration};
@@ -12,6 +13,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut rudp1 = Rudpbase::new(addr1).await?;
     let mut rudp2 = Rudpbase::new(addr2).await?;
 
+    // Print connection statistics as JSON once a second
+    rudp1.enable_stats_reporting(Duration::from_secs(1), StatsJsonWriter::new(std::io::stdout()));
+
     // Server task (rudp2)
     tokio::spawn(async move {
         let mut message_count = 0;
@@ -19,7 +23,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         loop {
             rudp2.tick().await;
             
-            if let Some(received) = rudp2.recv().await {
+            if let Some(received) = rudp2.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         message_count += 1;
@@ -75,7 +79,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         for _ in 0..200 { // Wait up to 200ms
             rudp1.tick().await;
             
-            if let Some(received) = rudp1.recv().await {
+            if let Some(received) = rudp1.recv_timeout(Duration::from_millis(1)).await {
                 match received.result {
                     Ok(buffer) => {
                         println!("Client received ACK from {}: {:?}", 
@@ -97,12 +101,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Client: No ACK received for message {}", i);
         }
         
-        // Print connection statistics
-        if let Some(stats) = rudp1.get_stats(addr2) {
-            println!("Connection stats: sent={}, received={}, lost={}, retransmissions={}", 
-                stats.packets_sent, stats.packets_received, stats.packets_lost, stats.retransmissions);
-        }
-        
         sleep(Duration::from_millis(300)).await;
     }
 
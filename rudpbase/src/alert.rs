@@ -0,0 +1,138 @@
+//! Threshold alerts on per-peer statistics
+//!
+//! [`AlertMonitor`] samples the counters of each peer once per `AlertConfig::interval`
+//! and compares the loss rate and retransmit ratio over the interval, and the current
+//! smoothed RTT, with the configured thresholds. Only changes are reported: an alert
+//! is raised when its value rises above the threshold and cleared when it falls back.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::AlertConfig;
+use crate::events::{Alert, AlertKind};
+use crate::stats::ConnectionStats;
+
+const KINDS: [AlertKind; 3] = [AlertKind::LossRate, AlertKind::Rtt, AlertKind::RetransmitRatio];
+
+/// Counters of one peer at the start of the current interval, and its raised alerts
+#[derive(Debug)]
+struct PeerAlerts {
+    next_check: Instant,
+    packets_sent: u64,
+    packets_lost: u64,
+    retransmissions: u64,
+    /// Indexed like `KINDS`
+    raised: [bool; 3],
+}
+
+/// Alert state of every peer
+#[derive(Debug, Default)]
+pub(crate) struct AlertMonitor {
+    peers: HashMap<SocketAddr, PeerAlerts>,
+}
+
+impl AlertMonitor {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the thresholds of `addr` if its interval is over, passing each alert that
+    /// was raised or cleared to `emit`; `srtt` is `None` before the first RTT sample
+    pub(crate) fn check(
+        &mut self,
+        config: &AlertConfig,
+        addr: SocketAddr,
+        stats: &ConnectionStats,
+        srtt: Option<Duration>,
+        now: Instant,
+        mut emit: impl FnMut(Alert),
+    ) {
+        // The first interval counts everything since the peer's state was created
+        let peer = self.peers.entry(addr).or_insert_with(|| PeerAlerts {
+            next_check: now + config.interval,
+            packets_sent: 0,
+            packets_lost: 0,
+            retransmissions: 0,
+            raised: [false; 3],
+        });
+        if now < peer.next_check {
+            return;
+        }
+        let sent = stats.packets_sent.saturating_sub(peer.packets_sent);
+        let rate = |count: u64| (sent > 0 && sent >= config.min_packets).then(|| count as f64 / sent as f64);
+        let values = [
+            rate(stats.packets_lost.saturating_sub(peer.packets_lost)),
+            srtt.map(|srtt| srtt.as_secs_f64() * 1000.0),
+            rate(stats.retransmissions.saturating_sub(peer.retransmissions)),
+        ];
+        let thresholds = [
+            config.loss_rate,
+            config.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            config.retransmit_ratio,
+        ];
+        peer.next_check = now + config.interval;
+        peer.packets_sent = stats.packets_sent;
+        peer.packets_lost = stats.packets_lost;
+        peer.retransmissions = stats.retransmissions;
+
+        for (index, kind) in KINDS.into_iter().enumerate() {
+            let (Some(value), Some(threshold)) = (values[index], thresholds[index]) else {
+                continue;
+            };
+            let raised = value > threshold;
+            if raised != peer.raised[index] {
+                peer.raised[index] = raised;
+                emit(Alert { kind, raised, value, threshold });
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        self.peers.remove(&addr);
+    }
+
+    pub(crate) fn move_peer(&mut self, old: SocketAddr, new: SocketAddr) {
+        if let Some(peer) = self.peers.remove(&old) {
+            self.peers.insert(new, peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_raised_and_cleared_once() {
+        let config = AlertConfig { loss_rate: Some(0.1), min_packets: 10, ..AlertConfig::default() };
+        let addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let start = Instant::now();
+        let mut monitor = AlertMonitor::new();
+        let mut stats = ConnectionStats::new();
+        let mut alerts = Vec::new();
+        let mut check = |monitor: &mut AlertMonitor, stats: &ConnectionStats, seconds: u64| {
+            monitor.check(&config, addr, stats, None, start + Duration::from_secs(seconds), |alert| alerts.push(alert));
+        };
+        check(&mut monitor, &stats, 0);
+
+        // 3 of 20 packets lost in the first interval
+        stats.packets_sent = 20;
+        stats.packets_lost = 3;
+        check(&mut monitor, &stats, 1);
+        // Still lossy, already raised
+        stats.packets_sent = 40;
+        stats.packets_lost = 6;
+        check(&mut monitor, &stats, 2);
+        // Too few packets to judge
+        stats.packets_sent = 45;
+        check(&mut monitor, &stats, 3);
+        stats.packets_sent = 65;
+        check(&mut monitor, &stats, 4);
+
+        assert_eq!(alerts, vec![
+            Alert { kind: AlertKind::LossRate, raised: true, value: 0.15, threshold: 0.1 },
+            Alert { kind: AlertKind::LossRate, raised: false, value: 0.0, threshold: 0.1 },
+        ]);
+    }
+}
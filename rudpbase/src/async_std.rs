@@ -0,0 +1,6 @@
+//! async-std adapter
+//!
+//! Enabled by the `async-std` feature. async-std drives its I/O with the `async-io`
+//! reactor, which this adapter uses directly, so no tokio runtime is needed.
+
+pub use crate::reactor::Rudpbase;
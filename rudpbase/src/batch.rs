@@ -0,0 +1,467 @@
+//! Batched datagram I/O
+//!
+//! On Linux, `recvmmsg(2)` and `sendmmsg(2)` move a whole batch of datagrams per
+//! system call. Other platforms fall back to draining the socket with non-blocking
+//! `try_recv_from`/`try_send_to` calls, which still saves a wake-up per datagram.
+//!
+//! With UDP offload enabled, runs of equally sized datagrams to the same peer are
+//! handed to the kernel as one buffer (`UDP_SEGMENT`, GSO), and the kernel may
+//! deliver several received datagrams coalesced in one buffer (`UDP_GRO`).
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+use crate::buffer_pool::DEFAULT_BUFFER_SIZE;
+
+/// Receive buffer size per slot when GRO may coalesce datagrams
+const GRO_BUFFER_SIZE: usize = u16::MAX as usize;
+
+/// Maximum datagrams per GSO buffer (`UDP_MAX_SEGMENTS` in the kernel)
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Maximum total payload of one GSO buffer
+const MAX_GSO_PAYLOAD: usize = 65507;
+
+/// Segmentation/receive offloads accepted by the kernel for a socket
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct UdpOffload {
+    pub(crate) gso: bool,
+    pub(crate) gro: bool,
+}
+
+impl UdpOffload {
+    /// Enable or disable offloads on the socket, keeping whatever the kernel supports
+    pub(crate) fn configure(socket: &UdpSocket, enabled: bool) -> Self {
+        Self {
+            gso: enabled && sys::supports_gso(socket),
+            gro: sys::set_gro(socket, enabled) && enabled,
+        }
+    }
+}
+
+/// Receive buffers for one batch of datagrams
+#[derive(Default)]
+pub(crate) struct RecvBatch {
+    buffer: Vec<u8>,
+    slot_size: usize,
+    /// (offset, length, source) of every datagram filled in by the last `recv`
+    received: Vec<(usize, usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    pub(crate) fn new(size: usize, gro: bool) -> Self {
+        let slot_size = if gro { GRO_BUFFER_SIZE } else { DEFAULT_BUFFER_SIZE };
+        Self {
+            buffer: vec![0u8; size * slot_size],
+            slot_size,
+            received: Vec::with_capacity(size),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.buffer.len().checked_div(self.slot_size).unwrap_or(0)
+    }
+
+    /// Whether the slots are large enough for GRO-coalesced buffers
+    pub(crate) fn gro(&self) -> bool {
+        self.slot_size == GRO_BUFFER_SIZE
+    }
+
+    /// Datagrams filled in by the last `recv` call
+    pub(crate) fn datagrams(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .map(|&(offset, len, from)| (&self.buffer[offset..offset + len], from))
+    }
+
+    /// Wait until the socket is readable, then receive up to `capacity()` buffers
+    pub(crate) async fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        loop {
+            socket.readable().await?;
+            match self.try_recv(socket) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Receive up to `capacity()` buffers without waiting
+    ///
+    /// Returns `WouldBlock` if nothing is currently readable.
+    pub(crate) fn try_recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.received.clear();
+        sys::try_recv_batch(socket, &mut self.buffer, self.slot_size, &mut self.received)?;
+        Ok(self.received.len())
+    }
+}
+
+/// Send all datagrams, using as few system calls as the platform allows
+///
+/// A datagram that fails to send is skipped so it cannot hold back the rest of the
+/// batch; the first such error is returned once everything else has been sent. If
+/// the kernel rejects a GSO buffer, GSO is turned off for the socket and the
+/// datagrams are resent individually.
+pub(crate) async fn send_batch(
+    socket: &UdpSocket,
+    datagrams: &[(&[u8], SocketAddr)],
+    offload: &mut UdpOffload,
+) -> io::Result<()> {
+    let mut first_error = None;
+    let mut sent = 0;
+
+    while sent < datagrams.len() {
+        match sys::try_send_batch(socket, &datagrams[sent..], offload.gso) {
+            Ok(count) => sent += count,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => socket.writable().await?,
+            Err(_) if offload.gso => offload.gso = false,
+            Err(e) => {
+                first_error.get_or_insert(e);
+                sent += 1;
+            }
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Split `datagrams` into runs that can share one GSO buffer
+///
+/// Returns the number of datagrams in each run. A run has a single destination and
+/// equally sized datagrams, except that the last one may be shorter.
+fn gso_runs(datagrams: &[(&[u8], SocketAddr)], gso: bool, max_runs: usize) -> Vec<usize> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+
+    while start < datagrams.len() && runs.len() < max_runs {
+        let (first, target) = datagrams[start];
+        let mut count = 1;
+        let mut total = first.len();
+
+        if gso && !first.is_empty() {
+            while let Some(&(next, next_target)) = datagrams.get(start + count) {
+                let previous = datagrams[start + count - 1].0;
+                if count == MAX_GSO_SEGMENTS
+                    || next_target != target
+                    || previous.len() != first.len()
+                    || next.len() > first.len()
+                    || total + next.len() > MAX_GSO_PAYLOAD
+                {
+                    break;
+                }
+                count += 1;
+                total += next.len();
+            }
+        }
+
+        runs.push(count);
+        start += count;
+    }
+
+    runs
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    use socket2::SockAddr;
+    use tokio::io::Interest;
+    use tokio::net::UdpSocket;
+
+    use super::gso_runs;
+    use crate::config::MAX_IO_BATCH_SIZE;
+
+    // Not exported by libc on every Linux target
+    const UDP_SEGMENT: libc::c_int = 103;
+    const UDP_GRO: libc::c_int = 104;
+
+    /// Control message buffer, aligned for `cmsghdr` and large enough for one int
+    type ControlBuffer = [u64; 4];
+
+    pub(super) fn supports_gso(socket: &UdpSocket) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: value and len are valid for writes of the advertised size
+        let result = unsafe {
+            libc::getsockopt(socket.as_raw_fd(), libc::SOL_UDP, UDP_SEGMENT, (&mut value as *mut libc::c_int).cast(), &mut len)
+        };
+        result == 0
+    }
+
+    pub(super) fn set_gro(socket: &UdpSocket, enabled: bool) -> bool {
+        let value = enabled as libc::c_int;
+        // SAFETY: value is valid for reads of the advertised size
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                UDP_GRO,
+                (&value as *const libc::c_int).cast(),
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        result == 0
+    }
+
+    pub(super) fn try_recv_batch(
+        socket: &UdpSocket,
+        buffer: &mut [u8],
+        slot_size: usize,
+        received: &mut Vec<(usize, usize, SocketAddr)>,
+    ) -> io::Result<usize> {
+        let count = (buffer.len() / slot_size).min(MAX_IO_BATCH_SIZE);
+        // SAFETY: sockaddr_storage is plain old data; all-zero is a valid value
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; count];
+        let mut controls: Vec<ControlBuffer> = vec![[0; 4]; count];
+        let mut iovecs: Vec<libc::iovec> = buffer
+            .chunks_exact_mut(slot_size)
+            .take(count)
+            .map(|slot| libc::iovec {
+                iov_base: slot.as_mut_ptr().cast(),
+                iov_len: slot.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .zip(controls.iter_mut())
+            .map(|((addr, iovec), control)| {
+                // SAFETY: mmsghdr is plain old data; all-zero is a valid value
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+                header.msg_hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+                header
+            })
+            .collect();
+
+        let filled = socket.try_io(Interest::READABLE, || {
+            // SAFETY: every header points at a live address slot, control buffer and
+            // data slot of the advertised length, and `count` does not exceed the
+            // header array
+            let result = unsafe {
+                libc::recvmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), count as libc::c_uint, 0, ptr::null_mut())
+            };
+            if result < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(result as usize)
+            }
+        })?;
+
+        for (slot, (header, addr)) in headers.iter().zip(&addrs).take(filled).enumerate() {
+            // SAFETY: the kernel filled in `msg_namelen` bytes of the address storage
+            let addr = unsafe { SockAddr::new(*addr, header.msg_hdr.msg_namelen) };
+            let from = addr.as_socket().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("unsupported address family {}", addr.family()))
+            })?;
+            let len = header.msg_len as usize;
+            // A GRO buffer holds several datagrams of `segment_size` bytes each
+            let segment_size = gro_segment_size(&header.msg_hdr).unwrap_or(len).max(1);
+            let base = slot * slot_size;
+            for offset in (0..len).step_by(segment_size) {
+                received.push((base + offset, segment_size.min(len - offset), from));
+            }
+            if len == 0 {
+                received.push((base, 0, from));
+            }
+        }
+        Ok(filled)
+    }
+
+    pub(super) fn try_send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)], gso: bool) -> io::Result<usize> {
+        let runs = gso_runs(datagrams, gso, MAX_IO_BATCH_SIZE);
+        let datagram_count: usize = runs.iter().sum();
+
+        let mut iovecs: Vec<libc::iovec> = datagrams[..datagram_count]
+            .iter()
+            .map(|(data, _)| libc::iovec {
+                iov_base: data.as_ptr() as *mut libc::c_void,
+                iov_len: data.len(),
+            })
+            .collect();
+        let mut addrs = Vec::with_capacity(runs.len());
+        let mut start = 0;
+        for &count in &runs {
+            let addr = SockAddr::from(datagrams[start].1);
+            let len = addr.len();
+            addrs.push((addr.as_storage(), len));
+            start += count;
+        }
+        let mut controls: Vec<ControlBuffer> = vec![[0; 4]; runs.len()];
+
+        let mut headers = Vec::with_capacity(runs.len());
+        let mut start = 0;
+        for ((&count, (addr, addr_len)), control) in runs.iter().zip(addrs.iter_mut()).zip(controls.iter_mut()) {
+            // SAFETY: mmsghdr is plain old data; all-zero is a valid value
+            let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+            header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+            header.msg_hdr.msg_namelen = *addr_len;
+            header.msg_hdr.msg_iov = iovecs[start..].as_mut_ptr();
+            header.msg_hdr.msg_iovlen = count as _;
+            if count > 1 {
+                set_segment_size(&mut header.msg_hdr, control, datagrams[start].0.len() as u16);
+            }
+            headers.push(header);
+            start += count;
+        }
+
+        let sent = socket.try_io(Interest::WRITABLE, || {
+            // SAFETY: every header points at a live address, control buffer and
+            // `msg_iovlen` payload slices; the kernel only reads from them
+            let result = unsafe {
+                libc::sendmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), headers.len() as libc::c_uint, 0)
+            };
+            if result < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(result as usize)
+            }
+        })?;
+
+        Ok(runs[..sent].iter().sum())
+    }
+
+    /// Attach a `UDP_SEGMENT` control message to `header`
+    fn set_segment_size(header: &mut libc::msghdr, control: &mut ControlBuffer, segment_size: u16) {
+        header.msg_control = control.as_mut_ptr().cast();
+        // SAFETY: the control buffer is aligned for cmsghdr and larger than
+        // CMSG_SPACE(sizeof(u16)), so the first header and its data fit
+        unsafe {
+            header.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(header);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<u16>(), segment_size);
+        }
+    }
+
+    /// Segment size reported by a `UDP_GRO` control message, if any
+    fn gro_segment_size(header: &libc::msghdr) -> Option<usize> {
+        // SAFETY: the kernel filled in msg_controllen bytes of well-formed control
+        // messages; CMSG_FIRSTHDR/CMSG_NXTHDR stay within that range
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == UDP_GRO {
+                    let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>());
+                    return usize::try_from(size).ok();
+                }
+                cmsg = libc::CMSG_NXTHDR(header, cmsg);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use tokio::net::UdpSocket;
+
+    pub(super) fn supports_gso(_socket: &UdpSocket) -> bool {
+        false
+    }
+
+    pub(super) fn set_gro(_socket: &UdpSocket, _enabled: bool) -> bool {
+        false
+    }
+
+    pub(super) fn try_recv_batch(
+        socket: &UdpSocket,
+        buffer: &mut [u8],
+        slot_size: usize,
+        received: &mut Vec<(usize, usize, SocketAddr)>,
+    ) -> io::Result<usize> {
+        for (slot, chunk) in buffer.chunks_exact_mut(slot_size).enumerate() {
+            match socket.try_recv_from(chunk) {
+                Ok((len, from)) => received.push((slot * slot_size, len, from)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && !received.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(received.len())
+    }
+
+    pub(super) fn try_send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)], _gso: bool) -> io::Result<usize> {
+        let mut sent = 0;
+        for (data, target) in datagrams {
+            match socket.try_send_to(data, *target) {
+                Ok(_) => sent += 1,
+                Err(_) if sent > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn round_trip(offload: bool) {
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = receiver.local_addr().unwrap();
+        let mut send_offload = UdpOffload::configure(&sender, offload);
+        let recv_offload = UdpOffload::configure(&receiver, offload);
+
+        // Equal sizes with a shorter tail, so GSO can coalesce the whole run
+        let mut payloads: Vec<Vec<u8>> = (0..9u8).map(|i| vec![i; 1200]).collect();
+        payloads.push(vec![9; 300]);
+        let datagrams: Vec<(&[u8], SocketAddr)> = payloads.iter().map(|p| (p.as_slice(), target)).collect();
+        send_batch(&sender, &datagrams, &mut send_offload).await.unwrap();
+
+        let mut batch = RecvBatch::new(4, recv_offload.gro);
+        let mut received = Vec::new();
+        while received.len() < payloads.len() {
+            assert!(batch.recv(&receiver).await.unwrap() >= 1);
+            for (data, from) in batch.datagrams() {
+                assert_eq!(from, sender.local_addr().unwrap());
+                received.push(data.to_vec());
+            }
+        }
+
+        assert_eq!(received, payloads);
+    }
+
+    #[tokio::test]
+    async fn test_batch_round_trip() {
+        round_trip(false).await;
+    }
+
+    #[tokio::test]
+    async fn test_batch_round_trip_with_offload() {
+        round_trip(true).await;
+    }
+
+    #[test]
+    fn test_gso_runs() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let full = [0u8; 100];
+        let short = [0u8; 40];
+        let datagrams: Vec<(&[u8], SocketAddr)> = vec![
+            (&full, a), (&full, a), (&short, a), // shorter tail ends the run
+            (&full, a), (&full, b),              // destination change ends the run
+            (&short, b), (&full, b),             // larger datagram starts a new run
+        ];
+
+        assert_eq!(gso_runs(&datagrams, true, 64), vec![3, 1, 2, 1]);
+        assert_eq!(gso_runs(&datagrams, false, 64), vec![1; 7]);
+        assert_eq!(gso_runs(&datagrams, true, 2), vec![3, 1]);
+    }
+}
@@ -1,14 +1,33 @@
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+use crate::config::PoolTrimConfig;
 use crate::error::RudpError;
 use crate::protocol::PROTOCOL_HEADER_SIZE;
 
 /// 默认buffer大小：协议头(9字节) + 数据区(1400字节)
 pub const DEFAULT_BUFFER_SIZE: usize = PROTOCOL_HEADER_SIZE + 1400;
 
-/// 内存池最大容量（固定值）
+/// 单个buffer可容纳的最大用户数据长度
+pub const MAX_PAYLOAD_SIZE: usize = DEFAULT_BUFFER_SIZE - PROTOCOL_HEADER_SIZE;
+
+/// 大数据buffer大小（用于分片等超过单个MTU的数据）
+pub const LARGE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// buffer大小等级（含协议头），从小到大排列
+///
+/// 小消息使用小等级的buffer，避免每个buffer都占用 DEFAULT_BUFFER_SIZE
+pub const BUFFER_SIZE_CLASSES: [usize; 4] = [128, 512, DEFAULT_BUFFER_SIZE, LARGE_BUFFER_SIZE];
+
+/// 大小等级数量
+const CLASS_COUNT: usize = BUFFER_SIZE_CLASSES.len();
+
+/// 内存池默认最大容量（所有大小等级合计）
 pub const MAX_POOL_CAPACITY: usize = 200000;
 
+/// 内存池分片数量上限
+const MAX_SHARDS: usize = 16;
+
 /// 内存池默认初始容量
 pub const DEFAULT_INITIAL_CAPACITY: usize = 500;
 
@@ -26,21 +45,10 @@ pub struct PooledBuffer {
     /// 用户数据的实际长度
     data_len: usize,
     /// 内存池的引用，用于归还buffer
-    pool: Arc<Mutex<BufferPool>>,
+    pool: Arc<BufferPool>,
 }
 
 impl PooledBuffer {
-    /// 创建新的池化buffer
-    /// 
-    /// 大小固定为 DEFAULT_BUFFER_SIZE
-    fn new(pool: Arc<Mutex<BufferPool>>) -> Self {
-        Self {
-            raw_buffer: vec![0u8; DEFAULT_BUFFER_SIZE],
-            data_len: 0,
-            pool,
-        }
-    }
-
     /// 获取用户数据区的可写切片
     /// 
     /// 返回从协议头之后开始的数据区域
@@ -66,23 +74,23 @@ impl PooledBuffer {
         Ok(())
     }
 
-    /// 获取用户数据长度
-    pub fn data_len(&self) -> usize {
-        self.data_len
-    }
-
-    /// 获取完整buffer（包含协议头空间）
+    /// 把`bytes`复制到数据区开头，并把数据长度设为`bytes.len()`
     /// 
-    /// 仅供rudpbase内部使用，用于填充协议头
-    pub(crate) fn raw_buffer(&self) -> &[u8] {
-        &self.raw_buffer
+    /// `bytes`超过数据区大小时返回`RudpError::BufferTooLarge`，不截断，buffer保持不变。
+    /// 数据区可能大于发往某个对端的载荷上限，发送时还会按`max_payload_for()`检查
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), RudpError> {
+        let max_data_len = self.raw_buffer.len() - PROTOCOL_HEADER_SIZE;
+        if bytes.len() > max_data_len {
+            return Err(RudpError::BufferTooLarge { size: bytes.len(), max: max_data_len });
+        }
+        self.data_mut()[..bytes.len()].copy_from_slice(bytes);
+        self.data_len = bytes.len();
+        Ok(())
     }
 
-    /// 获取完整buffer的可写引用
-    /// 
-    /// 仅供rudpbase内部使用，用于填充协议头
-    pub(crate) fn raw_buffer_mut(&mut self) -> &mut [u8] {
-        &mut self.raw_buffer
+    /// 获取用户数据长度
+    pub fn data_len(&self) -> usize {
+        self.data_len
     }
 
     /// 获取协议头区域的可写切片
@@ -95,6 +103,7 @@ impl PooledBuffer {
     /// 获取包含协议头的完整数据切片
     /// 
     /// 仅供rudpbase内部使用，用于发送数据
+    #[cfg_attr(not(test), allow(dead_code))]
     pub(crate) fn full_packet(&self) -> &[u8] {
         &self.raw_buffer[..PROTOCOL_HEADER_SIZE + self.data_len]
     }
@@ -106,14 +115,26 @@ impl PooledBuffer {
         &self.raw_buffer[..PROTOCOL_HEADER_SIZE + self.data_len]
     }
 
+    /// 获取完整buffer（含协议头空间）的只读切片
+    /// 
+    /// 仅供rudpbase内部使用，接收路径和io_uring后端直接读写整个buffer
+    pub(crate) fn raw_buffer(&self) -> &[u8] {
+        &self.raw_buffer
+    }
+
+    /// 获取完整buffer（含协议头空间）的可写切片
+    /// 
+    /// 仅供rudpbase内部使用，接收路径和io_uring后端直接读写整个buffer
+    pub(crate) fn raw_buffer_mut(&mut self) -> &mut [u8] {
+        &mut self.raw_buffer
+    }
+
     /// 填充协议头
     /// 
     /// 仅供rudpbase内部使用
-    pub(crate) fn fill_protocol_header(&mut self, packet_type: crate::protocol::PacketType, seq: u32) -> Result<(), RudpError> {
-        use crate::security::SecurityCode;
-        
+    pub(crate) fn fill_protocol_header(&mut self, packet_type: crate::protocol::PacketType, seq: u32, authenticator: &dyn crate::security::PacketAuthenticator) -> Result<(), RudpError> {
         // 计算安全码
-        let security_code = SecurityCode::calculate(packet_type, seq, self.data());
+        let security_code = authenticator.code(packet_type, seq, self.data());
         
         // 填充协议头
         let header = self.header_mut();
@@ -125,6 +146,7 @@ impl PooledBuffer {
     }
 
     /// 重置buffer状态，准备复用
+    #[allow(dead_code)]
     fn reset(&mut self) {
         // 只重置数据长度，不清零内存（性能优化）
         // 下次使用时会重新填充协议头和数据，无需清零
@@ -132,36 +154,89 @@ impl PooledBuffer {
     }
 }
 
+/// `Bytes`视图的所有者，只暴露用户数据区
+#[cfg(feature = "bytes")]
+struct PayloadOwner(PooledBuffer);
+
+#[cfg(feature = "bytes")]
+impl AsRef<[u8]> for PayloadOwner {
+    fn as_ref(&self) -> &[u8] {
+        self.0.data()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl PooledBuffer {
+    /// 转换为指向用户数据区的`Bytes`，不拷贝数据
+    ///
+    /// 底层buffer在最后一个`Bytes`克隆释放时归还内存池
+    pub fn into_bytes(self) -> bytes::Bytes {
+        bytes::Bytes::from_owner(PayloadOwner(self))
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<PooledBuffer> for bytes::Bytes {
+    fn from(buffer: PooledBuffer) -> Self {
+        buffer.into_bytes()
+    }
+}
+
 impl Drop for PooledBuffer {
     /// 自动归还buffer到内存池
     fn drop(&mut self) {
-        if let Ok(mut pool) = self.pool.lock() {
-            // 只重置数据长度，不清零内存（性能优化）
-            // 下次使用时会重新填充协议头和数据，无需清零
-            self.data_len = 0;
-            
-            // 归还到池中
-            if pool.free_buffers.len() < MAX_POOL_CAPACITY {
-                // 移动buffer到池中（避免clone）
-                let mut buffer = Vec::new();
-                std::mem::swap(&mut buffer, &mut self.raw_buffer);
-                pool.free_buffers.push_back(buffer);
-            }
-            // 如果池已满，则直接丢弃buffer（让操作系统回收内存）
-        }
+        // 只重置数据长度，不清零内存（性能优化）
+        // 下次使用时会重新填充协议头和数据，无需清零
+        self.data_len = 0;
+
+        // 移动buffer到池中（避免clone）
+        self.pool.put_buffer(std::mem::take(&mut self.raw_buffer));
     }
 }
 
+/// 下一个线程使用的分片下标
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// 当前线程优先使用的分片（按线程轮流分配）
+    static SHARD_HINT: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 内存池分片，保存一部分空闲buffer
+#[derive(Debug, Default)]
+struct Shard {
+    /// 每个大小等级的空闲buffer
+    free_buffers: [Vec<Vec<u8>>; CLASS_COUNT],
+}
+
 /// 内存池
 /// 
-/// 管理固定大小的buffer块，支持高效的分配和回收
-/// 所有buffer块大小固定为 DEFAULT_BUFFER_SIZE
+/// 按大小等级管理buffer块，支持高效的分配和回收。默认等级为 BUFFER_SIZE_CLASSES，
+/// 其中 DEFAULT_BUFFER_SIZE 可以在创建时替换为其他大小
+/// 
+/// 空闲buffer分散在多个分片中，每个线程优先使用自己的分片，多个实例或任务
+/// 并发分配和归还buffer时不会争用同一把锁。自己的分片为空时从其他分片取用，
+/// 使空闲buffer在线程间重新平衡
 #[derive(Debug)]
 pub struct BufferPool {
-    /// 空闲buffer队列
-    free_buffers: VecDeque<Vec<u8>>,
-    /// 统计信息
-    stats: PoolStats,
+    /// 每个等级的buffer大小（含协议头），从小到大排列
+    class_sizes: [usize; CLASS_COUNT],
+    /// 默认等级（`get_write_buffer`和预热使用）的下标
+    default_class: usize,
+    /// 空闲buffer总数上限
+    max_capacity: usize,
+    /// 空闲buffer分片
+    shards: Box<[Mutex<Shard>]>,
+    /// 所有分片的空闲buffer总数
+    free_count: AtomicUsize,
+    /// 总分配次数
+    total_allocations: AtomicU64,
+    /// 从池中获取的次数
+    pool_hits: AtomicU64,
+    /// 新分配的次数
+    pool_misses: AtomicU64,
+    /// 空闲buffer数量开始持续超过水位线的时间
+    above_watermark_since: Mutex<Option<Instant>>,
 }
 
 /// 内存池统计信息
@@ -173,8 +248,17 @@ pub struct PoolStats {
     pub pool_hits: u64,
     /// 新分配的次数
     pub pool_misses: u64,
-    /// 当前池中空闲buffer数量
+    /// 当前池中空闲buffer数量（所有等级合计）
     pub free_count: usize,
+    /// 每个大小等级的buffer大小（含协议头）
+    pub class_sizes: [usize; CLASS_COUNT],
+    /// 每个大小等级的空闲buffer数量，与 `class_sizes` 一一对应
+    pub free_by_class: [usize; CLASS_COUNT],
+}
+
+/// 加锁分片；分片中只有普通数据，锁中毒后仍可继续使用
+fn lock(shard: &Mutex<Shard>) -> MutexGuard<'_, Shard> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl BufferPool {
@@ -183,62 +267,187 @@ impl BufferPool {
     /// # 参数
     /// - `initial_capacity`: 初始预分配的buffer数量
     /// 
-    /// 注意：所有buffer大小固定为 DEFAULT_BUFFER_SIZE
+    /// 注意：预分配的buffer大小为 DEFAULT_BUFFER_SIZE
     pub fn new(initial_capacity: usize) -> Self {
-        let mut pool = Self {
-            free_buffers: VecDeque::with_capacity(MAX_POOL_CAPACITY),
-            stats: PoolStats {
-                total_allocations: 0,
-                pool_hits: 0,
-                pool_misses: 0,
-                free_count: 0,
-            },
+        Self::with_limits(initial_capacity, MAX_POOL_CAPACITY, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// 创建默认配置的内存池
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Self {
+        Self::new(DEFAULT_INITIAL_CAPACITY)
+    }
+
+    /// 创建指定容量和默认buffer大小的内存池
+    /// 
+    /// # 参数
+    /// - `initial_capacity`: 初始预分配的buffer数量（不超过 `max_capacity`）
+    /// - `max_capacity`: 池中最多保留的空闲buffer数量，超出的buffer归还时直接释放
+    /// - `buffer_size`: 默认buffer大小（含协议头），取代 DEFAULT_BUFFER_SIZE 等级
+    /// 
+    /// `buffer_size` 需要在 `PROTOCOL_HEADER_SIZE + 1..=LARGE_BUFFER_SIZE` 范围内
+    pub fn with_limits(initial_capacity: usize, max_capacity: usize, buffer_size: usize) -> Self {
+        assert!(
+            buffer_size > PROTOCOL_HEADER_SIZE && buffer_size <= LARGE_BUFFER_SIZE,
+            "buffer_size must be within {}..={}",
+            PROTOCOL_HEADER_SIZE + 1,
+            LARGE_BUFFER_SIZE
+        );
+        let mut class_sizes = BUFFER_SIZE_CLASSES;
+        class_sizes[2] = buffer_size;
+        class_sizes.sort_unstable();
+        let default_class = class_sizes.iter().position(|&size| size == buffer_size).unwrap();
+
+        let shard_count = std::thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_SHARDS);
+        Self::with_shards(initial_capacity, max_capacity, class_sizes, default_class, shard_count)
+    }
+
+    fn with_shards(
+        initial_capacity: usize,
+        max_capacity: usize,
+        class_sizes: [usize; CLASS_COUNT],
+        default_class: usize,
+        shard_count: usize,
+    ) -> Self {
+        let pool = Self {
+            class_sizes,
+            default_class,
+            max_capacity,
+            shards: (0..shard_count).map(|_| Mutex::default()).collect(),
+            free_count: AtomicUsize::new(0),
+            total_allocations: AtomicU64::new(0),
+            pool_hits: AtomicU64::new(0),
+            pool_misses: AtomicU64::new(0),
+            above_watermark_since: Mutex::new(None),
         };
 
-        // 预分配初始buffer，大小固定为 DEFAULT_BUFFER_SIZE
-        for _ in 0..initial_capacity {
-            pool.free_buffers.push_back(vec![0u8; DEFAULT_BUFFER_SIZE]);
+        // 预分配初始buffer，大小为默认等级大小
+        pool.warmup(initial_capacity);
+        pool
+    }
+
+    /// 默认等级的buffer大小（含协议头）
+    pub fn buffer_size(&self) -> usize {
+        self.class_sizes[self.default_class]
+    }
+
+    /// 大小正好等于某个等级的buffer所属的等级下标
+    fn class_of(&self, buffer_size: usize) -> Option<usize> {
+        self.class_sizes.iter().position(|&size| size == buffer_size)
+    }
+
+    /// 能容纳 `data_len` 字节用户数据的最小等级下标
+    fn class_for(&self, data_len: usize) -> Option<usize> {
+        self.class_sizes
+            .iter()
+            .position(|&size| size - PROTOCOL_HEADER_SIZE >= data_len)
+    }
+
+    /// 当前线程优先使用的分片下标
+    fn local_shard(&self) -> usize {
+        SHARD_HINT.with(|hint| *hint % self.shards.len())
+    }
+
+    /// 从指定等级的池中获取buffer
+    /// 
+    /// 先查本线程的分片，再尝试其他未被占用的分片，都没有时分配新buffer
+    fn get_buffer(&self, class: usize) -> Vec<u8> {
+        self.total_allocations.fetch_add(1, Ordering::Relaxed);
+
+        let local = self.local_shard();
+        let mut buffer = lock(&self.shards[local]).free_buffers[class].pop();
+        if buffer.is_none() && self.free_count.load(Ordering::Relaxed) > 0 {
+            buffer = (1..self.shards.len())
+                .map(|offset| &self.shards[(local + offset) % self.shards.len()])
+                .find_map(|shard| shard.try_lock().ok()?.free_buffers[class].pop());
         }
 
-        pool
+        match buffer {
+            Some(buffer) => {
+                // 从池中获取
+                self.free_count.fetch_sub(1, Ordering::Relaxed);
+                self.pool_hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                // 池为空，按等级大小分配新buffer
+                self.pool_misses.fetch_add(1, Ordering::Relaxed);
+                vec![0u8; self.class_sizes[class]]
+            }
+        }
     }
 
-    /// 创建默认配置的内存池
-    pub fn default() -> Self {
-        Self::new(DEFAULT_INITIAL_CAPACITY)
+    /// 归还buffer到本线程的分片
+    /// 
+    /// 大小不属于任何等级或池已满时直接丢弃buffer（让操作系统回收内存）
+    fn put_buffer(&self, buffer: Vec<u8>) {
+        let Some(class) = self.class_of(buffer.len()) else {
+            return;
+        };
+        if self.free_count.fetch_add(1, Ordering::Relaxed) >= self.max_capacity {
+            self.free_count.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+        lock(&self.shards[self.local_shard()]).free_buffers[class].push(buffer);
     }
 
-    /// 从池中获取buffer
-    fn get_buffer(&mut self) -> Vec<u8> {
-        self.stats.total_allocations += 1;
+    /// 预分配最多 `count` 个默认等级的buffer，空闲buffer总数不超过最大容量
+    fn warmup(&self, count: usize) {
+        let size = self.buffer_size();
+        for _ in 0..count {
+            if self.free_count.load(Ordering::Relaxed) >= self.max_capacity {
+                break;
+            }
+            self.put_buffer(vec![0u8; size]);
+        }
+    }
 
-        if let Some(buffer) = self.free_buffers.pop_front() {
-            // 从池中获取
-            self.stats.pool_hits += 1;
-            buffer
-        } else {
-            // 池为空，分配新buffer，大小固定为 DEFAULT_BUFFER_SIZE
-            self.stats.pool_misses += 1;
-            vec![0u8; DEFAULT_BUFFER_SIZE]
+    /// 释放空闲buffer直到最多剩下 `count` 个，返回释放的数量
+    /// 
+    /// 优先释放大等级的buffer，它们占用的内存最多
+    fn shrink_to(&self, count: usize) -> usize {
+        let mut released = 0;
+        for class in (0..CLASS_COUNT).rev() {
+            for shard in self.shards.iter() {
+                let mut shard = lock(shard);
+                let free = &mut shard.free_buffers[class];
+                let excess = self.free_count.load(Ordering::Relaxed).saturating_sub(count);
+                let take = excess.min(free.len());
+                free.truncate(free.len() - take);
+                free.shrink_to_fit();
+                self.free_count.fetch_sub(take, Ordering::Relaxed);
+                released += take;
+            }
         }
+        released
     }
 
     /// 获取统计信息
     pub fn stats(&self) -> PoolStats {
+        let mut free_by_class = [0; CLASS_COUNT];
+        for shard in self.shards.iter() {
+            let shard = lock(shard);
+            for (count, free) in free_by_class.iter_mut().zip(&shard.free_buffers) {
+                *count += free.len();
+            }
+        }
         PoolStats {
-            total_allocations: self.stats.total_allocations,
-            pool_hits: self.stats.pool_hits,
-            pool_misses: self.stats.pool_misses,
-            free_count: self.free_buffers.len(),
+            total_allocations: self.total_allocations.load(Ordering::Relaxed),
+            pool_hits: self.pool_hits.load(Ordering::Relaxed),
+            pool_misses: self.pool_misses.load(Ordering::Relaxed),
+            free_count: free_by_class.iter().sum(),
+            class_sizes: self.class_sizes,
+            free_by_class,
         }
     }
 }
 
+
 /// 共享内存池
 /// 
 /// 线程安全的内存池，可以在多个rudpbase实例间共享
 pub struct SharedBufferPool {
-    pool: Arc<Mutex<BufferPool>>,
+    pool: Arc<BufferPool>,
 }
 
 impl SharedBufferPool {
@@ -247,20 +456,46 @@ impl SharedBufferPool {
     /// # 参数
     /// - `initial_capacity`: 初始预分配的buffer数量
     /// 
-    /// 注意：所有buffer大小固定为 DEFAULT_BUFFER_SIZE
+    /// 注意：预分配的buffer大小为 DEFAULT_BUFFER_SIZE
     pub fn new(initial_capacity: usize) -> Self {
         Self {
-            pool: Arc::new(Mutex::new(BufferPool::new(initial_capacity))),
+            pool: Arc::new(BufferPool::new(initial_capacity)),
         }
     }
 
     /// 创建默认配置的共享内存池
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Self {
         Self {
-            pool: Arc::new(Mutex::new(BufferPool::default())),
+            pool: Arc::new(BufferPool::default()),
+        }
+    }
+
+    /// 创建指定容量和默认buffer大小的共享内存池
+    /// 
+    /// 参数含义见[`BufferPool::with_limits`]。创建后可以通过
+    /// [`Rudpbase::with_shared_pool`](crate::Rudpbase::with_shared_pool)
+    /// 在多个实例间共享
+    /// 
+    /// # 使用示例
+    /// ```rust
+    /// use rudpbase::SharedBufferPool;
+    /// 
+    /// // 最多缓存10000个空闲buffer，默认buffer只容纳512字节用户数据
+    /// let pool = SharedBufferPool::with_limits(1000, 10000, rudpbase::PROTOCOL_HEADER_SIZE + 512);
+    /// assert_eq!(pool.buffer_size().unwrap(), rudpbase::PROTOCOL_HEADER_SIZE + 512);
+    /// ```
+    pub fn with_limits(initial_capacity: usize, max_capacity: usize, buffer_size: usize) -> Self {
+        Self {
+            pool: Arc::new(BufferPool::with_limits(initial_capacity, max_capacity, buffer_size)),
         }
     }
 
+    /// 默认等级的buffer大小（含协议头）
+    pub fn buffer_size(&self) -> Result<usize, RudpError> {
+        Ok(self.pool.buffer_size())
+    }
+
     /// 获取一个buffer用于写入数据
     /// 
     /// # 返回
@@ -282,41 +517,80 @@ impl SharedBufferPool {
     /// // buffer会在离开作用域时自动归还到池中
     /// ```
     pub fn get_write_buffer(&self) -> Result<PooledBuffer, RudpError> {
-        let mut pool = self.pool.lock().map_err(|_| RudpError::InternalError)?;
-        let raw_buffer = pool.get_buffer();
-        
-        Ok(PooledBuffer {
-            raw_buffer,
+        Ok(self.take_buffer(self.pool.default_class))
+    }
+
+    /// 获取能容纳 `len` 字节用户数据的最小等级buffer
+    /// 
+    /// 小消息（如只有几十字节的控制消息）使用小buffer，减少大量小消息时的内存占用
+    /// 
+    /// # 返回
+    /// - `Ok(PooledBuffer)`: 数据区至少为 `len` 字节的buffer
+    /// - `Err(RudpError::BufferTooLarge)`: `len` 超过最大等级的数据区大小
+    /// 
+    /// # 使用示例
+    /// ```rust
+    /// use rudpbase::SharedBufferPool;
+    /// 
+    /// let pool = SharedBufferPool::default();
+    /// let mut buffer = pool.get_buffer_for(16).unwrap();
+    /// assert!(buffer.data_mut().len() < 128);
+    /// buffer.data_mut()[..16].copy_from_slice(&[7u8; 16]);
+    /// buffer.set_data_len(16).unwrap();
+    /// ```
+    pub fn get_buffer_for(&self, len: usize) -> Result<PooledBuffer, RudpError> {
+        let class = self.pool.class_for(len).ok_or(RudpError::BufferTooLarge {
+            size: len,
+            max: LARGE_BUFFER_SIZE - PROTOCOL_HEADER_SIZE,
+        })?;
+        Ok(self.take_buffer(class))
+    }
+
+    fn take_buffer(&self, class: usize) -> PooledBuffer {
+        PooledBuffer {
+            raw_buffer: self.pool.get_buffer(class),
             data_len: 0,
             pool: Arc::clone(&self.pool),
-        })
+        }
     }
 
     /// 获取内存池统计信息
     pub fn stats(&self) -> Result<PoolStats, RudpError> {
-        let pool = self.pool.lock().map_err(|_| RudpError::InternalError)?;
-        Ok(PoolStats {
-            total_allocations: pool.stats.total_allocations,
-            pool_hits: pool.stats.pool_hits,
-            pool_misses: pool.stats.pool_misses,
-            free_count: pool.free_buffers.len(),
-        })
+        Ok(self.pool.stats())
+    }
+
+    /// 释放空闲buffer，使池中最多保留 `count` 个，内存归还给操作系统
+    /// 
+    /// 正在使用中的buffer不受影响。返回释放的buffer数量
+    pub fn shrink_to(&self, count: usize) -> Result<usize, RudpError> {
+        *self.pool.above_watermark_since.lock().map_err(|_| RudpError::InternalError)? = None;
+        Ok(self.pool.shrink_to(count))
+    }
+
+    /// 按空闲回收策略检查内存池
+    /// 
+    /// 空闲buffer数量从超过水位线开始计时，持续 `trim.interval` 后收缩到水位线；
+    /// 期间一旦回落到水位线以下则重新计时。返回释放的buffer数量
+    pub fn trim_idle(&self, trim: &PoolTrimConfig, now: Instant) -> Result<usize, RudpError> {
+        let mut above_since = self.pool.above_watermark_since.lock().map_err(|_| RudpError::InternalError)?;
+        if self.pool.free_count.load(Ordering::Relaxed) <= trim.watermark {
+            *above_since = None;
+            return Ok(0);
+        }
+        let since = *above_since.get_or_insert(now);
+        if now.saturating_duration_since(since) < trim.interval {
+            return Ok(0);
+        }
+        *above_since = None;
+        Ok(self.pool.shrink_to(trim.watermark))
     }
 
     /// 预热内存池
     /// 
     /// 预分配指定数量的buffer，提高后续分配性能
-    /// 所有buffer大小固定为 DEFAULT_BUFFER_SIZE
+    /// 预分配的buffer大小为默认等级大小，空闲buffer总数不超过最大容量
     pub fn warmup(&self, count: usize) -> Result<(), RudpError> {
-        let mut pool = self.pool.lock().map_err(|_| RudpError::InternalError)?;
-        
-        for _ in 0..count {
-            if pool.free_buffers.len() >= MAX_POOL_CAPACITY {
-                break;
-            }
-            pool.free_buffers.push_back(vec![0u8; DEFAULT_BUFFER_SIZE]);
-        }
-        
+        self.pool.warmup(count);
         Ok(())
     }
 }
@@ -382,6 +656,25 @@ mod tests {
         assert!(buffer.set_data_len(max_data_len + 1).is_err());
     }
 
+    #[test]
+    fn test_write_bytes_rejects_oversized_slice() {
+        let pool = SharedBufferPool::default();
+        let mut buffer = pool.get_write_buffer().unwrap();
+        buffer.write_bytes(b"kept").unwrap();
+        assert_eq!(buffer.data(), b"kept");
+
+        let max_data_len = DEFAULT_BUFFER_SIZE - PROTOCOL_HEADER_SIZE;
+        let oversized = vec![1u8; max_data_len + 1];
+        assert!(matches!(
+            buffer.write_bytes(&oversized),
+            Err(RudpError::BufferTooLarge { size, max }) if size == max_data_len + 1 && max == max_data_len
+        ));
+        // 失败时不截断，原数据不变
+        assert_eq!(buffer.data(), b"kept");
+        buffer.write_bytes(&oversized[1..]).unwrap();
+        assert_eq!(buffer.data_len(), max_data_len);
+    }
+
     #[test]
     fn test_buffer_header_access() {
         let pool = SharedBufferPool::default();
@@ -406,4 +699,133 @@ mod tests {
         assert_eq!(full_packet[0], 1);
         assert_eq!(&full_packet[PROTOCOL_HEADER_SIZE..], test_data);
     }
-} 
\ No newline at end of file
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_into_bytes_returns_buffer_on_last_drop() {
+        let pool = SharedBufferPool::new(1);
+        let mut buffer = pool.get_write_buffer().unwrap();
+        buffer.data_mut()[..4].copy_from_slice(b"data");
+        buffer.set_data_len(4).unwrap();
+
+        let bytes = buffer.into_bytes();
+        let clone = bytes.clone();
+        assert_eq!(&bytes[..], b"data");
+        assert_eq!(pool.stats().unwrap().free_count, 0);
+
+        drop(bytes);
+        assert_eq!(pool.stats().unwrap().free_count, 0);
+        drop(clone);
+        assert_eq!(pool.stats().unwrap().free_count, 1);
+    }
+
+    #[test]
+    fn test_size_classes() {
+        let pool = SharedBufferPool::new(0);
+
+        let small = pool.get_buffer_for(10).unwrap();
+        assert_eq!(small.raw_buffer().len(), 128);
+        let medium = pool.get_buffer_for(128).unwrap();
+        assert_eq!(medium.raw_buffer().len(), 512);
+        let default = pool.get_buffer_for(MAX_PAYLOAD_SIZE).unwrap();
+        assert_eq!(default.raw_buffer().len(), DEFAULT_BUFFER_SIZE);
+        let mut large = pool.get_buffer_for(MAX_PAYLOAD_SIZE + 1).unwrap();
+        assert!(large.set_data_len(LARGE_BUFFER_SIZE - PROTOCOL_HEADER_SIZE).is_ok());
+        assert!(pool.get_buffer_for(LARGE_BUFFER_SIZE).is_err());
+
+        // Each buffer returns to its own class and is reused from there
+        drop((small, medium, default, large));
+        assert_eq!(pool.stats().unwrap().free_by_class, [1, 1, 1, 1]);
+        let _small = pool.get_buffer_for(1).unwrap();
+        let stats = pool.stats().unwrap();
+        assert_eq!(stats.free_by_class, [0, 1, 1, 1]);
+        assert_eq!(stats.pool_hits, 1);
+    }
+
+    #[test]
+    fn test_shrink_to_releases_largest_first() {
+        let pool = SharedBufferPool::new(3);
+        drop(pool.get_buffer_for(10).unwrap());
+        drop(pool.get_buffer_for(LARGE_BUFFER_SIZE - PROTOCOL_HEADER_SIZE).unwrap());
+        assert_eq!(pool.stats().unwrap().free_by_class, [1, 0, 3, 1]);
+
+        assert_eq!(pool.shrink_to(2).unwrap(), 3);
+        assert_eq!(pool.stats().unwrap().free_by_class, [1, 0, 1, 0]);
+        assert_eq!(pool.shrink_to(5).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_trim_idle_waits_for_interval() {
+        let pool = SharedBufferPool::new(10);
+        let trim = PoolTrimConfig {
+            watermark: 4,
+            interval: std::time::Duration::from_secs(30),
+        };
+        let start = Instant::now();
+
+        assert_eq!(pool.trim_idle(&trim, start).unwrap(), 0);
+        assert_eq!(pool.trim_idle(&trim, start + trim.interval / 2).unwrap(), 0);
+
+        // Dropping below the watermark restarts the interval
+        let held: Vec<_> = (0..7).map(|_| pool.get_write_buffer().unwrap()).collect();
+        assert_eq!(pool.trim_idle(&trim, start + trim.interval).unwrap(), 0);
+        drop(held);
+        assert_eq!(pool.trim_idle(&trim, start + trim.interval * 2).unwrap(), 0);
+        assert_eq!(pool.stats().unwrap().free_count, 10);
+
+        assert_eq!(pool.trim_idle(&trim, start + trim.interval * 3).unwrap(), 6);
+        assert_eq!(pool.stats().unwrap().free_count, 4);
+    }
+
+    #[test]
+    fn test_custom_limits() {
+        let pool = SharedBufferPool::with_limits(10, 4, 256);
+        let stats = pool.stats().unwrap();
+        assert_eq!(stats.class_sizes, [128, 256, 512, LARGE_BUFFER_SIZE]);
+        assert_eq!(stats.free_by_class, [0, 4, 0, 0]);
+
+        let buffer = pool.get_write_buffer().unwrap();
+        assert_eq!(buffer.raw_buffer().len(), 256);
+        assert_eq!(pool.get_buffer_for(300).unwrap().raw_buffer().len(), 512);
+
+        // Buffers beyond the maximum capacity are released instead of pooled
+        let held: Vec<_> = (0..4).map(|_| pool.get_write_buffer().unwrap()).collect();
+        drop(held);
+        drop(buffer);
+        assert_eq!(pool.stats().unwrap().free_count, 4);
+        pool.warmup(10).unwrap();
+        assert_eq!(pool.stats().unwrap().free_count, 4);
+    }
+
+    #[test]
+    fn test_buffers_move_between_threads() {
+        let shards = BufferPool::with_shards(0, MAX_POOL_CAPACITY, BUFFER_SIZE_CLASSES, 2, 4);
+        let pool = SharedBufferPool { pool: Arc::new(shards) };
+
+        // Buffers freed on other threads are reused here instead of reallocated
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let held: Vec<_> = (0..4).map(|_| pool.get_write_buffer().unwrap()).collect();
+                        drop(held);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let stats = pool.stats().unwrap();
+        assert_eq!(stats.total_allocations, 16000);
+        assert!(stats.pool_misses < 1000);
+        assert_eq!(stats.free_count as u64, stats.pool_misses);
+
+        // This thread's shard may be empty; it takes the other shards' buffers
+        let free = stats.free_count;
+        let held: Vec<_> = (0..free).map(|_| pool.get_write_buffer().unwrap()).collect();
+        assert_eq!(pool.stats().unwrap().pool_misses, stats.pool_misses);
+        drop(held);
+    }
+}
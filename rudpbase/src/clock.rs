@@ -0,0 +1,86 @@
+//! Time source
+//!
+//! Retransmission timeouts, keep-alive probing and idle buffer trimming read the current
+//! time through a [`Clock`]. [`SystemClock`] is the default; [`MockClock`] only moves
+//! when advanced, so timer-driven behavior can be tested deterministically together
+//! with an in-memory transport. [`TokioClock`] follows tokio's clock, which moves with
+//! `tokio::time::pause()`/`advance()` and with simulators such as turmoil.
+//!
+//! ```rust
+//! use rudpbase::{Clock, MockClock};
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::new();
+//! let start = clock.now();
+//! clock.advance(Duration::from_secs(30));
+//! assert_eq!(clock.now() - start, Duration::from_secs(30));
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time
+pub trait Clock: Send + Sync + 'static {
+    /// Current instant
+    fn now(&self) -> Instant;
+}
+
+/// Clock backed by [`Instant::now`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock backed by `tokio::time::Instant::now()`
+///
+/// The same as [`SystemClock`] unless tokio's time is paused, in which case protocol
+/// timers move in step with `tokio::time::sleep()` and the instance's own waits.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
+
+/// Manually driven clock
+///
+/// Clones share the same time, so a test can keep one handle to advance the clock
+/// after handing another to [`Rudpbase::set_clock`](crate::Rudpbase::set_clock).
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl MockClock {
+    /// Create a clock starting at the current system time
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,119 @@
+//! Payload compression
+//!
+//! With [`RudpConfig::compression`](crate::RudpConfig::compression) set, peers list the
+//! algorithms they accept in their ping exchange. Data and fragment payloads of at
+//! least `threshold` bytes sent to a peer that shares an algorithm are compressed
+//! before the security code is computed, and sent as `PacketType::Compressed` only
+//! when that makes them smaller. The receiver decompresses them before acknowledging,
+//! so `recv()` and `recv_message()` see the original payload.
+//!
+//! LZ4 requires the `lz4` feature and zstd the `zstd` feature.
+
+/// Compression algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// LZ4 block format: fast, moderate ratio (`lz4` feature)
+    Lz4,
+    /// Zstandard at the configured level: slower, better ratio (`zstd` feature)
+    Zstd,
+}
+
+impl Compression {
+    /// All algorithms, in the order preferred by `CompressionConfig::default()`
+    pub const ALL: [Compression; 2] = [Compression::Lz4, Compression::Zstd];
+
+    /// Whether the algorithm was compiled in
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Wire identifier, also the algorithm's bit in the advertised set
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.id() == id)
+    }
+}
+
+/// Set of algorithms advertised to peers
+pub(crate) fn advertised(algorithms: &[Compression]) -> u8 {
+    algorithms.iter().fold(0, |set, algorithm| set | algorithm.id())
+}
+
+/// Output space `compress` needs for `len` bytes of input
+pub(crate) fn max_compressed_len(algorithm: Compression, len: usize) -> usize {
+    match algorithm {
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => lz4_flex::block::get_maximum_output_size(len),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::zstd_safe::compress_bound(len),
+        #[allow(unreachable_patterns)]
+        _ => len,
+    }
+}
+
+/// Compress `input` into `output`, returning the compressed length
+///
+/// `output` should hold `max_compressed_len` bytes; returns `None` if it does not
+/// fit or the algorithm is not compiled in.
+#[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+pub(crate) fn compress(algorithm: Compression, level: i32, input: &[u8], output: &mut [u8]) -> Option<usize> {
+    match algorithm {
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => lz4_flex::block::compress_into(input, output).ok(),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::compress_to_buffer(input, output, level).ok(),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Decompress `input` into `output`, returning the decompressed length
+///
+/// Returns `None` for malformed input or output larger than `output`.
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+pub(crate) fn decompress(algorithm: Compression, input: &[u8], output: &mut [u8]) -> Option<usize> {
+    match algorithm {
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => lz4_flex::block::decompress_into(input, output).ok(),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::decompress_to_buffer(input, output).ok(),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_available_algorithms() {
+        let input: Vec<u8> = b"rudpbase ".iter().copied().cycle().take(1000).collect();
+        for algorithm in Compression::ALL.into_iter().filter(|algorithm| algorithm.is_available()) {
+            let mut compressed = vec![0u8; max_compressed_len(algorithm, input.len())];
+            let len = compress(algorithm, 1, &input, &mut compressed).unwrap();
+            assert!(len < 100, "{:?} compressed to {} bytes", algorithm, len);
+
+            let mut output = vec![0u8; input.len()];
+            assert_eq!(decompress(algorithm, &compressed[..len], &mut output), Some(input.len()));
+            assert_eq!(output, input);
+
+            // Output limits are enforced
+            assert_eq!(decompress(algorithm, &compressed[..len], &mut output[..100]), None);
+            assert_eq!(decompress(algorithm, b"\xff\xff\xff\xff", &mut output), None);
+        }
+
+        assert_eq!(Compression::from_id(Compression::Zstd.id()), Some(Compression::Zstd));
+        assert_eq!(Compression::from_id(3), None);
+        assert_eq!(advertised(&Compression::ALL), 3);
+    }
+}
@@ -0,0 +1,1074 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::compression::Compression;
+use crate::buffer_pool::{DEFAULT_BUFFER_SIZE, DEFAULT_INITIAL_CAPACITY, LARGE_BUFFER_SIZE, MAX_PAYLOAD_SIZE, MAX_POOL_CAPACITY};
+use crate::protocol::PROTOCOL_HEADER_SIZE;
+use crate::error::RudpError;
+use crate::message::DEFAULT_MAX_MESSAGE_SIZE;
+use crate::security::{PacketAuthenticator, SaltedFnv, DEFAULT_SALT};
+use crate::stats::{IDLE_TIMEOUT, MAX_PING_FAILURES, MAX_RETRIES, PING_INTERVAL};
+
+/// Initial retransmission timeout before any RTT sample is available
+pub const DEFAULT_INITIAL_RTO: Duration = Duration::from_millis(200);
+
+/// Lower bound of the retransmission timeout
+pub const DEFAULT_MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Upper bound of the retransmission timeout
+pub const DEFAULT_MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Factor a data packet's RTO is multiplied by on each retransmission timeout
+pub const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
+
+/// Initial congestion window in packets (RFC 6928)
+pub const DEFAULT_INITIAL_CWND: u32 = 10;
+
+/// Maximum congestion window in packets
+pub const DEFAULT_MAX_CWND: u32 = 1000;
+
+/// Datagrams per socket operation; 1 disables batched I/O
+pub const DEFAULT_IO_BATCH_SIZE: usize = 1;
+
+/// Upper bound of the I/O batch size
+pub const MAX_IO_BATCH_SIZE: usize = 64;
+
+/// Free buffers the pool keeps before idle trimming starts
+pub const DEFAULT_POOL_TRIM_WATERMARK: usize = 2000;
+
+/// How long the free count must stay above the watermark before the pool is trimmed
+pub const DEFAULT_POOL_TRIM_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Payloads shorter than this are sent uncompressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 128;
+
+/// zstd compression level; low levels keep per-packet latency small
+pub const DEFAULT_ZSTD_LEVEL: i32 = 1;
+
+/// Peers with protocol state before the least recently active one is evicted
+pub const DEFAULT_MAX_PEERS: usize = 10_000;
+
+/// Memory held by incomplete received messages across all peers
+pub const DEFAULT_MAX_REASSEMBLY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Received packets waiting for `recv()`
+pub const DEFAULT_MAX_QUEUED_PACKETS: usize = 65_536;
+
+/// Complete messages waiting for `recv_message()`
+pub const DEFAULT_MAX_QUEUED_MESSAGES: usize = 4096;
+
+/// Data packets per peer waiting for congestion window space
+pub const DEFAULT_MAX_QUEUED_SENDS: usize = 1024;
+
+/// Invalid packets a source may send within `invalid_packet_window` before it is ignored
+pub const DEFAULT_MAX_INVALID_PACKETS: u64 = 100;
+
+/// Period over which invalid packets are counted, and for which an offending source
+/// is ignored
+pub const DEFAULT_INVALID_PACKET_WINDOW: Duration = Duration::from_secs(10);
+
+/// Default time a resumption token is accepted after it was issued
+pub const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default datagram sizes data packets are padded to, see [`PaddingConfig`]
+pub const DEFAULT_PADDING_BUCKETS: [usize; 5] = [128, 256, 512, 1024, DEFAULT_BUFFER_SIZE];
+
+/// Default period over which alert thresholds are checked, see [`AlertConfig`]
+pub const DEFAULT_ALERT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default data packets a peer must be sent in an alert interval for its loss rate
+/// and retransmit ratio to be judged
+pub const DEFAULT_ALERT_MIN_PACKETS: u64 = 10;
+
+/// Default estimated loss rate above which critical packets are sent twice, see
+/// [`RedundancyConfig`]
+pub const DEFAULT_REDUNDANCY_LOSS_THRESHOLD: f64 = 0.1;
+
+/// Default delay between a critical packet and its redundant copy
+pub const DEFAULT_REDUNDANCY_SPACING: Duration = Duration::from_millis(5);
+
+/// Socket I/O implementation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IoBackend {
+    /// tokio socket, using `recvmmsg`/`sendmmsg` on Linux when batching is enabled
+    #[default]
+    Socket,
+    /// io_uring with receive operations kept posted (Linux, `io-uring` feature);
+    /// requires `io_batch_size > 1`, which sets the number of receive and send slots
+    IoUring,
+}
+
+/// Construction-time configuration for a Rudpbase instance
+///
+/// All fields are public; the `with_*` methods allow building a config in a single
+/// expression:
+///
+/// ```rust
+/// use rudpbase::RudpConfig;
+/// use std::time::Duration;
+///
+/// let config = RudpConfig::new()
+///     .with_rto_bounds(Duration::from_millis(50), Duration::from_secs(5))
+///     .with_initial_cwnd(32)
+///     .with_max_retries(8);
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RudpConfig {
+    /// Retransmission timeout used until the first RTT sample arrives
+    pub initial_rto: Duration,
+    /// Lower bound of the computed retransmission timeout
+    pub min_rto: Duration,
+    /// Upper bound of the computed retransmission timeout
+    pub max_rto: Duration,
+    /// Initial congestion window in packets
+    pub initial_cwnd: u32,
+    /// Maximum congestion window in packets
+    pub max_cwnd: u32,
+    /// Number of buffers preallocated in the buffer pool
+    pub pool_initial_capacity: usize,
+    /// Maximum number of free buffers the pool keeps for reuse
+    pub pool_max_capacity: usize,
+    /// Size of the pool's default buffers in bytes, including the protocol header;
+    /// must hold `max_payload_size` bytes of payload
+    pub pool_buffer_size: usize,
+    /// Release idle pool buffers to the OS during `tick()`; `None` lets the pool
+    /// keep every buffer it has allocated
+    pub pool_trim: Option<PoolTrimConfig>,
+    /// Maximum user payload per packet in bytes
+    pub max_payload_size: usize,
+    /// Largest message accepted by `send_message` and reassembled for `recv_message`
+    pub max_message_size: usize,
+    /// Keep-alive, dead-connection and retry thresholds
+    pub keepalive: KeepAliveConfig,
+    /// Growth of the retransmission timeout and the retry deadline
+    pub backoff: BackoffConfig,
+    /// Security code options
+    pub security: SecurityConfig,
+    /// Datagrams moved per system call (`recvmmsg`/`sendmmsg` on Linux); 1 disables
+    /// batching. When batching, data, control and retransmitted packets are queued and
+    /// sent once the queue fills or on the next `tick()`, `recv()` or `flush()`
+    pub io_batch_size: usize,
+    /// Use UDP GSO/GRO on Linux when batching is enabled, falling back silently if
+    /// the kernel does not support them
+    pub udp_offload: bool,
+    /// Socket I/O implementation; fixed for the lifetime of an instance
+    pub io_backend: IoBackend,
+    /// Kernel socket options applied when the socket is created
+    pub socket: SocketConfig,
+    /// Payload compression offered to peers; `None` neither compresses nor accepts
+    /// compressed packets
+    pub compression: Option<CompressionConfig>,
+    /// Bounds on the state and queues held for received traffic
+    pub limits: LimitsConfig,
+    /// Outbound bandwidth cap in bytes per second across all peers, counting every
+    /// datagram with its headers; `None` sends as fast as congestion control allows.
+    /// Datagrams over the budget wait in the send queue until a later `tick()`, and
+    /// up to 10ms worth of traffic may go out at once after an idle period
+    pub max_bandwidth: Option<u64>,
+    /// Add the transmit time to every data packet (10 bytes) so both ends can follow
+    /// the one-way delay of the path; peers echo it in acknowledgments whether or not
+    /// they enable it themselves
+    pub timestamps: bool,
+    /// Add a CRC32C of the whole payload to every data packet (6 bytes); packets
+    /// failing it are dropped and counted in `InvalidPacketStats::checksum_failures`.
+    /// Peers check the checksum whether or not they enable it themselves
+    pub payload_checksum: bool,
+    /// Issue resumption tokens to peers and accept 0-RTT data from peers presenting
+    /// one; `None` issues none and ignores presented tokens
+    pub resumption: Option<ResumptionConfig>,
+    /// Computes and checks packet security codes; `None` uses [`SaltedFnv`] with
+    /// `security.salt`. Compared by identity, so configs holding clones of the same
+    /// `Arc` are equal
+    pub authenticator: Option<Arc<dyn PacketAuthenticator>>,
+    /// Pad data packets to fixed sizes so their lengths do not reveal the messages;
+    /// `None` sends them at their natural size
+    pub padding: Option<PaddingConfig>,
+    /// Send the acknowledgments and pings queued for a peer by one `handle_timeout`
+    /// call in a single Batch datagram; the peer must understand Batch packets
+    pub coalesce_control: bool,
+    /// Hold small `send()` payloads to the same peer for up to this long and send them
+    /// together in one Bundle packet of at most `max_payload_size`; `flush_now()` sends
+    /// them at once. `None` sends each payload in its own packet. The peer must
+    /// understand Bundle packets
+    pub coalesce_delay: Option<Duration>,
+    /// Thresholds on per-peer loss, retransmissions and RTT reported to
+    /// `EventHandler::on_alert`; `None` checks none
+    pub alerts: Option<AlertConfig>,
+    /// When packets sent with `send_critical()` are duplicated
+    pub redundancy: RedundancyConfig,
+}
+
+impl Default for RudpConfig {
+    fn default() -> Self {
+        Self {
+            initial_rto: DEFAULT_INITIAL_RTO,
+            min_rto: DEFAULT_MIN_RTO,
+            max_rto: DEFAULT_MAX_RTO,
+            initial_cwnd: DEFAULT_INITIAL_CWND,
+            max_cwnd: DEFAULT_MAX_CWND,
+            pool_initial_capacity: DEFAULT_INITIAL_CAPACITY,
+            pool_max_capacity: MAX_POOL_CAPACITY,
+            pool_buffer_size: DEFAULT_BUFFER_SIZE,
+            pool_trim: Some(PoolTrimConfig::default()),
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            keepalive: KeepAliveConfig::default(),
+            backoff: BackoffConfig::default(),
+            security: SecurityConfig::default(),
+            io_batch_size: DEFAULT_IO_BATCH_SIZE,
+            udp_offload: false,
+            io_backend: IoBackend::Socket,
+            socket: SocketConfig::default(),
+            compression: None,
+            limits: LimitsConfig::default(),
+            max_bandwidth: None,
+            timestamps: false,
+            payload_checksum: false,
+            coalesce_control: false,
+            coalesce_delay: None,
+            alerts: None,
+            redundancy: RedundancyConfig::default(),
+            resumption: None,
+            authenticator: None,
+            padding: None,
+        }
+    }
+}
+
+impl RudpConfig {
+    /// Create a config with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the initial retransmission timeout
+    pub fn with_initial_rto(mut self, rto: Duration) -> Self {
+        self.initial_rto = rto;
+        self
+    }
+
+    /// Set the lower and upper bounds of the retransmission timeout
+    pub fn with_rto_bounds(mut self, min_rto: Duration, max_rto: Duration) -> Self {
+        self.min_rto = min_rto;
+        self.max_rto = max_rto;
+        self
+    }
+
+    /// Set the initial congestion window in packets
+    pub fn with_initial_cwnd(mut self, cwnd: u32) -> Self {
+        self.initial_cwnd = cwnd;
+        self
+    }
+
+    /// Set the maximum congestion window in packets
+    pub fn with_max_cwnd(mut self, cwnd: u32) -> Self {
+        self.max_cwnd = cwnd;
+        self
+    }
+
+    /// Set the number of buffers preallocated in the buffer pool
+    pub fn with_pool_initial_capacity(mut self, capacity: usize) -> Self {
+        self.pool_initial_capacity = capacity;
+        self
+    }
+
+    /// Set the maximum number of free buffers kept by the buffer pool
+    pub fn with_pool_max_capacity(mut self, capacity: usize) -> Self {
+        self.pool_max_capacity = capacity;
+        self
+    }
+
+    /// Set the size of the buffer pool's default buffers, including the protocol header
+    pub fn with_pool_buffer_size(mut self, size: usize) -> Self {
+        self.pool_buffer_size = size;
+        self
+    }
+
+    /// Set or disable idle trimming of the buffer pool
+    pub fn with_pool_trim(mut self, trim: Option<PoolTrimConfig>) -> Self {
+        self.pool_trim = trim;
+        self
+    }
+
+    /// Set the maximum user payload per packet
+    pub fn with_max_payload_size(mut self, size: usize) -> Self {
+        self.max_payload_size = size;
+        self
+    }
+
+    /// Set the largest message sent or reassembled by the message API
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Set the maximum number of retransmissions per data packet
+    pub fn with_max_retries(mut self, retries: u8) -> Self {
+        self.keepalive.max_retries = retries;
+        self
+    }
+
+    /// Set keep-alive and dead-connection thresholds
+    pub fn with_keepalive(mut self, keepalive: KeepAliveConfig) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Set the retransmission backoff policy
+    pub fn with_backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set security code options
+    pub fn with_security(mut self, security: SecurityConfig) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Set the number of datagrams moved per socket operation (1 disables batching)
+    pub fn with_io_batch_size(mut self, size: usize) -> Self {
+        self.io_batch_size = size;
+        self
+    }
+
+    /// Enable or disable UDP segmentation/receive offload for batched I/O
+    pub fn with_udp_offload(mut self, enabled: bool) -> Self {
+        self.udp_offload = enabled;
+        self
+    }
+
+    /// Select the socket I/O implementation
+    pub fn with_io_backend(mut self, backend: IoBackend) -> Self {
+        self.io_backend = backend;
+        self
+    }
+
+    /// Set kernel socket options (buffer sizes, DSCP, don't-fragment, interface)
+    pub fn with_socket(mut self, socket: SocketConfig) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Enable or disable payload compression
+    pub fn with_compression(mut self, compression: Option<CompressionConfig>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the bounds on state and queues held for received traffic
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set or remove the outbound bandwidth cap in bytes per second
+    pub fn with_max_bandwidth(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.max_bandwidth = bytes_per_sec;
+        self
+    }
+
+    /// Enable or disable send timestamps on data packets
+    pub fn with_timestamps(mut self, enabled: bool) -> Self {
+        self.timestamps = enabled;
+        self
+    }
+
+    /// Enable or disable payload checksums on sent data packets
+    pub fn with_payload_checksum(mut self, enabled: bool) -> Self {
+        self.payload_checksum = enabled;
+        self
+    }
+
+    /// Set how long small payloads wait to be bundled with others, `None` to disable
+    pub fn with_coalesce_delay(mut self, delay: Option<Duration>) -> Self {
+        self.coalesce_delay = delay;
+        self
+    }
+
+    /// Enable or disable coalescing of control packets into Batch datagrams
+    pub fn with_coalesce_control(mut self, enabled: bool) -> Self {
+        self.coalesce_control = enabled;
+        self
+    }
+
+    /// Set the alert thresholds checked for every peer, `None` to check none
+    pub fn with_alerts(mut self, alerts: Option<AlertConfig>) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// Set when critical packets are sent twice
+    pub fn with_redundancy(mut self, redundancy: RedundancyConfig) -> Self {
+        self.redundancy = redundancy;
+        self
+    }
+
+    /// Enable or disable issuing and accepting resumption tokens
+    pub fn with_resumption(mut self, resumption: Option<ResumptionConfig>) -> Self {
+        self.resumption = resumption;
+        self
+    }
+
+    /// Enable or disable padding data packets to fixed sizes
+    pub fn with_padding(mut self, padding: Option<PaddingConfig>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Set how packet security codes are computed and checked
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn PacketAuthenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// The authenticator in effect: the configured one or the salted default
+    pub(crate) fn packet_authenticator(&self) -> Arc<dyn PacketAuthenticator> {
+        self.authenticator.clone().unwrap_or_else(|| Arc::new(SaltedFnv::new(self.security.salt.clone())))
+    }
+
+    /// Whether batched I/O should request UDP GSO/GRO from the kernel
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn offload_requested(&self) -> bool {
+        self.udp_offload && self.io_batch_size > 1
+    }
+
+    /// Check that the configuration values are consistent
+    pub fn validate(&self) -> Result<(), RudpError> {
+        if self.min_rto.is_zero() || self.min_rto > self.max_rto {
+            return Err(invalid("min_rto must be non-zero and not greater than max_rto"));
+        }
+        if self.initial_rto < self.min_rto || self.initial_rto > self.max_rto {
+            return Err(invalid("initial_rto must lie within [min_rto, max_rto]"));
+        }
+        if self.initial_cwnd == 0 || self.initial_cwnd > self.max_cwnd {
+            return Err(invalid("initial_cwnd must be non-zero and not greater than max_cwnd"));
+        }
+        if self.max_payload_size == 0 || self.max_payload_size > MAX_PAYLOAD_SIZE {
+            return Err(RudpError::InvalidConfig {
+                message: format!("max_payload_size must be within 1..={}", MAX_PAYLOAD_SIZE),
+            });
+        }
+        if self.max_message_size == 0 {
+            return Err(invalid("max_message_size must be non-zero"));
+        }
+        if self.pool_initial_capacity > self.pool_max_capacity {
+            return Err(invalid("pool_initial_capacity must not be greater than pool_max_capacity"));
+        }
+        if self.pool_buffer_size < PROTOCOL_HEADER_SIZE + self.max_payload_size || self.pool_buffer_size > LARGE_BUFFER_SIZE {
+            return Err(RudpError::InvalidConfig {
+                message: format!(
+                    "pool_buffer_size must be within {}..={} (protocol header plus max_payload_size)",
+                    PROTOCOL_HEADER_SIZE + self.max_payload_size,
+                    LARGE_BUFFER_SIZE
+                ),
+            });
+        }
+        if self.pool_trim.as_ref().is_some_and(|trim| trim.interval.is_zero()) {
+            return Err(invalid("pool_trim interval must be non-zero"));
+        }
+        if let Some(compression) = &self.compression {
+            if compression.algorithms.is_empty() {
+                return Err(invalid("compression requires at least one algorithm"));
+            }
+            if let Some(algorithm) = compression.algorithms.iter().find(|algorithm| !algorithm.is_available()) {
+                return Err(RudpError::InvalidConfig {
+                    message: format!("compression algorithm {:?} is not compiled in", algorithm),
+                });
+            }
+        }
+        if self.limits.max_peers == 0
+            || self.limits.max_reassembly_bytes == 0
+            || self.limits.max_queued_packets == 0
+            || self.limits.max_queued_messages == 0
+            || self.limits.invalid_packet_window.is_zero()
+        {
+            return Err(invalid("limits must be non-zero"));
+        }
+        if self.coalesce_delay.is_some_and(|delay| delay > self.max_rto) {
+            return Err(invalid("coalesce_delay must not be greater than max_rto"));
+        }
+        if self.max_bandwidth == Some(0) {
+            return Err(invalid("max_bandwidth must be non-zero"));
+        }
+        if !(self.backoff.multiplier >= 1.0 && self.backoff.multiplier.is_finite()) {
+            return Err(invalid("backoff multiplier must be a finite number of at least 1"));
+        }
+        if self.backoff.max_rto.is_some_and(|max_rto| max_rto < self.min_rto) {
+            return Err(invalid("backoff max_rto must not be less than min_rto"));
+        }
+        if self.backoff.retry_deadline.is_some_and(|deadline| deadline.is_zero()) {
+            return Err(invalid("retry_deadline must be non-zero"));
+        }
+        if let Some(resumption) = &self.resumption {
+            if resumption.key.is_empty() {
+                return Err(invalid("resumption key must not be empty"));
+            }
+            if resumption.token_lifetime.is_zero() {
+                return Err(invalid("token_lifetime must be non-zero"));
+            }
+        }
+        if let Some(padding) = &self.padding {
+            if padding.buckets.is_empty() || !padding.buckets.windows(2).all(|pair| pair[0] < pair[1]) {
+                return Err(invalid("padding buckets must be non-empty and strictly increasing"));
+            }
+            // Receivers read datagrams of at most this size
+            let max_datagram = PROTOCOL_HEADER_SIZE + self.max_payload_size;
+            if padding.buckets.last().is_some_and(|size| *size > max_datagram) {
+                return Err(RudpError::InvalidConfig {
+                    message: format!("padding buckets must not exceed {} bytes (protocol header plus max_payload_size)", max_datagram),
+                });
+            }
+        }
+        if let Some(alerts) = &self.alerts {
+            if alerts.interval.is_zero() {
+                return Err(invalid("alert interval must be non-zero"));
+            }
+            if [alerts.loss_rate, alerts.retransmit_ratio].into_iter().flatten().any(|threshold| !(threshold >= 0.0 && threshold.is_finite())) {
+                return Err(invalid("alert rate thresholds must be finite and not negative"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.redundancy.loss_threshold) {
+            return Err(invalid("redundancy loss_threshold must be within 0..=1"));
+        }
+        if self.redundancy.spacing.is_zero() {
+            return Err(invalid("redundancy spacing must be non-zero"));
+        }
+        if self.keepalive.max_ping_failures == 0 {
+            return Err(invalid("max_ping_failures must be at least 1"));
+        }
+        if self.io_batch_size == 0 || self.io_batch_size > MAX_IO_BATCH_SIZE {
+            return Err(RudpError::InvalidConfig {
+                message: format!("io_batch_size must be within 1..={}", MAX_IO_BATCH_SIZE),
+            });
+        }
+        if self.io_backend == IoBackend::IoUring {
+            if !cfg!(all(feature = "io-uring", target_os = "linux")) {
+                return Err(invalid("io_uring backend requires the io-uring feature on Linux"));
+            }
+            if self.io_batch_size < 2 {
+                return Err(invalid("io_uring backend requires io_batch_size > 1"));
+            }
+        }
+        if self.socket.dscp.is_some_and(|dscp| dscp > MAX_DSCP) {
+            return Err(RudpError::InvalidConfig {
+                message: format!("dscp must be within 0..={}", MAX_DSCP),
+            });
+        }
+        if self.socket.recv_buffer_size == Some(0) || self.socket.send_buffer_size == Some(0) {
+            return Err(invalid("socket buffer sizes must be non-zero"));
+        }
+        if self.socket.bind_interface.as_ref().is_some_and(|name| name.is_empty()) {
+            return Err(invalid("bind_interface must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> RudpError {
+    RudpError::InvalidConfig {
+        message: message.to_string(),
+    }
+}
+
+/// Kernel socket options
+///
+/// Unset options keep the operating system defaults. The kernel may cap buffer sizes
+/// (`net.core.rmem_max`/`wmem_max` on Linux); with the `tracing` feature a warning is
+/// emitted when fewer bytes were granted than requested.
+///
+/// ```rust
+/// use rudpbase::{RudpConfig, SocketConfig};
+///
+/// let config = RudpConfig::new().with_socket(SocketConfig {
+///     recv_buffer_size: Some(4 * 1024 * 1024),
+///     dscp: Some(46), // Expedited Forwarding
+///     ..SocketConfig::default()
+/// });
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SocketConfig {
+    /// Kernel receive buffer size in bytes (`SO_RCVBUF`)
+    pub recv_buffer_size: Option<usize>,
+    /// Kernel send buffer size in bytes (`SO_SNDBUF`)
+    pub send_buffer_size: Option<usize>,
+    /// DSCP code point written to the IPv4 TOS / IPv6 traffic class field
+    pub dscp: Option<u8>,
+    /// Set the don't-fragment flag on outgoing datagrams (Linux only); oversized
+    /// sends then fail instead of being fragmented
+    pub dont_fragment: bool,
+    /// Network interface the socket is bound to (`SO_BINDTODEVICE` on Linux,
+    /// `IP_BOUND_IF`/`IPV6_BOUND_IF` on macOS), e.g. `"eth0"`
+    pub bind_interface: Option<String>,
+}
+
+/// Idle trimming of the buffer pool
+///
+/// Bursts grow the pool, and freed buffers are kept for reuse. Once the number of
+/// free buffers has stayed above `watermark` for `interval`, the excess is released
+/// so memory returns to the OS after the burst is over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolTrimConfig {
+    /// Free buffers kept after trimming
+    pub watermark: usize,
+    /// Time the free count must stay above the watermark before trimming
+    pub interval: Duration,
+}
+
+impl Default for PoolTrimConfig {
+    fn default() -> Self {
+        Self {
+            watermark: DEFAULT_POOL_TRIM_WATERMARK,
+            interval: DEFAULT_POOL_TRIM_INTERVAL,
+        }
+    }
+}
+
+/// Payload compression
+///
+/// The algorithms are offered to peers during the ping exchange; a payload is
+/// compressed with the first one in the list that the peer offers too.
+///
+/// ```rust
+/// use rudpbase::{CompressionConfig, RudpConfig};
+///
+/// let config = RudpConfig::new().with_compression(Some(CompressionConfig::default()));
+/// // Without the lz4 or zstd feature no algorithm is available
+/// assert_eq!(config.validate().is_ok(), cfg!(any(feature = "lz4", feature = "zstd")));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    /// Algorithms in order of preference
+    pub algorithms: Vec<Compression>,
+    /// Smallest payload in bytes worth compressing
+    pub threshold: usize,
+    /// zstd compression level
+    pub zstd_level: i32,
+}
+
+impl Default for CompressionConfig {
+    /// Every compiled-in algorithm, LZ4 first
+    fn default() -> Self {
+        Self {
+            algorithms: Compression::ALL.into_iter().filter(|algorithm| algorithm.is_available()).collect(),
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+/// Largest valid DSCP code point (6 bits)
+pub const MAX_DSCP: u8 = 63;
+
+/// Security code options
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityConfig {
+    /// Salt mixed into every security code; peers must share the same salt
+    pub salt: Vec<u8>,
+    /// Whether incoming packets are verified (disable only on trusted links)
+    pub verify: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            salt: DEFAULT_SALT.to_vec(),
+            verify: true,
+        }
+    }
+}
+
+/// Keep-alive and dead-connection thresholds
+///
+/// A peer that has been idle for `idle_timeout` is probed with a ping. Each ping that
+/// stays unanswered for `ping_interval` counts as a failure and is resent; after
+/// `max_ping_failures` consecutive failures the connection is considered dead and its
+/// state is removed. The worst-case detection time is therefore roughly
+/// `idle_timeout + max_ping_failures * ping_interval`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeepAliveConfig {
+    /// Idle time before the first ping is sent
+    pub idle_timeout: Duration,
+    /// Time to wait for a ping acknowledgment before counting a failure
+    pub ping_interval: Duration,
+    /// Consecutive ping failures before the connection is declared dead
+    pub max_ping_failures: u8,
+    /// Retransmissions of a data packet before it is dropped
+    pub max_retries: u8,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: IDLE_TIMEOUT,
+            ping_interval: PING_INTERVAL,
+            max_ping_failures: MAX_PING_FAILURES,
+            max_retries: MAX_RETRIES,
+        }
+    }
+}
+
+impl KeepAliveConfig {
+    /// Worst-case time between the last activity of a silent peer and its removal
+    pub fn dead_peer_detection_time(&self) -> Duration {
+        self.idle_timeout + self.ping_interval * self.max_ping_failures as u32
+    }
+}
+
+/// Retransmission backoff of data packets
+///
+/// Each time a data packet goes unacknowledged for its RTO it is retransmitted and its
+/// RTO is multiplied by `multiplier`, up to `max_rto`. The packet is given up after
+/// `keepalive.max_retries` retransmissions, or, when `retry_deadline` is set, once it
+/// has gone unacknowledged for that long however many retransmissions it took. The
+/// deadline suits long-lived transfers over flaky links, where a burst of loss
+/// should delay data rather than fail it.
+///
+/// ```rust
+/// use rudpbase::{BackoffConfig, RudpConfig};
+/// use std::time::Duration;
+///
+/// let config = RudpConfig::new().with_backoff(BackoffConfig {
+///     multiplier: 1.5,
+///     max_rto: Some(Duration::from_secs(5)),
+///     retry_deadline: Some(Duration::from_secs(120)),
+/// });
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackoffConfig {
+    /// Factor applied to a packet's RTO on each retransmission timeout; at least 1
+    pub multiplier: f64,
+    /// Upper bound of the backed-off RTO; `None` uses `RudpConfig::max_rto`
+    pub max_rto: Option<Duration>,
+    /// Retransmit until a packet has been unacknowledged this long, instead of giving
+    /// up after `keepalive.max_retries` retransmissions
+    pub retry_deadline: Option<Duration>,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            multiplier: DEFAULT_BACKOFF_MULTIPLIER,
+            max_rto: None,
+            retry_deadline: None,
+        }
+    }
+}
+
+/// Resumption tokens for 0-RTT reconnection
+///
+/// With this set, every ping acknowledgment carries a fresh
+/// [`ResumptionToken`](crate::ResumptionToken) bound to the peer's IP address. A peer
+/// that reconnects later, even after either side restarted, presents the token with
+/// its first data packet; a valid token starts a new session, so that data is
+/// delivered at once instead of being mistaken for a duplicate of the previous
+/// session, and the RTT measured in the previous session is restored. Each token is
+/// accepted once.
+///
+/// Tokens are checked with the same non-cryptographic hash as the security codes.
+///
+/// ```rust
+/// use rudpbase::{ResumptionConfig, RudpConfig};
+///
+/// // Keep the key across restarts so issued tokens stay valid
+/// let config = RudpConfig::new().with_resumption(Some(ResumptionConfig::new(b"server secret".to_vec())));
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumptionConfig {
+    /// Secret the tokens are authenticated with
+    pub key: Vec<u8>,
+    /// How long a token is accepted after it was issued
+    pub token_lifetime: Duration,
+}
+
+impl ResumptionConfig {
+    /// Tokens authenticated with `key`, valid for [`DEFAULT_TOKEN_LIFETIME`]
+    pub fn new(key: Vec<u8>) -> Self {
+        Self {
+            key,
+            token_lifetime: DEFAULT_TOKEN_LIFETIME,
+        }
+    }
+}
+
+/// Padding of data packets to fixed sizes
+///
+/// Each data packet is filled up with zeros until the whole datagram, headers
+/// included, reaches the smallest bucket it fits in, so an observer of the (possibly
+/// encrypted) traffic learns which bucket a packet fell into rather than its exact
+/// length. Datagrams larger than the last bucket go out unpadded. Buckets can be at
+/// most the protocol header plus `max_payload_size`, the largest datagram receivers
+/// read. The amount of
+/// padding travels in a 4-byte extension, so receivers strip it without any
+/// configuration of their own.
+///
+/// ```rust
+/// use rudpbase::{PaddingConfig, RudpConfig};
+///
+/// let config = RudpConfig::new().with_padding(Some(PaddingConfig::new(vec![256, 1024])));
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaddingConfig {
+    /// Datagram sizes in bytes, strictly increasing
+    pub buckets: Vec<usize>,
+}
+
+impl PaddingConfig {
+    pub fn new(buckets: Vec<usize>) -> Self {
+        Self { buckets }
+    }
+
+    /// Size a datagram of `len` bytes is padded to
+    pub(crate) fn padded_len(&self, len: usize) -> usize {
+        self.buckets.iter().copied().find(|size| *size >= len).unwrap_or(len)
+    }
+}
+
+impl Default for PaddingConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_PADDING_BUCKETS.to_vec())
+    }
+}
+
+/// Per-peer thresholds that raise and clear alerts
+///
+/// Every `interval`, each peer's loss rate and retransmit ratio over the interval and
+/// its smoothed RTT are compared with the thresholds that are set. A value rising
+/// above its threshold raises an alert and a later value at or below it clears it;
+/// both are reported once to [`EventHandler::on_alert`](crate::EventHandler::on_alert).
+/// Rates are only judged over intervals in which the peer was sent at least
+/// `min_packets` data packets, so an idle peer keeps its alerts as they are. Alerts
+/// of a peer whose state is removed are dropped without being cleared.
+///
+/// ```rust
+/// use rudpbase::{AlertConfig, RudpConfig};
+/// use std::time::Duration;
+///
+/// let config = RudpConfig::new().with_alerts(Some(AlertConfig {
+///     loss_rate: Some(0.05),
+///     rtt: Some(Duration::from_millis(300)),
+///     ..AlertConfig::default()
+/// }));
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    /// Fraction of data packets sent in an interval that timed out
+    pub loss_rate: Option<f64>,
+    /// Smoothed round-trip time
+    pub rtt: Option<Duration>,
+    /// Retransmissions per data packet sent in an interval
+    pub retransmit_ratio: Option<f64>,
+    /// Period over which rates are measured and thresholds checked
+    pub interval: Duration,
+    /// Data packets sent in an interval below which rates are not judged
+    pub min_packets: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            loss_rate: None,
+            rtt: None,
+            retransmit_ratio: None,
+            interval: DEFAULT_ALERT_INTERVAL,
+            min_packets: DEFAULT_ALERT_MIN_PACKETS,
+        }
+    }
+}
+
+/// Redundant copies of critical data packets
+///
+/// Each peer's loss rate is estimated from retransmission timeouts and NACKs against
+/// packets acknowledged on their first attempt. While the estimate is above
+/// `loss_threshold`, every packet sent with `send_critical()` is sent a second time
+/// `spacing` after the first, unless it was acknowledged or retransmitted in between,
+/// so a single loss is repaired without waiting for the retransmission timeout. The
+/// copy is not counted against the congestion window.
+///
+/// ```rust
+/// use rudpbase::{RedundancyConfig, RudpConfig};
+/// use std::time::Duration;
+///
+/// let config = RudpConfig::new().with_redundancy(RedundancyConfig {
+///     loss_threshold: 0.05,
+///     spacing: Duration::from_millis(2),
+/// });
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedundancyConfig {
+    /// Estimated fraction of lost data packets above which critical packets are
+    /// duplicated; 0 duplicates them after the first loss, 1 never
+    pub loss_threshold: f64,
+    /// Delay between a critical packet and its copy
+    pub spacing: Duration,
+}
+
+impl Default for RedundancyConfig {
+    fn default() -> Self {
+        Self {
+            loss_threshold: DEFAULT_REDUNDANCY_LOSS_THRESHOLD,
+            spacing: DEFAULT_REDUNDANCY_SPACING,
+        }
+    }
+}
+
+/// Receiver-side memory and connection limits
+///
+/// Every source that sends a valid packet gets protocol state, so without bounds a
+/// burst of spoofed sources could grow memory without limit. When `max_peers` is
+/// reached, the least recently active peer is sent a Close and its state is removed
+/// to make room for a new one, reported as `ConnectionEvent::Evicted`. When the receive queues or the reassembly memory are full, new data
+/// packets are dropped without acknowledgment, so senders retransmit them once the
+/// application has caught up; duplicates are still acknowledged. A source that sends
+/// more than `max_invalid_packets` invalid packets within `invalid_packet_window` is
+/// ignored for one window, so garbage floods get neither replies nor queued errors.
+///
+/// ```rust
+/// use rudpbase::{LimitsConfig, RudpConfig};
+///
+/// let config = RudpConfig::new().with_limits(LimitsConfig {
+///     max_peers: 256,
+///     ..LimitsConfig::default()
+/// });
+/// assert!(config.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitsConfig {
+    /// Peers with protocol state at the same time
+    pub max_peers: usize,
+    /// Bytes held by incomplete received messages across all peers, including the
+    /// per-fragment bookkeeping
+    pub max_reassembly_bytes: usize,
+    /// Received data packets and receive errors waiting for `recv()`; further
+    /// unreliable datagrams and errors are discarded
+    pub max_queued_packets: usize,
+    /// Complete messages waiting for `recv_message()`
+    pub max_queued_messages: usize,
+    /// Data packets per peer that `send()` queues while the congestion window is
+    /// full; beyond it `send()` fails like `try_send()`. 0 disables the queue
+    pub max_queued_sends: usize,
+    /// Invalid packets (failed security code, malformed, unknown type) a source may
+    /// send within `invalid_packet_window`; a source over it is ignored entirely,
+    /// valid packets included, for one window
+    pub max_invalid_packets: u64,
+    /// Period over which invalid packets are counted
+    pub invalid_packet_window: Duration,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_peers: DEFAULT_MAX_PEERS,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            max_queued_packets: DEFAULT_MAX_QUEUED_PACKETS,
+            max_queued_messages: DEFAULT_MAX_QUEUED_MESSAGES,
+            max_queued_sends: DEFAULT_MAX_QUEUED_SENDS,
+            max_invalid_packets: DEFAULT_MAX_INVALID_PACKETS,
+            invalid_packet_window: DEFAULT_INVALID_PACKET_WINDOW,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keepalive_defaults_match_constants() {
+        let config = KeepAliveConfig::default();
+        assert_eq!(config.idle_timeout, IDLE_TIMEOUT);
+        assert_eq!(config.ping_interval, PING_INTERVAL);
+        assert_eq!(config.max_ping_failures, MAX_PING_FAILURES);
+        assert_eq!(config.max_retries, MAX_RETRIES);
+    }
+
+    #[test]
+    fn test_dead_peer_detection_time() {
+        let config = KeepAliveConfig {
+            idle_timeout: Duration::from_secs(1),
+            ping_interval: Duration::from_millis(500),
+            max_ping_failures: 3,
+            max_retries: 5,
+        };
+        assert_eq!(config.dead_peer_detection_time(), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn test_rudp_config_validation() {
+        assert!(RudpConfig::default().validate().is_ok());
+
+        let inverted = RudpConfig::new().with_rto_bounds(Duration::from_secs(2), Duration::from_secs(1));
+        assert!(inverted.validate().is_err());
+
+        let zero_cwnd = RudpConfig::new().with_initial_cwnd(0);
+        assert!(zero_cwnd.validate().is_err());
+
+        let oversized = RudpConfig::new().with_max_payload_size(MAX_PAYLOAD_SIZE + 1);
+        assert!(oversized.validate().is_err());
+
+        let small_pool_buffers = RudpConfig::new().with_pool_buffer_size(512);
+        assert!(small_pool_buffers.validate().is_err());
+        assert!(small_pool_buffers.with_max_payload_size(512 - PROTOCOL_HEADER_SIZE).validate().is_ok());
+
+        let overfull_pool = RudpConfig::new().with_pool_max_capacity(DEFAULT_INITIAL_CAPACITY - 1);
+        assert!(overfull_pool.validate().is_err());
+
+        let no_batch = RudpConfig::new().with_io_batch_size(0);
+        assert!(no_batch.validate().is_err());
+
+        let unbatched_uring = RudpConfig::new().with_io_backend(IoBackend::IoUring);
+        assert!(unbatched_uring.validate().is_err());
+
+        let bad_dscp = RudpConfig::new().with_socket(SocketConfig {
+            dscp: Some(64),
+            ..SocketConfig::default()
+        });
+        assert!(bad_dscp.validate().is_err());
+
+        let no_algorithms = RudpConfig::new().with_compression(Some(CompressionConfig {
+            algorithms: Vec::new(),
+            ..CompressionConfig::default()
+        }));
+        assert!(no_algorithms.validate().is_err());
+
+        let zstd = RudpConfig::new().with_compression(Some(CompressionConfig {
+            algorithms: vec![Compression::Zstd],
+            ..CompressionConfig::default()
+        }));
+        assert_eq!(zstd.validate().is_ok(), cfg!(feature = "zstd"));
+
+        let slow_backoff = RudpConfig::new().with_backoff(BackoffConfig { multiplier: 0.5, ..BackoffConfig::default() });
+        assert!(matches!(slow_backoff.validate(), Err(RudpError::InvalidConfig { .. })));
+        let no_deadline = RudpConfig::new().with_backoff(BackoffConfig { retry_deadline: Some(Duration::ZERO), ..BackoffConfig::default() });
+        assert!(matches!(no_deadline.validate(), Err(RudpError::InvalidConfig { .. })));
+        assert!(RudpConfig::new().with_resumption(Some(ResumptionConfig::new(Vec::new()))).validate().is_err());
+        let no_lifetime = ResumptionConfig { token_lifetime: Duration::ZERO, ..ResumptionConfig::new(b"key".to_vec()) };
+        assert!(RudpConfig::new().with_resumption(Some(no_lifetime)).validate().is_err());
+
+        let no_peers = RudpConfig::new().with_limits(LimitsConfig {
+            max_peers: 0,
+            ..LimitsConfig::default()
+        });
+        assert!(no_peers.validate().is_err());
+
+        assert!(RudpConfig::new().with_max_bandwidth(Some(0)).validate().is_err());
+        assert!(RudpConfig::new().with_max_bandwidth(Some(125_000)).validate().is_ok());
+
+        let oversized_bucket = RudpConfig::new().with_padding(Some(PaddingConfig::new(vec![256, 2048])));
+        assert!(oversized_bucket.validate().is_err());
+        let small_payloads = RudpConfig::new().with_max_payload_size(500).with_padding(Some(PaddingConfig::new(vec![256, 512])));
+        assert!(small_payloads.validate().is_err());
+
+        let no_spacing = RudpConfig::new().with_redundancy(RedundancyConfig { spacing: Duration::ZERO, ..RedundancyConfig::default() });
+        assert!(no_spacing.validate().is_err());
+        let nan_threshold = RudpConfig::new().with_redundancy(RedundancyConfig { loss_threshold: f64::NAN, ..RedundancyConfig::default() });
+        assert!(nan_threshold.validate().is_err());
+    }
+}
@@ -1,141 +1,2319 @@
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::io::{self, IoSlice};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::{pin, Pin};
+use std::task::{ready, Context, Poll};
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::time;
-
-use crate::error::RudpError;
-use crate::stats::{ConnectionStats, ConnectionStatus, RttStats, ConnectionState, CongestionInfo};
-use crate::protocol::{PacketType, RawPacket, PingPacket, DataAckPacket, DataNackPacket, MAX_BUFFER_SIZE};
-use crate::security::SecurityCode;
-use crate::buffer_pool::{SharedBufferPool, PooledBuffer, DEFAULT_INITIAL_CAPACITY};
-
-/// 接收数据结构
-pub struct ReceivedData {
-    /// Data source address
-    pub from: SocketAddr,
-    /// Reception result
-    pub result: Result<PooledBuffer, RudpError>,
-}
+use tokio::time::{self, Sleep};
 
-/// Pending packet structure for retransmission
-#[derive(Debug)]
-struct PendingPacket {
-    /// Packet buffer (holds the actual memory from pool)
-    buffer: PooledBuffer,
-    /// Send timestamp
-    send_time: Instant,
-    /// Retry count
-    retry_count: u8,
-    /// Current RTO for this packet
-    rto: Duration,
-}
+use crate::config::{BackoffConfig, IoBackend, KeepAliveConfig, RudpConfig};
+use crate::engine::RudpCore;
+use crate::error::{ConnectionError, RudpError};
+use crate::events::{EventHandler, Verdict};
+use crate::qlog::QlogSink;
+use crate::report::StatsSink;
+use crate::keys::PeerKey;
+#[cfg(feature = "identity")]
+use crate::identity::{IdentityKey, PeerIdentity};
+#[cfg(feature = "otel")]
+use crate::otel::Telemetry;
+use crate::stats::{ConnectionStats, ConnectionStatus, CongestionInfo, GlobalStats, InvalidPacketStats, OneWayDelay, ClockOffset, RttStats};
+use crate::buffer_pool::{SharedBufferPool, PooledBuffer, DEFAULT_BUFFER_SIZE};
+use crate::stun::{self, BindingRequest, STUN_INITIAL_RTO, STUN_MAX_ATTEMPTS};
+use crate::batch::{self, RecvBatch, UdpOffload};
+use crate::uring::UringDriver;
+use crate::socket;
+use crate::transport::{self, Transport};
+use crate::clock::{Clock, SystemClock};
+use crate::delivery::{DeliveryHandle, PingHandle};
+use crate::resumption::ResumptionToken;
+use crate::protocol::CloseReason;
+use crate::message::ReceivedMessage;
+use crate::peer_id::PeerId;
 
-impl PendingPacket {
-    fn new(buffer: PooledBuffer, rto: Duration) -> Self {
-        Self {
-            buffer,
-            send_time: Instant::now(),
-            retry_count: 0,
-            rto,
-        }
-    }
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
 
-    fn should_retry(&self, now: Instant) -> bool {
-        now.duration_since(self.send_time) >= self.rto
-    }
+pub use crate::engine::ReceivedData;
 
-    fn retry(&mut self, rto: Duration) {
-        self.retry_count += 1;
-        self.send_time = Instant::now();
-        self.rto = rto;
-    }
+/// 定时任务在截止时间之后才触发，`recv()`等待core定时器时多等这么久
+const TIMER_SLACK: Duration = Duration::from_millis(1);
 
-    /// 获取完整的数据包内容（包含协议头）
-    fn packet_data(&self) -> &[u8] {
-        self.buffer.full_data()
-    }
+/// 读取传输层时在什么情况下停止
+#[derive(Debug, Clone, Copy)]
+enum ReadUntil {
+    /// 接收队列中有数据，`Some`时只看来自该地址的数据
+    Received(Option<SocketAddr>),
+    /// 读完当前所有可读的数据报
+    Drained,
 }
 
+/// `connect()`在返回前测得的RTT样本数
+const CONNECT_PINGS: usize = 3;
+
+/// `connect_any()`等待一个地址应答多久后同时尝试下一个地址（RFC 8305的推荐值）
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 /// Main Rudpbase structure
 /// 
+/// A tokio wrapper around the socket-free [`RudpCore`]: it reads datagrams from the
+/// transport into the core, sends the datagrams the core produces and supplies the
+/// current time from its [`Clock`].
+/// 
 /// Note: This structure is NOT thread-safe. It should be used within a single thread
 /// or protected by appropriate synchronization mechanisms (e.g., Mutex, RwLock).
 /// For multi-threaded usage, consider wrapping in Arc<Mutex<Rudpbase>>.
 pub struct Rudpbase {
-    /// UDP socket
-    socket: UdpSocket,
-    /// Send buffer: [target_addr][seq] -> (buffer, send_time, retry_count)
-    send_buffer: HashMap<SocketAddr, HashMap<u32, PendingPacket>>,
-    /// Receive buffer: [source_addr] -> received seq set
-    recv_acks: HashMap<SocketAddr, HashSet<u32>>,
-    /// Next sequence number for each target
-    next_seq: HashMap<SocketAddr, u32>,
-    /// RTT statistics for each connection
-    rtt_stats: HashMap<SocketAddr, RttStats>,
-    /// Connection statistics
-    connection_stats: HashMap<SocketAddr, ConnectionStats>,
-    /// Connection states
-    connection_states: HashMap<SocketAddr, ConnectionState>,
-    /// Pending ACKs to be sent
-    pending_acks: HashMap<SocketAddr, Vec<u32>>,
-    /// Last cleanup time
-    last_cleanup: Instant,
-    /// Shared buffer pool for memory management
-    buffer_pool: SharedBufferPool,
+    /// Datagram transport (a UDP socket unless supplied by the user)
+    transport: Box<dyn Transport>,
+    /// Time source for timers and timestamps
+    clock: Box<dyn Clock>,
+    /// Protocol state machine
+    core: RudpCore,
+    /// Per-peer local source address overrides
+    source_addrs: HashMap<SocketAddr, IpAddr>,
+    /// Receive buffers for batched I/O
+    rx_batch: RecvBatch,
+    /// UDP offloads accepted by the kernel
+    offload: UdpOffload,
+    /// io_uring backend, replacing socket calls for batched I/O
+    uring: Option<UringDriver>,
+    /// Wakes `poll_recv` for the core timer due at the given time
+    timer: Option<(Instant, Pin<Box<Sleep>>)>,
 }
 
+
 impl Rudpbase {
     /// Create a new Rudpbase instance
     pub async fn new(local_addr: SocketAddr) -> Result<Self, RudpError> {
-        let socket = UdpSocket::bind(local_addr).await?;
-        
-        // 创建内存池并自动预热
-        let buffer_pool = SharedBufferPool::default();
-        // 预热内存池，预分配一些buffer以提高性能
-        buffer_pool.warmup(DEFAULT_INITIAL_CAPACITY)?;
+        Self::with_config(local_addr, RudpConfig::default()).await
+    }
+
+    /// 使用自定义配置创建Rudpbase实例
+    /// 
+    /// 配置在创建时校验，包括RTO上下限、初始拥塞窗口、内存池大小、最大重传次数、
+    /// 心跳参数、单包最大载荷和安全码选项
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let config = RudpConfig::new()
+    ///         .with_rto_bounds(Duration::from_millis(50), Duration::from_secs(5))
+    ///         .with_initial_rto(Duration::from_millis(100))
+    ///         .with_max_retries(8);
+    ///     let rudp = Rudpbase::with_config("127.0.0.1:8080".parse()?, config).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn with_config(local_addr: SocketAddr, config: RudpConfig) -> Result<Self, RudpError> {
+        config.validate()?;
+        let socket = socket::bind(local_addr, &config.socket)?;
+        Self::with_transport(socket, config).await
+    }
+
+    /// 使用自定义传输层创建Rudpbase实例
+    /// 
+    /// 可接入模拟网络（确定性测试重传逻辑）、用户态网络栈或加密隧道。
+    /// `config.socket`中的socket选项只在`with_config`创建socket时使用。
+    /// 没有底层UDP socket的传输层（`Transport::udp_socket`返回`None`）不支持批量I/O，
+    /// `io_batch_size`必须为1
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    /// use tokio::net::UdpSocket;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let socket = UdpSocket::bind("127.0.0.1:8080").await?;
+    ///     let rudp = Rudpbase::with_transport(socket, RudpConfig::default()).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn with_transport(transport: impl Transport, config: RudpConfig) -> Result<Self, RudpError> {
+        config.validate()?;
+        // 按配置创建内存池并预热，预分配一些buffer以提高性能
+        let buffer_pool = SharedBufferPool::with_limits(
+            config.pool_initial_capacity,
+            config.pool_max_capacity,
+            config.pool_buffer_size,
+        );
+        Self::with_transport_and_pool(transport, config, buffer_pool).await
+    }
+
+    /// 使用已绑定的tokio socket创建Rudpbase实例
+    /// 
+    /// socket由调用方创建和配置，`config.socket`中的socket选项不再应用
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    /// use tokio::net::UdpSocket;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let socket = UdpSocket::bind("127.0.0.1:8080").await?;
+    ///     socket.set_ttl(16)?;
+    ///     let rudp = Rudpbase::from_socket(socket, RudpConfig::default()).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn from_socket(socket: UdpSocket, config: RudpConfig) -> Result<Self, RudpError> {
+        Self::with_transport(socket, config).await
+    }
+
+    /// 使用已绑定的标准库socket创建Rudpbase实例
+    /// 
+    /// 接受`std::net::UdpSocket`以及可以转换为它的`socket2::Socket`，用于在交给rudpbase
+    /// 之前设置`SO_REUSEPORT`等选项，或使用systemd等进程传入的文件描述符。
+    /// socket会被设为非阻塞模式，`config.socket`中的socket选项不再应用。
+    /// 必须在tokio运行时中调用
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    /// use socket2::{Domain, Protocol, Socket, Type};
+    /// use std::net::SocketAddr;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let addr: SocketAddr = "127.0.0.1:8080".parse()?;
+    ///     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    ///     socket.set_reuse_port(true)?;
+    ///     socket.bind(&addr.into())?;
+    ///     let rudp = Rudpbase::from_std(socket, RudpConfig::default()).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn from_std(socket: impl Into<std::net::UdpSocket>, config: RudpConfig) -> Result<Self, RudpError> {
+        let socket = socket.into();
+        socket.set_nonblocking(true)?;
+        Self::from_socket(UdpSocket::from_std(socket)?, config).await
+    }
+
+    /// 使用共享内存池创建Rudpbase实例
+    /// 
+    /// 多个实例共享同一个内存池时，一个实例释放的buffer可以被其他实例复用，
+    /// 总内存占用由内存池的容量统一控制。`config`中的`pool_*`参数被忽略，
+    /// 内存池的默认buffer必须能容纳`max_payload_size`字节的数据
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig, SharedBufferPool};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let pool = SharedBufferPool::new(2000);
+    ///     let first = Rudpbase::with_shared_pool("127.0.0.1:8080".parse()?, RudpConfig::default(), pool.clone()).await?;
+    ///     let second = Rudpbase::with_shared_pool("127.0.0.1:8081".parse()?, RudpConfig::default(), pool).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn with_shared_pool(local_addr: SocketAddr, config: RudpConfig, pool: SharedBufferPool) -> Result<Self, RudpError> {
+        config.validate()?;
+        let socket = socket::bind(local_addr, &config.socket)?;
+        Self::with_transport_and_pool(socket, config, pool).await
+    }
+
+    /// 使用自定义传输层和共享内存池创建Rudpbase实例
+    /// 
+    /// 参见[`with_transport`](Self::with_transport)和[`with_shared_pool`](Self::with_shared_pool)
+    pub async fn with_transport_and_pool(transport: impl Transport, config: RudpConfig, buffer_pool: SharedBufferPool) -> Result<Self, RudpError> {
+        let transport: Box<dyn Transport> = Box::new(transport);
+        check_transport(&*transport, &config)?;
+        let clock: Box<dyn Clock> = Box::new(SystemClock);
+        let core = RudpCore::with_pool(config, buffer_pool)?;
+        let config = core.config();
+        let offload = match transport.udp_socket() {
+            Some(socket) => UdpOffload::configure(socket, config.offload_requested()),
+            None => UdpOffload::default(),
+        };
+
+        let uring = match config.io_backend {
+            IoBackend::Socket => None,
+            IoBackend::IoUring => Some(UringDriver::new(udp_socket(&*transport), core.buffer_pool(), config.io_batch_size)?),
+        };
         
         Ok(Self {
-            socket,
-            send_buffer: HashMap::new(),
-            recv_acks: HashMap::new(),
-            next_seq: HashMap::new(),
-            rtt_stats: HashMap::new(),
-            connection_stats: HashMap::new(),
-            connection_states: HashMap::new(),
-            pending_acks: HashMap::new(),
-            last_cleanup: Instant::now(),
-            buffer_pool,
+            rx_batch: RecvBatch::new(config.io_batch_size, offload.gro),
+            transport,
+            clock,
+            core,
+            source_addrs: HashMap::new(),
+            offload,
+            uring,
+            timer: None,
         })
     }
 
     /// Close the Rudpbase instance and clean up all resources
+    /// 
+    /// Unacknowledged data is dropped; use [`close_graceful`](Self::close_graceful)
+    /// to deliver it first.
     pub async fn close(&mut self) {
-        // Send close packets to all active connections
-        let connections: Vec<SocketAddr> = self.connection_states.keys().cloned().collect();
-        
-        for addr in connections {
-            let _ = self.send_close_packet(addr).await;
+        // Send what is already queued, then close packets to all active connections
+        let _ = self.flush_transmits().await;
+        self.core.close();
+        let _ = self.flush_transmits().await;
+    }
+
+    /// Like [`close`](Self::close), with `reason` in the Close packets; peers receive it
+    /// as [`ConnectionEvent::Closed`](crate::ConnectionEvent::Closed)
+    pub async fn close_with_reason(&mut self, reason: CloseReason) -> Result<(), RudpError> {
+        let _ = self.flush_transmits().await;
+        self.core.close_with_reason(reason)?;
+        self.flush_transmits().await
+    }
+
+    /// 向`addr`发送携带`reason`的Close包并清理它的连接状态，其他连接不受影响
+    /// 
+    /// 发往`addr`的未确认数据被丢弃，之后2倍RTO内对端迟到的旧会话包只会收到Close；对端以[`ConnectionEvent::Closed`](crate::ConnectionEvent::Closed)
+    /// 收到关闭原因。载荷超过[`MAX_CLOSE_PAYLOAD`](crate::MAX_CLOSE_PAYLOAD)时返回
+    /// `RudpError::BufferTooLarge`
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{CloseReason, Rudpbase};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let received = rudp.recv().await;
+    ///     let reason = CloseReason::new(CloseReason::BANNED).with_payload("too many requests");
+    ///     rudp.close_peer(received.from, reason).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn close_peer(&mut self, addr: SocketAddr, reason: CloseReason) -> Result<(), RudpError> {
+        self.core.close_peer(addr, reason, self.clock.now())?;
+        self.flush_transmits().await
+    }
+
+    /// 停止向`addr`发送数据，继续接收它的数据（半关闭）
+    /// 
+    /// 之后发往`addr`的发送返回`ConnectionError::SendShutdown`。已发送的数据全部被确认后
+    /// 对端收到[`ConnectionEvent::Finished`](crate::ConnectionEvent::Finished)，此时它已收到
+    /// 本端发送的全部数据
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let server = "127.0.0.1:8081".parse()?;
+    ///     for chunk in [&b"part 1"[..], b"part 2"] {
+    ///         rudp.send_message(chunk, server).await?;
+    ///     }
+    ///     // 上传结束，继续等待响应
+    ///     rudp.shutdown_send(server).await?;
+    ///     if let Some(response) = rudp.recv_message().await {
+    ///         println!("response: {} bytes", response.data.len());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn shutdown_send(&mut self, addr: SocketAddr) -> Result<(), RudpError> {
+        self.core.shutdown_send(addr, self.clock.now())?;
+        self.flush_transmits().await
+    }
+
+    /// `addr`是否已停止向本端发送数据（见[`shutdown_send`](Self::shutdown_send)）
+    pub fn peer_finished(&self, addr: SocketAddr) -> bool {
+        self.core.peer_finished(addr)
+    }
+
+    /// 发送完所有未确认的数据后再关闭实例
+    /// 
+    /// 调用后不再接受新的发送（返回`RudpError::Closing`），已发送未确认的数据包和
+    /// 排队中的消息分片继续按正常方式重传，直到全部被确认或超过`timeout`，然后像
+    /// [`close`](Self::close)一样发送Close包并清理状态。等待期间收到的数据保留给
+    /// `recv()`和`recv_message()`
+    /// 
+    /// # 返回
+    /// - `Ok(())`: 所有数据都已被确认
+    /// - `Err(RudpError::Timeout)`: 超时，剩余未确认的数据被丢弃
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     rudp.send_message(b"last words", "127.0.0.1:8081".parse()?).await?;
+    ///     rudp.close_graceful(Duration::from_secs(5)).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn close_graceful(&mut self, timeout: Duration) -> Result<(), RudpError> {
+        self.core.set_closing(true);
+        let deadline = self.clock.now() + timeout;
+        let mut flushed = false;
+        loop {
+            self.tick().await;
+            if self.core.total_unacked_packets() == 0 {
+                flushed = true;
+                break;
+            }
+            if self.clock.now() >= deadline {
+                break;
+            }
+            self.poll_incoming().await;
         }
+        trace_event!(debug, flushed, "graceful close");
+
+        self.close().await;
+        self.core.set_closing(false);
+        if flushed {
+            Ok(())
+        } else {
+            Err(RudpError::Timeout)
+        }
+    }
+
+    /// 等待发往`addr`的数据全部被确认
+    /// 
+    /// 期间持续调用`tick()`并读取到达的包，收到的数据保留给`recv()`和`recv_message()`。
+    /// 与只发出批量I/O队列的[`flush`](Self::flush)不同，本方法等到对端确认为止，
+    /// 适合"发送后关闭"的场景，无需猜测等待时间
+    /// 
+    /// # 返回
+    /// - `Ok(())`: 发往`addr`的数据包和排队的消息分片都已被确认
+    /// - `Err(RudpError::Connection(ConnectionError::MaxRetriesExceeded))`: 等待期间有数据包
+    ///   重传次数耗尽或连接被判定断开，数据未能送达
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let target = "127.0.0.1:8081".parse()?;
+    ///     rudp.send_message(b"done", target).await?;
+    ///     rudp.wait_acked(target).await?;
+    ///     rudp.close().await;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn wait_acked(&mut self, addr: SocketAddr) -> Result<(), RudpError> {
+        self.wait_until_acked(Some(addr)).await
+    }
+
+    /// 等待发往所有对端的数据全部被确认
+    /// 
+    /// 任一对端的数据未能送达时返回错误，详见[`wait_acked`](Self::wait_acked)
+    pub async fn wait_all_acked(&mut self) -> Result<(), RudpError> {
+        self.wait_until_acked(None).await
+    }
+
+    async fn wait_until_acked(&mut self, addr: Option<SocketAddr>) -> Result<(), RudpError> {
+        let failures_before = self.core.failed_deliveries().clone();
+        loop {
+            self.tick().await;
+            let failed = self.core.failed_deliveries().iter().find(|(peer, count)| {
+                addr.is_none_or(|addr| addr == **peer) && failures_before.get(peer) != Some(count)
+            });
+            if let Some((&peer, _)) = failed {
+                return Err(ConnectionError::MaxRetriesExceeded { addr: peer }.into());
+            }
+            let pending = match addr {
+                Some(addr) => self.core.unacked_packets(addr),
+                None => self.core.total_unacked_packets(),
+            };
+            if pending == 0 {
+                return Ok(());
+            }
+            self.poll_incoming().await;
+        }
+    }
+
+    /// 在发送数据之前与`addr`建立连接
+    /// 
+    /// 协议本身无需握手即可发送，但第一个数据包会用尚未测量的初始RTO（默认200ms）
+    /// 和未协商的压缩参数发出。本方法先与对端交换ping：协商压缩算法、建立连接状态
+    /// 并开始保活，再用多个RTT样本初始化RTO和拥塞状态，之后的发送不再有首包延迟尖峰
+    /// 
+    /// `addr`之前被判定为断开时会先清除该状态。丢失的ping会重发，连续
+    /// `max_ping_failures`个ping超时（见[`KeepAliveConfig`](crate::KeepAliveConfig)）
+    /// 则放弃
+    /// 
+    /// # 返回
+    /// - `Ok(Duration)`: 连接已建立，返回平滑后的RTT
+    /// - `Err(RudpError::Timeout)`: 对端没有应答
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let server = "127.0.0.1:8081".parse()?;
+    ///     let rtt = rudp.connect(server).await?;
+    ///     println!("connected to {}, RTT {:?}", server, rtt);
+    ///     rudp.send_message(b"hello", server).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn connect(&mut self, addr: SocketAddr) -> Result<Duration, RudpError> {
+        self.core.connect(addr, self.clock.now())?;
+        let max_failures = self.core.peer_keepalive_config(addr).max_ping_failures.max(1);
+        let (mut answered, mut failures) = (0, 0);
+        while answered < CONNECT_PINGS {
+            match self.ping(addr).await {
+                Ok(_) => {
+                    answered += 1;
+                    failures = 0;
+                }
+                Err(RudpError::Timeout) if failures + 1 < max_failures => failures += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        trace_event!(debug, %addr, "connected");
+        Ok(self.core.rtt_stats(addr).map_or(Duration::ZERO, |rtt_stats| rtt_stats.srtt))
+    }
+
+    /// 解析`host`（如`"example.com:9000"`）并与最先应答的地址建立连接
+    /// 
+    /// 解析出的地址IPv6与IPv4交替、IPv6在前排列，按[`connect_any`](Self::connect_any)
+    /// 依次尝试。只尝试本端socket能发送的地址族：绑定IPv4地址时只用IPv4地址，
+    /// 绑定IPv6通配地址时IPv4地址以IPv4映射的IPv6地址发送
+    /// 
+    /// # 返回
+    /// - `Ok((SocketAddr, Duration))`: 建立连接的地址和平滑后的RTT，之后用这个地址收发
+    /// - `Err(RudpError::Io)`: 解析失败，或没有本端能发送的地址
+    /// - `Err(RudpError::Timeout)`: 所有地址都没有应答
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("[::]:0".parse()?).await?;
+    ///     let (server, rtt) = rudp.connect_host("example.com:9000").await?;
+    ///     println!("connected to {}, RTT {:?}", server, rtt);
+    ///     rudp.send_message(b"hello", server).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn connect_host(&mut self, host: &str) -> Result<(SocketAddr, Duration), RudpError> {
+        let local_addr = self.local_addr()?;
+        let candidates = connection_candidates(tokio::net::lookup_host(host).await?, local_addr);
+        if candidates.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, format!("{} has no address reachable from {}", host, local_addr)).into());
+        }
+        self.connect_any(&candidates).await
+    }
+
+    /// 依次尝试`candidates`中的地址，与最先应答的地址建立连接
+    /// 
+    /// 先向第一个地址发送ping，每过250ms仍没有任何应答就再尝试下一个地址，之前的尝试
+    /// 继续进行（Happy Eyeballs，RFC 8305）。每个地址的ping超时处理与
+    /// [`connect`](Self::connect)相同。最先应答的地址按`connect`完成连接，会话固定
+    /// 使用它；其他尝试过的地址的连接状态被清除并收到Close，应答较晚的对端也不会
+    /// 保留多余的会话
+    /// 
+    /// # 返回
+    /// - `Ok((SocketAddr, Duration))`: 建立连接的地址和平滑后的RTT
+    /// - `Err(RudpError::Timeout)`: 所有地址都没有应答
+    pub async fn connect_any(&mut self, candidates: &[SocketAddr]) -> Result<(SocketAddr, Duration), RudpError> {
+        let mut attempts: Vec<(SocketAddr, PingHandle, u8)> = Vec::new();
+        let mut started = 0;
+        let mut next_attempt = self.clock.now();
+        let winner = loop {
+            let now = self.clock.now();
+            if started < candidates.len() && (attempts.is_empty() || now >= next_attempt) {
+                let addr = candidates[started];
+                started += 1;
+                next_attempt = now + CONNECTION_ATTEMPT_DELAY;
+                self.core.connect(addr, now)?;
+                match self.core.ping(addr, now) {
+                    Ok(handle) => attempts.push((addr, handle, 0)),
+                    Err(_e) => trace_event!(debug, %addr, error = %_e, "connection attempt failed"),
+                }
+                let _ = self.transmit().await;
+                continue;
+            }
+            if attempts.is_empty() {
+                return Err(RudpError::Timeout);
+            }
+
+            self.tick().await;
+            // 从后往前检查，同一轮有多个应答时取最先尝试的地址
+            let mut answered = None;
+            for index in (0..attempts.len()).rev() {
+                let (addr, failures) = (attempts[index].0, attempts[index].2);
+                match attempts[index].1.try_result() {
+                    None => {}
+                    Some(Ok(_)) => answered = Some(addr),
+                    Some(Err(RudpError::Timeout)) if failures + 1 < self.core.peer_keepalive_config(addr).max_ping_failures.max(1) => {
+                        match self.core.ping(addr, self.clock.now()) {
+                            Ok(handle) => attempts[index] = (addr, handle, failures + 1),
+                            Err(_) => {
+                                attempts.swap_remove(index);
+                            }
+                        }
+                    }
+                    Some(Err(_e)) => {
+                        trace_event!(debug, %addr, error = %_e, "connection attempt failed");
+                        attempts.swap_remove(index);
+                    }
+                }
+            }
+            if let Some(addr) = answered {
+                break addr;
+            }
+            let _ = self.transmit().await;
+            self.poll_incoming().await;
+        };
+
+        let now = self.clock.now();
+        for &addr in &candidates[..started] {
+            if addr != winner {
+                self.core.close_peer(addr, CloseReason::default(), now)?;
+            }
+        }
+        let rtt = self.connect(winner).await?;
+        Ok((winner, rtt))
+    }
+
+    /// `addr`最近一次签发给本端的恢复令牌
+    /// 
+    /// 对端开启了`resumption`（见[`ResumptionConfig`](crate::ResumptionConfig)）时，
+    /// [`connect`](Self::connect)和保活ping都会收到新令牌。令牌在连接结束和实例关闭后
+    /// 仍然保留，可以用[`ResumptionToken::as_bytes`]保存
+    pub fn resumption_token(&self, addr: SocketAddr) -> Option<&ResumptionToken> {
+        self.core.resumption_token(addr)
+    }
+
+    /// 用恢复令牌重新连接`addr`，之后发送的第一个数据包即可被对端交付（0-RTT）
+    /// 
+    /// 不发送任何数据包，也不等待对端。第一个可靠数据包携带令牌，对端验证后丢弃
+    /// 上一次会话的接收状态并立即交付该数据；本端用令牌中上一次会话的RTT代替初始RTO。
+    /// 令牌无效、过期或已用过时，数据包按普通数据包处理
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{ResumptionToken, Rudpbase};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let server = "127.0.0.1:8081".parse()?;
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     match std::fs::read("session.token").ok().and_then(|bytes| ResumptionToken::from_bytes(&bytes)) {
+    ///         Some(token) => rudp.resume(server, token)?,
+    ///         None => {
+    ///             rudp.connect(server).await?;
+    ///         }
+    ///     }
+    ///     rudp.send_message(b"request", server).await?;
+    ///     
+    ///     // 保存新令牌，供下一次启动使用
+    ///     if let Some(token) = rudp.resumption_token(server) {
+    ///         std::fs::write("session.token", token.as_bytes())?;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn resume(&mut self, addr: SocketAddr, token: ResumptionToken) -> Result<(), RudpError> {
+        self.core.resume(addr, token, self.clock.now())
+    }
+
+    /// 导出每个对端的序列号、接收窗口、未确认数据、RTT和恢复令牌
+    /// 
+    /// 受监管的进程重启前调用，新进程用[`import_state`](Self::import_state)恢复后，
+    /// 对端不会判定连接断开，也不会把新数据包当成重复包。尚未发出的消息分片和
+    /// 未重组完的消息不包含在内，先用[`wait_all_acked`](Self::wait_all_acked)
+    /// 等待发送完成可以避免丢失。详见[`RudpCore::export_state`]
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let addr = "127.0.0.1:8080".parse()?;
+    ///     let mut rudp = Rudpbase::new(addr).await?;
+    ///     if let Ok(state) = std::fs::read("rudp.state") {
+    ///         rudp.import_state(&state)?;
+    ///     }
+    ///     
+    ///     // ... 正常收发 ...
+    ///     
+    ///     // 重启之前
+    ///     rudp.wait_all_acked().await?;
+    ///     std::fs::write("rudp.state", rudp.export_state())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn export_state(&self) -> Vec<u8> {
+        self.core.export_state()
+    }
+
+    /// 恢复[`export_state`](Self::export_state)导出的状态，返回恢复的对端数量
+    /// 
+    /// 需要在同一地址上、用相同的安全配置创建实例。未确认的数据包在下一次`tick()`时重新发送
+    pub fn import_state(&mut self, data: &[u8]) -> Result<usize, RudpError> {
+        self.core.import_state(data, self.clock.now())
+    }
+
+    /// 向`addr`发送一个ping并等待应答，返回测得的往返时间
+    /// 
+    /// 可以在发送数据之前探测路径是否可用。等待期间持续调用`tick()`并读取到达的包，
+    /// 收到的数据保留给`recv()`和`recv_message()`。ping不重传，在该对端的
+    /// `ping_interval`（见[`KeepAliveConfig`](crate::KeepAliveConfig)）内没有应答即超时
+    /// 
+    /// # 返回
+    /// - `Ok(Duration)`: 从发送ping到收到应答的时间
+    /// - `Err(RudpError::Timeout)`: 对端没有及时应答
+    /// - `Err(RudpError::Connection(ConnectionError::Dead))`: 对端已被判定断开
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let target = "127.0.0.1:8081".parse()?;
+    ///     match rudp.ping(target).await {
+    ///         Ok(rtt) => println!("{} reachable, RTT {:?}", target, rtt),
+    ///         Err(e) => println!("{} unreachable: {}", target, e),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn ping(&mut self, addr: SocketAddr) -> Result<Duration, RudpError> {
+        let mut handle = self.core.ping(addr, self.clock.now())?;
+        self.transmit().await?;
+        loop {
+            self.tick().await;
+            if let Some(result) = handle.try_result() {
+                return result;
+            }
+            self.poll_incoming().await;
+        }
+    }
+
+    /// 获取一个用于写入的buffer
+    /// 
+    /// 从内存池中获取一个预分配的buffer，用户可以直接写入数据区域
+    /// 协议头空间已预留，用户无需关心
+    /// 
+    /// # 返回
+    /// - `Ok(PooledBuffer)`: 可用的buffer，用户可以写入数据区
+    /// - `Err(RudpError)`: 获取失败
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     
+    ///     // 写入用户数据
+    ///     let data = b"Hello, world!";
+    ///     buffer.data_mut()[..data.len()].copy_from_slice(data);
+    ///     buffer.set_data_len(data.len())?;
+    ///     
+    ///     // 发送数据
+    ///     let target_addr = "127.0.0.1:8081".parse().unwrap();
+    ///     rudp.send(buffer, target_addr).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
+        self.core.get_buffer()
+    }
+
+    /// 获取能容纳 `len` 字节用户数据的最小buffer
+    /// 
+    /// 与[`get_buffer`](Self::get_buffer)相同，但按数据长度选择内存池的大小等级，
+    /// 发送大量小消息时可以减少内存占用。发送时数据长度仍受 `max_payload_size` 限制
+    pub fn get_buffer_for(&self, len: usize) -> Result<PooledBuffer, RudpError> {
+        self.core.get_buffer_for(len)
+    }
+
+    /// 发送数据
+    /// 
+    /// 这是零拷贝的发送方法，直接使用预分配的buffer
+    /// 
+    /// **重要**: 此方法包含拥塞控制，如果当前拥塞窗口已满，数据包在该对端的发送队列中
+    /// 排队，随后在`recv()`/`tick()`处理ACK、窗口打开时发出。需要立即发送或失败时使用
+    /// [`try_send`](Self::try_send)
+    /// 
+    /// # 参数
+    /// - `buffer`: 包含数据的内存池buffer
+    /// - `target`: 目标地址
+    /// 
+    /// # 返回
+    /// - `Ok(seq)`: 已发送或已排队，返回分配给该数据包的序列号，对端在`ReceivedData::seq`中收到同一序列号
+    /// - `Err(RudpError::CongestionWindowFull)`: 发送队列已满（`limits.max_queued_sends`），请稍后重试
+    /// - `Err(RudpError::Connection(ConnectionError::Dead))`: 对端已被判定断开，
+    ///   见[`clear_dead_peer`](Self::clear_dead_peer)
+    /// - `Err(RudpError)`: 其他发送失败原因
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     
+    ///     // 填充数据
+    ///     let data = b"Hello, world!";
+    ///     buffer.data_mut()[..data.len()].copy_from_slice(data);
+    ///     buffer.set_data_len(data.len())?;
+    ///     
+    ///     // 发送（拥塞窗口满时排队，发送队列也满时失败）
+    ///     let target_addr = "127.0.0.1:8081".parse().unwrap();
+    ///     match rudp.send(buffer, target_addr).await {
+    ///         Ok(seq) => println!("发送成功，序列号 {}", seq),
+    ///         Err(rudpbase::RudpError::CongestionWindowFull) => {
+    ///             println!("发送队列已满，请稍后重试");
+    ///             // 可以等待一段时间后重试，或者调用tick()处理ACK
+    ///         }
+    ///         Err(e) => println!("发送失败: {}", e),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self, buffer), fields(len = buffer.data_len())))]
+    pub async fn send(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.send(buffer, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 向`peer`当前所在的地址发送数据，对端迁移到新地址后仍然发给它
+    /// 
+    /// 返回值与[`send`](Self::send)相同；找不到`peer`的地址时返回
+    /// `ConnectionError::UnknownPeer`
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let server = "127.0.0.1:8081".parse()?;
+    ///     rudp.connect(server).await?;
+    ///     let peer = rudp.peer_id(server).ok_or("no peer id")?;
+    ///     
+    ///     // 之后按PeerId发送，不关心对端的地址是否变化
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..5].copy_from_slice(b"hello");
+    ///     buffer.set_data_len(5)?;
+    ///     rudp.send_to_peer(buffer, peer).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_to_peer(&mut self, buffer: PooledBuffer, peer: PeerId) -> Result<u32, RudpError> {
+        let seq = self.core.send_to_peer(buffer, peer, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 立即发出等待合并的小载荷（见`RudpConfig::coalesce_delay`），用于对延迟敏感的消息
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let config = RudpConfig::new().with_coalesce_delay(Some(Duration::from_millis(5)));
+    ///     let mut rudp = Rudpbase::with_config("127.0.0.1:8080".parse()?, config).await?;
+    ///     let target = "127.0.0.1:8081".parse()?;
+    ///     for input in [&b"move left"[..], b"move up", b"fire"] {
+    ///         let mut buffer = rudp.get_buffer_for(input.len())?;
+    ///         buffer.data_mut()[..input.len()].copy_from_slice(input);
+    ///         buffer.set_data_len(input.len())?;
+    ///         rudp.send(buffer, target).await?;
+    ///     }
+    ///     // 三条输入在一个包中立即发出
+    ///     rudp.flush_now(target).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn flush_now(&mut self, target: SocketAddr) -> Result<(), RudpError> {
+        self.core.flush_now(target, self.clock.now())?;
+        self.flush_transmits().await
+    }
+
+    /// 立即发送数据，不排队
+    /// 
+    /// 与[`send`](Self::send)相同，但拥塞窗口已满时直接返回
+    /// `RudpError::CongestionWindowFull`，buffer被丢弃
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpError};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..5].copy_from_slice(b"frame");
+    ///     buffer.set_data_len(5)?;
+    ///     
+    ///     // 实时数据：窗口已满时丢弃这一帧，而不是排在旧数据后面
+    ///     match rudp.try_send(buffer, "127.0.0.1:8081".parse().unwrap()).await {
+    ///         Ok(_) | Err(RudpError::CongestionWindowFull) => {}
+    ///         Err(e) => return Err(e.into()),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn try_send(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.try_send(buffer, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 发送关键数据，丢包严重时发送两份
+    /// 
+    /// 与[`send`](Self::send)相同，但不参与合并；对端的估计丢包率超过
+    /// `redundancy.loss_threshold`时，间隔`redundancy.spacing`再发送一份副本，
+    /// 单个丢包不必等待重传超时。副本的效果见`ConnectionStats::redundant_copies`和
+    /// `redundant_recoveries`
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let target = "127.0.0.1:8081".parse().unwrap();
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..4].copy_from_slice(b"fire");
+    ///     buffer.set_data_len(4)?;
+    ///     rudp.send_critical(buffer, target).await?;
+    ///     
+    ///     if let Some(stats) = rudp.get_stats(target) {
+    ///         println!("{} copies, {} delivered by the copy", stats.redundant_copies, stats.redundant_recoveries);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_critical(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.send_critical(buffer, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 发送数据并跟踪该数据包的送达结果
+    /// 
+    /// 与[`send`](Self::send)相同，另外返回一个[`DeliveryHandle`]，在对端确认该序列号时
+    /// 完成为`Ok(())`，重传次数耗尽、连接断开或实例关闭时完成为错误。应用可以据此实现
+    /// 至少一次（at-least-once）语义，而无需自行记录序列号
+    /// 
+    /// 结果在`tick()`/`recv()`中产生，等待handle期间需要继续驱动实例
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..5].copy_from_slice(b"order");
+    ///     buffer.set_data_len(5)?;
+    ///     let mut delivery = rudp.send_tracked(buffer, "127.0.0.1:8081".parse()?).await?;
+    ///     
+    ///     loop {
+    ///         tokio::select! {
+    ///             result = &mut delivery => {
+    ///                 println!("packet {} delivered: {:?}", delivery.seq(), result);
+    ///                 break;
+    ///             }
+    ///             // recv()等待期间处理ACK和重传
+    ///             _ = rudp.recv() => {}
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_tracked(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<DeliveryHandle, RudpError> {
+        let handle = self.core.send_tracked(buffer, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(handle)
+    }
+
+    /// 发送数据，超过`deadline`仍未被确认时放弃重传
+    /// 
+    /// 与[`send_tracked`](Self::send_tracked)相同，但不再按固定的最大重传次数和指数退避
+    /// 等待：到达`deadline`后数据包从重传队列移除，返回的[`DeliveryHandle`]完成为
+    /// `Err(RudpError::Timeout)`，同时触发`EventHandler::on_delivery_failed`。适合过期即
+    /// 无用的数据（如实时状态），避免陈旧数据占用发送窗口数秒。最大重传次数仍然有效，
+    /// 先到者为准
+    /// 
+    /// `deadline`与实例的时间源（见[`set_clock`](Self::set_clock)）比较，在`tick()`中检查
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::time::{Duration, Instant};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..8].copy_from_slice(&1.5f64.to_be_bytes());
+    ///     buffer.set_data_len(8)?;
+    ///     let deadline = Instant::now() + Duration::from_millis(200);
+    ///     let _position = rudp.send_with_deadline(buffer, "127.0.0.1:8081".parse()?, deadline).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_with_deadline(&mut self, buffer: PooledBuffer, target: SocketAddr, deadline: Instant) -> Result<DeliveryHandle, RudpError> {
+        let handle = self.core.send_with_deadline(buffer, target, deadline, self.clock.now())?;
+        self.transmit().await?;
+        Ok(handle)
+    }
+
+    /// 取消发往`addr`、序列号为`seq`的数据包，返回是否找到
+    /// 
+    /// 未确认的数据包不再重传，仍在排队的数据包不再发出，释放其占用的发送窗口；
+    /// `send_tracked()`返回的句柄完成为`Err(RudpError::Cancelled)`。已确认、已放弃或
+    /// 未知的序列号返回`false`。已经发出的副本仍可能到达对端
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     let mut previous = None;
+    ///     for frame in 0u32..100 {
+    ///         let mut buffer = rudp.get_buffer()?;
+    ///         buffer.data_mut()[..4].copy_from_slice(&frame.to_be_bytes());
+    ///         buffer.set_data_len(4)?;
+    ///         // 新的状态发出后，上一个未确认的状态不再需要
+    ///         if let Some(seq) = previous.replace(rudp.send(buffer, peer).await?) {
+    ///             rudp.cancel(peer, seq);
+    ///         }
+    ///         rudp.tick().await;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn cancel(&mut self, addr: SocketAddr, seq: u32) -> bool {
+        self.core.cancel(addr, seq, self.clock.now())
+    }
+
+    /// 立即重传发往`addr`、尚未确认的数据包`seq`，不等待重传超时，返回是否找到
+    /// 
+    /// 计入重传次数并重新开始重传计时。仍在排队、尚未发出的数据包返回`false`
+    pub async fn resend(&mut self, addr: SocketAddr, seq: u32) -> Result<bool, RudpError> {
+        let found = self.core.resend(addr, seq, self.clock.now());
+        if found {
+            self.flush_transmits().await?;
+        }
+        Ok(found)
+    }
+
+    /// 在`delay`之后发送数据，不需要为每条数据单独起定时任务
+    /// 
+    /// 数据由实例的定时器在`recv()`/`tick()`中按时交给[`send`](Self::send)，发送时才分配
+    /// 序列号；发送失败时错误以`seq`为`None`的接收结果返回。数据大小在调用时检查。每个对端
+    /// 最多`limits.max_queued_sends`个待发送的数据，超过时返回`RudpError::CongestionWindowFull`。
+    /// 待发送的数据计入[`pending_count`](Self::pending_count)
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     // 每秒一个心跳，全部预先安排好
+    ///     for beat in 1..=10u64 {
+    ///         let mut buffer = rudp.get_buffer()?;
+    ///         buffer.data_mut()[..8].copy_from_slice(&beat.to_be_bytes());
+    ///         buffer.set_data_len(8)?;
+    ///         rudp.send_after(buffer, peer, Duration::from_secs(beat))?;
+    ///     }
+    ///     while rudp.pending_count(peer) > 0 {
+    ///         rudp.recv_timeout(Duration::from_millis(100)).await;
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn send_after(&mut self, buffer: PooledBuffer, target: SocketAddr, delay: Duration) -> Result<(), RudpError> {
+        self.core.send_after(buffer, target, delay, self.clock.now())
+    }
+
+    /// 在`at`时刻发送数据，见[`send_after`](Self::send_after)
+    /// 
+    /// `at`与实例的时间源（见[`set_clock`](Self::set_clock)）比较
+    pub fn send_at(&mut self, buffer: PooledBuffer, target: SocketAddr, at: Instant) -> Result<(), RudpError> {
+        self.core.send_at(buffer, target, at)
+    }
+
+    /// 发送任意长度的消息
+    /// 
+    /// 面向只需要可靠消息传递、不关心零拷贝的应用：消息被拆分为多个分片，每个分片
+    /// 像数据包一样被确认和重传，对端用[`recv_message`](Self::recv_message)收到完整消息。
+    /// 拥塞窗口已满时，剩余分片在后续`tick()`中随窗口空出继续发送，因此本方法不会
+    /// 返回`CongestionWindowFull`
+    /// 
+    /// 消息长度不能超过`max_message_size`
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let message = vec![0u8; 100_000];
+    ///     rudp.send_message(&message, "127.0.0.1:8081".parse().unwrap()).await?;
+    ///     
+    ///     loop {
+    ///         rudp.tick().await;
+    ///         if let Some(message) = rudp.recv_message().await {
+    ///             println!("{} bytes from {}", message.data.len(), message.from);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn send_message(&mut self, data: &[u8], target: SocketAddr) -> Result<(), RudpError> {
+        self.core.send_message(data, target, self.clock.now())?;
+        self.transmit().await
+    }
+
+    /// 接收一条完整的消息
+    /// 
+    /// 返回对端用[`send_message`](Self::send_message)发送、已全部收齐的消息。
+    /// 最多等待1ms，没有完整消息时返回`None`；期间收到的普通数据包保留给`recv()`返回
+    pub async fn recv_message(&mut self) -> Option<ReceivedMessage> {
+        if !self.core.has_messages() {
+            self.poll_incoming().await;
+        }
+        self.core.poll_message()
+    }
+
+    /// 把消息放回接收队列头部，下次`recv_message()`按原顺序返回
+    #[cfg_attr(not(feature = "transfer"), allow(dead_code))]
+    pub(crate) fn requeue_messages(&mut self, messages: Vec<ReceivedMessage>) {
+        self.core.requeue_messages(messages);
+    }
+
+    /// 取出接收队列中的全部数据，不读取socket
+    #[cfg_attr(not(feature = "rpc"), allow(dead_code))]
+    pub(crate) fn take_received(&mut self) -> Vec<ReceivedData> {
+        self.core.drain_received(usize::MAX)
+    }
+
+    /// 发往`addr`、尚未被确认或仍在排队的数据包数量
+    /// 
+    /// 包括已发送等待确认的数据包和等待拥塞窗口的消息分片。可用于应用层的背压控制；
+    /// 为0时发往`addr`的数据都已被确认，可以安全关闭（也可用[`wait_acked`](Self::wait_acked)等待）
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     let chunk = vec![0u8; 4096];
+    ///     loop {
+    ///         // 未确认的数据超过1MB时先等待对端确认
+    ///         if rudp.pending_bytes(peer) > 1 << 20 {
+    ///             rudp.wait_acked(peer).await?;
+    ///         }
+    ///         rudp.send_message(&chunk, peer).await?;
+    ///     }
+    /// }
+    /// ```
+    pub fn pending_count(&self, addr: SocketAddr) -> usize {
+        self.core.unacked_packets(addr)
+    }
+
+    /// [`pending_count`](Self::pending_count)中的数据包的用户数据字节数（不含协议头）
+    pub fn pending_bytes(&self, addr: SocketAddr) -> usize {
+        self.core.pending_bytes(addr)
+    }
+
+    /// 发送由多个片段组成的数据
+    /// 
+    /// 各片段按顺序紧接在协议头之后写入一个内存池buffer，组成一个数据包发送，
+    /// 调用方无需先把消息头、消息体等片段拼接到一起。数据包需要保留到被确认
+    /// 以便重传，因此片段会被写入buffer一次，之后与[`send`](Self::send)相同
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::io::IoSlice;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let header = 42u32.to_be_bytes();
+    ///     let body = b"message body";
+    ///     rudp.send_vectored(&[IoSlice::new(&header), IoSlice::new(body)], "127.0.0.1:8081".parse().unwrap()).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_vectored(&mut self, bufs: &[IoSlice<'_>], target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.send_vectored(bufs, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 把同一份数据可靠地发送给多个对端
+    /// 
+    /// 适合游戏服务器等扇出场景：每个对端照常分配自己的序列号，独立确认、重传和拥塞控制，
+    /// 拥塞窗口已满时像[`send`](Self::send)一样排队。各对端共享`buffer`中的载荷，
+    /// 协议头和扩展在每次发出数据报时写入
+    /// 
+    /// # 返回
+    /// - `Ok(results)`: 按`targets`顺序给出每个对端的结果，成功时为分配的序列号，
+    ///   数据超过该对端的载荷上限等错误只影响对应的对端
+    /// - `Err(RudpError::Closing)`: 实例正在关闭
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::net::SocketAddr;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let players: Vec<SocketAddr> = vec!["127.0.0.1:8081".parse()?, "127.0.0.1:8082".parse()?];
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..5].copy_from_slice(b"state");
+    ///     buffer.set_data_len(5)?;
+    ///     for (player, result) in players.iter().zip(rudp.send_to_all(buffer, &players).await?) {
+    ///         if let Err(e) = result {
+    ///             println!("发送给 {} 失败: {}", player, e);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_to_all(&mut self, buffer: PooledBuffer, targets: &[SocketAddr]) -> Result<Vec<Result<u32, RudpError>>, RudpError> {
+        let results = self.core.send_to_all(buffer, targets, self.clock.now())?;
+        self.transmit().await?;
+        Ok(results)
+    }
+
+    /// 发送不可靠数据报
+    /// 
+    /// 数据报只发送一次：不分配序列号、不等待确认也不重传，不受拥塞窗口限制。
+    /// 对端照常通过`recv()`收到，`ReceivedData::seq`为`None`。`target`可以是组播地址，
+    /// 用于局域网发现等场景，组内成员需先调用[`join_multicast_v4`](Self::join_multicast_v4)
+    /// 或[`join_multicast_v6`](Self::join_multicast_v6)
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("0.0.0.0:5353".parse()?).await?;
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.data_mut()[..8].copy_from_slice(b"discover");
+    ///     buffer.set_data_len(8)?;
+    ///     rudp.send_unreliable(buffer, "239.255.0.1:5353".parse()?).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn send_unreliable(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<(), RudpError> {
+        self.core.send_unreliable(buffer, target)?;
+        self.flush_transmits().await
+    }
+
+    /// 发送`Bytes`数据
+    ///
+    /// 供已使用`bytes`生态的应用调用，无需先把数据拷贝进内存池buffer。等待确认期间
+    /// 只保留`Bytes`引用，协议头和扩展在每次发出数据报时写入
+    ///
+    /// 扩展、压缩、拥塞控制和错误与[`send`](Self::send)相同
+    ///
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use bytes::Bytes;
+    /// use rudpbase::Rudpbase;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let payload = Bytes::from_static(b"Hello, world!");
+    ///     rudp.send_bytes(payload, "127.0.0.1:8081".parse().unwrap()).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self, data), fields(len = data.len())))]
+    pub async fn send_bytes(&mut self, data: Bytes, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.send_bytes(data, target, self.clock.now())?;
+        self.transmit().await?;
+        Ok(seq)
+    }
+
+    /// 发送core产生的数据报
+    /// 
+    /// 未启用批量I/O时立即发送；启用时数据报留在队列中，队列满`io_batch_size`个时统一发送，
+    /// 其余的在下一次`tick()`/`recv()`时发送
+    async fn transmit(&mut self) -> Result<(), RudpError> {
+        if self.core.config().io_batch_size == 1 || self.core.transmit_count() >= self.core.config().io_batch_size {
+            self.flush_transmits().await?;
+        }
+        Ok(())
+    }
+
+    /// 获取内存池统计信息
+    pub fn get_buffer_pool_stats(&self) -> Result<crate::buffer_pool::PoolStats, RudpError> {
+        self.core.get_buffer_pool_stats()
+    }
+
+    /// 收缩内存池，最多保留 `count` 个空闲buffer
+    /// 
+    /// 返回释放的buffer数量，详见[`SharedBufferPool::shrink_to`]
+    pub fn shrink_buffer_pool(&self, count: usize) -> Result<usize, RudpError> {
+        self.core.shrink_buffer_pool(count)
+    }
+
+    /// 接收数据
+    /// 
+    /// 从内存池获取buffer来存储接收的数据，实现零拷贝接收
+    /// 
+    /// **重要说明**：
+    /// - 只返回Data包（PacketType::Data = 2）给上层应用
+    /// - 控制包（ACK、NACK、PING等）在库内部自动处理，不会返回给上层
+    /// - 等待期间按core的定时器处理重传、心跳、连接管理等逻辑，无需另外调用`tick()`
+    /// 
+    /// 没有数据时等待socket真正可读，不轮询。可以取消：在`tokio::select!`中与其他
+    /// future一起等待，或用`tokio::time::timeout`限定等待时间，被取消时不会丢失数据
+    /// 
+    /// 发送失败也从这里报告：数据包重传耗尽时收到`seq`为该包序列号的
+    /// `ConnectionError::MaxRetriesExceeded`，对端被判定断开时收到`ConnectionError::Dead`
+    /// 
+    /// # 返回
+    /// 接收到的用户数据包（包含发送方地址和池化buffer）或接收错误
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
+    ///     let mut report = tokio::time::interval(Duration::from_secs(1));
+    ///     
+    ///     loop {
+    ///         tokio::select! {
+    ///             received = rudp.recv() => match received.result {
+    ///                 Ok(buffer) => {
+    ///                     println!("Received user data from {}: {:?}", 
+    ///                         received.from, buffer.data());
+    ///                     // buffer会在离开作用域时自动归还到内存池
+    ///                 }
+    ///                 Err(e) => {
+    ///                     println!("Receive error: {}", e);
+    ///                 }
+    ///             },
+    ///             _ = report.tick() => println!("{:?}", rudp.global_stats()?),
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
+    pub async fn recv(&mut self) -> ReceivedData {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// [`recv`](Self::recv)的poll形式，供手写的`Future`和`Stream`使用
+    /// 
+    /// 返回`Poll::Pending`时已注册`cx`的waker：socket可读、core的定时器到期或
+    /// 发送队列可以继续发送时唤醒
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<ReceivedData> {
+        loop {
+            if let Some(received) = self.core.poll_received() {
+                return Poll::Ready(received);
+            }
+            ready!(self.poll_readable(cx, None));
+        }
+    }
+
+    /// 最多等待`timeout`接收数据
+    /// 
+    /// 与[`recv`](Self::recv)相同，超时仍没有数据时返回`None`
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     match rudp.recv_timeout(Duration::from_millis(100)).await {
+    ///         Some(received) => println!("{} bytes from {}", received.result?.data_len(), received.from),
+    ///         None => println!("nothing within 100ms"),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Option<ReceivedData> {
+        time::timeout(timeout, self.recv()).await.ok()
+    }
+
+    /// 只接收来自`addr`的数据
+    /// 
+    /// 等待期间收到的其他对端的数据留在接收队列中，之后由`recv()`按到达顺序返回，
+    /// 适合请求/响应式的交互。没有对端地址的接收错误（如socket错误）也留给`recv()`。
+    /// 留在队列中的数据计入`LimitsConfig::max_queued_packets`，队列满时新到的数据包
+    /// （包括来自`addr`的）不被确认，等待对端重传
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let server = "127.0.0.1:8081".parse()?;
+    ///     let mut request = rudp.get_buffer()?;
+    ///     request.data_mut()[..4].copy_from_slice(b"ping");
+    ///     request.set_data_len(4)?;
+    ///     rudp.send(request, server).await?;
+    ///     
+    ///     let response = rudp.recv_from(server).await;
+    ///     println!("response: {:?}", response.result?.data());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn recv_from(&mut self, addr: SocketAddr) -> ReceivedData {
+        poll_fn(|cx| loop {
+            if let Some(received) = self.core.poll_received_from(addr) {
+                return Poll::Ready(received);
+            }
+            ready!(self.poll_readable(cx, Some(addr)));
+        })
+        .await
+    }
+
+    /// 等待直到有数据可以接收
+    /// 
+    /// 返回后`recv()`立即返回，不再等待。与[`recv`](Self::recv)一样在等待期间
+    /// 处理到达的控制包和定时任务，可以在`tokio::select!`中使用
+    pub async fn readable(&mut self) {
+        poll_fn(|cx| self.poll_readable(cx, None)).await
+    }
+
+    /// 等待直到接收队列中有数据，`from`为`Some`时只等待来自该地址的数据
+    fn poll_readable(&mut self, cx: &mut Context<'_>, from: Option<SocketAddr>) -> Poll<()> {
+        loop {
+            if self.has_received(from) {
+                return Poll::Ready(());
+            }
+            // 两者都要轮询，确保socket和定时器都注册了waker
+            let read = self.poll_read(cx, ReadUntil::Received(from)).is_ready();
+            if read {
+                // 立即回复收到的数据包
+                self.core.handle_timeout(self.clock.now());
+            }
+            let fired = self.poll_timer(cx).is_ready();
+            self.poll_flush(cx);
+            if !read && !fired && !self.has_received(from) {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    pub(crate) fn has_received(&self, from: Option<SocketAddr>) -> bool {
+        match from {
+            Some(addr) => self.core.has_received_from(addr),
+            None => self.core.has_received(),
+        }
+    }
+
+    fn read_done(&self, until: ReadUntil) -> bool {
+        match until {
+            ReadUntil::Received(from) => self.has_received(from),
+            ReadUntil::Drained => false,
+        }
+    }
+
+    /// 等待传输层可读，读取当前到达的数据报交给core处理
+    /// 
+    /// 读到数据报或读取出错时返回`Ready`
+    fn poll_read(&mut self, cx: &mut Context<'_>, until: ReadUntil) -> Poll<()> {
+        let readiness = match (&self.uring, self.transport.udp_socket()) {
+            (Some(driver), _) => driver.poll_readable(cx),
+            (None, Some(socket)) => socket.poll_recv_ready(cx),
+            (None, None) => return self.poll_read_transport(cx, until),
+        };
+        if let Err(e) = ready!(readiness) {
+            self.read_succeeded(Err(e));
+            return Poll::Ready(());
+        }
+        while !self.read_done(until) && self.read_available() {}
+        Poll::Ready(())
+    }
+
+    /// 没有底层socket的传输层：直接轮询接收，每次`Pending`都注册`cx`的waker
+    fn poll_read_transport(&mut self, cx: &mut Context<'_>, until: ReadUntil) -> Poll<()> {
+        let mut read = false;
+        while !self.read_done(until) {
+            let mut buffer = match self.core.get_buffer() {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    self.core.push_received(ReceivedData {
+                        from: "0.0.0.0:0".parse().unwrap(),
+                        seq: None,
+                        result: Err(e),
+                    });
+                    return Poll::Ready(());
+                }
+            };
+            match self.transport.poll_recv_from(cx, buffer.raw_buffer_mut()) {
+                Poll::Ready(Ok((len, from))) => self.core.handle_buffer(buffer, len, from, self.clock.now()),
+                Poll::Ready(Err(e)) => {
+                    self.read_succeeded(Err(e));
+                    return Poll::Ready(());
+                }
+                Poll::Pending => break,
+            }
+            read = true;
+        }
+        if read {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// 读取当前到达的全部数据报并处理到期的定时任务，收到的数据留在接收队列中
+    /// 
+    /// 与[`recv`](Self::recv)不同，接收队列中已有数据时也继续读取，ACK不会因为上层
+    /// 没有取走数据而推迟。读到数据报或定时器到期时返回，可以取消
+    #[cfg_attr(not(feature = "task"), allow(dead_code))]
+    pub(crate) async fn drive(&mut self) {
+        poll_fn(|cx| {
+            let read = self.poll_read(cx, ReadUntil::Drained).is_ready();
+            if read {
+                self.core.handle_timeout(self.clock.now());
+            }
+            let fired = self.poll_timer(cx).is_ready();
+            self.poll_flush(cx);
+            if read || fired {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// 取出接收队列中的下一条数据，不读取socket
+    #[cfg_attr(not(feature = "task"), allow(dead_code))]
+    pub(crate) fn poll_received(&mut self) -> Option<ReceivedData> {
+        self.core.poll_received()
+    }
+
+    /// 取出下一条完整的消息，不读取socket
+    #[cfg_attr(not(feature = "task"), allow(dead_code))]
+    pub(crate) fn poll_message(&mut self) -> Option<ReceivedMessage> {
+        self.core.poll_message()
+    }
+
+    #[cfg_attr(not(feature = "task"), allow(dead_code))]
+    pub(crate) fn has_messages(&self) -> bool {
+        self.core.has_messages()
+    }
+
+    /// 等待core的下一个定时器，到期时处理定时任务
+    fn poll_timer(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(at) = self.core.poll_timeout() else {
+            self.timer = None;
+            return Poll::Pending;
+        };
+        if self.timer.as_ref().is_none_or(|(armed, _)| *armed != at) {
+            let wait = at.saturating_duration_since(self.clock.now()) + TIMER_SLACK;
+            self.timer = Some((at, Box::pin(time::sleep(wait))));
+        }
+        if let Some((_, sleep)) = &mut self.timer {
+            ready!(sleep.as_mut().poll(cx));
+        }
+        self.timer = None;
+        self.core.handle_timeout(self.clock.now());
+        Poll::Ready(())
+    }
+
+    /// 尝试发送core队列中的数据报，不等待
+    /// 
+    /// 未能立即发完时丢弃发送future：数据报留在队列中，socket可写时唤醒`cx`重试，
+    /// 已发出的部分可能重复发送一次
+    fn poll_flush(&mut self, cx: &mut Context<'_>) {
+        if self.core.transmit_count() > 0 {
+            let _ = pin!(self.flush_transmits()).poll(cx);
+        }
+    }
+
+    /// 最多等待1ms读取到达的包并交给core处理，收到的数据留在接收队列中
+    async fn poll_incoming(&mut self) {
+        if self.core.config().io_batch_size > 1 {
+            self.recv_batched().await;
+            return;
+        }
+
+        // 直接读入内存池buffer，数据包无需再拷贝
+        let mut buffer = match self.core.get_buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => return self.core.push_received(ReceivedData {
+                from: "0.0.0.0:0".parse().unwrap(),
+                seq: None,
+                result: Err(e),
+            }),
+        };
+        
+        match time::timeout(Duration::from_millis(1), transport::recv_from(&*self.transport, buffer.raw_buffer_mut())).await {
+            Ok(Ok((len, from))) => self.core.handle_buffer(buffer, len, from, self.clock.now()),
+            Ok(Err(e)) => self.core.push_received(ReceivedData {
+                from: "0.0.0.0:0".parse().unwrap(),
+                seq: None,
+                result: Err(RudpError::Io(e)),
+            }),
+            Err(_) => {} // Timeout, no data received
+        }
+        // 立即回复ping、Close等控制包，以及NACK触发的重传
+        let _ = self.flush_transmits().await;
+    }
+
+    /// 一次接收多条消息
+    /// 
+    /// 若队列中没有已收到的数据，最多等待1ms直到socket可读；之后不再等待，
+    /// 读取socket中当前所有可读的数据报，最多返回`max`条用户数据。
+    /// 未返回的数据保留在队列中，由下一次`recv()`/`recv_batch()`返回
+    /// 
+    /// # 参数
+    /// - `max`: 本次最多返回的消息数
+    /// 
+    /// # 返回
+    /// 收到的用户数据和接收错误，没有数据时返回空Vec
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     
+    ///     loop {
+    ///         rudp.tick().await;
+    ///         for received in rudp.recv_batch(64).await {
+    ///             if let Ok(buffer) = received.result {
+    ///                 println!("Received {} bytes from {}", buffer.data_len(), received.from);
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn recv_batch(&mut self, max: usize) -> Vec<ReceivedData> {
+        if !self.core.has_received() {
+            match (&self.uring, self.transport.udp_socket()) {
+                (Some(driver), _) => { let _ = time::timeout(Duration::from_millis(1), driver.readable()).await; }
+                (None, Some(socket)) => { let _ = time::timeout(Duration::from_millis(1), socket.readable()).await; }
+                (None, None) => {
+                    // 没有可等待就绪的socket，直接接收第一个数据报
+                    if let Ok(mut buffer) = self.core.get_buffer() {
+                        if let Ok(result) = time::timeout(Duration::from_millis(1), transport::recv_from(&*self.transport, buffer.raw_buffer_mut())).await {
+                            match result {
+                                Ok((len, from)) => self.core.handle_buffer(buffer, len, from, self.clock.now()),
+                                Err(e) => { self.read_succeeded(Err(e)); }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        while self.core.received_len() < max && self.read_available() {}
+        let _ = self.flush_transmits().await;
+
+        self.core.drain_received(max)
+    }
+
+    /// 批量接收：一次系统调用读取多个数据报，处理后统一发送产生的控制包
+    async fn recv_batched(&mut self) {
+        let now = self.clock.now();
+        if let Some(mut driver) = self.uring.take() {
+            match time::timeout(Duration::from_millis(1), driver.recv()).await {
+                Ok(Ok(_)) => {
+                    for (packet_data, from) in driver.datagrams() {
+                        self.core.handle_datagram(packet_data, from, now);
+                    }
+                }
+                Ok(Err(e)) => self.core.push_received(ReceivedData {
+                    from: "0.0.0.0:0".parse().unwrap(),
+                    seq: None,
+                    result: Err(RudpError::Io(e)),
+                }),
+                Err(_) => {} // Timeout, no data received
+            }
+            self.uring = Some(driver);
+            let _ = self.flush_transmits().await;
+            return;
+        }
+
+        let mut batch = std::mem::take(&mut self.rx_batch);
+
+        match time::timeout(Duration::from_millis(1), batch.recv(udp_socket(&*self.transport))).await {
+            Ok(Ok(_)) => {
+                for (packet_data, from) in batch.datagrams() {
+                    self.core.handle_datagram(packet_data, from, now);
+                }
+            }
+            Ok(Err(e)) => self.core.push_received(ReceivedData {
+                from: "0.0.0.0:0".parse().unwrap(),
+                seq: None,
+                result: Err(RudpError::Io(e)),
+            }),
+            Err(_) => {} // Timeout, no data received
+        }
+
+        self.rx_batch = batch;
+        let _ = self.flush_transmits().await;
+    }
+
+    /// 不等待地读取socket中当前可读的数据报（批量I/O时一次最多`io_batch_size`个）
+    /// 
+    /// 没有可读数据或读取出错时返回false
+    fn read_available(&mut self) -> bool {
+        let now = self.clock.now();
+        if let Some(mut driver) = self.uring.take() {
+            let result = driver.try_recv();
+            if result.is_ok() {
+                for (packet_data, from) in driver.datagrams() {
+                    self.core.handle_datagram(packet_data, from, now);
+                }
+            }
+            self.uring = Some(driver);
+            return self.read_succeeded(result);
+        }
+        if self.core.config().io_batch_size > 1 {
+            let mut batch = std::mem::take(&mut self.rx_batch);
+            let result = batch.try_recv(udp_socket(&*self.transport));
+            if result.is_ok() {
+                for (packet_data, from) in batch.datagrams() {
+                    self.core.handle_datagram(packet_data, from, now);
+                }
+            }
+            self.rx_batch = batch;
+            return self.read_succeeded(result);
+        }
+
+        let mut buffer = match self.core.get_buffer() {
+            Ok(buffer) => buffer,
+            Err(_) => return false,
+        };
+        match transport::try_recv_from(&*self.transport, buffer.raw_buffer_mut()) {
+            Ok((len, from)) => {
+                self.core.handle_buffer(buffer, len, from, now);
+                true
+            }
+            Err(e) => self.read_succeeded(Err(e)),
+        }
+    }
+
+    /// 读取出错时将错误放入接收队列（`WouldBlock`除外）
+    fn read_succeeded(&mut self, result: std::io::Result<usize>) -> bool {
+        match result {
+            Ok(_) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => false,
+            Err(e) => {
+                self.core.push_received(ReceivedData {
+                    from: "0.0.0.0:0".parse().unwrap(),
+                    seq: None,
+                    result: Err(RudpError::Io(e)),
+                });
+                false
+            }
+        }
+    }
+
+    /// 通过STUN服务器发现本地socket的公网映射地址
+    /// 
+    /// 使用与数据传输相同的socket发送STUN Binding请求，因此返回的地址正是
+    /// 对端看到的地址，无需额外的UDP socket
+    /// 
+    /// 等待响应期间收到的rudpbase数据包会照常处理，用户数据暂存后由`recv()`返回
+    /// 
+    /// # 参数
+    /// - `stun_server`: STUN服务器地址
+    /// 
+    /// # 返回
+    /// - `Ok(SocketAddr)`: 公网映射地址
+    /// - `Err(RudpError::Timeout)`: 多次重试后仍未收到响应
+    /// - `Err(RudpError)`: 服务器返回错误或响应格式不正确
+    pub async fn discover_public_addr(&mut self, stun_server: SocketAddr) -> Result<SocketAddr, RudpError> {
+        if let Some(mut driver) = self.uring.take() {
+            let result = self.discover_with_uring(&mut driver, stun_server).await;
+            self.uring = Some(driver);
+            return result;
+        }
+
+        let request = BindingRequest::new();
+        let request_data = request.serialize();
+        let mut buf = [0u8; DEFAULT_BUFFER_SIZE];
+        let mut rto = STUN_INITIAL_RTO;
+
+        for _ in 0..STUN_MAX_ATTEMPTS {
+            transport::send_to(&*self.transport, &request_data, stun_server).await?;
+            let deadline = time::Instant::now() + rto;
+
+            loop {
+                let remaining = deadline.saturating_duration_since(time::Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+
+                let (len, from) = match time::timeout(remaining, transport::recv_from(&*self.transport, &mut buf)).await {
+                    Ok(result) => result?,
+                    Err(_) => break,
+                };
+                let packet_data = &buf[..len];
+
+                if from == stun_server && stun::matches_transaction(packet_data, &request.transaction_id) {
+                    return stun::parse_binding_response(packet_data, &request.transaction_id);
+                }
+                if stun::is_stun_message(packet_data) {
+                    continue;
+                }
+
+                // 非STUN数据包照常交给协议栈处理
+                self.core.handle_datagram(packet_data, from, self.clock.now());
+                let _ = self.flush_transmits().await;
+            }
+
+            rto *= 2;
+        }
+
+        Err(RudpError::Timeout)
+    }
+
+    /// io_uring后端的STUN地址发现：接收操作常驻在ring上，响应必须从ring中取出
+    async fn discover_with_uring(&mut self, driver: &mut UringDriver, stun_server: SocketAddr) -> Result<SocketAddr, RudpError> {
+        let request = BindingRequest::new();
+        let request_data = request.serialize();
+        let mut rto = STUN_INITIAL_RTO;
+
+        for _ in 0..STUN_MAX_ATTEMPTS {
+            driver.send_batch(&[(&request_data, stun_server)])?;
+            let deadline = time::Instant::now() + rto;
+
+            loop {
+                let remaining = deadline.saturating_duration_since(time::Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                match time::timeout(remaining, driver.recv()).await {
+                    Ok(result) => { result?; }
+                    Err(_) => break,
+                }
+
+                let mut response = None;
+                for (packet_data, from) in driver.datagrams() {
+                    if from == stun_server && stun::matches_transaction(packet_data, &request.transaction_id) {
+                        response.get_or_insert_with(|| stun::parse_binding_response(packet_data, &request.transaction_id));
+                    } else {
+                        // 非STUN数据包照常交给协议栈处理
+                        self.core.handle_datagram(packet_data, from, self.clock.now());
+                    }
+                }
+                if let Some(response) = response {
+                    return response;
+                }
+            }
+
+            rto *= 2;
+        }
+
+        Err(RudpError::Timeout)
+    }
+
+    /// 获取本地绑定地址
+    pub fn local_addr(&self) -> Result<SocketAddr, RudpError> {
+        Ok(self.transport.local_addr()?)
+    }
+
+    /// 运行时换用绑定在`new_local_addr`上的新socket，保留所有对端状态
+    /// 
+    /// 适用于网卡切换、地址变化或端口冲突。队列中的包先从旧socket发出，新socket
+    /// 使用创建实例时的socket选项和I/O后端；之后向每个已连接的对端发送携带连接ID的ping，
+    /// 对端据此把连接迁移到新地址（见[`RudpCore::migrate`]），序列号、未确认的数据和
+    /// RTT统计都不受影响。自定义传输层同样被替换为UDP socket
+    /// 
+    /// # 返回
+    /// - `Ok(SocketAddr)`: 新socket实际绑定的地址
+    /// - `Err(RudpError::Io)`: 绑定失败，继续使用原来的socket
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("192.168.1.10:8080".parse()?).await?;
+    ///     rudp.send(rudp.get_buffer()?, "203.0.113.5:9000".parse()?).await?;
+    ///     
+    ///     // 切换到另一个网卡
+    ///     let local = rudp.rebind("10.0.0.7:0".parse()?).await?;
+    ///     println!("now sending from {}", local);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn rebind(&mut self, new_local_addr: SocketAddr) -> Result<SocketAddr, RudpError> {
+        let socket = socket::bind(new_local_addr, &self.core.config().socket)?;
+        let transport: Box<dyn Transport> = Box::new(socket);
+        let config = self.core.config();
+        let uring = match config.io_backend {
+            IoBackend::Socket => None,
+            IoBackend::IoUring => Some(UringDriver::new(udp_socket(&*transport), self.core.buffer_pool(), config.io_batch_size)?),
+        };
+        let offload = match transport.udp_socket() {
+            Some(socket) => UdpOffload::configure(socket, config.offload_requested()),
+            None => UdpOffload::default(),
+        };
+        let rx_batch = RecvBatch::new(config.io_batch_size, offload.gro);
+        let local_addr = transport.local_addr()?;
+        let _ = self.flush_transmits().await;
+
+        trace_event!(info, %local_addr, "socket rebound");
+        self.rx_batch = rx_batch;
+        self.transport = transport;
+        self.offload = offload;
+        self.uring = uring;
+        self.core.migrate(self.clock.now());
+        let _ = self.flush_transmits().await;
+        Ok(local_addr)
+    }
+
+    /// Maintenance function - handle retransmissions, timeouts, ACKs, etc.
+    pub async fn tick(&mut self) {
+        self.core.handle_timeout(self.clock.now());
+
+        // Flush batched control packets and retransmissions
+        let _ = self.flush_transmits().await;
+    }
+
+    /// 立即发送批量I/O队列中的所有数据包和控制包
+    /// 
+    /// 启用批量I/O（`io_batch_size > 1`）时，`send()`只将数据包加入队列，
+    /// 队列满或下一次`tick()`/`recv()`时才统一发送；需要降低延迟时可主动调用
+    pub async fn flush(&mut self) {
+        let _ = self.flush_transmits().await;
+    }
+
+    /// 设置默认的保活与断线检测参数
+    /// 
+    /// 对所有未单独配置的连接生效，包括已存在的连接
+    pub fn set_keepalive_config(&mut self, config: KeepAliveConfig) {
+        self.core.set_keepalive_config(config);
+    }
+
+    /// 获取默认的保活与断线检测参数
+    pub fn keepalive_config(&self) -> &KeepAliveConfig {
+        self.core.keepalive_config()
+    }
+
+    /// 获取实例配置
+    pub fn config(&self) -> &RudpConfig {
+        self.core.config()
+    }
+
+    /// 注册事件回调
+    /// 
+    /// 用于接收重传耗尽导致的发送失败、安全码校验失败等原本被静默处理的事件，
+    /// 新注册的回调会替换之前的回调
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.core.set_event_handler(handler);
+    }
+
+    /// 设置新来源地址的准入检查
+    ///
+    /// 来自尚无状态的地址的包在创建任何状态之前交给`filter`，返回`Verdict::Reject`
+    /// 的包被静默丢弃。可用于只接受特定网段或实现自定义的准入策略
+    pub fn set_peer_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(SocketAddr) -> Verdict + Send + Sync + 'static,
+    {
+        self.core.set_peer_filter(filter);
+    }
+
+    /// 移除准入检查，接受所有来源
+    pub fn clear_peer_filter(&mut self) {
+        self.core.clear_peer_filter();
+    }
+
+    /// 设置本端的Ed25519身份密钥，之后的ping和ping应答都带上签名的身份
+    ///
+    /// 对端验证通过后在`ConnectionEvent::Established`中报告本端身份：
+    /// ```rust,no_run
+    /// use rudpbase::{ConnectionEvent, EventHandler, IdentityKey, Rudpbase};
+    /// use std::net::SocketAddr;
+    ///
+    /// struct Log;
+    ///
+    /// impl EventHandler for Log {
+    ///     fn on_connection_event(&self, addr: SocketAddr, event: ConnectionEvent) {
+    ///         if let ConnectionEvent::Established { identity } = event {
+    ///             println!("{} is {:?}", addr, identity);
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer: SocketAddr = "127.0.0.1:8081".parse()?;
+    ///     let peer_key = IdentityKey::from_bytes(&[2; 32]).identity();
+    ///
+    ///     rudp.set_identity(IdentityKey::from_bytes(&[1; 32]));
+    ///     rudp.pin_peer_identity(peer, peer_key);
+    ///     rudp.set_event_handler(Log);
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "identity")]
+    pub fn set_identity(&mut self, key: IdentityKey) {
+        self.core.set_identity(key);
+    }
+
+    /// 固定指定对端的身份：只接受持有该公钥的对端，验证身份之前丢弃它的包
+    #[cfg(feature = "identity")]
+    pub fn pin_peer_identity(&mut self, addr: SocketAddr, identity: PeerIdentity) {
+        self.core.pin_peer_identity(addr, identity);
+    }
+
+    /// 取消指定对端的身份固定
+    #[cfg(feature = "identity")]
+    pub fn unpin_peer_identity(&mut self, addr: SocketAddr) {
+        self.core.unpin_peer_identity(addr);
+    }
+
+    /// 设置身份校验回调：所有对端都必须先出示身份，签名有效的身份再交给`verifier`
+    #[cfg(feature = "identity")]
+    pub fn set_identity_verifier<F>(&mut self, verifier: F)
+    where
+        F: Fn(SocketAddr, &PeerIdentity) -> Verdict + Send + Sync + 'static,
+    {
+        self.core.set_identity_verifier(verifier);
+    }
+
+    /// 移除身份校验回调
+    #[cfg(feature = "identity")]
+    pub fn clear_identity_verifier(&mut self) {
+        self.core.clear_identity_verifier();
+    }
+
+    /// 指定对端已验证的身份
+    #[cfg(feature = "identity")]
+    pub fn peer_identity(&self, addr: SocketAddr) -> Option<PeerIdentity> {
+        self.core.peer_identity(addr)
+    }
+
+    /// 忘记`addr`已断开，之后可以重新向它发送
+    /// 
+    /// 断开的对端再次发来有效包时也会自动恢复
+    pub fn clear_dead_peer(&mut self, addr: SocketAddr) {
+        self.core.clear_dead_peer(addr);
+    }
+
+    /// 替换时间源
+    /// 
+    /// 重传超时、保活探测和内存池空闲回收都通过时间源读取当前时间。测试中配合`MockClock`
+    /// 和内存传输层，可以不依赖真实时间地验证RTO退避和断线检测。
+    /// 应在开始收发之前设置
+    /// 
+    /// # 使用示例
+    /// ```rust
+    /// use rudpbase::{LoopbackTransport, MockClock, RudpConfig, Rudpbase};
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let (transport, _peer) = LoopbackTransport::pair("10.0.0.1:1".parse()?, "10.0.0.2:1".parse()?);
+    ///     let mut rudp = Rudpbase::with_transport(transport, RudpConfig::default()).await?;
+    ///     let clock = MockClock::new();
+    ///     rudp.set_clock(clock.clone());
+    ///     
+    ///     clock.advance(Duration::from_secs(1));
+    ///     rudp.tick().await;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_clock<C: Clock>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// 移除已注册的事件回调
+    pub fn clear_event_handler(&mut self) {
+        self.core.clear_event_handler();
+    }
+
+    /// 设置qlog风格的结构化事件日志输出
+    /// 
+    /// 记录每个连接的发包、收包、ACK、丢包、拥塞窗口和RTO变化等事件，
+    /// 可用于离线分析传输行为。事件时间相对于调用本方法的时刻
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use rudpbase::qlog::QlogJsonWriter;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let file = std::fs::File::create("rudpbase.sqlog")?;
+    ///     rudp.set_qlog_sink(QlogJsonWriter::new(file, "rudpbase")?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_qlog_sink<S: QlogSink + 'static>(&mut self, sink: S) {
+        self.core.set_qlog_sink(sink);
+    }
+
+    /// 停止记录结构化事件日志
+    pub fn clear_qlog_sink(&mut self) {
+        self.core.clear_qlog_sink();
+    }
+
+    /// 每隔`interval`把全局和每个连接的统计快照交给`sink`
+    ///
+    /// 报告由`tick()`和接收时的定时器处理生成，不需要应用自己定时打印统计。
+    /// `StatsJsonWriter`把每次报告写成一行JSON，也可以传入`std::sync::mpsc::Sender`或闭包
+    ///
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use rudpbase::report::StatsJsonWriter;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     rudp.enable_stats_reporting(Duration::from_secs(5), StatsJsonWriter::new(std::io::stdout()));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn enable_stats_reporting<S: StatsSink + 'static>(&mut self, interval: Duration, sink: S) {
+        self.core.enable_stats_reporting(interval, sink);
+    }
+
+    /// 停止统计报告
+    pub fn disable_stats_reporting(&mut self) {
+        self.core.disable_stats_reporting();
+    }
+
+    /// 通过OpenTelemetry报告握手、每个数据包从发送到确认的过程和连接的生命周期，并记录指标
+    ///
+    /// span和指标交给全局的tracer与meter provider，由应用配置导出方式（例如OTLP）。
+    /// 在应用自己的span中调用`send()`时，数据包的span成为它的子span
+    ///
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// use rudpbase::otel::Telemetry;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     rudp.set_telemetry(Telemetry::new());
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "otel")]
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.core.set_telemetry(telemetry);
+    }
+
+    /// 停止OpenTelemetry报告
+    #[cfg(feature = "otel")]
+    pub fn clear_telemetry(&mut self) {
+        self.core.clear_telemetry();
+    }
+
+    /// 运行时更新实例配置，不会断开已有连接
+    /// 
+    /// 新配置会立即作用于所有已有连接：
+    /// - 心跳间隔、断线阈值和最大重传次数在下一次`tick()`时生效
+    /// - RTO上下限和最大拥塞窗口会应用到每个连接的拥塞控制状态，
+    ///   当前RTO和窗口会被限制在新的范围内
+    /// - 单包最大载荷和安全码选项对之后收发的包生效
+    /// - 修改压缩配置后，下次发往各对端时重新协商压缩算法
+    /// 
+    /// 初始RTO和初始拥塞窗口只影响之后新建的连接，内存池预分配数量只在创建时使用，
+    /// I/O后端和socket选项不能在运行时修改
+    /// 
+    /// # 返回
+    /// - `Ok(())`: 更新成功
+    /// - `Err(RudpError::InvalidConfig)`: 配置不合法，原配置保持不变
+    pub fn update_config(&mut self, config: RudpConfig) -> Result<(), RudpError> {
+        config.validate()?;
+        if config.io_backend != self.config().io_backend {
+            return Err(RudpError::InvalidConfig {
+                message: "io_backend cannot be changed on a live instance".to_string(),
+            });
+        }
+        check_transport(&*self.transport, &config)?;
+        if config.socket != self.config().socket {
+            return Err(RudpError::InvalidConfig {
+                message: "socket options cannot be changed on a live instance".to_string(),
+            });
+        }
+
+        let offload_changed = config.offload_requested() != self.config().offload_requested();
+        self.core.update_config(config)?;
+
+        if offload_changed {
+            if let Some(socket) = self.transport.udp_socket() {
+                self.offload = UdpOffload::configure(socket, self.core.config().offload_requested());
+            }
+        }
+        let io_batch_size = self.core.config().io_batch_size;
+        if io_batch_size != self.rx_batch.capacity() || self.offload.gro != self.rx_batch.gro() {
+            self.rx_batch = RecvBatch::new(io_batch_size, self.offload.gro);
+        }
+        Ok(())
+    }
+
+    /// 为指定连接设置保活与断线检测参数，覆盖默认配置
+    /// 
+    /// 适合对延迟敏感的对端使用更激进的断线检测，例如：
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, KeepAliveConfig};
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     
+    ///     // 约2.5秒内检测到断线
+    ///     rudp.set_peer_keepalive_config("127.0.0.1:8081".parse()?, KeepAliveConfig {
+    ///         idle_timeout: Duration::from_secs(1),
+    ///         ping_interval: Duration::from_millis(500),
+    ///         max_ping_failures: 3,
+    ///         ..KeepAliveConfig::default()
+    ///     });
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_peer_keepalive_config(&mut self, addr: SocketAddr, config: KeepAliveConfig) {
+        self.core.set_peer_keepalive_config(addr, config);
+    }
+
+    /// 移除指定连接的保活参数覆盖，恢复使用默认配置
+    pub fn clear_peer_keepalive_config(&mut self, addr: SocketAddr) {
+        self.core.clear_peer_keepalive_config(addr);
+    }
+
+    /// 为指定对端设置认证密钥，发往该对端的包用它计算安全码，来自该对端的包用它校验
+    /// 
+    /// 再次设置ID不同的密钥即轮换，旧密钥在`KEY_ROTATION_GRACE`内仍被接受，连接不会中断：
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, PeerKey};
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     
+    ///     rudp.set_peer_key(peer, PeerKey::siphash(1, *b"0123456789abcdef"));
+    ///     // 之后换成新密钥，对端做同样的设置
+    ///     rudp.set_peer_key(peer, PeerKey::siphash(2, *b"fedcba9876543210"));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_peer_key(&mut self, addr: SocketAddr, key: PeerKey) {
+        self.core.set_peer_key(addr, key, self.clock.now());
+    }
+
+    /// 移除指定对端的密钥，恢复使用实例的认证器
+    pub fn clear_peer_key(&mut self, addr: SocketAddr) {
+        self.core.clear_peer_key(addr);
+    }
+
+    /// 指定对端当前用于发送的密钥ID
+    pub fn peer_key_id(&self, addr: SocketAddr) -> Option<u8> {
+        self.core.peer_key_id(addr)
+    }
+
+    /// 获取指定连接实际生效的保活参数
+    pub fn peer_keepalive_config(&self, addr: SocketAddr) -> &KeepAliveConfig {
+        self.core.peer_keepalive_config(addr)
+    }
+
+    /// 为指定连接设置重传退避参数，覆盖默认配置
+    /// 
+    /// 例如在不稳定链路上的长时间传输中，让数据包一直重传到截止时间，而不是在
+    /// `max_retries`次后放弃：
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, BackoffConfig};
+    /// use std::time::Duration;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     
+    ///     rudp.set_peer_backoff_config("127.0.0.1:8081".parse()?, BackoffConfig {
+    ///         multiplier: 1.5,
+    ///         max_rto: Some(Duration::from_secs(10)),
+    ///         retry_deadline: Some(Duration::from_secs(300)),
+    ///     });
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_peer_backoff_config(&mut self, addr: SocketAddr, config: BackoffConfig) {
+        self.core.set_peer_backoff_config(addr, config);
+    }
+
+    /// 移除指定连接的重传退避参数覆盖，恢复使用默认配置
+    pub fn clear_peer_backoff_config(&mut self, addr: SocketAddr) {
+        self.core.clear_peer_backoff_config(addr);
+    }
 
-        // Clear all internal state
-        self.send_buffer.clear();
-        self.recv_acks.clear();
-        self.next_seq.clear();
-        self.rtt_stats.clear();
-        self.connection_stats.clear();
-        self.connection_states.clear();
-        self.pending_acks.clear();
+    /// 获取指定连接实际生效的重传退避参数
+    pub fn peer_backoff_config(&self, addr: SocketAddr) -> &BackoffConfig {
+        self.core.peer_backoff_config(addr)
     }
 
-    /// 获取一个用于写入的buffer
-    /// 
-    /// 从内存池中获取一个预分配的buffer，用户可以直接写入数据区域
-    /// 协议头空间已预留，用户无需关心
+    /// 设置随每个ping和ping应答发送的心跳载荷，空载荷表示不发送
     /// 
-    /// # 返回
-    /// - `Ok(PooledBuffer)`: 可用的buffer，用户可以写入数据区
-    /// - `Err(RudpError)`: 获取失败
+    /// 对端通过`EventHandler::on_heartbeat`或[`peer_heartbeat`](Self::peer_heartbeat)读取，
+    /// 保活流量顺带交换少量状态，不需要额外的数据包。载荷超过[`MAX_HEARTBEAT_SIZE`](crate::MAX_HEARTBEAT_SIZE)
+    /// 时返回`RudpError::BufferTooLarge`
     /// 
     /// # 使用示例
     /// ```rust,no_run
@@ -143,38 +2321,81 @@ impl Rudpbase {
     /// 
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
-    ///     let mut buffer = rudp.get_buffer()?;
-    ///     
-    ///     // 写入用户数据
-    ///     let data = b"Hello, world!";
-    ///     buffer.data_mut()[..data.len()].copy_from_slice(data);
-    ///     buffer.set_data_len(data.len())?;
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     rudp.set_heartbeat(b"load=3")?;
     ///     
-    ///     // 发送数据
-    ///     let target_addr = "127.0.0.1:8081".parse().unwrap();
-    ///     rudp.send(buffer, target_addr).await?;
+    ///     // 应答带回对端的心跳载荷
+    ///     rudp.ping(peer).await?;
+    ///     if let Some(status) = rudp.peer_heartbeat(peer) {
+    ///         println!("peer status: {}", String::from_utf8_lossy(status));
+    ///     }
     ///     Ok(())
     /// }
     /// ```
-    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
-        self.buffer_pool.get_write_buffer()
+    pub fn set_heartbeat(&mut self, payload: &[u8]) -> Result<(), RudpError> {
+        self.core.set_heartbeat(payload)
     }
 
-    /// 发送数据
+    /// 本端的心跳载荷
+    pub fn heartbeat(&self) -> &[u8] {
+        self.core.heartbeat()
+    }
+
+    /// 最近一次从`addr`的ping或ping应答中收到的心跳载荷
+    pub fn peer_heartbeat(&self, addr: SocketAddr) -> Option<&[u8]> {
+        self.core.peer_heartbeat(addr)
+    }
+
+    /// 限制发往指定对端的单个数据包的用户数据大小，例如已知到该对端的路径MTU较小时
     /// 
-    /// 这是零拷贝的发送方法，直接使用预分配的buffer
+    /// 只能低于`max_payload_size`，更大的值按`max_payload_size`处理；消息分片和合并包也按
+    /// 此大小拆分
     /// 
-    /// **重要**: 此方法包含拥塞控制，如果当前拥塞窗口已满，会返回错误
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpError};
     /// 
-    /// # 参数
-    /// - `buffer`: 包含数据的内存池buffer
-    /// - `target`: 目标地址
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     // 经过隧道的路径只能承载较小的数据报
+    ///     rudp.set_peer_max_payload(peer, 1200);
+    ///     let mut buffer = rudp.get_buffer()?;
+    ///     buffer.write_bytes(&[0u8; 1300])?;
+    ///     match rudp.send(buffer, peer).await {
+    ///         Err(RudpError::BufferTooLarge { max, .. }) => assert_eq!(max, 1200),
+    ///         other => println!("{:?}", other),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_peer_max_payload(&mut self, addr: SocketAddr, size: usize) {
+        self.core.set_peer_max_payload(addr, size);
+    }
+
+    /// 移除指定对端的载荷大小限制，恢复使用`max_payload_size`
+    pub fn clear_peer_max_payload(&mut self, addr: SocketAddr) {
+        self.core.clear_peer_max_payload(addr);
+    }
+
+    /// 发往指定对端的单个数据包实际可携带的用户数据字节数
+    /// 
+    /// 已扣除数据包携带的扩展区（例如`timestamps`开启时的发送时间戳）。发送接口在做任何处理之前按此检查载荷大小，超过时返回带有该上限的
+    /// `RudpError::BufferTooLarge`
+    pub fn max_payload_for(&self, addr: SocketAddr) -> usize {
+        self.core.max_payload_for(addr)
+    }
+
+    /// 指定发往某个对端的数据包使用的本地源地址
+    /// 
+    /// 适用于多网卡主机：socket绑定在通配地址上，按对端选择出口地址（Linux）。
+    /// 源地址必须与对端地址族相同，且是本机已配置的地址
     /// 
     /// # 返回
-    /// - `Ok(())`: 发送成功
-    /// - `Err(RudpError::CongestionWindowFull)`: 拥塞窗口已满，请稍后重试
-    /// - `Err(RudpError)`: 其他发送失败原因
+    /// - `Ok(())`: 设置成功
+    /// - `Err(RudpError::InvalidConfig)`: 平台不支持、socket未绑定通配地址或地址族不匹配
     /// 
     /// # 使用示例
     /// ```rust,no_run
@@ -182,523 +2403,353 @@ impl Rudpbase {
     /// 
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
-    ///     let mut buffer = rudp.get_buffer()?;
-    ///     
-    ///     // 填充数据
-    ///     let data = b"Hello, world!";
-    ///     buffer.data_mut()[..data.len()].copy_from_slice(data);
-    ///     buffer.set_data_len(data.len())?;
-    ///     
-    ///     // 发送（可能因拥塞窗口满而失败）
-    ///     let target_addr = "127.0.0.1:8081".parse().unwrap();
-    ///     match rudp.send(buffer, target_addr).await {
-    ///         Ok(()) => println!("发送成功"),
-    ///         Err(rudpbase::RudpError::CongestionWindowFull) => {
-    ///             println!("拥塞窗口已满，请稍后重试");
-    ///             // 可以等待一段时间后重试，或者调用tick()处理ACK
-    ///         }
-    ///         Err(e) => println!("发送失败: {}", e),
-    ///     }
+    ///     let mut rudp = Rudpbase::new("0.0.0.0:8080".parse()?).await?;
+    ///     rudp.set_peer_source_addr("192.0.2.10:8081".parse()?, "192.0.2.1".parse()?)?;
     ///     Ok(())
     /// }
     /// ```
-    pub async fn send(&mut self, mut buffer: PooledBuffer, target: SocketAddr) -> Result<(), RudpError> {
-        // 检查拥塞窗口
-        let rtt_stats = self.rtt_stats.entry(target).or_insert_with(RttStats::new);
-        if !rtt_stats.can_send() {
-            return Err(RudpError::CongestionWindowFull);
+    pub fn set_peer_source_addr(&mut self, addr: SocketAddr, source: IpAddr) -> Result<(), RudpError> {
+        if !socket::SOURCE_SELECTION_SUPPORTED {
+            return Err(RudpError::InvalidConfig {
+                message: "per-peer source addresses are not supported on this platform".to_string(),
+            });
         }
-        
-        let seq = self.get_next_seq(target);
-        
-        // Fill protocol header
-        buffer.fill_protocol_header(PacketType::Data, seq)?;
-        
-        // Send packet first
-        self.socket.send_to(buffer.full_data(), target).await?;
-        
-        // Update congestion control (packet sent)
-        self.rtt_stats.get_mut(&target).unwrap().on_packet_sent();
-        
-        // Store for retransmission (after sending)
-        let rto = self.rtt_stats.get(&target).unwrap().rto;
-        let pending_packet = PendingPacket::new(buffer, rto);
-        self.send_buffer.entry(target).or_insert_with(HashMap::new).insert(seq, pending_packet);
-        
-        // Update statistics
-        self.connection_stats.entry(target).or_insert_with(ConnectionStats::new).record_packet_sent();
-        
-        // Update connection state
-        self.connection_states.entry(target).or_insert_with(ConnectionState::new).update_activity();
-        
+        let Some(socket) = self.transport.udp_socket() else {
+            return Err(RudpError::InvalidConfig {
+                message: "per-peer source addresses require a UDP socket transport".to_string(),
+            });
+        };
+        if !socket.local_addr()?.ip().is_unspecified() {
+            return Err(RudpError::InvalidConfig {
+                message: "per-peer source addresses require a socket bound to the wildcard address".to_string(),
+            });
+        }
+        if source.is_ipv4() != addr.is_ipv4() {
+            return Err(RudpError::InvalidConfig {
+                message: format!("source address {} does not match the address family of {}", source, addr),
+            });
+        }
+        self.source_addrs.insert(addr, source);
         Ok(())
     }
 
-    /// 获取内存池统计信息
-    pub fn get_buffer_pool_stats(&self) -> Result<crate::buffer_pool::PoolStats, RudpError> {
-        self.buffer_pool.stats()
-    }
-
-    /// 接收数据
-    /// 
-    /// 从内存池获取buffer来存储接收的数据，实现零拷贝接收
+    /// 加入IPv4组播组
     /// 
-    /// **重要说明**：
-    /// - 只返回Data包（PacketType::Data = 2）给上层应用
-    /// - 控制包（ACK、NACK、PING等）在库内部自动处理，不会返回给上层
-    /// - 库会自动处理重传、心跳、连接管理等逻辑
+    /// 加入后发往该组的数据报（对端用[`send_unreliable`](Self::send_unreliable)发送）通过
+    /// `recv()`返回。socket需绑定在通配地址和组播使用的端口上；`interface`为接收组播的
+    /// 本地网卡地址，`Ipv4Addr::UNSPECIFIED`表示由系统选择
     /// 
     /// # 返回
-    /// - `Some(ReceivedData)`: 接收到用户数据包，包含发送方地址和池化buffer
-    /// - `None`: 没有用户数据或超时（控制包已在内部处理）
+    /// - `Ok(())`: 加入成功
+    /// - `Err(RudpError::InvalidConfig)`: 传输层不是UDP socket
+    /// - `Err(RudpError::Io)`: 系统拒绝加入（如地址不是组播地址）
     /// 
     /// # 使用示例
     /// ```rust,no_run
     /// use rudpbase::Rudpbase;
+    /// use std::net::Ipv4Addr;
     /// 
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse().unwrap()).await?;
-    ///     
+    ///     let mut rudp = Rudpbase::new("0.0.0.0:5353".parse()?).await?;
+    ///     rudp.join_multicast_v4("239.255.0.1".parse()?, Ipv4Addr::UNSPECIFIED)?;
     ///     loop {
-    ///         // 维护连接（处理重传、心跳等）
-    ///         rudp.tick().await;
-    ///         
-    ///         // 接收用户数据
-    ///         if let Some(received) = rudp.recv().await {
-    ///             match received.result {
-    ///                 Ok(buffer) => {
-    ///                     println!("Received user data from {}: {:?}", 
-    ///                         received.from, buffer.data());
-    ///                     // buffer会在离开作用域时自动归还到内存池
-    ///                 }
-    ///                 Err(e) => {
-    ///                     println!("Receive error: {}", e);
-    ///                 }
-    ///             }
-    ///         }
+    ///         let received = rudp.recv().await;
+    ///         println!("announcement from {}", received.from);
     ///     }
     /// }
     /// ```
-    pub async fn recv(&mut self) -> Option<ReceivedData> {
-        let mut buf = [0u8; MAX_BUFFER_SIZE + 64]; // Extra space for headers
-        
-        match time::timeout(Duration::from_millis(1), self.socket.recv_from(&mut buf)).await {
-            Ok(Ok((len, from))) => {
-                let packet_data = &buf[..len];
-                match self.handle_received_packet(packet_data, from).await {
-                    Ok(Some(received)) => Some(received),
-                    Ok(None) => None, // Control packet, no data to return
-                    Err(e) => Some(ReceivedData {
-                        from,
-                        result: Err(e),
-                    }),
-                }
-            }
-            Ok(Err(e)) => Some(ReceivedData {
-                from: "0.0.0.0:0".parse().unwrap(),
-                result: Err(RudpError::Io(e)),
-            }),
-            Err(_) => None, // Timeout, no data received
-        }
+    pub fn join_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> Result<(), RudpError> {
+        self.multicast_socket()?.join_multicast_v4(group, interface)?;
+        Ok(())
     }
 
-    /// Maintenance function - handle retransmissions, timeouts, ACKs, etc.
-    pub async fn tick(&mut self) {
-        let now = Instant::now();
+    /// 离开IPv4组播组
+    pub fn leave_multicast_v4(&self, group: Ipv4Addr, interface: Ipv4Addr) -> Result<(), RudpError> {
+        self.multicast_socket()?.leave_multicast_v4(group, interface)?;
+        Ok(())
+    }
+
+    /// 加入IPv6组播组
+    /// 
+    /// 与[`join_multicast_v4`](Self::join_multicast_v4)相同，`interface`为网卡索引，0表示由系统选择
+    pub fn join_multicast_v6(&self, group: &Ipv6Addr, interface: u32) -> Result<(), RudpError> {
+        self.multicast_socket()?.join_multicast_v6(group, interface)?;
+        Ok(())
+    }
 
-        // Handle retransmissions
-        self.handle_retransmissions(now).await;
+    /// 离开IPv6组播组
+    pub fn leave_multicast_v6(&self, group: &Ipv6Addr, interface: u32) -> Result<(), RudpError> {
+        self.multicast_socket()?.leave_multicast_v6(group, interface)?;
+        Ok(())
+    }
 
-        // Send pending ACKs
-        self.send_pending_acks().await;
+    fn multicast_socket(&self) -> Result<&UdpSocket, RudpError> {
+        self.transport.udp_socket().ok_or_else(|| RudpError::InvalidConfig {
+            message: "multicast requires a UDP socket transport".to_string(),
+        })
+    }
 
-        // Check connection health
-        self.check_connection_health(now).await;
+    /// 移除指定对端的源地址设置，恢复由内核选择
+    pub fn clear_peer_source_addr(&mut self, addr: SocketAddr) {
+        self.source_addrs.remove(&addr);
+    }
 
-        // Periodic cleanup
-        if now.duration_since(self.last_cleanup) > Duration::from_secs(60) {
-            self.periodic_cleanup(now);
-            self.last_cleanup = now;
-        }
+    /// 获取指定对端设置的源地址
+    pub fn peer_source_addr(&self, addr: SocketAddr) -> Option<IpAddr> {
+        self.source_addrs.get(&addr).copied()
     }
 
     /// Get connection status
     pub fn connection_status(&self, addr: SocketAddr) -> ConnectionStatus {
-        self.connection_states.get(&addr)
-            .map(|state| state.status.clone())
-            .unwrap_or(ConnectionStatus::Dead)
+        self.core.connection_status(addr)
     }
 
     /// Get connection statistics
     pub fn get_stats(&self, addr: SocketAddr) -> Option<ConnectionStats> {
-        self.connection_stats.get(&addr).cloned()
+        self.core.get_stats(addr)
     }
 
-    /// 获取连接的拥塞控制状态
-    /// 
-    /// 返回指定地址的拥塞窗口大小、飞行中包数量等信息
-    pub fn get_congestion_info(&self, addr: SocketAddr) -> Option<CongestionInfo> {
-        self.rtt_stats.get(&addr).map(|stats| CongestionInfo {
-            congestion_window: stats.cwnd,
-            slow_start_threshold: stats.ssthresh,
-            in_flight_packets: stats.in_flight,
-            available_window: stats.available_window(),
-            congestion_state: stats.congestion_state.clone(),
-            current_rto: stats.rto,
-        })
+    /// 按[`PeerId`]获取连接统计
+    pub fn peer_stats(&self, peer: PeerId) -> Option<ConnectionStats> {
+        self.core.peer_stats(peer)
     }
 
-    // Private helper methods
-
-    /// 获取下一个序列号
-    /// 
-    /// 为每个目标地址维护独立的序列号计数器
-    /// 序列号从0开始，每次调用递增1
-    /// 
-    /// 序列号溢出处理：
-    /// - 使用 wrapping_add 安全处理 u32::MAX + 1 = 0 的情况
-    /// - 序列号是连续的：65534, 65535, 0, 1... 不需要特殊处理
-    fn get_next_seq(&mut self, addr: SocketAddr) -> u32 {
-        // 获取或创建该地址的序列号计数器，初始值为0
-        let seq = self.next_seq.entry(addr).or_insert(0);
-        let current = *seq;  // 保存当前值，这是要返回的序列号
-        *seq = seq.wrapping_add(1);  // 安全递增，处理溢出：u32::MAX + 1 = 0
+    /// `addr`处对端的[`PeerId`]，参见[`RudpCore::peer_id`]
+    pub fn peer_id(&self, addr: SocketAddr) -> Option<PeerId> {
+        self.core.peer_id(addr)
+    }
 
-        current  // 返回使用的序列号
+    /// `peer`当前所在的地址，对端迁移后随之更新
+    pub fn peer_addr(&self, peer: PeerId) -> Option<SocketAddr> {
+        self.core.peer_addr(peer)
     }
 
-    /// 处理接收到的包
+    /// 获取所有连接的汇总统计
     /// 
-    /// 内部处理所有控制包（ACK、NACK、PING等），只有Data包会返回给上层
-    async fn handle_received_packet(&mut self, packet_data: &[u8], from: SocketAddr) -> Result<Option<ReceivedData>, RudpError> {
-        let packet = RawPacket::parse(packet_data)?;
-
-        // Verify security code
-        if !SecurityCode::verify(packet.packet_type, packet.seq, &packet.data, packet.security_code) {
-            return Err(RudpError::Security);
-        }
-
-        // Update connection activity
-        if let Some(state) = self.connection_states.get_mut(&from) {
-            state.update_activity();
-        }
-
-        match packet.packet_type {
-            // 只有Data包返回给上层应用
-            PacketType::Data => self.handle_data_packet(packet, from).await,
-            
-            // 以下都是控制包，在库内部处理，不暴露给上层
-            PacketType::DataAck => {
-                self.handle_data_ack_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-            PacketType::DataNack => {
-                self.handle_data_nack_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-            PacketType::Ping => {
-                self.handle_ping_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-            PacketType::PingAck => {
-                self.handle_ping_ack_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-            PacketType::Close => {
-                self.handle_close_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-            PacketType::CloseAck => {
-                self.handle_close_ack_packet(packet, from).await;
-                Ok(None) // 不返回给上层
-            }
-        }
+    /// 汇总所有对端的收发包数、字节数、重传次数，以及活跃连接数、
+    /// 待确认包数量、内存池状态和收到的无效包总数
+    pub fn global_stats(&self) -> Result<GlobalStats, RudpError> {
+        self.core.global_stats()
     }
 
-    /// 处理数据包
-    async fn handle_data_packet(&mut self, packet: RawPacket, from: SocketAddr) -> Result<Option<ReceivedData>, RudpError> {
-        let received_seqs = self.recv_acks.entry(from).or_insert_with(HashSet::new);
-        
-        if received_seqs.contains(&packet.seq) {
-            // Duplicate packet, resend ACK
-            self.send_ack(from, packet.seq).await;
-            return Ok(None);
-        }
-
-        // New packet, process data
-        received_seqs.insert(packet.seq);
-        self.send_ack(from, packet.seq).await;
-
-        // Update statistics
-        self.connection_stats.entry(from).or_insert_with(ConnectionStats::new).record_packet_received();
-
-        // 从内存池获取buffer并拷贝数据
-        let mut buffer = self.buffer_pool.get_write_buffer()?;
-        if packet.data.len() > buffer.data_mut().len() {
-            return Err(RudpError::BufferTooLarge { 
-                size: packet.data.len(), 
-                max: buffer.data_mut().len() 
-            });
-        }
-        
-        // 将接收到的数据拷贝到内存池buffer中
-        buffer.data_mut()[..packet.data.len()].copy_from_slice(&packet.data);
-        buffer.set_data_len(packet.data.len())?;
-
-        Ok(Some(ReceivedData {
-            from,
-            result: Ok(buffer),
-        }))
-    }
-
-    async fn handle_data_ack_packet(&mut self, packet: RawPacket, from: SocketAddr) {
-        if let Some(ack_packet) = DataAckPacket::deserialize(&packet.data) {
-            for ack_seq in ack_packet.ack_seqs {
-                if let Some(pending_packets) = self.send_buffer.get_mut(&from) {
-                    if let Some(pending_packet) = pending_packets.remove(&ack_seq) {
-                        // Calculate RTT and update statistics
-                        let rtt = Instant::now().duration_since(pending_packet.send_time);
-                        let rtt_stats = self.rtt_stats.entry(from).or_insert_with(RttStats::new);
-                        rtt_stats.update_rtt(rtt);
-                        rtt_stats.on_ack_received(1);
-                        self.connection_stats.entry(from).or_insert_with(ConnectionStats::new).update_rtt(rtt);
-                    }
-                }
-            }
-        }
+    /// 遍历所有连接的统计信息
+    pub fn iter_stats(&self) -> impl Iterator<Item = (SocketAddr, &ConnectionStats)> {
+        self.core.iter_stats()
     }
 
-    async fn handle_data_nack_packet(&mut self, packet: RawPacket, from: SocketAddr) {
-        if let Some(nack_packet) = DataNackPacket::deserialize(&packet.data) {
-            for nack_seq in nack_packet.nack_seqs {
-                if let Some(pending_packets) = self.send_buffer.get_mut(&from) {
-                    if let Some(pending_packet) = pending_packets.get_mut(&nack_seq) {
-                        // Immediate retransmission for NACK
-                        let _ = self.socket.send_to(&pending_packet.buffer.full_data(), from).await;
-                        pending_packet.retry_count += 1;
-                        pending_packet.send_time = Instant::now();
-                        
-                        // Update statistics
-                        self.connection_stats.entry(from).or_insert_with(ConnectionStats::new).record_retransmission();
-                    }
-                }
-            }
-        }
+    /// 遍历当前所有连接的地址、连接状态和统计信息，参见[`RudpCore::connections`]
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     for (addr, status, stats) in rudp.connections() {
+    ///         println!("{} {:?} sent {} bytes, received {} bytes", addr, status, stats.bytes_sent, stats.bytes_received);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn connections(&self) -> impl Iterator<Item = (SocketAddr, ConnectionStatus, ConnectionStats)> + '_ {
+        self.core.connections()
     }
 
-    async fn handle_ping_packet(&mut self, packet: RawPacket, from: SocketAddr) {
-        // Send ping acknowledgment
-        let security_code = SecurityCode::calculate(PacketType::PingAck, packet.seq, &packet.data);
-        let ping_ack = RawPacket {
-            packet_type: PacketType::PingAck,
-            security_code,
-            seq: packet.seq,
-            data: packet.data, // Echo back the timestamp
-        };
-
-        let _ = self.socket.send_to(&ping_ack.serialize(), from).await;
+    /// 获取来自`addr`的无效包计数（安全码校验失败、解析失败、未知包类型）
+    ///
+    /// 在`invalid_packet_window`内发送超过`max_invalid_packets`个无效包的来源，
+    /// 在一个周期内的所有包都被丢弃且不回复
+    pub fn invalid_packet_stats(&self, addr: SocketAddr) -> Option<InvalidPacketStats> {
+        self.core.invalid_packet_stats(addr)
     }
 
-    async fn handle_ping_ack_packet(&mut self, packet: RawPacket, from: SocketAddr) {
-        if let Some(ping_packet) = PingPacket::deserialize(&packet.data) {
-            // Calculate RTT
-            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
-            if now > ping_packet.timestamp {
-                let rtt = Duration::from_nanos(now - ping_packet.timestamp);
-                                        let rtt_stats = self.rtt_stats.entry(from).or_insert_with(RttStats::new);
-                        rtt_stats.update_rtt(rtt);
-                        rtt_stats.on_ack_received(1);
-                self.connection_stats.entry(from).or_insert_with(ConnectionStats::new).update_rtt(rtt);
-            }
-        }
-
-        // Mark ping as received
-        if let Some(state) = self.connection_states.get_mut(&from) {
-            state.mark_ping_received();
-        }
+    /// 获取连接的拥塞控制状态
+    /// 
+    /// 返回指定地址的拥塞窗口、慢启动阈值、飞行中包数量、拥塞控制阶段、当前RTO和
+    /// 平滑RTT，应用可以据此调整发送速率
+    /// 
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     if let Some(info) = rudp.congestion_info("127.0.0.1:8081".parse()?) {
+    ///         if info.available_window == 0 {
+    ///             println!("window full, RTT {:?}, retry after {:?}", info.smoothed_rtt, info.current_rto);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn congestion_info(&self, addr: SocketAddr) -> Option<CongestionInfo> {
+        self.core.congestion_info(addr)
     }
 
-    async fn handle_close_packet(&mut self, packet: RawPacket, from: SocketAddr) {
-        // Send close acknowledgment
-        let security_code = SecurityCode::calculate(PacketType::CloseAck, packet.seq, &packet.data);
-        let close_ack = RawPacket {
-            packet_type: PacketType::CloseAck,
-            security_code,
-            seq: packet.seq,
-            data: vec![],
-        };
-
-        let _ = self.socket.send_to(&close_ack.serialize(), from).await;
-
-        // Clean up connection
-        self.cleanup_connection(from);
+    /// 与[`congestion_info`](Self::congestion_info)相同
+    pub fn get_congestion_info(&self, addr: SocketAddr) -> Option<CongestionInfo> {
+        self.core.congestion_info(addr)
     }
 
-    async fn handle_close_ack_packet(&mut self, _packet: RawPacket, from: SocketAddr) {
-        // Clean up connection
-        self.cleanup_connection(from);
+    /// 获取连接的完整RTT统计，包括RTT和抖动分布
+    pub fn rtt_stats(&self, addr: SocketAddr) -> Option<&RttStats> {
+        self.core.rtt_stats(addr)
     }
 
-    async fn send_ack(&mut self, target: SocketAddr, seq: u32) {
-        self.pending_acks.entry(target).or_insert_with(Vec::new).push(seq);
+    /// 获取发往`addr`的单向时延估计
+    ///
+    /// 开启`timestamps`后每个数据包携带发送时间，对端在ACK中回显。两端时钟不同步，
+    /// 因此只报告高于路径最低时延的部分（排队时延）及其变化趋势，可用于基于时延的
+    /// 拥塞控制或抖动缓冲
+    ///
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::{Rudpbase, RudpConfig};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let config = RudpConfig::new().with_timestamps(true);
+    ///     let rudp = Rudpbase::with_config("127.0.0.1:8080".parse()?, config).await?;
+    ///     if let Some(delay) = rudp.outgoing_delay("127.0.0.1:8081".parse()?) {
+    ///         if delay.trend > 0.05 {
+    ///             println!("queue building up: {:?}", delay.smoothed_queuing_delay);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn outgoing_delay(&self, addr: SocketAddr) -> Option<OneWayDelay> {
+        self.core.outgoing_delay(addr)
     }
 
-    async fn send_pending_acks(&mut self) {
-        let targets: Vec<SocketAddr> = self.pending_acks.keys().cloned().collect();
-        
-        for target in targets {
-            if let Some(ack_seqs) = self.pending_acks.remove(&target) {
-                if !ack_seqs.is_empty() {
-                    let ack_packet = DataAckPacket::new(ack_seqs);
-                    let seq = self.get_next_seq(target);
-                    let security_code = SecurityCode::calculate(PacketType::DataAck, seq, &ack_packet.serialize());
-                    
-                    let packet = RawPacket {
-                        packet_type: PacketType::DataAck,
-                        security_code,
-                        seq,
-                        data: ack_packet.serialize(),
-                    };
-
-                    let _ = self.socket.send_to(&packet.serialize(), target).await;
-                }
-            }
-        }
+    /// 获取从`addr`收到数据的单向时延估计，需要对端开启`timestamps`
+    pub fn incoming_delay(&self, addr: SocketAddr) -> Option<OneWayDelay> {
+        self.core.incoming_delay(addr)
     }
 
-    async fn send_close_packet(&mut self, target: SocketAddr) -> Result<(), RudpError> {
-        let seq = self.get_next_seq(target);
-        let security_code = SecurityCode::calculate(PacketType::Close, seq, &[]);
-        
-        let packet = RawPacket {
-            packet_type: PacketType::Close,
-            security_code,
-            seq,
-            data: vec![],
-        };
-
-        self.socket.send_to(&packet.serialize(), target).await?;
-        Ok(())
+    /// `addr`的时钟相对本端时钟的偏差，以及ping两个方向的单向时延
+    ///
+    /// 保活ping和[`ping`](Self::ping)的应答都带回对端应答时的时间，两端时钟不同步也能
+    /// 测量；尚未收到这样的应答时返回`None`
+    ///
+    /// # 使用示例
+    /// ```rust,no_run
+    /// use rudpbase::Rudpbase;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+    ///     let peer = "127.0.0.1:8081".parse()?;
+    ///     rudp.ping(peer).await?;
+    ///     if let Some(clock) = rudp.clock_offset(peer) {
+    ///         println!("peer clock {}ns ahead (±{:?}), {:?} there, {:?} back",
+    ///             clock.offset_nanos, clock.uncertainty, clock.outgoing_delay, clock.incoming_delay);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn clock_offset(&self, addr: SocketAddr) -> Option<ClockOffset> {
+        self.core.clock_offset(addr)
     }
 
-    async fn handle_retransmissions(&mut self, now: Instant) {
-        let mut to_remove = Vec::new();
+    /// 发送core队列中的所有数据报
+    /// 
+    /// 未启用批量I/O时逐个发送，返回第一个发送错误；启用时一次系统调用批量发送，
+    /// 失败只记录日志。指定了源地址的对端总是逐个发送
+    async fn flush_transmits(&mut self) -> Result<(), RudpError> {
+        if self.core.transmit_count() == 0 {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        {
+            let mut datagrams = self.core.queued_transmits();
+            if self.core.config().io_batch_size == 1 {
+                for (data, target) in datagrams {
+                    if let Err(e) = socket::send_to(&*self.transport, data, target, self.source_addrs.get(&target).copied()).await {
+                        result = result.and(Err(e.into()));
+                    }
+                }
+            } else {
+                // 指定了源地址的对端逐个发送
+                let mut sourced = Vec::new();
+                if !self.source_addrs.is_empty() {
+                    datagrams.retain(|&(data, target)| match self.source_addrs.get(&target) {
+                        Some(&source) => {
+                            sourced.push((data, target, source));
+                            false
+                        }
+                        None => true,
+                    });
+                }
 
-        for (addr, packets) in &mut self.send_buffer {
-            let mut addr_to_remove = Vec::new();
-            
-            for (seq, pending_packet) in packets.iter_mut() {
-                if pending_packet.should_retry(now) {
-                    if pending_packet.retry_count >= 5 {
-                        // Max retries reached, mark for removal
-                        addr_to_remove.push(*seq);
-                    } else {
-                        // Retry with exponential backoff
-                        let new_rto = pending_packet.rto * 2;
-                        pending_packet.retry(new_rto);
-                        
-                        let _ = self.socket.send_to(&pending_packet.buffer.full_data(), *addr).await;
-                        
-                        // Update statistics
-                        self.connection_stats.entry(*addr).or_insert_with(ConnectionStats::new).record_retransmission();
-                        
-                        // Update congestion control for packet loss
-                        self.rtt_stats.entry(*addr).or_insert_with(RttStats::new).on_packet_lost();
+                if !datagrams.is_empty() {
+                    let sent = match self.uring.as_mut() {
+                        Some(driver) => driver.send_batch(&datagrams),
+                        None => batch::send_batch(udp_socket(&*self.transport), &datagrams, &mut self.offload).await,
+                    };
+                    if let Err(_e) = sent {
+                        trace_event!(debug, error = %_e, datagrams = datagrams.len(), "batched send failed");
                     }
                 }
-            }
-            
-            // Remove failed packets
-            for seq in addr_to_remove {
-                packets.remove(&seq);
-            }
-            
-            // If no packets left for this address, mark for removal
-            if packets.is_empty() {
-                to_remove.push(*addr);
+                for (data, target, source) in sourced {
+                    let _ = socket::send_to(&*self.transport, data, target, Some(source)).await;
+                }
             }
         }
 
-        // Remove empty entries
-        for addr in to_remove {
-            self.send_buffer.remove(&addr);
-        }
-        
-        // Update connection states for packet loss
-        for (addr, _) in &self.send_buffer {
-            if let Some(state) = self.connection_states.get_mut(addr) {
-                state.mark_packet_lost();
-            }
-            self.connection_stats.entry(*addr).or_insert_with(ConnectionStats::new).record_packet_lost();
-        }
+        self.core.clear_transmits();
+        result
     }
+}
 
-    async fn check_connection_health(&mut self, now: Instant) {
-        let mut connections_to_ping = Vec::new();
-        let mut connections_to_close = Vec::new();
-
-        for (addr, state) in &self.connection_states {
-            if state.should_ping(now) {
-                connections_to_ping.push(*addr);
-            } else if state.should_close(now) {
-                connections_to_close.push(*addr);
-            }
+/// `connect_host()`依次尝试的地址：IPv6与IPv4交替、IPv6在前（RFC 8305），去掉重复地址
+/// 
+/// 只保留绑定在`local_addr`的socket能发送的地址：绑定IPv4地址时只用IPv4地址；
+/// 绑定IPv6通配地址（双栈）时IPv4地址映射为IPv4映射的IPv6地址，否则只用IPv6地址
+fn connection_candidates(resolved: impl Iterator<Item = SocketAddr>, local_addr: SocketAddr) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = resolved.partition(SocketAddr::is_ipv6);
+    let (v6, v4) = match local_addr.ip() {
+        IpAddr::V4(_) => (Vec::new(), v4),
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            let mapped = v4.into_iter().map(|addr| match addr.ip() {
+                IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+                IpAddr::V6(_) => addr,
+            });
+            (v6, mapped.collect())
         }
+        IpAddr::V6(_) => (v6, Vec::new()),
+    };
 
-        // Send ping packets
-        for addr in connections_to_ping {
-            let ping_packet = PingPacket::new();
-            let seq = self.get_next_seq(addr);
-            let security_code = SecurityCode::calculate(PacketType::Ping, seq, &ping_packet.serialize());
-            
-            let packet = RawPacket {
-                packet_type: PacketType::Ping,
-                security_code,
-                seq,
-                data: ping_packet.serialize(),
-            };
-
-            let _ = self.socket.send_to(&packet.serialize(), addr).await;
-            
-            if let Some(state) = self.connection_states.get_mut(&addr) {
-                state.mark_ping_sent();
-            }
+    let mut candidates = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        let (next_v6, next_v4) = (v6.next(), v4.next());
+        if next_v6.is_none() && next_v4.is_none() {
+            return candidates;
         }
-
-        // Close dead connections
-        for addr in connections_to_close {
-            self.cleanup_connection(addr);
+        for addr in next_v6.into_iter().chain(next_v4) {
+            if !candidates.contains(&addr) {
+                candidates.push(addr);
+            }
         }
     }
+}
 
-    fn cleanup_connection(&mut self, addr: SocketAddr) {
-        self.send_buffer.remove(&addr);
-        self.recv_acks.remove(&addr);
-        self.next_seq.remove(&addr);
-        self.rtt_stats.remove(&addr);
-        self.connection_stats.remove(&addr);
-        self.connection_states.remove(&addr);
-        self.pending_acks.remove(&addr);
+/// 检查传输层是否支持配置中的I/O方式
+fn check_transport(transport: &dyn Transport, config: &RudpConfig) -> Result<(), RudpError> {
+    if transport.udp_socket().is_none() && config.io_batch_size > 1 {
+        return Err(RudpError::InvalidConfig {
+            message: "batched I/O requires a transport backed by a UDP socket".to_string(),
+        });
     }
+    Ok(())
+}
 
-    fn periodic_cleanup(&mut self, now: Instant) {
-        // Clean up old received sequence numbers (older than 1 hour)
-        let cleanup_threshold = now - Duration::from_secs(3600);
-        
-        for (addr, seqs) in &mut self.recv_acks {
-            // 当序列号从u32::MAX溢出回到0时，清理1小时前的ACK缓存
-            // 这样可以避免新的seq=0与旧的seq=0冲突，防止新包被误认为重复包
-            if let Some(&current_seq) = self.next_seq.get(addr) {
-                if current_seq == 0 {
-                    // 序列号刚刚溢出，清理旧的缓存
-                    seqs.clear();
-                }
-            }
-        }
-
-        // Remove empty entries
-        self.recv_acks.retain(|_, seqs| !seqs.is_empty());
-    }
-} 
\ No newline at end of file
+/// 批量I/O使用的底层socket，`check_transport`保证批量模式下一定存在
+fn udp_socket(transport: &dyn Transport) -> &tokio::net::UdpSocket {
+    transport.udp_socket().expect("batched I/O requires a UDP socket transport")
+}
@@ -0,0 +1,223 @@
+//! One-way delay estimation from send timestamps
+//!
+//! With `timestamps` enabled, data packets carry their transmit time on the sender's
+//! clock and acknowledgments echo it together with the arrival time on the receiver's
+//! clock. The two clocks are unrelated, so a single sample (arrival minus transmit
+//! time) is the one-way delay plus an unknown offset. [`DelayEstimator`] therefore
+//! reports delays above the lowest sample seen on the path, which is the queuing
+//! delay the network added, and how fast that delay is changing.
+//!
+//! Pings give absolute one-way delays instead: each acknowledgment carries the time
+//! the peer answered, and [`ClockEstimator`] combines it with the ping's transmit
+//! and arrival times into an estimate of the offset between the two wall clocks.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::stats::{ClockOffset, OneWayDelay};
+
+/// Shortest gap between the transmit times of two samples used for the trend, in
+/// microseconds; packets sent back to back say little about the slope
+const TREND_INTERVAL_US: i64 = 1000;
+
+/// Smoothing factor of the queuing delay and the trend
+const GAIN: f64 = 0.125;
+
+/// Ping exchanges the clock offset is chosen from
+const CLOCK_SAMPLES: usize = 8;
+
+/// One ping exchange, wall-clock nanoseconds since the Unix epoch
+#[derive(Debug, Clone, Copy)]
+struct ClockSample {
+    /// Ping transmit time on the local clock
+    sent: u64,
+    /// Answer time on the peer's clock
+    answered: u64,
+    /// Acknowledgment arrival time on the local clock
+    received: u64,
+}
+
+impl ClockSample {
+    fn rtt(&self) -> u64 {
+        self.received - self.sent
+    }
+
+    /// Peer clock minus local clock, if both directions took equally long
+    fn offset(&self) -> i64 {
+        (i128::from(self.answered) - (i128::from(self.sent) + i128::from(self.received)) / 2) as i64
+    }
+}
+
+/// Clock offset of one peer from its ping acknowledgments
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClockEstimator {
+    /// Latest exchanges, oldest first
+    recent: VecDeque<ClockSample>,
+    samples: u64,
+}
+
+impl ClockEstimator {
+    /// Record a ping sent at `sent` and acknowledged at `received` on the local clock,
+    /// which the peer answered at `answered` on its clock
+    pub(crate) fn record(&mut self, sent: u64, answered: u64, received: u64) {
+        // The local clock was stepped back in between
+        if received < sent {
+            return;
+        }
+        if self.recent.len() == CLOCK_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(ClockSample { sent, answered, received });
+        self.samples += 1;
+    }
+
+    pub(crate) fn snapshot(&self) -> Option<ClockOffset> {
+        // Queuing delays only add to the round trip, so the shortest one is the most
+        // likely to be symmetric
+        let best = self.recent.iter().min_by_key(|sample| sample.rtt())?;
+        let latest = self.recent.back()?;
+        let offset = best.offset();
+        let outgoing = i128::from(latest.answered) - i128::from(offset) - i128::from(latest.sent);
+        let incoming = i128::from(latest.received) + i128::from(offset) - i128::from(latest.answered);
+        let delay = |nanos: i128| Duration::from_nanos(nanos.clamp(0, i128::from(latest.rtt())) as u64);
+        Some(ClockOffset {
+            offset_nanos: offset,
+            uncertainty: Duration::from_nanos(best.rtt() / 2),
+            outgoing_delay: delay(outgoing),
+            incoming_delay: delay(incoming),
+            samples: self.samples,
+        })
+    }
+}
+
+/// Queuing delay statistics of one path
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DelayEstimator {
+    /// First raw sample; later samples are taken relative to it so the clock offset
+    /// cancels out and wrapping timestamps compare correctly
+    base: Option<u32>,
+    /// Lowest relative delay seen, in microseconds
+    min: i64,
+    /// Latest relative delay, in microseconds
+    latest: i64,
+    /// Smoothed queuing delay in microseconds
+    smoothed: f64,
+    /// Transmit time and relative delay of the sample the next slope is measured from
+    anchor: Option<(u32, i64)>,
+    /// Smoothed delay change per unit of time
+    trend: f64,
+    samples: u64,
+}
+
+impl DelayEstimator {
+    /// Record a packet sent at `sent` on the sender's clock that arrived at `received`
+    /// on the receiver's clock (both microseconds, wrapping)
+    pub(crate) fn record(&mut self, sent: u32, received: u32) {
+        let raw = received.wrapping_sub(sent);
+        let base = *self.base.get_or_insert(raw);
+        let delay = raw.wrapping_sub(base) as i32 as i64;
+        self.min = if self.samples == 0 { delay } else { self.min.min(delay) };
+        self.latest = delay;
+
+        let queuing = (delay - self.min) as f64;
+        self.smoothed = if self.samples == 0 { queuing } else { self.smoothed + GAIN * (queuing - self.smoothed) };
+        self.samples += 1;
+
+        match self.anchor {
+            Some((anchor_sent, anchor_delay)) => {
+                let gap = sent.wrapping_sub(anchor_sent) as i32 as i64;
+                if gap >= TREND_INTERVAL_US {
+                    let slope = (delay - anchor_delay) as f64 / gap as f64;
+                    self.trend += GAIN * (slope - self.trend);
+                    self.anchor = Some((sent, delay));
+                }
+            }
+            None => self.anchor = Some((sent, delay)),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Option<OneWayDelay> {
+        (self.samples > 0).then(|| OneWayDelay {
+            queuing_delay: Duration::from_micros((self.latest - self.min) as u64),
+            smoothed_queuing_delay: Duration::from_micros(self.smoothed as u64),
+            trend: self.trend,
+            samples: self.samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queuing_delay_above_lowest_sample() {
+        let mut estimator = DelayEstimator::default();
+        assert!(estimator.snapshot().is_none());
+
+        // Clock offset of about an hour: only the differences matter
+        let offset = 3_600_000_000u32;
+        estimator.record(0, offset.wrapping_add(20_000));
+        estimator.record(10_000, offset.wrapping_add(10_000 + 25_000));
+        let delay = estimator.snapshot().unwrap();
+        assert_eq!(delay.queuing_delay, Duration::from_millis(5));
+        assert_eq!(delay.samples, 2);
+
+        // A faster packet lowers the floor
+        estimator.record(20_000, offset.wrapping_add(20_000 + 15_000));
+        assert_eq!(estimator.snapshot().unwrap().queuing_delay, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_clock_offset_from_fastest_ping() {
+        const MS: u64 = 1_000_000;
+        let mut estimator = ClockEstimator::default();
+        assert!(estimator.snapshot().is_none());
+
+        // The peer's clock is an hour ahead; 10ms each way on an idle path
+        let ahead = 3_600_000 * MS;
+        let start = 1_700_000_000_000 * MS;
+        estimator.record(start, start + ahead + 10 * MS, start + 20 * MS);
+        let clock = estimator.snapshot().unwrap();
+        assert_eq!(clock.offset_nanos, ahead as i64);
+        assert_eq!(clock.uncertainty, Duration::from_millis(10));
+        assert_eq!((clock.outgoing_delay, clock.incoming_delay), (Duration::from_millis(10), Duration::from_millis(10)));
+
+        // A queue on the way out: the offset keeps the fast exchange, the delays show
+        // where the time went
+        let sent = start + 1000 * MS;
+        estimator.record(sent, sent + ahead + 40 * MS, sent + 50 * MS);
+        let clock = estimator.snapshot().unwrap();
+        assert_eq!(clock.offset_nanos, ahead as i64);
+        assert_eq!((clock.outgoing_delay, clock.incoming_delay), (Duration::from_millis(40), Duration::from_millis(10)));
+        assert_eq!(clock.samples, 2);
+
+        // A peer clock behind ours, and a step back of the local clock is ignored
+        let mut estimator = ClockEstimator::default();
+        estimator.record(start, start - 5000 * MS, start + 2 * MS);
+        estimator.record(start, start, start - MS);
+        let clock = estimator.snapshot().unwrap();
+        assert_eq!(clock.offset_nanos, -5001 * MS as i64);
+        assert_eq!(clock.samples, 1);
+    }
+
+    #[test]
+    fn test_trend_follows_growing_queue() {
+        let mut estimator = DelayEstimator::default();
+        // Each packet, 10ms apart, waits 1ms longer than the one before
+        for i in 0..100u32 {
+            let sent = (u32::MAX - 500_000).wrapping_add(i * 10_000);
+            estimator.record(sent, sent.wrapping_add(30_000 + i * 1000));
+        }
+        let delay = estimator.snapshot().unwrap();
+        assert_eq!(delay.queuing_delay, Duration::from_millis(99));
+        assert!((delay.trend - 0.1).abs() < 0.001, "trend {}", delay.trend);
+
+        // The queue drains
+        for i in 0..100u32 {
+            let sent = 2_000_000 + i * 10_000;
+            estimator.record(sent, sent.wrapping_add(129_000 - i * 1000));
+        }
+        assert!(estimator.snapshot().unwrap().trend < 0.0);
+    }
+}
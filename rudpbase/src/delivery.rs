@@ -0,0 +1,166 @@
+//! Per-packet delivery tracking
+//!
+//! [`Rudpbase::send_tracked`](crate::Rudpbase::send_tracked) sends a data packet like
+//! `send()` and returns a [`DeliveryHandle`], a future that resolves once the peer
+//! acknowledges that packet's sequence number or the packet is given up on. The
+//! handle is settled from inside `tick()` / `recv()`, so the instance must keep being
+//! driven while the handle is awaited.
+//!
+//! [`PingHandle`] works the same way for an on-demand ping and resolves to the
+//! measured round-trip time.
+//!
+//! The handles do not depend on an async runtime and can be awaited from any executor.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::error::{ConnectionError, RudpError};
+
+/// State shared by a handle and its sender
+#[derive(Debug)]
+struct Slot<T> {
+    /// Outcome, once settled
+    result: Option<Result<T, RudpError>>,
+    /// Set when the sender is gone
+    closed: bool,
+    /// Task awaiting the handle
+    waker: Option<Waker>,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self { result: None, closed: false, waker: None }))
+    }
+
+    /// Outcome, if settled
+    fn take(&mut self) -> Option<Result<T, RudpError>> {
+        match self.result.take() {
+            Some(result) => Some(result),
+            None if self.closed => Some(Err(ConnectionError::Closed.into())),
+            None => None,
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RudpError>> {
+        match self.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Settles a [`DeliveryHandle`] (or a [`PingHandle`] when `T` is the round-trip
+/// time); dropping it unsettled resolves the handle to `ConnectionError::Closed`
+#[derive(Debug)]
+pub(crate) struct DeliverySender<T = ()> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> DeliverySender<T> {
+    pub(crate) fn send(self, result: Result<T, RudpError>) {
+        self.slot.lock().unwrap().result = Some(result);
+    }
+}
+
+impl<T> Drop for DeliverySender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.closed = true;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Outcome of one packet sent with `send_tracked`
+///
+/// Resolves to:
+/// - `Ok(())` when the peer acknowledged the packet
+/// - `Err(ConnectionError::MaxRetriesExceeded)` when all retransmissions were used up
+/// - `Err(RudpError::Timeout)` when the deadline given to `send_with_deadline` passed
+/// - `Err(RudpError::Cancelled)` when the packet was cancelled with `cancel`
+/// - `Err(ConnectionError::Dead)` when the connection was declared dead first
+/// - `Err(ConnectionError::Closed)` when the instance was closed or dropped first
+///
+/// Dropping the handle does not affect the packet, which is still retransmitted.
+#[derive(Debug)]
+pub struct DeliveryHandle {
+    target: SocketAddr,
+    seq: u32,
+    slot: Arc<Mutex<Slot<()>>>,
+}
+
+impl DeliveryHandle {
+    pub(crate) fn new(target: SocketAddr, seq: u32) -> (Self, DeliverySender) {
+        let slot = Slot::new();
+        (Self { target, seq, slot: Arc::clone(&slot) }, DeliverySender { slot })
+    }
+
+    /// Address the packet was sent to
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Sequence number of the packet
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    /// Outcome, if the packet has been acknowledged or given up on; does not wait
+    pub fn try_result(&mut self) -> Option<Result<(), RudpError>> {
+        self.slot.lock().unwrap().take()
+    }
+}
+
+impl Future for DeliveryHandle {
+    type Output = Result<(), RudpError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.slot.lock().unwrap().poll(cx)
+    }
+}
+
+/// Outcome of an on-demand ping sent with `ping`
+///
+/// Resolves to:
+/// - `Ok(rtt)` when the peer answered, with the time from sending the ping to
+///   receiving its acknowledgment
+/// - `Err(RudpError::Timeout)` when no answer arrived within the peer's `ping_interval`
+/// - `Err(ConnectionError::Closed)` when the instance was closed or dropped first
+#[derive(Debug)]
+pub struct PingHandle {
+    target: SocketAddr,
+    slot: Arc<Mutex<Slot<Duration>>>,
+}
+
+impl PingHandle {
+    pub(crate) fn new(target: SocketAddr) -> (Self, DeliverySender<Duration>) {
+        let slot = Slot::new();
+        (Self { target, slot: Arc::clone(&slot) }, DeliverySender { slot })
+    }
+
+    /// Address the ping was sent to
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Outcome, if the ping has been answered or given up on; does not wait
+    pub fn try_result(&mut self) -> Option<Result<Duration, RudpError>> {
+        self.slot.lock().unwrap().take()
+    }
+}
+
+impl Future for PingHandle {
+    type Output = Result<Duration, RudpError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.slot.lock().unwrap().poll(cx)
+    }
+}
@@ -0,0 +1,5286 @@
+//! Socket-free protocol core
+//!
+//! [`RudpCore`] holds all protocol state (sequence numbers, the retransmission queue,
+//! ACK bookkeeping, congestion control and the connection state machine) and performs
+//! no I/O: it consumes received datagrams together with the current time and produces
+//! datagrams to send, received data and complete messages. It never reads a clock, so
+//! the same inputs always produce the same outputs.
+//!
+//! `Rudpbase` is a thin tokio wrapper around it. Embedders using
+//! another runtime, a blocking socket or a custom event loop drive the core directly:
+//!
+//! - pass every received datagram to [`handle_datagram`](RudpCore::handle_datagram)
+//! - call [`handle_timeout`](RudpCore::handle_timeout) after handling datagrams and
+//!   whenever [`poll_timeout`](RudpCore::poll_timeout) expires
+//! - after each call, send everything [`poll_transmit`](RudpCore::poll_transmit) returns
+//!   and collect data from [`poll_received`](RudpCore::poll_received) and
+//!   [`poll_message`](RudpCore::poll_message)
+//!
+//! ```rust
+//! use rudpbase::{RudpConfig, RudpCore};
+//! use std::net::SocketAddr;
+//! use std::time::Instant;
+//!
+//! let a_addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+//! let b_addr: SocketAddr = "10.0.0.2:1".parse().unwrap();
+//! let now = Instant::now();
+//! let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+//! let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+//!
+//! let mut buffer = a.get_buffer().unwrap();
+//! buffer.data_mut()[..5].copy_from_slice(b"hello");
+//! buffer.set_data_len(5).unwrap();
+//! a.send(buffer, b_addr, now).unwrap();
+//!
+//! while let Some(transmit) = a.poll_transmit() {
+//!     b.handle_datagram(&transmit.contents, a_addr, now);
+//! }
+//! let received = b.poll_received().unwrap();
+//! assert_eq!(received.result.unwrap().data(), b"hello");
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{BackoffConfig, KeepAliveConfig, RudpConfig};
+use crate::error::{ConnectionError, RudpError};
+use crate::events::{ConnectionEvent, EventHandler, Verdict};
+use crate::qlog::{QlogEvent, QlogSink, QlogWriter};
+use crate::report::{PeerStatsReport, StatsReporter, StatsSink};
+#[cfg(feature = "otel")]
+use crate::otel::Telemetry;
+use crate::stats::{ConnectionStats, ConnectionStatus, RttStats, ConnectionState, CongestionInfo, GlobalStats, InvalidPacketStats, OneWayDelay, ClockOffset};
+use crate::protocol::{PacketType, PROTOCOL_HEADER_SIZE, EXTENSION_FLAG, EXTENSION_LENGTH_SIZE, EXTENSION_TIMESTAMP, TIMESTAMP_SECTION_SIZE, Timestamp, ConnectionId, Heartbeat, ReplyTime, PayloadChecksum, Padding, COMPRESSION_HEADER_SIZE, FRAGMENT_HEADER_SIZE, FragmentHeader, RawPacket, RawPacketRef, PingPacket, DataAckPacket, DataNackPacket, CloseReason, MAX_CLOSE_PAYLOAD, MAX_HEARTBEAT_SIZE, BATCH_FRAME_HEADER_SIZE, push_batch_frame, split_batch_frame};
+use crate::security::PacketAuthenticator;
+use crate::buffer_pool::{SharedBufferPool, PooledBuffer};
+use crate::stun;
+use crate::delivery::{DeliveryHandle, DeliverySender, PingHandle};
+use crate::message::{Reassembler, ReceivedMessage, MESSAGE_REASSEMBLY_TIMEOUT};
+use crate::peer_id::{ConnectionIds, PeerId};
+use crate::compression::{self, Compression};
+use crate::window::{ReceiveWindow, WINDOW_SIZE};
+use crate::session::{PeerSnapshot, RttSnapshot, SessionState};
+use crate::peers::PeerActivity;
+use crate::pacing::Pacer;
+use crate::invalid::{InvalidKind, InvalidSources};
+use crate::alert::AlertMonitor;
+use crate::outage::LossEpisodes;
+use crate::redundancy::Redundancy;
+use crate::delay::{ClockEstimator, DelayEstimator};
+use crate::resumption::{ResumptionToken, UsedTokens};
+use crate::keys::{KeyRing, PeerKey};
+#[cfg(feature = "identity")]
+use crate::identity::{Check, Identities, IdentityKey, PeerIdentity};
+
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+
+/// 接收数据结构
+pub struct ReceivedData {
+    /// Data source address
+    pub from: SocketAddr,
+    /// Sequence number the sender's `send()` returned for this packet; `None` for
+    /// unreliable datagrams sent with `send_unreliable()` and for errors, except
+    /// `MaxRetriesExceeded`, which carries the seq of the packet this side gave up on
+    ///
+    /// Sequence numbers are assigned per peer and wrap around at `u32::MAX`, so
+    /// `(from, seq)` identifies a packet within a connection.
+    pub seq: Option<u32>,
+    /// Reception result
+    pub result: Result<PooledBuffer, RudpError>,
+}
+
+/// Datagram produced by [`RudpCore`], to be sent to `destination`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transmit {
+    /// Peer address
+    pub destination: SocketAddr,
+    /// Complete packet, protocol header included
+    pub contents: Vec<u8>,
+}
+
+/// Datagram waiting to be sent
+#[derive(Debug)]
+enum QueuedTransmit {
+    /// Control packet, serialized into a pool buffer when queued
+    Control(PooledBuffer, SocketAddr),
+    /// Data packet held in the send buffer; skipped if it is acknowledged or dropped
+    /// before being sent
+    Data(SocketAddr, u32),
+    /// Data packet with a shared payload, written out for this transmission; skipped
+    /// like `Data`
+    Assembled(PooledBuffer, SocketAddr, u32),
+    /// Unreliable datagram
+    Datagram(PooledBuffer, SocketAddr),
+}
+
+/// Packet stored for retransmission
+#[derive(Debug)]
+enum PacketBuffer {
+    /// Pool buffer passed to `send`
+    Pooled(PooledBuffer),
+    /// Payload shared with the packets to other peers; the protocol header is written
+    /// each time the packet is sent
+    Shared {
+        packet_type: PacketType,
+        seq: u32,
+        /// Extension section written by `seal_shared()`, empty without extensions
+        extensions: Vec<u8>,
+        body: SharedBody,
+        /// Zero bytes after the payload
+        padding: usize,
+    },
+}
+
+impl PacketBuffer {
+    /// 完整的数据包内容（包含协议头），共享载荷的包为`None`
+    fn contents(&self) -> Option<&[u8]> {
+        match self {
+            PacketBuffer::Pooled(buffer) => Some(buffer.full_data()),
+            PacketBuffer::Shared { .. } => None,
+        }
+    }
+
+    /// 完整数据包的长度（包含协议头）
+    fn len(&self) -> usize {
+        match self {
+            PacketBuffer::Shared { extensions, body, padding, .. } => PROTOCOL_HEADER_SIZE + extensions.len() + body.data().len() + padding,
+            _ => self.contents().map_or(0, <[u8]>::len),
+        }
+    }
+
+    /// 用户数据长度
+    fn data_len(&self) -> usize {
+        self.len() - PROTOCOL_HEADER_SIZE
+    }
+
+    /// 协议头中的包类型（Data或Fragment）
+    fn packet_type(&self) -> PacketType {
+        match self {
+            PacketBuffer::Shared { packet_type, .. } => *packet_type,
+            _ => self.contents().and_then(|packet| PacketType::from_u8(packet[0] & !EXTENSION_FLAG)).unwrap_or(PacketType::Data),
+        }
+    }
+
+    /// 把完整的数据包写入`out`（长度为`len()`），共享载荷的包用`authenticator`计算安全码
+    fn write_to(&self, out: &mut [u8], authenticator: &dyn PacketAuthenticator) {
+        let PacketBuffer::Shared { packet_type, seq, extensions, body, padding } = self else {
+            out.copy_from_slice(self.contents().unwrap_or_default());
+            return;
+        };
+        let (header, data) = out.split_at_mut(PROTOCOL_HEADER_SIZE);
+        let (section, rest) = data.split_at_mut(extensions.len());
+        section.copy_from_slice(extensions);
+        let (payload, tail) = rest.split_at_mut(rest.len() - padding);
+        payload.copy_from_slice(body.data());
+        tail.fill(0);
+        let security_code = authenticator.code(*packet_type, *seq, data);
+        header[0] = *packet_type as u8 | if extensions.is_empty() { 0 } else { EXTENSION_FLAG };
+        header[1..5].copy_from_slice(&security_code.to_be_bytes());
+        header[5..9].copy_from_slice(&seq.to_be_bytes());
+    }
+
+    /// 把完整的数据包写入一个内存池buffer
+    fn assemble(&self, pool: &SharedBufferPool, authenticator: &dyn PacketAuthenticator) -> Result<PooledBuffer, RudpError> {
+        let data_len = self.data_len();
+        let mut buffer = pool.get_buffer_for(data_len)?;
+        self.write_to(&mut buffer.raw_buffer_mut()[..PROTOCOL_HEADER_SIZE + data_len], authenticator);
+        buffer.set_data_len(data_len)?;
+        Ok(buffer)
+    }
+
+    /// 改写`seal()`写入的发送时间戳并重新计算安全码，没有时间戳的包不变
+    fn stamp(&mut self, timestamp: u32, authenticator: &dyn PacketAuthenticator) {
+        match self {
+            PacketBuffer::Pooled(buffer) => {
+                let header = &buffer.full_data()[..PROTOCOL_HEADER_SIZE];
+                let Some(packet_type) = PacketType::from_u8(header[0] & !EXTENSION_FLAG).filter(|_| header[0] & EXTENSION_FLAG != 0) else {
+                    return;
+                };
+                if buffer.data().get(EXTENSION_LENGTH_SIZE..EXTENSION_LENGTH_SIZE + 2) != Some(&[EXTENSION_TIMESTAMP, 4]) {
+                    return;
+                }
+                let seq = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+                let value = &mut buffer.data_mut()[EXTENSION_LENGTH_SIZE + 2..TIMESTAMP_SECTION_SIZE];
+                if value == timestamp.to_be_bytes() {
+                    return;
+                }
+                value.copy_from_slice(&timestamp.to_be_bytes());
+                // 重写协议头会清除扩展标志，之后重新设置
+                if buffer.fill_protocol_header(packet_type, seq, authenticator).is_ok() {
+                    buffer.header_mut()[0] |= EXTENSION_FLAG;
+                }
+            }
+            // 安全码在写出数据报时计算
+            PacketBuffer::Shared { extensions, .. } => {
+                if extensions.get(EXTENSION_LENGTH_SIZE..EXTENSION_LENGTH_SIZE + 2) == Some(&[EXTENSION_TIMESTAMP, 4]) {
+                    extensions[EXTENSION_LENGTH_SIZE + 2..TIMESTAMP_SECTION_SIZE].copy_from_slice(&timestamp.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Payload shared by several data packets
+#[derive(Debug, Clone)]
+enum SharedBody {
+    /// Buffer passed to `send_to_all`, one for every target
+    Pooled(Arc<PooledBuffer>),
+    /// Data passed to `send_bytes`
+    #[cfg(feature = "bytes")]
+    Bytes(Bytes),
+}
+
+impl SharedBody {
+    fn data(&self) -> &[u8] {
+        match self {
+            SharedBody::Pooled(buffer) => buffer.data(),
+            #[cfg(feature = "bytes")]
+            SharedBody::Bytes(data) => data,
+        }
+    }
+}
+
+/// Payload of a data packet to send
+enum Payload {
+    Owned(PooledBuffer),
+    Shared(SharedBody),
+}
+
+impl Payload {
+    fn len(&self) -> usize {
+        match self {
+            Payload::Owned(buffer) => buffer.data_len(),
+            Payload::Shared(body) => body.data().len(),
+        }
+    }
+}
+
+impl From<PooledBuffer> for Payload {
+    fn from(buffer: PooledBuffer) -> Self {
+        Payload::Owned(buffer)
+    }
+}
+
+/// 发送`target`的数据包`seq`的队列项；共享载荷的包在这里写出完整的数据报
+fn data_transmit(pool: &SharedBufferPool, packet: &PacketBuffer, authenticator: &dyn PacketAuthenticator, target: SocketAddr, seq: u32) -> QueuedTransmit {
+    if packet.contents().is_none() {
+        if let Ok(buffer) = packet.assemble(pool, authenticator) {
+            return QueuedTransmit::Assembled(buffer, target, seq);
+        }
+    }
+    QueuedTransmit::Data(target, seq)
+}
+
+/// On-demand ping waiting for its acknowledgment
+#[derive(Debug)]
+struct PendingPing {
+    sent: Instant,
+    /// Resolved as `RudpError::Timeout` after this time
+    deadline: Instant,
+    sender: DeliverySender<Duration>,
+}
+
+/// Small payloads waiting to be sent together (`coalesce_delay`)
+#[derive(Debug)]
+struct Bundle {
+    /// Sequence number reserved for the packet, returned by each `send()` in it
+    seq: u32,
+    /// Length-prefixed payloads
+    frames: Vec<u8>,
+    /// Sent at this time at the latest
+    deadline: Instant,
+}
+
+/// Data to send later (`send_at()`)
+#[derive(Debug)]
+struct ScheduledSend {
+    at: Instant,
+    target: SocketAddr,
+    buffer: PooledBuffer,
+}
+
+/// Pending packet structure for retransmission
+#[derive(Debug)]
+struct PendingPacket {
+    /// Packet buffer (holds the actual memory from pool, or shared bytes)
+    buffer: PacketBuffer,
+    /// Send timestamp
+    send_time: Instant,
+    /// Time of the first transmission (`retry_deadline`)
+    first_sent: Instant,
+    /// Retry count
+    retry_count: u8,
+    /// Current RTO for this packet
+    rto: Duration,
+    /// Settles the `DeliveryHandle` returned by `send_tracked()`
+    delivery: Option<DeliverySender>,
+    /// Retransmission is abandoned after this time (`send_with_deadline()`)
+    deadline: Option<Instant>,
+    /// Sent with `send_critical()`, duplicated while the peer's loss is high
+    critical: bool,
+    /// When the redundant copy was sent
+    copy_sent: Option<Instant>,
+}
+
+impl PendingPacket {
+    /// The send time and RTO are set when the packet is first transmitted
+    fn new(buffer: PacketBuffer, now: Instant) -> Self {
+        Self {
+            buffer,
+            send_time: now,
+            first_sent: now,
+            retry_count: 0,
+            rto: Duration::ZERO,
+            delivery: None,
+            deadline: None,
+            critical: false,
+            copy_sent: None,
+        }
+    }
+
+    /// 通知`send_tracked()`的调用者发送结果
+    fn settle(&mut self, result: Result<(), RudpError>) {
+        if let Some(delivery) = self.delivery.take() {
+            delivery.send(result);
+        }
+    }
+
+    fn should_retry(&self, now: Instant) -> bool {
+        now.duration_since(self.send_time) >= self.rto
+    }
+
+    fn retry(&mut self, rto: Duration, now: Instant) {
+        self.retry_count = self.retry_count.saturating_add(1);
+        self.send_time = now;
+        self.rto = rto;
+    }
+}
+
+/// Socket-free protocol state machine
+///
+/// Sends and received datagrams take the current time as an argument; datagrams to
+/// send are queued until taken with [`poll_transmit`](Self::poll_transmit). Like
+/// `Rudpbase`, the core is not thread-safe.
+pub struct RudpCore {
+    /// Send buffer: [target_addr][seq] -> (buffer, send_time, retry_count)
+    send_buffer: HashMap<SocketAddr, HashMap<u32, PendingPacket>>,
+    /// Duplicate detection: [source_addr] -> window of received seqs
+    recv_windows: HashMap<SocketAddr, ReceiveWindow>,
+    /// Next sequence number for each target
+    next_seq: HashMap<SocketAddr, u32>,
+    /// RTT statistics for each connection
+    rtt_stats: HashMap<SocketAddr, RttStats>,
+    /// Connection statistics
+    connection_stats: HashMap<SocketAddr, ConnectionStats>,
+    /// Connection states
+    connection_states: HashMap<SocketAddr, ConnectionState>,
+    /// Every peer with protocol state, least recently active first
+    peer_activity: PeerActivity,
+    /// Peers declared dead by keep-alive, until they are heard from again
+    dead_peers: PeerActivity,
+    /// Peers whose connection was closed, until the deadline: late packets of the old
+    /// session are answered with a Close instead of creating state
+    tombstones: HashMap<SocketAddr, Instant>,
+    /// Peers we stopped sending to (`shutdown_send()`); true once the Shutdown packet
+    /// went out
+    send_shutdown: HashMap<SocketAddr, bool>,
+    /// Peers that shut down their sending direction
+    peers_finished: HashSet<SocketAddr>,
+    /// Pending ACKs to be sent; emptied lists are kept so their capacity is reused
+    pending_acks: HashMap<SocketAddr, Vec<u32>>,
+    /// Scratch space of `send_pending_acks()`: targets with ACKs and the extension section
+    ack_targets: Vec<SocketAddr>,
+    ack_extensions: Vec<u8>,
+    /// Shared buffer pool for memory management
+    buffer_pool: SharedBufferPool,
+    /// Received data waiting to be returned by poll_received()
+    recv_queue: VecDeque<ReceivedData>,
+    /// Next message id for each target
+    next_message_id: HashMap<SocketAddr, u32>,
+    /// Message fragments waiting for congestion window space, per target
+    outgoing_fragments: HashMap<SocketAddr, VecDeque<PooledBuffer>>,
+    /// Data packets sent while the congestion window was full, with their sequence
+    /// numbers and headers already assigned, per target
+    queued_sends: HashMap<SocketAddr, VecDeque<(u32, PendingPacket)>>,
+    /// Small payloads waiting to be sent in one packet, per target
+    bundles: HashMap<SocketAddr, Bundle>,
+    /// Data to send at a later time, in the order it is due
+    scheduled_sends: VecDeque<ScheduledSend>,
+    /// Number and payload bytes of the scheduled sends to each target
+    scheduled_totals: HashMap<SocketAddr, (usize, usize)>,
+    /// Incomplete received messages
+    reassembler: Reassembler,
+    /// Complete messages waiting to be returned by poll_message()
+    message_queue: VecDeque<ReceivedMessage>,
+    /// Data packets dropped after max retries or with a dead connection, per peer
+    failed_deliveries: HashMap<SocketAddr, u64>,
+    /// Datagrams waiting to be sent, in order
+    transmits: VecDeque<QueuedTransmit>,
+    /// Outbound bandwidth limit; `None` without `max_bandwidth`
+    pacer: Option<Pacer>,
+    /// Set while pending data drains before a close; new sends are rejected
+    closing: bool,
+    /// Instance configuration
+    config: RudpConfig,
+    /// Security code algorithm resolved from `config`
+    authenticator: Arc<dyn PacketAuthenticator>,
+    /// Compression algorithms each peer accepts; `None` while our offer is unanswered
+    peer_compression: HashMap<SocketAddr, Option<u8>>,
+    /// Per-peer keep-alive overrides
+    peer_keepalive: HashMap<SocketAddr, KeepAliveConfig>,
+    /// Per-peer authentication keys, used instead of `authenticator`
+    peer_keys: HashMap<SocketAddr, KeyRing>,
+    /// Our identity key, verification policy and verified peer identities
+    #[cfg(feature = "identity")]
+    identities: Identities,
+    /// Per-peer retransmission backoff overrides
+    peer_backoff: HashMap<SocketAddr, BackoffConfig>,
+    /// Per-peer payload limits below `max_payload_size`
+    peer_max_payload: HashMap<SocketAddr, usize>,
+    /// Payload sent with pings and ping acknowledgments, empty for none
+    heartbeat: Vec<u8>,
+    /// Heartbeat payload last received from each peer
+    peer_heartbeats: HashMap<SocketAddr, Vec<u8>>,
+    /// Pings sent by `ping()`, by target and sequence number
+    pings: HashMap<(SocketAddr, u32), PendingPing>,
+    /// Latest resumption token issued by each peer; kept when the connection ends
+    resumption_tokens: HashMap<SocketAddr, ResumptionToken>,
+    /// Token to present on the next data packet to each peer (`resume()`)
+    pending_resumption: HashMap<SocketAddr, ResumptionToken>,
+    /// Tokens this core accepted, so each starts one session only
+    used_tokens: UsedTokens,
+    /// Makes tokens issued within the same second distinct
+    token_nonce: u32,
+    /// Id carried by our pings and ping acknowledgments
+    connection_id: ConnectionId,
+    /// Connection id last seen from each peer
+    peer_connection_ids: ConnectionIds,
+    /// Zero point of the send timestamps, set by the first one taken
+    timestamp_epoch: Option<Instant>,
+    /// Instant and the wall clock time it stands for; ping timestamps and reply times
+    /// are taken from the instance clock relative to it
+    wall_clock: Option<(Instant, SystemTime)>,
+    /// Transmit and arrival time of the newest timestamped data packet from each peer,
+    /// echoed in the next acknowledgment
+    timestamp_echoes: HashMap<SocketAddr, Timestamp>,
+    /// One-way delay towards each peer, from echoed timestamps
+    outgoing_delay: HashMap<SocketAddr, DelayEstimator>,
+    /// One-way delay from each peer, from the timestamps of its data packets
+    incoming_delay: HashMap<SocketAddr, DelayEstimator>,
+    /// Clock offset of each peer, from the reply times on its ping acknowledgments
+    clock_offsets: HashMap<SocketAddr, ClockEstimator>,
+    /// Application event callbacks
+    event_handler: Option<Box<dyn EventHandler>>,
+    /// Invalid packet counts per source, and sources being ignored for them
+    invalid_sources: InvalidSources,
+    /// Alert thresholds each peer is above
+    alerts: AlertMonitor,
+    /// Timeouts of peers not heard from since
+    loss_episodes: LossEpisodes,
+    /// Loss estimates of peers and copies of critical packets to send
+    redundancy: Redundancy,
+    /// Admission check for packets from source addresses without state
+    peer_filter: Option<Box<dyn Fn(SocketAddr) -> Verdict + Send + Sync>>,
+    /// Structured protocol event log
+    qlog: Option<QlogWriter>,
+    /// Periodic statistics reports
+    stats_reporter: Option<StatsReporter>,
+    /// OpenTelemetry spans and metrics
+    #[cfg(feature = "otel")]
+    telemetry: Option<Telemetry>,
+}
+
+impl RudpCore {
+    /// 使用配置创建协议核心
+    ///
+    /// 按配置中的`pool_*`参数创建内存池
+    pub fn new(config: RudpConfig) -> Result<Self, RudpError> {
+        config.validate()?;
+        let buffer_pool = SharedBufferPool::with_limits(
+            config.pool_initial_capacity,
+            config.pool_max_capacity,
+            config.pool_buffer_size,
+        );
+        Self::with_pool(config, buffer_pool)
+    }
+
+    /// 使用共享内存池创建协议核心
+    ///
+    /// `config`中的`pool_*`参数被忽略，内存池的默认buffer必须能容纳`max_payload_size`字节的数据
+    pub fn with_pool(config: RudpConfig, buffer_pool: SharedBufferPool) -> Result<Self, RudpError> {
+        config.validate()?;
+        check_pool(&buffer_pool, &config)?;
+        Ok(Self {
+            send_buffer: HashMap::new(),
+            recv_windows: HashMap::new(),
+            next_seq: HashMap::new(),
+            rtt_stats: HashMap::new(),
+            connection_stats: HashMap::new(),
+            connection_states: HashMap::new(),
+            peer_activity: PeerActivity::new(),
+            dead_peers: PeerActivity::new(),
+            tombstones: HashMap::new(),
+            send_shutdown: HashMap::new(),
+            peers_finished: HashSet::new(),
+            pending_acks: HashMap::new(),
+            ack_targets: Vec::new(),
+            ack_extensions: Vec::new(),
+            buffer_pool,
+            recv_queue: VecDeque::new(),
+            next_message_id: HashMap::new(),
+            outgoing_fragments: HashMap::new(),
+            queued_sends: HashMap::new(),
+            bundles: HashMap::new(),
+            scheduled_sends: VecDeque::new(),
+            scheduled_totals: HashMap::new(),
+            reassembler: Reassembler::new(),
+            message_queue: VecDeque::new(),
+            failed_deliveries: HashMap::new(),
+            transmits: VecDeque::new(),
+            pacer: config.max_bandwidth.map(Pacer::new),
+            closing: false,
+            peer_compression: HashMap::new(),
+            peer_keepalive: HashMap::new(),
+            peer_keys: HashMap::new(),
+            #[cfg(feature = "identity")]
+            identities: Identities::default(),
+            peer_backoff: HashMap::new(),
+            peer_max_payload: HashMap::new(),
+            heartbeat: Vec::new(),
+            peer_heartbeats: HashMap::new(),
+            pings: HashMap::new(),
+            resumption_tokens: HashMap::new(),
+            pending_resumption: HashMap::new(),
+            used_tokens: UsedTokens::default(),
+            token_nonce: 0,
+            connection_id: ConnectionId(RandomState::new().build_hasher().finish()),
+            peer_connection_ids: ConnectionIds::default(),
+            timestamp_epoch: None,
+            wall_clock: None,
+            timestamp_echoes: HashMap::new(),
+            outgoing_delay: HashMap::new(),
+            incoming_delay: HashMap::new(),
+            clock_offsets: HashMap::new(),
+            event_handler: None,
+            invalid_sources: InvalidSources::new(),
+            alerts: AlertMonitor::new(),
+            loss_episodes: LossEpisodes::new(),
+            redundancy: Redundancy::new(),
+            peer_filter: None,
+            qlog: None,
+            stats_reporter: None,
+            #[cfg(feature = "otel")]
+            telemetry: None,
+            authenticator: config.packet_authenticator(),
+            config,
+        })
+    }
+
+    /// 向所有连接发送Close包并清理连接状态
+    ///
+    /// 未确认的数据被丢弃，Close包留在发送队列中
+    pub fn close(&mut self) {
+        self.close_all(&CloseReason::default());
+    }
+
+    /// 与[`close`](Self::close)相同，Close包携带`reason`，对端以
+    /// [`ConnectionEvent::Closed`]收到
+    ///
+    /// `reason`的载荷超过[`MAX_CLOSE_PAYLOAD`]时返回`RudpError::BufferTooLarge`，不关闭任何连接
+    pub fn close_with_reason(&mut self, reason: CloseReason) -> Result<(), RudpError> {
+        check_close_reason(&reason)?;
+        self.close_all(&reason);
+        Ok(())
+    }
+
+    /// 向`target`发送携带`reason`的Close包并清理它的连接状态，例如拒绝或封禁一个对端
+    ///
+    /// 发往`target`的未确认数据被丢弃；之后2倍RTO内对端迟到的旧会话包只会收到Close，
+    /// 不会重新建立状态。`reason`的载荷超过[`MAX_CLOSE_PAYLOAD`]时返回
+    /// `RudpError::BufferTooLarge`
+    pub fn close_peer(&mut self, target: SocketAddr, reason: CloseReason, now: Instant) -> Result<(), RudpError> {
+        check_close_reason(&reason)?;
+        trace_event!(debug, %target, code = reason.code, "closing connection");
+        self.send_close_packet(target, &reason);
+        self.close_connection(target, now);
+        Ok(())
+    }
+
+    /// 停止向`target`发送数据，继续接收并确认它发来的数据（半关闭）
+    ///
+    /// 之后发往`target`的发送返回`ConnectionError::SendShutdown`。已发送和排队中的数据
+    /// 照常重传，全部被确认后发出Shutdown包，对端收到后报告[`ConnectionEvent::Finished`]，
+    /// 此时它已收到本端发送的全部数据。连接状态被清理后（关闭、Reset等）重新可以发送
+    pub fn shutdown_send(&mut self, target: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        self.ensure_open()?;
+        self.ensure_alive(target)?;
+        self.ensure_writable(target)?;
+        trace_event!(debug, %target, "shutting down sending");
+        self.flush_now(target, now)?;
+        self.send_shutdown.insert(target, false);
+        self.send_pending_shutdowns(now);
+        Ok(())
+    }
+
+    /// `addr`是否已停止向本端发送数据（见[`shutdown_send`](Self::shutdown_send)）
+    pub fn peer_finished(&self, addr: SocketAddr) -> bool {
+        self.peers_finished.contains(&addr)
+    }
+
+    fn close_all(&mut self, reason: &CloseReason) {
+        let connections: Vec<SocketAddr> = self.connection_states.keys().cloned().collect();
+        for addr in connections {
+            self.send_close_packet(addr, reason);
+        }
+
+        // Clear all internal state
+        self.send_buffer.clear();
+        self.recv_windows.clear();
+        self.next_seq.clear();
+        self.rtt_stats.clear();
+        self.connection_stats.clear();
+        self.connection_states.clear();
+        self.peer_activity.clear();
+        self.pending_acks.clear();
+        self.next_message_id.clear();
+        self.outgoing_fragments.clear();
+        self.queued_sends.clear();
+        self.bundles.clear();
+        self.scheduled_sends.clear();
+        self.scheduled_totals.clear();
+        self.redundancy.clear();
+        self.peer_heartbeats.clear();
+        self.pings.clear();
+        self.pending_resumption.clear();
+        self.peer_compression.clear();
+        self.timestamp_echoes.clear();
+        self.outgoing_delay.clear();
+        self.incoming_delay.clear();
+        self.clock_offsets.clear();
+        self.failed_deliveries.clear();
+        self.tombstones.clear();
+        self.send_shutdown.clear();
+        self.peers_finished.clear();
+    }
+
+    /// 关闭过程中拒绝新的发送
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn set_closing(&mut self, closing: bool) {
+        self.closing = closing;
+    }
+
+    fn ensure_open(&self) -> Result<(), RudpError> {
+        if self.closing {
+            return Err(RudpError::Closing);
+        }
+        Ok(())
+    }
+
+    /// 所有对端尚未被确认或仍在排队的数据包数量
+    pub fn total_unacked_packets(&self) -> usize {
+        self.send_buffer.values().map(HashMap::len).sum::<usize>()
+            + self.queued_sends.values().map(VecDeque::len).sum::<usize>()
+            + self.outgoing_fragments.values().map(VecDeque::len).sum::<usize>()
+            + self.bundles.len()
+            + self.scheduled_sends.len()
+    }
+
+    /// 发往`addr`、尚未被确认或仍在排队的数据包数量
+    ///
+    /// 可用于应用层的背压控制；为0时发往`addr`的数据都已被确认，可以安全关闭
+    pub fn unacked_packets(&self, addr: SocketAddr) -> usize {
+        self.send_buffer.get(&addr).map_or(0, |packets| packets.len())
+            + self.queued_sends.get(&addr).map_or(0, VecDeque::len)
+            + self.outgoing_fragments.get(&addr).map_or(0, VecDeque::len)
+            + usize::from(self.bundles.contains_key(&addr))
+            + self.scheduled_totals.get(&addr).map_or(0, |(count, _)| *count)
+    }
+
+    /// [`unacked_packets`](Self::unacked_packets)中的数据包的用户数据字节数（不含协议头）
+    pub fn pending_bytes(&self, addr: SocketAddr) -> usize {
+        self.send_buffer.get(&addr).map_or(0, |packets| packets.values().map(|pending| pending.buffer.data_len()).sum())
+            + self.queued_sends.get(&addr).map_or(0, |packets| packets.iter().map(|(_, pending)| pending.buffer.data_len()).sum())
+            + self.outgoing_fragments.get(&addr).map_or(0, |fragments| fragments.iter().map(PooledBuffer::data_len).sum())
+            + self.bundles.get(&addr).map_or(0, |bundle| bundle.frames.len())
+            + self.scheduled_totals.get(&addr).map_or(0, |(_, bytes)| *bytes)
+    }
+
+    /// 各对端因重传耗尽或连接断开而丢弃的数据包数量
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn failed_deliveries(&self) -> &HashMap<SocketAddr, u64> {
+        &self.failed_deliveries
+    }
+
+    /// 获取一个用于写入的buffer，协议头空间已预留
+    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
+        self.buffer_pool.get_write_buffer()
+    }
+
+    /// 获取能容纳 `len` 字节用户数据的最小buffer
+    pub fn get_buffer_for(&self, len: usize) -> Result<PooledBuffer, RudpError> {
+        self.buffer_pool.get_buffer_for(len)
+    }
+
+    /// 实例使用的内存池
+    pub fn buffer_pool(&self) -> &SharedBufferPool {
+        &self.buffer_pool
+    }
+
+    /// 获取内存池统计信息
+    pub fn get_buffer_pool_stats(&self) -> Result<crate::buffer_pool::PoolStats, RudpError> {
+        self.buffer_pool.stats()
+    }
+
+    /// 收缩内存池，最多保留 `count` 个空闲buffer
+    pub fn shrink_buffer_pool(&self, count: usize) -> Result<usize, RudpError> {
+        self.buffer_pool.shrink_to(count)
+    }
+
+    /// 发送数据，返回分配给该数据包的序列号
+    ///
+    /// 数据包进入重传队列并加入发送队列，通过[`poll_transmit`](Self::poll_transmit)取出。
+    /// 拥塞窗口已满时数据包在该对端的发送队列中排队（序列号已分配），窗口打开后由
+    /// `handle_timeout()`发出；队列达到`limits.max_queued_sends`时返回
+    /// `RudpError::CongestionWindowFull`
+    ///
+    /// 设置了`coalesce_delay`时，小载荷先与发往同一对端的其他载荷合并，最迟在延迟到期、
+    /// 合并包达到`max_payload_size`或调用[`flush_now`](Self::flush_now)时作为一个包发出；
+    /// 同一个包中的载荷返回相同的序列号
+    ///
+    /// 载荷超过[`max_payload_for`](Self::max_payload_for)时返回`RudpError::BufferTooLarge`
+    pub fn send(&mut self, buffer: PooledBuffer, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(buffer.data_len(), target)?;
+        if let Some(delay) = self.config.coalesce_delay {
+            if BATCH_FRAME_HEADER_SIZE + buffer.data_len() <= self.max_payload_for(target) {
+                return self.bundle(buffer, target, delay, now);
+            }
+        }
+        self.send_unbundled(buffer.into(), target, now)
+    }
+
+    fn send_unbundled(&mut self, payload: Payload, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        // 先发出合并包，保持发送顺序
+        self.flush_now(target, now)?;
+        let window_open = self.rtt_stats.get(&target).is_none_or(RttStats::can_send);
+        if window_open && !self.queued_sends.contains_key(&target) {
+            return self.send_packet(PacketType::Data, payload, target, now);
+        }
+        self.queue_send(payload, target, now)
+    }
+
+    /// 立即发送数据，返回分配给该数据包的序列号
+    ///
+    /// 与[`send`](Self::send)不同，拥塞窗口已满时不排队，直接返回
+    /// `RudpError::CongestionWindowFull`
+    pub fn try_send(&mut self, buffer: PooledBuffer, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(buffer.data_len(), target)?;
+        self.flush_now(target, now)?;
+        self.send_packet(PacketType::Data, buffer.into(), target, now)
+    }
+
+    /// 发送数据并跟踪该数据包的送达结果
+    ///
+    /// 返回的[`DeliveryHandle`]在之后的`handle_datagram()`/`handle_timeout()`中完成
+    pub fn send_tracked(&mut self, buffer: PooledBuffer, target: SocketAddr, now: Instant) -> Result<DeliveryHandle, RudpError> {
+        self.send_tracked_until(buffer, target, None, now)
+    }
+
+    /// 发送数据，超过`deadline`仍未被确认时放弃重传
+    pub fn send_with_deadline(&mut self, buffer: PooledBuffer, target: SocketAddr, deadline: Instant, now: Instant) -> Result<DeliveryHandle, RudpError> {
+        self.send_tracked_until(buffer, target, Some(deadline), now)
+    }
+
+    fn send_tracked_until(&mut self, buffer: PooledBuffer, target: SocketAddr, deadline: Option<Instant>, now: Instant) -> Result<DeliveryHandle, RudpError> {
+        // 跟踪的是单个数据包，不参与合并
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(buffer.data_len(), target)?;
+        let seq = self.send_unbundled(buffer.into(), target, now)?;
+        let (handle, delivery) = DeliveryHandle::new(target, seq);
+        let queued = self.queued_sends.get_mut(&target).and_then(|packets| packets.back_mut()).filter(|(queued_seq, _)| *queued_seq == seq);
+        let pending_packet = match queued {
+            Some((_, pending_packet)) => Some(pending_packet),
+            None => self.send_buffer.get_mut(&target).and_then(|packets| packets.get_mut(&seq)),
+        };
+        if let Some(pending_packet) = pending_packet {
+            pending_packet.delivery = Some(delivery);
+            pending_packet.deadline = deadline;
+        }
+        Ok(handle)
+    }
+
+    /// 发送关键数据，返回分配给该数据包的序列号
+    ///
+    /// 与[`send`](Self::send)相同，但不参与合并。对端的估计丢包率超过
+    /// `redundancy.loss_threshold`时，数据包发出`redundancy.spacing`之后再发送一份副本
+    /// （期间已确认或已重传则不再发送），单个丢包不必等待重传超时即可恢复；发出的副本与
+    /// 由副本送达的包分别计入`ConnectionStats::redundant_copies`和`redundant_recoveries`
+    pub fn send_critical(&mut self, buffer: PooledBuffer, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(buffer.data_len(), target)?;
+        let seq = self.send_unbundled(buffer.into(), target, now)?;
+        if let Some(pending_packet) = self.send_buffer.get_mut(&target).and_then(|packets| packets.get_mut(&seq)) {
+            pending_packet.critical = true;
+            self.schedule_copy(target, seq, now);
+        } else if let Some((_, pending_packet)) = self.queued_sends.get_mut(&target).and_then(|packets| packets.back_mut()).filter(|(queued_seq, _)| *queued_seq == seq) {
+            // 离开发送队列时安排副本
+            pending_packet.critical = true;
+        }
+        Ok(seq)
+    }
+
+    /// 取消发往`addr`、序列号为`seq`的数据包，返回是否找到
+    ///
+    /// 未确认的数据包不再重传，仍在等待拥塞窗口或合并的数据包不再发出（合并包中的载荷
+    /// 共享序列号，一起取消）。`send_tracked()`返回的句柄完成为`Err(RudpError::Cancelled)`。
+    /// 已确认、已放弃或未知的序列号返回`false`；已经发出的副本仍可能到达对端
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn cancel(&mut self, addr: SocketAddr, seq: u32, now: Instant) -> bool {
+        if let Some(packets) = self.send_buffer.get_mut(&addr) {
+            if let Some(mut pending_packet) = packets.remove(&seq) {
+                if packets.is_empty() {
+                    self.send_buffer.remove(&addr);
+                }
+                pending_packet.settle(Err(RudpError::Cancelled));
+                if let Some(rtt_stats) = self.rtt_stats.get_mut(&addr) {
+                    rtt_stats.on_packet_dropped();
+                }
+                if let Some(qlog) = self.qlog.as_mut() {
+                    qlog.log(addr, QlogEvent::PacketDropped { seq });
+                }
+                telemetry!(self.telemetry, dropped(addr, seq, "cancelled", now));
+                trace_event!(debug, %addr, seq, "in-flight packet cancelled");
+                return true;
+            }
+        }
+        if let Some(packets) = self.queued_sends.get_mut(&addr) {
+            if let Some(index) = packets.iter().position(|(queued_seq, _)| *queued_seq == seq) {
+                if let Some((_, mut pending_packet)) = packets.remove(index) {
+                    pending_packet.settle(Err(RudpError::Cancelled));
+                }
+                if packets.is_empty() {
+                    self.queued_sends.remove(&addr);
+                }
+                trace_event!(debug, %addr, seq, "queued packet cancelled");
+                return true;
+            }
+        }
+        if self.bundles.get(&addr).is_some_and(|bundle| bundle.seq == seq) {
+            self.bundles.remove(&addr);
+            trace_event!(debug, %addr, seq, "bundled payloads cancelled");
+            return true;
+        }
+        false
+    }
+
+    /// 立即重传发往`addr`、尚未确认的数据包`seq`，不等待重传超时，返回是否找到
+    ///
+    /// 与收到NACK时一样计入重传次数并重新开始重传计时；仍在排队、尚未发出的数据包返回
+    /// `false`
+    pub fn resend(&mut self, addr: SocketAddr, seq: u32, now: Instant) -> bool {
+        let found = self.retransmit_now(addr, seq, now);
+        if found {
+            trace_event!(debug, %addr, seq, "retransmitting on request");
+        }
+        found
+    }
+
+    /// 在`at`时刻发送数据，由`handle_timeout()`按时调用[`send`](Self::send)
+    ///
+    /// 数据在调用时检查大小，发送时才分配序列号；`poll_timeout()`包含最早的发送时间。
+    /// 发送失败时错误以`seq`为`None`的`ReceivedData`放入接收队列。每个对端最多
+    /// `limits.max_queued_sends`个待发送的数据，超过时返回`RudpError::CongestionWindowFull`。
+    /// 已到时间的数据在下一次`handle_timeout()`中发送
+    pub fn send_at(&mut self, buffer: PooledBuffer, target: SocketAddr, at: Instant) -> Result<(), RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_payload(buffer.data_len(), target)?;
+        let (count, bytes) = self.scheduled_totals.get(&target).copied().unwrap_or_default();
+        if count >= self.config.limits.max_queued_sends {
+            trace_event!(debug, %target, "too many scheduled sends");
+            return Err(RudpError::CongestionWindowFull);
+        }
+        // 同一时刻的数据按调用顺序发送
+        self.scheduled_totals.insert(target, (count + 1, bytes + buffer.data_len()));
+        let index = self.scheduled_sends.partition_point(|scheduled| scheduled.at <= at);
+        self.scheduled_sends.insert(index, ScheduledSend { at, target, buffer });
+        Ok(())
+    }
+
+    /// 在`now`之后`delay`发送数据，见[`send_at`](Self::send_at)
+    pub fn send_after(&mut self, buffer: PooledBuffer, target: SocketAddr, delay: Duration, now: Instant) -> Result<(), RudpError> {
+        self.send_at(buffer, target, now + delay)
+    }
+
+    /// 发送到时间的数据
+    fn send_scheduled(&mut self, now: Instant) {
+        while self.scheduled_sends.front().is_some_and(|scheduled| scheduled.at <= now) {
+            let Some(scheduled) = self.scheduled_sends.pop_front() else {
+                break;
+            };
+            let target = scheduled.target;
+            if let Some((count, bytes)) = self.scheduled_totals.get_mut(&target) {
+                *count -= 1;
+                *bytes -= scheduled.buffer.data_len();
+                if *count == 0 {
+                    self.scheduled_totals.remove(&target);
+                }
+            }
+            if let Err(error) = self.send(scheduled.buffer, target, now) {
+                trace_event!(debug, %target, %error, "scheduled send failed");
+                if self.recv_queue.len() < self.config.limits.max_queued_packets {
+                    self.recv_queue.push_back(ReceivedData { from: target, seq: None, result: Err(error) });
+                }
+            }
+        }
+    }
+
+    /// 把载荷加入发往`target`的合并包，放不下时先发出已有的合并包
+    fn bundle(&mut self, buffer: PooledBuffer, target: SocketAddr, delay: Duration, now: Instant) -> Result<u32, RudpError> {
+        self.ensure_alive(target)?;
+        let max = self.max_payload_for(target);
+        let frame_len = BATCH_FRAME_HEADER_SIZE + buffer.data_len();
+        if self.bundles.get(&target).is_some_and(|bundle| bundle.frames.len() + frame_len > max) {
+            self.flush_now(target, now)?;
+        }
+        if !self.bundles.contains_key(&target) {
+            self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+            let seq = self.get_next_seq(target);
+            self.bundles.insert(target, Bundle { seq, frames: Vec::with_capacity(max), deadline: now + delay });
+        }
+        let bundle = self.bundles.get_mut(&target).expect("bundle was just created");
+        push_batch_frame(&mut bundle.frames, buffer.data());
+        let (seq, full) = (bundle.seq, bundle.frames.len() + BATCH_FRAME_HEADER_SIZE >= max);
+        if full {
+            self.flush_now(target, now)?;
+        }
+        Ok(seq)
+    }
+
+    /// 立即发出发往`target`的合并包，用于对延迟敏感的消息
+    ///
+    /// 没有等待合并的载荷时什么也不做。拥塞窗口已满时合并包像[`send`](Self::send)的数据包
+    /// 一样排队
+    pub fn flush_now(&mut self, target: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        let Some(bundle) = self.bundles.remove(&target) else {
+            return Ok(());
+        };
+        trace_event!(trace, %target, seq = bundle.seq, len = bundle.frames.len(), "sending bundled payloads");
+        let mut buffer = self.buffer_pool.get_buffer_for(bundle.frames.len())?;
+        buffer.data_mut()[..bundle.frames.len()].copy_from_slice(&bundle.frames);
+        buffer.set_data_len(bundle.frames.len())?;
+        let buffer = self.seal(PacketType::Bundle, buffer, bundle.seq, target, now)?;
+        let pending_packet = PendingPacket::new(PacketBuffer::Pooled(buffer), now);
+        let window_open = self.rtt_stats.get(&target).is_none_or(RttStats::can_send);
+        if window_open && !self.queued_sends.contains_key(&target) {
+            self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+            self.transmit_new(pending_packet, bundle.seq, target, now);
+        } else {
+            self.queued_sends.entry(target).or_default().push_back((bundle.seq, pending_packet));
+        }
+        Ok(())
+    }
+
+    /// 发出合并延迟已到期的合并包
+    fn flush_expired_bundles(&mut self, now: Instant) {
+        let expired: Vec<SocketAddr> = self.bundles.iter().filter(|(_, bundle)| now >= bundle.deadline).map(|(target, _)| *target).collect();
+        for target in expired {
+            if let Err(_e) = self.flush_now(target, now) {
+                trace_event!(warn, %target, error = %_e, "failed to send bundled payloads");
+            }
+        }
+    }
+
+    /// 分配序列号、填充协议头并发送可靠数据包（Data或Fragment），返回序列号
+    fn send_packet(&mut self, packet_type: PacketType, payload: Payload, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        let seq = self.admit(payload.len(), target, now)?;
+        let buffer = self.seal_payload(packet_type, payload, seq, target, now)?;
+        self.transmit_new(PendingPacket::new(buffer, now), seq, target, now);
+        Ok(seq)
+    }
+
+    /// 分配序列号、填充协议头，把数据包放入`target`的发送队列等待拥塞窗口
+    fn queue_send(&mut self, payload: Payload, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        if self.queued_sends.get(&target).map_or(0, VecDeque::len) >= self.config.limits.max_queued_sends {
+            trace_event!(debug, %target, "send queue full");
+            return Err(RudpError::CongestionWindowFull);
+        }
+        self.check_payload(payload.len(), target)?;
+        self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+        let seq = self.get_next_seq(target);
+        let buffer = self.seal_payload(PacketType::Data, payload, seq, target, now)?;
+        let pending_packet = PendingPacket::new(buffer, now);
+        self.queued_sends.entry(target).or_default().push_back((seq, pending_packet));
+        Ok(seq)
+    }
+
+    /// 按需压缩载荷、加入发送时间戳、载荷校验和与填充，并填充协议头
+    fn seal(&mut self, packet_type: PacketType, buffer: PooledBuffer, seq: u32, target: SocketAddr, now: Instant) -> Result<PooledBuffer, RudpError> {
+        self.announce(target, now);
+        let (packet_type, mut buffer) = self.compress_payload(packet_type, buffer, target);
+        let len = buffer.data_len();
+        let (entries, padding) = self.extension_entries(buffer.data(), target, now);
+        if entries.is_empty() {
+            buffer.fill_protocol_header(packet_type, seq, signing_authenticator(&self.peer_keys, &self.authenticator, target))?;
+            return Ok(buffer);
+        }
+
+        // 扩展区放在协议头和载荷之间，填充放在载荷之后，buffer放不下时换一个更大的
+        let section_size = EXTENSION_LENGTH_SIZE + entries.len();
+        let total = section_size + len + padding.unwrap_or(0);
+        if buffer.data_mut().len() < total {
+            let mut larger = self.buffer_pool.get_buffer_for(total)?;
+            larger.data_mut()[section_size..section_size + len].copy_from_slice(buffer.data());
+            buffer = larger;
+        } else {
+            buffer.data_mut().copy_within(..len, section_size);
+        }
+        buffer.data_mut()[..EXTENSION_LENGTH_SIZE].copy_from_slice(&(entries.len() as u16).to_be_bytes());
+        buffer.data_mut()[EXTENSION_LENGTH_SIZE..section_size].copy_from_slice(&entries);
+        buffer.data_mut()[section_size + len..total].fill(0);
+        buffer.set_data_len(total)?;
+
+        buffer.fill_protocol_header(packet_type, seq, signing_authenticator(&self.peer_keys, &self.authenticator, target))?;
+        buffer.header_mut()[0] |= EXTENSION_FLAG;
+        Ok(buffer)
+    }
+
+    /// 与`seal()`相同，但载荷与发往其他对端的数据包共享，协议头在发出时写入
+    ///
+    /// 需要压缩时把载荷复制到内存池buffer后交给`seal()`
+    fn seal_shared(&mut self, packet_type: PacketType, body: SharedBody, seq: u32, target: SocketAddr, now: Instant) -> Result<PacketBuffer, RudpError> {
+        self.announce(target, now);
+        let data = body.data();
+        if self.compression_for(data.len(), target).is_some() {
+            let mut buffer = self.buffer_pool.get_buffer_for(data.len())?;
+            buffer.data_mut()[..data.len()].copy_from_slice(data);
+            buffer.set_data_len(data.len())?;
+            return Ok(PacketBuffer::Pooled(self.seal(packet_type, buffer, seq, target, now)?));
+        }
+        let (entries, padding) = self.extension_entries(data, target, now);
+        let mut extensions = Vec::new();
+        if !entries.is_empty() {
+            extensions.reserve_exact(EXTENSION_LENGTH_SIZE + entries.len());
+            extensions.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(&entries);
+        }
+        Ok(PacketBuffer::Shared { packet_type, seq, extensions, body, padding: padding.unwrap_or(0) })
+    }
+
+    /// 按载荷类型调用`seal()`或`seal_shared()`
+    fn seal_payload(&mut self, packet_type: PacketType, payload: Payload, seq: u32, target: SocketAddr, now: Instant) -> Result<PacketBuffer, RudpError> {
+        match payload {
+            Payload::Owned(buffer) => Ok(PacketBuffer::Pooled(self.seal(packet_type, buffer, seq, target, now)?)),
+            Payload::Shared(body) => self.seal_shared(packet_type, body, seq, target, now),
+        }
+    }
+
+    /// 首次发往`target`时通过ping交换各自支持的压缩算法、出示本端身份
+    fn announce(&mut self, target: SocketAddr, now: Instant) {
+        telemetry!(self.telemetry, advance(now));
+        // 首次发往该对端时通过ping交换各自支持的压缩算法
+        if self.config.compression.is_some() && !self.peer_compression.contains_key(&target) {
+            self.peer_compression.insert(target, None);
+            self.send_ping(target, now);
+        }
+        // 首次发往该对端时通过ping出示本端身份
+        #[cfg(feature = "identity")]
+        if self.identities.needs_announce(target) {
+            self.send_ping(target, now);
+        }
+    }
+
+    /// 发往`target`的（已压缩）载荷`payload`的扩展条目，以及载荷之后的填充字节数
+    fn extension_entries(&mut self, payload: &[u8], target: SocketAddr, now: Instant) -> (Vec<u8>, Option<usize>) {
+        // 时间戳必须是第一个扩展，重传时`PacketBuffer::stamp()`按固定位置改写
+        let mut entries = Vec::new();
+        if self.config.timestamps {
+            Timestamp::Sent(self.timestamp(now)).encode(&mut entries);
+        }
+        // 紧跟时间戳，扩展区开头落在安全码覆盖的前16字节内
+        if self.config.payload_checksum {
+            PayloadChecksum::of(payload).encode(&mut entries);
+        }
+        if let Some(keys) = self.peer_keys.get(&target) {
+            keys.current().encode_id(&mut entries);
+        }
+        if let Some(token) = self.pending_resumption.remove(&target) {
+            token.encode(&mut entries);
+        }
+        // 填充到整个数据报恰好是某个档位的大小
+        let padding = self.config.padding.as_ref().map(|padding| {
+            let unpadded = PROTOCOL_HEADER_SIZE + EXTENSION_LENGTH_SIZE + entries.len() + Padding::ENTRY_SIZE + payload.len();
+            padding.padded_len(unpadded) - unpadded
+        });
+        if let Some(padding) = padding {
+            Padding(padding as u16).encode(&mut entries);
+        }
+        (entries, padding)
+    }
+
+    /// 实例时钟`now`对应的墙上时间（自UNIX纪元的纳秒），用于ping时间戳和应答时间
+    ///
+    /// 没有调用过[`set_wall_clock`](Self::set_wall_clock)时，第一次取值把`now`对应到当前系统时间
+    fn wall_time(&mut self, now: Instant) -> u64 {
+        let (base, wall_clock) = *self.wall_clock.get_or_insert_with(|| (now, SystemTime::now()));
+        let time = match now.checked_duration_since(base) {
+            Some(elapsed) => wall_clock + elapsed,
+            None => wall_clock - base.duration_since(now),
+        };
+        time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+    }
+
+    /// 本端时钟的当前时间（微秒，回绕），用于发送时间戳
+    fn timestamp(&mut self, now: Instant) -> u32 {
+        let epoch = *self.timestamp_epoch.get_or_insert(now);
+        now.saturating_duration_since(epoch).as_micros() as u32
+    }
+
+    /// 发送任意长度的消息，拆分为分片后按拥塞窗口发送
+    pub fn send_message(&mut self, data: &[u8], target: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        self.ensure_open()?;
+        self.ensure_alive(target)?;
+        self.ensure_writable(target)?;
+        self.flush_now(target, now)?;
+        if data.len() > self.config.max_message_size {
+            return Err(RudpError::BufferTooLarge {
+                size: data.len(),
+                max: self.config.max_message_size,
+            });
+        }
+        let chunk_size = self.max_payload_for(target).saturating_sub(FRAGMENT_HEADER_SIZE);
+        let count = data.len().div_ceil(chunk_size.max(1)).max(1);
+        if chunk_size == 0 || count > u16::MAX as usize {
+            return Err(RudpError::InvalidConfig {
+                message: "max_payload_size is too small to fragment this message".to_string(),
+            });
+        }
+
+        let message_id = self.next_message_id.entry(target).or_insert(0);
+        let id = *message_id;
+        *message_id = message_id.wrapping_add(1);
+
+        let mut fragments = VecDeque::with_capacity(count);
+        for index in 0..count {
+            let chunk = &data[(index * chunk_size).min(data.len())..((index + 1) * chunk_size).min(data.len())];
+            let header = FragmentHeader { message_id: id, index: index as u16, count: count as u16 };
+            let mut buffer = self.buffer_pool.get_buffer_for(FRAGMENT_HEADER_SIZE + chunk.len())?;
+            buffer.data_mut()[..FRAGMENT_HEADER_SIZE].copy_from_slice(&header.serialize());
+            buffer.data_mut()[FRAGMENT_HEADER_SIZE..FRAGMENT_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            buffer.set_data_len(FRAGMENT_HEADER_SIZE + chunk.len())?;
+            fragments.push_back(buffer);
+        }
+        self.outgoing_fragments.entry(target).or_default().extend(fragments);
+
+        self.send_queued_fragments(now);
+        Ok(())
+    }
+
+    /// 向已停止发送、数据全部被确认的对端发出Shutdown包，它像数据包一样重传
+    fn send_pending_shutdowns(&mut self, now: Instant) {
+        let ready: Vec<SocketAddr> = self.send_shutdown.iter().filter(|(target, sent)| !**sent && self.unacked_packets(**target) == 0).map(|(target, _)| *target).collect();
+        for target in ready {
+            let result = self.buffer_pool.get_buffer_for(0).and_then(|buffer| self.send_packet(PacketType::Shutdown, buffer.into(), target, now));
+            match result {
+                Ok(_) => {
+                    self.send_shutdown.insert(target, true);
+                }
+                Err(_e) => trace_event!(debug, %target, error = %_e, "shutdown packet not sent yet"),
+            }
+        }
+    }
+
+    /// 按拥塞窗口发送`send()`排队的数据包
+    fn send_queued_packets(&mut self, now: Instant) {
+        let targets: Vec<SocketAddr> = self.queued_sends.keys().copied().collect();
+        for target in targets {
+            while self.rtt_stats.get(&target).is_none_or(RttStats::can_send) {
+                let Some((seq, pending_packet)) = self.queued_sends.get_mut(&target).and_then(VecDeque::pop_front) else {
+                    break;
+                };
+                self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+                self.transmit_new(pending_packet, seq, target, now);
+            }
+            if self.queued_sends.get(&target).is_some_and(VecDeque::is_empty) {
+                self.queued_sends.remove(&target);
+            }
+        }
+    }
+
+    /// 按拥塞窗口发送排队的消息分片
+    fn send_queued_fragments(&mut self, now: Instant) {
+        let targets: Vec<SocketAddr> = self.outgoing_fragments.keys().copied().collect();
+        for target in targets {
+            while let Some(buffer) = self.outgoing_fragments.get_mut(&target).and_then(VecDeque::pop_front) {
+                let can_send = self.rtt_stats.get(&target).is_none_or(RttStats::can_send);
+                if !can_send {
+                    self.outgoing_fragments.get_mut(&target).unwrap().push_front(buffer);
+                    break;
+                }
+                if let Err(_e) = self.send_packet(PacketType::Fragment, buffer.into(), target, now) {
+                    // 消息缺少这个分片，对端在重组超时后丢弃它
+                    trace_event!(warn, %target, error = %_e, "failed to send message fragment");
+                }
+            }
+            if self.outgoing_fragments.get(&target).is_some_and(VecDeque::is_empty) {
+                self.outgoing_fragments.remove(&target);
+            }
+        }
+    }
+
+    /// 把多个片段写入一个buffer后作为一个数据包发送
+    pub fn send_vectored(&mut self, bufs: &[IoSlice<'_>], target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.check_size(len, target)?;
+
+        let mut buffer = self.buffer_pool.get_buffer_for(len)?;
+        let mut offset = 0;
+        for buf in bufs {
+            buffer.data_mut()[offset..offset + buf.len()].copy_from_slice(buf);
+            offset += buf.len();
+        }
+        buffer.set_data_len(len)?;
+
+        self.send(buffer, target, now)
+    }
+
+    /// 把同一份数据可靠地发送给多个对端，按`targets`顺序返回每个对端的结果
+    ///
+    /// 各对端的数据包共享`buffer`，只各自写入协议头和扩展。每个对端像[`send`](Self::send)
+    /// 一样发送，拥塞窗口已满时排队
+    pub fn send_to_all(&mut self, buffer: PooledBuffer, targets: &[SocketAddr], now: Instant) -> Result<Vec<Result<u32, RudpError>>, RudpError> {
+        self.ensure_open()?;
+        let body = SharedBody::Pooled(Arc::new(buffer));
+        let len = body.data().len();
+        Ok(targets
+            .iter()
+            .map(|&target| {
+                self.ensure_writable(target)
+                    .and_then(|_| self.check_size(len, target))
+                    .and_then(|_| self.send_unbundled(Payload::Shared(body.clone()), target, now))
+            })
+            .collect())
+    }
+
+    /// 发送不可靠数据报：不分配序列号、不等待确认也不重传
+    pub fn send_unreliable(&mut self, mut buffer: PooledBuffer, target: SocketAddr) -> Result<(), RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(buffer.data_len(), target)?;
+        buffer.fill_protocol_header(PacketType::Datagram, 0, signing_authenticator(&self.peer_keys, &self.authenticator, target))?;
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.log(target, QlogEvent::PacketSent {
+                packet_type: PacketType::Datagram,
+                seq: 0,
+                length: buffer.full_data().len(),
+                retransmission: false,
+            });
+        }
+        self.transmits.push_back(QueuedTransmit::Datagram(buffer, target));
+        Ok(())
+    }
+
+    /// 为`target`建立连接状态，不发送任何数据包
+    ///
+    /// 清除断开标记，初始化RTT与拥塞状态并开始保活。之后用[`ping`](Self::ping)测量RTT，
+    /// 可以避免第一个数据包使用未测量的初始RTO
+    pub fn connect(&mut self, target: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        self.ensure_open()?;
+        self.dead_peers.remove(target);
+        self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+        self.connection_states.entry(target).or_insert_with(|| ConnectionState::new_at(now)).update_activity(now);
+        self.touch_peer(target);
+        Ok(())
+    }
+
+    /// 本端的连接ID，随ping和ping应答发给对端
+    pub fn connection_id(&self) -> ConnectionId {
+        self.connection_id
+    }
+
+    /// `addr`处对端的[`PeerId`]：握手中验证过的身份，否则是它的连接ID
+    ///
+    /// 还没有收到过该对端的ping或ping应答时为`None`
+    pub fn peer_id(&self, addr: SocketAddr) -> Option<PeerId> {
+        #[cfg(feature = "identity")]
+        if let Some(identity) = self.identities.verified(addr) {
+            return Some(PeerId::Identity(identity));
+        }
+        self.peer_connection_ids.get(addr).map(PeerId::Connection)
+    }
+
+    /// `peer`当前所在的地址，对端迁移后随之更新；它的状态已被移除时为`None`
+    pub fn peer_addr(&self, peer: PeerId) -> Option<SocketAddr> {
+        match peer {
+            #[cfg(feature = "identity")]
+            PeerId::Identity(identity) => self.identities.addr(identity),
+            PeerId::Connection(id) => self.peer_connection_ids.addr(id),
+        }
+    }
+
+    /// 向`peer`当前所在的地址可靠发送，参见[`send`](Self::send)
+    ///
+    /// 找不到`peer`的地址时返回`ConnectionError::UnknownPeer`
+    pub fn send_to_peer(&mut self, buffer: PooledBuffer, peer: PeerId, now: Instant) -> Result<u32, RudpError> {
+        let target = self.peer_addr(peer).ok_or(ConnectionError::UnknownPeer { peer })?;
+        self.send(buffer, target, now)
+    }
+
+    /// `peer`当前地址的连接统计，参见[`get_stats`](Self::get_stats)
+    pub fn peer_stats(&self, peer: PeerId) -> Option<ConnectionStats> {
+        self.get_stats(self.peer_addr(peer)?)
+    }
+
+    /// 本端地址改变后（例如socket重新绑定）调用：向每个已连接的对端发送携带连接ID的ping
+    ///
+    /// 对端收到后把本端的状态移到新地址（[`ConnectionEvent::Migrated`]），之后的应答、
+    /// 重传和新数据都发往新地址。序列号、未确认的数据包和RTT统计保持不变
+    pub fn migrate(&mut self, now: Instant) {
+        let peers: Vec<SocketAddr> = self.connection_states.keys().copied().filter(|addr| !self.dead_peers.contains(*addr)).collect();
+        for addr in peers {
+            trace_event!(debug, %addr, "announcing new local address");
+            self.send_ping(addr, now);
+        }
+    }
+
+    /// `addr`最近一次签发给本端的恢复令牌
+    ///
+    /// 开启了`resumption`的对端在每个ping应答中签发新令牌（见[`connect`](Self::connect)
+    /// 和[`ping`](Self::ping)）。连接结束甚至实例关闭后令牌仍然保留，可以保存下来，
+    /// 之后用[`resume`](Self::resume)重新连接
+    pub fn resumption_token(&self, addr: SocketAddr) -> Option<&ResumptionToken> {
+        self.resumption_tokens.get(&addr)
+    }
+
+    /// 用恢复令牌重新连接`target`，之后发送的第一个可靠数据包携带令牌（0-RTT）
+    ///
+    /// 像[`connect`](Self::connect)一样建立连接状态，并用令牌中上一次会话测得的RTT
+    /// 代替初始RTO。对端验证令牌后丢弃上一次会话的接收状态，第一个数据包无需等待
+    /// 往返即被交付；令牌无效或已用过时数据包按普通数据包处理
+    pub fn resume(&mut self, target: SocketAddr, token: ResumptionToken, now: Instant) -> Result<(), RudpError> {
+        self.connect(target, now)?;
+        if let (Some(rtt), Some(rtt_stats)) = (token.rtt(), self.rtt_stats.get_mut(&target)) {
+            rtt_stats.restore_rtt(rtt);
+        }
+        self.pending_resumption.insert(target, token);
+        Ok(())
+    }
+
+    /// 导出每个对端的协议状态，供重启后的进程用[`import_state`](Self::import_state)恢复
+    ///
+    /// 包含序列号、接收窗口、未确认和排队中的数据包、RTT与拥塞窗口、恢复令牌和已用过的令牌。
+    /// 统计、尚未分配序列号的消息分片、未重组完的消息以及发送回执不包含在内，
+    /// 在消息分片全部发出后导出可以避免丢失消息。安全配置不导出，重启后使用相同的配置
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut addrs: Vec<SocketAddr> = self
+            .next_seq
+            .keys()
+            .chain(self.recv_windows.keys())
+            .chain(self.rtt_stats.keys())
+            .chain(self.resumption_tokens.keys())
+            .copied()
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+
+        let peers = addrs
+            .into_iter()
+            .map(|addr| {
+                let mut packets: Vec<(u32, &PendingPacket)> = self
+                    .send_buffer
+                    .get(&addr)
+                    .into_iter()
+                    .flat_map(|buffer| buffer.iter().map(|(seq, packet)| (*seq, packet)))
+                    .chain(self.queued_sends.get(&addr).into_iter().flat_map(|queue| queue.iter().map(|(seq, packet)| (*seq, packet))))
+                    .collect();
+                // 最早分配的序列号在前，序列号回绕时也成立
+                let next_seq = self.next_seq.get(&addr).copied().unwrap_or(0);
+                packets.sort_by_key(|(seq, _)| seq.wrapping_sub(next_seq));
+                PeerSnapshot {
+                    addr,
+                    next_seq: self.next_seq.get(&addr).copied(),
+                    next_message_id: self.next_message_id.get(&addr).copied(),
+                    window: self.recv_windows.get(&addr).cloned(),
+                    rtt: self.rtt_stats.get(&addr).map(RttSnapshot::capture),
+                    token: self.resumption_tokens.get(&addr).cloned(),
+                    packets: packets
+                        .into_iter()
+                        .map(|(_, packet)| {
+                            let mut data = vec![0; packet.buffer.len()];
+                            packet.buffer.write_to(&mut data, signing_authenticator(&self.peer_keys, &self.authenticator, addr));
+                            data
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        SessionState { peers, used_tokens: self.used_tokens.entries().collect() }.encode()
+    }
+
+    /// 恢复[`export_state`](Self::export_state)导出的状态，返回恢复的对端数量
+    ///
+    /// 对端看不到连接中断：序列号从导出时继续，已收到的包仍被去重，未确认的数据包
+    /// 重新排队发送，每个对端的连接状态从`now`开始计算活动时间。已有同一对端的状态被覆盖。
+    /// 数据无法解析时返回`RudpError::Serialization`，此时不修改任何状态
+    pub fn import_state(&mut self, data: &[u8], now: Instant) -> Result<usize, RudpError> {
+        let state = SessionState::decode(data)?;
+        // 先分配所有buffer，失败时不留下恢复了一半的状态
+        let mut peers = Vec::with_capacity(state.peers.len());
+        for peer in state.peers {
+            let mut packets = VecDeque::with_capacity(peer.packets.len());
+            for packet in &peer.packets {
+                let mut buffer = self.buffer_pool.get_buffer_for(packet.len() - PROTOCOL_HEADER_SIZE)?;
+                buffer.raw_buffer_mut()[..packet.len()].copy_from_slice(packet);
+                buffer.set_data_len(packet.len() - PROTOCOL_HEADER_SIZE)?;
+                let seq = u32::from_be_bytes([packet[5], packet[6], packet[7], packet[8]]);
+                packets.push_back((seq, PendingPacket::new(PacketBuffer::Pooled(buffer), now)));
+            }
+            peers.push((peer, packets));
+        }
+
+        let count = peers.len();
+        for (peer, packets) in peers {
+            let addr = peer.addr;
+            self.cleanup_connection(addr);
+            self.dead_peers.remove(addr);
+            if let Some(seq) = peer.next_seq {
+                self.next_seq.insert(addr, seq);
+            }
+            if let Some(id) = peer.next_message_id {
+                self.next_message_id.insert(addr, id);
+            }
+            if let Some(window) = peer.window {
+                self.recv_windows.insert(addr, window);
+            }
+            let rtt_stats = match &peer.rtt {
+                Some(rtt) => rtt.restore(&self.config),
+                None => RttStats::with_config(&self.config),
+            };
+            self.rtt_stats.insert(addr, rtt_stats);
+            if let Some(token) = peer.token {
+                self.resumption_tokens.insert(addr, token);
+            }
+            if !packets.is_empty() {
+                self.queued_sends.insert(addr, packets);
+            }
+            self.connection_states.insert(addr, ConnectionState::new_at(now));
+            self.touch_peer(addr);
+        }
+        for (id, expires) in state.used_tokens {
+            self.used_tokens.restore(id, expires);
+        }
+        self.send_queued_packets(now);
+        Ok(count)
+    }
+
+    /// 向`target`发送一个ping，返回的[`PingHandle`]在收到应答时完成为往返时间
+    ///
+    /// 在该对端的`ping_interval`内没有应答时完成为`Err(RudpError::Timeout)`，ping不重传
+    pub fn ping(&mut self, target: SocketAddr, now: Instant) -> Result<PingHandle, RudpError> {
+        self.ensure_open()?;
+        self.ensure_alive(target)?;
+        telemetry!(self.telemetry, advance(now));
+        let seq = self.send_ping(target, now);
+        let (handle, sender) = PingHandle::new(target);
+        let deadline = now + self.peer_keepalive_config(target).ping_interval;
+        self.pings.insert((target, seq), PendingPing { sent: now, deadline, sender });
+        Ok(handle)
+    }
+
+    /// 发送`Bytes`数据，等待确认期间保留`data`本身，协议头和扩展在发出数据报时写入
+    ///
+    /// 与[`send`](Self::send)一样加入扩展、按需压缩，拥塞窗口已满时排队
+    #[cfg(feature = "bytes")]
+    pub fn send_bytes(&mut self, data: Bytes, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.ensure_open()?;
+        self.ensure_writable(target)?;
+        self.check_size(data.len(), target)?;
+        self.send_unbundled(Payload::Shared(SharedBody::Bytes(data)), target, now)
+    }
+
+    /// 对端支持压缩且载荷达到阈值时压缩载荷，压缩后没有变小则原样返回
+    fn compress_payload(&self, packet_type: PacketType, buffer: PooledBuffer, target: SocketAddr) -> (PacketType, PooledBuffer) {
+        let data_len = buffer.data_len();
+        let (Some(config), Some(algorithm)) = (&self.config.compression, self.compression_for(data_len, target)) else {
+            return (packet_type, buffer);
+        };
+        let bound = COMPRESSION_HEADER_SIZE + compression::max_compressed_len(algorithm, data_len);
+        let Ok(mut compressed) = self.buffer_pool.get_buffer_for(bound) else {
+            return (packet_type, buffer);
+        };
+
+        compressed.data_mut()[0] = algorithm.id();
+        compressed.data_mut()[1] = packet_type as u8;
+        let output = &mut compressed.data_mut()[COMPRESSION_HEADER_SIZE..bound];
+        match compression::compress(algorithm, config.zstd_level, buffer.data(), output) {
+            Some(len) if COMPRESSION_HEADER_SIZE + len < data_len => {
+                trace_event!(trace, %target, ?algorithm, from = data_len, to = COMPRESSION_HEADER_SIZE + len, "payload compressed");
+                // Cannot fail: the length is within the output area
+                let _ = compressed.set_data_len(COMPRESSION_HEADER_SIZE + len);
+                (PacketType::Compressed, compressed)
+            }
+            _ => (packet_type, buffer),
+        }
+    }
+
+    /// 发往`target`的`data_len`字节载荷使用的压缩算法，不压缩时为`None`
+    fn compression_for(&self, data_len: usize, target: SocketAddr) -> Option<Compression> {
+        let config = self.config.compression.as_ref()?;
+        let accepted = self.peer_compression.get(&target).copied().flatten().unwrap_or(0);
+        let algorithm = config.algorithms.iter().copied().find(|algorithm| accepted & algorithm.id() != 0)?;
+        (data_len >= config.threshold.max(1)).then_some(algorithm)
+    }
+
+    /// 检查对端状态、数据长度和拥塞窗口，通过后分配序列号
+    fn admit(&mut self, data_len: usize, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.check_payload(data_len, target)?;
+
+        // 检查拥塞窗口
+        let rtt_stats = self.rtt_stats.entry(target).or_insert_with(|| RttStats::with_config(&self.config));
+        if !rtt_stats.can_send() {
+            trace_event!(debug, cwnd = rtt_stats.cwnd, in_flight = rtt_stats.in_flight, "congestion window full");
+            return Err(RudpError::CongestionWindowFull);
+        }
+
+        self.refill_pacer(now);
+        Ok(self.get_next_seq(target))
+    }
+
+    /// 检查对端未断开且载荷不超过`max_payload_size`
+    fn check_payload(&self, data_len: usize, target: SocketAddr) -> Result<(), RudpError> {
+        self.ensure_alive(target)?;
+        self.check_size(data_len, target)
+    }
+
+    /// 载荷超过发往`target`的上限时返回`RudpError::BufferTooLarge`
+    fn check_size(&self, data_len: usize, target: SocketAddr) -> Result<(), RudpError> {
+        let max = self.max_payload_for(target);
+        if data_len > max {
+            trace_event!(debug, %target, size = data_len, max, "payload too large");
+            return Err(RudpError::BufferTooLarge { size: data_len, max });
+        }
+        Ok(())
+    }
+
+    /// 保存已填好协议头的数据包以备重传，并加入发送队列
+    fn transmit_new(&mut self, mut pending_packet: PendingPacket, seq: u32, target: SocketAddr, now: Instant) {
+        let timestamp = self.timestamp(now);
+        let critical = pending_packet.critical;
+        let buffer = &pending_packet.buffer;
+        let data_len = buffer.data_len();
+
+        trace_event!(trace, seq, "data packet sent");
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.log(target, QlogEvent::PacketSent {
+                packet_type: buffer.packet_type(),
+                seq,
+                length: buffer.len(),
+                retransmission: false,
+            });
+        }
+        telemetry!(self.telemetry, data_sent(target, seq, data_len, now));
+
+        // Update congestion control (packet sent)
+        let rtt_stats = self.rtt_stats.get_mut(&target).unwrap();
+        rtt_stats.on_packet_sent();
+
+        // Store for retransmission
+        pending_packet.send_time = now;
+        pending_packet.first_sent = now;
+        pending_packet.rto = rtt_stats.rto;
+        pending_packet.buffer.stamp(timestamp, signing_authenticator(&self.peer_keys, &self.authenticator, target));
+        let authenticator = signing_authenticator(&self.peer_keys, &self.authenticator, target);
+        self.transmits.push_back(data_transmit(&self.buffer_pool, &pending_packet.buffer, authenticator, target, seq));
+        self.send_buffer.entry(target).or_default().insert(seq, pending_packet);
+
+        // Update statistics
+        let stats = self.connection_stats.entry(target).or_default();
+        stats.record_packet_sent(now);
+        stats.record_bytes_sent(data_len, now);
+
+        // Update connection state
+        self.connection_states.entry(target).or_insert_with(|| ConnectionState::new_at(now)).update_activity(now);
+        self.touch_peer(target);
+        if critical {
+            self.schedule_copy(target, seq, now);
+        }
+    }
+
+    /// 对端的估计丢包率超过`redundancy.loss_threshold`时，安排关键数据包的副本
+    fn schedule_copy(&mut self, target: SocketAddr, seq: u32, now: Instant) {
+        if self.redundancy.loss_rate(target) > self.config.redundancy.loss_threshold {
+            self.redundancy.schedule(target, seq, now + self.config.redundancy.spacing);
+        }
+    }
+
+    /// 发送到期的关键数据包副本；期间已确认或已重传的包不再发送
+    fn send_redundant_copies(&mut self, now: Instant) {
+        let timestamp = self.timestamp(now);
+        while let Some((addr, seq)) = self.redundancy.pop_due(now) {
+            let Some(pending_packet) = self.send_buffer.get_mut(&addr).and_then(|packets| packets.get_mut(&seq)) else {
+                continue;
+            };
+            if pending_packet.retry_count > 0 {
+                continue;
+            }
+            pending_packet.copy_sent = Some(now);
+            let authenticator = signing_authenticator(&self.peer_keys, &self.authenticator, addr);
+            pending_packet.buffer.stamp(timestamp, authenticator);
+            self.transmits.push_back(data_transmit(&self.buffer_pool, &pending_packet.buffer, authenticator, addr, seq));
+            trace_event!(debug, %addr, seq, "redundant copy sent");
+            if let Some(qlog) = self.qlog.as_mut() {
+                qlog.log(addr, QlogEvent::PacketSent {
+                    packet_type: pending_packet.buffer.packet_type(),
+                    seq,
+                    length: pending_packet.buffer.len(),
+                    retransmission: true,
+                });
+            }
+            self.connection_stats.entry(addr).or_default().record_redundant_copy();
+        }
+    }
+
+    /// 取出下一个待发送的数据报
+    ///
+    /// 发送数据、处理收到的包和超时都可能产生待发送的数据报，每次调用这些方法后
+    /// 应取出并发送全部数据报。设置了`max_bandwidth`时，超出带宽预算的数据报留在队列中，
+    /// 在[`poll_timeout`](Self::poll_timeout)之后的`handle_timeout()`中放行
+    pub fn poll_transmit(&mut self) -> Option<Transmit> {
+        while let Some(queued) = self.transmits.front() {
+            let Some((contents, destination)) = self.transmit_contents(queued) else {
+                // 已确认或已丢弃的包不再发送
+                self.transmits.pop_front();
+                continue;
+            };
+            if self.pacer.as_ref().is_some_and(|pacer| !pacer.allows(0, contents.len())) {
+                return None;
+            }
+            let transmit = Transmit { destination, contents: contents.to_vec() };
+            if let Some(pacer) = self.pacer.as_mut() {
+                pacer.consume(transmit.contents.len());
+            }
+            self.transmits.pop_front();
+            return Some(transmit);
+        }
+        None
+    }
+
+    /// 带宽预算内可发送的数据报（不拷贝），由调用方发送后调用`clear_transmits()`
+    pub(crate) fn queued_transmits(&self) -> Vec<(&[u8], SocketAddr)> {
+        self.transmits
+            .iter()
+            .take(self.paced_len())
+            .filter_map(|queued| self.transmit_contents(queued))
+            .collect()
+    }
+
+    /// 待发送队列中的数据报数量，包括等待带宽预算的
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn transmit_count(&self) -> usize {
+        self.transmits.len()
+    }
+
+    /// 移除`queued_transmits()`返回的数据报并计入带宽预算
+    pub(crate) fn clear_transmits(&mut self) {
+        let count = self.paced_len();
+        if self.pacer.is_some() {
+            let sent: usize = self.transmits.iter().take(count).filter_map(|queued| self.transmit_contents(queued)).map(|(data, _)| data.len()).sum();
+            if let Some(pacer) = self.pacer.as_mut() {
+                pacer.consume(sent);
+            }
+        }
+        self.transmits.drain(..count);
+    }
+
+    /// 队列头部在带宽预算内的条目数
+    fn paced_len(&self) -> usize {
+        let Some(pacer) = &self.pacer else {
+            return self.transmits.len();
+        };
+        let mut spent = 0;
+        for (index, queued) in self.transmits.iter().enumerate() {
+            if let Some((data, _)) = self.transmit_contents(queued) {
+                if !pacer.allows(spent, data.len()) {
+                    return index;
+                }
+                spent += data.len();
+            }
+        }
+        self.transmits.len()
+    }
+
+    /// 待发送条目的内容，已确认或已丢弃的数据包为`None`
+    fn transmit_contents<'a>(&'a self, queued: &'a QueuedTransmit) -> Option<(&'a [u8], SocketAddr)> {
+        match queued {
+            QueuedTransmit::Control(buffer, target) => Some((buffer.full_data(), *target)),
+            QueuedTransmit::Datagram(buffer, target) => Some((buffer.full_data(), *target)),
+            QueuedTransmit::Data(target, seq) => self
+                .pending_packet(*target, *seq)
+                .and_then(|pending_packet| pending_packet.buffer.contents())
+                .map(|contents| (contents, *target)),
+            QueuedTransmit::Assembled(buffer, target, seq) => self.pending_packet(*target, *seq).map(|_| (buffer.full_data(), *target)),
+        }
+    }
+
+    fn refill_pacer(&mut self, now: Instant) {
+        if let Some(pacer) = self.pacer.as_mut() {
+            pacer.refill(now);
+        }
+    }
+
+    fn pending_packet(&self, target: SocketAddr, seq: u32) -> Option<&PendingPacket> {
+        self.send_buffer.get(&target).and_then(|packets| packets.get(&seq))
+    }
+
+    /// 取出下一个收到的用户数据包或接收错误
+    pub fn poll_received(&mut self) -> Option<ReceivedData> {
+        self.recv_queue.pop_front()
+    }
+
+    /// 取出下一个来自`addr`的数据包或接收错误，其他对端的数据留在队列中
+    pub fn poll_received_from(&mut self, addr: SocketAddr) -> Option<ReceivedData> {
+        let index = self.recv_queue.iter().position(|received| received.from == addr)?;
+        self.recv_queue.remove(index)
+    }
+
+    /// 取出下一条已收齐的消息
+    pub fn poll_message(&mut self) -> Option<ReceivedMessage> {
+        self.message_queue.pop_front()
+    }
+
+    /// 接收队列中是否有数据
+    pub(crate) fn has_received(&self) -> bool {
+        !self.recv_queue.is_empty()
+    }
+
+    /// 接收队列中是否有来自`addr`的数据
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn has_received_from(&self, addr: SocketAddr) -> bool {
+        self.recv_queue.iter().any(|received| received.from == addr)
+    }
+
+    pub(crate) fn has_messages(&self) -> bool {
+        !self.message_queue.is_empty()
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn received_len(&self) -> usize {
+        self.recv_queue.len()
+    }
+
+    /// 取出最多`max`个收到的数据
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn drain_received(&mut self, max: usize) -> Vec<ReceivedData> {
+        let count = max.min(self.recv_queue.len());
+        self.recv_queue.drain(..count).collect()
+    }
+
+    /// 将I/O错误等放入接收队列，由`poll_received()`返回
+    pub(crate) fn push_received(&mut self, received: ReceivedData) {
+        self.recv_queue.push_back(received);
+    }
+
+    /// 把消息放回接收队列头部，下次`poll_message()`按原顺序返回
+    #[cfg_attr(not(feature = "transfer"), allow(dead_code))]
+    pub(crate) fn requeue_messages(&mut self, messages: Vec<ReceivedMessage>) {
+        for message in messages.into_iter().rev() {
+            self.message_queue.push_front(message);
+        }
+    }
+
+    /// 处理一个从`from`收到的数据报
+    ///
+    /// 控制包在内部处理，用户数据和错误放入接收队列，完整的消息放入消息队列。
+    /// STUN消息被忽略。产生的ACK在下一次[`handle_timeout`](Self::handle_timeout)中发送
+    pub fn handle_datagram(&mut self, packet_data: &[u8], from: SocketAddr, now: Instant) {
+        if stun::is_stun_message(packet_data) {
+            // 迟到的STUN响应，直接丢弃
+            return;
+        }
+        if !self.admits(from, now) {
+            return;
+        }
+        self.refill_pacer(now);
+        match self.handle_received_packet(packet_data, from, now) {
+            Ok(Some(received)) => self.recv_queue.push_back(received),
+            Ok(None) => {}
+            Err(e) => self.queue_error(from, None, e),
+        }
+    }
+
+    /// 处理一个已读入内存池buffer的数据报，Data包的buffer直接放入接收队列
+    pub(crate) fn handle_buffer(&mut self, buffer: PooledBuffer, len: usize, from: SocketAddr, now: Instant) {
+        if stun::is_stun_message(&buffer.raw_buffer()[..len]) {
+            // 迟到的STUN响应，直接丢弃
+            return;
+        }
+        if !self.admits(from, now) {
+            return;
+        }
+        self.refill_pacer(now);
+        match self.handle_received_buffer(buffer, len, from, now) {
+            Ok(Some(received)) => self.recv_queue.push_back(received),
+            Ok(None) => {}
+            Err(e) => self.queue_error(from, None, e),
+        }
+    }
+
+    /// 无效包过多的来源一律丢弃；已有状态的对端直接接受，新来源交给准入检查
+    fn admits(&self, from: SocketAddr, now: Instant) -> bool {
+        if self.invalid_sources.is_blocked(from, now) {
+            return false;
+        }
+        if self.peer_activity.contains(from) {
+            return true;
+        }
+        let verdict = self.peer_filter.as_ref().map_or(Verdict::Accept, |filter| filter(from));
+        if verdict == Verdict::Reject {
+            trace_event!(debug, %from, "packet from rejected source dropped");
+        }
+        verdict == Verdict::Accept
+    }
+
+    /// 接收错误放入接收队列，队列已满时丢弃
+    fn queue_error(&mut self, from: SocketAddr, seq: Option<u32>, error: RudpError) {
+        if self.recv_queue.len() < self.config.limits.max_queued_packets {
+            self.recv_queue.push_back(ReceivedData { from, seq, result: Err(error) });
+        }
+    }
+
+    /// 发往被保活判定为断开的对端时返回`ConnectionError::Dead`
+    fn ensure_alive(&self, target: SocketAddr) -> Result<(), RudpError> {
+        if self.dead_peers.contains(target) {
+            return Err(ConnectionError::Dead { addr: target }.into());
+        }
+        Ok(())
+    }
+
+    /// [`shutdown_send`](Self::shutdown_send)之后发往`target`时返回`ConnectionError::SendShutdown`
+    fn ensure_writable(&self, target: SocketAddr) -> Result<(), RudpError> {
+        if self.send_shutdown.contains_key(&target) {
+            return Err(ConnectionError::SendShutdown { addr: target }.into());
+        }
+        Ok(())
+    }
+
+    /// 忘记`addr`已断开，之后可以重新向它发送
+    ///
+    /// 断开的对端再次发来有效包时也会自动恢复
+    pub fn clear_dead_peer(&mut self, addr: SocketAddr) {
+        self.dead_peers.remove(addr);
+    }
+
+    /// 处理定时任务：重传、发送ACK、发送排队的消息分片和保活探测
+    pub fn handle_timeout(&mut self, now: Instant) {
+        telemetry!(self.telemetry, advance(now));
+        self.refill_pacer(now);
+        let queued = self.transmits.len();
+
+        // Handle retransmissions, then copies of critical packets not retransmitted
+        self.handle_retransmissions(now);
+        self.send_redundant_copies(now);
+
+        // Send pending ACKs
+        self.send_pending_acks();
+
+        // Send scheduled data, then queued packets and message fragments the congestion
+        // window now has room for
+        self.send_scheduled(now);
+        self.flush_expired_bundles(now);
+        self.send_queued_packets(now);
+        self.send_queued_fragments(now);
+        self.send_pending_shutdowns(now);
+        self.reassembler.expire(now, MESSAGE_REASSEMBLY_TIMEOUT);
+
+        // Check connection health
+        self.check_connection_health(now);
+        self.expire_pings(now);
+        self.tombstones.retain(|_, deadline| now < *deadline);
+        self.coalesce_control(queued);
+        self.check_alerts(now);
+        self.report_stats(now);
+
+        // Release idle pool buffers
+        if let Some(trim) = &self.config.pool_trim {
+            let _ = self.buffer_pool.trim_idle(trim, now);
+        }
+    }
+
+    /// 开启统计报告时，到了报告时间就把全局和每个连接的统计交给报告输出
+    fn report_stats(&mut self, now: Instant) {
+        if !self.stats_reporter.as_mut().is_some_and(|reporter| reporter.due(now)) {
+            return;
+        }
+        let Ok(global) = self.global_stats() else {
+            return;
+        };
+        let peers = self.connections().map(|(addr, status, stats)| PeerStatsReport::new(addr, status, &stats, now)).collect();
+        if let Some(reporter) = self.stats_reporter.as_mut() {
+            reporter.report(&global, peers, now);
+        }
+    }
+
+    /// 收到对端的包时结束它的丢包期，够长的按突发丢包或中断记入统计并通知事件回调
+    fn end_loss_episode(&mut self, addr: SocketAddr, now: Instant) {
+        let Some(event) = self.loss_episodes.heard_from(addr, now) else {
+            return;
+        };
+        let stats = self.connection_stats.entry(addr).or_default();
+        match &event {
+            ConnectionEvent::Outage { duration } => {
+                trace_event!(info, %addr, duration_ms = duration.as_millis() as u64, "outage ended");
+                stats.record_outage(*duration);
+            }
+            _ => {
+                trace_event!(info, %addr, event = ?event, "loss burst ended");
+                stats.record_loss_burst();
+            }
+        }
+        let peer = self.peer_id(addr);
+        self.notify(addr, peer, event);
+    }
+
+    /// 配置了告警阈值时检查每个对端，越过或回落到阈值时通知事件回调
+    fn check_alerts(&mut self, now: Instant) {
+        let (Some(config), Some(handler)) = (&self.config.alerts, &self.event_handler) else {
+            return;
+        };
+        for (addr, stats) in &self.connection_stats {
+            let srtt = self.rtt_stats.get(addr).filter(|rtt_stats| rtt_stats.last_rtt_sample.is_some()).map(|rtt_stats| rtt_stats.srtt);
+            self.alerts.check(config, *addr, stats, srtt, now, |alert| {
+                trace_event!(info, %addr, kind = ?alert.kind, raised = alert.raised, value = alert.value, "alert threshold crossed");
+                handler.on_alert(*addr, alert);
+            });
+        }
+    }
+
+    /// 开启`coalesce_control`时，把`start`之后排入的控制包按对端合并为Batch数据报
+    ///
+    /// 合并后的数据报排在本轮的数据包之前，每个不超过协议头加`max_payload_size`
+    fn coalesce_control(&mut self, start: usize) {
+        if !self.config.coalesce_control || self.transmits.len() < start + 2 {
+            return;
+        }
+        let mut others = Vec::new();
+        let mut controls: Vec<(SocketAddr, Vec<PooledBuffer>)> = Vec::new();
+        for queued in self.transmits.drain(start..) {
+            match queued {
+                QueuedTransmit::Control(buffer, target) => match controls.iter_mut().find(|(addr, _)| *addr == target) {
+                    Some((_, packets)) => packets.push(buffer),
+                    None => controls.push((target, vec![buffer])),
+                },
+                other => others.push(other),
+            }
+        }
+        let limit = PROTOCOL_HEADER_SIZE + self.config.max_payload_size;
+        for (target, packets) in controls {
+            let mut group = Vec::new();
+            let mut frames_len = 0;
+            for packet in packets {
+                let frame_len = BATCH_FRAME_HEADER_SIZE + packet.full_data().len();
+                if !group.is_empty() && PROTOCOL_HEADER_SIZE + frames_len + frame_len > limit {
+                    self.queue_batch(std::mem::take(&mut group), frames_len, target);
+                    frames_len = 0;
+                }
+                group.push(packet);
+                frames_len += frame_len;
+            }
+            self.queue_batch(group, frames_len, target);
+        }
+        self.transmits.extend(others);
+    }
+
+    /// 把一组控制包合成一个Batch排入，`frames_len`为其载荷长度；只有一个包时直接排入它
+    fn queue_batch(&mut self, mut packets: Vec<PooledBuffer>, frames_len: usize, target: SocketAddr) {
+        if packets.len() == 1 {
+            if let Some(packet) = packets.pop() {
+                self.transmits.push_back(QueuedTransmit::Control(packet, target));
+            }
+            return;
+        }
+        let mut frames = Vec::with_capacity(frames_len);
+        for packet in &packets {
+            push_batch_frame(&mut frames, packet.full_data());
+        }
+        let mut batch = RawPacket {
+            packet_type: PacketType::Batch,
+            security_code: 0,
+            seq: 0,
+            extensions: Vec::new(),
+            data: frames,
+        };
+        if let Some(buffer) = self.sign(&mut batch, target) {
+            self.transmits.push_back(QueuedTransmit::Control(buffer, target));
+        }
+    }
+
+    /// 下一次需要调用[`handle_timeout`](Self::handle_timeout)的时间
+    ///
+    /// 包括重传超时、发送截止时间、保活探测、ping超时和等待带宽预算的数据报。
+    /// 收到数据后产生的ACK不在其中，处理完收到的数据报后应立即调用一次`handle_timeout()`
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        let retransmissions = self.send_buffer.values().flat_map(HashMap::values).map(|pending_packet| {
+            let retry_at = pending_packet.send_time + pending_packet.rto;
+            pending_packet.deadline.map_or(retry_at, |deadline| deadline.min(retry_at))
+        });
+        let keepalive = self.connection_states.iter().map(|(addr, state)| {
+            let config = self.peer_keepalive_config(*addr);
+            match state.ping_sent {
+                Some(ping_time) => ping_time + config.ping_interval,
+                None => state.last_activity + config.idle_timeout,
+            }
+        });
+        let paced = self.pacer.as_ref().and_then(|pacer| {
+            let (data, _) = self.transmits.iter().find_map(|queued| self.transmit_contents(queued))?;
+            pacer.ready_at(data.len())
+        });
+        let pings = self.pings.values().map(|ping| ping.deadline);
+        let bundles = self.bundles.values().map(|bundle| bundle.deadline);
+        let report = self.stats_reporter.as_ref().and_then(StatsReporter::next_report);
+        let scheduled = self.scheduled_sends.front().map(|scheduled| scheduled.at);
+        let copies = self.redundancy.next_copy();
+        retransmissions.chain(keepalive).chain(paced).chain(pings).chain(bundles).chain(report).chain(scheduled).chain(copies).min()
+    }
+
+    /// 设置默认的保活与断线检测参数
+    pub fn set_keepalive_config(&mut self, config: KeepAliveConfig) {
+        self.config.keepalive = config;
+    }
+
+    /// 获取默认的保活与断线检测参数
+    pub fn keepalive_config(&self) -> &KeepAliveConfig {
+        &self.config.keepalive
+    }
+
+    /// 获取配置
+    pub fn config(&self) -> &RudpConfig {
+        &self.config
+    }
+
+    /// 注册事件回调，替换之前的回调
+    pub fn set_event_handler<H: EventHandler + 'static>(&mut self, handler: H) {
+        self.event_handler = Some(Box::new(handler));
+    }
+
+    /// 移除已注册的事件回调
+    pub fn clear_event_handler(&mut self) {
+        self.event_handler = None;
+    }
+
+    /// 设置新来源地址的准入检查，替换之前的检查
+    ///
+    /// 来自尚无任何状态的地址的包先交给`filter`，返回`Verdict::Reject`时直接丢弃，
+    /// 不创建状态也不回复。已有状态的对端（包括本端主动发送过的对端）不再检查
+    pub fn set_peer_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(SocketAddr) -> Verdict + Send + Sync + 'static,
+    {
+        self.peer_filter = Some(Box::new(filter));
+    }
+
+    /// 移除准入检查，接受所有来源
+    pub fn clear_peer_filter(&mut self) {
+        self.peer_filter = None;
+    }
+
+    /// 设置qlog风格的结构化事件日志输出
+    pub fn set_qlog_sink<S: QlogSink + 'static>(&mut self, sink: S) {
+        self.qlog = Some(QlogWriter::new(Box::new(sink)));
+    }
+
+    /// 停止记录结构化事件日志
+    pub fn clear_qlog_sink(&mut self) {
+        self.qlog = None;
+    }
+
+    /// 每隔`interval`把全局和每个连接的统计快照交给`sink`，替换之前的报告设置
+    ///
+    /// 报告在`handle_timeout()`中生成，第一次报告在开启后第一次调用`handle_timeout()`的
+    /// `interval`之后。详见[`report`](crate::report)模块
+    pub fn enable_stats_reporting<S: StatsSink + 'static>(&mut self, interval: Duration, sink: S) {
+        self.stats_reporter = Some(StatsReporter::new(interval, Box::new(sink)));
+    }
+
+    /// 停止统计报告
+    pub fn disable_stats_reporting(&mut self) {
+        self.stats_reporter = None;
+    }
+
+    /// 通过OpenTelemetry报告握手、每个数据包从发送到确认的过程和连接的生命周期，并记录指标
+    ///
+    /// 详见[`otel`](crate::otel)模块
+    #[cfg(feature = "otel")]
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = Some(telemetry);
+    }
+
+    /// 停止OpenTelemetry报告，结束所有未结束的span
+    #[cfg(feature = "otel")]
+    pub fn clear_telemetry(&mut self) {
+        self.telemetry = None;
+    }
+
+    /// 运行时更新配置，不会断开已有连接
+    ///
+    /// 协议核心不涉及I/O，`io_backend`、`io_batch_size`和socket选项由外层检查
+    pub fn update_config(&mut self, config: RudpConfig) -> Result<(), RudpError> {
+        config.validate()?;
+        check_pool(&self.buffer_pool, &config)?;
+
+        for stats in self.rtt_stats.values_mut() {
+            stats.apply_config(&config);
+        }
+        for packets in self.send_buffer.values_mut() {
+            for pending_packet in packets.values_mut() {
+                pending_packet.rto = pending_packet.rto.min(config.max_rto);
+            }
+        }
+        if config.compression != self.config.compression {
+            // 重新向对端通告压缩算法
+            self.peer_compression.clear();
+        }
+
+        if self.pacer.as_ref().map(Pacer::rate) != config.max_bandwidth {
+            self.pacer = config.max_bandwidth.map(Pacer::new);
+        }
+
+        self.authenticator = config.packet_authenticator();
+        self.config = config;
+        self.evict_excess_peers();
+        Ok(())
+    }
+
+    /// 为指定连接设置保活与断线检测参数，覆盖默认配置
+    pub fn set_peer_keepalive_config(&mut self, addr: SocketAddr, config: KeepAliveConfig) {
+        self.peer_keepalive.insert(addr, config);
+    }
+
+    /// 移除指定连接的保活参数覆盖，恢复使用默认配置
+    pub fn clear_peer_keepalive_config(&mut self, addr: SocketAddr) {
+        self.peer_keepalive.remove(&addr);
+    }
+
+    /// 为指定对端设置认证密钥，发往该对端的包用它计算安全码，来自该对端的包用它校验
+    ///
+    /// 再次设置ID不同的密钥即轮换：新密钥立即用于发送，旧密钥在
+    /// [`KEY_ROTATION_GRACE`](crate::keys::KEY_ROTATION_GRACE)内仍被接受，两端先后换上
+    /// 新密钥期间连接不会中断。两端必须为彼此设置相同的密钥
+    pub fn set_peer_key(&mut self, addr: SocketAddr, key: PeerKey, now: Instant) {
+        match self.peer_keys.get_mut(&addr) {
+            Some(keys) => keys.rotate(key, now),
+            None => {
+                self.peer_keys.insert(addr, KeyRing::new(key));
+            }
+        }
+    }
+
+    /// 移除指定对端的密钥，恢复使用实例的认证器
+    pub fn clear_peer_key(&mut self, addr: SocketAddr) {
+        self.peer_keys.remove(&addr);
+    }
+
+    /// 指定对端当前用于发送的密钥ID
+    pub fn peer_key_id(&self, addr: SocketAddr) -> Option<u8> {
+        self.peer_keys.get(&addr).map(|keys| keys.current().id())
+    }
+
+    /// 设置本端的身份密钥，之后的ping和ping应答都带上签名的身份
+    #[cfg(feature = "identity")]
+    pub fn set_identity(&mut self, key: IdentityKey) {
+        self.identities.set_key(key);
+    }
+
+    /// 固定指定对端的身份：只接受持有该公钥的对端，验证身份之前丢弃它的包
+    #[cfg(feature = "identity")]
+    pub fn pin_peer_identity(&mut self, addr: SocketAddr, identity: PeerIdentity) {
+        self.identities.pin(addr, identity);
+    }
+
+    /// 取消指定对端的身份固定
+    #[cfg(feature = "identity")]
+    pub fn unpin_peer_identity(&mut self, addr: SocketAddr) {
+        self.identities.unpin(addr);
+    }
+
+    /// 设置身份校验回调，替换之前的回调
+    ///
+    /// 设置后所有对端都必须先出示身份，签名有效的身份再交给`verifier`，返回
+    /// `Verdict::Reject`的身份按无效包处理
+    #[cfg(feature = "identity")]
+    pub fn set_identity_verifier<F>(&mut self, verifier: F)
+    where
+        F: Fn(SocketAddr, &PeerIdentity) -> Verdict + Send + Sync + 'static,
+    {
+        self.identities.set_verifier(Box::new(verifier));
+    }
+
+    /// 移除身份校验回调，只有固定了身份的对端仍需出示身份
+    #[cfg(feature = "identity")]
+    pub fn clear_identity_verifier(&mut self) {
+        self.identities.clear_verifier();
+    }
+
+    /// 指定对端已验证的身份
+    #[cfg(feature = "identity")]
+    pub fn peer_identity(&self, addr: SocketAddr) -> Option<PeerIdentity> {
+        self.identities.verified(addr)
+    }
+
+    /// 获取指定连接实际生效的保活参数
+    pub fn peer_keepalive_config(&self, addr: SocketAddr) -> &KeepAliveConfig {
+        self.peer_keepalive.get(&addr).unwrap_or(&self.config.keepalive)
+    }
+
+    /// 为指定连接设置重传退避参数，覆盖默认配置
+    pub fn set_peer_backoff_config(&mut self, addr: SocketAddr, config: BackoffConfig) {
+        self.peer_backoff.insert(addr, config);
+    }
+
+    /// 移除指定连接的重传退避参数覆盖，恢复使用默认配置
+    pub fn clear_peer_backoff_config(&mut self, addr: SocketAddr) {
+        self.peer_backoff.remove(&addr);
+    }
+
+    /// 获取指定连接实际生效的重传退避参数
+    pub fn peer_backoff_config(&self, addr: SocketAddr) -> &BackoffConfig {
+        self.peer_backoff.get(&addr).unwrap_or(&self.config.backoff)
+    }
+
+    /// 设置随每个ping和ping应答发送的心跳载荷，空载荷表示不发送
+    ///
+    /// 对端收到后通过[`EventHandler::on_heartbeat`]通知并可用
+    /// [`peer_heartbeat`](Self::peer_heartbeat)查询，应用无需额外的数据包即可交换少量
+    /// 状态。载荷超过[`MAX_HEARTBEAT_SIZE`]时返回`RudpError::BufferTooLarge`
+    pub fn set_heartbeat(&mut self, payload: &[u8]) -> Result<(), RudpError> {
+        if payload.len() > MAX_HEARTBEAT_SIZE {
+            return Err(RudpError::BufferTooLarge { size: payload.len(), max: MAX_HEARTBEAT_SIZE });
+        }
+        self.heartbeat.clear();
+        self.heartbeat.extend_from_slice(payload);
+        Ok(())
+    }
+
+    /// 本端的心跳载荷
+    pub fn heartbeat(&self) -> &[u8] {
+        &self.heartbeat
+    }
+
+    /// 最近一次从`addr`的ping或ping应答中收到的心跳载荷
+    pub fn peer_heartbeat(&self, addr: SocketAddr) -> Option<&[u8]> {
+        self.peer_heartbeats.get(&addr).map(Vec::as_slice)
+    }
+
+    /// 限制发往指定对端的单个数据包的用户数据大小，例如已知到该对端的路径MTU较小时
+    ///
+    /// 只能低于`max_payload_size`，更大的值按`max_payload_size`处理。消息分片和合并包
+    /// 也按此大小拆分
+    pub fn set_peer_max_payload(&mut self, addr: SocketAddr, size: usize) {
+        self.peer_max_payload.insert(addr, size);
+    }
+
+    /// 移除指定对端的载荷大小限制，恢复使用`max_payload_size`
+    pub fn clear_peer_max_payload(&mut self, addr: SocketAddr) {
+        self.peer_max_payload.remove(&addr);
+    }
+
+    /// 发往指定对端的单个数据包实际可携带的用户数据字节数
+    ///
+    /// 已扣除该对端的数据包携带的扩展区（时间戳、载荷校验和、密钥编号、恢复令牌和填充），
+    /// 整个数据报不超过接收端读取的`max_payload_size`加协议头。发送接口在做任何处理之前
+    /// 按此检查载荷大小，超过时返回带有该上限的`RudpError::BufferTooLarge`，不分配序列号，
+    /// 也不发出等待合并的载荷
+    pub fn max_payload_for(&self, addr: SocketAddr) -> usize {
+        let max = self.config.max_payload_size;
+        let max = self.peer_max_payload.get(&addr).map_or(max, |size| (*size).min(max));
+        max.saturating_sub(self.extension_overhead(addr))
+    }
+
+    /// `seal()`加在发往`target`的数据包上的扩展区大小
+    fn extension_overhead(&self, target: SocketAddr) -> usize {
+        let entries = [
+            (self.config.timestamps, TIMESTAMP_SECTION_SIZE - EXTENSION_LENGTH_SIZE),
+            (self.config.payload_checksum, PayloadChecksum::ENTRY_SIZE),
+            (self.peer_keys.contains_key(&target), PeerKey::ID_ENTRY_SIZE),
+            (self.pending_resumption.contains_key(&target), ResumptionToken::ENTRY_SIZE),
+            (self.config.padding.is_some(), Padding::ENTRY_SIZE),
+        ];
+        let size: usize = entries.iter().filter(|(present, _)| *present).map(|(_, size)| size).sum();
+        if size == 0 {
+            0
+        } else {
+            EXTENSION_LENGTH_SIZE + size
+        }
+    }
+
+    /// Get connection status
+    pub fn connection_status(&self, addr: SocketAddr) -> ConnectionStatus {
+        self.connection_states.get(&addr)
+            .map(|state| state.status.clone())
+            .unwrap_or(ConnectionStatus::Dead)
+    }
+
+    /// Get connection statistics
+    pub fn get_stats(&self, addr: SocketAddr) -> Option<ConnectionStats> {
+        let mut stats = self.connection_stats.get(&addr).cloned()?;
+        if let Some(rtt_stats) = self.rtt_stats.get(&addr) {
+            stats.rtt_percentiles = rtt_stats.rtt_percentiles();
+            stats.jitter_percentiles = rtt_stats.jitter_percentiles();
+        }
+        stats.clock = self.clock_offset(addr);
+        Some(stats)
+    }
+
+    /// 获取所有连接的汇总统计
+    pub fn global_stats(&self) -> Result<GlobalStats, RudpError> {
+        let mut global = GlobalStats::new(self.buffer_pool.stats()?);
+        for stats in self.connection_stats.values() {
+            global.accumulate(stats);
+        }
+        global.active_connections = self.connection_states.len();
+        global.pending_packets = self.send_buffer.values().map(|packets| packets.len()).sum();
+        global.invalid_packets = self.invalid_sources.totals();
+        Ok(global)
+    }
+
+    /// 获取来自`addr`的无效包计数，未收到过或已不再跟踪时返回`None`
+    ///
+    /// 最多跟踪`max_peers`个来源，超出时最久没有发送无效包的来源被遗忘
+    pub fn invalid_packet_stats(&self, addr: SocketAddr) -> Option<InvalidPacketStats> {
+        self.invalid_sources.stats(addr)
+    }
+
+    /// 遍历所有被跟踪来源的无效包计数
+    pub fn iter_invalid_packet_stats(&self) -> impl Iterator<Item = (SocketAddr, &InvalidPacketStats)> {
+        self.invalid_sources.iter()
+    }
+
+    /// 遍历所有连接的统计信息
+    pub fn iter_stats(&self) -> impl Iterator<Item = (SocketAddr, &ConnectionStats)> {
+        self.connection_stats.iter().map(|(addr, stats)| (*addr, stats))
+    }
+
+    /// 遍历当前所有连接：地址、连接状态和统计信息（含RTT与抖动分位数）
+    ///
+    /// 与`global_stats()`中`active_connections`计数的连接相同：`connect()`、发送过数据或
+    /// 有心跳往来的对端；连接被关闭或被驱逐后不再出现。统计信息是调用时的快照，顺序不固定
+    pub fn connections(&self) -> impl Iterator<Item = (SocketAddr, ConnectionStatus, ConnectionStats)> + '_ {
+        self.connection_states.iter().map(|(addr, state)| {
+            (*addr, state.status.clone(), self.get_stats(*addr).unwrap_or_default())
+        })
+    }
+
+    /// 获取连接的拥塞控制状态
+    ///
+    /// 包括拥塞窗口、慢启动阈值、飞行中包数量、拥塞控制阶段、当前RTO和平滑RTT，
+    /// 应用可以据此调整发送速率。没有与`addr`的连接时返回`None`
+    pub fn congestion_info(&self, addr: SocketAddr) -> Option<CongestionInfo> {
+        self.rtt_stats.get(&addr).map(|stats| CongestionInfo {
+            congestion_window: stats.cwnd,
+            slow_start_threshold: stats.ssthresh,
+            in_flight_packets: stats.in_flight,
+            available_window: stats.available_window(),
+            congestion_state: stats.congestion_state.clone(),
+            current_rto: stats.rto,
+            smoothed_rtt: stats.srtt,
+            rtt_variance: stats.rttvar,
+        })
+    }
+
+    /// 与[`congestion_info`](Self::congestion_info)相同
+    pub fn get_congestion_info(&self, addr: SocketAddr) -> Option<CongestionInfo> {
+        self.congestion_info(addr)
+    }
+
+    /// 获取连接的完整RTT统计，包括RTT和抖动分布
+    pub fn rtt_stats(&self, addr: SocketAddr) -> Option<&RttStats> {
+        self.rtt_stats.get(&addr)
+    }
+
+    /// 发往`addr`的单向时延，由对端ACK回显的发送时间戳测得
+    ///
+    /// 需要开启`timestamps`，尚未收到回显时返回`None`
+    pub fn outgoing_delay(&self, addr: SocketAddr) -> Option<OneWayDelay> {
+        self.outgoing_delay.get(&addr).and_then(DelayEstimator::snapshot)
+    }
+
+    /// 从`addr`收到数据的单向时延，由对端数据包的发送时间戳测得
+    ///
+    /// 需要对端开启`timestamps`，尚未收到带时间戳的数据包时返回`None`
+    pub fn incoming_delay(&self, addr: SocketAddr) -> Option<OneWayDelay> {
+        self.incoming_delay.get(&addr).and_then(DelayEstimator::snapshot)
+    }
+
+    /// `addr`的时钟相对本端时钟的偏差，以及ping两个方向的单向时延
+    ///
+    /// 由ping应答带回的对端应答时间测得，两端时钟不同步也适用；尚未收到带应答时间的
+    /// ping应答时返回`None`
+    pub fn clock_offset(&self, addr: SocketAddr) -> Option<ClockOffset> {
+        self.clock_offsets.get(&addr).and_then(ClockEstimator::snapshot)
+    }
+
+    /// 把实例时钟的`now`对应到墙上时间`wall_clock`
+    ///
+    /// ping时间戳和ping应答的应答时间都由实例时钟换算成墙上时间。不调用时，第一次需要时
+    /// 把当时的`now`对应到系统时间；虚拟时钟驱动的实例可以用它得到与真实时间无关的结果
+    pub fn set_wall_clock(&mut self, now: Instant, wall_clock: SystemTime) {
+        self.wall_clock = Some((now, wall_clock));
+    }
+
+    // Private helper methods
+
+    /// 获取下一个序列号
+    ///
+    /// 为每个目标地址维护独立的序列号计数器
+    /// 序列号从0开始，每次调用递增1
+    ///
+    /// 序列号溢出处理：
+    /// - 使用 wrapping_add 安全处理 u32::MAX + 1 = 0 的情况
+    /// - 序列号是连续的：65534, 65535, 0, 1... 不需要特殊处理
+    fn get_next_seq(&mut self, addr: SocketAddr) -> u32 {
+        // 获取或创建该地址的序列号计数器，初始值为0
+        let seq = self.next_seq.entry(addr).or_insert(0);
+        let current = *seq;  // 保存当前值，这是要返回的序列号
+        *seq = seq.wrapping_add(1);  // 安全递增，处理溢出：u32::MAX + 1 = 0
+
+        current  // 返回使用的序列号
+    }
+
+    /// 处理接收到的包
+    ///
+    /// 内部处理所有控制包（ACK、NACK、PING等），只有Data包和不可靠数据报会返回给上层，
+    /// 其数据拷贝到内存池buffer中
+    fn handle_received_packet(&mut self, packet_data: &[u8], from: SocketAddr, now: Instant) -> Result<Option<ReceivedData>, RudpError> {
+        let Some(packet) = self.accept_packet(packet_data, from, now)? else {
+            return Ok(None);
+        };
+        if packet.packet_type == PacketType::Compressed {
+            return self.handle_compressed_packet(packet, from, now);
+        }
+        if packet.packet_type == PacketType::Fragment {
+            self.handle_fragment_packet(packet, from, now);
+            return Ok(None);
+        }
+        if packet.packet_type == PacketType::Bundle {
+            self.accept_bundle(packet.seq, packet.data, from, now)?;
+            return Ok(None);
+        }
+        let reliable = packet.packet_type == PacketType::Data;
+        if !reliable && packet.packet_type != PacketType::Datagram {
+            self.handle_control_packet(packet, from, now);
+            return Ok(None);
+        }
+        let has_room = self.recv_queue_has_room();
+        if reliable {
+            if !self.accept_data(packet.seq, packet.data.len(), has_room, from, now) {
+                return Ok(None);
+            }
+        } else if !has_room {
+            trace_event!(debug, %from, "receive queue full, dropping datagram");
+            return Ok(None);
+        }
+
+        // 从内存池获取buffer并拷贝数据
+        let mut buffer = self.buffer_pool.get_write_buffer()?;
+        if packet.data.len() > buffer.data_mut().len() {
+            return Err(RudpError::BufferTooLarge {
+                size: packet.data.len(),
+                max: buffer.data_mut().len()
+            });
+        }
+        buffer.data_mut()[..packet.data.len()].copy_from_slice(packet.data);
+        buffer.set_data_len(packet.data.len())?;
+
+        Ok(Some(ReceivedData {
+            from,
+            seq: reliable.then_some(packet.seq),
+            result: Ok(buffer),
+        }))
+    }
+
+    /// 处理已直接读入内存池buffer的包
+    ///
+    /// 协议头原地解析，Data包的buffer直接返回给上层，无需分配和拷贝
+    fn handle_received_buffer(&mut self, mut buffer: PooledBuffer, len: usize, from: SocketAddr, now: Instant) -> Result<Option<ReceivedData>, RudpError> {
+        let (seq, data_start, data_len) = {
+            let Some(packet) = self.accept_packet(&buffer.raw_buffer()[..len], from, now)? else {
+                return Ok(None);
+            };
+            if packet.packet_type == PacketType::Compressed {
+                return self.handle_compressed_packet(packet, from, now);
+            }
+            if packet.packet_type == PacketType::Fragment {
+                self.handle_fragment_packet(packet, from, now);
+                return Ok(None);
+            }
+            if packet.packet_type == PacketType::Bundle {
+                self.accept_bundle(packet.seq, packet.data, from, now)?;
+                return Ok(None);
+            }
+            let reliable = packet.packet_type == PacketType::Data;
+            if !reliable && packet.packet_type != PacketType::Datagram {
+                self.handle_control_packet(packet, from, now);
+                return Ok(None);
+            }
+            // 去掉填充后载荷不一定在末尾，按地址计算起点
+            let data_start = packet.data.as_ptr() as usize - buffer.raw_buffer().as_ptr() as usize;
+            (reliable.then_some(packet.seq), data_start, packet.data.len())
+        };
+        let has_room = self.recv_queue_has_room();
+        match seq {
+            Some(seq) if !self.accept_data(seq, data_len, has_room, from, now) => return Ok(None),
+            None if !has_room => {
+                trace_event!(debug, %from, "receive queue full, dropping datagram");
+                return Ok(None);
+            }
+            _ => {}
+        }
+        if data_start != PROTOCOL_HEADER_SIZE {
+            // 扩展区之后的数据移到协议头之后
+            buffer.raw_buffer_mut().copy_within(data_start..data_start + data_len, PROTOCOL_HEADER_SIZE);
+        }
+        buffer.set_data_len(data_len)?;
+
+        Ok(Some(ReceivedData {
+            from,
+            seq,
+            result: Ok(buffer),
+        }))
+    }
+
+    /// 解析并校验收到的包，记录日志并更新连接活跃时间；需要先验证身份的对端的包返回`None`
+    fn accept_packet<'a>(&mut self, packet_data: &'a [u8], from: SocketAddr, now: Instant) -> Result<Option<RawPacketRef<'a>>, RudpError> {
+        let packet = match RawPacketRef::parse(packet_data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                let unknown_type = packet_data.len() >= PROTOCOL_HEADER_SIZE && PacketType::from_u8(packet_data[0] & !EXTENSION_FLAG).is_none();
+                self.record_invalid(from, if unknown_type { InvalidKind::UnknownType } else { InvalidKind::Parse }, now);
+                return Err(e);
+            }
+        };
+
+        // Verify security code
+        if self.config.security.verify
+            && !self.verify_security_code(&packet, &packet_data[PROTOCOL_HEADER_SIZE..], from, now)
+        {
+            trace_event!(warn, %from, packet_type = ?packet.packet_type, seq = packet.seq, "security code verification failed");
+            self.record_invalid(from, InvalidKind::Security, now);
+            if let Some(handler) = &self.event_handler {
+                handler.on_auth_failure(from);
+            }
+            return Err(RudpError::Security);
+        }
+        let Some(packet) = Padding::strip(packet) else {
+            trace_event!(debug, %from, seq = packet.seq, "padding longer than the packet");
+            self.record_invalid(from, InvalidKind::Parse, now);
+            return Err(RudpError::Protocol {
+                message: "padding longer than the packet".to_string(),
+            });
+        };
+        if !PayloadChecksum::verify(&packet) {
+            trace_event!(warn, %from, packet_type = ?packet.packet_type, seq = packet.seq, "payload checksum mismatch");
+            self.record_invalid(from, InvalidKind::Checksum, now);
+            return Err(RudpError::Checksum);
+        }
+        #[cfg(feature = "identity")]
+        match self.identities.check(&packet, from, SystemTime::now()) {
+            Check::Accept => {}
+            Check::Established(identity) => {
+                trace_event!(info, %from, ?identity, "peer identity verified");
+                self.notify(from, Some(PeerId::Identity(identity)), ConnectionEvent::Established { identity });
+            }
+            Check::Invalid => {
+                trace_event!(warn, %from, packet_type = ?packet.packet_type, "peer identity rejected");
+                self.record_invalid(from, InvalidKind::Security, now);
+                if let Some(handler) = &self.event_handler {
+                    handler.on_auth_failure(from);
+                }
+                return Err(RudpError::Security);
+            }
+            Check::Unverified => {
+                trace_event!(debug, %from, packet_type = ?packet.packet_type, seq = packet.seq, "dropping packet from unidentified peer");
+                return Ok(None);
+            }
+        }
+
+        trace_event!(trace, %from, packet_type = ?packet.packet_type, seq = packet.seq, len = packet.data.len(), "packet received");
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.log(from, QlogEvent::PacketReceived {
+                packet_type: packet.packet_type,
+                seq: packet.seq,
+                length: packet_data.len(),
+            });
+        }
+
+        // 对端换了地址：先把状态移到新地址，再按新地址更新活跃时间
+        if matches!(packet.packet_type, PacketType::Ping | PacketType::PingAck) {
+            if let Some(id) = ConnectionId::find(&packet) {
+                self.follow_connection_id(id, from, now);
+            }
+        }
+
+        // 连接刚关闭：上一个会话迟到的包回复Close，不建立状态；ping开始新的会话
+        if !self.peer_activity.contains(from) && self.tombstones.get(&from).is_some_and(|deadline| now < *deadline) {
+            match packet.packet_type {
+                PacketType::Ping => {
+                    self.tombstones.remove(&from);
+                }
+                PacketType::Close | PacketType::CloseAck | PacketType::Reset | PacketType::Relay | PacketType::Batch => {}
+                PacketType::PingAck | PacketType::Datagram => return Ok(None),
+                PacketType::Data | PacketType::Fragment | PacketType::Compressed | PacketType::Bundle | PacketType::Shutdown | PacketType::DataAck | PacketType::DataNack => {
+                    trace_event!(debug, %from, packet_type = ?packet.packet_type, seq = packet.seq, "late packet of a closed connection");
+                    self.send_reply(PacketType::Close, from, packet.seq);
+                    return Ok(None);
+                }
+            }
+        }
+
+        // 对端还在使用本端没有状态的会话（例如本端重启过）：回复Reset让它立即结束会话
+        if !self.peer_activity.contains(from) && requires_session(&packet) {
+            trace_event!(debug, %from, packet_type = ?packet.packet_type, seq = packet.seq, "packet of an unknown session, sending reset");
+            self.send_reply(PacketType::Reset, from, packet.seq);
+            return Ok(None);
+        }
+
+        // Update connection activity
+        if let Some(state) = self.connection_states.get_mut(&from) {
+            if state.status != ConnectionStatus::Alive {
+                trace_event!(info, %from, from_status = ?state.status, "connection alive again");
+            }
+            state.update_activity(now);
+        }
+        // Close、CloseAck和Reset只会移除状态，不算作活跃，避免被驱逐对端的CloseAck再驱逐另一个对端；
+        // Batch中的包各自更新活跃状态
+        if !matches!(packet.packet_type, PacketType::Close | PacketType::CloseAck | PacketType::Reset | PacketType::Batch) {
+            self.dead_peers.remove(from);
+            self.touch_peer(from);
+            telemetry!(self.telemetry, packet_received(from, now));
+            self.end_loss_episode(from, now);
+        }
+
+        if matches!(packet.packet_type, PacketType::Data | PacketType::Fragment | PacketType::Compressed | PacketType::Bundle) {
+            // 记录数据包的发送时间戳，在下一个ACK中回显
+            if let Some(Timestamp::Sent(sent)) = Timestamp::find(&packet) {
+                let received = self.timestamp(now);
+                self.incoming_delay.entry(from).or_default().record(sent, received);
+                self.timestamp_echoes.insert(from, Timestamp::Echo { sent, received });
+            }
+            if let Some(token) = ResumptionToken::find(&packet) {
+                self.accept_resumption(&token, from, now);
+            }
+        }
+
+        Ok(Some(packet))
+    }
+
+    /// 确认消息分片并加入重组，消息完整后放入消息队列
+    fn handle_fragment_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        let Some(header) = FragmentHeader::deserialize(packet.data) else {
+            trace_event!(debug, %from, seq = packet.seq, "malformed fragment");
+            self.record_invalid(from, InvalidKind::Parse, now);
+            return;
+        };
+        let chunk = &packet.data[FRAGMENT_HEADER_SIZE..];
+        let cost = self.reassembler.insert_cost(from, header, chunk.len());
+        let has_room = self.message_queue.len() < self.config.limits.max_queued_messages
+            && self.reassembler.pending_bytes() + cost <= self.config.limits.max_reassembly_bytes;
+        if !self.accept_data(packet.seq, packet.data.len(), has_room, from, now) {
+            return;
+        }
+        if let Some(data) = self.reassembler.insert(from, header, chunk, self.config.max_message_size, now) {
+            self.message_queue.push_back(ReceivedMessage { from, data });
+        }
+    }
+
+    /// 确认合并包，把其中的每个载荷作为单独的数据放入接收队列，它们带有相同的序列号
+    fn accept_bundle(&mut self, seq: u32, data: &[u8], from: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        let mut count = 0;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let Some((_, tail)) = split_batch_frame(rest) else {
+                trace_event!(debug, %from, seq, "malformed bundle");
+                self.record_invalid(from, InvalidKind::Parse, now);
+                return Ok(());
+            };
+            count += 1;
+            rest = tail;
+        }
+        let has_room = self.recv_queue.len() + count <= self.config.limits.max_queued_packets;
+        if !self.accept_data(seq, data.len(), has_room, from, now) {
+            return Ok(());
+        }
+        let mut rest = data;
+        while let Some((frame, tail)) = split_batch_frame(rest) {
+            let mut buffer = self.buffer_pool.get_buffer_for(frame.len())?;
+            buffer.data_mut()[..frame.len()].copy_from_slice(frame);
+            buffer.set_data_len(frame.len())?;
+            self.recv_queue.push_back(ReceivedData { from, seq: Some(seq), result: Ok(buffer) });
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// 解压载荷后按原包类型（Data、Fragment或Bundle）处理
+    ///
+    /// 只要算法已编译进来就接受压缩包，运行时关闭压缩时对端仍在途的压缩包不会丢失。
+    /// 解压失败的包不确认，由对端重传
+    fn handle_compressed_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) -> Result<Option<ReceivedData>, RudpError> {
+        let header = packet.data.get(..COMPRESSION_HEADER_SIZE);
+        let algorithm = header.and_then(|header| Compression::from_id(header[0]));
+        let packet_type = header.and_then(|header| PacketType::from_u8(header[1]));
+        let (Some(algorithm), Some(packet_type @ (PacketType::Data | PacketType::Fragment | PacketType::Bundle))) = (algorithm, packet_type) else {
+            trace_event!(debug, %from, seq = packet.seq, "malformed compressed packet");
+            self.record_invalid(from, InvalidKind::Parse, now);
+            return Ok(None);
+        };
+
+        let mut buffer = self.buffer_pool.get_write_buffer()?;
+        let Some(len) = compression::decompress(algorithm, &packet.data[COMPRESSION_HEADER_SIZE..], buffer.data_mut()) else {
+            trace_event!(debug, %from, seq = packet.seq, ?algorithm, "failed to decompress packet");
+            self.record_invalid(from, InvalidKind::Parse, now);
+            return Ok(None);
+        };
+        buffer.set_data_len(len)?;
+
+        if packet_type == PacketType::Fragment {
+            let fragment = RawPacketRef { packet_type, data: buffer.data(), ..packet };
+            self.handle_fragment_packet(fragment, from, now);
+            return Ok(None);
+        }
+        if packet_type == PacketType::Bundle {
+            self.accept_bundle(packet.seq, buffer.data(), from, now)?;
+            return Ok(None);
+        }
+        let has_room = self.recv_queue_has_room();
+        if !self.accept_data(packet.seq, len, has_room, from, now) {
+            return Ok(None);
+        }
+        Ok(Some(ReceivedData {
+            from,
+            seq: Some(packet.seq),
+            result: Ok(buffer),
+        }))
+    }
+
+    /// 记录来自`from`的无效包，超过阈值的来源在一个统计周期内被忽略
+    fn record_invalid(&mut self, from: SocketAddr, kind: InvalidKind, now: Instant) {
+        if self.invalid_sources.record(from, kind, &self.config.limits, now) {
+            trace_event!(warn, %from, window_ms = self.config.limits.invalid_packet_window.as_millis() as u64, "too many invalid packets, ignoring source");
+        }
+    }
+
+    /// 控制包在库内部处理，不暴露给上层
+    fn handle_control_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        match packet.packet_type {
+            PacketType::Data | PacketType::Fragment | PacketType::Compressed | PacketType::Bundle | PacketType::Datagram | PacketType::Relay => {}
+            PacketType::DataAck => self.handle_data_ack_packet(packet, from, now),
+            PacketType::DataNack => self.handle_data_nack_packet(packet, from, now),
+            PacketType::Ping => self.handle_ping_packet(packet, from, now),
+            PacketType::PingAck => self.handle_ping_ack_packet(packet, from, now),
+            PacketType::Close => self.handle_close_packet(packet, from, now),
+            PacketType::CloseAck => self.handle_close_ack_packet(packet, from, now),
+            PacketType::Reset => self.handle_reset_packet(packet, from),
+            PacketType::Shutdown => self.handle_shutdown_packet(packet, from, now),
+            PacketType::Batch => self.handle_batch_packet(packet, from, now),
+        }
+    }
+
+    /// 接收队列是否还能放入数据
+    fn recv_queue_has_room(&self) -> bool {
+        self.recv_queue.len() < self.config.limits.max_queued_packets
+    }
+
+    /// 处理数据包的确认和去重
+    ///
+    /// 返回false表示重复包或`has_room`为false时被拒收的包，不再交给上层。
+    /// 被拒收的包不确认也不记入接收窗口，由对端稍后重传
+    /// 对端出示了有效的恢复令牌：丢弃上一次会话的接收状态，这个数据包开始新的会话
+    fn accept_resumption(&mut self, token: &ResumptionToken, from: SocketAddr, now: Instant) {
+        let Some(resumption) = &self.config.resumption else {
+            return;
+        };
+        let wall_clock = SystemTime::now();
+        if !token.verify(&resumption.key, from, resumption.token_lifetime, wall_clock) {
+            trace_event!(debug, %from, "invalid resumption token ignored");
+            return;
+        }
+        // 令牌只能使用一次，带令牌的重传包照常去重
+        if !self.used_tokens.insert(token, resumption.token_lifetime, wall_clock) {
+            return;
+        }
+        trace_event!(info, %from, "session resumed");
+        self.recv_windows.remove(&from);
+        self.reassembler.remove_peer(from);
+        let rtt_stats = self.rtt_stats.entry(from).or_insert_with(|| RttStats::with_config(&self.config));
+        if let Some(rtt) = token.rtt() {
+            rtt_stats.restore_rtt(rtt);
+        }
+        self.connection_states.entry(from).or_insert_with(|| ConnectionState::new_at(now)).update_activity(now);
+        self.notify(from, self.peer_id(from), ConnectionEvent::Resumed);
+    }
+
+    fn accept_data(&mut self, seq: u32, data_len: usize, has_room: bool, from: SocketAddr, now: Instant) -> bool {
+        let window = self.recv_windows.entry(from).or_insert_with(|| ReceiveWindow::new(seq));
+
+        if window.contains(seq) {
+            trace_event!(debug, %from, seq, "duplicate data packet");
+            // Duplicate packet, resend ACK
+            self.send_ack(from, seq);
+            return false;
+        }
+        if !has_room {
+            trace_event!(debug, %from, seq, "receive limits reached, dropping data packet");
+            return false;
+        }
+        window.insert(seq);
+
+        // New packet, process data
+        self.send_ack(from, seq);
+
+        // Update statistics
+        let stats = self.connection_stats.entry(from).or_default();
+        stats.record_packet_received(now);
+        stats.record_bytes_received(data_len);
+        true
+    }
+
+    fn handle_data_ack_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        if let Some(Timestamp::Echo { sent, received }) = Timestamp::find(&packet) {
+            self.outgoing_delay.entry(from).or_default().record(sent, received);
+        }
+        if let Some(ack_packet) = DataAckPacket::deserialize(packet.data) {
+            for ack_seq in ack_packet.ack_seqs {
+                if let Some(pending_packets) = self.send_buffer.get_mut(&from) {
+                    if let Some(mut pending_packet) = pending_packets.remove(&ack_seq) {
+                        pending_packet.settle(Ok(()));
+                        // Calculate RTT and update statistics
+                        let rtt = now.duration_since(pending_packet.send_time);
+                        // Karn's algorithm: an ACK of a retransmitted or duplicated packet may
+                        // answer any of its copies, so it gives no RTT sample
+                        let first_attempt = pending_packet.retry_count == 0;
+                        let sampled = first_attempt && pending_packet.copy_sent.is_none();
+                        let rtt_stats = self.rtt_stats.entry(from).or_insert_with(|| RttStats::with_config(&self.config));
+                        if first_attempt {
+                            self.redundancy.record(from, false);
+                        }
+                        // An ACK later than the original could have been answered most
+                        // likely answers the copy
+                        let recovered = first_attempt && pending_packet.copy_sent.is_some() && rtt > rtt_stats.srtt + self.config.redundancy.spacing / 2;
+                        if sampled {
+                            rtt_stats.update_rtt(rtt);
+                        }
+                        rtt_stats.on_ack_received(1);
+                        trace_event!(trace, %from, seq = ack_seq, rtt_us = rtt.as_micros() as u64, sampled, srtt_ms = rtt_stats.srtt.as_millis() as u64, rto_ms = rtt_stats.rto.as_millis() as u64, cwnd = rtt_stats.cwnd, "data packet acknowledged");
+                        if let Some(qlog) = self.qlog.as_mut() {
+                            qlog.log(from, QlogEvent::PacketAcked { seq: ack_seq, rtt });
+                            qlog.log_metrics(from, rtt_stats);
+                        }
+                        telemetry!(self.telemetry, acked(from, ack_seq, sampled.then_some(rtt), now));
+                        let stats = self.connection_stats.entry(from).or_default();
+                        if sampled {
+                            stats.update_rtt(rtt);
+                        }
+                        if recovered {
+                            trace_event!(debug, %from, seq = ack_seq, "redundant copy delivered");
+                            stats.record_redundant_recovery();
+                        }
+                        stats.record_bytes_acked(pending_packet.buffer.data_len(), now);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_data_nack_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        if let Some(nack_packet) = DataNackPacket::deserialize(packet.data) {
+            for nack_seq in nack_packet.nack_seqs {
+                if self.retransmit_now(from, nack_seq, now) {
+                    self.redundancy.record(from, true);
+                    self.connection_stats.entry(from).or_default().record_packet_lost();
+                    if let Some(state) = self.connection_states.get_mut(&from) {
+                        state.mark_packet_lost();
+                    }
+                    trace_event!(debug, %from, seq = nack_seq, "retransmitting on NACK");
+                }
+            }
+        }
+    }
+
+    /// 立即重传发往`addr`的未确认数据包`seq`，不存在时返回`false`
+    fn retransmit_now(&mut self, addr: SocketAddr, seq: u32, now: Instant) -> bool {
+        let timestamp = self.timestamp(now);
+        let Some(pending_packet) = self.send_buffer.get_mut(&addr).and_then(|packets| packets.get_mut(&seq)) else {
+            return false;
+        };
+        telemetry!(self.telemetry, retransmitted(addr, seq));
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.log(addr, QlogEvent::PacketSent {
+                packet_type: pending_packet.buffer.packet_type(),
+                seq,
+                length: pending_packet.buffer.len(),
+                retransmission: true,
+            });
+        }
+        pending_packet.retry_count = pending_packet.retry_count.saturating_add(1);
+        pending_packet.send_time = now;
+        let authenticator = signing_authenticator(&self.peer_keys, &self.authenticator, addr);
+        pending_packet.buffer.stamp(timestamp, authenticator);
+        self.transmits.push_back(data_transmit(&self.buffer_pool, &pending_packet.buffer, authenticator, addr, seq));
+
+        // Update statistics
+        let stats = self.connection_stats.entry(addr).or_default();
+        stats.record_retransmission();
+        stats.record_bytes_retransmitted(pending_packet.buffer.data_len(), now);
+        true
+    }
+
+    fn handle_ping_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        self.receive_heartbeat(&packet, from);
+        // Echo back the timestamp, answering the peer's compression offer with ours
+        let data = match PingPacket::deserialize(packet.data) {
+            Some(ping) => {
+                self.peer_compression.insert(from, Some(ping.compression.unwrap_or(0)));
+                PingPacket { timestamp: ping.timestamp, compression: self.local_compression() }.serialize()
+            }
+            None => packet.data.to_vec(),
+        };
+
+        // Send ping acknowledgment with our connection id, and a fresh resumption token
+        // when they are enabled
+        let mut extensions = Vec::new();
+        self.connection_id.encode(&mut extensions);
+        self.encode_heartbeat(&mut extensions);
+        ReplyTime(self.wall_time(now)).encode(&mut extensions);
+        #[cfg(feature = "identity")]
+        self.identities.announce(PacketType::PingAck, packet.seq, self.connection_id, &data, from, &mut extensions);
+        if let Some(resumption) = &self.config.resumption {
+            let rtt = self.rtt_stats.get(&from).filter(|rtt_stats| rtt_stats.last_rtt_sample.is_some()).map(|rtt_stats| rtt_stats.srtt);
+            self.token_nonce = self.token_nonce.wrapping_add(1);
+            ResumptionToken::issue(&resumption.key, from, rtt, self.token_nonce, SystemTime::now()).encode(&mut extensions);
+        }
+        let ping_ack = RawPacket {
+            packet_type: PacketType::PingAck,
+            security_code: 0,
+            seq: packet.seq,
+            extensions,
+            data,
+        };
+        self.send_raw_packet(ping_ack, from);
+    }
+
+    fn handle_ping_ack_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        self.receive_heartbeat(&packet, from);
+        if let Some(token) = ResumptionToken::find(&packet) {
+            if self.resumption_tokens.len() < self.config.limits.max_peers || self.resumption_tokens.contains_key(&from) {
+                self.resumption_tokens.insert(from, token);
+            }
+        }
+        let measured = self.pings.remove(&(from, packet.seq)).map(|ping| {
+            let rtt = now.saturating_duration_since(ping.sent);
+            ping.sender.send(Ok(rtt));
+            rtt
+        });
+
+        if let Some(ping_packet) = PingPacket::deserialize(packet.data) {
+            self.peer_compression.insert(from, Some(ping_packet.compression.unwrap_or(0)));
+
+            // The echoed timestamp is from our own clock; the peer's clock only enters
+            // through its reply time, into the offset estimate
+            let timestamp = self.wall_time(now);
+            if let Some(ReplyTime(answered)) = ReplyTime::find(&packet) {
+                self.clock_offsets.entry(from).or_default().record(ping_packet.timestamp, answered, timestamp);
+            }
+
+            // Calculate RTT; pings sent by `ping()` are timed with the instance clock
+            let rtt = measured.or_else(|| (timestamp > ping_packet.timestamp).then(|| Duration::from_nanos(timestamp - ping_packet.timestamp)));
+            if let Some(rtt) = rtt {
+                trace_event!(debug, %from, rtt_us = rtt.as_micros() as u64, "ping acknowledged");
+                let rtt_stats = self.rtt_stats.entry(from).or_insert_with(|| RttStats::with_config(&self.config));
+                rtt_stats.update_rtt(rtt);
+                rtt_stats.on_ack_received(1);
+                if let Some(qlog) = self.qlog.as_mut() {
+                    qlog.log_metrics(from, rtt_stats);
+                }
+                self.connection_stats.entry(from).or_default().update_rtt(rtt);
+                telemetry!(self.telemetry, ping_acked(from, rtt, now));
+            }
+        }
+
+        // Mark ping as received
+        if let Some(state) = self.connection_states.get_mut(&from) {
+            state.mark_ping_received(now);
+        }
+    }
+
+    fn handle_close_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        let reason = CloseReason::deserialize(packet.data).unwrap_or_default();
+        trace_event!(info, %from, code = reason.code, "connection closed by peer");
+        // Send close acknowledgment
+        self.send_reply(PacketType::CloseAck, from, packet.seq);
+
+        // Clean up connection
+        let known = self.peer_activity.contains(from);
+        let peer = self.peer_id(from);
+        if known {
+            self.close_connection(from, now);
+            self.notify(from, peer, ConnectionEvent::Closed { reason });
+        } else {
+            self.cleanup_connection(from);
+        }
+    }
+
+    fn handle_close_ack_packet(&mut self, _packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        // Clean up connection
+        if self.peer_activity.contains(from) {
+            self.close_connection(from, now);
+        } else {
+            self.cleanup_connection(from);
+        }
+    }
+
+    /// 逐个处理Batch中的包，每个包单独校验；Batch中不能再嵌套Batch
+    fn handle_batch_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        let mut frames = packet.data;
+        while !frames.is_empty() {
+            let Some((frame, rest)) = split_batch_frame(frames) else {
+                trace_event!(debug, %from, "truncated batch frame");
+                self.record_invalid(from, InvalidKind::Parse, now);
+                return;
+            };
+            frames = rest;
+            if frame.first().is_some_and(|first| first & !EXTENSION_FLAG == PacketType::Batch as u8) {
+                self.record_invalid(from, InvalidKind::Parse, now);
+                continue;
+            }
+            match self.handle_received_packet(frame, from, now) {
+                Ok(Some(received)) => self.recv_queue.push_back(received),
+                Ok(None) => {}
+                Err(e) => self.queue_error(from, None, e),
+            }
+        }
+    }
+
+    /// 确认Shutdown包，第一次收到时记录对端不再发送数据
+    fn handle_shutdown_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr, now: Instant) {
+        if !self.accept_data(packet.seq, 0, true, from, now) {
+            return;
+        }
+        trace_event!(debug, %from, "peer shut down sending");
+        self.peers_finished.insert(from);
+        self.notify(from, self.peer_id(from), ConnectionEvent::Finished);
+    }
+
+    /// 对端没有`seq`所在的会话：本端刚发过这个序列号时结束连接
+    ///
+    /// 序列号不在本端已发送的范围内的Reset来自更早的会话，忽略
+    fn handle_reset_packet(&mut self, packet: RawPacketRef<'_>, from: SocketAddr) {
+        let sent = self.next_seq.get(&from).is_some_and(|next| next.wrapping_sub(packet.seq).wrapping_sub(1) < u32::MAX / 2);
+        if !sent {
+            trace_event!(debug, %from, seq = packet.seq, "stale reset ignored");
+            return;
+        }
+        self.reset_connection(from);
+    }
+
+    /// 回复一个不带数据的控制包，使用触发它的包的序列号；本端不为此建立任何状态
+    fn send_reply(&mut self, packet_type: PacketType, target: SocketAddr, seq: u32) {
+        let reply = RawPacket {
+            packet_type,
+            security_code: 0,
+            seq,
+            extensions: Vec::new(),
+            data: vec![],
+        };
+        self.send_raw_packet(reply, target);
+    }
+
+    fn send_ack(&mut self, target: SocketAddr, seq: u32) {
+        self.pending_acks.entry(target).or_default().push(seq);
+    }
+
+    /// 发送各对端待发的ACK
+    ///
+    /// 目标列表、序列号列表和扩展区都复用上一轮的空间，ACK包直接写入内存池的buffer，
+    /// 池中有空闲buffer时整个过程不分配内存
+    fn send_pending_acks(&mut self) {
+        let mut targets = std::mem::take(&mut self.ack_targets);
+        targets.extend(self.pending_acks.iter().filter(|(_, seqs)| !seqs.is_empty()).map(|(target, _)| *target));
+        let mut extensions = std::mem::take(&mut self.ack_extensions);
+
+        for target in targets.drain(..) {
+            let Some(mut ack_seqs) = self.pending_acks.get_mut(&target).map(std::mem::take) else {
+                continue;
+            };
+            // 每个ACK包最多携带255个序列号
+            for chunk in ack_seqs.chunks(u8::MAX as usize) {
+                let seq = self.get_next_seq(target);
+                // 与`RawPacket::body()`相同，有扩展时包体以带长度前缀的扩展区开头
+                extensions.clear();
+                extensions.extend_from_slice(&[0; EXTENSION_LENGTH_SIZE]);
+                if let Some(echo) = self.timestamp_echoes.remove(&target) {
+                    echo.encode(&mut extensions);
+                }
+                if let Some(keys) = self.peer_keys.get(&target) {
+                    keys.current().encode_id(&mut extensions);
+                }
+                let extensions_len = extensions.len() - EXTENSION_LENGTH_SIZE;
+                let mut packet_type = PacketType::DataAck as u8;
+                if extensions_len == 0 {
+                    extensions.clear();
+                } else {
+                    extensions[..EXTENSION_LENGTH_SIZE].copy_from_slice(&(extensions_len as u16).to_be_bytes());
+                    packet_type |= EXTENSION_FLAG;
+                }
+
+                let body_len = extensions.len() + 1 + chunk.len() * 4;
+                let Some(mut buffer) = self.control_buffer(body_len) else {
+                    continue;
+                };
+                let body = buffer.data_mut();
+                body[..extensions.len()].copy_from_slice(&extensions);
+                DataAckPacket::write_into(chunk, &mut body[extensions.len()..]);
+                if buffer.set_data_len(body_len).is_err() {
+                    continue;
+                }
+                let header = buffer.header_mut();
+                header[0] = packet_type;
+                header[5..PROTOCOL_HEADER_SIZE].copy_from_slice(&seq.to_be_bytes());
+                self.write_security_code(&mut buffer, PacketType::DataAck, seq, target);
+                self.queue_control(buffer, PacketType::DataAck, seq, target);
+            }
+            // 放回清空的列表，下一轮接着用它的空间
+            ack_seqs.clear();
+            self.pending_acks.insert(target, ack_seqs);
+        }
+
+        self.ack_targets = targets;
+        self.ack_extensions = extensions;
+    }
+
+    fn send_close_packet(&mut self, target: SocketAddr, reason: &CloseReason) {
+        let seq = self.get_next_seq(target);
+        let packet = RawPacket {
+            packet_type: PacketType::Close,
+            security_code: 0,
+            seq,
+            extensions: Vec::new(),
+            data: reason.serialize(),
+        };
+        self.send_raw_packet(packet, target);
+    }
+
+    /// 把控制包序列化到内存池的buffer中并计算安全码；`target`设置了密钥时先写入密钥ID
+    ///
+    /// 安全码直接按buffer中的包体计算，不再单独构造包体
+    fn sign(&self, packet: &mut RawPacket, target: SocketAddr) -> Option<PooledBuffer> {
+        if let Some(keys) = self.peer_keys.get(&target) {
+            keys.current().encode_id(&mut packet.extensions);
+        }
+        let len = packet.serialized_len();
+        let mut buffer = self.control_buffer(len - PROTOCOL_HEADER_SIZE)?;
+        packet.serialize_into(buffer.raw_buffer_mut())?;
+        buffer.set_data_len(len - PROTOCOL_HEADER_SIZE).ok()?;
+        self.write_security_code(&mut buffer, packet.packet_type, packet.seq, target);
+        Some(buffer)
+    }
+
+    /// 从内存池取一个能容纳`body_len`字节包体的buffer；包体超过最大等级时丢弃这个控制包
+    fn control_buffer(&self, body_len: usize) -> Option<PooledBuffer> {
+        match self.buffer_pool.get_buffer_for(body_len) {
+            Ok(buffer) => Some(buffer),
+            Err(_e) => {
+                trace_event!(warn, error = %_e, "control packet dropped");
+                None
+            }
+        }
+    }
+
+    /// 按buffer中的包体计算安全码，写入已填好类型和序列号的协议头
+    fn write_security_code(&self, buffer: &mut PooledBuffer, packet_type: PacketType, seq: u32, target: SocketAddr) {
+        let security_code = signing_authenticator(&self.peer_keys, &self.authenticator, target).code(packet_type, seq, buffer.data());
+        buffer.header_mut()[1..5].copy_from_slice(&security_code.to_be_bytes());
+    }
+
+    /// 用`from`的密钥（没有时用实例的认证器）校验安全码
+    ///
+    /// 从新地址到达、带已知连接ID的ping按原地址的密钥校验，设置了密钥的对端也能迁移
+    fn verify_security_code(&self, packet: &RawPacketRef<'_>, body: &[u8], from: SocketAddr, now: Instant) -> bool {
+        let keys = self.peer_keys.get(&from).or_else(|| {
+            if !matches!(packet.packet_type, PacketType::Ping | PacketType::PingAck) {
+                return None;
+            }
+            let id = ConnectionId::find(packet)?;
+            self.peer_keys.get(&self.peer_connection_ids.addr(id)?)
+        });
+        match keys {
+            Some(keys) => keys.verify(packet, body, now),
+            None => self.authenticator.verify(packet.packet_type, packet.seq, body, packet.security_code),
+        }
+    }
+
+    /// 签名并序列化控制包，加入发送队列
+    fn send_raw_packet(&mut self, mut packet: RawPacket, target: SocketAddr) {
+        if let Some(buffer) = self.sign(&mut packet, target) {
+            self.queue_control(buffer, packet.packet_type, packet.seq, target);
+        }
+    }
+
+    /// 把序列化好的控制包加入发送队列
+    fn queue_control(&mut self, buffer: PooledBuffer, packet_type: PacketType, seq: u32, target: SocketAddr) {
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.log(target, QlogEvent::PacketSent {
+                packet_type,
+                seq,
+                length: buffer.full_data().len(),
+                retransmission: false,
+            });
+        }
+
+        self.transmits.push_back(QueuedTransmit::Control(buffer, target));
+    }
+
+    fn handle_retransmissions(&mut self, now: Instant) {
+        let timestamp = self.timestamp(now);
+        let mut to_remove = Vec::new();
+
+        for (addr, packets) in &mut self.send_buffer {
+            let mut addr_to_remove = Vec::new();
+
+            let max_retries = self.peer_keepalive.get(addr).unwrap_or(&self.config.keepalive).max_retries;
+            let backoff = self.peer_backoff.get(addr).unwrap_or(&self.config.backoff);
+            let max_rto = backoff.max_rto.unwrap_or(self.config.max_rto);
+
+            // Handle due packets in the order they were first sent, so retransmissions
+            // go out and back off the RTO the same way on every run
+            let mut due: Vec<u32> = packets
+                .iter()
+                .filter(|(_, pending_packet)| pending_packet.deadline.is_some_and(|deadline| now >= deadline) || pending_packet.should_retry(now))
+                .map(|(seq, _)| *seq)
+                .collect();
+            due.sort_by_key(|seq| (packets[seq].first_sent, *seq));
+            for seq in &due {
+                let Some(pending_packet) = packets.get_mut(seq) else {
+                    continue;
+                };
+                if pending_packet.deadline.is_some_and(|deadline| now >= deadline) {
+                    // Deadline passed, mark for removal
+                    trace_event!(debug, %addr, seq = *seq, retries = pending_packet.retry_count, "delivery deadline passed");
+                    addr_to_remove.push((*seq, false));
+                } else if pending_packet.should_retry(now) {
+                    let exhausted = match backoff.retry_deadline {
+                        Some(limit) => now.saturating_duration_since(pending_packet.first_sent) >= limit,
+                        None => pending_packet.retry_count >= max_retries,
+                    };
+                    if exhausted {
+                        // Max retries reached, mark for removal
+                        trace_event!(warn, %addr, seq = *seq, retries = pending_packet.retry_count, "delivery failed after max retries");
+                        addr_to_remove.push((*seq, true));
+                    } else {
+                        // Retry with exponential backoff
+                        let new_rto = Duration::try_from_secs_f64(pending_packet.rto.as_secs_f64() * backoff.multiplier)
+                            .map_or(max_rto, |rto| rto.min(max_rto));
+                        self.loss_episodes.timed_out(*addr, *seq, pending_packet.send_time);
+                        self.redundancy.record(*addr, true);
+                        pending_packet.retry(new_rto, now);
+                        let authenticator = signing_authenticator(&self.peer_keys, &self.authenticator, *addr);
+                        pending_packet.buffer.stamp(timestamp, authenticator);
+                        trace_event!(debug, %addr, seq = *seq, retry = pending_packet.retry_count, rto_ms = new_rto.as_millis() as u64, "retransmitting on timeout");
+                        self.transmits.push_back(data_transmit(&self.buffer_pool, &pending_packet.buffer, authenticator, *addr, *seq));
+
+                        // Update statistics
+                        let stats = self.connection_stats.entry(*addr).or_default();
+                        stats.record_packet_lost();
+                        stats.record_retransmission();
+                        stats.record_bytes_retransmitted(pending_packet.buffer.data_len(), now);
+                        if let Some(state) = self.connection_states.get_mut(addr) {
+                            state.mark_packet_lost();
+                        }
+
+                        // Update congestion control for packet loss; new packets use the
+                        // backed-off RTO until a fresh RTT sample arrives
+                        let rtt_stats = self.rtt_stats.entry(*addr).or_insert_with(|| RttStats::with_config(&self.config));
+                        rtt_stats.on_packet_lost(now);
+                        rtt_stats.back_off_rto(new_rto);
+
+                        if let Some(qlog) = self.qlog.as_mut() {
+                            qlog.log(*addr, QlogEvent::PacketLost { seq: *seq });
+                            qlog.log(*addr, QlogEvent::PacketSent {
+                                packet_type: pending_packet.buffer.packet_type(),
+                                seq: *seq,
+                                length: pending_packet.buffer.len(),
+                                retransmission: true,
+                            });
+                            qlog.log_metrics(*addr, rtt_stats);
+                        }
+                        telemetry!(self.telemetry, retransmitted(*addr, *seq));
+                    }
+                }
+            }
+
+            // Remove failed packets
+            for (seq, retries_exhausted) in addr_to_remove {
+                let error = || -> RudpError {
+                    if retries_exhausted {
+                        ConnectionError::MaxRetriesExceeded { addr: *addr }.into()
+                    } else {
+                        RudpError::Timeout
+                    }
+                };
+                if let Some(mut pending_packet) = packets.remove(&seq) {
+                    pending_packet.settle(Err(error()));
+                }
+                *self.failed_deliveries.entry(*addr).or_default() += 1;
+                if let Some(rtt_stats) = self.rtt_stats.get_mut(addr) {
+                    rtt_stats.on_packet_dropped();
+                }
+                if let Some(qlog) = self.qlog.as_mut() {
+                    qlog.log(*addr, QlogEvent::PacketDropped { seq });
+                }
+                telemetry!(self.telemetry, dropped(*addr, seq, if retries_exhausted { "retries exhausted" } else { "deadline exceeded" }, now));
+                if let Some(handler) = &self.event_handler {
+                    handler.on_delivery_failed(*addr, seq);
+                }
+                // 重传耗尽时也通过接收队列通知发送方，超过截止时间只通知send_with_deadline()的调用者
+                if retries_exhausted && self.recv_queue.len() < self.config.limits.max_queued_packets {
+                    self.recv_queue.push_back(ReceivedData { from: *addr, seq: Some(seq), result: Err(error()) });
+                }
+            }
+
+            // If no packets left for this address, mark for removal
+            if packets.is_empty() {
+                to_remove.push(*addr);
+            }
+        }
+
+        // Remove empty entries
+        for addr in to_remove {
+            self.send_buffer.remove(&addr);
+        }
+    }
+
+    /// 超过`ping_interval`未收到应答的ping完成为超时
+    fn expire_pings(&mut self, now: Instant) {
+        let expired: Vec<(SocketAddr, u32)> = self.pings.iter().filter(|(_, ping)| now >= ping.deadline).map(|(key, _)| *key).collect();
+        for (addr, seq) in expired {
+            if let Some(ping) = self.pings.remove(&(addr, seq)) {
+                trace_event!(debug, %addr, seq, "ping timed out");
+                ping.sender.send(Err(RudpError::Timeout));
+            }
+        }
+    }
+
+    fn check_connection_health(&mut self, now: Instant) {
+        let mut connections_to_ping = Vec::new();
+        let mut connections_to_close = Vec::new();
+
+        for (addr, state) in &mut self.connection_states {
+            let config = self.peer_keepalive.get(addr).unwrap_or(&self.config.keepalive);
+
+            if state.ping_timed_out(now, config) {
+                // ping超时未响应，记录失败并重新探测
+                state.mark_ping_failed(config.max_ping_failures);
+                trace_event!(warn, %addr, failures = state.consecutive_ping_failures, status = ?state.status, "ping timed out");
+                if state.should_close(config) {
+                    connections_to_close.push(*addr);
+                } else {
+                    connections_to_ping.push(*addr);
+                }
+            } else if state.should_ping(now, config) {
+                connections_to_ping.push(*addr);
+            }
+        }
+
+        // Send ping packets
+        for addr in connections_to_ping {
+            self.send_ping(addr, now);
+            if let Some(state) = self.connection_states.get_mut(&addr) {
+                state.mark_ping_sent(now);
+            }
+        }
+
+        // Close dead connections
+        for addr in connections_to_close {
+            trace_event!(warn, %addr, "connection dead, removing state");
+            let peer = self.peer_id(addr);
+            self.cleanup_connection(addr);
+            self.mark_dead(addr, peer);
+        }
+    }
+
+    /// 发送ping，附带本端接受的压缩算法和连接ID
+    fn send_ping(&mut self, addr: SocketAddr, now: Instant) -> u32 {
+        let ping_packet = PingPacket {
+            timestamp: self.wall_time(now),
+            compression: self.local_compression(),
+        };
+        let seq = self.get_next_seq(addr);
+        let data = ping_packet.serialize();
+        let mut extensions = Vec::new();
+        self.connection_id.encode(&mut extensions);
+        self.encode_heartbeat(&mut extensions);
+        #[cfg(feature = "identity")]
+        self.identities.announce(PacketType::Ping, seq, self.connection_id, &data, addr, &mut extensions);
+        let packet = RawPacket {
+            packet_type: PacketType::Ping,
+            security_code: 0,
+            seq,
+            extensions,
+            data,
+        };
+        self.send_raw_packet(packet, addr);
+        trace_event!(debug, %addr, seq, "ping sent");
+        telemetry!(self.telemetry, ping_sent(addr));
+        seq
+    }
+
+    /// 设置了心跳载荷时把它加入ping或ping应答的扩展
+    fn encode_heartbeat(&self, extensions: &mut Vec<u8>) {
+        if !self.heartbeat.is_empty() {
+            Heartbeat(&self.heartbeat).encode(extensions);
+        }
+    }
+
+    /// 记录ping或ping应答携带的对端心跳载荷，并通知事件回调
+    fn receive_heartbeat(&mut self, packet: &RawPacketRef<'_>, from: SocketAddr) {
+        let Some(Heartbeat(payload)) = Heartbeat::find(packet) else {
+            return;
+        };
+        trace_event!(trace, %from, len = payload.len(), "heartbeat payload received");
+        if let Some(handler) = &self.event_handler {
+            handler.on_heartbeat(from, payload);
+        }
+        let stored = self.peer_heartbeats.entry(from).or_default();
+        stored.clear();
+        stored.extend_from_slice(payload);
+    }
+
+    /// 本端接受的压缩算法集合，未启用压缩时为`None`
+    fn local_compression(&self) -> Option<u8> {
+        self.config.compression.as_ref().map(|compression| compression::advertised(&compression.algorithms))
+    }
+
+    /// 记住断开的对端，之后的发送返回`ConnectionError::Dead`，并通过接收队列和事件回调通知应用
+    fn mark_dead(&mut self, addr: SocketAddr, peer: Option<PeerId>) {
+        if self.dead_peers.touch(addr) {
+            while self.dead_peers.len() > self.config.limits.max_peers {
+                let Some(oldest) = self.dead_peers.least_recent() else {
+                    break;
+                };
+                self.dead_peers.remove(oldest);
+            }
+        }
+        self.queue_error(addr, None, ConnectionError::Dead { addr }.into());
+        self.notify(addr, peer, ConnectionEvent::Dead);
+    }
+
+    /// 记录对端活跃，新对端超出`max_peers`时关闭最久未活跃的对端并通知事件回调
+    fn touch_peer(&mut self, addr: SocketAddr) {
+        if self.peer_activity.touch(addr) {
+            self.tombstones.remove(&addr);
+            self.evict_excess_peers();
+        }
+    }
+
+    fn evict_excess_peers(&mut self) {
+        while self.peer_activity.len() > self.config.limits.max_peers {
+            let Some(addr) = self.peer_activity.least_recent() else {
+                break;
+            };
+            trace_event!(warn, %addr, max_peers = self.config.limits.max_peers, "peer limit reached, evicting least recently active peer");
+            self.send_close_packet(addr, &CloseReason::new(CloseReason::SERVER_FULL));
+            let peer = self.peer_id(addr);
+            self.cleanup_connection(addr);
+            self.notify(addr, peer, ConnectionEvent::Evicted);
+        }
+    }
+
+    /// 对端不再有这个会话：移除状态，未确认的包和ping以`ConnectionError::Reset`失败
+    fn reset_connection(&mut self, addr: SocketAddr) {
+        trace_event!(info, %addr, "connection reset by peer");
+        let peer = self.peer_id(addr);
+        self.remove_connection(addr, |addr| ConnectionError::Reset { addr });
+        self.queue_error(addr, None, ConnectionError::Reset { addr }.into());
+        self.notify(addr, peer, ConnectionEvent::Reset);
+    }
+
+    fn cleanup_connection(&mut self, addr: SocketAddr) {
+        self.remove_connection(addr, |addr| ConnectionError::Dead { addr });
+    }
+
+    /// 连接以Close结束：清理状态，并在2倍RTO内吸收上一个会话迟到的包
+    fn close_connection(&mut self, addr: SocketAddr, now: Instant) {
+        let rto = self.rtt_stats.get(&addr).map_or(self.config.initial_rto, |rtt_stats| rtt_stats.rto);
+        self.cleanup_connection(addr);
+        self.tombstones.retain(|_, deadline| now < *deadline);
+        if self.tombstones.len() >= self.config.limits.max_peers {
+            return;
+        }
+        self.tombstones.insert(addr, now + rto * 2);
+    }
+
+    /// 移除`addr`的连接状态，未确认的包和ping以`error`失败
+    fn remove_connection(&mut self, addr: SocketAddr, error: fn(SocketAddr) -> ConnectionError) {
+        trace_event!(debug, %addr, "connection state removed");
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.forget(addr);
+        }
+        telemetry!(self.telemetry, connection_removed(addr));
+        if let Some(mut packets) = self.send_buffer.remove(&addr).filter(|packets| !packets.is_empty()) {
+            *self.failed_deliveries.entry(addr).or_default() += packets.len() as u64;
+            for pending_packet in packets.values_mut() {
+                pending_packet.settle(Err(error(addr).into()));
+            }
+        }
+        if let Some(mut packets) = self.queued_sends.remove(&addr).filter(|packets| !packets.is_empty()) {
+            *self.failed_deliveries.entry(addr).or_default() += packets.len() as u64;
+            for (_, pending_packet) in packets.iter_mut() {
+                pending_packet.settle(Err(error(addr).into()));
+            }
+        }
+        let pings: Vec<(SocketAddr, u32)> = self.pings.keys().filter(|(target, _)| *target == addr).copied().collect();
+        for key in pings {
+            if let Some(ping) = self.pings.remove(&key) {
+                ping.sender.send(Err(error(addr).into()));
+            }
+        }
+        self.recv_windows.remove(&addr);
+        self.next_seq.remove(&addr);
+        self.rtt_stats.remove(&addr);
+        self.connection_stats.remove(&addr);
+        self.connection_states.remove(&addr);
+        self.peer_activity.remove(addr);
+        self.pending_acks.remove(&addr);
+        self.next_message_id.remove(&addr);
+        self.outgoing_fragments.remove(&addr);
+        self.bundles.remove(&addr);
+        // 待发送的数据不再发出，否则会重新建立连接
+        if self.scheduled_totals.remove(&addr).is_some() {
+            self.scheduled_sends.retain(|scheduled| scheduled.target != addr);
+        }
+        self.reassembler.remove_peer(addr);
+        self.alerts.remove(addr);
+        self.loss_episodes.remove(addr);
+        self.redundancy.remove(addr);
+        self.peer_heartbeats.remove(&addr);
+        self.peer_compression.remove(&addr);
+        self.pending_resumption.remove(&addr);
+        self.timestamp_echoes.remove(&addr);
+        self.outgoing_delay.remove(&addr);
+        self.incoming_delay.remove(&addr);
+        self.clock_offsets.remove(&addr);
+        self.peer_connection_ids.remove(addr);
+        self.send_shutdown.remove(&addr);
+        self.peers_finished.remove(&addr);
+        #[cfg(feature = "identity")]
+        self.identities.remove_peer(addr);
+    }
+
+    /// 记录`from`的连接ID；这个ID之前来自另一个地址时把该对端的状态移到`from`，
+    /// `from`之前用的是另一个ID时说明对端重启过，上一个会话的状态作废
+    fn follow_connection_id(&mut self, id: ConnectionId, from: SocketAddr, now: Instant) {
+        if self.peer_connection_ids.get(from) == Some(id) {
+            return;
+        }
+        if let Some(old) = self.peer_connection_ids.addr(id) {
+            self.migrate_peer(old, from, now);
+        } else if self.peer_connection_ids.contains(from) {
+            self.reset_connection(from);
+        }
+        self.peer_connection_ids.insert(from, id);
+    }
+
+    /// 把`old`的全部状态移到`new`，`new`已有的状态被丢弃
+    fn migrate_peer(&mut self, old: SocketAddr, new: SocketAddr, now: Instant) {
+        trace_event!(info, from = %old, to = %new, "peer migrated to a new address");
+        let peer = self.peer_id(old);
+        // 迁移之前先到达的包可能已为新地址建立了状态
+        if self.peer_activity.contains(new) {
+            self.cleanup_connection(new);
+        }
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.forget(old);
+        }
+        telemetry!(self.telemetry, migrated(old, new));
+        move_entry(&mut self.send_buffer, old, new);
+        move_entry(&mut self.recv_windows, old, new);
+        move_entry(&mut self.next_seq, old, new);
+        move_entry(&mut self.rtt_stats, old, new);
+        move_entry(&mut self.connection_stats, old, new);
+        move_entry(&mut self.connection_states, old, new);
+        move_entry(&mut self.pending_acks, old, new);
+        move_entry(&mut self.next_message_id, old, new);
+        move_entry(&mut self.outgoing_fragments, old, new);
+        move_entry(&mut self.queued_sends, old, new);
+        move_entry(&mut self.bundles, old, new);
+        move_entry(&mut self.failed_deliveries, old, new);
+        self.alerts.move_peer(old, new);
+        self.loss_episodes.move_peer(old, new);
+        self.redundancy.move_peer(old, new);
+        move_entry(&mut self.peer_compression, old, new);
+        move_entry(&mut self.peer_keepalive, old, new);
+        move_entry(&mut self.peer_keys, old, new);
+        move_entry(&mut self.peer_backoff, old, new);
+        move_entry(&mut self.peer_max_payload, old, new);
+        move_entry(&mut self.peer_heartbeats, old, new);
+        move_entry(&mut self.resumption_tokens, old, new);
+        move_entry(&mut self.pending_resumption, old, new);
+        move_entry(&mut self.timestamp_echoes, old, new);
+        move_entry(&mut self.outgoing_delay, old, new);
+        move_entry(&mut self.incoming_delay, old, new);
+        move_entry(&mut self.clock_offsets, old, new);
+        move_entry(&mut self.send_shutdown, old, new);
+        if self.peers_finished.remove(&old) {
+            self.peers_finished.insert(new);
+        }
+        self.peer_connection_ids.remove(old);
+        self.reassembler.move_peer(old, new);
+        #[cfg(feature = "identity")]
+        self.identities.move_peer(old, new);
+        let pings: Vec<(SocketAddr, u32)> = self.pings.keys().filter(|(target, _)| *target == old).copied().collect();
+        for key in pings {
+            if let Some(ping) = self.pings.remove(&key) {
+                self.pings.insert((new, key.1), ping);
+            }
+        }
+        // 还没发出的包改发到新地址
+        if self.scheduled_totals.contains_key(&old) {
+            for scheduled in self.scheduled_sends.iter_mut().filter(|scheduled| scheduled.target == old) {
+                scheduled.target = new;
+            }
+            move_entry(&mut self.scheduled_totals, old, new);
+        }
+        for transmit in self.transmits.iter_mut() {
+            match transmit {
+                QueuedTransmit::Control(_, target)
+                | QueuedTransmit::Data(target, _)
+                | QueuedTransmit::Assembled(_, target, _)
+                | QueuedTransmit::Datagram(_, target)
+                    if *target == old =>
+                {
+                    *target = new
+                }
+                _ => {}
+            }
+        }
+        self.peer_activity.remove(old);
+        self.dead_peers.remove(old);
+        self.touch_peer(new);
+        if let Some(state) = self.connection_states.get_mut(&new) {
+            state.update_activity(now);
+        }
+        self.notify(new, peer, ConnectionEvent::Migrated { from: old });
+    }
+
+    /// 向事件回调报告`addr`的事件；对端有PeerId时接着以`peer`报告一次
+    fn notify(&mut self, addr: SocketAddr, peer: Option<PeerId>, event: ConnectionEvent) {
+        telemetry!(self.telemetry, connection_event(addr, &event));
+        let Some(handler) = &self.event_handler else {
+            return;
+        };
+        match peer {
+            Some(peer) => {
+                handler.on_connection_event(addr, event.clone());
+                handler.on_peer_event(peer, addr, event);
+            }
+            None => handler.on_connection_event(addr, event),
+        }
+    }
+}
+
+/// 载荷超过[`MAX_CLOSE_PAYLOAD`]的关闭原因返回`RudpError::BufferTooLarge`
+fn check_close_reason(reason: &CloseReason) -> Result<(), RudpError> {
+    if reason.payload.len() > MAX_CLOSE_PAYLOAD {
+        return Err(RudpError::BufferTooLarge { size: reason.payload.len(), max: MAX_CLOSE_PAYLOAD });
+    }
+    Ok(())
+}
+
+/// 为`target`签名的认证器：对端的当前密钥，没有时为实例的认证器
+fn signing_authenticator<'a>(peer_keys: &'a HashMap<SocketAddr, KeyRing>, default: &'a Arc<dyn PacketAuthenticator>, target: SocketAddr) -> &'a dyn PacketAuthenticator {
+    peer_keys.get(&target).map_or(&**default, |keys| keys.current().authenticator())
+}
+
+/// 只能属于已建立会话的包：确认，以及不可能是会话开头的数据包
+///
+/// 会话的序列号从0开始，不带恢复令牌、序列号至少为`WINDOW_SIZE`的数据包前面已有
+/// 整个接收窗口的包，接收方不可能还没有它的状态
+fn requires_session(packet: &RawPacketRef<'_>) -> bool {
+    match packet.packet_type {
+        PacketType::DataAck | PacketType::DataNack => true,
+        PacketType::Data | PacketType::Fragment | PacketType::Compressed | PacketType::Bundle | PacketType::Shutdown => packet.seq >= WINDOW_SIZE && ResumptionToken::find(packet).is_none(),
+        _ => false,
+    }
+}
+
+/// 把`map`中`old`的值移到`new`
+fn move_entry<V>(map: &mut HashMap<SocketAddr, V>, old: SocketAddr, new: SocketAddr) {
+    if let Some(value) = map.remove(&old) {
+        map.insert(new, value);
+    }
+}
+
+/// 检查内存池的默认buffer能否容纳配置的最大数据长度
+fn check_pool(pool: &SharedBufferPool, config: &RudpConfig) -> Result<(), RudpError> {
+    if pool.buffer_size()? < PROTOCOL_HEADER_SIZE + config.max_payload_size {
+        return Err(RudpError::InvalidConfig {
+            message: "buffer pool buffers are too small for max_payload_size".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AlertConfig, LimitsConfig, PaddingConfig, RedundancyConfig, DEFAULT_REDUNDANCY_SPACING};
+    use crate::events::{Alert, AlertKind};
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<(SocketAddr, ConnectionEvent)>>>);
+
+    impl EventHandler for Recorder {
+        fn on_connection_event(&self, addr: SocketAddr, event: ConnectionEvent) {
+            self.0.lock().unwrap().push((addr, event));
+        }
+    }
+
+    fn addrs() -> (SocketAddr, SocketAddr) {
+        ("10.0.0.1:1".parse().unwrap(), "10.0.0.2:1".parse().unwrap())
+    }
+
+    fn payload(core: &RudpCore, data: &[u8]) -> PooledBuffer {
+        let mut buffer = core.get_buffer().unwrap();
+        buffer.data_mut()[..data.len()].copy_from_slice(data);
+        buffer.set_data_len(data.len()).unwrap();
+        buffer
+    }
+
+    /// 把`from`待发送的数据报全部交给`to`，返回数据报数量
+    fn deliver(from: &mut RudpCore, from_addr: SocketAddr, to: &mut RudpCore, now: Instant) -> usize {
+        let mut count = 0;
+        while let Some(transmit) = from.poll_transmit() {
+            to.handle_datagram(&transmit.contents, from_addr, now);
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn test_congestion_info_tracks_window_and_rtt() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        assert!(a.congestion_info(b_addr).is_none());
+
+        a.send(payload(&a, b"probe"), b_addr, now).unwrap();
+        let info = a.congestion_info(b_addr).unwrap();
+        assert_eq!(info.in_flight_packets, 1);
+        assert_eq!(info.available_window, info.congestion_window - 1);
+        assert_eq!(info.congestion_state, crate::stats::CongestionState::SlowStart);
+        let initial_rtt = info.smoothed_rtt;
+
+        let acked_at = now + Duration::from_millis(40);
+        deliver(&mut a, a_addr, &mut b, acked_at);
+        b.handle_timeout(acked_at);
+        deliver(&mut b, b_addr, &mut a, acked_at);
+        let info = a.congestion_info(b_addr).unwrap();
+        assert_eq!(info.in_flight_packets, 0);
+        // A faster sample than the initial estimate pulls the smoothed RTT down
+        assert!(info.smoothed_rtt < initial_rtt);
+        assert_eq!(a.rtt_stats(b_addr).unwrap().last_rtt_sample, Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn test_unacked_packets_and_pending_bytes() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_initial_cwnd(1);
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+        assert_eq!((a.unacked_packets(b_addr), a.pending_bytes(b_addr)), (0, 0));
+
+        a.send(payload(&a, b"12345"), b_addr, now).unwrap();
+        // Fragments beyond the congestion window wait in the queue and count as well
+        let message = vec![7u8; 3 * a.config().max_payload_size];
+        a.send_message(&message, b_addr, now).unwrap();
+        assert!(a.unacked_packets(b_addr) > 3);
+        assert!(a.pending_bytes(b_addr) > 5 + message.len());
+        assert_eq!(a.unacked_packets(a_addr), 0);
+
+        let mut now = now;
+        while a.unacked_packets(b_addr) > 0 {
+            now += Duration::from_millis(10);
+            deliver(&mut a, a_addr, &mut b, now);
+            b.handle_timeout(now);
+            deliver(&mut b, b_addr, &mut a, now);
+            a.handle_timeout(now);
+        }
+        assert_eq!(a.pending_bytes(b_addr), 0);
+    }
+
+    #[test]
+    fn test_retransmitted_packets_give_no_rtt_sample() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let config = RudpConfig::new()
+            .with_rto_bounds(Duration::from_millis(20), Duration::from_secs(2))
+            .with_initial_rto(Duration::from_millis(100));
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config.clone()).unwrap();
+
+        a.send(payload(&a, b"lost"), b_addr, start).unwrap();
+        // The first copy is lost
+        while a.poll_transmit().is_some() {}
+        let retransmit = start + Duration::from_millis(100);
+        a.handle_timeout(retransmit);
+        assert_eq!(a.rtt_stats(b_addr).unwrap().rto, Duration::from_millis(200));
+
+        // The ACK could answer either copy, so it is not an RTT sample
+        let acked = retransmit + Duration::from_millis(10);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, acked), 1);
+        b.handle_timeout(acked);
+        deliver(&mut b, b_addr, &mut a, acked);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        let rtt_stats = a.rtt_stats(b_addr).unwrap();
+        assert_eq!(rtt_stats.last_rtt_sample, None);
+        assert_eq!(rtt_stats.rto, Duration::from_millis(200));
+
+        // A packet sent once gives a fresh sample, which replaces the backed-off RTO
+        let sent = acked + Duration::from_millis(10);
+        a.send(payload(&a, b"fresh"), b_addr, sent).unwrap();
+        deliver(&mut a, a_addr, &mut b, sent);
+        let acked = sent + Duration::from_millis(30);
+        b.handle_timeout(acked);
+        deliver(&mut b, b_addr, &mut a, acked);
+        let mut expected = RttStats::with_config(&config);
+        expected.update_rtt(Duration::from_millis(30));
+        let rtt_stats = a.rtt_stats(b_addr).unwrap();
+        assert_eq!(rtt_stats.last_rtt_sample, Some(Duration::from_millis(30)));
+        assert_eq!(rtt_stats.rto, expected.rto);
+    }
+
+    #[test]
+    fn test_ping_resolves_with_round_trip_time() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let mut answered = a.ping(b_addr, start).unwrap();
+        assert!(answered.try_result().is_none());
+        let mut unanswered = a.ping(b_addr, start).unwrap();
+        assert_eq!(deliver(&mut a, a_addr, &mut b, start), 2);
+        // Only the first ping's answer makes it back
+        let pong = b.poll_transmit().unwrap();
+        let acked = start + Duration::from_millis(30);
+        a.handle_datagram(&pong.contents, b_addr, acked);
+        assert_eq!(answered.try_result().unwrap().unwrap(), Duration::from_millis(30));
+        assert!(unanswered.try_result().is_none());
+
+        let ping_interval = a.peer_keepalive_config(b_addr).ping_interval;
+        assert_eq!(a.poll_timeout(), Some(start + ping_interval));
+        a.handle_timeout(start + ping_interval);
+        assert!(matches!(unanswered.try_result(), Some(Err(RudpError::Timeout))));
+
+        // Pings still outstanding when the instance closes resolve as closed
+        let mut closed = a.ping(b_addr, start).unwrap();
+        a.close();
+        assert!(matches!(closed.try_result(), Some(Err(RudpError::Connection(ConnectionError::Closed)))));
+    }
+
+    #[test]
+    fn test_resumption_token_starts_new_session() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_resumption(Some(crate::config::ResumptionConfig::new(b"secret".to_vec())));
+        let mut server = RudpCore::new(config).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        server.set_event_handler(Recorder(Arc::clone(&events)));
+
+        // First session: the ping exchange hands out a token
+        let mut client = RudpCore::new(RudpConfig::default()).unwrap();
+        client.send(payload(&client, b"first"), b_addr, now).unwrap();
+        client.ping(b_addr, now).unwrap();
+        deliver(&mut client, a_addr, &mut server, now);
+        assert_eq!(server.poll_received().unwrap().result.unwrap().data(), b"first");
+        deliver(&mut server, b_addr, &mut client, now);
+        let token = client.resumption_token(b_addr).unwrap().clone();
+
+        // A restarted client numbers its packets from 0 again, which the server takes
+        // for a duplicate of the previous session
+        let mut restarted = RudpCore::new(RudpConfig::default()).unwrap();
+        restarted.send(payload(&restarted, b"lost"), b_addr, now).unwrap();
+        deliver(&mut restarted, a_addr, &mut server, now);
+        assert!(server.poll_received().is_none());
+
+        // With the token its first packet starts a new session and is delivered at once
+        let mut resumed = RudpCore::new(RudpConfig::default()).unwrap();
+        resumed.resume(b_addr, ResumptionToken::from_bytes(token.as_bytes()).unwrap(), now).unwrap();
+        resumed.send(payload(&resumed, b"resumed"), b_addr, now).unwrap();
+        let first = resumed.poll_transmit().unwrap();
+        server.handle_datagram(&first.contents, a_addr, now);
+        assert_eq!(server.poll_received().unwrap().result.unwrap().data(), b"resumed");
+        assert_eq!(*events.lock().unwrap(), vec![(a_addr, ConnectionEvent::Resumed)]);
+        // Only the first packet carries the token
+        resumed.send(payload(&resumed, b"next"), b_addr, now).unwrap();
+        deliver(&mut resumed, a_addr, &mut server, now);
+        assert_eq!(server.poll_received().unwrap().result.unwrap().data(), b"next");
+
+        // A replayed copy neither resets the session nor is delivered twice
+        server.handle_datagram(&first.contents, a_addr, now);
+        assert!(server.poll_received().is_none());
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_imported_state_continues_session() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        a.send(payload(&a, b"one"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"one");
+        deliver(&mut b, b_addr, &mut a, now);
+        // Lost on the way: still unacknowledged when `a` restarts
+        a.send(payload(&a, b"two"), b_addr, now).unwrap();
+        while a.poll_transmit().is_some() {}
+        // Delivered, but the acknowledgment is lost
+        b.send(payload(&b, b"reply"), a_addr, now).unwrap();
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"reply");
+        while a.poll_transmit().is_some() {}
+
+        let state = a.export_state();
+        drop(a);
+        let mut restarted = RudpCore::new(RudpConfig::default()).unwrap();
+        assert!(restarted.import_state(&state[..state.len() - 1], now).is_err());
+        assert_eq!(restarted.import_state(&state, now).unwrap(), 1);
+        assert_eq!(restarted.connection_status(b_addr), ConnectionStatus::Alive);
+
+        // The unacknowledged packet goes out again
+        deliver(&mut restarted, a_addr, &mut b, now);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"two");
+        // The peer's retransmission is recognized as a duplicate
+        let later = now + Duration::from_secs(1);
+        b.handle_timeout(later);
+        deliver(&mut b, b_addr, &mut restarted, later);
+        assert!(restarted.poll_received().is_none());
+        // New packets continue the sequence instead of starting from 0 again
+        restarted.send(payload(&restarted, b"three"), b_addr, later).unwrap();
+        deliver(&mut restarted, a_addr, &mut b, later);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"three");
+        b.handle_timeout(later);
+        deliver(&mut b, b_addr, &mut restarted, later);
+        assert_eq!(restarted.total_unacked_packets(), 0);
+    }
+
+    #[test]
+    fn test_connection_id_migrates_peer_to_new_address() {
+        let (a_addr, b_addr) = addrs();
+        let moved: SocketAddr = "10.0.0.3:7".parse().unwrap();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        b.set_event_handler(Recorder(Arc::clone(&events)));
+
+        // The ping exchange tells `b` the connection id of `a`
+        a.connect(b_addr, now).unwrap();
+        a.ping(b_addr, now).unwrap();
+        a.send(payload(&a, b"before"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"before");
+        // `b` has not acknowledged it yet when `a` moves
+        while b.poll_transmit().is_some() {}
+        b.send(payload(&b, b"pending"), a_addr, now).unwrap();
+
+        // `a` now sends from another address
+        a.migrate(now);
+        deliver(&mut a, moved, &mut b, now);
+        assert_eq!(*events.lock().unwrap(), vec![(moved, ConnectionEvent::Migrated { from: a_addr })]);
+        assert!(b.get_stats(a_addr).is_none() && b.get_stats(moved).is_some());
+        // Queued packets and later retransmissions follow the peer
+        let transmit = b.poll_transmit().unwrap();
+        assert_eq!(transmit.destination, moved);
+        a.handle_datagram(&transmit.contents, b_addr, now);
+        while let Some(transmit) = b.poll_transmit() {
+            assert_eq!(transmit.destination, moved);
+            a.handle_datagram(&transmit.contents, b_addr, now);
+        }
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"pending");
+
+        // The receive window moved too: a retransmission is not delivered twice
+        let later = now + Duration::from_secs(1);
+        a.handle_timeout(later);
+        deliver(&mut a, moved, &mut b, later);
+        assert!(b.poll_received().is_none());
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_timestamps_measure_one_way_delay() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::new().with_timestamps(true)).unwrap();
+        // The receiver echoes timestamps without enabling them itself
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        // Every packet waits 5ms longer in the network than the one before
+        for i in 0..5u32 {
+            let sent = start + Duration::from_millis(10) * i;
+            a.send(payload(&a, b"stamped"), b_addr, sent).unwrap();
+            let arrived = sent + Duration::from_millis(20 + 5 * i as u64);
+            assert_eq!(deliver(&mut a, a_addr, &mut b, arrived), 1);
+            assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"stamped");
+            b.handle_timeout(arrived);
+            deliver(&mut b, b_addr, &mut a, arrived);
+        }
+        assert_eq!(a.unacked_packets(b_addr), 0);
+
+        let incoming = b.incoming_delay(a_addr).unwrap();
+        assert_eq!(incoming.samples, 5);
+        assert_eq!(incoming.queuing_delay, Duration::from_millis(20));
+        assert!(incoming.trend > 0.0);
+        let outgoing = a.outgoing_delay(b_addr).unwrap();
+        assert_eq!(outgoing.samples, 5);
+        assert_eq!(outgoing.queuing_delay, Duration::from_millis(20));
+
+        // Packets from b carry no timestamp
+        let now = start + Duration::from_secs(1);
+        b.send(payload(&b, b"plain"), a_addr, now).unwrap();
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"plain");
+        assert!(a.incoming_delay(b_addr).is_none());
+        assert!(b.outgoing_delay(a_addr).is_none());
+    }
+
+    #[test]
+    fn test_send_to_all_shares_payload_and_queues_per_peer() {
+        let (a_addr, b_addr) = addrs();
+        let c_addr: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let start = Instant::now();
+        let config = RudpConfig::new()
+            .with_initial_cwnd(1)
+            .with_rto_bounds(Duration::from_millis(100), Duration::from_secs(60))
+            .with_initial_rto(Duration::from_millis(100))
+            .with_timestamps(true)
+            .with_payload_checksum(true)
+            .with_padding(Some(PaddingConfig::new(vec![128])));
+        let mut a = RudpCore::new(config).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut c = RudpCore::new(RudpConfig::default()).unwrap();
+
+        // c's congestion window is already full, so its copy waits in the send queue
+        a.send(payload(&a, b"first"), c_addr, start).unwrap();
+        let results = a.send_to_all(payload(&a, b"state"), &[b_addr, c_addr], start).unwrap();
+        assert_eq!(results.iter().map(|result| *result.as_ref().unwrap()).collect::<Vec<_>>(), vec![0, 1]);
+        let sent: Vec<Transmit> = std::iter::from_fn(|| a.poll_transmit()).collect();
+        assert_eq!(sent.iter().map(|transmit| (transmit.destination, transmit.contents.len())).collect::<Vec<_>>(), vec![(c_addr, 128), (b_addr, 128)]);
+
+        // The first copy to b is lost; the retransmission carries a fresh timestamp. The
+        // timeout also makes room for c's queued copy
+        let later = start + Duration::from_millis(150);
+        a.handle_timeout(later);
+        for transmit in std::iter::from_fn(|| a.poll_transmit()) {
+            assert_eq!(transmit.contents.len(), 128);
+            if transmit.destination == b_addr {
+                assert_ne!(transmit.contents, sent[1].contents);
+                b.handle_datagram(&transmit.contents, a_addr, later);
+            } else {
+                c.handle_datagram(&transmit.contents, a_addr, later);
+            }
+        }
+        let received = b.poll_received().unwrap();
+        assert_eq!((received.seq, received.result.unwrap().data()), (Some(0), &b"state"[..]));
+        assert_eq!(c.poll_received().unwrap().result.unwrap().data(), b"first");
+        let received = c.poll_received().unwrap();
+        assert_eq!((received.seq, received.result.unwrap().data()), (Some(1), &b"state"[..]));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_send_bytes_carries_extensions_and_queues() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let config = RudpConfig::new()
+            .with_initial_cwnd(1)
+            .with_timestamps(true)
+            .with_payload_checksum(true)
+            .with_padding(Some(PaddingConfig::new(vec![128])));
+        let mut a = RudpCore::new(config).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let data = Bytes::from_static(b"shared payload");
+        assert_eq!(a.send_bytes(data.clone(), b_addr, start).unwrap(), 0);
+        // The window is full, so the second packet waits like one passed to `send`
+        assert_eq!(a.send_bytes(data.clone(), b_addr, start).unwrap(), 1);
+        let transmit = a.poll_transmit().unwrap();
+        assert_eq!(transmit.contents.len(), 128);
+        assert_ne!(transmit.contents[0] & EXTENSION_FLAG, 0);
+        assert!(a.poll_transmit().is_none());
+
+        b.handle_datagram(&transmit.contents, a_addr, start);
+        let received = b.poll_received().unwrap();
+        assert_eq!((received.seq, received.result.unwrap().data()), (Some(0), &data[..]));
+        let later = start + Duration::from_millis(10);
+        b.handle_timeout(later);
+        deliver(&mut b, b_addr, &mut a, later);
+        a.handle_timeout(later);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, later), 1);
+        let received = b.poll_received().unwrap();
+        assert_eq!((received.seq, received.result.unwrap().data()), (Some(1), &data[..]));
+        assert_eq!(a.outgoing_delay(b_addr).unwrap().samples, 1);
+    }
+
+    #[test]
+    fn test_backoff_policy_per_peer() {
+        let (_, b_addr) = addrs();
+        let c_addr: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let start = Instant::now();
+        let config = RudpConfig::new()
+            .with_rto_bounds(Duration::from_millis(100), Duration::from_secs(60))
+            .with_initial_rto(Duration::from_millis(100))
+            .with_max_retries(2);
+        let mut a = RudpCore::new(config).unwrap();
+        a.set_peer_backoff_config(b_addr, BackoffConfig {
+            multiplier: 1.5,
+            max_rto: Some(Duration::from_millis(200)),
+            retry_deadline: Some(Duration::from_secs(2)),
+        });
+        assert_eq!(a.peer_backoff_config(c_addr), &BackoffConfig::default());
+
+        a.send(payload(&a, b"flaky"), b_addr, start).unwrap();
+        a.send(payload(&a, b"gone"), c_addr, start).unwrap();
+        while a.poll_transmit().is_some() {}
+
+        // Neither peer answers
+        let mut retransmissions = HashMap::new();
+        let mut now = start;
+        while now < start + Duration::from_secs(3) {
+            now += Duration::from_millis(10);
+            a.handle_timeout(now);
+            while let Some(transmit) = a.poll_transmit() {
+                *retransmissions.entry(transmit.destination).or_insert(0) += 1;
+            }
+            if now == start + Duration::from_secs(1) {
+                // The default policy gave up after two retransmissions (at 100ms and 300ms)
+                assert_eq!(a.unacked_packets(c_addr), 0);
+                assert_eq!(a.unacked_packets(b_addr), 1);
+                assert_eq!(a.rtt_stats(b_addr).unwrap().rto, Duration::from_millis(200));
+            }
+        }
+
+        // RTO 100, 150, then capped at 200ms: retransmitted until 2s had passed
+        assert_eq!(retransmissions[&c_addr], 2);
+        assert_eq!(retransmissions[&b_addr], 10);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        let failed: Vec<SocketAddr> = std::iter::from_fn(|| a.poll_received()).map(|received| received.from).collect();
+        assert_eq!(failed, [c_addr, b_addr]);
+
+        a.clear_peer_backoff_config(b_addr);
+        assert_eq!(a.peer_backoff_config(b_addr), &BackoffConfig::default());
+    }
+
+    #[test]
+    fn test_send_queues_while_window_is_full() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let limits = LimitsConfig { max_queued_sends: 2, ..LimitsConfig::default() };
+        let config = RudpConfig::new().with_initial_cwnd(1).with_limits(limits);
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+
+        let first = a.send(payload(&a, b"first"), b_addr, now).unwrap();
+        assert!(matches!(a.try_send(payload(&a, b"now"), b_addr, now), Err(RudpError::CongestionWindowFull)));
+        let second = a.send(payload(&a, b"second"), b_addr, now).unwrap();
+        let third = a.send(payload(&a, b"third"), b_addr, now).unwrap();
+        assert!(matches!(a.send(payload(&a, b"fourth"), b_addr, now), Err(RudpError::CongestionWindowFull)));
+        assert_eq!(a.unacked_packets(b_addr), 3);
+        // Only the packet inside the window goes out
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+
+        let mut now = now;
+        let mut received = Vec::new();
+        while a.unacked_packets(b_addr) > 0 {
+            now += Duration::from_millis(10);
+            b.handle_timeout(now);
+            deliver(&mut b, b_addr, &mut a, now);
+            a.handle_timeout(now);
+            deliver(&mut a, a_addr, &mut b, now);
+            while let Some(data) = b.poll_received() {
+                received.push((data.seq, data.result.unwrap().data().to_vec()));
+            }
+        }
+        assert_eq!(received, [
+            (Some(first), b"first".to_vec()),
+            (Some(second), b"second".to_vec()),
+            (Some(third), b"third".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_exchange_without_sockets() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let seq = a.send(payload(&a, b"hello"), b_addr, now).unwrap();
+        assert_eq!(a.unacked_packets(b_addr), 1);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+
+        let received = b.poll_received().unwrap();
+        assert_eq!(received.from, a_addr);
+        assert_eq!(received.seq, Some(seq));
+        assert_eq!(received.result.unwrap().data(), b"hello");
+
+        // The ACK is produced by handle_timeout and settles the packet
+        assert!(b.poll_transmit().is_none());
+        b.handle_timeout(now);
+        assert_eq!(deliver(&mut b, b_addr, &mut a, now), 1);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        assert!(a.poll_received().is_none());
+    }
+
+    #[test]
+    fn test_retransmits_at_poll_timeout() {
+        let (_, b_addr) = addrs();
+        let start = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+
+        a.send(payload(&a, b"lost"), b_addr, start).unwrap();
+        let first = a.poll_transmit().unwrap();
+        assert!(a.poll_transmit().is_none());
+
+        let timeout = a.poll_timeout().unwrap();
+        assert_eq!(timeout, start + a.config().initial_rto);
+        a.handle_timeout(timeout - Duration::from_millis(1));
+        assert!(a.poll_transmit().is_none());
+
+        a.handle_timeout(timeout);
+        let retransmitted = a.poll_transmit().unwrap();
+        assert_eq!(retransmitted, first);
+        assert_eq!(a.get_stats(b_addr).unwrap().retransmissions, 1);
+        assert!(a.poll_timeout().unwrap() > timeout);
+    }
+
+    #[test]
+    fn test_messages_reassemble_across_cores() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::default().with_max_payload_size(64);
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+
+        let message: Vec<u8> = (0..200u8).collect();
+        a.send_message(&message, b_addr, now).unwrap();
+        assert!(deliver(&mut a, a_addr, &mut b, now) > 1);
+
+        let received = b.poll_message().unwrap();
+        assert_eq!(received.from, a_addr);
+        assert_eq!(received.data, message);
+        assert!(b.poll_received().is_none());
+    }
+
+    #[test]
+    fn test_peer_limit_evicts_least_recently_active() {
+        let now = Instant::now();
+        let limits = LimitsConfig { max_peers: 2, ..LimitsConfig::default() };
+        let mut server = RudpCore::new(RudpConfig::default().with_limits(limits)).unwrap();
+        let server_addr: SocketAddr = "10.0.0.100:1".parse().unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        server.set_event_handler(Recorder(Arc::clone(&events)));
+
+        let peers: Vec<SocketAddr> = (1..=3).map(|i| format!("10.0.0.{}:1", i).parse().unwrap()).collect();
+        let mut clients: Vec<RudpCore> = peers.iter().map(|_| RudpCore::new(RudpConfig::default()).unwrap()).collect();
+        for (client, addr) in clients.iter_mut().zip(&peers) {
+            client.send(payload(client, b"hi"), server_addr, now).unwrap();
+            deliver(client, *addr, &mut server, now);
+        }
+
+        assert_eq!(server.peer_activity.len(), 2);
+        assert!(!server.recv_windows.contains_key(&peers[0]));
+        assert!(server.recv_windows.contains_key(&peers[1]));
+        assert!(server.recv_windows.contains_key(&peers[2]));
+        assert_eq!(*events.lock().unwrap(), vec![(peers[0], ConnectionEvent::Evicted)]);
+        // Data already received from the evicted peer is still delivered
+        assert_eq!(std::iter::from_fn(|| server.poll_received()).count(), 3);
+
+        // The evicted peer is told with a Close; its CloseAck evicts nobody else
+        server.handle_timeout(now);
+        while let Some(transmit) = server.poll_transmit() {
+            let index = peers.iter().position(|addr| *addr == transmit.destination).unwrap();
+            clients[index].handle_datagram(&transmit.contents, server_addr, now);
+        }
+        assert_eq!(clients[0].connection_status(server_addr), ConnectionStatus::Dead);
+        assert_eq!(clients[1].connection_status(server_addr), ConnectionStatus::Alive);
+        deliver(&mut clients[0], peers[0], &mut server, now);
+        assert_eq!(server.peer_activity.len(), 2);
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_full_receive_queue_applies_backpressure() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let limits = LimitsConfig { max_queued_packets: 2, ..LimitsConfig::default() };
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default().with_limits(limits)).unwrap();
+
+        for data in [b"one", b"two", b"3rd"] {
+            a.send(payload(&a, data), b_addr, start).unwrap();
+        }
+        deliver(&mut a, a_addr, &mut b, start);
+        b.handle_timeout(start);
+        deliver(&mut b, b_addr, &mut a, start);
+        // The packet that did not fit was not acknowledged
+        assert_eq!(a.unacked_packets(b_addr), 1);
+
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"one");
+        let retry_at = a.poll_timeout().unwrap();
+        a.handle_timeout(retry_at);
+        deliver(&mut a, a_addr, &mut b, retry_at);
+        b.handle_timeout(retry_at);
+        deliver(&mut b, b_addr, &mut a, retry_at);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"two");
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"3rd");
+        assert!(b.poll_received().is_none());
+    }
+
+    #[test]
+    fn test_bandwidth_limit_paces_transmits() {
+        let (_, b_addr) = addrs();
+        let start = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default().with_max_bandwidth(Some(100_000))).unwrap();
+        for _ in 0..10 {
+            a.send(payload(&a, &[0; 500]), b_addr, start).unwrap();
+        }
+        let packet_len = PROTOCOL_HEADER_SIZE + 500;
+
+        let mut now = start;
+        let mut sent = 0;
+        loop {
+            while let Some(transmit) = a.poll_transmit() {
+                sent += transmit.contents.len();
+            }
+            // Never more than the 10ms bucket plus what the elapsed time earned
+            assert!(sent as f64 <= 1000.0 + (now - start).as_secs_f64() * 100_000.0 + 1.0);
+            if sent == 10 * packet_len {
+                break;
+            }
+            now = a.poll_timeout().unwrap();
+            a.handle_timeout(now);
+        }
+        let elapsed = now - start;
+        assert!(elapsed >= Duration::from_millis(35) && elapsed < Duration::from_millis(60), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_invalid_packet_flood_is_ignored() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let limits = LimitsConfig { max_invalid_packets: 3, ..LimitsConfig::default() };
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default().with_limits(limits)).unwrap();
+
+        let mut forged = vec![PacketType::Data as u8, 0xde, 0xad, 0xbe, 0xef, 0, 0, 0, 1];
+        forged.extend_from_slice(b"forged");
+        b.handle_datagram(&forged, a_addr, now);
+        b.handle_datagram(&[0x7f; PROTOCOL_HEADER_SIZE], a_addr, now);
+        b.handle_datagram(&[PacketType::Data as u8, 1], a_addr, now);
+        let stats = b.invalid_packet_stats(a_addr).unwrap();
+        assert_eq!((stats.security_failures, stats.unknown_types, stats.parse_errors), (1, 1, 1));
+        assert_eq!(std::iter::from_fn(|| b.poll_received()).filter(|received| received.result.is_err()).count(), 3);
+
+        // Over the threshold the source is ignored, valid packets included
+        b.handle_datagram(&forged, a_addr, now);
+        a.send(payload(&a, b"valid"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        b.handle_timeout(now);
+        assert!(matches!(b.poll_received().unwrap().result, Err(RudpError::Security)));
+        assert!(b.poll_received().is_none());
+        assert!(b.poll_transmit().is_none());
+        assert_eq!(b.global_stats().unwrap().invalid_packets.total(), 4);
+
+        // Once the window has passed it is heard again
+        let later = now + b.config().limits.invalid_packet_window;
+        a.handle_timeout(later);
+        deliver(&mut a, a_addr, &mut b, later);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"valid");
+    }
+
+    #[test]
+    fn test_peer_filter_rejects_new_sources() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        b.set_peer_filter(move |addr| if addr == a_addr { Verdict::Reject } else { Verdict::Accept });
+
+        a.send(payload(&a, b"denied"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        b.handle_timeout(now);
+        assert!(b.poll_received().is_none());
+        assert!(b.poll_transmit().is_none());
+        assert!(b.recv_windows.is_empty() && b.connection_stats.is_empty());
+
+        // A peer this side sent to already has state and is not filtered
+        b.send(payload(&b, b"hello"), a_addr, now).unwrap();
+        let retry_at = a.poll_timeout().unwrap();
+        a.handle_timeout(retry_at);
+        deliver(&mut a, a_addr, &mut b, retry_at);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"denied");
+    }
+
+    #[test]
+    fn test_failures_are_reported_to_sender() {
+        let (_, b_addr) = addrs();
+        let mut now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(Recorder(Arc::clone(&events)));
+        let seq = a.send(payload(&a, b"lost"), b_addr, now).unwrap();
+
+        // b never answers: the packet runs out of retries, then the peer is declared dead
+        let mut errors = Vec::new();
+        while !events.lock().unwrap().contains(&(b_addr, ConnectionEvent::Dead)) {
+            // Keep-alive timers fire once strictly past their deadline
+            now = a.poll_timeout().expect("no timer pending") + Duration::from_millis(1);
+            a.handle_timeout(now);
+            while a.poll_transmit().is_some() {}
+            while let Some(received) = a.poll_received() {
+                errors.push((received.from, received.seq, received.result.unwrap_err()));
+            }
+        }
+
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                (from, Some(failed), RudpError::Connection(ConnectionError::MaxRetriesExceeded { .. })),
+                (dead, None, RudpError::Connection(ConnectionError::Dead { .. })),
+            ] if *from == b_addr && *failed == seq && *dead == b_addr
+        ));
+        assert!(matches!(
+            a.send(payload(&a, b"again"), b_addr, now),
+            Err(RudpError::Connection(ConnectionError::Dead { addr })) if addr == b_addr
+        ));
+        assert!(matches!(
+            a.send_message(b"again", b_addr, now),
+            Err(RudpError::Connection(ConnectionError::Dead { .. }))
+        ));
+
+        a.clear_dead_peer(b_addr);
+        assert!(a.send(payload(&a, b"again"), b_addr, now).is_ok());
+    }
+
+    #[test]
+    fn test_peers_must_share_authenticator() {
+        use crate::security::{Crc32c, NoAuthentication};
+
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let crc = RudpConfig::new().with_authenticator(Arc::new(Crc32c::default()));
+        let mut a = RudpCore::new(crc.clone()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        // The default authenticator rejects CRC32C codes
+        a.send(payload(&a, b"crc"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert!(matches!(b.poll_received().unwrap().result, Err(RudpError::Security)));
+        assert_eq!(b.global_stats().unwrap().invalid_packets.total(), 1);
+
+        // Switching to the same authenticator lets the retransmission through
+        b.update_config(crc).unwrap();
+        let later = now + Duration::from_secs(1);
+        a.handle_timeout(later);
+        deliver(&mut a, a_addr, &mut b, later);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"crc");
+
+        // Without authentication anything is accepted
+        b.update_config(RudpConfig::new().with_authenticator(Arc::new(NoAuthentication))).unwrap();
+        a.send(payload(&a, b"open"), b_addr, later).unwrap();
+        deliver(&mut a, a_addr, &mut b, later);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"open");
+    }
+
+    #[test]
+    fn test_payload_checksum_catches_late_corruption() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let data = [5u8; 100];
+        for checksum in [false, true] {
+            let mut a = RudpCore::new(RudpConfig::new().with_payload_checksum(checksum)).unwrap();
+            let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+            a.send(payload(&a, &data), b_addr, now).unwrap();
+            let mut datagram = a.poll_transmit().unwrap().contents;
+            // Past the 16 bytes the security code covers
+            *datagram.last_mut().unwrap() ^= 0xFF;
+            b.handle_datagram(&datagram, a_addr, now);
+
+            let received = b.poll_received().unwrap();
+            if checksum {
+                assert!(matches!(received.result, Err(RudpError::Checksum)));
+                assert_eq!(b.global_stats().unwrap().invalid_packets.checksum_failures, 1);
+            } else {
+                assert_ne!(received.result.unwrap().data(), &data[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_peer_key_rotation_keeps_connection() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        a.set_peer_key(b_addr, PeerKey::siphash(1, [1; 16]), now);
+        b.set_peer_key(a_addr, PeerKey::siphash(1, [1; 16]), now);
+
+        a.send(payload(&a, b"first"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"first");
+
+        // `a` rotates first: it still accepts the old key, `b` rejects the new one
+        a.set_peer_key(b_addr, PeerKey::siphash(2, [2; 16]), now);
+        assert_eq!(a.peer_key_id(b_addr), Some(2));
+        b.send(payload(&b, b"old key"), a_addr, now).unwrap();
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"old key");
+        a.send(payload(&a, b"new key"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert!(matches!(b.poll_received().unwrap().result, Err(RudpError::Security)));
+
+        // Once `b` rotates too, the retransmission gets through
+        b.set_peer_key(a_addr, PeerKey::siphash(2, [2; 16]), now);
+        let later = now + Duration::from_secs(1);
+        a.handle_timeout(later);
+        deliver(&mut a, a_addr, &mut b, later);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"new key");
+
+        // A peer without the key is rejected
+        let mut c = RudpCore::new(RudpConfig::default()).unwrap();
+        c.send(payload(&c, b"no key"), b_addr, later).unwrap();
+        deliver(&mut c, a_addr, &mut b, later);
+        assert!(matches!(b.poll_received().unwrap().result, Err(RudpError::Security)));
+    }
+
+    #[cfg(feature = "identity")]
+    #[test]
+    fn test_pinned_identity_gates_peer() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let a_key = IdentityKey::from_bytes(&[1; 32]);
+        let b_key = IdentityKey::from_bytes(&[2; 32]);
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        b.set_event_handler(Recorder(Arc::clone(&events)));
+        b.pin_peer_identity(a_addr, a_key.identity());
+        b.set_identity(b_key.clone());
+
+        // Without an identity the pinned peer is ignored
+        a.send(payload(&a, b"anonymous"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert!(b.poll_received().is_none());
+
+        // The first send after setting an identity pings to present it
+        a.set_identity(a_key.clone());
+        a.send(payload(&a, b"signed"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"signed");
+        assert_eq!(b.peer_identity(a_addr), Some(a_key.identity()));
+        assert_eq!(*events.lock().unwrap(), vec![(a_addr, ConnectionEvent::Established { identity: a_key.identity() })]);
+
+        // The ping acknowledgment carries b's identity back
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.peer_identity(b_addr), Some(b_key.identity()));
+
+        // Another key from the pinned address is refused
+        let mut impostor = RudpCore::new(RudpConfig::default()).unwrap();
+        impostor.set_identity(IdentityKey::from_bytes(&[3; 32]));
+        let _ping = impostor.ping(b_addr, now).unwrap();
+        deliver(&mut impostor, a_addr, &mut b, now);
+        assert!(matches!(b.poll_received().unwrap().result, Err(RudpError::Security)));
+    }
+
+    #[test]
+    fn test_padding_hides_payload_length() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let padding = PaddingConfig::new(vec![64, 256]);
+        let mut a = RudpCore::new(RudpConfig::new().with_padding(Some(padding)).with_payload_checksum(true)).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let messages: [&[u8]; 3] = [b"hi", &[7; 40], &[9; 300]];
+        for message in messages {
+            a.send(payload(&a, message), b_addr, now).unwrap();
+        }
+        let sizes: Vec<usize> = std::iter::from_fn(|| a.poll_transmit()).map(|transmit| {
+            // Alternate between both receive paths
+            if transmit.contents.len() == 64 {
+                let mut buffer = b.get_buffer().unwrap();
+                buffer.raw_buffer_mut()[..64].copy_from_slice(&transmit.contents);
+                b.handle_buffer(buffer, 64, a_addr, now);
+            } else {
+                b.handle_datagram(&transmit.contents, a_addr, now);
+            }
+            transmit.contents.len()
+        }).collect();
+        // Packets above the largest bucket keep their size
+        assert_eq!(sizes[..2], [64, 64]);
+        assert!(sizes[2] > 300 + PROTOCOL_HEADER_SIZE);
+
+        for message in messages {
+            assert_eq!(b.poll_received().unwrap().result.unwrap().data(), message);
+        }
+    }
+
+    #[test]
+    fn test_reset_recovers_from_peer_restart() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(Recorder(Arc::clone(&events)));
+
+        b.send(payload(&b, b"before"), a_addr, now).unwrap();
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"before");
+
+        // b restarts before a's acknowledgment arrives; it answers with a Reset
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        a.handle_timeout(now);
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.peer_activity.len(), 0);
+        assert_eq!(deliver(&mut b, b_addr, &mut a, now), 1);
+        assert!(matches!(a.poll_received().unwrap().result, Err(RudpError::Connection(ConnectionError::Reset { .. }))));
+        assert_eq!(*events.lock().unwrap(), [(b_addr, ConnectionEvent::Reset)]);
+
+        // The new session reuses sequence number 0, which a no longer treats as a duplicate
+        b.send(payload(&b, b"after"), a_addr, now).unwrap();
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"after");
+    }
+
+    #[test]
+    fn test_close_reason_reaches_peer() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(Recorder(Arc::clone(&events)));
+
+        a.send(payload(&a, b"hello"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        let oversized = CloseReason::new(CloseReason::BANNED).with_payload(vec![0; MAX_CLOSE_PAYLOAD + 1]);
+        assert!(matches!(b.close_peer(a_addr, oversized, now), Err(RudpError::BufferTooLarge { .. })));
+
+        let reason = CloseReason::new(CloseReason::BANNED).with_payload("rate limited");
+        b.close_peer(a_addr, reason.clone(), now).unwrap();
+        assert_eq!(b.peer_activity.len(), 0);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(*events.lock().unwrap(), [(b_addr, ConnectionEvent::Closed { reason })]);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+    }
+
+    #[test]
+    fn test_closed_peer_lingers_for_late_packets() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let rto = RudpConfig::default().initial_rto;
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(Recorder(Arc::clone(&events)));
+
+        a.send(payload(&a, b"late"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert!(b.poll_received().unwrap().result.is_ok());
+
+        // b's Close is lost; a's retransmission arrives after b removed its state
+        b.close_peer(a_addr, CloseReason::default(), now).unwrap();
+        while b.poll_transmit().is_some() {}
+        a.handle_timeout(now + rto);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now + rto), 1);
+        assert_eq!(b.peer_activity.len(), 0);
+        assert!(b.poll_received().is_none());
+
+        // The straggler is answered with a Close, which ends a's side too
+        deliver(&mut b, b_addr, &mut a, now + rto);
+        assert_eq!(*events.lock().unwrap(), [(b_addr, ConnectionEvent::Closed { reason: CloseReason::default() })]);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+
+        b.handle_timeout(now + rto * 2);
+        assert!(b.tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_send_keeps_receiving() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        b.set_event_handler(Recorder(Arc::clone(&events)));
+
+        a.send(payload(&a, b"upload"), b_addr, now).unwrap();
+        a.shutdown_send(b_addr, now).unwrap();
+        assert!(matches!(a.send(payload(&a, b"more"), b_addr, now), Err(RudpError::Connection(ConnectionError::SendShutdown { .. }))));
+        assert!(matches!(a.send_message(b"more", b_addr, now), Err(RudpError::Connection(ConnectionError::SendShutdown { .. }))));
+
+        // The Shutdown packet waits until the upload is acknowledged
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+        assert!(events.lock().unwrap().is_empty());
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        a.handle_timeout(now);
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(*events.lock().unwrap(), [(a_addr, ConnectionEvent::Finished)]);
+        assert!(b.peer_finished(a_addr));
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"upload");
+        assert!(b.poll_received().is_none());
+
+        // a still receives and acknowledges the response, and its Shutdown is acknowledged
+        b.send(payload(&b, b"response"), a_addr, now).unwrap();
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.poll_received().unwrap().result.unwrap().data(), b"response");
+        a.handle_timeout(now);
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        assert_eq!(b.unacked_packets(a_addr), 0);
+    }
+
+    #[test]
+    fn test_control_packets_coalesced_per_tick() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let keepalive = KeepAliveConfig { idle_timeout: Duration::from_millis(50), ..KeepAliveConfig::default() };
+        let mut a = RudpCore::new(RudpConfig::new().with_initial_cwnd(300)).unwrap();
+        let mut b = RudpCore::new(RudpConfig::new().with_keepalive(keepalive).with_coalesce_control(true)).unwrap();
+
+        // More acknowledgments than one DataAck holds
+        for _ in 0..300 {
+            a.try_send(payload(&a, b"x"), b_addr, now).unwrap();
+        }
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 300);
+        b.connect(a_addr, now).unwrap();
+        let later = now + Duration::from_millis(60);
+        b.handle_timeout(later);
+        let transmits: Vec<Transmit> = std::iter::from_fn(|| b.poll_transmit()).collect();
+        assert!(transmits.iter().all(|transmit| transmit.contents[0] == PacketType::Batch as u8));
+        assert!(transmits.len() < 3);
+
+        // Every packet in the batches is handled: all data acknowledged, the ping answered
+        for transmit in &transmits {
+            a.handle_datagram(&transmit.contents, b_addr, later);
+        }
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        assert!(a.poll_received().is_none());
+        assert_eq!(a.poll_transmit().unwrap().contents[0] & !EXTENSION_FLAG, PacketType::PingAck as u8);
+    }
+
+    #[test]
+    fn test_small_sends_bundled_until_delay_or_flush() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::new().with_coalesce_delay(Some(Duration::from_millis(10)))).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let seqs: Vec<u32> = [&b"one"[..], b"two", b"three"].into_iter().map(|data| a.send(payload(&a, data), b_addr, now).unwrap()).collect();
+        assert!(seqs.iter().all(|seq| *seq == seqs[0]));
+        assert!(a.poll_transmit().is_none());
+        assert_eq!(a.poll_timeout(), Some(now + Duration::from_millis(10)));
+
+        // The delay expires: one packet, delivered as separate payloads
+        let later = now + Duration::from_millis(10);
+        a.handle_timeout(later);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, later), 1);
+        let received: Vec<Vec<u8>> = std::iter::from_fn(|| b.poll_received()).map(|received| received.result.unwrap().data().to_vec()).collect();
+        assert_eq!(received, [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        b.handle_timeout(later);
+        deliver(&mut b, b_addr, &mut a, later);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+
+        // flush_now sends without waiting
+        a.send(payload(&a, b"urgent"), b_addr, later).unwrap();
+        assert_eq!(a.unacked_packets(b_addr), 1);
+        a.flush_now(b_addr, later).unwrap();
+        assert_eq!(deliver(&mut a, a_addr, &mut b, later), 1);
+        assert_eq!(b.poll_received().unwrap().result.unwrap().data(), b"urgent");
+    }
+
+    #[test]
+    fn test_peer_id_follows_migration() {
+        struct PeerRecorder(Arc<Mutex<Vec<(PeerId, SocketAddr, ConnectionEvent)>>>);
+        impl EventHandler for PeerRecorder {
+            fn on_peer_event(&self, peer: PeerId, addr: SocketAddr, event: ConnectionEvent) {
+                self.0.lock().unwrap().push((peer, addr, event));
+            }
+        }
+
+        let (a_addr, b_addr) = addrs();
+        let moved: SocketAddr = "10.0.0.3:7".parse().unwrap();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        b.set_event_handler(PeerRecorder(Arc::clone(&events)));
+
+        // No id before the ping exchange
+        a.send(payload(&a, b"data"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(b.peer_id(a_addr), None);
+        let unknown = PeerId::Connection(a.connection_id());
+        assert!(matches!(b.send_to_peer(payload(&b, b"x"), unknown, now), Err(RudpError::Connection(ConnectionError::UnknownPeer { .. }))));
+
+        a.ping(b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        let peer = b.peer_id(a_addr).unwrap();
+        assert_eq!(peer, PeerId::Connection(a.connection_id()));
+        assert_eq!(b.peer_addr(peer), Some(a_addr));
+
+        // After `a` moves, its id leads to the new address
+        a.migrate(now);
+        deliver(&mut a, moved, &mut b, now);
+        assert_eq!(*events.lock().unwrap(), vec![(peer, moved, ConnectionEvent::Migrated { from: a_addr })]);
+        assert_eq!(b.peer_addr(peer), Some(moved));
+        assert!(b.peer_stats(peer).is_some());
+        while b.poll_transmit().is_some() {}
+        b.send_to_peer(payload(&b, b"follow"), peer, now).unwrap();
+        assert_eq!(b.poll_transmit().unwrap().destination, moved);
+
+        // A Close removes the state, and the event still carries the id
+        a.close_peer(b_addr, CloseReason::default(), now).unwrap();
+        deliver(&mut a, moved, &mut b, now);
+        assert_eq!(events.lock().unwrap().last(), Some(&(peer, moved, ConnectionEvent::Closed { reason: CloseReason::default() })));
+        assert_eq!(b.peer_addr(peer), None);
+    }
+
+    #[test]
+    fn test_connections_lists_live_peers() {
+        let (a_addr, b_addr) = addrs();
+        let c_addr: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut c = RudpCore::new(RudpConfig::default()).unwrap();
+        assert_eq!(a.connections().count(), 0);
+
+        a.send(payload(&a, b"to b"), b_addr, now).unwrap();
+        a.send(payload(&a, b"to c, longer"), c_addr, now).unwrap();
+        while let Some(transmit) = a.poll_transmit() {
+            let to = if transmit.destination == b_addr { &mut b } else { &mut c };
+            to.handle_datagram(&transmit.contents, a_addr, now);
+        }
+
+        let mut connections: Vec<_> = a.connections().collect();
+        connections.sort_by_key(|(addr, _, _)| *addr);
+        let summary: Vec<_> = connections
+            .iter()
+            .map(|(addr, status, stats)| (*addr, status.clone(), stats.packets_sent))
+            .collect();
+        assert_eq!(summary, vec![(b_addr, ConnectionStatus::Alive, 1), (c_addr, ConnectionStatus::Alive, 1)]);
+
+        // Closed connections are no longer listed
+        a.close_peer(b_addr, CloseReason::default(), now).unwrap();
+        assert_eq!(a.connections().map(|(addr, _, _)| addr).collect::<Vec<_>>(), vec![c_addr]);
+    }
+
+    #[test]
+    fn test_retransmissions_raise_and_clear_alert() {
+        struct AlertRecorder(Arc<Mutex<Vec<(SocketAddr, AlertKind, bool)>>>);
+        impl EventHandler for AlertRecorder {
+            fn on_alert(&self, addr: SocketAddr, alert: Alert) {
+                self.0.lock().unwrap().push((addr, alert.kind, alert.raised));
+            }
+        }
+
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let alerts = AlertConfig { retransmit_ratio: Some(0.5), min_packets: 2, ..AlertConfig::default() };
+        let mut a = RudpCore::new(RudpConfig::default().with_alerts(Some(alerts))).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(AlertRecorder(Arc::clone(&events)));
+
+        // Both packets of the first interval are lost once
+        a.send(payload(&a, b"one"), b_addr, start).unwrap();
+        a.send(payload(&a, b"two"), b_addr, start).unwrap();
+        a.handle_timeout(start);
+        while a.poll_transmit().is_some() {}
+        let retransmit = start + Duration::from_millis(500);
+        a.handle_timeout(retransmit);
+        deliver(&mut a, a_addr, &mut b, retransmit);
+        b.handle_timeout(retransmit);
+        deliver(&mut b, b_addr, &mut a, retransmit);
+        a.handle_timeout(start + Duration::from_secs(1));
+        assert_eq!(*events.lock().unwrap(), vec![(b_addr, AlertKind::RetransmitRatio, true)]);
+
+        // The next interval delivers everything the first time
+        let clean = start + Duration::from_millis(1500);
+        a.send(payload(&a, b"three"), b_addr, clean).unwrap();
+        a.send(payload(&a, b"four"), b_addr, clean).unwrap();
+        deliver(&mut a, a_addr, &mut b, clean);
+        b.handle_timeout(clean);
+        deliver(&mut b, b_addr, &mut a, clean);
+        a.handle_timeout(start + Duration::from_secs(2));
+        assert_eq!(events.lock().unwrap().last(), Some(&(b_addr, AlertKind::RetransmitRatio, false)));
+    }
+
+    #[test]
+    fn test_busy_lossless_link_raises_no_loss_alert() {
+        struct AlertRecorder(Arc<Mutex<Vec<(SocketAddr, AlertKind, bool)>>>);
+        impl EventHandler for AlertRecorder {
+            fn on_alert(&self, addr: SocketAddr, alert: Alert) {
+                self.0.lock().unwrap().push((addr, alert.kind, alert.raised));
+            }
+        }
+
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let alerts = AlertConfig { loss_rate: Some(0.01), min_packets: 2, ..AlertConfig::default() };
+        let mut a = RudpCore::new(RudpConfig::default().with_alerts(Some(alerts))).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(AlertRecorder(Arc::clone(&events)));
+
+        // Several ticks while every packet is still in flight, nothing lost
+        for tick in 0..300 {
+            let now = start + Duration::from_millis(tick * 10);
+            a.send(payload(&a, b"data"), b_addr, now).unwrap();
+            for _ in 0..5 {
+                a.handle_timeout(now);
+            }
+            deliver(&mut a, a_addr, &mut b, now);
+            b.handle_timeout(now + Duration::from_millis(5));
+            deliver(&mut b, b_addr, &mut a, now + Duration::from_millis(5));
+        }
+        let stats = a.get_stats(b_addr).unwrap();
+        assert_eq!((stats.packets_lost, stats.retransmissions), (0, 0));
+        assert_eq!(stats.packet_loss_rate(), 0.0);
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_silent_peer_reported_as_outage() {
+        let (a_addr, b_addr) = addrs();
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        a.set_event_handler(Recorder(Arc::clone(&events)));
+
+        // The packet and its first retransmission are lost
+        a.send(payload(&a, b"hello"), b_addr, start).unwrap();
+        while a.poll_transmit().is_some() {}
+        a.handle_timeout(at(200));
+        while a.poll_transmit().is_some() {}
+        a.handle_timeout(at(600));
+        deliver(&mut a, a_addr, &mut b, at(600));
+        b.handle_timeout(at(700));
+        deliver(&mut b, b_addr, &mut a, at(700));
+
+        assert_eq!(*events.lock().unwrap(), vec![(b_addr, ConnectionEvent::Outage { duration: Duration::from_millis(700) })]);
+        let stats = a.get_stats(b_addr).unwrap();
+        assert_eq!((stats.outages, stats.outage_time, stats.loss_bursts), (1, Duration::from_millis(700), 0));
+    }
+
+    #[test]
+    fn test_stats_reported_periodically() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        a.enable_stats_reporting(Duration::from_secs(1), sender);
+
+        a.handle_timeout(now);
+        assert_eq!(a.poll_timeout(), Some(now + Duration::from_secs(1)));
+        let data = payload(&a, b"hello");
+        a.send(data, b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert!(receiver.try_recv().is_err());
+
+        a.handle_timeout(now + Duration::from_secs(1));
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.time_ms, 1000.0);
+        assert_eq!(report.global.active_connections, 1);
+        assert_eq!(report.global.packets_sent, 1);
+        assert_eq!(report.peers.len(), 1);
+        assert_eq!(report.peers[0].addr, b_addr);
+        assert_eq!(report.peers[0].bytes_acked, 5);
+        assert!(receiver.try_recv().is_err());
+
+        a.disable_stats_reporting();
+        a.handle_timeout(now + Duration::from_secs(2));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_cancel_removes_in_flight_and_queued_packets() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_initial_cwnd(1);
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+
+        let mut in_flight = a.send_tracked(payload(&a, b"stale"), b_addr, now).unwrap();
+        let mut queued = a.send_tracked(payload(&a, b"also stale"), b_addr, now).unwrap();
+        let kept = a.send(payload(&a, b"fresh"), b_addr, now).unwrap();
+        assert_eq!(a.unacked_packets(b_addr), 3);
+
+        assert!(a.cancel(b_addr, queued.seq(), now));
+        assert!(matches!(queued.try_result(), Some(Err(RudpError::Cancelled))));
+        assert!(a.cancel(b_addr, in_flight.seq(), now));
+        assert!(matches!(in_flight.try_result(), Some(Err(RudpError::Cancelled))));
+        assert!(!a.cancel(b_addr, in_flight.seq(), now));
+        assert_eq!(a.unacked_packets(b_addr), 1);
+
+        // The cancelled packet is not sent, and its window slot goes to the queued one
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 0);
+        a.handle_timeout(now);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+        let received = b.poll_received().unwrap();
+        assert_eq!(received.seq, Some(kept));
+        assert!(b.poll_received().is_none());
+    }
+
+    #[test]
+    fn test_resend_retransmits_before_timeout() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        let seq = a.send(payload(&a, b"lost"), b_addr, now).unwrap();
+        while a.poll_transmit().is_some() {}
+        assert!(!a.resend(b_addr, seq + 1, now));
+
+        let now = now + Duration::from_millis(10);
+        assert!(a.resend(b_addr, seq, now));
+        assert_eq!(a.get_stats(b_addr).unwrap().retransmissions, 1);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+        assert_eq!(b.poll_received().unwrap().seq, Some(seq));
+
+        let now = now + Duration::from_millis(50);
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        assert!(!a.resend(b_addr, seq, now));
+    }
+
+    #[test]
+    fn test_scheduled_sends_go_out_when_due() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_max_payload_size(8);
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+
+        a.send_after(payload(&a, b"second"), b_addr, Duration::from_millis(100), now).unwrap();
+        a.send_at(payload(&a, b"first"), b_addr, now + Duration::from_millis(50)).unwrap();
+        assert!(matches!(a.send_at(payload(&a, b"oversized"), b_addr, now), Err(RudpError::BufferTooLarge { .. })));
+        assert_eq!(a.unacked_packets(b_addr), 2);
+        assert_eq!(a.pending_bytes(b_addr), 11);
+        assert_eq!(a.poll_timeout(), Some(now + Duration::from_millis(50)));
+
+        a.handle_timeout(now + Duration::from_millis(49));
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 0);
+        for (millis, expected) in [(50, &b"first"[..]), (100, b"second")] {
+            let now = now + Duration::from_millis(millis);
+            a.handle_timeout(now);
+            assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+            let received = b.poll_received().unwrap();
+            assert_eq!(received.result.unwrap().data(), expected);
+        }
+        assert_eq!(a.unacked_packets(b_addr), 2);
+    }
+
+    #[test]
+    fn test_scheduled_sends_dropped_with_connection() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        a.send(payload(&a, b"hello"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        a.send_after(payload(&a, b"late"), b_addr, Duration::from_millis(100), now).unwrap();
+        assert_eq!(a.unacked_packets(b_addr), 2);
+        assert_eq!(a.pending_bytes(b_addr), 9);
+
+        // Closing drops the scheduled send instead of reopening the connection with it
+        a.close_peer(b_addr, CloseReason::default(), now).unwrap();
+        assert_eq!(a.unacked_packets(b_addr), 0);
+        assert_eq!(a.pending_bytes(b_addr), 0);
+        assert_eq!(a.poll_timeout().filter(|at| *at <= now + Duration::from_millis(100)), None);
+        deliver(&mut a, a_addr, &mut b, now);
+        a.handle_timeout(now + Duration::from_millis(100));
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now + Duration::from_millis(100)), 0);
+        assert!(a.get_stats(b_addr).is_none());
+    }
+
+    #[test]
+    fn test_oversized_payload_rejected_with_peer_limit() {
+        let (a_addr, b_addr) = addrs();
+        let c_addr: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_coalesce_delay(Some(Duration::from_millis(5)));
+        let mut a = RudpCore::new(config.clone()).unwrap();
+        let mut b = RudpCore::new(config).unwrap();
+
+        a.set_peer_max_payload(b_addr, 100);
+        assert_eq!(a.max_payload_for(b_addr), 100);
+        assert_eq!(a.max_payload_for(c_addr), a.config().max_payload_size);
+        a.set_peer_max_payload(c_addr, usize::MAX);
+        assert_eq!(a.max_payload_for(c_addr), a.config().max_payload_size);
+
+        // A small payload waits to be coalesced; the rejected one must not flush it
+        let seq = a.send(payload(&a, b"small"), b_addr, now).unwrap();
+        let large = payload(&a, &[7; 101]);
+        assert!(matches!(a.send(large, b_addr, now), Err(RudpError::BufferTooLarge { size: 101, max: 100 })));
+        assert!(matches!(a.try_send(payload(&a, &[7; 101]), b_addr, now), Err(RudpError::BufferTooLarge { max: 100, .. })));
+        assert!(matches!(a.send_tracked(payload(&a, &[7; 101]), b_addr, now), Err(RudpError::BufferTooLarge { max: 100, .. })));
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 0);
+
+        // Messages are fragmented to the peer's limit
+        a.send_message(&[9; 250], b_addr, now).unwrap();
+        a.flush_now(b_addr, now).unwrap();
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 4);
+        assert_eq!(b.poll_received().unwrap().seq, Some(seq));
+        assert_eq!(b.poll_message().unwrap().data, vec![9; 250]);
+
+        a.clear_peer_max_payload(b_addr);
+        assert!(a.send(payload(&a, &[7; 101]), b_addr, now).is_ok());
+    }
+
+    #[test]
+    fn test_critical_packets_duplicated_under_loss() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let config = RudpConfig::new().with_redundancy(RedundancyConfig { loss_threshold: 0.05, ..RedundancyConfig::default() });
+        let mut a = RudpCore::new(config).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+        // 20ms RTT sample
+        a.send(payload(&a, b"hello"), b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        let now = now + Duration::from_millis(20);
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+
+        // No loss yet: a lost critical packet waits for its retransmission timeout
+        a.send_critical(payload(&a, b"first"), b_addr, now).unwrap();
+        assert!(a.poll_transmit().is_some());
+        a.handle_timeout(now + Duration::from_millis(5));
+        assert!(a.poll_transmit().is_none());
+        let now = now + Duration::from_millis(200);
+        a.handle_timeout(now);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+        let now = now + Duration::from_millis(20);
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+        while b.poll_received().is_some() {}
+
+        // After the loss the copy follows 5ms later and gets through in its place
+        let seq = a.send_critical(payload(&a, b"second"), b_addr, now).unwrap();
+        assert!(a.poll_transmit().is_some());
+        assert_eq!(a.poll_timeout(), Some(now + DEFAULT_REDUNDANCY_SPACING));
+        let now = now + DEFAULT_REDUNDANCY_SPACING;
+        a.handle_timeout(now);
+        assert_eq!(deliver(&mut a, a_addr, &mut b, now), 1);
+        assert_eq!(b.poll_received().map(|received| received.seq), Some(Some(seq)));
+        let now = now + Duration::from_millis(20);
+        b.handle_timeout(now);
+        deliver(&mut b, b_addr, &mut a, now);
+
+        let stats = a.get_stats(b_addr).unwrap();
+        assert_eq!((stats.redundant_copies, stats.redundant_recoveries, stats.retransmissions), (1, 1, 1));
+        assert_eq!(a.unacked_packets(b_addr), 0);
+    }
+
+    #[test]
+    fn test_heartbeat_payloads_ride_on_pings() {
+        type Heartbeats = Arc<Mutex<Vec<(SocketAddr, Vec<u8>)>>>;
+        struct HeartbeatRecorder(Heartbeats);
+        impl EventHandler for HeartbeatRecorder {
+            fn on_heartbeat(&self, addr: SocketAddr, payload: &[u8]) {
+                self.0.lock().unwrap().push((addr, payload.to_vec()));
+            }
+        }
+
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        let heartbeats = Arc::new(Mutex::new(Vec::new()));
+        b.set_event_handler(HeartbeatRecorder(heartbeats.clone()));
+
+        assert!(matches!(a.set_heartbeat(&[0; MAX_HEARTBEAT_SIZE + 1]), Err(RudpError::BufferTooLarge { .. })));
+        a.set_heartbeat(b"load=3").unwrap();
+        b.set_heartbeat(b"ok").unwrap();
+
+        a.ping(b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(*heartbeats.lock().unwrap(), vec![(a_addr, b"load=3".to_vec())]);
+        assert_eq!(b.peer_heartbeat(a_addr), Some(&b"load=3"[..]));
+
+        // The acknowledgment carries the answering side's payload
+        assert_eq!(a.peer_heartbeat(b_addr), None);
+        deliver(&mut b, b_addr, &mut a, now);
+        assert_eq!(a.peer_heartbeat(b_addr), Some(&b"ok"[..]));
+
+        // Without a payload the pings carry none and the last one received is kept
+        a.set_heartbeat(&[]).unwrap();
+        a.ping(b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now);
+        assert_eq!(heartbeats.lock().unwrap().len(), 1);
+        assert_eq!(b.peer_heartbeat(a_addr), Some(&b"load=3"[..]));
+    }
+
+    #[test]
+    fn test_clock_offset_from_ping_reply_times() {
+        let (a_addr, b_addr) = addrs();
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        // b's clock is 5s ahead of a's
+        let wall_clock = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        a.set_wall_clock(now, wall_clock);
+        b.set_wall_clock(now, wall_clock + Duration::from_secs(5));
+
+        a.ping(b_addr, now).unwrap();
+        deliver(&mut a, a_addr, &mut b, now + Duration::from_millis(10));
+        assert!(a.clock_offset(b_addr).is_none());
+        deliver(&mut b, b_addr, &mut a, now + Duration::from_millis(30));
+
+        let clock = a.clock_offset(b_addr).unwrap();
+        assert_eq!(clock.samples, 1);
+        assert_eq!(clock.offset_nanos, 5_000_000_000 - 5_000_000, "{:?}", clock);
+        assert_eq!(clock.uncertainty, Duration::from_millis(15));
+        assert_eq!(a.get_stats(b_addr).unwrap().clock, Some(clock));
+        assert!(b.clock_offset(a_addr).is_none());
+    }
+}
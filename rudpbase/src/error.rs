@@ -1,6 +1,8 @@
 use thiserror::Error;
 use std::net::SocketAddr;
 
+use crate::peer_id::PeerId;
+
 /// Main error type for Rudpbase operations
 #[derive(Error, Debug)]
 pub enum RudpError {
@@ -16,6 +18,9 @@ pub enum RudpError {
     #[error("Security error: invalid security code")]
     Security,
     
+    #[error("Checksum error: payload does not match its checksum")]
+    Checksum,
+    
     #[error("Buffer too large: {size} bytes (max: {max})")]
     BufferTooLarge { size: usize, max: usize },
     
@@ -30,6 +35,18 @@ pub enum RudpError {
     
     #[error("Congestion window is full, cannot send more packets")]
     CongestionWindowFull,
+    
+    #[error("Invalid configuration: {message}")]
+    InvalidConfig { message: String },
+    
+    #[error("Instance is closing, no new data is accepted")]
+    Closing,
+    
+    #[error("Serialization error: {message}")]
+    Serialization { message: String },
+    
+    #[error("Send was cancelled")]
+    Cancelled,
 }
 
 /// Connection-specific errors
@@ -50,6 +67,12 @@ pub enum ConnectionError {
     #[error("Connection reset by peer {addr}")]
     Reset { addr: SocketAddr },
     
+    #[error("Sending to {addr} was shut down")]
+    SendShutdown { addr: SocketAddr },
+    
+    #[error("No known address for peer {peer}")]
+    UnknownPeer { peer: PeerId },
+    
     #[error("Connection closed by peer")]
     Closed,
     
@@ -76,11 +99,16 @@ impl RudpError {
             RudpError::Connection(conn_err) => conn_err.severity(),
             RudpError::Protocol { .. } => ErrorSeverity::Recoverable,
             RudpError::Security => ErrorSeverity::Critical,
+            RudpError::Checksum => ErrorSeverity::Recoverable,
             RudpError::BufferTooLarge { .. } => ErrorSeverity::Recoverable,
             RudpError::InternalError => ErrorSeverity::Critical,
             RudpError::PacketTooSmall { .. } => ErrorSeverity::Recoverable,
             RudpError::Timeout => ErrorSeverity::Degraded,
             RudpError::CongestionWindowFull => ErrorSeverity::Degraded,
+            RudpError::InvalidConfig { .. } => ErrorSeverity::Critical,
+            RudpError::Closing => ErrorSeverity::Critical,
+            RudpError::Serialization { .. } => ErrorSeverity::Recoverable,
+            RudpError::Cancelled => ErrorSeverity::Recoverable,
         }
     }
 }
@@ -94,6 +122,8 @@ impl ConnectionError {
             ConnectionError::MaxRetriesExceeded { .. } => ErrorSeverity::Critical,
             ConnectionError::Degraded { .. } => ErrorSeverity::Degraded,
             ConnectionError::Reset { .. } => ErrorSeverity::Critical,
+            ConnectionError::SendShutdown { .. } => ErrorSeverity::Critical,
+            ConnectionError::UnknownPeer { .. } => ErrorSeverity::Critical,
             ConnectionError::Closed => ErrorSeverity::Critical,
             ConnectionError::TooManyRetries => ErrorSeverity::Critical,
         }
@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::peer_id::PeerId;
+
+/// Change in the state of a peer, reported to [`EventHandler::on_connection_event`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// The peer proved it holds the private key of `identity` in a ping handshake,
+    /// and the identity passed the pinned key or verifier, if any
+    #[cfg(feature = "identity")]
+    Established { identity: crate::identity::PeerIdentity },
+    /// The peer limit (`LimitsConfig::max_peers`) was reached and this peer had been
+    /// inactive the longest; it was sent a Close with [`CloseReason::SERVER_FULL`]
+    /// and its pending packets failed
+    ///
+    /// [`CloseReason::SERVER_FULL`]: crate::CloseReason::SERVER_FULL
+    Evicted,
+    /// The peer closed the connection for `reason`; its state was removed and pending
+    /// packets failed
+    Closed { reason: crate::protocol::CloseReason },
+    /// The peer shut down its sending direction once everything it had sent was
+    /// acknowledged; it sends no more data but still receives
+    Finished,
+    /// Keep-alive declared the peer dead; sends to it fail with
+    /// `ConnectionError::Dead` until it is heard from again
+    Dead,
+    /// The peer presented a valid resumption token; the receive state of its
+    /// previous session was discarded and its data starts a new session
+    Resumed,
+    /// The peer now sends from this address; its connection id (see
+    /// [`ConnectionId`](crate::protocol::ConnectionId)) was last seen from `from`,
+    /// whose state was moved here
+    Migrated { from: SocketAddr },
+    /// The peer no longer has the session, for example after a restart: it answered
+    /// with a Reset, or its pings carry a new connection id. Pending packets failed
+    /// with `ConnectionError::Reset` and the next send starts a new session
+    Reset,
+    /// Data packets to the peer timed out `lost` times before it was heard from again,
+    /// each recovering on its first retransmission, as under congestion; `duration`
+    /// runs from sending the first lost packet until the peer's next packet
+    LossBurst { lost: u32, duration: Duration },
+    /// The peer went silent: a data packet timed out again after its backed-off RTO,
+    /// as when a link flaps, until the peer was heard from again after `duration`
+    Outage { duration: Duration },
+}
+
+/// Metric compared with a threshold of [`AlertConfig`](crate::AlertConfig)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// Fraction of data packets sent in the last interval that timed out
+    LossRate,
+    /// Smoothed round-trip time
+    Rtt,
+    /// Retransmissions per data packet sent in the last interval
+    RetransmitRatio,
+}
+
+/// A peer crossed or cleared an alert threshold, reported to [`EventHandler::on_alert`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// True when the value rose above the threshold, false when it is back at or below
+    pub raised: bool,
+    /// Measured value: a fraction for rates, milliseconds for RTT
+    pub value: f64,
+    /// Threshold, in the unit of `value`
+    pub threshold: f64,
+}
+
+/// Decision of a peer filter about a new source address
+///
+/// ```rust
+/// use rudpbase::{RudpConfig, RudpCore, Verdict};
+/// use std::net::IpAddr;
+///
+/// let mut core = RudpCore::new(RudpConfig::default()).unwrap();
+/// // Only admit peers from 10.0.0.0/8
+/// core.set_peer_filter(|addr| match addr.ip() {
+///     IpAddr::V4(ip) if ip.octets()[0] == 10 => Verdict::Accept,
+///     _ => Verdict::Reject,
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Handle packets from the source and create state for it
+    Accept,
+    /// Drop the packet without creating any state or answering it
+    Reject,
+}
+
+/// Application callbacks for events that are otherwise handled silently
+///
+/// All methods have empty default implementations, so a handler only needs to
+/// override the events it cares about. Callbacks run synchronously inside
+/// `tick()` / `recv()` and should return quickly.
+///
+/// ```rust
+/// use rudpbase::EventHandler;
+/// use std::net::SocketAddr;
+///
+/// struct Logger;
+///
+/// impl EventHandler for Logger {
+///     fn on_delivery_failed(&self, addr: SocketAddr, seq: u32) {
+///         eprintln!("packet {} to {} was never acknowledged", seq, addr);
+///     }
+/// }
+/// ```
+pub trait EventHandler: Send + Sync {
+    /// A data packet was dropped after exhausting all retransmissions
+    fn on_delivery_failed(&self, _addr: SocketAddr, _seq: u32) {}
+
+    /// A packet from `addr` failed security code verification
+    fn on_auth_failure(&self, _addr: SocketAddr) {}
+
+    /// The connection to `addr` was removed for the given reason
+    fn on_connection_event(&self, _addr: SocketAddr, _event: ConnectionEvent) {}
+
+    /// The same event for a peer that has a [`PeerId`], called right after
+    /// `on_connection_event` with the id the peer had, also when its state is gone
+    fn on_peer_event(&self, _peer: PeerId, _addr: SocketAddr, _event: ConnectionEvent) {}
+
+    /// A statistic of `addr` crossed or cleared its threshold in `RudpConfig::alerts`
+    fn on_alert(&self, _addr: SocketAddr, _alert: Alert) {}
+
+    /// A ping or ping acknowledgment from `addr` carried the peer's heartbeat payload,
+    /// set on its side with `set_heartbeat()`
+    fn on_heartbeat(&self, _addr: SocketAddr, _payload: &[u8]) {}
+}
@@ -0,0 +1,298 @@
+//! Ed25519 peer identities
+//!
+//! An instance given an [`IdentityKey`] with
+//! [`RudpCore::set_identity`](crate::RudpCore::set_identity) adds an
+//! [`EXTENSION_IDENTITY`] entry to its pings and ping acknowledgments: its public key
+//! and a signature over the packet type, sequence number, connection id and ping
+//! payload. The payload holds the ping's send time, echoed unchanged in the
+//! acknowledgment, and signatures over a time further than [`IDENTITY_MAX_AGE`] from
+//! the receiver's clock are refused. An acknowledgment therefore proves the peer
+//! holds the key right now, while a ping can be replayed within that window.
+//!
+//! Peers with a pinned identity, and every peer once a verifier callback is set,
+//! must present a valid identity before anything else they send is accepted; until
+//! then their packets are dropped unanswered and recovered by retransmission. A
+//! verified identity is reported with [`ConnectionEvent::Established`]. Only the
+//! handshake is signed: combine identities with per-peer keys (see
+//! [`PeerKey`](crate::PeerKey)) to authenticate every packet.
+//!
+//! [`ConnectionEvent::Established`]: crate::ConnectionEvent::Established
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::events::Verdict;
+use crate::protocol::{ConnectionId, PacketType, PingPacket, RawPacketRef, EXTENSION_IDENTITY};
+
+/// Largest difference between the send time of a signed ping and the receiver's clock
+pub const IDENTITY_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Encoded size: public key (32) and signature (64)
+const ENTRY_SIZE: usize = 96;
+
+/// Domain separation prefix of signed handshakes
+const CONTEXT: &[u8] = b"rudpbase identity";
+
+/// Public Ed25519 key identifying a peer
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerIdentity(pub [u8; 32]);
+
+impl PeerIdentity {
+    /// The raw public key
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PeerIdentity(")?;
+        for byte in &self.0[..8] {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "..)")
+    }
+}
+
+/// Private Ed25519 key of this instance
+#[derive(Clone)]
+pub struct IdentityKey(SigningKey);
+
+impl IdentityKey {
+    /// Key from its 32-byte secret, generated by the application with a secure random
+    /// number generator
+    pub fn from_bytes(secret: &[u8; 32]) -> Self {
+        Self(SigningKey::from_bytes(secret))
+    }
+
+    /// The public identity peers see
+    pub fn identity(&self) -> PeerIdentity {
+        PeerIdentity(self.0.verifying_key().to_bytes())
+    }
+}
+
+/// Keeps the secret out of logs
+impl fmt::Debug for IdentityKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IdentityKey").field(&self.identity()).finish()
+    }
+}
+
+/// Application check of a verified identity
+type Verifier = Box<dyn Fn(SocketAddr, &PeerIdentity) -> Verdict + Send + Sync>;
+
+/// Outcome of checking a received packet against the identity policy
+pub(crate) enum Check {
+    /// Handle the packet
+    Accept,
+    /// Handle the packet; it carried a newly verified identity
+    Established(PeerIdentity),
+    /// The packet presented an identity that failed verification or was refused
+    Invalid,
+    /// The peer has to identify itself first; drop the packet
+    Unverified,
+}
+
+/// Identity key, verification policy and verified identities of an instance
+#[derive(Default)]
+pub(crate) struct Identities {
+    key: Option<IdentityKey>,
+    verifier: Option<Verifier>,
+    pins: HashMap<SocketAddr, PeerIdentity>,
+    verified: HashMap<SocketAddr, PeerIdentity>,
+    /// Address each verified identity was last seen from
+    addrs: HashMap<PeerIdentity, SocketAddr>,
+    /// Peers our identity was sent to since their state was created
+    announced: HashSet<SocketAddr>,
+}
+
+impl Identities {
+    pub(crate) fn set_key(&mut self, key: IdentityKey) {
+        self.key = Some(key);
+        self.announced.clear();
+    }
+
+    pub(crate) fn set_verifier(&mut self, verifier: Verifier) {
+        self.verifier = Some(verifier);
+    }
+
+    pub(crate) fn clear_verifier(&mut self) {
+        self.verifier = None;
+    }
+
+    pub(crate) fn pin(&mut self, addr: SocketAddr, identity: PeerIdentity) {
+        self.pins.insert(addr, identity);
+        if self.verified.get(&addr).is_some_and(|verified| *verified != identity) {
+            self.unverify(addr);
+        }
+    }
+
+    pub(crate) fn unpin(&mut self, addr: SocketAddr) {
+        self.pins.remove(&addr);
+    }
+
+    pub(crate) fn verified(&self, addr: SocketAddr) -> Option<PeerIdentity> {
+        self.verified.get(&addr).copied()
+    }
+
+    /// Address a verified identity was last seen from
+    pub(crate) fn addr(&self, identity: PeerIdentity) -> Option<SocketAddr> {
+        self.addrs.get(&identity).copied()
+    }
+
+    /// Whether a ping should be sent to `addr` to present our identity
+    pub(crate) fn needs_announce(&self, addr: SocketAddr) -> bool {
+        self.key.is_some() && !self.announced.contains(&addr)
+    }
+
+    /// Append our signed identity to a ping or ping acknowledgment for `target`
+    pub(crate) fn announce(&mut self, packet_type: PacketType, seq: u32, connection_id: ConnectionId, data: &[u8], target: SocketAddr, out: &mut Vec<u8>) {
+        let Some(key) = &self.key else {
+            return;
+        };
+        let signature = key.0.sign(&signed_message(packet_type, seq, connection_id, data));
+        out.extend_from_slice(&[EXTENSION_IDENTITY, ENTRY_SIZE as u8]);
+        out.extend_from_slice(key.0.verifying_key().as_bytes());
+        out.extend_from_slice(&signature.to_bytes());
+        self.announced.insert(target);
+    }
+
+    /// Check a packet from `from` that passed the security code check
+    pub(crate) fn check(&mut self, packet: &RawPacketRef<'_>, from: SocketAddr, now: SystemTime) -> Check {
+        if packet.packet_type == PacketType::Batch {
+            // Each packet in it is checked on its own
+            return Check::Accept;
+        }
+        let handshake = matches!(packet.packet_type, PacketType::Ping | PacketType::PingAck);
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_IDENTITY).filter(|_| handshake);
+        if let Some(entry) = entry {
+            let Some(identity) = verify_entry(entry.value, packet, now) else {
+                return Check::Invalid;
+            };
+            if self.pins.get(&from).is_some_and(|pin| *pin != identity) {
+                return Check::Invalid;
+            }
+            if self.verifier.as_ref().is_some_and(|verifier| verifier(from, &identity) == Verdict::Reject) {
+                return Check::Invalid;
+            }
+            return match self.verified.get(&from) {
+                Some(previous) if *previous == identity => Check::Accept,
+                _ => {
+                    self.unverify(from);
+                    self.verified.insert(from, identity);
+                    self.addrs.insert(identity, from);
+                    Check::Established(identity)
+                }
+            };
+        }
+        let required = self.verifier.is_some() || self.pins.contains_key(&from);
+        if required && !self.verified.contains_key(&from) {
+            Check::Unverified
+        } else {
+            Check::Accept
+        }
+    }
+
+    /// Forget the verified identity of a peer whose state was removed
+    pub(crate) fn remove_peer(&mut self, addr: SocketAddr) {
+        self.unverify(addr);
+        self.announced.remove(&addr);
+    }
+
+    pub(crate) fn move_peer(&mut self, old: SocketAddr, new: SocketAddr) {
+        if self.verified.contains_key(&old) {
+            self.unverify(new);
+        }
+        for map in [&mut self.pins, &mut self.verified] {
+            if let Some(identity) = map.remove(&old) {
+                map.insert(new, identity);
+            }
+        }
+        if self.announced.remove(&old) {
+            self.announced.insert(new);
+        }
+        if let Some(identity) = self.verified.get(&new) {
+            self.addrs.insert(*identity, new);
+        }
+    }
+
+    fn unverify(&mut self, addr: SocketAddr) {
+        if let Some(identity) = self.verified.remove(&addr) {
+            if self.addrs.get(&identity) == Some(&addr) {
+                self.addrs.remove(&identity);
+            }
+        }
+    }
+}
+
+fn signed_message(packet_type: PacketType, seq: u32, connection_id: ConnectionId, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(CONTEXT.len() + 13 + data.len());
+    message.extend_from_slice(CONTEXT);
+    message.push(packet_type as u8);
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(&connection_id.0.to_be_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// The identity in `value` if its signature and send time check out
+fn verify_entry(value: &[u8], packet: &RawPacketRef<'_>, now: SystemTime) -> Option<PeerIdentity> {
+    let value: &[u8; ENTRY_SIZE] = value.try_into().ok()?;
+    let (public, signature) = value.split_at(32);
+    let public: [u8; 32] = public.try_into().ok()?;
+    let key = VerifyingKey::from_bytes(&public).ok()?;
+    let signature = Signature::from_bytes(signature.try_into().ok()?);
+
+    let sent = Duration::from_nanos(PingPacket::deserialize(packet.data)?.timestamp);
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    if sent.abs_diff(now) > IDENTITY_MAX_AGE {
+        return None;
+    }
+
+    let connection_id = ConnectionId::find(packet)?;
+    let message = signed_message(packet.packet_type, packet.seq, connection_id, packet.data);
+    key.verify_strict(&message, &signature).ok()?;
+    Some(PeerIdentity(public))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RawPacket;
+
+    fn ping(identities: &mut Identities, data: Vec<u8>) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        let connection_id = ConnectionId(7);
+        connection_id.encode(&mut extensions);
+        identities.announce(PacketType::Ping, 1, connection_id, &data, "10.0.0.2:1".parse().unwrap(), &mut extensions);
+        RawPacket { packet_type: PacketType::Ping, security_code: 0, seq: 1, extensions, data }.serialize()
+    }
+
+    #[test]
+    fn test_signed_ping_verifies_once_and_expires() {
+        let from: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let key = IdentityKey::from_bytes(&[9; 32]);
+        let mut signer = Identities::default();
+        signer.set_key(key.clone());
+        let datagram = ping(&mut signer, PingPacket::new().serialize());
+        let packet = RawPacketRef::parse(&datagram).unwrap();
+
+        let mut receiver = Identities::default();
+        receiver.pin(from, key.identity());
+        let now = SystemTime::now();
+        assert!(matches!(receiver.check(&packet, from, now), Check::Established(identity) if identity == key.identity()));
+        assert!(matches!(receiver.check(&packet, from, now), Check::Accept));
+        receiver.remove_peer(from);
+        assert!(matches!(receiver.check(&packet, from, now + IDENTITY_MAX_AGE * 2), Check::Invalid));
+
+        // Another key is refused for the pinned address
+        let mut impostor = Identities::default();
+        impostor.set_key(IdentityKey::from_bytes(&[3; 32]));
+        let datagram = ping(&mut impostor, PingPacket::new().serialize());
+        assert!(matches!(receiver.check(&RawPacketRef::parse(&datagram).unwrap(), from, now), Check::Invalid));
+    }
+}
@@ -0,0 +1,160 @@
+//! Per-source accounting of invalid packets
+//!
+//! [`InvalidSources`] counts the packets each source sent that failed verification or
+//! parsing. A source that sends more than `max_invalid_packets` of them within
+//! `invalid_packet_window` is blocked: everything it sends is dropped unanswered for
+//! one window, which stops garbage floods from producing errors and replies. At most
+//! `max_peers` sources are tracked; the one with the oldest invalid packet is
+//! forgotten first.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::config::LimitsConfig;
+use crate::peers::PeerActivity;
+use crate::stats::InvalidPacketStats;
+
+/// Why a packet was discarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InvalidKind {
+    Security,
+    Parse,
+    UnknownType,
+    Checksum,
+}
+
+/// Invalid packet history of one source
+#[derive(Debug)]
+struct Source {
+    stats: InvalidPacketStats,
+    window_start: Instant,
+    /// Invalid packets since `window_start`
+    window_count: u64,
+    blocked_until: Option<Instant>,
+}
+
+/// Invalid packet counts of recent offenders
+#[derive(Debug, Default)]
+pub(crate) struct InvalidSources {
+    sources: HashMap<SocketAddr, Source>,
+    /// Bounds `sources`; ordered by the latest invalid packet
+    order: PeerActivity,
+    /// Counts of all sources, including forgotten ones
+    totals: InvalidPacketStats,
+}
+
+impl InvalidSources {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an invalid packet from `from`; returns true if the source is now blocked
+    pub(crate) fn record(&mut self, from: SocketAddr, kind: InvalidKind, limits: &LimitsConfig, now: Instant) -> bool {
+        add(&mut self.totals, kind);
+        if self.order.touch(from) {
+            while self.order.len() > limits.max_peers {
+                let Some(oldest) = self.order.least_recent() else {
+                    break;
+                };
+                self.order.remove(oldest);
+                self.sources.remove(&oldest);
+            }
+        }
+
+        let source = self.sources.entry(from).or_insert_with(|| Source {
+            stats: InvalidPacketStats::default(),
+            window_start: now,
+            window_count: 0,
+            blocked_until: None,
+        });
+        add(&mut source.stats, kind);
+        if now.saturating_duration_since(source.window_start) >= limits.invalid_packet_window {
+            source.window_start = now;
+            source.window_count = 0;
+        }
+        source.window_count += 1;
+        if source.window_count > limits.max_invalid_packets && source.blocked_until.is_none_or(|until| until <= now) {
+            source.blocked_until = Some(now + limits.invalid_packet_window);
+            return true;
+        }
+        false
+    }
+
+    /// Whether packets from `from` are currently ignored
+    pub(crate) fn is_blocked(&self, from: SocketAddr, now: Instant) -> bool {
+        self.sources
+            .get(&from)
+            .and_then(|source| source.blocked_until)
+            .is_some_and(|until| now < until)
+    }
+
+    pub(crate) fn stats(&self, from: SocketAddr) -> Option<InvalidPacketStats> {
+        self.sources.get(&from).map(|source| source.stats)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (SocketAddr, &InvalidPacketStats)> {
+        self.sources.iter().map(|(addr, source)| (*addr, &source.stats))
+    }
+
+    pub(crate) fn totals(&self) -> InvalidPacketStats {
+        self.totals
+    }
+}
+
+fn add(stats: &mut InvalidPacketStats, kind: InvalidKind) {
+    match kind {
+        InvalidKind::Security => stats.security_failures += 1,
+        InvalidKind::Parse => stats.parse_errors += 1,
+        InvalidKind::UnknownType => stats.unknown_types += 1,
+        InvalidKind::Checksum => stats.checksum_failures += 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_blocks_over_threshold_for_one_window() {
+        let from: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let start = Instant::now();
+        let limits = LimitsConfig {
+            max_invalid_packets: 2,
+            invalid_packet_window: Duration::from_secs(1),
+            ..LimitsConfig::default()
+        };
+        let mut sources = InvalidSources::new();
+
+        assert!(!sources.record(from, InvalidKind::Security, &limits, start));
+        assert!(!sources.record(from, InvalidKind::Parse, &limits, start));
+        assert!(!sources.is_blocked(from, start));
+        assert!(sources.record(from, InvalidKind::UnknownType, &limits, start));
+        assert!(sources.is_blocked(from, start + Duration::from_millis(999)));
+        assert!(!sources.is_blocked(from, start + Duration::from_secs(1)));
+
+        let stats = sources.stats(from).unwrap();
+        assert_eq!((stats.security_failures, stats.parse_errors, stats.unknown_types), (1, 1, 1));
+        assert_eq!(sources.totals().total(), 3);
+
+        // A new window starts the count again
+        let later = start + Duration::from_secs(2);
+        assert!(!sources.record(from, InvalidKind::Security, &limits, later));
+        assert!(!sources.is_blocked(from, later));
+    }
+
+    #[test]
+    fn test_tracked_sources_are_bounded() {
+        let now = Instant::now();
+        let limits = LimitsConfig { max_peers: 2, ..LimitsConfig::default() };
+        let mut sources = InvalidSources::new();
+        for i in 1..=3 {
+            let from: SocketAddr = format!("10.0.0.{}:1", i).parse().unwrap();
+            sources.record(from, InvalidKind::Parse, &limits, now);
+        }
+        assert_eq!(sources.iter().count(), 2);
+        assert!(sources.stats("10.0.0.1:1".parse().unwrap()).is_none());
+        assert_eq!(sources.totals().parse_errors, 3);
+    }
+}
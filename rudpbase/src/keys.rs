@@ -0,0 +1,160 @@
+//! Per-peer authentication keys and key rotation
+//!
+//! A peer registered with [`RudpCore::set_peer_key`](crate::RudpCore::set_peer_key)
+//! has the security codes of its packets computed and checked with that key's
+//! [`PacketAuthenticator`] instead of the instance-wide one. Each key has a one-byte
+//! id, written into an [`EXTENSION_KEY_ID`] entry of every packet that carries an
+//! extension section, so the receiver knows which key to check against. Packets
+//! without the entry are checked against each key still accepted.
+//!
+//! Registering a key with a new id rotates: the new key signs everything sent from
+//! then on, and the previous one stays accepted for [`KEY_ROTATION_GRACE`] so
+//! packets already in flight, and those the peer sends before it rotates too, are
+//! not dropped.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{RawPacketRef, EXTENSION_KEY_ID};
+use crate::security::{PacketAuthenticator, SipHash};
+
+/// How long the previous key of a peer stays accepted after a rotation
+pub const KEY_ROTATION_GRACE: Duration = Duration::from_secs(60);
+
+/// Authentication key of one peer
+#[derive(Clone)]
+pub struct PeerKey {
+    id: u8,
+    authenticator: Arc<dyn PacketAuthenticator>,
+}
+
+impl PeerKey {
+    /// Key `id` computing security codes with `authenticator`
+    pub fn new(id: u8, authenticator: Arc<dyn PacketAuthenticator>) -> Self {
+        Self { id, authenticator }
+    }
+
+    /// Key `id` computing security codes with SipHash-2-4 keyed with `key`
+    pub fn siphash(id: u8, key: [u8; 16]) -> Self {
+        Self::new(id, Arc::new(SipHash::new(key)))
+    }
+
+    /// Id carried by packets signed with this key
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub(crate) fn authenticator(&self) -> &dyn PacketAuthenticator {
+        &*self.authenticator
+    }
+
+    /// Size of the TLV entry written by [`encode_id`](Self::encode_id)
+    pub(crate) const ID_ENTRY_SIZE: usize = 3;
+
+    /// Append the TLV encoding of this key's id to `out`
+    pub(crate) fn encode_id(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_KEY_ID, 1, self.id]);
+    }
+}
+
+/// Keeps key material out of logs
+impl fmt::Debug for PeerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// The key id entry of `packet`'s extension section
+pub(crate) fn find_key_id(packet: &RawPacketRef<'_>) -> Option<u8> {
+    match packet.extensions().find(|entry| entry.kind == EXTENSION_KEY_ID)?.value {
+        [id] => Some(*id),
+        _ => None,
+    }
+}
+
+/// Current and previous key of one peer
+#[derive(Debug, Clone)]
+pub(crate) struct KeyRing {
+    current: PeerKey,
+    /// Key replaced by the last rotation, accepted until the deadline
+    previous: Option<(PeerKey, Instant)>,
+}
+
+impl KeyRing {
+    pub(crate) fn new(key: PeerKey) -> Self {
+        Self { current: key, previous: None }
+    }
+
+    /// Key that signs outgoing packets
+    pub(crate) fn current(&self) -> &PeerKey {
+        &self.current
+    }
+
+    /// Make `key` the current key; a key with a new id keeps the old one accepted
+    /// for [`KEY_ROTATION_GRACE`], one with the current id simply replaces it
+    pub(crate) fn rotate(&mut self, key: PeerKey, now: Instant) {
+        if key.id == self.current.id {
+            self.current = key;
+            return;
+        }
+        let previous = std::mem::replace(&mut self.current, key);
+        self.previous = Some((previous, now + KEY_ROTATION_GRACE));
+    }
+
+    /// Accepted keys, newest first
+    fn accepted(&self, now: Instant) -> impl Iterator<Item = &PeerKey> {
+        let previous = self.previous.as_ref().filter(|(_, deadline)| now < *deadline).map(|(key, _)| key);
+        std::iter::once(&self.current).chain(previous)
+    }
+
+    /// Whether the packet's security code verifies under the key it names, or under
+    /// any accepted key when it names none
+    pub(crate) fn verify(&self, packet: &RawPacketRef<'_>, body: &[u8], now: Instant) -> bool {
+        let key_id = find_key_id(packet);
+        self.accepted(now)
+            .filter(|key| key_id.is_none_or(|id| id == key.id))
+            .any(|key| key.authenticator.verify(packet.packet_type, packet.seq, body, packet.security_code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PacketType, RawPacket, PROTOCOL_HEADER_SIZE};
+
+    /// Data packet naming `id`, signed with `signer`
+    fn signed(signer: &PeerKey, id: Option<u8>) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        if let Some(id) = id {
+            extensions.extend_from_slice(&[EXTENSION_KEY_ID, 1, id]);
+        }
+        let mut packet = RawPacket { packet_type: PacketType::Data, security_code: 0, seq: 3, extensions, data: b"rotate".to_vec() };
+        packet.security_code = signer.authenticator().code(PacketType::Data, 3, &packet.body());
+        packet.serialize()
+    }
+
+    fn verifies(ring: &KeyRing, datagram: &[u8], now: Instant) -> bool {
+        let packet = RawPacketRef::parse(datagram).unwrap();
+        ring.verify(&packet, &datagram[PROTOCOL_HEADER_SIZE..], now)
+    }
+
+    #[test]
+    fn test_previous_key_accepted_during_grace() {
+        let now = Instant::now();
+        let old = PeerKey::siphash(1, [1; 16]);
+        let new = PeerKey::siphash(2, [2; 16]);
+        let mut ring = KeyRing::new(old.clone());
+        ring.rotate(new.clone(), now);
+        assert_eq!(ring.current().id(), 2);
+
+        for (key, id) in [(&new, Some(2)), (&new, None), (&old, Some(1)), (&old, None)] {
+            assert!(verifies(&ring, &signed(key, id), now));
+        }
+        assert!(!verifies(&ring, &signed(&old, Some(1)), now + KEY_ROTATION_GRACE));
+        assert!(!verifies(&ring, &signed(&old, None), now + KEY_ROTATION_GRACE));
+
+        // A packet naming one key is not checked against the other
+        assert!(!verifies(&ring, &signed(&old, Some(2)), now));
+    }
+}
@@ -7,9 +7,52 @@
 //! - **Reliable transmission**: Ensures no packet loss through acknowledgment and retransmission
 //! - **High performance**: 9-byte protocol header, no encryption overhead
 //! - **Simple design**: Focus on reliability without packet ordering
-//! - **Security**: 4-byte security code with salt protection
+//! - **Security**: 4-byte security code with salt protection; `RudpConfig::with_authenticator()` swaps the salted FNV-1a default for CRC32C, keyed SipHash, none at all or any `PacketAuthenticator`
+//! - **Payload integrity**: `with_payload_checksum(true)` adds a CRC32C of the whole payload to data packets, so corruption past the bytes the security code covers is caught and counted separately
+//! - **Per-peer keys**: `set_peer_key()` authenticates a peer with its own key, named by id in each packet; setting a key with a new id rotates without dropping the connection
+//! - **Length hiding**: `RudpConfig::padding` pads data packets up to fixed datagram sizes so traffic analysis sees buckets instead of message lengths
+//! - **Peer identities**: `set_identity()` signs the ping handshake with an Ed25519 key; peers check it against a pinned key or a callback and report it in `ConnectionEvent::Established` (enable the `identity` feature)
 //! - **High bandwidth support**: 4-byte sequence numbers support up to 4.2 billion packets
 //! - **Memory pool**: Zero-copy buffer management for optimal performance
+//! - **Control coalescing**: `with_coalesce_control(true)` sends the acknowledgments and pings queued for a peer in one tick as a single Batch datagram
+//! - **Small-message coalescing**: `with_coalesce_delay(Some(delay))` bundles small payloads sent to a peer within `delay` into one packet; `flush_now()` sends them at once
+//! - **Per-peer payload limits**: `set_peer_max_payload()` lowers the payload size for one peer; sends check it before anything else and report it in `RudpError::BufferTooLarge`
+//! - **Adaptive redundancy**: `send_critical()` sends a packet twice, a few milliseconds apart, while the peer's estimated loss rate is above `RedundancyConfig::loss_threshold`, cutting the tail latency of single losses
+//! - **Heartbeat payloads**: `set_heartbeat()` attaches a small application payload to every ping and ping acknowledgment; peers read it with `peer_heartbeat()` or `EventHandler::on_heartbeat`
+//! - **Clock offset**: ping acknowledgments carry the peer's reply time, so `clock_offset()` estimates how far its clock is from ours and the one-way delay in each direction
+//! - **Receiver limits**: `LimitsConfig` caps tracked peers, reassembly memory and receive queues, evicting the least recently active peer with a Close and a `ConnectionEvent::Evicted` and pushing back on senders when full
+//! - **Bandwidth limit**: `max_bandwidth` paces all outbound datagrams of an instance to a bytes-per-second budget, however many peers are active
+//! - **Admission control**: `set_peer_filter()` accepts or rejects each new source address before any state is created for it; invalid packets are counted per source, and sources flooding them are ignored for a while
+//! - **Chaos testing**: `testing::Scenario` scripts phases of loss, latency, jitter, duplication and partitions, replays them on a virtual clock between two in-memory cores and reports broken delivery and statistics invariants
+//! - **Bottleneck simulation**: `testing::BottleneckScenario` runs bulk flows through a link of limited rate and queue depth, with drop-tail or RED drops, and reports per-flow throughput, link utilization and fairness
+//! - **Stats reporting**: `enable_stats_reporting()` hands a snapshot of the global and per-peer statistics to a `report::StatsSink` every interval; `report::StatsJsonWriter` writes one JSON line per report
+//! - **Alerts**: `RudpConfig::alerts` sets thresholds on per-peer loss rate, RTT and retransmit ratio; `EventHandler::on_alert` is called when a peer crosses or clears one
+//! - **Outage detection**: timeouts are grouped until the peer is heard from again and reported as `ConnectionEvent::LossBurst` (several packets lost once, as under congestion) or `ConnectionEvent::Outage` (a packet lost through backed-off RTOs, as when a link flaps), with their duration, and counted in `ConnectionStats`
+//! - **Delivery tracking**: `send_tracked()` returns a future that resolves when the peer acknowledges that packet
+//! - **In-flight control**: `cancel()` stops retransmitting or sending a packet that is no longer needed, `resend()` retransmits one without waiting for its timeout
+//! - **Scheduled sends**: `send_after()` and `send_at()` hand data to the instance's timer, which sends it when due
+//! - **0-RTT reconnection**: with `RudpConfig::resumption` set, peers receive resumption tokens; a client that reconnects with `resume()` has its first data delivered without a round trip
+//! - **Hostname connect**: `connect_host()` resolves a name and tries its addresses IPv6 first, starting the next one every 250ms (happy eyeballs), and keeps the first that answers for the session; `connect_any()` does the same for a given address list
+//! - **Peer ids**: `PeerId` names a peer by its verified identity or connection id instead of its address; `send_to_peer()`, `peer_stats()` and `EventHandler::on_peer_event` use it, and `peer_addr()` follows the peer across migrations
+//! - **Connection migration**: pings carry a random connection id, so a peer that shows up at a new address keeps its state; `rebind()` swaps the local socket at runtime
+//! - **Pre-bound sockets**: `from_socket()`/`from_std()` take a socket the application already configured and bound, such as one with `SO_REUSEPORT` built with `socket2` or a descriptor inherited from systemd
+//! - **Restart without disconnecting**: `export_state()`/`import_state()` carry sequence numbers, receive windows, unacknowledged packets and RTT estimates into a restarted process, so peers see neither a dead connection nor duplicates
+//! - **Sans-IO core**: `RudpCore` holds the protocol state machine without sockets or clocks, so it can be driven from any I/O model
+//! - **Sharded core**: `ShardedCore` splits peers by address hash across independently locked `RudpCore`s, so peers in different shards are processed in parallel
+//! - **Runtime choice**: tokio by default; `smol::Rudpbase` and `async_std::Rudpbase` drive the same core without tokio (enable the `smol` or `async-std` feature, optionally with `default-features = false`)
+//! - **Blocking API**: `sync::Rudpbase` runs on `std::net::UdpSocket` with a maintenance thread, for programs without an async runtime (enable the `sync` feature)
+//! - **Typed messages**: `typed::TypedChannel<T>` sends and receives serde values encoded with postcard into pooled buffers (enable the `serde` feature)
+//! - **Observability**: Optional `tracing` instrumentation (enable the `tracing` feature)
+//! - **OpenTelemetry**: `set_telemetry()` reports handshake, per-packet delivery and connection lifetime spans and exports RTT, delivery and connection metrics through the application's OpenTelemetry pipeline (enable the `otel` feature)
+//! - **Relay fallback**: `relay::Relay` forwards packets between peers that registered with it, for when hole punching fails
+//! - **Compression**: LZ4/zstd payload compression negotiated with each peer (enable the `lz4` or `zstd` feature)
+//! - **Stream transfer**: `transfer::send_stream()`/`recv_stream()` copy an `AsyncRead` to a peer's `AsyncWrite` with progress and resume (enable the `transfer` feature)
+//! - **RPC**: `rpc::spawn()` runs request/response calls with per-call timeouts, many in flight at once over one instance (enable the `rpc` feature)
+//! - **Publish/subscribe**: `pubsub::PubSub` keeps topic subscriptions per connection and fans each publication out reliably to current subscribers (enable the `pubsub` feature)
+//! - **Receive task**: `task::spawn()` reads the socket and sends ACKs in a background task, handing received data to the application through a bounded channel so slow processing does not delay acknowledgments (enable the `task` feature)
+//! - **Turmoil simulation**: `turmoil::TurmoilTransport` runs instances as hosts of a turmoil simulation, with `TokioClock` keeping protocol timers on simulated time, for reproducible multi-node tests across partitions (enable the `turmoil` feature)
+//! - **TCP fallback**: `tcp::FallbackTransport` switches peers that cannot be reached over UDP to the same packets framed over TCP, behind the unchanged `Rudpbase` API (enable the `tcp` feature)
+//! - **`bytes` interop**: `send_bytes()` and zero-copy `PooledBuffer` to `Bytes` conversion (enable the `bytes` feature)
 //! 
 //! ## Usage
 //! 
@@ -18,6 +61,7 @@
 //! ```rust
 //! use rudpbase::Rudpbase;
 //! use std::net::SocketAddr;
+//! use std::time::Duration;
 //! 
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,8 +74,8 @@
 //!     buffer.set_data_len(data.len())?;
 //!     rudp.send(buffer, "127.0.0.1:8081".parse()?).await?;
 //!     
-//!     // 接收数据
-//!     if let Some(received) = rudp.recv().await {
+//!     // 接收数据，最多等待100ms
+//!     if let Some(received) = rudp.recv_timeout(Duration::from_millis(100)).await {
 //!         match received.result {
 //!             Ok(buffer) => {
 //!                 println!("Received from {}: {:?}", received.from, buffer.data());
@@ -46,21 +90,117 @@
 //! }
 //! ```
 
+// Without a runtime adapter only the sans-IO core is built and the crate-internal
+// helpers the adapters share go unused
+#![cfg_attr(not(any(feature = "tokio", feature = "smol", feature = "async-std", feature = "sync")), allow(dead_code))]
+
+#[cfg(feature = "tokio")]
 use std::net::SocketAddr;
 
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "tokio")]
 pub mod core;
+pub mod engine;
+pub mod config;
 pub mod protocol;
 pub mod error;
+pub mod events;
 pub mod stats;
 pub mod security;
 pub mod buffer_pool;
+pub mod stun;
+pub mod qlog;
+pub mod report;
+#[cfg(feature = "tokio")]
+pub mod transport;
+#[cfg(feature = "tokio")]
+pub mod loopback;
+#[cfg(feature = "tokio")]
+pub mod sim;
+pub mod clock;
+pub mod message;
+pub mod delivery;
+pub mod resumption;
+pub mod keys;
+pub mod peer_id;
+pub mod compression;
+pub mod shard;
+pub mod testing;
+mod window;
+mod peers;
+mod pacing;
+mod invalid;
+mod alert;
+mod outage;
+mod redundancy;
+mod delay;
+mod rng;
+mod session;
+#[cfg(feature = "tokio")]
+pub mod relay;
+#[cfg(feature = "transfer")]
+pub mod transfer;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+#[cfg(feature = "task")]
+pub mod task;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(feature = "identity")]
+pub mod identity;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "turmoil")]
+pub mod turmoil;
+#[cfg(feature = "smol")]
+pub mod smol;
+#[cfg(feature = "async-std")]
+pub mod async_std;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "serde")]
+pub mod typed;
+#[cfg(feature = "tokio")]
+mod batch;
+#[cfg(any(feature = "tokio", feature = "smol", feature = "async-std", feature = "sync"))]
+mod socket;
+#[cfg(feature = "tokio")]
+mod uring;
+#[cfg(any(feature = "smol", feature = "async-std"))]
+mod reactor;
 
-pub use core::{Rudpbase, ReceivedData};
+#[cfg(feature = "tokio")]
+pub use core::Rudpbase;
+pub use engine::{ReceivedData, RudpCore, Transmit};
+pub use shard::ShardedCore;
+pub use message::ReceivedMessage;
+pub use delivery::{DeliveryHandle, PingHandle};
+pub use resumption::ResumptionToken;
+pub use keys::PeerKey;
+pub use peer_id::PeerId;
+#[cfg(feature = "identity")]
+pub use identity::{IdentityKey, PeerIdentity};
+pub use config::{RudpConfig, KeepAliveConfig, BackoffConfig, SecurityConfig, SocketConfig, IoBackend, PoolTrimConfig, CompressionConfig, LimitsConfig, ResumptionConfig, PaddingConfig, AlertConfig, RedundancyConfig};
+pub use compression::Compression;
 pub use error::{RudpError, ConnectionError, ErrorSeverity};
-pub use stats::{ConnectionStatus, ConnectionStats, RttStats, ConnectionState, CongestionInfo, CongestionState};
-pub use protocol::{PacketType, PROTOCOL_HEADER_SIZE};
-pub use security::SecurityCode;
-pub use buffer_pool::{PooledBuffer, SharedBufferPool, PoolStats};
+pub use events::{Alert, AlertKind, ConnectionEvent, EventHandler, Verdict};
+pub use stats::{ConnectionStatus, ConnectionStats, RttStats, ConnectionState, CongestionInfo, CongestionState, OneWayDelay, ClockOffset, GlobalStats, InvalidPacketStats, LatencyHistogram, LatencyPercentiles};
+pub use protocol::{PacketType, CloseReason, PROTOCOL_HEADER_SIZE, MAX_CLOSE_PAYLOAD, MAX_HEARTBEAT_SIZE};
+pub use security::{SecurityCode, PacketAuthenticator, SaltedFnv, Crc32c, SipHash, NoAuthentication};
+#[cfg(feature = "tokio")]
+pub use transport::Transport;
+#[cfg(feature = "tokio")]
+pub use loopback::{LoopbackNetwork, LoopbackTransport};
+#[cfg(feature = "tokio")]
+pub use sim::{Delay, SimConfig, SimStats, SimTransport};
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use buffer_pool::{PooledBuffer, SharedBufferPool, PoolStats, BUFFER_SIZE_CLASSES};
 
 /// Create a new Rudpbase instance
 /// 
@@ -71,15 +211,18 @@ pub use buffer_pool::{PooledBuffer, SharedBufferPool, PoolStats};
 /// # Returns
 /// 
 /// Returns a Result containing the Rudpbase instance or an error
+#[cfg(feature = "tokio")]
 pub async fn new_rudpbase(local_addr: SocketAddr) -> Result<Rudpbase, RudpError> {
     Rudpbase::new(local_addr).await
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "tokio"))]
 mod tests {
+    #[allow(unused_imports)]
     use super::*;
 
     #[tokio::test]
+    #[allow(clippy::assertions_on_constants)]
     async fn test_basic_functionality() {
         // Basic tests will be implemented here
         assert!(true);
@@ -0,0 +1,191 @@
+//! In-process loopback transport
+//!
+//! Connects [`Rudpbase`](crate::Rudpbase) instances through in-memory queues, with no
+//! OS sockets involved. Tests need no free ports, and datagrams are delivered
+//! instantly and in order, so exchanges run deterministically and fast.
+//!
+//! ```rust
+//! use rudpbase::{LoopbackTransport, RudpConfig, Rudpbase};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let (a, b) = LoopbackTransport::pair("10.0.0.1:1000".parse()?, "10.0.0.2:1000".parse()?);
+//!     let mut alice = Rudpbase::with_transport(a, RudpConfig::default()).await?;
+//!     let mut bob = Rudpbase::with_transport(b, RudpConfig::default()).await?;
+//!
+//!     let mut buffer = alice.get_buffer()?;
+//!     buffer.data_mut()[..2].copy_from_slice(b"hi");
+//!     buffer.set_data_len(2)?;
+//!     alice.send(buffer, "10.0.0.2:1000".parse()?).await?;
+//!
+//!     let received = bob.recv().await;
+//!     assert_eq!(received.result?.data(), b"hi");
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::transport::Transport;
+
+/// Datagrams queued per endpoint before further ones are dropped
+pub const LOOPBACK_QUEUE_CAPACITY: usize = 4096;
+
+/// Receive queue of one endpoint
+#[derive(Default)]
+struct Inbox {
+    datagrams: VecDeque<(Vec<u8>, SocketAddr)>,
+    waker: Option<Waker>,
+}
+
+type SharedInbox = Arc<Mutex<Inbox>>;
+
+/// A set of in-memory endpoints that can reach each other by address
+///
+/// Like UDP, datagrams to an address without an endpoint, or to an endpoint whose
+/// queue is full, are silently dropped.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    endpoints: Arc<Mutex<HashMap<SocketAddr, SharedInbox>>>,
+}
+
+impl LoopbackNetwork {
+    /// Create an empty network
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an endpoint at `addr`
+    ///
+    /// Returns `AddrInUse` if another live endpoint already has this address.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<LoopbackTransport> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if endpoints.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is already bound", addr)));
+        }
+        let inbox = SharedInbox::default();
+        endpoints.insert(addr, Arc::clone(&inbox));
+        Ok(LoopbackTransport {
+            addr,
+            inbox,
+            network: self.clone(),
+        })
+    }
+
+    fn deliver(&self, data: &[u8], from: SocketAddr, target: SocketAddr) {
+        let inbox = match self.endpoints.lock().unwrap().get(&target) {
+            Some(inbox) => Arc::clone(inbox),
+            None => return,
+        };
+        let mut inbox = inbox.lock().unwrap();
+        if inbox.datagrams.len() >= LOOPBACK_QUEUE_CAPACITY {
+            return;
+        }
+        inbox.datagrams.push_back((data.to_vec(), from));
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// An endpoint of a [`LoopbackNetwork`]
+pub struct LoopbackTransport {
+    addr: SocketAddr,
+    inbox: SharedInbox,
+    network: LoopbackNetwork,
+}
+
+impl LoopbackTransport {
+    /// Create two endpoints connected to each other on a private network
+    pub fn pair(addr1: SocketAddr, addr2: SocketAddr) -> (Self, Self) {
+        assert_ne!(addr1, addr2, "loopback pair needs two distinct addresses");
+        let network = LoopbackNetwork::new();
+        let first = network.bind(addr1).expect("fresh network");
+        let second = network.bind(addr2).expect("fresh network");
+        (first, second)
+    }
+
+    /// Number of datagrams waiting to be received
+    pub fn queued(&self) -> usize {
+        self.inbox.lock().unwrap().datagrams.len()
+    }
+}
+
+impl std::fmt::Debug for LoopbackTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoopbackTransport").field("addr", &self.addr).finish()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn poll_send_to(&self, _cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        self.network.deliver(buf, self.addr, target);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut inbox = self.inbox.lock().unwrap();
+        match inbox.datagrams.pop_front() {
+            Some((data, from)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Poll::Ready(Ok((len, from)))
+            }
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        self.network.endpoints.lock().unwrap().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport;
+
+    #[tokio::test]
+    async fn test_pair_delivery() {
+        let addr1 = "10.0.0.1:1".parse().unwrap();
+        let addr2 = "10.0.0.2:2".parse().unwrap();
+        let (a, b) = LoopbackTransport::pair(addr1, addr2);
+
+        transport::send_to(&a, b"ping", addr2).await.unwrap();
+        assert_eq!(b.queued(), 1);
+
+        let mut buf = [0u8; 16];
+        let (len, from) = transport::recv_from(&b, &mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(from, addr1);
+        assert_eq!(
+            transport::try_recv_from(&b, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn test_bind_and_unbind() {
+        let network = LoopbackNetwork::new();
+        let addr = "10.0.0.1:1".parse().unwrap();
+
+        let endpoint = network.bind(addr).unwrap();
+        assert_eq!(network.bind(addr).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        drop(endpoint);
+        assert!(network.bind(addr).is_ok());
+    }
+}
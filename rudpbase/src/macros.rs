@@ -0,0 +1,23 @@
+//! Internal instrumentation macros
+//!
+//! With the `tracing` feature enabled these forward to the corresponding `tracing`
+//! macros; otherwise they expand to nothing so the hot paths carry no cost.
+
+/// Emit a `tracing` event at the given level (`trace`, `debug`, `info`, `warn`, `error`)
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    }};
+}
+
+/// Call a hook of the instance's [`Telemetry`](crate::otel::Telemetry), if the `otel`
+/// feature is enabled and one is set
+macro_rules! telemetry {
+    ($telemetry:expr, $hook:ident($($arg:expr),*)) => {{
+        #[cfg(feature = "otel")]
+        if let Some(telemetry) = $telemetry.as_mut() {
+            telemetry.$hook($($arg),*);
+        }
+    }};
+}
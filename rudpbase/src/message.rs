@@ -0,0 +1,234 @@
+//! Message fragmentation and reassembly
+//!
+//! [`Rudpbase::send_message`](crate::Rudpbase::send_message) splits a message of any
+//! size (up to `max_message_size`) into fragment packets that are acknowledged and
+//! retransmitted like data packets. The receiver collects the fragments, which may
+//! arrive in any order, and [`Rudpbase::recv_message`](crate::Rudpbase::recv_message)
+//! returns each message once all of its fragments are in.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::protocol::FragmentHeader;
+
+/// Incomplete messages are discarded when no fragment arrived for this long
+pub const MESSAGE_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default upper bound of a message passed to `send_message`
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// A complete message received with `recv_message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    /// Sender address
+    pub from: SocketAddr,
+    /// Message contents
+    pub data: Vec<u8>,
+}
+
+/// Bookkeeping per expected fragment, charged when a message's first fragment arrives
+const FRAGMENT_SLOT_SIZE: usize = std::mem::size_of::<Option<Vec<u8>>>();
+
+/// Fragments of one message received so far
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    size: usize,
+    last_update: Instant,
+}
+
+impl Partial {
+    /// Memory held by this message
+    fn footprint(&self) -> usize {
+        self.size + self.fragments.len() * FRAGMENT_SLOT_SIZE
+    }
+}
+
+/// Collects fragments into complete messages
+pub(crate) struct Reassembler {
+    partial: HashMap<(SocketAddr, u32), Partial>,
+    /// Sum of the footprints of all incomplete messages
+    pending_bytes: usize,
+}
+
+impl Reassembler {
+    pub(crate) fn new() -> Self {
+        Self {
+            partial: HashMap::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    /// Memory held by incomplete messages
+    pub(crate) fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    /// Memory that inserting a fragment of `len` bytes would add
+    pub(crate) fn insert_cost(&self, from: SocketAddr, header: FragmentHeader, len: usize) -> usize {
+        if header.count == 1 || self.partial.contains_key(&(from, header.message_id)) {
+            len
+        } else {
+            len + header.count as usize * FRAGMENT_SLOT_SIZE
+        }
+    }
+
+    /// Add a fragment, returning the message if it is now complete
+    ///
+    /// Messages growing beyond `max_size` bytes are discarded.
+    pub(crate) fn insert(
+        &mut self,
+        from: SocketAddr,
+        header: FragmentHeader,
+        chunk: &[u8],
+        max_size: usize,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        if header.count == 1 {
+            return (chunk.len() <= max_size).then(|| chunk.to_vec());
+        }
+        if header.count as usize > max_size {
+            return None;
+        }
+
+        let key = (from, header.message_id);
+        let partial = self.partial.entry(key).or_insert_with(|| {
+            self.pending_bytes += header.count as usize * FRAGMENT_SLOT_SIZE;
+            Partial {
+                fragments: vec![None; header.count as usize],
+                received: 0,
+                size: 0,
+                last_update: now,
+            }
+        });
+        let index = header.index as usize;
+        // A fragment count that disagrees with earlier fragments is malformed
+        if partial.fragments.len() != header.count as usize || partial.fragments[index].is_some() {
+            return None;
+        }
+        partial.size += chunk.len();
+        self.pending_bytes += chunk.len();
+        if partial.size > max_size {
+            trace_event!(debug, %from, message_id = header.message_id, "discarding oversized message");
+            self.remove(key);
+            return None;
+        }
+        partial.fragments[index] = Some(chunk.to_vec());
+        partial.received += 1;
+        partial.last_update = now;
+
+        if partial.received < partial.fragments.len() {
+            return None;
+        }
+        let partial = self.remove(key)?;
+        let mut message = Vec::with_capacity(partial.size);
+        for fragment in partial.fragments.into_iter().flatten() {
+            message.extend_from_slice(&fragment);
+        }
+        Some(message)
+    }
+
+    /// Drop messages that have not made progress within `timeout`
+    pub(crate) fn expire(&mut self, now: Instant, timeout: Duration) {
+        self.retain(|_, partial| now.saturating_duration_since(partial.last_update) < timeout);
+    }
+
+    /// Drop all incomplete messages from `addr`
+    pub(crate) fn remove_peer(&mut self, addr: SocketAddr) {
+        self.retain(|(from, _), _| *from != addr);
+    }
+
+    /// Move the incomplete messages of `old` to `new` after the peer changed address
+    pub(crate) fn move_peer(&mut self, old: SocketAddr, new: SocketAddr) {
+        let keys: Vec<(SocketAddr, u32)> = self.partial.keys().filter(|(from, _)| *from == old).copied().collect();
+        for key in keys {
+            if let Some(partial) = self.partial.remove(&key) {
+                self.partial.insert((new, key.1), partial);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: (SocketAddr, u32)) -> Option<Partial> {
+        let partial = self.partial.remove(&key)?;
+        self.pending_bytes -= partial.footprint();
+        Some(partial)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&(SocketAddr, u32), &Partial) -> bool) {
+        let pending_bytes = &mut self.pending_bytes;
+        self.partial.retain(|key, partial| {
+            let kept = keep(key, partial);
+            if !kept {
+                *pending_bytes -= partial.footprint();
+            }
+            kept
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(message_id: u32, index: u16, count: u16) -> FragmentHeader {
+        FragmentHeader { message_id, index, count }
+    }
+
+    #[test]
+    fn test_reassembles_out_of_order() {
+        let from = "10.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+
+        assert_eq!(reassembler.insert(from, header(1, 2, 3), b"c", 100, now), None);
+        assert_eq!(reassembler.insert(from, header(1, 0, 3), b"a", 100, now), None);
+        // Duplicates are ignored
+        assert_eq!(reassembler.insert(from, header(1, 0, 3), b"a", 100, now), None);
+        assert_eq!(reassembler.insert(from, header(1, 1, 3), b"b", 100, now), Some(b"abc".to_vec()));
+        assert_eq!(reassembler.partial.len(), 0);
+
+        assert_eq!(reassembler.insert(from, header(2, 0, 1), b"single", 100, now), Some(b"single".to_vec()));
+    }
+
+    #[test]
+    fn test_limits_and_expiry() {
+        let from = "10.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+
+        assert_eq!(reassembler.insert(from, header(1, 0, 2), b"abc", 5, now), None);
+        assert_eq!(reassembler.insert(from, header(1, 1, 2), b"def", 5, now), None);
+        assert_eq!(reassembler.partial.len(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+
+        reassembler.insert(from, header(2, 0, 2), b"a", 5, now);
+        assert_eq!(reassembler.pending_bytes(), 1 + 2 * FRAGMENT_SLOT_SIZE);
+        reassembler.expire(now + MESSAGE_REASSEMBLY_TIMEOUT / 2, MESSAGE_REASSEMBLY_TIMEOUT);
+        assert_eq!(reassembler.partial.len(), 1);
+        reassembler.expire(now + MESSAGE_REASSEMBLY_TIMEOUT, MESSAGE_REASSEMBLY_TIMEOUT);
+        assert_eq!(reassembler.partial.len(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn test_insert_cost_and_accounting() {
+        let from = "10.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+
+        // The first fragment also pays for the slots of the whole message
+        assert_eq!(reassembler.insert_cost(from, header(1, 0, 3), 4), 4 + 3 * FRAGMENT_SLOT_SIZE);
+        reassembler.insert(from, header(1, 0, 3), b"abcd", 100, now);
+        assert_eq!(reassembler.insert_cost(from, header(1, 1, 3), 4), 4);
+        reassembler.insert(from, header(1, 1, 3), b"efgh", 100, now);
+        assert_eq!(reassembler.pending_bytes(), 8 + 3 * FRAGMENT_SLOT_SIZE);
+
+        assert!(reassembler.insert(from, header(1, 2, 3), b"ij", 100, now).is_some());
+        assert_eq!(reassembler.pending_bytes(), 0);
+
+        reassembler.insert(from, header(2, 0, 2), b"abcd", 100, now);
+        reassembler.remove_peer(from);
+        assert_eq!(reassembler.pending_bytes(), 0);
+    }
+}
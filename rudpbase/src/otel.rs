@@ -0,0 +1,504 @@
+//! OpenTelemetry spans and metrics
+//!
+//! A [`Telemetry`] set on an instance with `set_telemetry()` reports its traffic
+//! through the OpenTelemetry API:
+//!
+//! - a `rudp.connection` span for the lifetime of each peer's state, from the first
+//!   packet exchanged until it is closed, reset, evicted or declared dead, with
+//!   migrations, resumptions and verified identities as span events
+//! - a `rudp.handshake` span from the first ping to a peer until it is acknowledged
+//! - a `rudp.delivery` span per data packet, from its first transmission until the
+//!   peer acknowledges it, with an event per retransmission; packets that are never
+//!   acknowledged end the span with an error status
+//! - counters of sent, retransmitted and dropped packets and of connection events,
+//!   histograms of RTT, handshake and delivery durations, and the number of active
+//!   connections
+//!
+//! Spans started while the application has an active OpenTelemetry context, for
+//! example a send from within a traced request, become children of it and link to
+//! their connection span, so rudpbase traffic shows up in existing distributed
+//! traces. Other spans are children of their connection span.
+//!
+//! [`Telemetry::new`] uses the global tracer and meter providers, so spans and
+//! metrics go wherever the application exports them, such as an OTLP pipeline built
+//! with `opentelemetry-otlp`. Span timestamps follow the `now` passed to the core.
+//!
+//! ```rust,no_run
+//! use rudpbase::otel::Telemetry;
+//! use rudpbase::{RudpConfig, RudpCore};
+//!
+//! // After installing the OTLP tracer and meter providers with
+//! // opentelemetry::global::set_tracer_provider() and set_meter_provider()
+//! let mut core = RudpCore::new(RudpConfig::default()).unwrap();
+//! core.set_telemetry(Telemetry::new());
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+use opentelemetry::global::{self, BoxedSpan, BoxedTracer};
+use opentelemetry::metrics::{Counter, Histogram, Meter, MeterProvider, UpDownCounter};
+use opentelemetry::trace::{Link, Span, SpanKind, Status, TraceContextExt, Tracer, TracerProvider};
+use opentelemetry::{Context, KeyValue};
+
+use crate::events::ConnectionEvent;
+use crate::protocol::CloseReason;
+
+/// Instrumentation scope of the spans and metrics
+const SCOPE: &str = "rudpbase";
+
+/// OpenTelemetry instrumentation of one instance
+pub struct Telemetry {
+    tracer: BoxedTracer,
+    metrics: Metrics,
+    /// Wall clock time of `anchor`, to timestamp spans from the core's instants
+    anchor: (Instant, SystemTime),
+    /// Latest instant passed to the core, for hooks that are not given one
+    now: Instant,
+    connections: HashMap<SocketAddr, Connection>,
+    /// Span of the connection removed last, with the time it was removed; kept open
+    /// for the event reported right after the removal, which says why
+    removed: Option<(SocketAddr, BoxedSpan, SystemTime)>,
+}
+
+struct Metrics {
+    packets_sent: Counter<u64>,
+    packets_dropped: Counter<u64>,
+    connection_events: Counter<u64>,
+    active_connections: UpDownCounter<i64>,
+    rtt: Histogram<f64>,
+    handshake_duration: Histogram<f64>,
+    delivery_duration: Histogram<f64>,
+}
+
+/// Spans of one peer
+struct Connection {
+    span: BoxedSpan,
+    /// Context of `span`, parent or link of the peer's other spans
+    context: Context,
+    handshake: Handshake,
+    deliveries: HashMap<u32, Delivery>,
+}
+
+enum Handshake {
+    NotStarted,
+    InProgress { span: BoxedSpan, started: Instant, pings: u32 },
+    Done,
+}
+
+struct Delivery {
+    span: BoxedSpan,
+    sent: Instant,
+}
+
+impl Telemetry {
+    /// Report through the global tracer and meter providers
+    pub fn new() -> Self {
+        Self::with_instruments(global::tracer(SCOPE), global::meter(SCOPE))
+    }
+
+    /// Report through the given providers instead of the global ones
+    pub fn with_providers<T, M>(tracer_provider: &T, meter_provider: &M) -> Self
+    where
+        T: TracerProvider,
+        T::Tracer: Send + Sync + 'static,
+        <T::Tracer as Tracer>::Span: Send + Sync + 'static,
+        M: MeterProvider,
+    {
+        let tracer = BoxedTracer::new(Box::new(tracer_provider.tracer(SCOPE)));
+        Self::with_instruments(tracer, meter_provider.meter(SCOPE))
+    }
+
+    fn with_instruments(tracer: BoxedTracer, meter: Meter) -> Self {
+        let metrics = Metrics {
+            packets_sent: meter
+                .u64_counter("rudp.packets.sent")
+                .with_description("Data packets sent, by whether they were retransmissions")
+                .build(),
+            packets_dropped: meter
+                .u64_counter("rudp.packets.dropped")
+                .with_description("Data packets dropped without being acknowledged")
+                .build(),
+            connection_events: meter
+                .u64_counter("rudp.connection.events")
+                .with_description("Connection events, by kind")
+                .build(),
+            active_connections: meter
+                .i64_up_down_counter("rudp.connections.active")
+                .with_description("Peers with connection state")
+                .build(),
+            rtt: meter.f64_histogram("rudp.rtt").with_unit("s").with_description("Round-trip time samples").build(),
+            handshake_duration: meter
+                .f64_histogram("rudp.handshake.duration")
+                .with_unit("s")
+                .with_description("Time from the first ping to a peer until it was acknowledged")
+                .build(),
+            delivery_duration: meter
+                .f64_histogram("rudp.delivery.duration")
+                .with_unit("s")
+                .with_description("Time from sending a data packet until it was acknowledged")
+                .build(),
+        };
+        let now = Instant::now();
+        Self {
+            tracer,
+            metrics,
+            anchor: (now, SystemTime::now()),
+            now,
+            connections: HashMap::new(),
+            removed: None,
+        }
+    }
+
+    /// Note the current instant
+    pub(crate) fn advance(&mut self, now: Instant) {
+        self.now = self.now.max(now);
+    }
+
+    /// A verified packet arrived from `addr`
+    pub(crate) fn packet_received(&mut self, addr: SocketAddr, now: Instant) {
+        self.advance(now);
+        self.open(addr);
+    }
+
+    /// A ping was sent to `addr`; the first one starts the handshake
+    pub(crate) fn ping_sent(&mut self, addr: SocketAddr) {
+        let now = self.now;
+        let start = self.time(now);
+        self.open(addr);
+        let (tracer, Some(connection)) = (&self.tracer, self.connections.get_mut(&addr)) else {
+            return;
+        };
+        match &mut connection.handshake {
+            Handshake::NotStarted => {
+                let span = tracer
+                    .span_builder("rudp.handshake")
+                    .with_kind(SpanKind::Client)
+                    .with_start_time(start)
+                    .with_attributes(peer_attributes(addr))
+                    .start_with_context(tracer, &connection.context);
+                connection.handshake = Handshake::InProgress { span, started: now, pings: 1 };
+            }
+            Handshake::InProgress { pings, .. } => *pings += 1,
+            Handshake::Done => {}
+        }
+    }
+
+    /// `addr` answered a ping after `rtt`
+    pub(crate) fn ping_acked(&mut self, addr: SocketAddr, rtt: Duration, now: Instant) {
+        self.advance(now);
+        self.metrics.rtt.record(rtt.as_secs_f64(), &[]);
+        let end = self.time(now);
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        if let Handshake::InProgress { mut span, started, pings } = std::mem::replace(&mut connection.handshake, Handshake::Done) {
+            span.set_attribute(KeyValue::new("rudp.handshake.pings", i64::from(pings)));
+            span.end_with_timestamp(end);
+            self.metrics.handshake_duration.record(now.saturating_duration_since(started).as_secs_f64(), &[]);
+        }
+    }
+
+    /// Data packet `seq` of `payload_len` bytes was sent to `addr` for the first time
+    pub(crate) fn data_sent(&mut self, addr: SocketAddr, seq: u32, payload_len: usize, now: Instant) {
+        self.advance(now);
+        self.metrics.packets_sent.add(1, &[KeyValue::new("rudp.retransmission", false)]);
+        let start = self.time(now);
+        self.open(addr);
+        let (tracer, Some(connection)) = (&self.tracer, self.connections.get_mut(&addr)) else {
+            return;
+        };
+        let mut attributes = peer_attributes(addr);
+        attributes.push(KeyValue::new("rudp.seq", i64::from(seq)));
+        attributes.push(KeyValue::new("rudp.payload_size", payload_len as i64));
+        let builder = tracer
+            .span_builder("rudp.delivery")
+            .with_kind(SpanKind::Producer)
+            .with_start_time(start)
+            .with_attributes(attributes);
+        // Within a traced operation of the application the delivery belongs to it
+        let current = Context::current();
+        let span = if current.has_active_span() {
+            builder
+                .with_links(vec![Link::with_context(connection.context.span().span_context().clone())])
+                .start_with_context(tracer, &current)
+        } else {
+            builder.start_with_context(tracer, &connection.context)
+        };
+        connection.deliveries.insert(seq, Delivery { span, sent: now });
+    }
+
+    /// Data packet `seq` was sent to `addr` again
+    pub(crate) fn retransmitted(&mut self, addr: SocketAddr, seq: u32) {
+        self.metrics.packets_sent.add(1, &[KeyValue::new("rudp.retransmission", true)]);
+        let time = self.time(self.now);
+        if let Some(delivery) = self.connections.get_mut(&addr).and_then(|connection| connection.deliveries.get_mut(&seq)) {
+            delivery.span.add_event_with_timestamp("retransmission", time, Vec::new());
+        }
+    }
+
+    /// `addr` acknowledged data packet `seq`; `rtt` is its RTT sample, if it gave one
+    pub(crate) fn acked(&mut self, addr: SocketAddr, seq: u32, rtt: Option<Duration>, now: Instant) {
+        self.advance(now);
+        if let Some(rtt) = rtt {
+            self.metrics.rtt.record(rtt.as_secs_f64(), &[]);
+        }
+        let end = self.time(now);
+        if let Some(mut delivery) = self.connections.get_mut(&addr).and_then(|connection| connection.deliveries.remove(&seq)) {
+            delivery.span.set_status(Status::Ok);
+            delivery.span.end_with_timestamp(end);
+            self.metrics.delivery_duration.record(now.saturating_duration_since(delivery.sent).as_secs_f64(), &[]);
+        }
+    }
+
+    /// Data packet `seq` to `addr` was given up on for `reason`
+    pub(crate) fn dropped(&mut self, addr: SocketAddr, seq: u32, reason: &'static str, now: Instant) {
+        self.advance(now);
+        self.metrics.packets_dropped.add(1, &[]);
+        let end = self.time(now);
+        if let Some(mut delivery) = self.connections.get_mut(&addr).and_then(|connection| connection.deliveries.remove(&seq)) {
+            delivery.span.set_status(Status::error(reason));
+            delivery.span.end_with_timestamp(end);
+        }
+    }
+
+    /// The state of `addr` was moved to `new`
+    pub(crate) fn migrated(&mut self, old: SocketAddr, new: SocketAddr) {
+        let Some(mut connection) = self.connections.remove(&old) else {
+            return;
+        };
+        connection.span.set_attributes(peer_attributes(new));
+        if let Some(mut stale) = self.connections.insert(new, connection) {
+            stale.end(self.time(self.now));
+            self.metrics.active_connections.add(-1, &[]);
+        }
+    }
+
+    /// The state of `addr` was removed; pending deliveries and the handshake fail
+    pub(crate) fn connection_removed(&mut self, addr: SocketAddr) {
+        self.flush_removed();
+        let Some(mut connection) = self.connections.remove(&addr) else {
+            return;
+        };
+        let end = self.time(self.now);
+        for (_, mut delivery) in connection.deliveries.drain() {
+            delivery.span.set_status(Status::error("connection removed"));
+            delivery.span.end_with_timestamp(end);
+        }
+        if let Handshake::InProgress { mut span, .. } = std::mem::replace(&mut connection.handshake, Handshake::Done) {
+            span.set_status(Status::error("connection removed"));
+            span.end_with_timestamp(end);
+        }
+        self.metrics.active_connections.add(-1, &[]);
+        self.removed = Some((addr, connection.span, end));
+    }
+
+    /// `event` was reported for `addr`
+    pub(crate) fn connection_event(&mut self, addr: SocketAddr, event: &ConnectionEvent) {
+        let name = event_name(event);
+        self.metrics.connection_events.add(1, &[KeyValue::new("rudp.event", name)]);
+        let time = self.time(self.now);
+        let attributes = event_attributes(event);
+        if let Some((_, mut span, end)) = self.removed.take_if(|(removed, _, _)| *removed == addr) {
+            span.add_event_with_timestamp(name, time, attributes);
+            match event {
+                ConnectionEvent::Closed { reason } if reason.code == CloseReason::NORMAL => span.set_status(Status::Ok),
+                ConnectionEvent::Closed { .. }
+                | ConnectionEvent::Evicted
+                | ConnectionEvent::Dead
+                | ConnectionEvent::Reset => span.set_status(Status::error(name)),
+                _ => {}
+            }
+            span.end_with_timestamp(end);
+        } else if let Some(connection) = self.connections.get_mut(&addr) {
+            connection.span.add_event_with_timestamp(name, time, attributes);
+        }
+    }
+
+    /// Start the connection span of `addr` if it has none
+    fn open(&mut self, addr: SocketAddr) {
+        self.flush_removed();
+        let start = self.time(self.now);
+        let tracer = &self.tracer;
+        let metrics = &self.metrics;
+        self.connections.entry(addr).or_insert_with(|| {
+            metrics.active_connections.add(1, &[]);
+            let span = tracer
+                .span_builder("rudp.connection")
+                .with_kind(SpanKind::Internal)
+                .with_start_time(start)
+                .with_attributes(peer_attributes(addr))
+                .start_with_context(tracer, &Context::current());
+            let context = Context::new().with_remote_span_context(span.span_context().clone());
+            Connection { span, context, handshake: Handshake::NotStarted, deliveries: HashMap::new() }
+        });
+    }
+
+    /// End the span of the connection removed last, if no event explained it
+    fn flush_removed(&mut self) {
+        if let Some((_, mut span, end)) = self.removed.take() {
+            span.end_with_timestamp(end);
+        }
+    }
+
+    /// Wall clock time of `instant`
+    fn time(&self, instant: Instant) -> SystemTime {
+        let (anchor, wall_clock) = self.anchor;
+        match instant.checked_duration_since(anchor) {
+            Some(elapsed) => wall_clock + elapsed,
+            None => wall_clock - anchor.duration_since(instant),
+        }
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        self.flush_removed();
+        let end = self.time(self.now);
+        for (_, mut connection) in self.connections.drain() {
+            connection.end(end);
+        }
+    }
+}
+
+impl Connection {
+    /// End every span of the connection at `end`
+    fn end(&mut self, end: SystemTime) {
+        for (_, mut delivery) in self.deliveries.drain() {
+            delivery.span.end_with_timestamp(end);
+        }
+        if let Handshake::InProgress { span, .. } = &mut self.handshake {
+            span.end_with_timestamp(end);
+        }
+        self.span.end_with_timestamp(end);
+    }
+}
+
+fn peer_attributes(addr: SocketAddr) -> Vec<KeyValue> {
+    vec![
+        KeyValue::new("network.transport", "udp"),
+        KeyValue::new("network.peer.address", addr.ip().to_string()),
+        KeyValue::new("network.peer.port", i64::from(addr.port())),
+    ]
+}
+
+fn event_name(event: &ConnectionEvent) -> &'static str {
+    match event {
+        #[cfg(feature = "identity")]
+        ConnectionEvent::Established { .. } => "established",
+        ConnectionEvent::Evicted => "evicted",
+        ConnectionEvent::Closed { .. } => "closed",
+        ConnectionEvent::Finished => "finished",
+        ConnectionEvent::Dead => "dead",
+        ConnectionEvent::Resumed => "resumed",
+        ConnectionEvent::Migrated { .. } => "migrated",
+        ConnectionEvent::Reset => "reset",
+        ConnectionEvent::LossBurst { .. } => "loss_burst",
+        ConnectionEvent::Outage { .. } => "outage",
+    }
+}
+
+fn event_attributes(event: &ConnectionEvent) -> Vec<KeyValue> {
+    match event {
+        #[cfg(feature = "identity")]
+        ConnectionEvent::Established { identity } => {
+            vec![KeyValue::new("rudp.peer_id", crate::PeerId::Identity(*identity).to_string())]
+        }
+        ConnectionEvent::Closed { reason } => vec![KeyValue::new("rudp.close_code", i64::from(reason.code))],
+        ConnectionEvent::Migrated { from } => vec![KeyValue::new("rudp.migrated_from", from.to_string())],
+        ConnectionEvent::LossBurst { lost, duration } => vec![
+            KeyValue::new("rudp.lost", i64::from(*lost)),
+            KeyValue::new("rudp.duration", duration.as_secs_f64()),
+        ],
+        ConnectionEvent::Outage { duration } => vec![KeyValue::new("rudp.duration", duration.as_secs_f64())],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    fn spans(exporter: &InMemorySpanExporter) -> Vec<(String, Status)> {
+        exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .map(|span| (span.name.into_owned(), span.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_spans_follow_connection() {
+        let exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let mut telemetry = Telemetry::with_providers(&tracer_provider, &SdkMeterProvider::default());
+        let addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let now = Instant::now();
+
+        telemetry.ping_sent(addr);
+        telemetry.data_sent(addr, 1, 10, now);
+        telemetry.data_sent(addr, 2, 10, now);
+        telemetry.retransmitted(addr, 1);
+        telemetry.ping_acked(addr, Duration::from_millis(20), now + Duration::from_millis(20));
+        telemetry.acked(addr, 1, None, now + Duration::from_millis(50));
+        assert_eq!(spans(&exporter), vec![
+            ("rudp.handshake".to_string(), Status::Unset),
+            ("rudp.delivery".to_string(), Status::Ok),
+        ]);
+
+        // The removal fails the pending delivery, and the event after it ends the connection
+        telemetry.connection_removed(addr);
+        telemetry.connection_event(addr, &ConnectionEvent::Dead);
+        let finished = exporter.get_finished_spans().unwrap();
+        let connection = finished.iter().find(|span| span.name == "rudp.connection").unwrap();
+        assert_eq!(connection.status, Status::error("dead"));
+        assert_eq!(connection.events.events[0].name, "dead");
+        assert_eq!(spans(&exporter)[2], ("rudp.delivery".to_string(), Status::error("connection removed")));
+        let delivery = &finished[1];
+        assert_eq!(delivery.parent_span_id, connection.span_context.span_id());
+        assert_eq!(delivery.end_time.duration_since(delivery.start_time).unwrap(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_core_reports_deliveries() {
+        use crate::{RudpConfig, RudpCore};
+
+        let exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let (a_addr, b_addr): (SocketAddr, SocketAddr) = ("10.0.0.1:1".parse().unwrap(), "10.0.0.2:1".parse().unwrap());
+        let now = Instant::now();
+        let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+        let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+        a.set_telemetry(Telemetry::with_providers(&tracer_provider, &SdkMeterProvider::default()));
+
+        let mut buffer = a.get_buffer().unwrap();
+        buffer.data_mut()[..5].copy_from_slice(b"hello");
+        buffer.set_data_len(5).unwrap();
+        a.send(buffer, b_addr, now).unwrap();
+        while let Some(transmit) = a.poll_transmit() {
+            b.handle_datagram(&transmit.contents, a_addr, now);
+        }
+        let acked = now + Duration::from_millis(30);
+        b.handle_timeout(acked);
+        while let Some(transmit) = b.poll_transmit() {
+            a.handle_datagram(&transmit.contents, b_addr, acked);
+        }
+        assert_eq!(spans(&exporter), vec![("rudp.delivery".to_string(), Status::Ok)]);
+        let delivery = exporter.get_finished_spans().unwrap().remove(0);
+        assert_eq!(delivery.end_time.duration_since(delivery.start_time).unwrap(), Duration::from_millis(30));
+
+        a.close_peer(b_addr, CloseReason::default(), acked).unwrap();
+        a.clear_telemetry();
+        assert_eq!(spans(&exporter)[1].0, "rudp.connection");
+    }
+}
@@ -0,0 +1,108 @@
+//! Loss burst and outage detection
+//!
+//! A loss episode of a peer starts when one of its data packets times out and lasts
+//! until anything is heard from the peer again. [`LossEpisodes`] classifies each
+//! episode when it ends: a packet that timed out [`OUTAGE_MIN_TIMEOUTS`] times in a
+//! row means the link went silent through several backed-off RTOs, an outage; at least
+//! [`BURST_MIN_TIMEOUTS`] timeouts that each recovered on the first retransmission
+//! are a burst loss, typical of congestion. Fewer timeouts are ordinary loss and are
+//! not reported.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::events::ConnectionEvent;
+
+/// Timeouts in one episode from which it is a burst loss
+pub(crate) const BURST_MIN_TIMEOUTS: u32 = 3;
+
+/// Timeouts of the same packet in one episode from which it is an outage
+pub(crate) const OUTAGE_MIN_TIMEOUTS: u8 = 2;
+
+#[derive(Debug)]
+struct Episode {
+    /// When the first packet that timed out was sent
+    start: Instant,
+    timeouts: u32,
+    /// Timeouts of each packet during the episode
+    packet_timeouts: HashMap<u32, u8>,
+}
+
+/// Ongoing loss episodes per peer
+#[derive(Debug, Default)]
+pub(crate) struct LossEpisodes {
+    episodes: HashMap<SocketAddr, Episode>,
+}
+
+impl LossEpisodes {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Data packet `seq` to `addr`, last sent at `sent`, timed out
+    pub(crate) fn timed_out(&mut self, addr: SocketAddr, seq: u32, sent: Instant) {
+        let episode = self.episodes.entry(addr).or_insert_with(|| Episode {
+            start: sent,
+            timeouts: 0,
+            packet_timeouts: HashMap::new(),
+        });
+        episode.timeouts += 1;
+        let count = episode.packet_timeouts.entry(seq).or_default();
+        *count = count.saturating_add(1);
+    }
+
+    /// `addr` was heard from at `now`; returns the event its episode is reported as,
+    /// if one was in progress and long enough to report
+    pub(crate) fn heard_from(&mut self, addr: SocketAddr, now: Instant) -> Option<ConnectionEvent> {
+        let episode = self.episodes.remove(&addr)?;
+        let duration = now.saturating_duration_since(episode.start);
+        if episode.packet_timeouts.values().any(|count| *count >= OUTAGE_MIN_TIMEOUTS) {
+            Some(ConnectionEvent::Outage { duration })
+        } else if episode.timeouts >= BURST_MIN_TIMEOUTS {
+            Some(ConnectionEvent::LossBurst { lost: episode.timeouts, duration })
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        self.episodes.remove(&addr);
+    }
+
+    pub(crate) fn move_peer(&mut self, old: SocketAddr, new: SocketAddr) {
+        if let Some(episode) = self.episodes.remove(&old) {
+            self.episodes.insert(new, episode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_episodes_classified_by_timeouts() {
+        let addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut episodes = LossEpisodes::new();
+
+        // A single loss is not reported
+        episodes.timed_out(addr, 1, start);
+        assert_eq!(episodes.heard_from(addr, at(300)), None);
+        assert_eq!(episodes.heard_from(addr, at(400)), None);
+
+        // Several packets lost once each
+        for seq in 2..5 {
+            episodes.timed_out(addr, seq, at(500));
+        }
+        assert_eq!(episodes.heard_from(addr, at(800)), Some(ConnectionEvent::LossBurst { lost: 3, duration: Duration::from_millis(300) }));
+
+        // One packet timing out again
+        episodes.timed_out(addr, 5, at(1000));
+        episodes.timed_out(addr, 5, at(1200));
+        assert_eq!(episodes.heard_from(addr, at(2000)), Some(ConnectionEvent::Outage { duration: Duration::from_secs(1) }));
+    }
+}
@@ -0,0 +1,107 @@
+//! Instance-wide outbound bandwidth limit
+//!
+//! [`Pacer`] is a token bucket shared by every datagram the core sends, to all peers
+//! and of every packet type. It refills at `max_bandwidth` bytes per second and holds
+//! at most [`PACING_BURST`] worth of tokens, so an idle instance can send a short
+//! burst but the long-run rate never exceeds the limit. A datagram larger than the
+//! bucket is let through once the bucket is full, leaving it in debt.
+
+use std::time::{Duration, Instant};
+
+/// Traffic an idle instance may send at once
+pub(crate) const PACING_BURST: Duration = Duration::from_millis(10);
+
+/// Token bucket limiting outbound bytes per second
+#[derive(Debug, Clone)]
+pub(crate) struct Pacer {
+    /// Bytes per second
+    rate: u64,
+    /// Bucket size in bytes
+    burst: f64,
+    /// Bytes that may be sent now; negative after an oversized datagram
+    tokens: f64,
+    /// Time the tokens were last brought up to date
+    updated: Option<Instant>,
+}
+
+impl Pacer {
+    pub(crate) fn new(rate: u64) -> Self {
+        let burst = (rate as f64 * PACING_BURST.as_secs_f64()).max(1.0);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: None,
+        }
+    }
+
+    pub(crate) fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Add the tokens earned since the last update
+    pub(crate) fn refill(&mut self, now: Instant) {
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst);
+        }
+        self.updated = Some(self.updated.map_or(now, |updated| updated.max(now)));
+    }
+
+    /// Whether `len` more bytes may go out after `spent` bytes already taken from the
+    /// current tokens
+    pub(crate) fn allows(&self, spent: usize, len: usize) -> bool {
+        let tokens = self.tokens - spent as f64;
+        tokens >= len as f64 || tokens >= self.burst
+    }
+
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.tokens -= len as f64;
+    }
+
+    /// Earliest time a datagram of `len` bytes may be sent
+    pub(crate) fn ready_at(&self, len: usize) -> Option<Instant> {
+        let needed = (len as f64).min(self.burst) - self.tokens;
+        let updated = self.updated?;
+        if needed <= 0.0 {
+            return Some(updated);
+        }
+        // Rounded up, so the tokens are there when the time comes
+        Some(updated + Duration::from_nanos((needed * 1e9 / self.rate as f64).ceil() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_and_burst() {
+        let start = Instant::now();
+        // 100 KB/s: 1000 byte bucket
+        let mut pacer = Pacer::new(100_000);
+        pacer.refill(start);
+        assert!(pacer.allows(0, 1000));
+        assert!(!pacer.allows(600, 600));
+        pacer.consume(1000);
+        assert!(!pacer.allows(0, 500));
+        assert_eq!(pacer.ready_at(500), Some(start + Duration::from_millis(5)));
+
+        pacer.refill(start + Duration::from_millis(5));
+        assert!(pacer.allows(0, 500));
+        // Idle time does not earn more than the bucket holds
+        pacer.refill(start + Duration::from_secs(10));
+        assert!(!pacer.allows(1000, 1));
+    }
+
+    #[test]
+    fn test_oversized_datagram_goes_into_debt() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(100_000);
+        pacer.refill(start);
+        assert!(pacer.allows(0, 5000));
+        pacer.consume(5000);
+        // The debt is paid off before the bucket is full again
+        assert_eq!(pacer.ready_at(5000), Some(start + Duration::from_millis(50)));
+    }
+}
@@ -0,0 +1,107 @@
+//! Peer identifiers independent of addresses
+//!
+//! The core keys per-peer state by address, and moves it when a peer shows up at a
+//! new one (see [`ConnectionEvent::Migrated`]). A [`PeerId`] names the peer itself:
+//! the Ed25519 identity it proved in the handshake when the `identity` feature is
+//! used, otherwise the connection id its pings carry. Both are learned from the
+//! ping exchange, so a peer has an id once a ping or ping acknowledgment from it
+//! arrived, for example after [`connect`](crate::Rudpbase::connect).
+//!
+//! [`RudpCore::peer_addr`](crate::RudpCore::peer_addr) finds the current address of
+//! an id, `send_to_peer()` and `peer_stats()` take one instead of an address, and
+//! [`EventHandler::on_peer_event`](crate::EventHandler::on_peer_event) reports
+//! events with the id the peer had. A connection id lasts as long as the peer's
+//! instance; after a restart the peer has a new one.
+//!
+//! [`ConnectionEvent::Migrated`]: crate::ConnectionEvent::Migrated
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+#[cfg(feature = "identity")]
+use crate::identity::PeerIdentity;
+use crate::protocol::ConnectionId;
+
+/// Identifier of a peer that stays the same when its address changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerId {
+    /// Public key the peer proved it holds in the ping handshake
+    #[cfg(feature = "identity")]
+    Identity(PeerIdentity),
+    /// Random id of the peer's instance, carried by its pings
+    Connection(ConnectionId),
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "identity")]
+            PeerId::Identity(identity) => write!(f, "{:?}", identity),
+            PeerId::Connection(id) => write!(f, "connection {:016x}", id.0),
+        }
+    }
+}
+
+/// Connection ids of peers by address, and the address of each connection id
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionIds {
+    by_addr: HashMap<SocketAddr, ConnectionId>,
+    by_id: HashMap<ConnectionId, SocketAddr>,
+}
+
+impl ConnectionIds {
+    pub(crate) fn get(&self, addr: SocketAddr) -> Option<ConnectionId> {
+        self.by_addr.get(&addr).copied()
+    }
+
+    pub(crate) fn contains(&self, addr: SocketAddr) -> bool {
+        self.by_addr.contains_key(&addr)
+    }
+
+    /// Address the connection id was last seen from
+    pub(crate) fn addr(&self, id: ConnectionId) -> Option<SocketAddr> {
+        self.by_id.get(&id).copied()
+    }
+
+    /// Record `id` for `addr`, replacing the previous id of `addr` and the previous
+    /// address of `id`
+    pub(crate) fn insert(&mut self, addr: SocketAddr, id: ConnectionId) {
+        self.remove(addr);
+        if let Some(previous) = self.by_id.insert(id, addr) {
+            self.by_addr.remove(&previous);
+        }
+        self.by_addr.insert(addr, id);
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        if let Some(id) = self.by_addr.remove(&addr) {
+            self.by_id.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_ids_stay_one_to_one() {
+        let (a, b): (SocketAddr, SocketAddr) = ("10.0.0.1:1".parse().unwrap(), "10.0.0.2:1".parse().unwrap());
+        let mut ids = ConnectionIds::default();
+        ids.insert(a, ConnectionId(1));
+        assert_eq!(ids.addr(ConnectionId(1)), Some(a));
+
+        // The id moves to another address
+        ids.insert(b, ConnectionId(1));
+        assert_eq!(ids.addr(ConnectionId(1)), Some(b));
+        assert!(!ids.contains(a));
+
+        // The address gets a new id
+        ids.insert(b, ConnectionId(2));
+        assert_eq!(ids.addr(ConnectionId(1)), None);
+        assert_eq!(ids.get(b), Some(ConnectionId(2)));
+        ids.remove(b);
+        assert_eq!(ids.addr(ConnectionId(2)), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! Least-recently-active ordering of peers
+//!
+//! [`PeerActivity`] records every peer the core holds state for, in the order they
+//! were last active, so the least recently active one can be found in `O(log n)` when
+//! the peer limit is reached.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+/// Peers ordered by their last activity
+#[derive(Debug, Default)]
+pub(crate) struct PeerActivity {
+    /// Activity stamp of each peer
+    stamps: HashMap<SocketAddr, u64>,
+    /// Peers by activity stamp, least recent first
+    order: BTreeMap<u64, SocketAddr>,
+    next_stamp: u64,
+}
+
+impl PeerActivity {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark `addr` as the most recently active peer; returns true if it is new
+    pub(crate) fn touch(&mut self, addr: SocketAddr) -> bool {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        self.order.insert(stamp, addr);
+        match self.stamps.insert(addr, stamp) {
+            Some(previous) => {
+                self.order.remove(&previous);
+                false
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn contains(&self, addr: SocketAddr) -> bool {
+        self.stamps.contains_key(&addr)
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        if let Some(stamp) = self.stamps.remove(&addr) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    /// The peer that has been inactive the longest
+    pub(crate) fn least_recent(&self) -> Option<SocketAddr> {
+        self.order.values().next().copied()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.stamps.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.stamps.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recent_order() {
+        let a: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let c: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let mut peers = PeerActivity::new();
+
+        assert!(peers.touch(a));
+        assert!(peers.touch(b));
+        assert!(peers.touch(c));
+        assert_eq!(peers.least_recent(), Some(a));
+
+        // Activity moves a peer to the back
+        assert!(!peers.touch(a));
+        assert_eq!(peers.len(), 3);
+        assert!(peers.contains(a));
+        assert_eq!(peers.least_recent(), Some(b));
+
+        peers.remove(b);
+        assert!(!peers.contains(b));
+        assert_eq!(peers.least_recent(), Some(c));
+        peers.clear();
+        assert_eq!(peers.least_recent(), None);
+    }
+}
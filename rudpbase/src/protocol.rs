@@ -3,6 +3,50 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// Protocol header size in bytes
 pub const PROTOCOL_HEADER_SIZE: usize = 9; // type(1) + security_code(4) + seq(4)
 
+/// Bit of the type byte marking a packet that carries an extension section
+///
+/// The section follows the 9-byte base header: a 2-byte length, then that many bytes
+/// of TLV entries, each `type(1) + len(1) + value(len)`. Receivers skip entries of
+/// unknown type, so new extensions can be added without changing the base header.
+pub const EXTENSION_FLAG: u8 = 0x80;
+
+/// Size of the extension section's length prefix
+pub const EXTENSION_LENGTH_SIZE: usize = 2;
+
+/// Extension type reserved for piggybacked acknowledgments
+pub const EXTENSION_ACK: u8 = 1;
+/// Extension type of sender timestamps, see [`Timestamp`]
+pub const EXTENSION_TIMESTAMP: u8 = 2;
+/// Extension type reserved for ECN echo
+pub const EXTENSION_ECN_ECHO: u8 = 3;
+/// Extension type reserved for receive window advertisements
+pub const EXTENSION_WINDOW: u8 = 4;
+/// Extension type of resumption tokens, see [`ResumptionToken`](crate::ResumptionToken)
+pub const EXTENSION_RESUMPTION: u8 = 5;
+/// Extension type of connection ids, see [`ConnectionId`]
+pub const EXTENSION_CONNECTION_ID: u8 = 6;
+/// Extension type of payload checksums, see [`PayloadChecksum`]
+pub const EXTENSION_CHECKSUM: u8 = 7;
+/// Extension type naming the per-peer key a packet is signed with, see
+/// [`PeerKey`](crate::PeerKey)
+pub const EXTENSION_KEY_ID: u8 = 8;
+/// Extension type of signed Ed25519 identities on pings and ping acknowledgments
+/// (`identity` feature)
+pub const EXTENSION_IDENTITY: u8 = 9;
+/// Extension type of the zero bytes appended to a padded data packet, see [`Padding`]
+pub const EXTENSION_PADDING: u8 = 10;
+/// Extension type of application heartbeat payloads on pings and ping
+/// acknowledgments, see [`Heartbeat`]
+pub const EXTENSION_HEARTBEAT: u8 = 11;
+/// Extension type of the responder's clock on ping acknowledgments, see [`ReplyTime`]
+pub const EXTENSION_REPLY_TIME: u8 = 12;
+
+/// Maximum size of a heartbeat payload, the largest value of one extension entry
+pub const MAX_HEARTBEAT_SIZE: usize = u8::MAX as usize;
+
+/// Size of an extension section holding only a data packet's [`Timestamp::Sent`]
+pub const TIMESTAMP_SECTION_SIZE: usize = EXTENSION_LENGTH_SIZE + 2 + 4;
+
 /// Maximum buffer size (to ensure it fits in standard MTU)
 pub const MAX_BUFFER_SIZE: usize = 1200;
 
@@ -24,6 +68,26 @@ pub enum PacketType {
     Close = 5,
     /// Close acknowledgment
     CloseAck = 6,
+    /// Fragment of a message sent with `send_message`
+    Fragment = 7,
+    /// Compressed data or fragment packet
+    Compressed = 8,
+    /// Unreliable datagram sent with `send_unreliable`, never acknowledged
+    Datagram = 9,
+    /// Frame exchanged with a relay, see the `relay` module
+    Relay = 10,
+    /// Reply to a packet of a session the receiver has no state for; carries the
+    /// sequence number of that packet
+    Reset = 11,
+    /// The sender will send no more data but keeps receiving; acknowledged like a
+    /// data packet
+    Shutdown = 12,
+    /// Several control packets for the same peer in one datagram, each a complete
+    /// packet preceded by its length (see [`push_batch_frame`])
+    Batch = 13,
+    /// Reliable data packet holding several application payloads, framed like a
+    /// Batch packet and delivered separately
+    Bundle = 14,
 }
 
 impl PacketType {
@@ -37,6 +101,14 @@ impl PacketType {
             4 => Some(PacketType::DataNack),
             5 => Some(PacketType::Close),
             6 => Some(PacketType::CloseAck),
+            7 => Some(PacketType::Fragment),
+            8 => Some(PacketType::Compressed),
+            9 => Some(PacketType::Datagram),
+            10 => Some(PacketType::Relay),
+            11 => Some(PacketType::Reset),
+            12 => Some(PacketType::Shutdown),
+            13 => Some(PacketType::Batch),
+            14 => Some(PacketType::Bundle),
             _ => None,
         }
     }
@@ -46,6 +118,15 @@ impl PacketType {
 #[derive(Debug, Clone)]
 pub struct PingPacket {
     pub timestamp: u64, // 8 bytes timestamp
+    /// Compression algorithms the sender accepts (one bit per algorithm id); absent
+    /// when compression is disabled or the peer predates it
+    pub compression: Option<u8>,
+}
+
+impl Default for PingPacket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PingPacket {
@@ -55,11 +136,30 @@ impl PingPacket {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_nanos() as u64,
+            compression: None,
         }
     }
 
+    /// Encoded length: the timestamp and, when present, the compression offer
+    pub fn serialized_len(&self) -> usize {
+        8 + usize::from(self.compression.is_some())
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
-        self.timestamp.to_be_bytes().to_vec()
+        let mut data = vec![0; self.serialized_len()];
+        self.serialize_into(&mut data);
+        data
+    }
+
+    /// Write the packet to the start of `out`, returning the bytes written; `None`
+    /// if `out` is shorter than [`serialized_len`](Self::serialized_len)
+    pub fn serialize_into(&self, out: &mut [u8]) -> Option<usize> {
+        let out = out.get_mut(..self.serialized_len())?;
+        out[..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        if let Some(compression) = self.compression {
+            out[8] = compression;
+        }
+        Some(out.len())
     }
 
     pub fn deserialize(data: &[u8]) -> Option<Self> {
@@ -68,7 +168,7 @@ impl PingPacket {
                 data[0], data[1], data[2], data[3],
                 data[4], data[5], data[6], data[7],
             ]);
-            Some(Self { timestamp })
+            Some(Self { timestamp, compression: data.get(8).copied() })
         } else {
             None
         }
@@ -86,17 +186,34 @@ impl DataAckPacket {
         Self { ack_seqs: seqs }
     }
 
+    /// Encoded length: the count and four bytes per sequence number
+    pub fn serialized_len(&self) -> usize {
+        1 + self.ack_seqs.len() * 4
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        data.push(self.ack_seqs.len() as u8); // ack_count
-        
-        for seq in &self.ack_seqs {
-            data.extend_from_slice(&seq.to_be_bytes());
-        }
-        
+        let mut data = vec![0; self.serialized_len()];
+        self.serialize_into(&mut data);
         data
     }
 
+    /// Write the packet to the start of `out`, returning the bytes written; `None`
+    /// if `out` is shorter than [`serialized_len`](Self::serialized_len)
+    pub fn serialize_into(&self, out: &mut [u8]) -> Option<usize> {
+        Self::write_into(&self.ack_seqs, out)
+    }
+
+    /// Write the payload of an acknowledgment of `seqs` (at most 255) to the start
+    /// of `out`, without building a packet first
+    pub fn write_into(seqs: &[u32], out: &mut [u8]) -> Option<usize> {
+        let out = out.get_mut(..1 + seqs.len() * 4)?;
+        out[0] = seqs.len() as u8; // ack_count
+        for (seq, chunk) in seqs.iter().zip(out[1..].chunks_exact_mut(4)) {
+            chunk.copy_from_slice(&seq.to_be_bytes());
+        }
+        Some(out.len())
+    }
+
     pub fn deserialize(data: &[u8]) -> Option<Self> {
         if data.is_empty() {
             return None;
@@ -171,18 +288,197 @@ impl DataNackPacket {
     }
 }
 
+/// Size of the length prefix of each packet in a Batch packet
+pub const BATCH_FRAME_HEADER_SIZE: usize = 2;
+
+/// Append `packet` to the payload of a Batch packet, or a payload to a Bundle packet
+pub fn push_batch_frame(out: &mut Vec<u8>, packet: &[u8]) {
+    out.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    out.extend_from_slice(packet);
+}
+
+/// The first packet of a Batch payload and the rest; `None` if the payload is truncated
+pub fn split_batch_frame(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = data.split_first_chunk::<BATCH_FRAME_HEADER_SIZE>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+/// Largest application payload of a Close packet
+pub const MAX_CLOSE_PAYLOAD: usize = 256;
+
+/// Why a connection was closed, carried by Close packets
+///
+/// Close packets from peers that predate reasons are empty and read as
+/// [`NORMAL`](Self::NORMAL) without payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloseReason {
+    /// One of the codes below, or an application code from [`APPLICATION`](Self::APPLICATION) up
+    pub code: u16,
+    /// Application data, at most [`MAX_CLOSE_PAYLOAD`] bytes
+    pub payload: Vec<u8>,
+}
+
+impl CloseReason {
+    /// Graceful shutdown
+    pub const NORMAL: u16 = 0;
+    /// The connection failed, for example on a protocol violation
+    pub const ERROR: u16 = 1;
+    /// The peer is not allowed to connect
+    pub const BANNED: u16 = 2;
+    /// The peer limit was reached; sent to evicted peers
+    pub const SERVER_FULL: u16 = 3;
+    /// First code left to applications
+    pub const APPLICATION: u16 = 0x100;
+
+    pub fn new(code: u16) -> Self {
+        Self { code, payload: Vec::new() }
+    }
+
+    pub fn with_payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.code.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.payload);
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        match data {
+            [] => Some(Self::default()),
+            [high, low, payload @ ..] if payload.len() <= MAX_CLOSE_PAYLOAD => {
+                Some(Self { code: u16::from_be_bytes([*high, *low]), payload: payload.to_vec() })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Compression header size in bytes, at the start of a compressed packet's payload
+pub const COMPRESSION_HEADER_SIZE: usize = 2; // algorithm(1) + original packet type(1)
+
+/// Fragment header size in bytes, at the start of a fragment packet's payload
+pub const FRAGMENT_HEADER_SIZE: usize = 8; // message_id(4) + index(2) + count(2)
+
+/// Position of a fragment within its message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    pub message_id: u32,
+    pub index: u16,
+    pub count: u16,
+}
+
+impl FragmentHeader {
+    pub fn serialize(&self) -> [u8; FRAGMENT_HEADER_SIZE] {
+        let mut data = [0u8; FRAGMENT_HEADER_SIZE];
+        data[0..4].copy_from_slice(&self.message_id.to_be_bytes());
+        data[4..6].copy_from_slice(&self.index.to_be_bytes());
+        data[6..8].copy_from_slice(&self.count.to_be_bytes());
+        data
+    }
+
+    /// Parse the header; the fragment's data follows at `FRAGMENT_HEADER_SIZE`
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        if data.len() < FRAGMENT_HEADER_SIZE {
+            return None;
+        }
+        let header = Self {
+            message_id: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            index: u16::from_be_bytes([data[4], data[5]]),
+            count: u16::from_be_bytes([data[6], data[7]]),
+        };
+        (header.index < header.count).then_some(header)
+    }
+}
+
 /// Raw packet structure for parsing
 #[derive(Debug, Clone)]
 pub struct RawPacket {
     pub packet_type: PacketType,
     pub security_code: u32,
     pub seq: u32,
+    /// TLV entries of the extension section, without its length prefix; empty
+    /// when the packet has no extension section
+    pub extensions: Vec<u8>,
     pub data: Vec<u8>,
 }
 
 impl RawPacket {
     /// Parse a raw UDP packet into RawPacket structure
     pub fn parse(packet: &[u8]) -> Result<Self, crate::error::RudpError> {
+        RawPacketRef::parse(packet).map(|packet| packet.to_packet())
+    }
+
+    /// Serialize the packet into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut packet = vec![0; self.serialized_len()];
+        self.serialize_into(&mut packet);
+        packet
+    }
+
+    /// Length of the serialized packet, protocol header included
+    pub fn serialized_len(&self) -> usize {
+        PROTOCOL_HEADER_SIZE + self.body_len()
+    }
+
+    /// Serialize the packet to the start of `out`, returning its length; `None` if
+    /// `out` is shorter than [`serialized_len`](Self::serialized_len)
+    pub fn serialize_into(&self, out: &mut [u8]) -> Option<usize> {
+        let out = out.get_mut(..self.serialized_len())?;
+        if self.extensions.is_empty() {
+            out[0] = self.packet_type as u8;
+        } else {
+            out[0] = self.packet_type as u8 | EXTENSION_FLAG;
+        }
+        out[1..5].copy_from_slice(&self.security_code.to_be_bytes());
+        out[5..PROTOCOL_HEADER_SIZE].copy_from_slice(&self.seq.to_be_bytes());
+        self.write_body(&mut out[PROTOCOL_HEADER_SIZE..]);
+        Some(out.len())
+    }
+
+    /// Bytes following the base header, which the security code covers
+    pub fn body(&self) -> Vec<u8> {
+        let mut body = vec![0; self.body_len()];
+        self.write_body(&mut body);
+        body
+    }
+
+    fn body_len(&self) -> usize {
+        let extensions = if self.extensions.is_empty() { 0 } else { EXTENSION_LENGTH_SIZE + self.extensions.len() };
+        extensions + self.data.len()
+    }
+
+    /// Write the body into `out`, which is exactly `body_len()` long
+    fn write_body(&self, out: &mut [u8]) {
+        let data = if self.extensions.is_empty() {
+            out
+        } else {
+            let (extensions, data) = out.split_at_mut(EXTENSION_LENGTH_SIZE + self.extensions.len());
+            extensions[..EXTENSION_LENGTH_SIZE].copy_from_slice(&(self.extensions.len() as u16).to_be_bytes());
+            extensions[EXTENSION_LENGTH_SIZE..].copy_from_slice(&self.extensions);
+            data
+        };
+        data.copy_from_slice(&self.data);
+    }
+}
+
+/// Packet parsed in place, borrowing its payload from the received datagram
+#[derive(Debug, Clone, Copy)]
+pub struct RawPacketRef<'a> {
+    pub packet_type: PacketType,
+    pub security_code: u32,
+    pub seq: u32,
+    /// TLV entries of the extension section, without its length prefix
+    pub extensions: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> RawPacketRef<'a> {
+    /// Parse the header of a raw UDP packet without copying the payload
+    pub fn parse(packet: &'a [u8]) -> Result<Self, crate::error::RudpError> {
         if packet.len() < PROTOCOL_HEADER_SIZE {
             return Err(crate::error::RudpError::PacketTooSmall {
                 size: packet.len(),
@@ -190,9 +486,9 @@ impl RawPacket {
             });
         }
 
-        let packet_type = PacketType::from_u8(packet[0])
+        let packet_type = PacketType::from_u8(packet[0] & !EXTENSION_FLAG)
             .ok_or_else(|| crate::error::RudpError::Protocol {
-                message: format!("Unknown packet type: {}", packet[0]),
+                message: format!("Unknown packet type: {}", packet[0] & !EXTENSION_FLAG),
             })?;
 
         let security_code = u32::from_be_bytes([
@@ -203,26 +499,269 @@ impl RawPacket {
             packet[5], packet[6], packet[7], packet[8],
         ]);
 
-        let data = packet[PROTOCOL_HEADER_SIZE..].to_vec();
+        let body = &packet[PROTOCOL_HEADER_SIZE..];
+        let (extensions, data) = if packet[0] & EXTENSION_FLAG == 0 {
+            (&body[..0], body)
+        } else {
+            let malformed = || crate::error::RudpError::Protocol {
+                message: "Truncated extension section".to_string(),
+            };
+            let len = body.get(..EXTENSION_LENGTH_SIZE).ok_or_else(malformed)?;
+            let end = EXTENSION_LENGTH_SIZE + u16::from_be_bytes([len[0], len[1]]) as usize;
+            let extensions = body.get(EXTENSION_LENGTH_SIZE..end).ok_or_else(malformed)?;
+            (extensions, &body[end..])
+        };
 
         Ok(Self {
             packet_type,
             security_code,
             seq,
+            extensions,
             data,
         })
     }
 
-    /// Serialize the packet into bytes
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut packet = Vec::with_capacity(PROTOCOL_HEADER_SIZE + self.data.len());
-        
-        packet.push(self.packet_type as u8);
-        packet.extend_from_slice(&self.security_code.to_be_bytes());
-        packet.extend_from_slice(&self.seq.to_be_bytes());
-        packet.extend_from_slice(&self.data);
-        
-        packet
+    /// Entries of the extension section
+    pub fn extensions(&self) -> Extensions<'a> {
+        Extensions { data: self.extensions }
+    }
+
+    /// Copy into an owned packet
+    pub fn to_packet(&self) -> RawPacket {
+        RawPacket {
+            packet_type: self.packet_type,
+            security_code: self.security_code,
+            seq: self.seq,
+            extensions: self.extensions.to_vec(),
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+/// One entry of a packet's extension section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extension<'a> {
+    /// Extension type, one of the `EXTENSION_*` constants or a newer one
+    pub kind: u8,
+    pub value: &'a [u8],
+}
+
+impl Extension<'_> {
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), crate::error::RudpError> {
+        if self.value.len() > u8::MAX as usize {
+            return Err(crate::error::RudpError::BufferTooLarge {
+                size: self.value.len(),
+                max: u8::MAX as usize,
+            });
+        }
+        out.push(self.kind);
+        out.push(self.value.len() as u8);
+        out.extend_from_slice(self.value);
+        Ok(())
+    }
+}
+
+/// Iterator over the TLV entries of an extension section
+///
+/// Iteration stops at a truncated entry.
+#[derive(Debug, Clone)]
+pub struct Extensions<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Extensions<'a> {
+    type Item = Extension<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&kind, rest) = self.data.split_first()?;
+        let (&len, rest) = rest.split_first()?;
+        if rest.len() < len as usize {
+            self.data = &[];
+            return None;
+        }
+        let (value, rest) = rest.split_at(len as usize);
+        self.data = rest;
+        Some(Extension { kind, value })
+    }
+}
+
+/// Value of an [`EXTENSION_TIMESTAMP`] entry
+///
+/// Times are microseconds on the writer's own clock and wrap around; only
+/// differences between times from the same clock are meaningful.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Transmit time of a data packet
+    Sent(u32),
+    /// Carried by acknowledgments: transmit time of the newest timestamped data
+    /// packet they answer, and the time it arrived
+    Echo { sent: u32, received: u32 },
+}
+
+impl Timestamp {
+    /// The first timestamp entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_TIMESTAMP)?;
+        let word = |at: usize| u32::from_be_bytes([entry.value[at], entry.value[at + 1], entry.value[at + 2], entry.value[at + 3]]);
+        match entry.value.len() {
+            4 => Some(Timestamp::Sent(word(0))),
+            8 => Some(Timestamp::Echo { sent: word(0), received: word(4) }),
+            _ => None,
+        }
+    }
+
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = [0u8; 8];
+        let len = match *self {
+            Timestamp::Sent(sent) => {
+                value[..4].copy_from_slice(&sent.to_be_bytes());
+                4
+            }
+            Timestamp::Echo { sent, received } => {
+                value[..4].copy_from_slice(&sent.to_be_bytes());
+                value[4..].copy_from_slice(&received.to_be_bytes());
+                8
+            }
+        };
+        out.extend_from_slice(&[EXTENSION_TIMESTAMP, len as u8]);
+        out.extend_from_slice(&value[..len]);
+    }
+}
+
+/// CRC32C of a data packet's payload (the bytes after the extension section)
+///
+/// The security code only covers the first 16 bytes of the body, so damage further
+/// into a packet goes unnoticed without it. Receivers check the entry whenever it is
+/// present, whether or not they add it to their own packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadChecksum(pub u32);
+
+impl PayloadChecksum {
+    /// Size of the TLV entry
+    pub const ENTRY_SIZE: usize = 6;
+
+    /// Checksum of `payload`
+    pub fn of(payload: &[u8]) -> Self {
+        PayloadChecksum(crate::security::crc32c(payload))
+    }
+
+    /// The checksum entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_CHECKSUM)?;
+        Some(PayloadChecksum(u32::from_be_bytes(entry.value.try_into().ok()?)))
+    }
+
+    /// Whether `packet` carries no checksum or one matching its payload
+    pub fn verify(packet: &RawPacketRef<'_>) -> bool {
+        Self::find(packet).is_none_or(|checksum| checksum == Self::of(packet.data))
+    }
+
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_CHECKSUM, 4]);
+        out.extend_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+/// Number of zero bytes appended after a data packet's payload
+///
+/// Receivers strip them before anything else looks at the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Padding(pub u16);
+
+impl Padding {
+    /// Size of the TLV entry
+    pub const ENTRY_SIZE: usize = 4;
+
+    /// The padding entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_PADDING)?;
+        Some(Padding(u16::from_be_bytes(entry.value.try_into().ok()?)))
+    }
+
+    /// `packet` without its padding, or `None` when the padding is longer than the
+    /// data
+    pub fn strip<'a>(packet: RawPacketRef<'a>) -> Option<RawPacketRef<'a>> {
+        let Some(Padding(len)) = Self::find(&packet) else {
+            return Some(packet);
+        };
+        let end = packet.data.len().checked_sub(len as usize)?;
+        Some(RawPacketRef { data: &packet.data[..end], ..packet })
+    }
+
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_PADDING, 2]);
+        out.extend_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+/// Application payload carried by an instance's pings and ping acknowledgments
+///
+/// Lets peers exchange a small status, such as load or a version, along with the
+/// keep-alive traffic instead of sending data packets for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat<'a>(pub &'a [u8]);
+
+impl<'a> Heartbeat<'a> {
+    /// The heartbeat entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'a>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_HEARTBEAT)?;
+        Some(Heartbeat(entry.value))
+    }
+
+    /// Append the TLV encoding of this entry to `out`; the payload must be at most
+    /// [`MAX_HEARTBEAT_SIZE`] bytes
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        debug_assert!(self.0.len() <= MAX_HEARTBEAT_SIZE);
+        out.extend_from_slice(&[EXTENSION_HEARTBEAT, self.0.len() as u8]);
+        out.extend_from_slice(self.0);
+    }
+}
+
+/// Wall-clock time at which a ping was answered, in nanoseconds since the Unix epoch
+/// on the responder's clock
+///
+/// Together with the ping's own timestamp and the time its acknowledgment arrives,
+/// it lets the pinging side estimate the offset between the two clocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyTime(pub u64);
+
+impl ReplyTime {
+    /// The reply time entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_REPLY_TIME)?;
+        Some(ReplyTime(u64::from_be_bytes(entry.value.try_into().ok()?)))
+    }
+
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_REPLY_TIME, 8]);
+        out.extend_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+/// Random id of an instance, carried by its pings and ping acknowledgments
+///
+/// Peers remember the id last seen from each address. A ping from a new address
+/// carrying a known id moves that peer's state to the new address, so connections
+/// survive the sender rebinding its socket or a NAT assigning it a new port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId(pub u64);
+
+impl ConnectionId {
+    /// The connection id entry of `packet`'s extension section
+    pub fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_CONNECTION_ID)?;
+        Some(ConnectionId(u64::from_be_bytes(entry.value.try_into().ok()?)))
+    }
+
+    /// Append the TLV encoding of this entry to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_CONNECTION_ID, 8]);
+        out.extend_from_slice(&self.0.to_be_bytes());
     }
 }
 
@@ -243,6 +782,10 @@ mod tests {
         let serialized = ping.serialize();
         let deserialized = PingPacket::deserialize(&serialized).unwrap();
         assert_eq!(ping.timestamp, deserialized.timestamp);
+        assert_eq!(deserialized.compression, None);
+
+        let ping = PingPacket { compression: Some(3), ..PingPacket::new() };
+        assert_eq!(PingPacket::deserialize(&ping.serialize()).unwrap().compression, Some(3));
     }
 
     #[test]
@@ -253,6 +796,15 @@ mod tests {
         assert_eq!(ack.ack_seqs, deserialized.ack_seqs);
     }
 
+    #[test]
+    fn test_close_reason_serialization() {
+        let reason = CloseReason::new(CloseReason::SERVER_FULL).with_payload("retry later");
+        assert_eq!(CloseReason::deserialize(&reason.serialize()), Some(reason));
+        // Close packets without a reason
+        assert_eq!(CloseReason::deserialize(&[]), Some(CloseReason::new(CloseReason::NORMAL)));
+        assert_eq!(CloseReason::deserialize(&[1]), None);
+    }
+
     #[test]
     fn test_raw_packet_parsing() {
         let mut packet = vec![2u8]; // Data packet
@@ -266,4 +818,99 @@ mod tests {
         assert_eq!(parsed.seq, 100);
         assert_eq!(parsed.data, b"Hello");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_raw_packet_ref_parses_in_place() {
+        let mut packet = vec![3u8]; // DataAck packet
+        packet.extend_from_slice(&0u32.to_be_bytes());
+        packet.extend_from_slice(&7u32.to_be_bytes());
+        packet.extend_from_slice(&[1, 0, 0, 0, 7]);
+
+        let parsed = RawPacketRef::parse(&packet).unwrap();
+        assert_eq!(parsed.packet_type, PacketType::DataAck);
+        assert_eq!(parsed.seq, 7);
+        assert!(std::ptr::eq(parsed.data, &packet[PROTOCOL_HEADER_SIZE..]));
+
+        assert!(RawPacketRef::parse(&packet[..PROTOCOL_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_extension_section() {
+        let mut extensions = Vec::new();
+        Extension { kind: EXTENSION_TIMESTAMP, value: &[1, 2, 3, 4] }.encode(&mut extensions).unwrap();
+        Extension { kind: 200, value: &[] }.encode(&mut extensions).unwrap();
+        assert!(Extension { kind: EXTENSION_ACK, value: &[0; 256] }.encode(&mut extensions).is_err());
+
+        let packet = RawPacket {
+            packet_type: PacketType::Data,
+            security_code: 0,
+            seq: 9,
+            extensions,
+            data: b"payload".to_vec(),
+        };
+        let serialized = packet.serialize();
+        assert_eq!(serialized[0], PacketType::Data as u8 | EXTENSION_FLAG);
+        assert_eq!(&serialized[PROTOCOL_HEADER_SIZE..], &packet.body()[..]);
+
+        let parsed = RawPacketRef::parse(&serialized).unwrap();
+        assert_eq!(parsed.packet_type, PacketType::Data);
+        assert_eq!(parsed.data, b"payload");
+        let entries: Vec<_> = parsed.extensions().collect();
+        assert_eq!(entries, vec![
+            Extension { kind: EXTENSION_TIMESTAMP, value: &[1, 2, 3, 4] },
+            Extension { kind: 200, value: &[] },
+        ]);
+
+        // A length prefix beyond the packet is rejected
+        let truncated = &serialized[..PROTOCOL_HEADER_SIZE + EXTENSION_LENGTH_SIZE + 3];
+        assert!(RawPacketRef::parse(truncated).is_err());
+
+        // Timestamp entries
+        assert_eq!(Timestamp::find(&parsed), Some(Timestamp::Sent(0x01020304)));
+        let mut extensions = Vec::new();
+        Timestamp::Echo { sent: 7, received: u32::MAX }.encode(&mut extensions);
+        let echo = RawPacket { extensions, ..packet.clone() }.serialize();
+        assert_eq!(Timestamp::find(&RawPacketRef::parse(&echo).unwrap()), Some(Timestamp::Echo { sent: 7, received: u32::MAX }));
+
+        // Packets without the flag have no extensions
+        assert_eq!(RawPacketRef::parse(&RawPacket { extensions: Vec::new(), ..packet }.serialize()).unwrap().extensions().count(), 0);
+    }
+
+    #[test]
+    fn test_serialize_into_matches_serialize() {
+        let mut extensions = Vec::new();
+        Timestamp::Sent(5).encode(&mut extensions);
+        let ack = DataAckPacket::new(vec![1, 2, 0xdeadbeef]);
+        let packets = [
+            RawPacket { packet_type: PacketType::DataAck, security_code: 7, seq: 3, extensions, data: ack.serialize() },
+            RawPacket { packet_type: PacketType::Ping, security_code: 0, seq: 4, extensions: Vec::new(), data: PingPacket::new().serialize() },
+        ];
+        let mut out = [0xff; 64];
+        for packet in &packets {
+            let len = packet.serialize_into(&mut out).unwrap();
+            assert_eq!(len, packet.serialized_len());
+            assert_eq!(&out[..len], &packet.serialize()[..]);
+            assert!(packet.serialize_into(&mut out[..len - 1]).is_none());
+        }
+
+        let len = ack.serialize_into(&mut out).unwrap();
+        assert_eq!(DataAckPacket::deserialize(&out[..len]).unwrap().ack_seqs, ack.ack_seqs);
+        let ping = PingPacket { timestamp: 9, compression: Some(3) };
+        let len = ping.serialize_into(&mut out).unwrap();
+        assert_eq!(&out[..len], &ping.serialize()[..]);
+        assert!(ping.serialize_into(&mut out[..8]).is_none());
+    }
+
+    #[test]
+    fn test_fragment_header_roundtrip() {
+        let header = FragmentHeader { message_id: 9, index: 2, count: 3 };
+        let mut data = header.serialize().to_vec();
+        data.extend_from_slice(b"chunk");
+        assert_eq!(FragmentHeader::deserialize(&data), Some(header));
+        assert_eq!(&data[FRAGMENT_HEADER_SIZE..], b"chunk");
+
+        let out_of_range = FragmentHeader { message_id: 9, index: 3, count: 3 };
+        assert_eq!(FragmentHeader::deserialize(&out_of_range.serialize()), None);
+        assert_eq!(FragmentHeader::deserialize(&data[..FRAGMENT_HEADER_SIZE - 1]), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Topic-based publish/subscribe over the message API
+//!
+//! [`PubSub`] wraps a [`Rudpbase`]. A peer calls [`subscribe`](PubSub::subscribe)
+//! with the publisher's address and a topic name; the publisher records the
+//! subscription against that peer's connection, and [`publish`](PubSub::publish)
+//! sends the data reliably to every current subscriber of the topic. Subscriptions
+//! belong to the connection: when a subscriber closes, or is declared dead by
+//! keep-alive, `tick()` forgets all of its topics.
+//!
+//! Every instance can publish and subscribe at the same time, so the same type
+//! serves star and mesh layouts. Subscription requests are handled inside
+//! [`recv`](PubSub::recv), which returns only publications.
+//!
+//! ```rust,no_run
+//! use rudpbase::pubsub::PubSub;
+//! use rudpbase::Rudpbase;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let rudp = Rudpbase::new("127.0.0.1:8081".parse()?).await?;
+//!     let mut sensor = PubSub::new(rudp);
+//!     sensor.subscribe("127.0.0.1:8080".parse()?, "temperature").await?;
+//!     loop {
+//!         sensor.tick().await;
+//!         if let Some(publication) = sensor.recv().await {
+//!             println!("{}: {:?}", publication.topic, publication.data);
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+use crate::core::Rudpbase;
+use crate::error::RudpError;
+use crate::stats::ConnectionStatus;
+
+/// First byte of every pub/sub message
+const PUBSUB_MAGIC: u8 = 0xF9;
+
+/// Pub/sub message header size before the topic: magic(1) + kind(1) + topic_len(2)
+const PUBSUB_HEADER_SIZE: usize = 4;
+
+/// Longest topic name in bytes
+pub const MAX_TOPIC_LEN: usize = u16::MAX as usize;
+
+/// Message kinds
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Subscribe = 0,
+    Unsubscribe = 1,
+    Publish = 2,
+}
+
+/// Decoded pub/sub message
+struct Frame<'a> {
+    kind: Kind,
+    topic: &'a str,
+    data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    fn encode(kind: Kind, topic: &str, data: &[u8]) -> Result<Vec<u8>, RudpError> {
+        if topic.len() > MAX_TOPIC_LEN {
+            return Err(RudpError::BufferTooLarge { size: topic.len(), max: MAX_TOPIC_LEN });
+        }
+        let mut message = Vec::with_capacity(PUBSUB_HEADER_SIZE + topic.len() + data.len());
+        message.push(PUBSUB_MAGIC);
+        message.push(kind as u8);
+        message.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+        message.extend_from_slice(topic.as_bytes());
+        message.extend_from_slice(data);
+        Ok(message)
+    }
+
+    fn decode(message: &'a [u8]) -> Option<Self> {
+        if message.len() < PUBSUB_HEADER_SIZE || message[0] != PUBSUB_MAGIC {
+            return None;
+        }
+        let kind = match message[1] {
+            0 => Kind::Subscribe,
+            1 => Kind::Unsubscribe,
+            2 => Kind::Publish,
+            _ => return None,
+        };
+        let topic_end = PUBSUB_HEADER_SIZE + u16::from_be_bytes([message[2], message[3]]) as usize;
+        let topic = std::str::from_utf8(message.get(PUBSUB_HEADER_SIZE..topic_end)?).ok()?;
+        Some(Self { kind, topic, data: &message[topic_end..] })
+    }
+}
+
+/// Data received on a subscribed topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publication {
+    /// Publisher address
+    pub from: SocketAddr,
+    /// Topic the data was published on
+    pub topic: String,
+    /// Published data
+    pub data: Vec<u8>,
+}
+
+/// Rudpbase instance with topic subscriptions
+///
+/// Use [`get_ref`](Self::get_ref) and [`get_mut`](Self::get_mut) for statistics,
+/// configuration and plain data packets. Messages that are not pub/sub messages are
+/// discarded by [`recv`](Self::recv).
+pub struct PubSub {
+    rudp: Rudpbase,
+    /// Subscribers of each topic this instance publishes
+    topics: HashMap<String, HashSet<SocketAddr>>,
+}
+
+impl PubSub {
+    /// Publish and subscribe over `rudp`
+    pub fn new(rudp: Rudpbase) -> Self {
+        Self { rudp, topics: HashMap::new() }
+    }
+
+    /// Ask `publisher` to send data published on `topic` to this instance
+    pub async fn subscribe(&mut self, publisher: SocketAddr, topic: &str) -> Result<(), RudpError> {
+        let message = Frame::encode(Kind::Subscribe, topic, &[])?;
+        self.rudp.send_message(&message, publisher).await
+    }
+
+    /// Stop receiving `topic` from `publisher`
+    pub async fn unsubscribe(&mut self, publisher: SocketAddr, topic: &str) -> Result<(), RudpError> {
+        let message = Frame::encode(Kind::Unsubscribe, topic, &[])?;
+        self.rudp.send_message(&message, publisher).await
+    }
+
+    /// Send `data` reliably to every current subscriber of `topic`
+    ///
+    /// Returns the number of subscribers it was sent to. Sending continues past a
+    /// failed subscriber; the first error is returned after all were tried.
+    pub async fn publish(&mut self, topic: &str, data: &[u8]) -> Result<usize, RudpError> {
+        let Some(subscribers) = self.topics.get(topic).filter(|subscribers| !subscribers.is_empty()) else {
+            return Ok(0);
+        };
+        let subscribers: Vec<SocketAddr> = subscribers.iter().copied().collect();
+        let message = Frame::encode(Kind::Publish, topic, data)?;
+        let mut result = Ok(subscribers.len());
+        for subscriber in subscribers {
+            if let Err(e) = self.rudp.send_message(&message, subscriber).await {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Receive the next publication, waiting up to 1ms like [`Rudpbase::recv_message`]
+    ///
+    /// Subscription requests from peers are applied here; `None` means no
+    /// publication arrived.
+    pub async fn recv(&mut self) -> Option<Publication> {
+        let message = self.rudp.recv_message().await?;
+        let frame = Frame::decode(&message.data)?;
+        match frame.kind {
+            Kind::Subscribe => {
+                self.topics.entry(frame.topic.to_string()).or_default().insert(message.from);
+                None
+            }
+            Kind::Unsubscribe => {
+                if let Some(subscribers) = self.topics.get_mut(frame.topic) {
+                    subscribers.remove(&message.from);
+                    if subscribers.is_empty() {
+                        self.topics.remove(frame.topic);
+                    }
+                }
+                None
+            }
+            Kind::Publish => Some(Publication {
+                from: message.from,
+                topic: frame.topic.to_string(),
+                data: frame.data.to_vec(),
+            }),
+        }
+    }
+
+    /// Handle retransmissions, ACKs and keep-alive; see [`Rudpbase::tick`]
+    ///
+    /// Also drops the subscriptions of peers whose connection is gone.
+    pub async fn tick(&mut self) {
+        self.rudp.tick().await;
+        let rudp = &self.rudp;
+        self.topics.retain(|_, subscribers| {
+            subscribers.retain(|subscriber| rudp.connection_status(*subscriber) != ConnectionStatus::Dead);
+            !subscribers.is_empty()
+        });
+    }
+
+    /// Current subscribers of `topic`
+    pub fn subscribers(&self, topic: &str) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.topics.get(topic).into_iter().flatten().copied()
+    }
+
+    /// Topics this instance has subscribers for
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.topics.keys().map(String::as_str)
+    }
+
+    /// The wrapped instance
+    pub fn get_ref(&self) -> &Rudpbase {
+        &self.rudp
+    }
+
+    /// The wrapped instance, mutably
+    pub fn get_mut(&mut self) -> &mut Rudpbase {
+        &mut self.rudp
+    }
+
+    /// Unwrap the instance
+    pub fn into_inner(self) -> Rudpbase {
+        self.rudp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let message = Frame::encode(Kind::Publish, "sensors/temp", b"21.5").unwrap();
+        assert_eq!(message.len(), PUBSUB_HEADER_SIZE + 12 + 4);
+        let frame = Frame::decode(&message).unwrap();
+        assert_eq!(frame.kind, Kind::Publish);
+        assert_eq!(frame.topic, "sensors/temp");
+        assert_eq!(frame.data, b"21.5");
+
+        // Truncated topics, invalid UTF-8 and other messages are not pub/sub frames
+        assert!(Frame::decode(&message[..PUBSUB_HEADER_SIZE + 3]).is_none());
+        let mut invalid = message.clone();
+        invalid[PUBSUB_HEADER_SIZE] = 0xff;
+        assert!(Frame::decode(&invalid).is_none());
+        assert!(Frame::decode(b"plain message").is_none());
+
+        let long_topic = "t".repeat(MAX_TOPIC_LEN + 1);
+        assert!(matches!(Frame::encode(Kind::Subscribe, &long_topic, &[]), Err(RudpError::BufferTooLarge { .. })));
+    }
+}
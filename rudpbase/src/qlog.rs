@@ -0,0 +1,322 @@
+//! qlog-style structured event log
+//!
+//! Records per-connection protocol events (packets sent/received/acknowledged/lost,
+//! congestion window and RTO changes) so transfers can be analysed offline with
+//! qlog tooling. Events are delivered to a user-provided [`QlogSink`];
+//! [`QlogJsonWriter`] serializes them as JSON-SEQ (one JSON object per line).
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::protocol::PacketType;
+use crate::stats::{CongestionState, RttStats};
+
+/// A single protocol event
+#[derive(Debug, Clone, PartialEq)]
+pub enum QlogEvent {
+    /// A packet was put on the wire
+    PacketSent {
+        packet_type: PacketType,
+        seq: u32,
+        length: usize,
+        retransmission: bool,
+    },
+    /// A packet was received and passed verification
+    PacketReceived {
+        packet_type: PacketType,
+        seq: u32,
+        length: usize,
+    },
+    /// A data packet was acknowledged by the peer
+    PacketAcked { seq: u32, rtt: Duration },
+    /// A data packet timed out and is considered lost
+    PacketLost { seq: u32 },
+    /// A data packet was dropped after exhausting all retransmissions
+    PacketDropped { seq: u32 },
+    /// Congestion control or RTT estimator state changed
+    MetricsUpdated {
+        cwnd: u32,
+        ssthresh: u32,
+        in_flight: u32,
+        srtt: Duration,
+        rttvar: Duration,
+        rto: Duration,
+        congestion_state: CongestionState,
+    },
+}
+
+impl QlogEvent {
+    /// qlog event name (`category:event`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            QlogEvent::PacketSent { .. } => "transport:packet_sent",
+            QlogEvent::PacketReceived { .. } => "transport:packet_received",
+            QlogEvent::PacketAcked { .. } => "recovery:packet_acked",
+            QlogEvent::PacketLost { .. } => "recovery:packet_lost",
+            QlogEvent::PacketDropped { .. } => "transport:packet_dropped",
+            QlogEvent::MetricsUpdated { .. } => "recovery:metrics_updated",
+        }
+    }
+
+    /// Serialize the event-specific `data` object
+    fn write_data(&self, out: &mut String) {
+        let _ = match self {
+            QlogEvent::PacketSent { packet_type, seq, length, retransmission } => write!(
+                out,
+                r#"{{"header":{{"packet_type":"{}","packet_number":{}}},"raw":{{"length":{}}},"is_retransmission":{}}}"#,
+                packet_type_name(*packet_type), seq, length, retransmission
+            ),
+            QlogEvent::PacketReceived { packet_type, seq, length } => write!(
+                out,
+                r#"{{"header":{{"packet_type":"{}","packet_number":{}}},"raw":{{"length":{}}}}}"#,
+                packet_type_name(*packet_type), seq, length
+            ),
+            QlogEvent::PacketAcked { seq, rtt } => write!(
+                out,
+                r#"{{"packet_number":{},"rtt":{}}}"#,
+                seq, millis(*rtt)
+            ),
+            QlogEvent::PacketLost { seq } | QlogEvent::PacketDropped { seq } => write!(
+                out,
+                r#"{{"header":{{"packet_type":"data","packet_number":{}}}}}"#,
+                seq
+            ),
+            QlogEvent::MetricsUpdated { cwnd, ssthresh, in_flight, srtt, rttvar, rto, congestion_state } => write!(
+                out,
+                r#"{{"congestion_window":{},"ssthresh":{},"packets_in_flight":{},"smoothed_rtt":{},"rtt_variance":{},"rto":{},"congestion_state":"{}"}}"#,
+                cwnd, ssthresh, in_flight, millis(*srtt), millis(*rttvar), millis(*rto),
+                congestion_state_name(congestion_state)
+            ),
+        };
+    }
+}
+
+/// An event tagged with its connection and relative time
+#[derive(Debug, Clone, PartialEq)]
+pub struct QlogRecord {
+    /// Time since the log was started
+    pub time: Duration,
+    /// Peer the event belongs to (used as the qlog `group_id`)
+    pub peer: SocketAddr,
+    /// The event itself
+    pub event: QlogEvent,
+}
+
+impl QlogRecord {
+    /// Serialize as a single-line qlog JSON event
+    pub fn to_json(&self) -> String {
+        let mut out = String::with_capacity(192);
+        let _ = write!(
+            out,
+            r#"{{"time":{},"name":"{}","group_id":"{}","data":"#,
+            millis(self.time),
+            self.event.name(),
+            self.peer
+        );
+        self.event.write_data(&mut out);
+        out.push('}');
+        out
+    }
+}
+
+/// Destination for qlog records
+///
+/// Implemented for any `FnMut(&QlogRecord) + Send` closure, so records can be pushed
+/// straight into a channel or a collection.
+pub trait QlogSink: Send {
+    fn write_record(&mut self, record: &QlogRecord);
+}
+
+impl<F: FnMut(&QlogRecord) + Send> QlogSink for F {
+    fn write_record(&mut self, record: &QlogRecord) {
+        self(record)
+    }
+}
+
+/// Sink writing JSON-SEQ qlog output to any `Write` implementation
+pub struct QlogJsonWriter<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> QlogJsonWriter<W> {
+    /// Create a writer and emit the qlog header line
+    pub fn new(mut writer: W, title: &str) -> std::io::Result<Self> {
+        writeln!(
+            writer,
+            r#"{{"qlog_version":"0.3","qlog_format":"JSON-SEQ","title":"{}","trace":{{"vantage_point":{{"type":"endpoint"}},"common_fields":{{"time_format":"relative"}}}}}}"#,
+            title.replace('\\', "\\\\").replace('"', "\\\"")
+        )?;
+        Ok(Self { writer })
+    }
+
+    /// Consume the sink and return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> QlogSink for QlogJsonWriter<W> {
+    fn write_record(&mut self, record: &QlogRecord) {
+        let _ = writeln!(self.writer, "{}", record.to_json());
+    }
+}
+
+/// Metrics last reported per peer, used to suppress duplicate metrics_updated events
+#[derive(Debug, Clone, PartialEq)]
+struct MetricsSnapshot {
+    cwnd: u32,
+    ssthresh: u32,
+    srtt: Duration,
+    rto: Duration,
+    congestion_state: CongestionState,
+}
+
+/// Event log attached to a Rudpbase instance
+pub(crate) struct QlogWriter {
+    sink: Box<dyn QlogSink>,
+    start: Instant,
+    last_metrics: HashMap<SocketAddr, MetricsSnapshot>,
+}
+
+impl QlogWriter {
+    pub(crate) fn new(sink: Box<dyn QlogSink>) -> Self {
+        Self {
+            sink,
+            start: Instant::now(),
+            last_metrics: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn log(&mut self, peer: SocketAddr, event: QlogEvent) {
+        let record = QlogRecord {
+            time: self.start.elapsed(),
+            peer,
+            event,
+        };
+        self.sink.write_record(&record);
+    }
+
+    /// Emit metrics_updated if the congestion or RTT state changed since the last report
+    pub(crate) fn log_metrics(&mut self, peer: SocketAddr, stats: &RttStats) {
+        let snapshot = MetricsSnapshot {
+            cwnd: stats.cwnd,
+            ssthresh: stats.ssthresh,
+            srtt: stats.srtt,
+            rto: stats.rto,
+            congestion_state: stats.congestion_state.clone(),
+        };
+        if self.last_metrics.get(&peer) == Some(&snapshot) {
+            return;
+        }
+        self.last_metrics.insert(peer, snapshot);
+
+        self.log(peer, QlogEvent::MetricsUpdated {
+            cwnd: stats.cwnd,
+            ssthresh: stats.ssthresh,
+            in_flight: stats.in_flight,
+            srtt: stats.srtt,
+            rttvar: stats.rttvar,
+            rto: stats.rto,
+            congestion_state: stats.congestion_state.clone(),
+        });
+    }
+
+    /// Forget per-peer state when a connection is removed
+    pub(crate) fn forget(&mut self, peer: SocketAddr) {
+        self.last_metrics.remove(&peer);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn packet_type_name(packet_type: PacketType) -> &'static str {
+    match packet_type {
+        PacketType::Ping => "ping",
+        PacketType::PingAck => "ping_ack",
+        PacketType::Data => "data",
+        PacketType::DataAck => "data_ack",
+        PacketType::DataNack => "data_nack",
+        PacketType::Close => "close",
+        PacketType::CloseAck => "close_ack",
+        PacketType::Fragment => "fragment",
+        PacketType::Compressed => "compressed",
+        PacketType::Datagram => "datagram",
+        PacketType::Relay => "relay",
+        PacketType::Reset => "reset",
+        PacketType::Shutdown => "shutdown",
+        PacketType::Batch => "batch",
+        PacketType::Bundle => "bundle",
+    }
+}
+
+fn congestion_state_name(state: &CongestionState) -> &'static str {
+    match state {
+        CongestionState::SlowStart => "slow_start",
+        CongestionState::CongestionAvoidance => "congestion_avoidance",
+        CongestionState::FastRecovery => "fast_recovery",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_record_json() {
+        let record = QlogRecord {
+            time: Duration::from_micros(1500),
+            peer: "127.0.0.1:9000".parse().unwrap(),
+            event: QlogEvent::PacketSent {
+                packet_type: PacketType::Data,
+                seq: 7,
+                length: 42,
+                retransmission: false,
+            },
+        };
+
+        assert_eq!(
+            record.to_json(),
+            r#"{"time":1.5,"name":"transport:packet_sent","group_id":"127.0.0.1:9000","data":{"header":{"packet_type":"data","packet_number":7},"raw":{"length":42},"is_retransmission":false}}"#
+        );
+    }
+
+    #[test]
+    fn test_json_writer_output() {
+        let mut sink = QlogJsonWriter::new(Vec::new(), "test").unwrap();
+        sink.write_record(&QlogRecord {
+            time: Duration::ZERO,
+            peer: "127.0.0.1:9000".parse().unwrap(),
+            event: QlogEvent::PacketLost { seq: 3 },
+        });
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""qlog_format":"JSON-SEQ""#));
+        assert!(lines[1].contains(r#""name":"recovery:packet_lost""#));
+    }
+
+    #[test]
+    fn test_metrics_deduplicated() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&records);
+        let mut writer = QlogWriter::new(Box::new(move |record: &QlogRecord| {
+            collected.lock().unwrap().push(record.clone());
+        }));
+
+        let peer = "127.0.0.1:9000".parse().unwrap();
+        let mut stats = RttStats::new();
+        writer.log_metrics(peer, &stats);
+        writer.log_metrics(peer, &stats);
+        stats.on_packet_lost(Instant::now());
+        writer.log_metrics(peer, &stats);
+
+        assert_eq!(records.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,299 @@
+//! Rudpbase on the async-io reactor
+//!
+//! smol and async-std both run their I/O on the `async-io` reactor, so one adapter
+//! serves both: it owns an `async_io::Async<UdpSocket>` and drives the sans-IO
+//! [`RudpCore`] the same way the tokio `Rudpbase` does. It is
+//! re-exported as `rudpbase::smol::Rudpbase` and `rudpbase::async_std::Rudpbase`.
+//!
+//! The adapter covers the common send/receive path. Everything else the core offers
+//! (statistics, keep-alive overrides, qlog, event handlers, runtime configuration) is
+//! reached through [`core`](Rudpbase::core) and [`core_mut`](Rudpbase::core_mut).
+//! Batched I/O, the io_uring backend, custom transports and STUN discovery need the
+//! tokio build.
+
+use std::future::{poll_fn, Future};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use async_io::{Async, Timer};
+
+use crate::buffer_pool::PooledBuffer;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{IoBackend, RudpConfig};
+use crate::delivery::DeliveryHandle;
+use crate::engine::{ReceivedData, RudpCore};
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+use crate::socket;
+
+/// How long `recv()` and `recv_message()` wait for a datagram
+const RECV_WAIT: Duration = Duration::from_millis(1);
+
+/// Reliable UDP endpoint for smol and async-std
+///
+/// Usage mirrors the tokio `Rudpbase`: call [`tick`](Self::tick) regularly and
+/// [`recv`](Self::recv) to receive data.
+///
+/// ```rust,no_run
+/// use rudpbase::smol::Rudpbase;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     async_io::block_on(async {
+///         let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?)?;
+///         let mut buffer = rudp.get_buffer()?;
+///         buffer.data_mut()[..5].copy_from_slice(b"hello");
+///         buffer.set_data_len(5)?;
+///         rudp.send(buffer, "127.0.0.1:8081".parse()?).await?;
+///         loop {
+///             rudp.tick().await;
+///             if let Some(received) = rudp.recv().await {
+///                 println!("{} bytes from {}", received.result?.data_len(), received.from);
+///             }
+///         }
+///     })
+/// }
+/// ```
+pub struct Rudpbase {
+    socket: Async<UdpSocket>,
+    clock: Box<dyn Clock>,
+    core: RudpCore,
+}
+
+impl Rudpbase {
+    /// Bind a socket to `local_addr` with the default configuration
+    pub fn new(local_addr: SocketAddr) -> Result<Self, RudpError> {
+        Self::with_config(local_addr, RudpConfig::default())
+    }
+
+    /// Bind a socket to `local_addr`, applying `config.socket`
+    pub fn with_config(local_addr: SocketAddr, config: RudpConfig) -> Result<Self, RudpError> {
+        config.validate()?;
+        let socket = socket::bind_std(local_addr, &config.socket)?;
+        Self::with_socket(socket, config)
+    }
+
+    /// Use an already bound socket
+    ///
+    /// `config.socket` is ignored. Only unbatched socket I/O is available:
+    /// `io_batch_size` must be 1 and `io_backend` must be `IoBackend::Socket`.
+    pub fn with_socket(socket: UdpSocket, config: RudpConfig) -> Result<Self, RudpError> {
+        if config.io_batch_size != 1 || config.io_backend != IoBackend::Socket {
+            return Err(RudpError::InvalidConfig {
+                message: "batched I/O and io_uring require the tokio build".to_string(),
+            });
+        }
+        let clock: Box<dyn Clock> = Box::new(SystemClock);
+        let core = RudpCore::new(config)?;
+        Ok(Self {
+            socket: Async::new(socket)?,
+            clock,
+            core,
+        })
+    }
+
+    /// Local address of the socket
+    pub fn local_addr(&self) -> Result<SocketAddr, RudpError> {
+        Ok(self.socket.get_ref().local_addr()?)
+    }
+
+    /// The protocol core, for statistics and configuration
+    pub fn core(&self) -> &RudpCore {
+        &self.core
+    }
+
+    /// Mutable access to the protocol core
+    ///
+    /// Sending through the core directly queues datagrams that go out with the next
+    /// `tick()` or `recv()`.
+    pub fn core_mut(&mut self) -> &mut RudpCore {
+        &mut self.core
+    }
+
+    /// Replace the time source; see [`Clock`]
+    pub fn set_clock<C: Clock>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Get a pool buffer to write a payload into
+    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
+        self.core.get_buffer()
+    }
+
+    /// Send a payload reliably, returning its sequence number
+    ///
+    /// Queued while the congestion window is full; returns
+    /// `RudpError::CongestionWindowFull` when the peer's send queue is full too.
+    pub async fn send(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.send(buffer, target, self.clock.now())?;
+        self.flush_transmits().await?;
+        Ok(seq)
+    }
+
+    /// Send a payload now, returning `RudpError::CongestionWindowFull` instead of queueing
+    pub async fn try_send(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let seq = self.core.try_send(buffer, target, self.clock.now())?;
+        self.flush_transmits().await?;
+        Ok(seq)
+    }
+
+    /// Send a payload and track its delivery; see [`DeliveryHandle`]
+    pub async fn send_tracked(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<DeliveryHandle, RudpError> {
+        let handle = self.core.send_tracked(buffer, target, self.clock.now())?;
+        self.flush_transmits().await?;
+        Ok(handle)
+    }
+
+    /// Send a message of any length up to `max_message_size`
+    pub async fn send_message(&mut self, data: &[u8], target: SocketAddr) -> Result<(), RudpError> {
+        self.core.send_message(data, target, self.clock.now())?;
+        self.flush_transmits().await
+    }
+
+    /// Send an unreliable datagram
+    pub async fn send_unreliable(&mut self, buffer: PooledBuffer, target: SocketAddr) -> Result<(), RudpError> {
+        self.core.send_unreliable(buffer, target)?;
+        self.flush_transmits().await
+    }
+
+    /// Receive user data, waiting up to 1ms
+    ///
+    /// Control packets are handled internally; `None` means no user data arrived.
+    pub async fn recv(&mut self) -> Option<ReceivedData> {
+        if !self.core.has_received() {
+            self.poll_incoming().await;
+        }
+        self.core.poll_received()
+    }
+
+    /// Receive a complete message, waiting up to 1ms
+    pub async fn recv_message(&mut self) -> Option<ReceivedMessage> {
+        if !self.core.has_messages() {
+            self.poll_incoming().await;
+        }
+        self.core.poll_message()
+    }
+
+    /// Handle retransmissions, ACKs, keep-alive and cleanup
+    pub async fn tick(&mut self) {
+        self.core.handle_timeout(self.clock.now());
+        let _ = self.flush_transmits().await;
+    }
+
+    /// Send close packets to all peers and clear connection state
+    pub async fn close(&mut self) {
+        let _ = self.flush_transmits().await;
+        self.core.close();
+        let _ = self.flush_transmits().await;
+    }
+
+    /// Read at most one datagram into the core
+    async fn poll_incoming(&mut self) {
+        let mut buffer = match self.core.get_buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => return self.core.push_received(ReceivedData {
+                from: "0.0.0.0:0".parse().unwrap(),
+                seq: None,
+                result: Err(e),
+            }),
+        };
+        match recv_within(&self.socket, buffer.raw_buffer_mut(), RECV_WAIT).await {
+            Some(Ok((len, from))) => self.core.handle_buffer(buffer, len, from, self.clock.now()),
+            Some(Err(e)) => self.core.push_received(ReceivedData {
+                from: "0.0.0.0:0".parse().unwrap(),
+                seq: None,
+                result: Err(RudpError::Io(e)),
+            }),
+            None => {}
+        }
+        let _ = self.flush_transmits().await;
+    }
+
+    /// Send every datagram the core has queued, returning the first error
+    async fn flush_transmits(&mut self) -> Result<(), RudpError> {
+        let mut result = Ok(());
+        for (data, target) in self.core.queued_transmits() {
+            if let Err(e) = self.socket.send_to(data, target).await {
+                result = result.and(Err(e.into()));
+            }
+        }
+        self.core.clear_transmits();
+        result
+    }
+}
+
+/// Receive one datagram, or `None` if none arrives within `wait`
+async fn recv_within(socket: &Async<UdpSocket>, buf: &mut [u8], wait: Duration) -> Option<io::Result<(usize, SocketAddr)>> {
+    let mut timer = Timer::at(Instant::now() + wait);
+    loop {
+        match socket.get_ref().recv_from(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => return Some(result),
+        }
+        let readable = poll_fn(|cx| {
+            if let Poll::Ready(result) = socket.poll_readable(cx) {
+                return Poll::Ready(Some(result));
+            }
+            Pin::new(&mut timer).poll(cx).map(|_| None)
+        });
+        match readable.await {
+            Some(Ok(())) => continue,
+            Some(Err(e)) => return Some(Err(e)),
+            None => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(rudp: &Rudpbase, data: &[u8]) -> PooledBuffer {
+        let mut buffer = rudp.get_buffer().unwrap();
+        buffer.data_mut()[..data.len()].copy_from_slice(data);
+        buffer.set_data_len(data.len()).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_exchange_on_async_io() {
+        async_io::block_on(async {
+            let mut a = Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap();
+            let mut b = Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap();
+            let a_addr = a.local_addr().unwrap();
+            let b_addr = b.local_addr().unwrap();
+
+            let mut delivery = a.send_tracked(payload(&a, b"hello"), b_addr).await.unwrap();
+            let mut received = None;
+            for _ in 0..1000 {
+                if let Some(data) = b.recv().await {
+                    received = Some(data);
+                    break;
+                }
+            }
+            let received = received.expect("no data received");
+            assert_eq!(received.from, a_addr);
+            assert_eq!(received.result.unwrap().data(), b"hello");
+
+            // The ACK goes out with b's next tick and settles the handle on a
+            b.tick().await;
+            for _ in 0..1000 {
+                a.recv().await;
+                if a.core().unacked_packets(b_addr) == 0 {
+                    break;
+                }
+            }
+            assert_eq!(a.core().unacked_packets(b_addr), 0);
+            assert!(poll_fn(|cx| Poll::Ready(Pin::new(&mut delivery).poll(cx))).await.is_ready());
+        });
+    }
+
+    #[test]
+    fn test_rejects_batched_io() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = RudpConfig { io_batch_size: 8, ..RudpConfig::default() };
+        assert!(matches!(Rudpbase::with_socket(socket, config), Err(RudpError::InvalidConfig { .. })));
+    }
+}
@@ -0,0 +1,118 @@
+//! Redundant copies of critical data packets
+//!
+//! [`Redundancy`] keeps a moving estimate of each peer's packet loss: every
+//! retransmission timeout or NACK counts as a lost packet, every data packet
+//! acknowledged without having been retransmitted as a delivered one. While a peer's
+//! estimate is above `RedundancyConfig::loss_threshold`, critical packets sent to it
+//! are scheduled to go out a second time after `RedundancyConfig::spacing`, so a
+//! single loss no longer costs a full retransmission timeout.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Weight of a new sample in the loss estimate
+const LOSS_GAIN: f64 = 1.0 / 16.0;
+
+/// Loss estimates and scheduled copies per peer
+#[derive(Debug, Default)]
+pub(crate) struct Redundancy {
+    loss: HashMap<SocketAddr, f64>,
+    /// Copies to send, by time; the spacing can change between copies
+    copies: VecDeque<(Instant, SocketAddr, u32)>,
+}
+
+impl Redundancy {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// A data packet to `addr` was lost (`true`) or delivered on its first attempt
+    pub(crate) fn record(&mut self, addr: SocketAddr, lost: bool) {
+        let estimate = self.loss.entry(addr).or_default();
+        *estimate += LOSS_GAIN * (f64::from(u8::from(lost)) - *estimate);
+    }
+
+    /// Estimated fraction of data packets to `addr` that are lost
+    pub(crate) fn loss_rate(&self, addr: SocketAddr) -> f64 {
+        self.loss.get(&addr).copied().unwrap_or(0.0)
+    }
+
+    /// Send a copy of packet `seq` to `addr` at `at`
+    pub(crate) fn schedule(&mut self, addr: SocketAddr, seq: u32, at: Instant) {
+        let index = self.copies.partition_point(|(copy_at, _, _)| *copy_at <= at);
+        self.copies.insert(index, (at, addr, seq));
+    }
+
+    /// When the next copy is due
+    pub(crate) fn next_copy(&self) -> Option<Instant> {
+        self.copies.front().map(|(at, _, _)| *at)
+    }
+
+    /// Take the next copy due at `now`
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<(SocketAddr, u32)> {
+        let (at, _, _) = self.copies.front()?;
+        if *at > now {
+            return None;
+        }
+        self.copies.pop_front().map(|(_, addr, seq)| (addr, seq))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.loss.clear();
+        self.copies.clear();
+    }
+
+    pub(crate) fn remove(&mut self, addr: SocketAddr) {
+        self.loss.remove(&addr);
+        self.copies.retain(|(_, copy_addr, _)| *copy_addr != addr);
+    }
+
+    pub(crate) fn move_peer(&mut self, old: SocketAddr, new: SocketAddr) {
+        if let Some(estimate) = self.loss.remove(&old) {
+            self.loss.insert(new, estimate);
+        }
+        for (_, addr, _) in &mut self.copies {
+            if *addr == old {
+                *addr = new;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_loss_estimate_and_copy_schedule() {
+        let addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let mut redundancy = Redundancy::new();
+        assert_eq!(redundancy.loss_rate(addr), 0.0);
+
+        // Two losses in a row cross a 10% threshold, deliveries bring it back down
+        redundancy.record(addr, true);
+        redundancy.record(addr, true);
+        assert!(redundancy.loss_rate(addr) > 0.1);
+        for _ in 0..10 {
+            redundancy.record(addr, false);
+        }
+        assert!(redundancy.loss_rate(addr) < 0.1);
+
+        let start = Instant::now();
+        redundancy.schedule(addr, 7, start + Duration::from_millis(5));
+        assert_eq!(redundancy.next_copy(), Some(start + Duration::from_millis(5)));
+        assert_eq!(redundancy.pop_due(start), None);
+        assert_eq!(redundancy.pop_due(start + Duration::from_millis(5)), Some((addr, 7)));
+        assert_eq!(redundancy.next_copy(), None);
+
+        // A shorter spacing puts a later packet's copy ahead of an earlier one
+        redundancy.schedule(addr, 8, start + Duration::from_millis(20));
+        redundancy.schedule(addr, 9, start + Duration::from_millis(10));
+        assert_eq!(redundancy.next_copy(), Some(start + Duration::from_millis(10)));
+        assert_eq!(redundancy.pop_due(start + Duration::from_millis(10)), Some((addr, 9)));
+        assert_eq!(redundancy.pop_due(start + Duration::from_millis(10)), None);
+        assert_eq!(redundancy.pop_due(start + Duration::from_millis(20)), Some((addr, 8)));
+    }
+}
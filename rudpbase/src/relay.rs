@@ -0,0 +1,393 @@
+//! Relay fallback for peers that cannot reach each other directly
+//!
+//! When hole punching fails, two peers can exchange packets through a [`Relay`] both
+//! of them reach. Each peer registers with the relay under a numeric peer ID and
+//! wraps its transport in a [`RelayTransport`]. Packets to a peer routed through the
+//! relay are sent as `PacketType::Relay` frames naming the destination ID; the relay
+//! replaces it with the sender's ID and forwards the frame. The enclosed Rudpbase
+//! packet is forwarded byte for byte, so its sequence number and security code are
+//! checked end to end and the relay only needs the frame salt.
+//!
+//! Frames add [`RELAY_OVERHEAD`] bytes to every datagram, which `max_payload_size`
+//! should leave room for.
+//!
+//! ```rust,no_run
+//! use rudpbase::relay::{Relay, RelayTransport};
+//! use rudpbase::{RudpConfig, Rudpbase};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     // On the relay host
+//!     let mut relay = Relay::bind("0.0.0.0:3478".parse()?).await?;
+//!     tokio::spawn(async move { relay.run().await });
+//!
+//!     // On peer 1, after hole punching to peer 2 at 198.51.100.2:9000 failed
+//!     let socket = tokio::net::UdpSocket::bind("0.0.0.0:9000").await?;
+//!     let transport = RelayTransport::new(socket, 1);
+//!     let routes = transport.routes();
+//!     let mut rudp = Rudpbase::with_transport(transport, RudpConfig::default()).await?;
+//!     let relay_addr = "203.0.113.1:3478".parse()?;
+//!     routes.register(relay_addr).await?;
+//!     routes.add("198.51.100.2:9000".parse()?, relay_addr, 2);
+//!
+//!     let mut buffer = rudp.get_buffer()?;
+//!     buffer.data_mut()[..5].copy_from_slice(b"hello");
+//!     buffer.set_data_len(5)?;
+//!     rudp.send(buffer, "198.51.100.2:9000".parse()?).await?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use crate::error::RudpError;
+use crate::protocol::{PacketType, RawPacket, RawPacketRef, PROTOCOL_HEADER_SIZE};
+use crate::security::{SecurityCode, DEFAULT_SALT};
+use crate::transport::{self, Transport};
+
+/// Bytes a relay frame adds in front of the enclosed packet
+pub const RELAY_OVERHEAD: usize = PROTOCOL_HEADER_SIZE + RELAY_HEADER_SIZE;
+
+/// Registrations not refreshed for this long are dropped by the relay
+pub const DEFAULT_RELAY_PEER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Frame kind and peer ID following the base header
+const RELAY_HEADER_SIZE: usize = 9;
+
+/// Largest datagram the relay forwards
+const MAX_RELAY_DATAGRAM: usize = 65536;
+
+/// Relay frame kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    /// Peer to relay: the peer ID is the sender's own
+    Register = 0,
+    /// Peer to relay: the peer ID is the destination; relay to peer: the source
+    Forward = 1,
+}
+
+/// Serialize a relay frame enclosing `payload`
+fn encode_frame(salt: &[u8], kind: FrameKind, peer_id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(RELAY_HEADER_SIZE + payload.len());
+    data.push(kind as u8);
+    data.extend_from_slice(&peer_id.to_be_bytes());
+    data.extend_from_slice(payload);
+    RawPacket {
+        packet_type: PacketType::Relay,
+        security_code: SecurityCode::calculate_with_salt(salt, PacketType::Relay, 0, &data),
+        seq: 0,
+        extensions: Vec::new(),
+        data,
+    }
+    .serialize()
+}
+
+/// Parse a relay frame, returning its kind, peer ID and enclosed payload
+///
+/// Returns `None` for other packets and frames with a bad security code.
+fn decode_frame<'a>(salt: &[u8], datagram: &'a [u8]) -> Option<(FrameKind, u64, &'a [u8])> {
+    let packet = RawPacketRef::parse(datagram).ok()?;
+    if packet.packet_type != PacketType::Relay
+        || !packet.extensions.is_empty()
+        || packet.data.len() < RELAY_HEADER_SIZE
+        || !SecurityCode::verify_with_salt(salt, PacketType::Relay, packet.seq, packet.data, packet.security_code)
+    {
+        return None;
+    }
+    let kind = match packet.data[0] {
+        0 => FrameKind::Register,
+        1 => FrameKind::Forward,
+        _ => return None,
+    };
+    let peer_id = u64::from_be_bytes(packet.data[1..RELAY_HEADER_SIZE].try_into().unwrap());
+    Some((kind, peer_id, &packet.data[RELAY_HEADER_SIZE..]))
+}
+
+/// A registered peer
+struct Registration {
+    addr: SocketAddr,
+    last_seen: Instant,
+}
+
+/// Forwards relay frames between registered peers
+///
+/// Frames from unregistered senders or to unknown peer IDs are dropped. A peer is
+/// registered by its register frames and refreshed by every frame it sends.
+pub struct Relay {
+    transport: Box<dyn Transport>,
+    salt: Vec<u8>,
+    peer_timeout: Duration,
+    peers: HashMap<u64, Registration>,
+    peer_ids: HashMap<SocketAddr, u64>,
+    last_expiry: Instant,
+}
+
+impl Relay {
+    /// Create a relay listening on a UDP socket bound to `addr`
+    pub async fn bind(addr: SocketAddr) -> Result<Self, RudpError> {
+        Ok(Self::with_transport(UdpSocket::bind(addr).await?))
+    }
+
+    /// Create a relay on a custom transport
+    pub fn with_transport<T: Transport>(transport: T) -> Self {
+        Self {
+            transport: Box::new(transport),
+            salt: DEFAULT_SALT.to_vec(),
+            peer_timeout: DEFAULT_RELAY_PEER_TIMEOUT,
+            peers: HashMap::new(),
+            peer_ids: HashMap::new(),
+            last_expiry: Instant::now(),
+        }
+    }
+
+    /// Salt of the frame security code; peers' `RelayTransport`s must use the same
+    pub fn with_salt(mut self, salt: impl Into<Vec<u8>>) -> Self {
+        self.salt = salt.into();
+        self
+    }
+
+    /// Time after which a silent peer's registration is dropped
+    pub fn with_peer_timeout(mut self, timeout: Duration) -> Self {
+        self.peer_timeout = timeout;
+        self
+    }
+
+    /// Local address of the relay
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Address peer `peer_id` is registered from
+    pub fn peer_addr(&self, peer_id: u64) -> Option<SocketAddr> {
+        self.peers.get(&peer_id).map(|registration| registration.addr)
+    }
+
+    /// Number of registered peers
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Forward frames until the transport fails
+    pub async fn run(&mut self) -> Result<(), RudpError> {
+        let mut buf = vec![0u8; MAX_RELAY_DATAGRAM];
+        loop {
+            self.process(&mut buf).await?;
+        }
+    }
+
+    /// Receive and handle one datagram
+    async fn process(&mut self, buf: &mut [u8]) -> Result<(), RudpError> {
+        let (len, from) = transport::recv_from(&*self.transport, buf).await?;
+        let now = Instant::now();
+        if now.duration_since(self.last_expiry) >= self.peer_timeout / 4 {
+            self.expire(now);
+        }
+        let Some((kind, peer_id, payload)) = decode_frame(&self.salt, &buf[..len]) else {
+            trace_event!(debug, %from, len, "dropping non-relay datagram");
+            return Ok(());
+        };
+
+        match kind {
+            FrameKind::Register => {
+                if let Some(previous) = self.peers.insert(peer_id, Registration { addr: from, last_seen: now }) {
+                    self.peer_ids.remove(&previous.addr);
+                }
+                if let Some(previous_id) = self.peer_ids.insert(from, peer_id).filter(|id| *id != peer_id) {
+                    self.peers.remove(&previous_id);
+                }
+                trace_event!(debug, %from, peer_id, "relay peer registered");
+            }
+            FrameKind::Forward => {
+                let Some(&source_id) = self.peer_ids.get(&from) else {
+                    trace_event!(debug, %from, "dropping frame from unregistered sender");
+                    return Ok(());
+                };
+                if let Some(source) = self.peers.get_mut(&source_id) {
+                    source.last_seen = now;
+                }
+                let Some(target) = self.peers.get(&peer_id).map(|registration| registration.addr) else {
+                    trace_event!(debug, %from, peer_id, "dropping frame to unknown peer");
+                    return Ok(());
+                };
+                let frame = encode_frame(&self.salt, FrameKind::Forward, source_id, payload);
+                if let Err(_e) = transport::send_to(&*self.transport, &frame, target).await {
+                    trace_event!(debug, %target, error = %_e, "relay forward failed");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop registrations that have not been refreshed within the peer timeout
+    fn expire(&mut self, now: Instant) {
+        let timeout = self.peer_timeout;
+        let peer_ids = &mut self.peer_ids;
+        self.peers.retain(|_, registration| {
+            let alive = now.duration_since(registration.last_seen) < timeout;
+            if !alive {
+                peer_ids.remove(&registration.addr);
+            }
+            alive
+        });
+        self.last_expiry = now;
+    }
+}
+
+/// Routes of one `RelayTransport`
+#[derive(Default)]
+struct RouteTable {
+    /// Peer address used by Rudpbase -> (relay, peer ID)
+    routes: HashMap<SocketAddr, (SocketAddr, u64)>,
+    /// (relay, peer ID) -> peer address used by Rudpbase
+    peers: HashMap<(SocketAddr, u64), SocketAddr>,
+}
+
+/// Transport wrapper sending packets to routed peers through a relay
+///
+/// Packets to peers without a route go directly through the wrapped transport.
+/// Relayed packets arriving from a routed peer are unwrapped and reported with the
+/// peer's address, so Rudpbase sees the same peer either way. Batched I/O is not
+/// available through the wrapper.
+pub struct RelayTransport {
+    inner: Arc<dyn Transport>,
+    routes: RelayRoutes,
+}
+
+impl RelayTransport {
+    /// Wrap `inner`, registering as `local_id` with relays
+    pub fn new<T: Transport>(inner: T, local_id: u64) -> Self {
+        let inner: Arc<dyn Transport> = Arc::new(inner);
+        Self {
+            routes: RelayRoutes {
+                inner: Arc::clone(&inner),
+                table: Arc::default(),
+                local_id,
+                salt: Arc::from(DEFAULT_SALT),
+            },
+            inner,
+        }
+    }
+
+    /// Salt of the frame security code, which must match the relay's
+    pub fn with_salt(mut self, salt: impl Into<Vec<u8>>) -> Self {
+        self.routes.salt = Arc::from(salt.into());
+        self
+    }
+
+    /// Handle for changing routes after the transport is passed to Rudpbase
+    pub fn routes(&self) -> RelayRoutes {
+        self.routes.clone()
+    }
+}
+
+impl Transport for RelayTransport {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        let Some((relay, peer_id)) = self.routes.route(target) else {
+            return self.inner.poll_send_to(cx, buf, target);
+        };
+        let frame = encode_frame(&self.routes.salt, FrameKind::Forward, peer_id, buf);
+        ready!(self.inner.poll_send_to(cx, &frame, relay))?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        loop {
+            let (len, from) = ready!(self.inner.poll_recv_from(cx, buf))?;
+            let Some((FrameKind::Forward, source_id, payload)) = decode_frame(&self.routes.salt, &buf[..len]) else {
+                return Poll::Ready(Ok((len, from)));
+            };
+            let peer = self.routes.table.lock().unwrap().peers.get(&(from, source_id)).copied();
+            if let Some(peer) = peer {
+                let payload_len = payload.len();
+                buf.copy_within(RELAY_OVERHEAD..RELAY_OVERHEAD + payload_len, 0);
+                return Poll::Ready(Ok((payload_len, peer)));
+            }
+            // Relayed packets from peers without a route are dropped
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+/// Shared handle to the routes of a [`RelayTransport`]
+#[derive(Clone)]
+pub struct RelayRoutes {
+    inner: Arc<dyn Transport>,
+    table: Arc<Mutex<RouteTable>>,
+    local_id: u64,
+    salt: Arc<[u8]>,
+}
+
+impl RelayRoutes {
+    /// Register this transport's peer ID with `relay`
+    ///
+    /// Relays drop registrations after a period without frames from the peer
+    /// (`DEFAULT_RELAY_PEER_TIMEOUT` by default), and NAT mappings expire as well,
+    /// so idle peers should register again periodically.
+    pub async fn register(&self, relay: SocketAddr) -> io::Result<()> {
+        let frame = encode_frame(&self.salt, FrameKind::Register, self.local_id, &[]);
+        transport::send_to(&*self.inner, &frame, relay).await?;
+        Ok(())
+    }
+
+    /// Send packets for `peer` to `relay`, addressed to `peer_id`
+    ///
+    /// `peer` is the address Rudpbase uses for the peer, typically the candidate
+    /// address hole punching failed to reach; it does not need to be reachable.
+    pub fn add(&self, peer: SocketAddr, relay: SocketAddr, peer_id: u64) {
+        let mut table = self.table.lock().unwrap();
+        if let Some(previous) = table.routes.insert(peer, (relay, peer_id)) {
+            table.peers.remove(&previous);
+        }
+        table.peers.insert((relay, peer_id), peer);
+    }
+
+    /// Send packets for `peer` directly again
+    pub fn remove(&self, peer: SocketAddr) {
+        let mut table = self.table.lock().unwrap();
+        if let Some(route) = table.routes.remove(&peer) {
+            table.peers.remove(&route);
+        }
+    }
+
+    /// Relay and peer ID packets for `peer` are sent to
+    pub fn route(&self, peer: SocketAddr) -> Option<(SocketAddr, u64)> {
+        self.table.lock().unwrap().routes.get(&peer).copied()
+    }
+
+    /// Peer ID registered with relays
+    pub fn local_id(&self) -> u64 {
+        self.local_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = encode_frame(DEFAULT_SALT, FrameKind::Forward, 42, b"inner packet");
+        assert_eq!(frame.len(), RELAY_OVERHEAD + 12);
+        assert_eq!(decode_frame(DEFAULT_SALT, &frame), Some((FrameKind::Forward, 42, &b"inner packet"[..])));
+
+        // Wrong salt, corrupted frames and other packet types are rejected
+        assert_eq!(decode_frame(b"other", &frame), None);
+        let mut corrupted = frame.clone();
+        corrupted[PROTOCOL_HEADER_SIZE + 1] ^= 1;
+        assert_eq!(decode_frame(DEFAULT_SALT, &corrupted), None);
+        let mut data = frame;
+        data[0] = PacketType::Data as u8;
+        assert_eq!(decode_frame(DEFAULT_SALT, &data), None);
+        assert_eq!(decode_frame(DEFAULT_SALT, &encode_frame(DEFAULT_SALT, FrameKind::Register, 7, &[])).map(|frame| frame.1), Some(7));
+    }
+}
@@ -0,0 +1,321 @@
+//! Periodic statistics reports
+//!
+//! Once enabled with `enable_stats_reporting()`, the protocol core takes a
+//! [`StatsReport`] of the global and per-peer statistics every interval, from its
+//! regular timer handling, and passes it to a user-provided [`StatsSink`].
+//! [`StatsJsonWriter`] writes each report as one JSON object per line; a
+//! `std::sync::mpsc::Sender<StatsReport>` or any closure can be used instead. With
+//! the `serde` feature the report types implement `serde::Serialize` for other
+//! formats.
+//!
+//! Durations are reported in milliseconds and rates in bytes per second, so every
+//! field is a plain number.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::stats::{ConnectionStats, ConnectionStatus, GlobalStats};
+
+/// Aggregate statistics of the instance at the time of a report
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GlobalStatsReport {
+    pub active_connections: usize,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    pub retransmissions: u64,
+    pub loss_bursts: u64,
+    pub outages: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub bytes_retransmitted: u64,
+    pub pending_packets: usize,
+    /// Invalid packets of all kinds from all sources
+    pub invalid_packets: u64,
+    /// Buffers currently free in the pool
+    pub pool_free_buffers: usize,
+    /// Fraction of pool allocations that had to allocate new memory
+    pub pool_miss_rate: f64,
+}
+
+impl From<&GlobalStats> for GlobalStatsReport {
+    fn from(stats: &GlobalStats) -> Self {
+        Self {
+            active_connections: stats.active_connections,
+            packets_sent: stats.packets_sent,
+            packets_received: stats.packets_received,
+            packets_lost: stats.packets_lost,
+            retransmissions: stats.retransmissions,
+            loss_bursts: stats.loss_bursts,
+            outages: stats.outages,
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            bytes_retransmitted: stats.bytes_retransmitted,
+            pending_packets: stats.pending_packets,
+            invalid_packets: stats.invalid_packets.total(),
+            pool_free_buffers: stats.buffer_pool.free_count,
+            pool_miss_rate: stats.pool_miss_rate(),
+        }
+    }
+}
+
+/// Statistics of one connection at the time of a report
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PeerStatsReport {
+    pub addr: SocketAddr,
+    pub status: ConnectionStatus,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    pub retransmissions: u64,
+    pub loss_rate: f64,
+    pub loss_bursts: u64,
+    pub outages: u64,
+    pub outage_time_ms: f64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub bytes_retransmitted: u64,
+    pub bytes_acked: u64,
+    pub goodput: f64,
+    pub send_rate: f64,
+    pub avg_rtt_ms: f64,
+    pub rtt_p50_ms: f64,
+    pub rtt_p99_ms: f64,
+    pub jitter_p50_ms: f64,
+    pub jitter_p99_ms: f64,
+}
+
+impl PeerStatsReport {
+    /// Report of `stats` as returned by `get_stats()`, with rates estimated at `now`
+    pub fn new(addr: SocketAddr, status: ConnectionStatus, stats: &ConnectionStats, now: Instant) -> Self {
+        Self {
+            addr,
+            status,
+            packets_sent: stats.packets_sent,
+            packets_received: stats.packets_received,
+            packets_lost: stats.packets_lost,
+            retransmissions: stats.retransmissions,
+            loss_rate: stats.packet_loss_rate(),
+            loss_bursts: stats.loss_bursts,
+            outages: stats.outages,
+            outage_time_ms: millis(stats.outage_time),
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            bytes_retransmitted: stats.bytes_retransmitted,
+            bytes_acked: stats.bytes_acked,
+            goodput: stats.goodput.rate_at(now),
+            send_rate: stats.send_rate.rate_at(now),
+            avg_rtt_ms: millis(stats.avg_rtt),
+            rtt_p50_ms: millis(stats.rtt_percentiles.p50),
+            rtt_p99_ms: millis(stats.rtt_percentiles.p99),
+            jitter_p50_ms: millis(stats.jitter_percentiles.p50),
+            jitter_p99_ms: millis(stats.jitter_percentiles.p99),
+        }
+    }
+}
+
+/// One periodic snapshot of the statistics of an instance
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatsReport {
+    /// Milliseconds since reporting started
+    pub time_ms: f64,
+    pub global: GlobalStatsReport,
+    /// Every connection, as listed by `connections()`
+    pub peers: Vec<PeerStatsReport>,
+}
+
+impl StatsReport {
+    /// Serialize as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let mut out = String::with_capacity(384 + 512 * self.peers.len());
+        let global = &self.global;
+        let _ = write!(
+            out,
+            r#"{{"time_ms":{},"global":{{"active_connections":{},"packets_sent":{},"packets_received":{},"packets_lost":{},"retransmissions":{},"loss_bursts":{},"outages":{},"bytes_sent":{},"bytes_received":{},"bytes_retransmitted":{},"pending_packets":{},"invalid_packets":{},"pool_free_buffers":{},"pool_miss_rate":{}}},"peers":["#,
+            self.time_ms, global.active_connections, global.packets_sent, global.packets_received,
+            global.packets_lost, global.retransmissions, global.loss_bursts, global.outages,
+            global.bytes_sent, global.bytes_received, global.bytes_retransmitted, global.pending_packets,
+            global.invalid_packets, global.pool_free_buffers, number(global.pool_miss_rate)
+        );
+        for (index, peer) in self.peers.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                r#"{{"addr":"{}","status":"{}","packets_sent":{},"packets_received":{},"packets_lost":{},"retransmissions":{},"loss_rate":{},"loss_bursts":{},"outages":{},"outage_time_ms":{},"bytes_sent":{},"bytes_received":{},"bytes_retransmitted":{},"bytes_acked":{},"goodput":{},"send_rate":{},"avg_rtt_ms":{},"rtt_p50_ms":{},"rtt_p99_ms":{},"jitter_p50_ms":{},"jitter_p99_ms":{}}}"#,
+                peer.addr, status_name(&peer.status), peer.packets_sent, peer.packets_received,
+                peer.packets_lost, peer.retransmissions, number(peer.loss_rate), peer.loss_bursts,
+                peer.outages, peer.outage_time_ms, peer.bytes_sent, peer.bytes_received,
+                peer.bytes_retransmitted, peer.bytes_acked, number(peer.goodput), number(peer.send_rate),
+                peer.avg_rtt_ms, peer.rtt_p50_ms, peer.rtt_p99_ms, peer.jitter_p50_ms, peer.jitter_p99_ms
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// Destination for statistics reports
+///
+/// Implemented for any `FnMut(&StatsReport) + Send` closure and for
+/// `std::sync::mpsc::Sender<StatsReport>`, which drops reports once its receiver is
+/// gone.
+pub trait StatsSink: Send {
+    fn report(&mut self, report: &StatsReport);
+}
+
+impl<F: FnMut(&StatsReport) + Send> StatsSink for F {
+    fn report(&mut self, report: &StatsReport) {
+        self(report)
+    }
+}
+
+impl StatsSink for Sender<StatsReport> {
+    fn report(&mut self, report: &StatsReport) {
+        let _ = self.send(report.clone());
+    }
+}
+
+/// Sink writing each report as a line of JSON to any `Write` implementation
+pub struct StatsJsonWriter<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> StatsJsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Consume the sink and return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> StatsSink for StatsJsonWriter<W> {
+    fn report(&mut self, report: &StatsReport) {
+        let _ = writeln!(self.writer, "{}", report.to_json());
+        let _ = self.writer.flush();
+    }
+}
+
+/// Reporting schedule attached to a protocol core
+pub(crate) struct StatsReporter {
+    sink: Box<dyn StatsSink>,
+    interval: Duration,
+    /// Set by the first timer run after reporting was enabled
+    start: Option<Instant>,
+    next_report: Option<Instant>,
+}
+
+impl StatsReporter {
+    pub(crate) fn new(interval: Duration, sink: Box<dyn StatsSink>) -> Self {
+        Self {
+            sink,
+            interval,
+            start: None,
+            next_report: None,
+        }
+    }
+
+    pub(crate) fn next_report(&self) -> Option<Instant> {
+        self.next_report
+    }
+
+    /// Whether a report is due at `now`; the first call only starts the schedule
+    pub(crate) fn due(&mut self, now: Instant) -> bool {
+        let Some(next_report) = self.next_report else {
+            self.start = Some(now);
+            self.next_report = Some(now + self.interval);
+            return false;
+        };
+        if now < next_report {
+            return false;
+        }
+        // Skip reports missed while no timer ran instead of sending them in a burst
+        let missed = (now.duration_since(next_report).as_nanos() / self.interval.as_nanos().max(1)) as u32;
+        self.next_report = Some(next_report + self.interval * (missed + 1));
+        true
+    }
+
+    pub(crate) fn report(&mut self, global: &GlobalStats, peers: Vec<PeerStatsReport>, now: Instant) {
+        let start = self.start.unwrap_or(now);
+        self.sink.report(&StatsReport {
+            time_ms: millis(now.duration_since(start)),
+            global: GlobalStatsReport::from(global),
+            peers,
+        });
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// JSON has no representation for NaN or infinity
+fn number(value: f64) -> f64 {
+    if value.is_finite() { value } else { 0.0 }
+}
+
+fn status_name(status: &ConnectionStatus) -> &'static str {
+    match status {
+        ConnectionStatus::Alive => "alive",
+        ConnectionStatus::Probing => "probing",
+        ConnectionStatus::Degraded => "degraded",
+        ConnectionStatus::Dead => "dead",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_pool::SharedBufferPool;
+
+    #[test]
+    fn test_reports_follow_interval() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut reporter = StatsReporter::new(Duration::from_millis(100), Box::new(|_: &StatsReport| {}));
+
+        assert!(!reporter.due(start));
+        assert_eq!(reporter.next_report(), Some(at(100)));
+        assert!(!reporter.due(at(99)));
+        assert!(reporter.due(at(100)));
+        // Missed reports are skipped
+        assert!(reporter.due(at(350)));
+        assert_eq!(reporter.next_report(), Some(at(400)));
+    }
+
+    #[test]
+    fn test_report_json() {
+        let now = Instant::now();
+        let addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let mut stats = ConnectionStats::new();
+        stats.packets_sent = 10;
+        stats.packets_lost = 1;
+        stats.avg_rtt = Duration::from_millis(20);
+        let mut global = GlobalStats::new(SharedBufferPool::new(0).stats().unwrap());
+        global.accumulate(&stats);
+        global.active_connections = 1;
+        let report = StatsReport {
+            time_ms: 1000.0,
+            global: GlobalStatsReport::from(&global),
+            peers: vec![PeerStatsReport::new(addr, ConnectionStatus::Alive, &stats, now)],
+        };
+
+        let json = report.to_json();
+        assert!(json.starts_with(r#"{"time_ms":1000,"global":{"active_connections":1,"packets_sent":10,"#));
+        assert!(json.contains(r#""peers":[{"addr":"10.0.0.1:1","status":"alive","packets_sent":10,"#));
+        assert!(json.contains(r#""loss_rate":0.1,"#));
+        assert!(json.contains(r#""avg_rtt_ms":20,"#));
+        assert!(json.ends_with("}]}"));
+        assert!(!json.contains('\n'));
+    }
+}
@@ -0,0 +1,174 @@
+//! Resumption tokens for 0-RTT reconnection
+//!
+//! A core with `RudpConfig::resumption` set attaches a [`ResumptionToken`] to every
+//! ping acknowledgment it sends, in an [`EXTENSION_RESUMPTION`] entry. The token
+//! records when it was issued and the RTT the issuer measured to the peer, and ends
+//! with a tag over those fields, the peer's IP address and the issuer's key. After
+//! `resume()` the peer presents the token in the same extension on its first data
+//! packet. The issuer checks the tag and the token's age, then remembers the tag
+//! until the token expires, so each token starts at most one session.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fnv::FnvHasher;
+
+use crate::protocol::{RawPacketRef, EXTENSION_RESUMPTION};
+
+/// Encoded size: issue time (8), nonce (4), RTT in microseconds (4), tag (8)
+const TOKEN_SIZE: usize = 24;
+
+/// Offset of the tag, which covers every byte before it
+const TAG_OFFSET: usize = 16;
+
+/// Proof of an earlier session with a peer, presented to it on reconnection
+///
+/// Obtained from [`RudpCore::resumption_token`](crate::RudpCore::resumption_token)
+/// once the peer has answered a ping. Store it with [`as_bytes`](Self::as_bytes)
+/// to reconnect after a restart, and pass it to
+/// [`RudpCore::resume`](crate::RudpCore::resume) to send 0-RTT data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumptionToken {
+    bytes: [u8; TOKEN_SIZE],
+}
+
+impl ResumptionToken {
+    /// Size of the TLV entry written by [`encode`](Self::encode)
+    pub(crate) const ENTRY_SIZE: usize = 2 + TOKEN_SIZE;
+
+    /// Issue a token to `addr`
+    pub(crate) fn issue(key: &[u8], addr: SocketAddr, rtt: Option<Duration>, nonce: u32, now: SystemTime) -> Self {
+        let mut bytes = [0u8; TOKEN_SIZE];
+        let issued_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        bytes[..8].copy_from_slice(&issued_at.to_be_bytes());
+        bytes[8..12].copy_from_slice(&nonce.to_be_bytes());
+        let rtt = rtt.map_or(0, |rtt| rtt.as_micros().clamp(1, u32::MAX as u128) as u32);
+        bytes[12..16].copy_from_slice(&rtt.to_be_bytes());
+        let tag = tag(key, addr.ip(), &bytes[..TAG_OFFSET]);
+        bytes[TAG_OFFSET..].copy_from_slice(&tag.to_be_bytes());
+        Self { bytes }
+    }
+
+    /// Parse a token saved with [`as_bytes`](Self::as_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self { bytes: bytes.try_into().ok()? })
+    }
+
+    /// Encoded token, opaque to everyone but its issuer
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Time the token was issued, on the issuer's clock
+    pub fn issued_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(self.bytes[..8].try_into().unwrap()))
+    }
+
+    /// RTT the issuer had measured to this end, if it had a sample
+    pub fn rtt(&self) -> Option<Duration> {
+        let micros = u32::from_be_bytes(self.bytes[12..16].try_into().unwrap());
+        (micros > 0).then(|| Duration::from_micros(micros as u64))
+    }
+
+    /// Identifies the token among those accepted before
+    fn id(&self) -> u64 {
+        u64::from_be_bytes(self.bytes[TAG_OFFSET..].try_into().unwrap())
+    }
+
+    /// Whether this core issued the token to `addr` less than `lifetime` ago
+    pub(crate) fn verify(&self, key: &[u8], addr: SocketAddr, lifetime: Duration, now: SystemTime) -> bool {
+        tag(key, addr.ip(), &self.bytes[..TAG_OFFSET]) == self.id()
+            && now.duration_since(self.issued_at()).unwrap_or_default() < lifetime
+    }
+
+    /// The token in `packet`'s extension section
+    pub(crate) fn find(packet: &RawPacketRef<'_>) -> Option<Self> {
+        let entry = packet.extensions().find(|entry| entry.kind == EXTENSION_RESUMPTION)?;
+        Self::from_bytes(entry.value)
+    }
+
+    /// Append the TLV encoding of the token to `out`
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[EXTENSION_RESUMPTION, TOKEN_SIZE as u8]);
+        out.extend_from_slice(&self.bytes);
+    }
+}
+
+fn tag(key: &[u8], ip: IpAddr, fields: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(key);
+    match ip {
+        IpAddr::V4(ip) => hasher.write(&ip.octets()),
+        IpAddr::V6(ip) => hasher.write(&ip.octets()),
+    }
+    hasher.write(fields);
+    hasher.finish()
+}
+
+/// Tokens accepted so far, kept until they expire
+#[derive(Debug, Default)]
+pub(crate) struct UsedTokens {
+    expiry: HashMap<u64, SystemTime>,
+}
+
+impl UsedTokens {
+    /// Record `token` as used; returns false if it was used before
+    pub(crate) fn insert(&mut self, token: &ResumptionToken, lifetime: Duration, now: SystemTime) -> bool {
+        self.expiry.retain(|_, expires| *expires > now);
+        let expires = token.issued_at() + lifetime;
+        self.expiry.insert(token.id(), expires).is_none()
+    }
+
+    /// Token ids with their expiry times, for `export_state()`
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (u64, SystemTime)> + '_ {
+        self.expiry.iter().map(|(id, expires)| (*id, *expires))
+    }
+
+    pub(crate) fn restore(&mut self, id: u64, expires: SystemTime) {
+        self.expiry.insert(id, expires);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bound_to_key_address_and_lifetime() {
+        let addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let now = SystemTime::now();
+        let lifetime = Duration::from_secs(60);
+        let token = ResumptionToken::issue(b"key", addr, Some(Duration::from_millis(30)), 1, now);
+        assert_eq!(token.rtt(), Some(Duration::from_millis(30)));
+        assert_eq!(ResumptionToken::from_bytes(token.as_bytes()), Some(token.clone()));
+
+        assert!(token.verify(b"key", addr, lifetime, now));
+        // A new port is fine, a new address or key is not
+        assert!(token.verify(b"key", "10.0.0.1:2000".parse().unwrap(), lifetime, now));
+        assert!(!token.verify(b"key", "10.0.0.2:1000".parse().unwrap(), lifetime, now));
+        assert!(!token.verify(b"other", addr, lifetime, now));
+        assert!(!token.verify(b"key", addr, lifetime, now + lifetime + Duration::from_secs(1)));
+
+        let mut forged = token.as_bytes().to_vec();
+        forged[12] ^= 1;
+        assert!(!ResumptionToken::from_bytes(&forged).unwrap().verify(b"key", addr, lifetime, now));
+        assert!(ResumptionToken::issue(b"key", addr, None, 2, now).rtt().is_none());
+    }
+
+    #[test]
+    fn test_each_token_is_used_once() {
+        let addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let now = SystemTime::now();
+        let lifetime = Duration::from_secs(60);
+        let token = ResumptionToken::issue(b"key", addr, None, 1, now);
+        let mut used = UsedTokens::default();
+        assert!(used.insert(&token, lifetime, now));
+        assert!(!used.insert(&token, lifetime, now));
+        assert!(used.insert(&ResumptionToken::issue(b"key", addr, None, 2, now), lifetime, now));
+        // Expired tokens are forgotten
+        used.insert(&ResumptionToken::issue(b"key", addr, None, 3, now), lifetime, now + lifetime * 2);
+        assert_eq!(used.expiry.len(), 1);
+    }
+}
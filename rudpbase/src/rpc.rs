@@ -0,0 +1,373 @@
+//! Request/response calls over the message API
+//!
+//! [`spawn`] moves a [`Rudpbase`] into a background task that multiplexes calls over
+//! it. [`RpcClient::call`] sends a request as one message and resolves with the
+//! peer's response; every call carries an ID, so any number of calls to the same or
+//! different peers can be in flight at once, and each one fails with
+//! `RudpError::Timeout` on its own deadline. Requests from peers arrive on
+//! [`RpcRequests`] and are answered with [`RpcRequest::respond`]. A request dropped
+//! without a response is rejected, so the caller fails fast instead of timing out.
+//!
+//! The instance is dedicated to RPC: plain data packets and messages that are not
+//! RPC frames are discarded. The task runs until every [`RpcClient`], the
+//! [`RpcRequests`] stream and all unanswered [`RpcRequest`]s are dropped, then
+//! closes the instance.
+//!
+//! ```rust,no_run
+//! use rudpbase::rpc::{self, RpcOptions};
+//! use rudpbase::Rudpbase;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let server = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+//!     let (_, mut requests) = rpc::spawn(server, RpcOptions::default());
+//!     tokio::spawn(async move {
+//!         while let Some(request) = requests.next().await {
+//!             let mut reply = request.data.clone();
+//!             reply.reverse();
+//!             request.respond(&reply);
+//!         }
+//!     });
+//!
+//!     let client = Rudpbase::new("127.0.0.1:8081".parse()?).await?;
+//!     let (client, _) = rpc::spawn(client, RpcOptions::default());
+//!     let reply = client.call("127.0.0.1:8080".parse()?, b"hello").await?;
+//!     assert_eq!(reply, b"olleh");
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::core::Rudpbase;
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+
+/// First byte of every RPC message
+const RPC_MAGIC: u8 = 0xF8;
+
+/// RPC message header size: magic(1) + kind(1) + request_id(8)
+const RPC_HEADER_SIZE: usize = 10;
+
+/// Default time a call waits for its response
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of received requests waiting for [`RpcRequests::next`]
+pub const DEFAULT_MAX_QUEUED_REQUESTS: usize = 1024;
+
+/// Options of an RPC endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcOptions {
+    /// Deadline of calls made with [`RpcClient::call`]
+    pub timeout: Duration,
+    /// Received requests waiting to be taken from [`RpcRequests`]; further requests
+    /// are rejected until the application catches up
+    pub max_queued_requests: usize,
+}
+
+impl Default for RpcOptions {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_CALL_TIMEOUT,
+            max_queued_requests: DEFAULT_MAX_QUEUED_REQUESTS,
+        }
+    }
+}
+
+/// Frame kinds
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Request = 0,
+    Response = 1,
+    /// The peer dropped the request without responding
+    Reject = 2,
+}
+
+/// Decoded RPC message
+struct Frame<'a> {
+    kind: Kind,
+    id: u64,
+    data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    fn encode(kind: Kind, id: u64, data: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(RPC_HEADER_SIZE + data.len());
+        message.push(RPC_MAGIC);
+        message.push(kind as u8);
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    fn decode(message: &'a [u8]) -> Option<Self> {
+        if message.len() < RPC_HEADER_SIZE || message[0] != RPC_MAGIC {
+            return None;
+        }
+        let kind = match message[1] {
+            0 => Kind::Request,
+            1 => Kind::Response,
+            2 => Kind::Reject,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            id: u64::from_be_bytes(message[2..RPC_HEADER_SIZE].try_into().ok()?),
+            data: &message[RPC_HEADER_SIZE..],
+        })
+    }
+}
+
+/// Work handed to the driver task
+enum Command {
+    Call {
+        target: SocketAddr,
+        data: Vec<u8>,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<Vec<u8>, RudpError>>,
+    },
+    Respond {
+        target: SocketAddr,
+        id: u64,
+        kind: Kind,
+        data: Vec<u8>,
+    },
+}
+
+/// Start an RPC endpoint on `rudp`
+///
+/// Must be called within a tokio runtime; the driver task is spawned on it.
+pub fn spawn(rudp: Rudpbase, options: RpcOptions) -> (RpcClient, RpcRequests) {
+    let (commands_tx, commands) = mpsc::unbounded_channel();
+    let (requests_tx, requests) = mpsc::channel(options.max_queued_requests.max(1));
+    let driver = Driver {
+        rudp,
+        commands,
+        responder: commands_tx.downgrade(),
+        requests: requests_tx,
+        calls: HashMap::new(),
+        next_id: 0,
+    };
+    tokio::spawn(driver.run());
+    (
+        RpcClient {
+            commands: commands_tx.clone(),
+            timeout: options.timeout,
+        },
+        RpcRequests {
+            requests,
+            _commands: commands_tx,
+        },
+    )
+}
+
+/// Makes calls through an RPC endpoint; cheap to clone
+#[derive(Clone)]
+pub struct RpcClient {
+    commands: mpsc::UnboundedSender<Command>,
+    timeout: Duration,
+}
+
+impl RpcClient {
+    /// Send `request` to `target` and wait for its response
+    ///
+    /// Fails with `RudpError::Timeout` after [`RpcOptions::timeout`], with
+    /// `RudpError::Protocol` if the peer rejected the request and with
+    /// `RudpError::Closing` if the endpoint has stopped.
+    pub async fn call(&self, target: SocketAddr, request: &[u8]) -> Result<Vec<u8>, RudpError> {
+        self.call_with_timeout(target, request, self.timeout).await
+    }
+
+    /// Like [`call`](Self::call) with a deadline for this call only
+    pub async fn call_with_timeout(&self, target: SocketAddr, request: &[u8], timeout: Duration) -> Result<Vec<u8>, RudpError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(Command::Call { target, data: request.to_vec(), timeout, reply })
+            .map_err(|_| RudpError::Closing)?;
+        response.await.unwrap_or(Err(RudpError::Closing))
+    }
+}
+
+/// Requests received by an RPC endpoint
+pub struct RpcRequests {
+    requests: mpsc::Receiver<RpcRequest>,
+    /// Keeps the driver running while requests can still be taken
+    _commands: mpsc::UnboundedSender<Command>,
+}
+
+impl RpcRequests {
+    /// Wait for the next request; `None` once the endpoint has stopped
+    pub async fn next(&mut self) -> Option<RpcRequest> {
+        self.requests.recv().await
+    }
+}
+
+/// A request waiting for its response
+pub struct RpcRequest {
+    /// Caller address
+    pub from: SocketAddr,
+    /// Request payload
+    pub data: Vec<u8>,
+    id: u64,
+    responder: Option<mpsc::UnboundedSender<Command>>,
+}
+
+impl RpcRequest {
+    /// Request ID, unique per caller while the call is in flight
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Send the response to the caller
+    pub fn respond(mut self, response: &[u8]) {
+        self.reply(Kind::Response, response.to_vec());
+    }
+
+    fn reply(&mut self, kind: Kind, data: Vec<u8>) {
+        if let Some(responder) = self.responder.take() {
+            let _ = responder.send(Command::Respond { target: self.from, id: self.id, kind, data });
+        }
+    }
+}
+
+impl Drop for RpcRequest {
+    fn drop(&mut self) {
+        self.reply(Kind::Reject, Vec::new());
+    }
+}
+
+/// A call waiting for its response
+struct PendingCall {
+    deadline: Instant,
+    reply: oneshot::Sender<Result<Vec<u8>, RudpError>>,
+}
+
+/// Background task that owns the instance
+struct Driver {
+    rudp: Rudpbase,
+    commands: mpsc::UnboundedReceiver<Command>,
+    /// Handed to received requests; weak so the driver does not keep itself alive
+    responder: mpsc::WeakUnboundedSender<Command>,
+    requests: mpsc::Sender<RpcRequest>,
+    calls: HashMap<(SocketAddr, u64), PendingCall>,
+    next_id: u64,
+}
+
+impl Driver {
+    async fn run(mut self) {
+        loop {
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.handle_command(command).await,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.rudp.close().await;
+                        return;
+                    }
+                }
+            }
+
+            // Expired calls, and calls whose caller stopped waiting
+            let now = Instant::now();
+            let expired: Vec<_> = self
+                .calls
+                .iter()
+                .filter(|(_, call)| call.deadline <= now || call.reply.is_closed())
+                .map(|(key, _)| *key)
+                .collect();
+            for key in expired {
+                if let Some(call) = self.calls.remove(&key) {
+                    let _ = call.reply.send(Err(RudpError::Timeout));
+                }
+            }
+
+            self.rudp.tick().await;
+            if let Some(message) = self.rudp.recv_message().await {
+                self.handle_message(message);
+            }
+            // Not RPC traffic; drained so it does not hold pool buffers
+            self.rudp.take_received();
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Call { target, data, timeout, reply } => {
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+                match self.rudp.send_message(&Frame::encode(Kind::Request, id, &data), target).await {
+                    Ok(()) => {
+                        let deadline = Instant::now() + timeout;
+                        self.calls.insert((target, id), PendingCall { deadline, reply });
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Command::Respond { target, id, kind, data } => {
+                // The caller times out if the response cannot be sent
+                let _ = self.rudp.send_message(&Frame::encode(kind, id, &data), target).await;
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: ReceivedMessage) {
+        let Some(frame) = Frame::decode(&message.data) else {
+            return;
+        };
+        match frame.kind {
+            Kind::Request => {
+                let request = RpcRequest {
+                    from: message.from,
+                    data: frame.data.to_vec(),
+                    id: frame.id,
+                    responder: self.responder.upgrade(),
+                };
+                // A full queue or a dropped RpcRequests rejects the request on drop
+                let _ = self.requests.try_send(request);
+            }
+            Kind::Response | Kind::Reject => {
+                let Some(call) = self.calls.remove(&(message.from, frame.id)) else {
+                    // Late response to a call that already timed out
+                    return;
+                };
+                let result = match frame.kind {
+                    Kind::Response => Ok(frame.data.to_vec()),
+                    _ => Err(RudpError::Protocol {
+                        message: format!("request {} rejected by {}", frame.id, message.from),
+                    }),
+                };
+                let _ = call.reply.send(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let message = Frame::encode(Kind::Response, 0x0102_0304_0506_0708, b"payload");
+        assert_eq!(message.len(), RPC_HEADER_SIZE + 7);
+        let frame = Frame::decode(&message).unwrap();
+        assert_eq!(frame.kind, Kind::Response);
+        assert_eq!(frame.id, 0x0102_0304_0506_0708);
+        assert_eq!(frame.data, b"payload");
+
+        // Other messages, short messages and unknown kinds are not RPC frames
+        assert!(Frame::decode(b"plain message").is_none());
+        assert!(Frame::decode(&message[..RPC_HEADER_SIZE - 1]).is_none());
+        let mut unknown = message;
+        unknown[1] = 9;
+        assert!(Frame::decode(&unknown).is_none());
+    }
+}
@@ -1,14 +1,15 @@
 use fnv::FnvHasher;
+use std::fmt;
 use std::hash::Hasher;
 use crate::protocol::PacketType;
 
+/// Default salt value for security code calculation
+pub const DEFAULT_SALT: &[u8] = b"ffmesh";
+
 /// Security code calculator
 pub struct SecurityCode;
 
 impl SecurityCode {
-    /// Salt value for security code calculation
-    const SALT: &'static [u8] = b"ffmesh";
-
     /// Calculate security code for a packet
     /// 
     /// Algorithm:
@@ -17,10 +18,17 @@ impl SecurityCode {
     /// 3. Calculate: hash = FNV1a_32(salt + type + seq + data_len + first_16_bytes_data)
     /// 4. Security code = hash & 0xFFFFFFFF
     pub fn calculate(packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
+        Self::calculate_with_salt(DEFAULT_SALT, packet_type, seq, data)
+    }
+
+    /// Calculate security code for a packet using a custom salt
+    /// 
+    /// Peers must be configured with the same salt to accept each other's packets.
+    pub fn calculate_with_salt(salt: &[u8], packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
         let mut hasher = FnvHasher::default();
         
         // Add salt
-        hasher.write(Self::SALT);
+        hasher.write(salt);
         
         // Add packet type
         hasher.write(&[packet_type as u8]);
@@ -45,11 +53,204 @@ impl SecurityCode {
 
     /// Verify security code for a packet
     pub fn verify(packet_type: PacketType, seq: u32, data: &[u8], expected_code: u32) -> bool {
-        let calculated_code = Self::calculate(packet_type, seq, data);
+        Self::verify_with_salt(DEFAULT_SALT, packet_type, seq, data, expected_code)
+    }
+
+    /// Verify security code for a packet using a custom salt
+    pub fn verify_with_salt(salt: &[u8], packet_type: PacketType, seq: u32, data: &[u8], expected_code: u32) -> bool {
+        let calculated_code = Self::calculate_with_salt(salt, packet_type, seq, data);
         calculated_code == expected_code
     }
 }
 
+/// Computes and checks the 32-bit security code carried in every packet header
+///
+/// The code covers the packet type, the sequence number and the packet body (the
+/// bytes after the 9-byte header, extensions included). Both peers must use the
+/// same authenticator with the same key material, otherwise every packet is
+/// dropped as invalid. Install one with [`RudpConfig::with_authenticator`]; the
+/// default is [`SaltedFnv`] over [`SecurityConfig::salt`].
+///
+/// ```
+/// use rudpbase::{PacketAuthenticator, PacketType};
+///
+/// /// Sum of the body bytes, for illustration only
+/// struct Checksum;
+///
+/// impl PacketAuthenticator for Checksum {
+///     fn code(&self, packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
+///         data.iter().fold(seq ^ packet_type as u32, |sum, byte| sum.wrapping_add(*byte as u32))
+///     }
+/// }
+///
+/// let code = Checksum.code(PacketType::Data, 1, b"abc");
+/// assert!(Checksum.verify(PacketType::Data, 1, b"abc", code));
+/// ```
+///
+/// [`RudpConfig::with_authenticator`]: crate::RudpConfig::with_authenticator
+/// [`SecurityConfig::salt`]: crate::SecurityConfig::salt
+pub trait PacketAuthenticator: Send + Sync {
+    /// Security code of a packet
+    fn code(&self, packet_type: PacketType, seq: u32, data: &[u8]) -> u32;
+
+    /// Whether `code` is the security code of the packet
+    fn verify(&self, packet_type: PacketType, seq: u32, data: &[u8], code: u32) -> bool {
+        self.code(packet_type, seq, data) == code
+    }
+}
+
+impl fmt::Debug for dyn PacketAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PacketAuthenticator")
+    }
+}
+
+/// Authenticators compare by identity: two configs are equal only when they share
+/// the same instance
+impl PartialEq for dyn PacketAuthenticator {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+/// The default authenticator: [`SecurityCode`] with a salt
+///
+/// Cheap, but it only covers the first 16 bytes of the body and is not keyed in any
+/// cryptographic sense; it filters stray and misrouted traffic, not attackers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaltedFnv {
+    salt: Vec<u8>,
+}
+
+impl SaltedFnv {
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self { salt: salt.into() }
+    }
+}
+
+impl Default for SaltedFnv {
+    fn default() -> Self {
+        Self::new(DEFAULT_SALT)
+    }
+}
+
+impl PacketAuthenticator for SaltedFnv {
+    fn code(&self, packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
+        SecurityCode::calculate_with_salt(&self.salt, packet_type, seq, data)
+    }
+}
+
+/// CRC32C (Castagnoli) over the salt, type, sequence number and the whole body
+///
+/// Detects corruption anywhere in the packet, which the default does not; like the
+/// default it offers no protection against forgery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crc32c {
+    salt: Vec<u8>,
+}
+
+impl Crc32c {
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self { salt: salt.into() }
+    }
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Self::new(DEFAULT_SALT)
+    }
+}
+
+/// Lookup table of the reflected CRC32C polynomial
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// CRC32C of `bytes`
+pub(crate) fn crc32c(bytes: &[u8]) -> u32 {
+    !crc32c_update(!0, bytes)
+}
+
+impl PacketAuthenticator for Crc32c {
+    fn code(&self, packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
+        let mut crc = crc32c_update(!0, &self.salt);
+        crc = crc32c_update(crc, &[packet_type as u8]);
+        crc = crc32c_update(crc, &seq.to_be_bytes());
+        !crc32c_update(crc, data)
+    }
+}
+
+/// SipHash-2-4 keyed with a 128-bit secret over the type, sequence number and the
+/// whole body
+///
+/// Without the key a third party cannot produce packets the peer accepts, short of
+/// guessing a 32-bit code; packets are still readable and replayable.
+#[derive(Clone, PartialEq)]
+pub struct SipHash {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipHash {
+    pub fn new(key: [u8; 16]) -> Self {
+        let (k0, k1) = key.split_at(8);
+        Self {
+            k0: u64::from_le_bytes(k0.try_into().unwrap()),
+            k1: u64::from_le_bytes(k1.try_into().unwrap()),
+        }
+    }
+}
+
+/// Keeps the key out of logs
+impl fmt::Debug for SipHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SipHash")
+    }
+}
+
+impl PacketAuthenticator for SipHash {
+    #[allow(deprecated)] // `SipHasher` is SipHash-2-4; only its use as a default hasher is deprecated
+    fn code(&self, packet_type: PacketType, seq: u32, data: &[u8]) -> u32 {
+        let mut hasher = std::hash::SipHasher::new_with_keys(self.k0, self.k1);
+        hasher.write(&[packet_type as u8]);
+        hasher.write(&seq.to_be_bytes());
+        hasher.write(data);
+        let hash = hasher.finish();
+        (hash ^ (hash >> 32)) as u32
+    }
+}
+
+/// Writes a zero code and accepts every packet, for trusted links where the hash is
+/// wasted work
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NoAuthentication;
+
+impl PacketAuthenticator for NoAuthentication {
+    fn code(&self, _packet_type: PacketType, _seq: u32, _data: &[u8]) -> u32 {
+        0
+    }
+
+    fn verify(&self, _packet_type: PacketType, _seq: u32, _data: &[u8], _code: u32) -> bool {
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -120,4 +321,54 @@ mod tests {
         // Different data should produce different codes
         assert_ne!(code1, code2);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_custom_salt() {
+        let data = b"Salted";
+        let code = SecurityCode::calculate_with_salt(b"other-mesh", PacketType::Data, 7, data);
+
+        assert!(SecurityCode::verify_with_salt(b"other-mesh", PacketType::Data, 7, data, code));
+        // Peers with the default salt must reject it
+        assert!(!SecurityCode::verify(PacketType::Data, 7, data, code));
+    }
+
+    #[test]
+    fn test_salted_fnv_matches_security_code() {
+        let data = b"Compatible";
+        let code = SaltedFnv::default().code(PacketType::Data, 3, data);
+        assert_eq!(code, SecurityCode::calculate(PacketType::Data, 3, data));
+        assert!(!SaltedFnv::new("other-mesh").verify(PacketType::Data, 3, data, code));
+    }
+
+    #[test]
+    fn test_crc32c_covers_whole_body() {
+        // Standard check value of CRC32C
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+
+        let crc = Crc32c::default();
+        let data = [7u8; 64];
+        let code = crc.code(PacketType::Data, 9, &data);
+        assert!(crc.verify(PacketType::Data, 9, &data, code));
+
+        // A change past the first 16 bytes is caught, unlike with the default
+        let mut changed = data;
+        changed[40] ^= 1;
+        assert!(!crc.verify(PacketType::Data, 9, &changed, code));
+        assert!(SecurityCode::verify(PacketType::Data, 9, &changed, SecurityCode::calculate(PacketType::Data, 9, &data)));
+    }
+
+    #[test]
+    fn test_siphash_depends_on_key() {
+        let data = b"Keyed";
+        let code = SipHash::new([1; 16]).code(PacketType::Data, 5, data);
+        assert!(SipHash::new([1; 16]).verify(PacketType::Data, 5, data, code));
+        assert!(!SipHash::new([2; 16]).verify(PacketType::Data, 5, data, code));
+        assert!(!SipHash::new([1; 16]).verify(PacketType::Data, 6, data, code));
+    }
+
+    #[test]
+    fn test_no_authentication_accepts_anything() {
+        assert_eq!(NoAuthentication.code(PacketType::Data, 1, b"x"), 0);
+        assert!(NoAuthentication.verify(PacketType::Data, 1, b"x", 12345));
+    }
+}
@@ -0,0 +1,354 @@
+//! Per-peer protocol state carried across a process restart
+//!
+//! [`SessionState`] holds what peers would notice if an instance came back with empty
+//! state: the sequence counters (a peer's receive window would discard new packets as
+//! duplicates), our receive windows (retransmissions would be delivered twice),
+//! packets the peer has not acknowledged yet, RTT and congestion estimates, and
+//! resumption tokens. Counters, histograms and anything tied to `Instant`s are left
+//! out; the restarted instance starts them afresh.
+//!
+//! The encoding is a compact binary layout private to this crate, versioned so that
+//! state written by an incompatible release is rejected instead of misread.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::RudpConfig;
+use crate::error::RudpError;
+use crate::protocol::{PacketType, EXTENSION_FLAG, PROTOCOL_HEADER_SIZE};
+use crate::resumption::ResumptionToken;
+use crate::stats::{CongestionState, RttStats};
+use crate::window::{ReceiveWindow, WORDS};
+
+const MAGIC: &[u8; 4] = b"RUDP";
+const VERSION: u8 = 1;
+
+const HAS_NEXT_SEQ: u8 = 1;
+const HAS_MESSAGE_ID: u8 = 1 << 1;
+const HAS_WINDOW: u8 = 1 << 2;
+const HAS_RTT: u8 = 1 << 3;
+const HAS_TOKEN: u8 = 1 << 4;
+
+/// Length of an encoded resumption token
+const TOKEN_SIZE: usize = 24;
+
+/// RTT and congestion estimates of one peer
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RttSnapshot {
+    pub(crate) srtt: Duration,
+    pub(crate) rttvar: Duration,
+    pub(crate) rto: Duration,
+    pub(crate) cwnd: u32,
+    pub(crate) ssthresh: u32,
+    pub(crate) last_rtt_sample: Option<Duration>,
+}
+
+impl RttSnapshot {
+    pub(crate) fn capture(stats: &RttStats) -> Self {
+        Self {
+            srtt: stats.srtt,
+            rttvar: stats.rttvar,
+            rto: stats.rto,
+            cwnd: stats.cwnd,
+            ssthresh: stats.ssthresh,
+            last_rtt_sample: stats.last_rtt_sample,
+        }
+    }
+
+    /// Fresh statistics carrying the saved estimates, within the limits of `config`
+    pub(crate) fn restore(&self, config: &RudpConfig) -> RttStats {
+        let mut stats = RttStats::with_config(config);
+        stats.srtt = self.srtt;
+        stats.rttvar = self.rttvar;
+        stats.rto = self.rto.clamp(stats.min_rto, stats.max_rto);
+        stats.cwnd = self.cwnd.clamp(1, stats.max_cwnd);
+        stats.ssthresh = self.ssthresh;
+        stats.last_rtt_sample = self.last_rtt_sample;
+        if stats.cwnd >= stats.ssthresh {
+            stats.congestion_state = CongestionState::CongestionAvoidance;
+        }
+        stats
+    }
+}
+
+/// Exported state of one peer
+#[derive(Debug)]
+pub(crate) struct PeerSnapshot {
+    pub(crate) addr: SocketAddr,
+    pub(crate) next_seq: Option<u32>,
+    pub(crate) next_message_id: Option<u32>,
+    pub(crate) window: Option<ReceiveWindow>,
+    pub(crate) rtt: Option<RttSnapshot>,
+    pub(crate) token: Option<ResumptionToken>,
+    /// Unacknowledged and queued data packets, header included, in sequence order
+    pub(crate) packets: Vec<Vec<u8>>,
+}
+
+/// Everything `export_state()` writes
+#[derive(Debug, Default)]
+pub(crate) struct SessionState {
+    pub(crate) peers: Vec<PeerSnapshot>,
+    /// Ids and expiry times of resumption tokens already accepted
+    pub(crate) used_tokens: Vec<(u64, SystemTime)>,
+}
+
+impl SessionState {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        out.extend_from_slice(&(self.peers.len() as u32).to_be_bytes());
+        for peer in &self.peers {
+            encode_addr(peer.addr, &mut out);
+            let flags = flag(peer.next_seq.is_some(), HAS_NEXT_SEQ)
+                | flag(peer.next_message_id.is_some(), HAS_MESSAGE_ID)
+                | flag(peer.window.is_some(), HAS_WINDOW)
+                | flag(peer.rtt.is_some(), HAS_RTT)
+                | flag(peer.token.is_some(), HAS_TOKEN);
+            out.push(flags);
+            if let Some(seq) = peer.next_seq {
+                out.extend_from_slice(&seq.to_be_bytes());
+            }
+            if let Some(id) = peer.next_message_id {
+                out.extend_from_slice(&id.to_be_bytes());
+            }
+            if let Some(window) = &peer.window {
+                out.extend_from_slice(&window.base().to_be_bytes());
+                for word in window.bits() {
+                    out.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            if let Some(rtt) = &peer.rtt {
+                encode_duration(rtt.srtt, &mut out);
+                encode_duration(rtt.rttvar, &mut out);
+                encode_duration(rtt.rto, &mut out);
+                out.extend_from_slice(&rtt.cwnd.to_be_bytes());
+                out.extend_from_slice(&rtt.ssthresh.to_be_bytes());
+                // u64::MAX marks a peer without RTT samples
+                let sample = rtt.last_rtt_sample.map_or(u64::MAX, |sample| sample.as_micros() as u64);
+                out.extend_from_slice(&sample.to_be_bytes());
+            }
+            if let Some(token) = &peer.token {
+                out.extend_from_slice(token.as_bytes());
+            }
+            out.extend_from_slice(&(peer.packets.len() as u32).to_be_bytes());
+            for packet in &peer.packets {
+                out.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+                out.extend_from_slice(packet);
+            }
+        }
+
+        out.extend_from_slice(&(self.used_tokens.len() as u32).to_be_bytes());
+        for (id, expires) in &self.used_tokens {
+            out.extend_from_slice(&id.to_be_bytes());
+            let secs = expires.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+            out.extend_from_slice(&secs.to_be_bytes());
+        }
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, RudpError> {
+        let mut reader = Reader { data };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not an exported rudpbase state"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported state version {}", version)));
+        }
+
+        let peer_count = reader.u32()?;
+        let mut peers = Vec::new();
+        for _ in 0..peer_count {
+            let addr = reader.addr()?;
+            let flags = reader.u8()?;
+            let next_seq = (flags & HAS_NEXT_SEQ != 0).then(|| reader.u32()).transpose()?;
+            let next_message_id = (flags & HAS_MESSAGE_ID != 0).then(|| reader.u32()).transpose()?;
+            let window = if flags & HAS_WINDOW != 0 {
+                let base = reader.u32()?;
+                let mut bits = [0u64; WORDS];
+                for word in &mut bits {
+                    *word = reader.u64()?;
+                }
+                Some(ReceiveWindow::from_parts(base, bits))
+            } else {
+                None
+            };
+            let rtt = if flags & HAS_RTT != 0 {
+                Some(RttSnapshot {
+                    srtt: reader.duration()?,
+                    rttvar: reader.duration()?,
+                    rto: reader.duration()?,
+                    cwnd: reader.u32()?,
+                    ssthresh: reader.u32()?,
+                    last_rtt_sample: Some(reader.u64()?).filter(|&us| us != u64::MAX).map(Duration::from_micros),
+                })
+            } else {
+                None
+            };
+            let token = if flags & HAS_TOKEN != 0 {
+                let token = ResumptionToken::from_bytes(reader.take(TOKEN_SIZE)?).ok_or_else(|| invalid("malformed resumption token"))?;
+                Some(token)
+            } else {
+                None
+            };
+            let packet_count = reader.u32()?;
+            let mut packets = Vec::new();
+            for _ in 0..packet_count {
+                let len = reader.u32()? as usize;
+                let packet = reader.take(len)?;
+                let is_data = packet.len() >= PROTOCOL_HEADER_SIZE
+                    && matches!(PacketType::from_u8(packet[0] & !EXTENSION_FLAG), Some(PacketType::Data | PacketType::Fragment));
+                if !is_data {
+                    return Err(invalid("malformed pending packet"));
+                }
+                packets.push(packet.to_vec());
+            }
+            peers.push(PeerSnapshot { addr, next_seq, next_message_id, window, rtt, token, packets });
+        }
+
+        let token_count = reader.u32()?;
+        let mut used_tokens = Vec::new();
+        for _ in 0..token_count {
+            let id = reader.u64()?;
+            let expires = UNIX_EPOCH + Duration::from_secs(reader.u64()?);
+            used_tokens.push((id, expires));
+        }
+        if !reader.data.is_empty() {
+            return Err(invalid("trailing bytes after exported state"));
+        }
+        Ok(Self { peers, used_tokens })
+    }
+}
+
+fn flag(present: bool, bit: u8) -> u8 {
+    if present { bit } else { 0 }
+}
+
+fn encode_addr(addr: SocketAddr, out: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+fn encode_duration(duration: Duration, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(duration.as_micros() as u64).to_be_bytes());
+}
+
+fn invalid(message: &str) -> RudpError {
+    RudpError::Serialization { message: message.to_string() }
+}
+
+/// Cursor over the encoded state
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RudpError> {
+        if self.data.len() < len {
+            return Err(invalid("exported state is truncated"));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, RudpError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, RudpError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, RudpError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn duration(&mut self) -> Result<Duration, RudpError> {
+        Ok(Duration::from_micros(self.u64()?))
+    }
+
+    fn addr(&mut self) -> Result<SocketAddr, RudpError> {
+        let ip = match self.u8()? {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(self.take(4)?).unwrap())),
+            6 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(self.take(16)?).unwrap())),
+            _ => return Err(invalid("malformed peer address")),
+        };
+        let port = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+        Ok(SocketAddr::new(ip, port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_rejects_damaged_input() {
+        let mut window = ReceiveWindow::new(10);
+        window.insert(10);
+        window.insert(12);
+        let mut packet = vec![PacketType::Data as u8, 0, 0, 0, 0, 0, 0, 0, 7];
+        packet.extend_from_slice(b"payload");
+        let state = SessionState {
+            peers: vec![
+                PeerSnapshot {
+                    addr: "10.0.0.1:1000".parse().unwrap(),
+                    next_seq: Some(8),
+                    next_message_id: Some(3),
+                    window: Some(window),
+                    rtt: Some(RttSnapshot {
+                        srtt: Duration::from_millis(40),
+                        rttvar: Duration::from_millis(5),
+                        rto: Duration::from_millis(200),
+                        cwnd: 12,
+                        ssthresh: 32,
+                        last_rtt_sample: None,
+                    }),
+                    token: None,
+                    packets: vec![packet.clone()],
+                },
+                PeerSnapshot {
+                    addr: "[::1]:2000".parse().unwrap(),
+                    next_seq: None,
+                    next_message_id: None,
+                    window: None,
+                    rtt: None,
+                    token: None,
+                    packets: Vec::new(),
+                },
+            ],
+            used_tokens: vec![(42, UNIX_EPOCH + Duration::from_secs(1_000_000))],
+        };
+
+        let encoded = state.encode();
+        let decoded = SessionState::decode(&encoded).unwrap();
+        assert_eq!(decoded.peers.len(), 2);
+        let peer = &decoded.peers[0];
+        assert_eq!((peer.next_seq, peer.next_message_id), (Some(8), Some(3)));
+        let window = peer.window.as_ref().unwrap();
+        assert!(window.contains(10) && !window.contains(11) && window.contains(12));
+        assert_eq!(peer.rtt, state.peers[0].rtt);
+        assert_eq!(peer.packets, vec![packet]);
+        assert_eq!(decoded.peers[1].addr, state.peers[1].addr);
+        assert!(decoded.peers[1].window.is_none());
+        assert_eq!(decoded.used_tokens, state.used_tokens);
+
+        assert!(SessionState::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(SessionState::decode(b"nope").is_err());
+        let mut newer = encoded.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(SessionState::decode(&newer).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Per-peer state split into independently locked shards
+//!
+//! [`ShardedCore`] holds several [`RudpCore`]s, each behind its own lock, and routes
+//! every peer to one of them by a hash of its address. All state of a peer (send
+//! buffer, receive window, RTT and connection statistics, keep-alive) lives in its
+//! shard, so threads or tasks handling peers in different shards never wait for each
+//! other: a multi-threaded driver can read datagrams on several sockets or workers
+//! and pass each to [`handle_datagram`](ShardedCore::handle_datagram) concurrently.
+//!
+//! The shards share one buffer pool. Each shard enforces `LimitsConfig` on its own,
+//! so the instance-wide peer and queue limits are the configured ones times the
+//! number of shards. A peer whose address changes is usually routed to another
+//! shard, which does not know it; it starts a new session there instead of migrating.
+
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use fnv::FnvHasher;
+
+use crate::buffer_pool::{PooledBuffer, SharedBufferPool};
+use crate::config::RudpConfig;
+use crate::engine::{ReceivedData, RudpCore, Transmit};
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+use crate::stats::{ConnectionStats, ConnectionStatus};
+
+/// Protocol cores for disjoint sets of peers, each behind its own lock
+pub struct ShardedCore {
+    shards: Box<[Mutex<RudpCore>]>,
+    buffer_pool: SharedBufferPool,
+    /// Shard the next `poll_received()`/`poll_message()` looks at first, so one busy
+    /// shard does not starve the others
+    next_poll: AtomicUsize,
+}
+
+impl ShardedCore {
+    /// `shards` cores with configuration `config`, sharing a buffer pool created from
+    /// its `pool_*` parameters
+    pub fn new(config: RudpConfig, shards: usize) -> Result<Self, RudpError> {
+        config.validate()?;
+        let buffer_pool = SharedBufferPool::with_limits(
+            config.pool_initial_capacity,
+            config.pool_max_capacity,
+            config.pool_buffer_size,
+        );
+        Self::with_pool(config, shards, buffer_pool)
+    }
+
+    /// `shards` cores with configuration `config` using `buffer_pool`
+    pub fn with_pool(config: RudpConfig, shards: usize, buffer_pool: SharedBufferPool) -> Result<Self, RudpError> {
+        if shards == 0 {
+            return Err(RudpError::InvalidConfig {
+                message: "at least one shard is required".to_string(),
+            });
+        }
+        let shards = (0..shards)
+            .map(|_| RudpCore::with_pool(config.clone(), buffer_pool.clone()).map(Mutex::new))
+            .collect::<Result<Box<[_]>, _>>()?;
+        Ok(Self {
+            shards,
+            buffer_pool,
+            next_poll: AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard holding the state of `addr`
+    pub fn shard_index(&self, addr: SocketAddr) -> usize {
+        let mut hasher = FnvHasher::default();
+        addr.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard holding the state of `addr`, for any per-peer operation
+    pub fn shard(&self, addr: SocketAddr) -> MutexGuard<'_, RudpCore> {
+        self.lock(self.shard_index(addr))
+    }
+
+    /// Lock shard `index`; panics if it is not below [`shard_count`](Self::shard_count)
+    pub fn lock(&self, index: usize) -> MutexGuard<'_, RudpCore> {
+        self.shards[index].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Get a pool buffer to write a payload into
+    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
+        self.buffer_pool.get_write_buffer()
+    }
+
+    /// Handle a datagram from `from` in its shard
+    pub fn handle_datagram(&self, packet_data: &[u8], from: SocketAddr, now: Instant) {
+        self.shard(from).handle_datagram(packet_data, from, now);
+    }
+
+    /// Handle a datagram read into a pool buffer, without copying it
+    pub fn handle_buffer(&self, buffer: PooledBuffer, len: usize, from: SocketAddr, now: Instant) {
+        self.shard(from).handle_buffer(buffer, len, from, now);
+    }
+
+    /// Send a payload reliably from the shard of `target`
+    pub fn send(&self, buffer: PooledBuffer, target: SocketAddr, now: Instant) -> Result<u32, RudpError> {
+        self.shard(target).send(buffer, target, now)
+    }
+
+    /// Send a message of any length up to `max_message_size`
+    pub fn send_message(&self, data: &[u8], target: SocketAddr, now: Instant) -> Result<(), RudpError> {
+        self.shard(target).send_message(data, target, now)
+    }
+
+    /// Run the timers of every shard, one shard locked at a time
+    pub fn handle_timeout(&self, now: Instant) {
+        for index in 0..self.shards.len() {
+            self.lock(index).handle_timeout(now);
+        }
+    }
+
+    /// Earliest timer of any shard
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        (0..self.shards.len()).filter_map(|index| self.lock(index).poll_timeout()).min()
+    }
+
+    /// Next datagram to send from any shard
+    ///
+    /// Drivers that send from several threads call `poll_transmit()` on the shards
+    /// they own through [`lock`](Self::lock) instead.
+    pub fn poll_transmit(&self) -> Option<Transmit> {
+        (0..self.shards.len()).find_map(|index| self.lock(index).poll_transmit())
+    }
+
+    /// Next received data or receive error of any shard
+    pub fn poll_received(&self) -> Option<ReceivedData> {
+        self.poll_each(RudpCore::poll_received)
+    }
+
+    /// Next complete message of any shard
+    pub fn poll_message(&self) -> Option<ReceivedMessage> {
+        self.poll_each(RudpCore::poll_message)
+    }
+
+    /// Connections of every shard; see [`RudpCore::connections`]
+    pub fn connections(&self) -> Vec<(SocketAddr, ConnectionStatus, ConnectionStats)> {
+        (0..self.shards.len()).flat_map(|index| self.lock(index).connections().collect::<Vec<_>>()).collect()
+    }
+
+    /// Close every shard; see [`RudpCore::close`]
+    pub fn close(&self) {
+        for index in 0..self.shards.len() {
+            self.lock(index).close();
+        }
+    }
+
+    fn poll_each<T>(&self, mut poll: impl FnMut(&mut RudpCore) -> Option<T>) -> Option<T> {
+        let start = self.next_poll.fetch_add(1, Ordering::Relaxed);
+        (0..self.shards.len()).find_map(|offset| poll(&mut self.lock((start + offset) % self.shards.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_peers_in_different_shards_progress_in_parallel() {
+        let now = Instant::now();
+        let server_addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let server = ShardedCore::new(RudpConfig::default(), 4).unwrap();
+        let clients: Vec<(SocketAddr, RudpCore)> = (1..=16)
+            .map(|port| (SocketAddr::from(([10, 0, 0, 2], port)), RudpCore::new(RudpConfig::default()).unwrap()))
+            .collect();
+        let used: HashSet<usize> = clients.iter().map(|(addr, _)| server.shard_index(*addr)).collect();
+        assert!(used.len() > 1);
+
+        // Each thread drives its own client against the shared server
+        thread::scope(|scope| {
+            for (addr, mut client) in clients {
+                let server = &server;
+                scope.spawn(move || {
+                    let mut buffer = client.get_buffer().unwrap();
+                    buffer.data_mut()[..2].copy_from_slice(&addr.port().to_be_bytes());
+                    buffer.set_data_len(2).unwrap();
+                    client.send(buffer, server_addr, now).unwrap();
+                    while let Some(transmit) = client.poll_transmit() {
+                        server.handle_datagram(&transmit.contents, addr, now);
+                    }
+                    let mut shard = server.shard(addr);
+                    shard.handle_timeout(now);
+                    while let Some(transmit) = shard.poll_transmit() {
+                        client.handle_datagram(&transmit.contents, server_addr, now);
+                    }
+                    assert_eq!(client.unacked_packets(server_addr), 0);
+                });
+            }
+        });
+
+        // Every payload arrived once, from the client that sent it
+        let mut senders = Vec::new();
+        while let Some(received) = server.poll_received() {
+            assert_eq!(received.result.unwrap().data(), received.from.port().to_be_bytes());
+            senders.push(received.from);
+        }
+        senders.sort();
+        senders.dedup();
+        assert_eq!(senders.len(), 16);
+    }
+}
@@ -0,0 +1,344 @@
+//! Network-condition simulator
+//!
+//! [`SimTransport`] wraps another [`Transport`] and impairs the datagrams it sends:
+//! random loss, delay drawn from a [`Delay`] distribution, reordering, duplication,
+//! and corruption by bit flips or truncation.
+//! Wrapping both endpoints impairs both directions. All random decisions come from a
+//! seeded generator, so a run with the same seed and traffic makes the same
+//! decisions.
+//!
+//! ```rust
+//! use rudpbase::{Delay, LoopbackTransport, SimConfig, SimTransport};
+//! use std::time::Duration;
+//!
+//! let (a, _b) = LoopbackTransport::pair("10.0.0.1:1".parse().unwrap(), "10.0.0.2:1".parse().unwrap());
+//! let lossy = SimTransport::new(a, SimConfig {
+//!     loss: 0.05,
+//!     delay: Delay::Uniform { min: Duration::from_millis(20), max: Duration::from_millis(40) },
+//!     seed: Some(7),
+//!     ..SimConfig::default()
+//! });
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+
+use crate::rng::SplitMix64;
+use crate::transport::{self, Transport};
+
+/// Distribution of the one-way delay added to each datagram
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Delay {
+    /// Send immediately
+    #[default]
+    None,
+    /// Constant delay
+    Fixed(Duration),
+    /// Uniformly distributed in `[min, max]`
+    Uniform { min: Duration, max: Duration },
+    /// Normally distributed, truncated at zero
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+/// Impairments applied by a [`SimTransport`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    /// Probability in `[0, 1]` that a datagram is dropped
+    pub loss: f64,
+    /// Delay added to every datagram that is not dropped
+    pub delay: Delay,
+    /// Probability in `[0, 1]` that a datagram is held back by `reorder_window`
+    /// on top of its delay, letting later datagrams overtake it
+    pub reorder: f64,
+    /// Extra delay of reordered datagrams
+    pub reorder_window: Duration,
+    /// Probability in `[0, 1]` that a datagram is sent twice; the copy gets its
+    /// own delay
+    pub duplicate: f64,
+    /// Probability in `[0, 1]` that one random bit of a datagram is flipped
+    pub corrupt: f64,
+    /// Probability in `[0, 1]` that a datagram is cut to a random shorter length
+    pub truncate: f64,
+    /// Seed of the random generator; a random seed is used if unset
+    pub seed: Option<u64>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            delay: Delay::None,
+            reorder: 0.0,
+            reorder_window: Duration::from_millis(10),
+            duplicate: 0.0,
+            corrupt: 0.0,
+            truncate: 0.0,
+            seed: None,
+        }
+    }
+}
+
+/// Counts of impairments applied so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    /// Datagrams passed to the simulator
+    pub sent: u64,
+    /// Datagrams dropped
+    pub dropped: u64,
+    /// Datagrams held back for reordering
+    pub reordered: u64,
+    /// Extra copies sent
+    pub duplicated: u64,
+    /// Datagrams with a flipped bit
+    pub corrupted: u64,
+    /// Datagrams cut short
+    pub truncated: u64,
+}
+
+/// Transport wrapper injecting loss, delay, reordering and duplication
+///
+/// Delayed datagrams are sent from tokio tasks, so the transport must be used
+/// inside a tokio runtime. Batched I/O is not available through the simulator.
+pub struct SimTransport<T: Transport> {
+    inner: Arc<T>,
+    config: SimConfig,
+    rng: Mutex<SplitMix64>,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    reordered: AtomicU64,
+    duplicated: AtomicU64,
+    corrupted: AtomicU64,
+    truncated: AtomicU64,
+}
+
+impl<T: Transport> SimTransport<T> {
+    /// Wrap `inner` with the given impairments
+    pub fn new(inner: T, config: SimConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Self {
+            inner: Arc::new(inner),
+            config,
+            rng: Mutex::new(SplitMix64(seed)),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            reordered: AtomicU64::new(0),
+            duplicated: AtomicU64::new(0),
+            corrupted: AtomicU64::new(0),
+            truncated: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Impairments applied so far
+    pub fn stats(&self) -> SimStats {
+        SimStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            reordered: self.reordered.load(Ordering::Relaxed),
+            duplicated: self.duplicated.load(Ordering::Relaxed),
+            corrupted: self.corrupted.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Draw the delay of one copy, including any reordering hold-back
+    fn sample_delay(&self, rng: &mut SplitMix64) -> Duration {
+        let mut delay = match self.config.delay {
+            Delay::None => Duration::ZERO,
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform { min, max } => {
+                let span = max.saturating_sub(min);
+                min + span.mul_f64(rng.next_f64())
+            }
+            Delay::Normal { mean, std_dev } => {
+                let sample = mean.as_secs_f64() + std_dev.as_secs_f64() * rng.next_normal();
+                Duration::from_secs_f64(sample.max(0.0))
+            }
+        };
+        if rng.chance(self.config.reorder) {
+            self.reordered.fetch_add(1, Ordering::Relaxed);
+            delay += self.config.reorder_window;
+        }
+        delay
+    }
+
+    /// Damage `buf` as configured; `None` if it is sent unchanged
+    fn damage(&self, rng: &mut SplitMix64, buf: &[u8]) -> Option<Vec<u8>> {
+        let mut damaged = None;
+        if !buf.is_empty() && rng.chance(self.config.corrupt) {
+            self.corrupted.fetch_add(1, Ordering::Relaxed);
+            let bit = rng.next_u64() % (buf.len() as u64 * 8);
+            let data = damaged.get_or_insert_with(|| buf.to_vec());
+            data[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+        if !buf.is_empty() && rng.chance(self.config.truncate) {
+            self.truncated.fetch_add(1, Ordering::Relaxed);
+            let len = (rng.next_u64() % buf.len() as u64) as usize;
+            damaged.get_or_insert_with(|| buf.to_vec()).truncate(len);
+        }
+        damaged
+    }
+
+    /// Send one copy now, or from a task once its delay has passed
+    fn dispatch(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr, delay: Duration) -> Poll<io::Result<usize>> {
+        if delay.is_zero() {
+            return self.inner.poll_send_to(cx, buf, target);
+        }
+        let inner = Arc::clone(&self.inner);
+        let data = buf.to_vec();
+        let deadline = Instant::now() + delay;
+        tokio::spawn(async move {
+            time::sleep_until(deadline).await;
+            let _ = transport::send_to(&*inner, &data, target).await;
+        });
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+impl<T: Transport> Transport for SimTransport<T> {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        let (dropped, delay, duplicate, damaged) = {
+            let mut rng = self.rng.lock().unwrap();
+            let dropped = rng.chance(self.config.loss);
+            let delay = self.sample_delay(&mut rng);
+            let duplicate = rng.chance(self.config.duplicate).then(|| self.sample_delay(&mut rng));
+            let damaged = if dropped { None } else { self.damage(&mut rng, buf) };
+            (dropped, delay, duplicate, damaged)
+        };
+        self.sent.fetch_add(1, Ordering::Relaxed);
+
+        if dropped {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Poll::Ready(Ok(buf.len()));
+        }
+        // Both copies carry the same damage; the caller sees its whole datagram as sent
+        let contents = damaged.as_deref().unwrap_or(buf);
+        let result = self.dispatch(cx, contents, target, delay).map_ok(|_| buf.len());
+        if let (Poll::Ready(Ok(_)), Some(delay)) = (&result, duplicate) {
+            self.duplicated.fetch_add(1, Ordering::Relaxed);
+            let _ = self.dispatch(cx, contents, target, delay);
+        }
+        result
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        self.inner.poll_recv_from(cx, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackTransport;
+
+    fn pair() -> (LoopbackTransport, LoopbackTransport, SocketAddr) {
+        let target: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let (a, b) = LoopbackTransport::pair("10.0.0.1:1".parse().unwrap(), target);
+        (a, b, target)
+    }
+
+    #[tokio::test]
+    async fn test_loss_and_duplication() {
+        let (a, b, target) = pair();
+        let lossy = SimTransport::new(a, SimConfig { loss: 1.0, ..SimConfig::default() });
+        for _ in 0..10 {
+            transport::send_to(&lossy, b"x", target).await.unwrap();
+        }
+        assert_eq!(b.queued(), 0);
+        assert_eq!(lossy.stats().dropped, 10);
+
+        let (a, b, target) = pair();
+        let doubled = SimTransport::new(a, SimConfig { duplicate: 1.0, ..SimConfig::default() });
+        transport::send_to(&doubled, b"x", target).await.unwrap();
+        assert_eq!(b.queued(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_and_reorder() {
+        let (a, b, target) = pair();
+        let sim = SimTransport::new(a, SimConfig {
+            delay: Delay::Fixed(Duration::from_millis(50)),
+            ..SimConfig::default()
+        });
+        transport::send_to(&sim, b"x", target).await.unwrap();
+        time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(b.queued(), 0);
+        time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(b.queued(), 1);
+
+        // Datagrams that are not held back overtake the ones that are
+        let (a, b, target) = pair();
+        let sim = SimTransport::new(a, SimConfig {
+            reorder: 0.5,
+            reorder_window: Duration::from_millis(10),
+            seed: Some(1),
+            ..SimConfig::default()
+        });
+        for i in 0..20u8 {
+            transport::send_to(&sim, &[i], target).await.unwrap();
+        }
+        time::sleep(Duration::from_millis(20)).await;
+
+        let mut order = Vec::new();
+        let mut buf = [0u8; 1];
+        while let Ok((_, _)) = transport::try_recv_from(&b, &mut buf) {
+            order.push(buf[0]);
+        }
+        assert_eq!(order.len(), 20);
+        let reordered = sim.stats().reordered;
+        assert!(reordered > 0 && reordered < 20);
+        assert_ne!(order, (0..20).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_corruption_and_truncation() {
+        let sent: Vec<u8> = (0..64).collect();
+        let mut buf = [0u8; 64];
+
+        let (a, b, target) = pair();
+        let corrupting = SimTransport::new(a, SimConfig { corrupt: 1.0, seed: Some(5), ..SimConfig::default() });
+        for _ in 0..10 {
+            transport::send_to(&corrupting, &sent, target).await.unwrap();
+            let (len, _) = transport::try_recv_from(&b, &mut buf).unwrap();
+            assert_eq!(len, sent.len());
+            let flipped: u32 = sent.iter().zip(&buf).map(|(sent, received)| (sent ^ received).count_ones()).sum();
+            assert_eq!(flipped, 1);
+        }
+        assert_eq!(corrupting.stats().corrupted, 10);
+
+        let (a, b, target) = pair();
+        let truncating = SimTransport::new(a, SimConfig { truncate: 1.0, seed: Some(6), ..SimConfig::default() });
+        for _ in 0..10 {
+            transport::send_to(&truncating, &sent, target).await.unwrap();
+            let (len, _) = transport::try_recv_from(&b, &mut buf).unwrap();
+            assert!(len < sent.len());
+            assert_eq!(buf[..len], sent[..len]);
+        }
+        assert_eq!(truncating.stats().truncated, 10);
+    }
+
+    #[test]
+    fn test_seeded_decisions_repeat() {
+        let mut first = SplitMix64(42);
+        let mut second = SplitMix64(42);
+        for _ in 0..100 {
+            assert_eq!(first.chance(0.3), second.chance(0.3));
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! smol adapter
+//!
+//! Enabled by the `smol` feature. smol drives its I/O with the `async-io` reactor,
+//! which this adapter uses directly, so no tokio runtime is needed.
+
+pub use crate::reactor::Rudpbase;
@@ -0,0 +1,293 @@
+//! UDP socket creation
+//!
+//! The socket is created through `socket2` so kernel options from [`SocketConfig`]
+//! can be applied before binding: buffer sizes must be set early to take effect for
+//! the first burst, and DSCP/don't-fragment apply to every packet sent.
+//!
+//! On multi-homed hosts the socket can be pinned to one interface, and datagrams to
+//! selected peers can leave from a chosen local address (`IP_PKTINFO`/`IPV6_PKTINFO`
+//! on Linux) while the socket itself stays bound to the wildcard address.
+
+use std::io;
+#[cfg(feature = "tokio")]
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(feature = "tokio")]
+use tokio::io::Interest;
+#[cfg(feature = "tokio")]
+use tokio::net::UdpSocket;
+
+use crate::config::SocketConfig;
+#[cfg(feature = "tokio")]
+use crate::transport::{self, Transport};
+
+/// Create a non-blocking tokio UDP socket with the configured options and bind it
+#[cfg(feature = "tokio")]
+pub(crate) fn bind(local_addr: SocketAddr, config: &SocketConfig) -> io::Result<UdpSocket> {
+    UdpSocket::from_std(bind_std(local_addr, config)?)
+}
+
+/// Create a non-blocking std UDP socket with the configured options and bind it
+pub(crate) fn bind_std(local_addr: SocketAddr, config: &SocketConfig) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(local_addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if let Some(size) = config.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+        let granted = socket.recv_buffer_size()?;
+        if granted < size {
+            trace_event!(warn, requested = size, granted, "receive buffer size capped by the kernel");
+        }
+    }
+    if let Some(size) = config.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+        let granted = socket.send_buffer_size()?;
+        if granted < size {
+            trace_event!(warn, requested = size, granted, "send buffer size capped by the kernel");
+        }
+    }
+    if let Some(dscp) = config.dscp {
+        set_traffic_class(&socket, local_addr, u32::from(dscp) << 2)?;
+    }
+    if config.dont_fragment {
+        set_dont_fragment(&socket, local_addr)?;
+    }
+    if let Some(interface) = &config.bind_interface {
+        sys::bind_interface(&socket, interface, local_addr.is_ipv6())?;
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&local_addr.into())?;
+    Ok(socket.into())
+}
+
+/// Send one datagram, from `source` when given instead of the kernel-selected address
+///
+/// Source selection needs the transport's OS socket; other transports ignore `source`.
+#[cfg(feature = "tokio")]
+pub(crate) async fn send_to(
+    transport: &dyn Transport,
+    data: &[u8],
+    target: SocketAddr,
+    source: Option<IpAddr>,
+) -> io::Result<usize> {
+    match (source, transport.udp_socket()) {
+        (Some(source), Some(socket)) => {
+            socket.async_io(Interest::WRITABLE, || sys::send_from(socket, data, target, source)).await
+        }
+        _ => transport::send_to(transport, data, target).await,
+    }
+}
+
+/// Whether per-peer source addresses can be used on this platform
+#[cfg(feature = "tokio")]
+pub(crate) const SOURCE_SELECTION_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Set the IPv4 TOS byte or IPv6 traffic class
+fn set_traffic_class(socket: &Socket, local_addr: SocketAddr, value: u32) -> io::Result<()> {
+    if local_addr.is_ipv4() {
+        return socket.set_tos(value);
+    }
+    sys::set_tclass_v6(socket, value)
+}
+
+/// Forbid fragmentation of outgoing datagrams (path MTU discovery "do")
+fn set_dont_fragment(socket: &Socket, local_addr: SocketAddr) -> io::Result<()> {
+    sys::set_dont_fragment(socket, local_addr.is_ipv6())
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    #[cfg(feature = "tokio")]
+    use std::mem;
+    #[cfg(feature = "tokio")]
+    use std::net::{IpAddr, SocketAddr};
+    use std::os::fd::AsRawFd;
+    #[cfg(feature = "tokio")]
+    use std::ptr;
+
+    use socket2::Socket;
+    #[cfg(feature = "tokio")]
+    use socket2::SockAddr;
+    #[cfg(feature = "tokio")]
+    use tokio::net::UdpSocket;
+
+    /// Control message buffer, aligned for `cmsghdr` and large enough for `in6_pktinfo`
+    #[cfg(feature = "tokio")]
+    type ControlBuffer = [u64; 8];
+
+    pub(super) fn bind_interface(socket: &Socket, interface: &str, _ipv6: bool) -> io::Result<()> {
+        socket.bind_device(Some(interface.as_bytes()))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(super) fn send_from(socket: &UdpSocket, data: &[u8], target: SocketAddr, source: IpAddr) -> io::Result<usize> {
+        let addr = SockAddr::from(target);
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut control: ControlBuffer = [0; 8];
+        // SAFETY: msghdr is plain old data; all-zero is a valid value
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_name = addr.as_ptr() as *mut libc::c_void;
+        header.msg_namelen = addr.len();
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr().cast();
+
+        // SAFETY: the control buffer is aligned for cmsghdr and larger than
+        // CMSG_SPACE(sizeof(in6_pktinfo)), so the first header and its data fit
+        unsafe {
+            match source {
+                IpAddr::V4(ip) => {
+                    let info = libc::in_pktinfo {
+                        ipi_ifindex: 0,
+                        ipi_spec_dst: libc::in_addr { s_addr: u32::from_ne_bytes(ip.octets()) },
+                        ipi_addr: libc::in_addr { s_addr: 0 },
+                    };
+                    write_control(&mut header, libc::IPPROTO_IP, libc::IP_PKTINFO, info);
+                }
+                IpAddr::V6(ip) => {
+                    let info = libc::in6_pktinfo {
+                        ipi6_addr: libc::in6_addr { s6_addr: ip.octets() },
+                        ipi6_ifindex: 0,
+                    };
+                    write_control(&mut header, libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, info);
+                }
+            }
+        }
+
+        // SAFETY: the header points at a live address, control buffer and payload;
+        // the kernel only reads from them
+        let result = unsafe { libc::sendmsg(socket.as_raw_fd(), &header, 0) };
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    /// Fill the first control message of `header` with `value`
+    ///
+    /// # Safety
+    /// `header.msg_control` must point at an aligned buffer of at least
+    /// `CMSG_SPACE(size_of::<T>())` bytes
+    #[cfg(feature = "tokio")]
+    unsafe fn write_control<T>(header: &mut libc::msghdr, level: libc::c_int, kind: libc::c_int, value: T) {
+        header.msg_controllen = libc::CMSG_SPACE(mem::size_of::<T>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(header);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = kind;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<T>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<T>(), value);
+    }
+
+    fn setsockopt(socket: &Socket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+        // SAFETY: the option value is a valid c_int living for the duration of the call
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub(super) fn set_tclass_v6(socket: &Socket, value: u32) -> io::Result<()> {
+        setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, value as libc::c_int)
+    }
+
+    pub(super) fn set_dont_fragment(socket: &Socket, ipv6: bool) -> io::Result<()> {
+        if ipv6 {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO)
+        } else {
+            setsockopt(socket, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+
+    use socket2::Socket;
+    #[cfg(feature = "tokio")]
+    use tokio::net::UdpSocket;
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub(super) fn bind_interface(socket: &Socket, interface: &str, ipv6: bool) -> io::Result<()> {
+        let name = std::ffi::CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains a NUL byte"))?;
+        // SAFETY: name is a valid NUL-terminated string
+        let index = std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+            .ok_or_else(io::Error::last_os_error)?;
+        if ipv6 {
+            socket.bind_device_by_index_v6(Some(index))
+        } else {
+            socket.bind_device_by_index_v4(Some(index))
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub(super) fn bind_interface(_socket: &Socket, _interface: &str, _ipv6: bool) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "binding to an interface is not supported on this platform"))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(super) fn send_from(_socket: &UdpSocket, _data: &[u8], _target: SocketAddr, _source: IpAddr) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "source address selection is only supported on Linux"))
+    }
+
+    pub(super) fn set_tclass_v6(_socket: &Socket, _value: u32) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "IPv6 DSCP marking is only supported on Linux"))
+    }
+
+    pub(super) fn set_dont_fragment(_socket: &Socket, _ipv6: bool) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "don't-fragment is only supported on Linux"))
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use socket2::SockRef;
+
+    #[tokio::test]
+    async fn test_socket_options_applied() {
+        let config = SocketConfig {
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(64 * 1024),
+            dscp: Some(46),
+            dont_fragment: cfg!(target_os = "linux"),
+            bind_interface: None,
+        };
+        let socket = bind("127.0.0.1:0".parse().unwrap(), &config).unwrap();
+        let sock = SockRef::from(&socket);
+
+        assert!(sock.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(sock.send_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(sock.tos().unwrap(), 46 << 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_interface() {
+        let config = SocketConfig {
+            bind_interface: Some("lo".to_string()),
+            ..SocketConfig::default()
+        };
+        let socket = bind("127.0.0.1:0".parse().unwrap(), &config).unwrap();
+        assert_eq!(SockRef::from(&socket).device().unwrap().as_deref(), Some(&b"lo"[..]));
+    }
+}
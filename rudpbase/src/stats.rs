@@ -1,7 +1,11 @@
 use std::time::{Duration, Instant};
 
+use crate::buffer_pool::PoolStats;
+use crate::config::{KeepAliveConfig, RudpConfig};
+
 /// Connection status enumeration
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
 pub enum ConnectionStatus {
     /// Connection is alive and healthy
     Alive,
@@ -24,12 +28,48 @@ pub struct ConnectionStats {
     pub packets_lost: u64,
     /// Total number of retransmissions
     pub retransmissions: u64,
+    /// Loss bursts reported as `ConnectionEvent::LossBurst`
+    pub loss_bursts: u64,
+    /// Outages reported as `ConnectionEvent::Outage`
+    pub outages: u64,
+    /// Total duration of those outages
+    pub outage_time: Duration,
+    /// Redundant copies sent of packets from `send_critical()`
+    pub redundant_copies: u64,
+    /// Duplicated packets whose acknowledgment came too late to answer the original,
+    /// so the copy most likely got through in its place (estimated)
+    pub redundant_recoveries: u64,
+    /// Total payload bytes sent to this connection (excluding retransmissions)
+    pub bytes_sent: u64,
+    /// Total payload bytes received from this connection (excluding duplicates)
+    pub bytes_received: u64,
+    /// Total payload bytes retransmitted to this connection
+    pub bytes_retransmitted: u64,
+    /// Total payload bytes acknowledged by this connection
+    pub bytes_acked: u64,
+    /// Moving estimate of acknowledged payload throughput
+    pub goodput: RateEstimator,
+    /// Moving estimate of payload send rate, including retransmissions
+    pub send_rate: RateEstimator,
     /// Average round-trip time
     pub avg_rtt: Duration,
+    /// Round-trip time percentiles (filled in by `get_stats()`)
+    pub rtt_percentiles: LatencyPercentiles,
+    /// Jitter percentiles (filled in by `get_stats()`)
+    pub jitter_percentiles: LatencyPercentiles,
+    /// Peer clock offset and one-way delays from ping timestamps (filled in by
+    /// `get_stats()`)
+    pub clock: Option<ClockOffset>,
     /// Last activity timestamp
     pub last_activity: Instant,
 }
 
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConnectionStats {
     pub fn new() -> Self {
         Self {
@@ -37,19 +77,52 @@ impl ConnectionStats {
             packets_received: 0,
             packets_lost: 0,
             retransmissions: 0,
+            loss_bursts: 0,
+            outages: 0,
+            outage_time: Duration::ZERO,
+            redundant_copies: 0,
+            redundant_recoveries: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            bytes_retransmitted: 0,
+            bytes_acked: 0,
+            goodput: RateEstimator::new(),
+            send_rate: RateEstimator::new(),
             avg_rtt: Duration::from_millis(200), // Initial RTT estimate
+            rtt_percentiles: LatencyPercentiles::default(),
+            jitter_percentiles: LatencyPercentiles::default(),
+            clock: None,
             last_activity: Instant::now(),
         }
     }
 
-    pub fn record_packet_sent(&mut self) {
+    pub fn record_packet_sent(&mut self, now: Instant) {
         self.packets_sent += 1;
-        self.last_activity = Instant::now();
+        self.last_activity = now;
     }
 
-    pub fn record_packet_received(&mut self) {
+    pub fn record_packet_received(&mut self, now: Instant) {
         self.packets_received += 1;
-        self.last_activity = Instant::now();
+        self.last_activity = now;
+    }
+
+    pub fn record_bytes_sent(&mut self, bytes: usize, now: Instant) {
+        self.bytes_sent += bytes as u64;
+        self.send_rate.record(bytes, now);
+    }
+
+    pub fn record_bytes_retransmitted(&mut self, bytes: usize, now: Instant) {
+        self.bytes_retransmitted += bytes as u64;
+        self.send_rate.record(bytes, now);
+    }
+
+    pub fn record_bytes_acked(&mut self, bytes: usize, now: Instant) {
+        self.bytes_acked += bytes as u64;
+        self.goodput.record(bytes, now);
+    }
+
+    pub fn record_bytes_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
     }
 
     pub fn record_packet_lost(&mut self) {
@@ -60,10 +133,27 @@ impl ConnectionStats {
         self.retransmissions += 1;
     }
 
+    pub fn record_loss_burst(&mut self) {
+        self.loss_bursts += 1;
+    }
+
+    pub fn record_outage(&mut self, duration: Duration) {
+        self.outages += 1;
+        self.outage_time += duration;
+    }
+
+    pub fn record_redundant_copy(&mut self) {
+        self.redundant_copies += 1;
+    }
+
+    pub fn record_redundant_recovery(&mut self) {
+        self.redundant_recoveries += 1;
+    }
+
     pub fn update_rtt(&mut self, rtt: Duration) {
         // Simple moving average for RTT
         self.avg_rtt = Duration::from_nanos(
-            ((self.avg_rtt.as_nanos() as u64 * 7 + rtt.as_nanos() as u64) / 8) as u64
+            (self.avg_rtt.as_nanos() as u64 * 7 + rtt.as_nanos() as u64) / 8
         );
     }
 
@@ -74,6 +164,302 @@ impl ConnectionStats {
             self.packets_lost as f64 / self.packets_sent as f64
         }
     }
+
+    /// Goodput estimate at `now` in payload bytes per second
+    pub fn goodput_bytes_per_sec(&self, now: Instant) -> f64 {
+        self.goodput.rate_at(now)
+    }
+
+    /// Send rate estimate at `now` in payload bytes per second
+    pub fn send_rate_bytes_per_sec(&self, now: Instant) -> f64 {
+        self.send_rate.rate_at(now)
+    }
+}
+
+/// Sampling interval of the rate estimators
+pub const RATE_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exponentially weighted moving average of a byte rate
+/// 
+/// Bytes are accumulated over `RATE_SAMPLE_INTERVAL` windows; every completed window
+/// is folded into the average with weight `ALPHA`, so idle periods decay the estimate
+/// towards zero.
+#[derive(Debug, Clone)]
+pub struct RateEstimator {
+    /// Smoothed rate in bytes per second as of `window_start`
+    rate: f64,
+    /// Start of the current accumulation window, set by the first recorded bytes
+    window_start: Option<Instant>,
+    /// Bytes recorded in the current window
+    window_bytes: u64,
+}
+
+impl Default for RateEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateEstimator {
+    const ALPHA: f64 = 0.25;
+
+    pub fn new() -> Self {
+        Self {
+            rate: 0.0,
+            window_start: None,
+            window_bytes: 0,
+        }
+    }
+
+    /// Record bytes transferred at `now`
+    pub fn record(&mut self, bytes: usize, now: Instant) {
+        let window_start = *self.window_start.get_or_insert(now);
+        if now.saturating_duration_since(window_start) >= RATE_SAMPLE_INTERVAL {
+            self.rate = self.rate_at(now);
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes as u64;
+    }
+
+    /// Estimated rate in bytes per second at `now`
+    pub fn rate_at(&self, now: Instant) -> f64 {
+        let Some(window_start) = self.window_start else {
+            return self.rate;
+        };
+        let elapsed = now.saturating_duration_since(window_start);
+        if elapsed < RATE_SAMPLE_INTERVAL {
+            return self.rate;
+        }
+
+        // Treat the elapsed time as several intervals at the window's average rate
+        let sample = self.window_bytes as f64 / elapsed.as_secs_f64();
+        let intervals = (elapsed.as_secs_f64() / RATE_SAMPLE_INTERVAL.as_secs_f64()) as i32;
+        let decay = (1.0 - Self::ALPHA).powi(intervals);
+        self.rate * decay + sample * (1.0 - decay)
+    }
+}
+
+/// Aggregate statistics across all peers of an instance
+#[derive(Debug, Clone)]
+pub struct GlobalStats {
+    /// Number of peers with connection state
+    pub active_connections: usize,
+    /// Total packets sent to all peers
+    pub packets_sent: u64,
+    /// Total packets received from all peers
+    pub packets_received: u64,
+    /// Total packets lost (estimated)
+    pub packets_lost: u64,
+    /// Total number of retransmissions
+    pub retransmissions: u64,
+    /// Loss bursts of all peers
+    pub loss_bursts: u64,
+    /// Outages of all peers
+    pub outages: u64,
+    /// Redundant copies of critical packets sent to all peers
+    pub redundant_copies: u64,
+    /// Critical packets most likely delivered by their copy
+    pub redundant_recoveries: u64,
+    /// Total payload bytes sent
+    pub bytes_sent: u64,
+    /// Total payload bytes received
+    pub bytes_received: u64,
+    /// Total payload bytes retransmitted
+    pub bytes_retransmitted: u64,
+    /// Data packets waiting for acknowledgment across all peers
+    pub pending_packets: usize,
+    /// Buffer pool state (pool pressure)
+    pub buffer_pool: PoolStats,
+    /// Invalid packets received from all sources since the instance was created
+    pub invalid_packets: InvalidPacketStats,
+}
+
+impl GlobalStats {
+    /// Create an empty aggregate for the given pool state
+    pub fn new(buffer_pool: PoolStats) -> Self {
+        Self {
+            active_connections: 0,
+            packets_sent: 0,
+            packets_received: 0,
+            packets_lost: 0,
+            retransmissions: 0,
+            loss_bursts: 0,
+            outages: 0,
+            redundant_copies: 0,
+            redundant_recoveries: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            bytes_retransmitted: 0,
+            pending_packets: 0,
+            buffer_pool,
+            invalid_packets: InvalidPacketStats::default(),
+        }
+    }
+
+    /// Add one peer's statistics to the aggregate
+    pub fn accumulate(&mut self, stats: &ConnectionStats) {
+        self.packets_sent += stats.packets_sent;
+        self.packets_received += stats.packets_received;
+        self.packets_lost += stats.packets_lost;
+        self.retransmissions += stats.retransmissions;
+        self.loss_bursts += stats.loss_bursts;
+        self.outages += stats.outages;
+        self.redundant_copies += stats.redundant_copies;
+        self.redundant_recoveries += stats.redundant_recoveries;
+        self.bytes_sent += stats.bytes_sent;
+        self.bytes_received += stats.bytes_received;
+        self.bytes_retransmitted += stats.bytes_retransmitted;
+    }
+
+    /// Fraction of pool allocations that had to allocate new memory
+    pub fn pool_miss_rate(&self) -> f64 {
+        if self.buffer_pool.total_allocations == 0 {
+            0.0
+        } else {
+            self.buffer_pool.pool_misses as f64 / self.buffer_pool.total_allocations as f64
+        }
+    }
+}
+
+/// Packets from one source that were discarded as invalid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InvalidPacketStats {
+    /// Packets whose security code did not verify
+    pub security_failures: u64,
+    /// Packets too short for their header, or with a malformed extension section,
+    /// fragment header or compressed payload
+    pub parse_errors: u64,
+    /// Packets with an unknown packet type
+    pub unknown_types: u64,
+    /// Packets whose payload did not match their payload checksum
+    pub checksum_failures: u64,
+}
+
+impl InvalidPacketStats {
+    /// All invalid packets
+    pub fn total(&self) -> u64 {
+        self.security_failures + self.parse_errors + self.unknown_types + self.checksum_failures
+    }
+}
+
+/// Linear buckets below `1 << HISTOGRAM_SUB_BITS` microseconds
+const HISTOGRAM_SUB_BITS: u32 = 3;
+const HISTOGRAM_SUB_BUCKETS: usize = 1 << HISTOGRAM_SUB_BITS;
+/// Largest recordable exponent; samples above ~71 minutes are clamped
+const HISTOGRAM_MAX_EXPONENT: u32 = 31;
+const HISTOGRAM_BUCKETS: usize =
+    2 * HISTOGRAM_SUB_BUCKETS + (HISTOGRAM_MAX_EXPONENT - HISTOGRAM_SUB_BITS) as usize * HISTOGRAM_SUB_BUCKETS;
+/// Sample count at which all buckets are halved, so the histogram follows recent behaviour
+const HISTOGRAM_AGING_THRESHOLD: u64 = 1 << 16;
+
+/// Log-linear (HDR-style) histogram of durations with microsecond resolution
+///
+/// Each power of two is split into eight buckets, bounding the relative error of a
+/// reported percentile to 12.5%. Memory use is fixed at about 1 KiB.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: Vec<u32>,
+    count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; HISTOGRAM_BUCKETS],
+            count: 0,
+        }
+    }
+
+    /// Record a sample
+    pub fn record(&mut self, sample: Duration) {
+        if self.count >= HISTOGRAM_AGING_THRESHOLD {
+            self.age();
+        }
+        let index = Self::bucket_index(sample.as_micros().min(u64::MAX as u128) as u64);
+        self.buckets[index] += 1;
+        self.count += 1;
+    }
+
+    /// Number of samples currently in the histogram
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Value at quantile `q` (0.0..=1.0), or `None` if no samples were recorded
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0u64;
+        for (index, &bucket) in self.buckets.iter().enumerate() {
+            seen += bucket as u64;
+            if seen >= rank {
+                return Some(Duration::from_micros(Self::bucket_midpoint(index)));
+            }
+        }
+        None
+    }
+
+    /// p50/p95/p99 summary (all zero if no samples were recorded)
+    pub fn percentiles(&self) -> LatencyPercentiles {
+        LatencyPercentiles {
+            p50: self.percentile(0.50).unwrap_or_default(),
+            p95: self.percentile(0.95).unwrap_or_default(),
+            p99: self.percentile(0.99).unwrap_or_default(),
+        }
+    }
+
+    /// Remove all samples
+    pub fn clear(&mut self) {
+        self.buckets.iter_mut().for_each(|bucket| *bucket = 0);
+        self.count = 0;
+    }
+
+    fn age(&mut self) {
+        self.count = 0;
+        for bucket in self.buckets.iter_mut() {
+            *bucket /= 2;
+            self.count += *bucket as u64;
+        }
+    }
+
+    fn bucket_index(micros: u64) -> usize {
+        if micros < HISTOGRAM_SUB_BUCKETS as u64 * 2 {
+            return micros as usize;
+        }
+        let exponent = (63 - micros.leading_zeros()).min(HISTOGRAM_MAX_EXPONENT);
+        let micros = micros.min((2u64 << HISTOGRAM_MAX_EXPONENT) - 1);
+        let sub = (micros >> (exponent - HISTOGRAM_SUB_BITS)) as usize & (HISTOGRAM_SUB_BUCKETS - 1);
+        HISTOGRAM_SUB_BUCKETS * 2 + (exponent - HISTOGRAM_SUB_BITS - 1) as usize * HISTOGRAM_SUB_BUCKETS + sub
+    }
+
+    fn bucket_midpoint(index: usize) -> u64 {
+        if index < HISTOGRAM_SUB_BUCKETS * 2 {
+            return index as u64;
+        }
+        let group = (index - HISTOGRAM_SUB_BUCKETS * 2) / HISTOGRAM_SUB_BUCKETS;
+        let sub = ((index - HISTOGRAM_SUB_BUCKETS * 2) % HISTOGRAM_SUB_BUCKETS) as u64;
+        let exponent = group as u32 + HISTOGRAM_SUB_BITS + 1;
+        let width = 1u64 << (exponent - HISTOGRAM_SUB_BITS);
+        (1u64 << exponent) + sub * width + width / 2
+    }
+}
+
+/// Tail latency summary
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
 }
 
 /// RTT统计和拥塞控制
@@ -95,8 +481,24 @@ pub struct RttStats {
     pub last_congestion: Option<Instant>,
     /// 拥塞控制状态
     pub congestion_state: CongestionState,
+    /// RTO下限
+    pub min_rto: Duration,
+    /// RTO上限
+    pub max_rto: Duration,
+    /// 最大拥塞窗口
+    pub max_cwnd: u32,
+    /// RTT样本分布
+    pub rtt_histogram: LatencyHistogram,
+    /// 抖动（相邻RTT样本之差）分布
+    pub jitter_histogram: LatencyHistogram,
+    /// 上一个RTT样本
+    pub last_rtt_sample: Option<Duration>,
 }
 
+/// RTO = SRTT + max(RTO_GRANULARITY, RTO_K * RTTVAR)
+const RTO_K: u32 = 4;
+const RTO_GRANULARITY: Duration = Duration::from_millis(10);
+
 /// 拥塞控制状态
 #[derive(Debug, Clone, PartialEq)]
 pub enum CongestionState {
@@ -108,42 +510,104 @@ pub enum CongestionState {
     FastRecovery,
 }
 
+impl Default for RttStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RttStats {
     pub fn new() -> Self {
+        Self::with_config(&RudpConfig::default())
+    }
+
+    /// 按配置创建RTT统计（初始RTO、RTO上下限、初始及最大拥塞窗口）
+    pub fn with_config(config: &RudpConfig) -> Self {
         Self {
             srtt: Duration::from_millis(100),
             rttvar: Duration::from_millis(50),
-            rto: Duration::from_millis(200),
-            cwnd: 1,  // 初始拥塞窗口为1
+            rto: config.initial_rto,
+            cwnd: config.initial_cwnd,
             ssthresh: 65535,  // 初始慢启动阈值设为最大值
             in_flight: 0,
             last_congestion: None,
             congestion_state: CongestionState::SlowStart,
+            min_rto: config.min_rto,
+            max_rto: config.max_rto,
+            max_cwnd: config.max_cwnd,
+            rtt_histogram: LatencyHistogram::new(),
+            jitter_histogram: LatencyHistogram::new(),
+            last_rtt_sample: None,
         }
     }
 
+    /// 应用新的RTO上下限和最大拥塞窗口，保留已测得的RTT和当前窗口
+    pub fn apply_config(&mut self, config: &RudpConfig) {
+        self.min_rto = config.min_rto;
+        self.max_rto = config.max_rto;
+        self.max_cwnd = config.max_cwnd;
+        self.rto = self.rto.clamp(self.min_rto, self.max_rto);
+        self.cwnd = self.cwnd.min(self.max_cwnd);
+    }
+
     /// 更新RTT统计
     pub fn update_rtt(&mut self, rtt_sample: Duration) {
         const ALPHA: f64 = 0.125;
         const BETA: f64 = 0.25;
-        const K: u32 = 4;
-        const G: Duration = Duration::from_millis(10);
-
-        let rtt_sample_ms = rtt_sample.as_millis() as f64;
-        let srtt_ms = self.srtt.as_millis() as f64;
-        let rttvar_ms = self.rttvar.as_millis() as f64;
 
-        // 更新RTTVAR
-        let new_rttvar_ms = (1.0 - BETA) * rttvar_ms + BETA * (srtt_ms - rtt_sample_ms).abs();
-        self.rttvar = Duration::from_millis(new_rttvar_ms as u64);
+        // 记录RTT和抖动分布
+        self.rtt_histogram.record(rtt_sample);
+        let first_sample = self.last_rtt_sample.is_none();
+        if let Some(last) = self.last_rtt_sample {
+            self.jitter_histogram.record(rtt_sample.abs_diff(last));
+        }
+        self.last_rtt_sample = Some(rtt_sample);
 
-        // 更新SRTT
-        let new_srtt_ms = (1.0 - ALPHA) * srtt_ms + ALPHA * rtt_sample_ms;
+        let rtt_sample_ms = rtt_sample.as_millis() as f64;
+        let (new_srtt_ms, new_rttvar_ms) = if first_sample {
+            // 第一个样本直接作为SRTT，RTTVAR取其一半（RFC 6298）
+            (rtt_sample_ms, rtt_sample_ms / 2.0)
+        } else {
+            let srtt_ms = self.srtt.as_millis() as f64;
+            let rttvar_ms = self.rttvar.as_millis() as f64;
+            (
+                (1.0 - ALPHA) * srtt_ms + ALPHA * rtt_sample_ms,
+                (1.0 - BETA) * rttvar_ms + BETA * (srtt_ms - rtt_sample_ms).abs(),
+            )
+        };
         self.srtt = Duration::from_millis(new_srtt_ms as u64);
+        self.rttvar = Duration::from_millis(new_rttvar_ms as u64);
 
         // 计算RTO
-        let rto_ms = new_srtt_ms + (K as f64 * new_rttvar_ms).max(G.as_millis() as f64);
-        self.rto = Duration::from_millis(rto_ms.min(60000.0).max(200.0) as u64);
+        let rto_ms = new_srtt_ms + (RTO_K as f64 * new_rttvar_ms).max(RTO_GRANULARITY.as_millis() as f64);
+        let min_rto_ms = self.min_rto.as_millis() as f64;
+        let max_rto_ms = self.max_rto.as_millis() as f64;
+        self.rto = Duration::from_millis(rto_ms.clamp(min_rto_ms, max_rto_ms) as u64);
+    }
+
+    /// 用上一次会话测得的RTT代替初始估计，之后的第一个样本仍按首个样本处理
+    pub fn restore_rtt(&mut self, srtt: Duration) {
+        if self.last_rtt_sample.is_some() {
+            return;
+        }
+        self.srtt = srtt;
+        self.rttvar = srtt / 2;
+        self.rto = (srtt + (self.rttvar * RTO_K).max(RTO_GRANULARITY)).clamp(self.min_rto, self.max_rto);
+    }
+
+    /// 超时重传后把RTO提高到退避后的`rto`（已按退避上限截断），下一个有效RTT样本重新计算RTO
+    pub fn back_off_rto(&mut self, rto: Duration) {
+        self.rto = self.rto.max(rto);
+    }
+
+    /// RTT的p50/p95/p99
+    pub fn rtt_percentiles(&self) -> LatencyPercentiles {
+        self.rtt_histogram.percentiles()
+    }
+
+    /// 抖动的p50/p95/p99
+    pub fn jitter_percentiles(&self) -> LatencyPercentiles {
+        self.jitter_histogram.percentiles()
     }
 
     /// 包发送时调用（增加飞行中包数量）
@@ -176,13 +640,16 @@ impl RttStats {
         }
         
         // 限制最大窗口大小
-        self.cwnd = self.cwnd.min(1000);
+        self.cwnd = self.cwnd.min(self.max_cwnd);
+    }
+
+    /// 放弃重传时调用（包不再占用拥塞窗口）
+    pub fn on_packet_dropped(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
     }
 
     /// 检测到丢包时调用
-    pub fn on_packet_lost(&mut self) {
-        let now = Instant::now();
-        
+    pub fn on_packet_lost(&mut self, now: Instant) {
         // 避免在短时间内多次触发拥塞控制
         if let Some(last) = self.last_congestion {
             if now.duration_since(last) < self.rto {
@@ -232,39 +699,50 @@ pub struct ConnectionState {
     pub status: ConnectionStatus,
 }
 
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConnectionState {
     pub fn new() -> Self {
+        Self::new_at(Instant::now())
+    }
+
+    /// 创建以`now`为最后活动时间的连接状态
+    pub fn new_at(now: Instant) -> Self {
         Self {
-            last_activity: Instant::now(),
+            last_activity: now,
             ping_sent: None,
             consecutive_ping_failures: 0,
             status: ConnectionStatus::Alive,
         }
     }
 
-    pub fn update_activity(&mut self) {
-        self.last_activity = Instant::now();
+    pub fn update_activity(&mut self, now: Instant) {
+        self.last_activity = now;
         self.consecutive_ping_failures = 0;
         self.status = ConnectionStatus::Alive;
     }
 
-    pub fn mark_ping_sent(&mut self) {
-        self.ping_sent = Some(Instant::now());
+    pub fn mark_ping_sent(&mut self, now: Instant) {
+        self.ping_sent = Some(now);
         self.status = ConnectionStatus::Probing;
     }
 
-    pub fn mark_ping_received(&mut self) {
+    pub fn mark_ping_received(&mut self, now: Instant) {
         self.ping_sent = None;
         self.consecutive_ping_failures = 0;
         self.status = ConnectionStatus::Alive;
-        self.last_activity = Instant::now();
+        self.last_activity = now;
     }
 
-    pub fn mark_ping_failed(&mut self) {
+    pub fn mark_ping_failed(&mut self, max_ping_failures: u8) {
         self.ping_sent = None;
-        self.consecutive_ping_failures += 1;
+        self.consecutive_ping_failures = self.consecutive_ping_failures.saturating_add(1);
         
-        if self.consecutive_ping_failures >= 3 {
+        if self.consecutive_ping_failures >= max_ping_failures {
             self.status = ConnectionStatus::Dead;
         } else {
             self.status = ConnectionStatus::Degraded;
@@ -272,19 +750,22 @@ impl ConnectionState {
     }
 
     /// 检查是否应该发送ping
-    pub fn should_ping(&self, now: Instant) -> bool {
-        // 如果空闲时间超过30秒且没有待处理的ping
-        now.duration_since(self.last_activity) > Duration::from_secs(30) && self.ping_sent.is_none()
+    pub fn should_ping(&self, now: Instant, config: &KeepAliveConfig) -> bool {
+        // 如果空闲时间超过idle_timeout且没有待处理的ping
+        now.duration_since(self.last_activity) > config.idle_timeout && self.ping_sent.is_none()
+    }
+
+    /// 检查待处理的ping是否已超时未响应
+    pub fn ping_timed_out(&self, now: Instant, config: &KeepAliveConfig) -> bool {
+        self.ping_sent
+            .map(|ping_time| now.duration_since(ping_time) > config.ping_interval)
+            .unwrap_or(false)
     }
 
     /// 检查是否应该关闭连接
-    pub fn should_close(&self, now: Instant) -> bool {
-        // 如果有待处理的ping且已超时，或者连续ping失败次数过多
-        if let Some(ping_time) = self.ping_sent {
-            now.duration_since(ping_time) > Duration::from_secs(10) && self.consecutive_ping_failures >= 3
-        } else {
-            self.consecutive_ping_failures >= 3
-        }
+    pub fn should_close(&self, config: &KeepAliveConfig) -> bool {
+        // 连续ping失败次数达到上限
+        self.consecutive_ping_failures >= config.max_ping_failures
     }
 
     /// 标记包丢失
@@ -308,6 +789,44 @@ pub struct CongestionInfo {
     pub congestion_state: CongestionState,
     /// 当前RTO
     pub current_rto: Duration,
+    /// 平滑RTT，尚无RTT样本时为初始值
+    pub smoothed_rtt: Duration,
+    /// RTT变化量
+    pub rtt_variance: Duration,
+}
+
+/// 单向时延统计，由`timestamps`开启的发送时间戳测得
+///
+/// 两端时钟不同步，时延以该路径上见过的最低单向时延为基准，即网络排队造成的时延
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneWayDelay {
+    /// 最新数据包的单向时延高出最低值的部分
+    pub queuing_delay: Duration,
+    /// 平滑后的排队时延
+    pub smoothed_queuing_delay: Duration,
+    /// 单向时延的平滑变化率（每秒增加的时延秒数），队列增长时为正，排空时为负
+    pub trend: f64,
+    /// 已测量的数据包数量
+    pub samples: u64,
+}
+
+/// 由ping时间戳估计的对端时钟偏差和单向时延
+///
+/// 每个ping应答带回对端应答时的时钟，与本端发送ping和收到应答的时间组成一次NTP式的
+/// 测量。偏差取最近几次测量中往返时间最短的一次：假设那次往返两个方向的时延相同，
+/// 误差不超过其往返时间的一半。单向时延由最近一次测量按该偏差换算
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockOffset {
+    /// 对端时钟减去本端时钟（纳秒），对端时钟较快时为正
+    pub offset_nanos: i64,
+    /// 偏差估计的误差上限
+    pub uncertainty: Duration,
+    /// 最近一次ping从本端到对端的单向时延
+    pub outgoing_delay: Duration,
+    /// 最近一次ping应答从对端到本端的单向时延
+    pub incoming_delay: Duration,
+    /// 已完成的测量次数
+    pub samples: u64,
 }
 
 // Constants for connection management
@@ -315,4 +834,89 @@ pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 pub const PING_INTERVAL: Duration = Duration::from_secs(10);
 pub const MAX_PING_FAILURES: u8 = 3;
 pub const MAX_RETRIES: u8 = 5;
-pub const CLEANUP_THRESHOLD: Duration = Duration::from_secs(300); // 5 minutes 
\ No newline at end of file
+pub const CLEANUP_THRESHOLD: Duration = Duration::from_secs(300); // 5 minutes 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_estimator_converges() {
+        let start = Instant::now();
+        let mut estimator = RateEstimator::new();
+
+        // 1000 bytes every 10ms = 100 KB/s for 2 seconds
+        for i in 0..200 {
+            estimator.record(1000, start + Duration::from_millis(i * 10));
+        }
+
+        let rate = estimator.rate_at(start + Duration::from_millis(2000));
+        assert!((rate - 100_000.0).abs() < 10_000.0, "rate = {}", rate);
+    }
+
+    #[test]
+    fn test_rate_estimator_decays_when_idle() {
+        let start = Instant::now();
+        let mut estimator = RateEstimator::new();
+        for i in 0..100 {
+            estimator.record(1000, start + Duration::from_millis(i * 10));
+        }
+
+        let active = estimator.rate_at(start + Duration::from_millis(1000));
+        let idle = estimator.rate_at(start + Duration::from_secs(5));
+        assert!(idle < active / 10.0, "active = {}, idle = {}", active, idle);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::new();
+        assert_eq!(histogram.percentile(0.5), None);
+
+        // 1..=100ms, one sample each
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        let percentiles = histogram.percentiles();
+        for (actual, expected) in [(percentiles.p50, 50.0), (percentiles.p95, 95.0), (percentiles.p99, 99.0)] {
+            let error = (actual.as_secs_f64() * 1000.0 - expected).abs() / expected;
+            assert!(error <= 0.125, "expected ~{}ms, got {:?}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_histogram_bucket_bounds() {
+        for micros in [0, 1, 15, 16, 17, 1000, 123_456, u32::MAX as u64, u64::MAX] {
+            let index = LatencyHistogram::bucket_index(micros);
+            assert!(index < HISTOGRAM_BUCKETS, "{} -> {}", micros, index);
+        }
+        for index in 1..HISTOGRAM_BUCKETS {
+            assert!(LatencyHistogram::bucket_midpoint(index) > LatencyHistogram::bucket_midpoint(index - 1));
+        }
+    }
+
+    #[test]
+    fn test_rtt_stats_tracks_jitter() {
+        let mut stats = RttStats::new();
+        for ms in [100, 110, 100, 110] {
+            stats.update_rtt(Duration::from_millis(ms));
+        }
+
+        assert_eq!(stats.rtt_histogram.count(), 4);
+        assert_eq!(stats.jitter_histogram.count(), 3);
+        let jitter = stats.jitter_percentiles().p50.as_millis();
+        assert!((9..=11).contains(&jitter), "jitter = {}ms", jitter);
+    }
+
+    #[test]
+    fn test_first_rtt_sample_replaces_initial_estimate() {
+        let config = RudpConfig::new().with_rto_bounds(Duration::from_millis(10), Duration::from_secs(60));
+        let mut stats = RttStats::with_config(&config);
+        stats.update_rtt(Duration::from_millis(20));
+        assert_eq!((stats.srtt, stats.rttvar), (Duration::from_millis(20), Duration::from_millis(10)));
+        assert_eq!(stats.rto, Duration::from_millis(60));
+
+        stats.update_rtt(Duration::from_millis(28));
+        assert_eq!((stats.srtt, stats.rttvar), (Duration::from_millis(21), Duration::from_millis(9)));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::error::RudpError;
+
+/// STUN magic cookie (RFC 5389)
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// STUN message header size in bytes
+pub const STUN_HEADER_SIZE: usize = 20; // type(2) + length(2) + cookie(4) + transaction_id(12)
+
+/// Initial retransmission timeout for binding requests
+pub const STUN_INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Maximum number of binding requests sent before giving up
+pub const STUN_MAX_ATTEMPTS: u32 = 5;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// STUN binding request
+#[derive(Debug, Clone)]
+pub struct BindingRequest {
+    pub transaction_id: [u8; 12],
+}
+
+impl Default for BindingRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BindingRequest {
+    /// Create a binding request with a random transaction id
+    pub fn new() -> Self {
+        let high = RandomState::new().build_hasher().finish();
+        let low = RandomState::new().build_hasher().finish();
+
+        let mut transaction_id = [0u8; 12];
+        transaction_id[..8].copy_from_slice(&high.to_be_bytes());
+        transaction_id[8..].copy_from_slice(&low.to_be_bytes()[..4]);
+
+        Self { transaction_id }
+    }
+
+    /// Serialize the request (no attributes)
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(STUN_HEADER_SIZE);
+
+        packet.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+        packet.extend_from_slice(&0u16.to_be_bytes()); // message length
+        packet.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        packet.extend_from_slice(&self.transaction_id);
+
+        packet
+    }
+}
+
+/// Check whether a datagram looks like a STUN message
+///
+/// STUN messages start with two zero bits and carry the magic cookie at bytes 4..8,
+/// which lets them share a socket with rudpbase traffic.
+pub fn is_stun_message(packet: &[u8]) -> bool {
+    packet.len() >= STUN_HEADER_SIZE
+        && packet[0] & 0xC0 == 0
+        && packet[4..8] == MAGIC_COOKIE.to_be_bytes()
+}
+
+/// Check whether a datagram is a STUN message belonging to the given transaction
+pub fn matches_transaction(packet: &[u8], transaction_id: &[u8; 12]) -> bool {
+    is_stun_message(packet) && packet[8..STUN_HEADER_SIZE] == transaction_id[..]
+}
+
+/// Parse a binding response and return the reflexive address it carries
+///
+/// XOR-MAPPED-ADDRESS is preferred; MAPPED-ADDRESS is accepted from older servers.
+pub fn parse_binding_response(packet: &[u8], transaction_id: &[u8; 12]) -> Result<SocketAddr, RudpError> {
+    if !matches_transaction(packet, transaction_id) {
+        return Err(RudpError::Protocol {
+            message: "STUN response does not match transaction".to_string(),
+        });
+    }
+
+    let message_type = u16::from_be_bytes([packet[0], packet[1]]);
+    let message_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if packet.len() < STUN_HEADER_SIZE + message_len {
+        return Err(RudpError::PacketTooSmall {
+            size: packet.len(),
+            min: STUN_HEADER_SIZE + message_len,
+        });
+    }
+
+    let mut mapped = None;
+    let mut xor_mapped = None;
+    let mut error_code = None;
+
+    let attributes = &packet[STUN_HEADER_SIZE..STUN_HEADER_SIZE + message_len];
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let attr_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let attr_len = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value_start = offset + 4;
+        if value_start + attr_len > attributes.len() {
+            break;
+        }
+        let value = &attributes[value_start..value_start + attr_len];
+
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = decode_address(value, Some(transaction_id)),
+            ATTR_MAPPED_ADDRESS => mapped = decode_address(value, None),
+            ATTR_ERROR_CODE if value.len() >= 4 => {
+                let code = value[2] as u16 * 100 + value[3] as u16;
+                let reason = String::from_utf8_lossy(&value[4..]).into_owned();
+                error_code = Some((code, reason));
+            }
+            _ => {}
+        }
+
+        // Attributes are padded to a multiple of 4 bytes
+        offset = value_start + ((attr_len + 3) & !3);
+    }
+
+    match message_type {
+        BINDING_SUCCESS_RESPONSE => xor_mapped.or(mapped).ok_or_else(|| RudpError::Protocol {
+            message: "STUN response without mapped address".to_string(),
+        }),
+        BINDING_ERROR_RESPONSE => {
+            let (code, reason) = error_code.unwrap_or((0, String::new()));
+            Err(RudpError::Protocol {
+                message: format!("STUN error response: {} {}", code, reason),
+            })
+        }
+        other => Err(RudpError::Protocol {
+            message: format!("Unexpected STUN message type: {:#06x}", other),
+        }),
+    }
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS attribute value
+fn decode_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    if value.len() < 4 {
+        return None;
+    }
+
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let mut port = u16::from_be_bytes([value[2], value[3]]);
+    if xor_transaction_id.is_some() {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+    }
+
+    let ip = match value[1] {
+        FAMILY_IPV4 if value.len() >= 8 => {
+            let mut octets = [value[4], value[5], value[6], value[7]];
+            if xor_transaction_id.is_some() {
+                for (octet, mask) in octets.iter_mut().zip(cookie.iter()) {
+                    *octet ^= mask;
+                }
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        FAMILY_IPV6 if value.len() >= 20 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&value[4..20]);
+            if let Some(transaction_id) = xor_transaction_id {
+                let mask = cookie.iter().chain(transaction_id.iter());
+                for (octet, mask) in octets.iter_mut().zip(mask) {
+                    *octet ^= mask;
+                }
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xor_mapped_response(transaction_id: &[u8; 12], addr: SocketAddr) -> Vec<u8> {
+        let cookie = MAGIC_COOKIE.to_be_bytes();
+        let mut value = vec![0u8];
+        let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                value.push(FAMILY_IPV4);
+                value.extend_from_slice(&port.to_be_bytes());
+                value.extend(ip.octets().iter().zip(cookie.iter()).map(|(a, b)| a ^ b));
+            }
+            IpAddr::V6(ip) => {
+                value.push(FAMILY_IPV6);
+                value.extend_from_slice(&port.to_be_bytes());
+                let mask = cookie.iter().chain(transaction_id.iter());
+                value.extend(ip.octets().iter().zip(mask).map(|(a, b)| a ^ b));
+            }
+        }
+
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&BINDING_SUCCESS_RESPONSE.to_be_bytes());
+        packet.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+        packet.extend_from_slice(&cookie);
+        packet.extend_from_slice(transaction_id);
+        packet.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        packet.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&value);
+        packet
+    }
+
+    #[test]
+    fn test_binding_request_serialization() {
+        let request = BindingRequest::new();
+        let packet = request.serialize();
+
+        assert_eq!(packet.len(), STUN_HEADER_SIZE);
+        assert_eq!(&packet[0..2], &BINDING_REQUEST.to_be_bytes());
+        assert!(matches_transaction(&packet, &request.transaction_id));
+        assert_ne!(request.transaction_id, BindingRequest::new().transaction_id);
+    }
+
+    #[test]
+    fn test_parse_xor_mapped_ipv4() {
+        let request = BindingRequest::new();
+        let addr: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let response = xor_mapped_response(&request.transaction_id, addr);
+
+        assert_eq!(parse_binding_response(&response, &request.transaction_id).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_parse_xor_mapped_ipv6() {
+        let request = BindingRequest::new();
+        let addr: SocketAddr = "[2001:db8::1]:3478".parse().unwrap();
+        let response = xor_mapped_response(&request.transaction_id, addr);
+
+        assert_eq!(parse_binding_response(&response, &request.transaction_id).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_transaction_mismatch_rejected() {
+        let request = BindingRequest::new();
+        let response = xor_mapped_response(&request.transaction_id, "198.51.100.1:1".parse().unwrap());
+
+        let other = BindingRequest::new();
+        assert!(parse_binding_response(&response, &other.transaction_id).is_err());
+    }
+
+    #[test]
+    fn test_rudp_packet_is_not_stun() {
+        let mut packet = vec![2u8]; // Data packet
+        packet.extend_from_slice(&0x12345678u32.to_be_bytes());
+        packet.extend_from_slice(&7u32.to_be_bytes());
+        packet.extend_from_slice(b"payload bytes");
+
+        assert!(!is_stun_message(&packet));
+    }
+}
@@ -0,0 +1,335 @@
+//! Blocking Rudpbase
+//!
+//! For programs without an async runtime. [`Rudpbase`] owns a blocking
+//! `std::net::UdpSocket` and a maintenance thread that reads the socket and runs
+//! retransmissions, ACKs and keep-alive on the sans-IO [`RudpCore`], so the caller
+//! never has to call `tick()`. Sends go out on the calling thread; received data is
+//! queued by the maintenance thread and taken with [`recv`](Rudpbase::recv) or
+//! [`recv_timeout`](Rudpbase::recv_timeout).
+//!
+//! All methods take `&self`, so an instance can be shared between threads in an
+//! `Arc`. Statistics and runtime configuration are reached through
+//! [`with_core`](Rudpbase::with_core).
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::buffer_pool::PooledBuffer;
+use crate::config::{IoBackend, RudpConfig};
+use crate::engine::{ReceivedData, RudpCore};
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+use crate::socket;
+
+/// Longest the maintenance thread blocks on the socket before checking timers again
+const MAX_WAIT: Duration = Duration::from_millis(10);
+
+/// Shortest socket read timeout; a zero timeout is rejected by the OS
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+/// Reliable UDP endpoint with a blocking API
+///
+/// ```rust,no_run
+/// use rudpbase::sync::Rudpbase;
+/// use std::time::Duration;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let rudp = Rudpbase::new("127.0.0.1:8080".parse()?)?;
+///     let mut buffer = rudp.get_buffer()?;
+///     buffer.data_mut()[..5].copy_from_slice(b"hello");
+///     buffer.set_data_len(5)?;
+///     rudp.send(buffer, "127.0.0.1:8081".parse()?)?;
+///
+///     if let Some(received) = rudp.recv_timeout(Duration::from_secs(1)) {
+///         println!("{} bytes from {}", received.result?.data_len(), received.from);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Rudpbase {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// State shared with the maintenance thread
+struct Shared {
+    socket: UdpSocket,
+    core: Mutex<RudpCore>,
+    /// Signalled when received data or a complete message is queued
+    readable: Condvar,
+    running: AtomicBool,
+}
+
+impl Rudpbase {
+    /// Bind a socket to `local_addr` with the default configuration
+    pub fn new(local_addr: SocketAddr) -> Result<Self, RudpError> {
+        Self::with_config(local_addr, RudpConfig::default())
+    }
+
+    /// Bind a socket to `local_addr`, applying `config.socket`
+    pub fn with_config(local_addr: SocketAddr, config: RudpConfig) -> Result<Self, RudpError> {
+        config.validate()?;
+        let socket = socket::bind_std(local_addr, &config.socket)?;
+        Self::with_socket(socket, config)
+    }
+
+    /// Use an already bound socket
+    ///
+    /// `config.socket` is ignored. Only unbatched socket I/O is available:
+    /// `io_batch_size` must be 1 and `io_backend` must be `IoBackend::Socket`.
+    pub fn with_socket(socket: UdpSocket, config: RudpConfig) -> Result<Self, RudpError> {
+        if config.io_batch_size != 1 || config.io_backend != IoBackend::Socket {
+            return Err(RudpError::InvalidConfig {
+                message: "batched I/O and io_uring require the tokio build".to_string(),
+            });
+        }
+        socket.set_nonblocking(false)?;
+        let core = RudpCore::new(config)?;
+        let shared = Arc::new(Shared {
+            socket,
+            core: Mutex::new(core),
+            readable: Condvar::new(),
+            running: AtomicBool::new(true),
+        });
+        let worker = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("rudpbase-maintenance".to_string())
+                .spawn(move || shared.run())?
+        };
+        Ok(Self {
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Local address of the socket
+    pub fn local_addr(&self) -> Result<SocketAddr, RudpError> {
+        Ok(self.shared.socket.local_addr()?)
+    }
+
+    /// Get a pool buffer to write a payload into
+    pub fn get_buffer(&self) -> Result<PooledBuffer, RudpError> {
+        self.shared.lock().get_buffer()
+    }
+
+    /// Send a payload reliably, returning its sequence number
+    ///
+    /// Queued while the congestion window is full; the maintenance thread sends it as
+    /// ACKs arrive. Returns `RudpError::CongestionWindowFull` when the peer's send
+    /// queue is full too.
+    pub fn send(&self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let mut core = self.shared.lock();
+        let seq = core.send(buffer, target, Instant::now())?;
+        self.shared.flush(&mut core)?;
+        Ok(seq)
+    }
+
+    /// Send a payload now, returning `RudpError::CongestionWindowFull` instead of queueing
+    pub fn try_send(&self, buffer: PooledBuffer, target: SocketAddr) -> Result<u32, RudpError> {
+        let mut core = self.shared.lock();
+        let seq = core.try_send(buffer, target, Instant::now())?;
+        self.shared.flush(&mut core)?;
+        Ok(seq)
+    }
+
+    /// Send a message of any length up to `max_message_size`
+    pub fn send_message(&self, data: &[u8], target: SocketAddr) -> Result<(), RudpError> {
+        let mut core = self.shared.lock();
+        core.send_message(data, target, Instant::now())?;
+        self.shared.flush(&mut core)
+    }
+
+    /// Send an unreliable datagram
+    pub fn send_unreliable(&self, buffer: PooledBuffer, target: SocketAddr) -> Result<(), RudpError> {
+        let mut core = self.shared.lock();
+        core.send_unreliable(buffer, target)?;
+        self.shared.flush(&mut core)
+    }
+
+    /// Block until user data or a receive error arrives
+    pub fn recv(&self) -> ReceivedData {
+        let mut core = self.shared.lock();
+        loop {
+            if let Some(received) = core.poll_received() {
+                return received;
+            }
+            core = self.shared.readable.wait(core).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Wait up to `timeout` for user data; `None` if nothing arrived
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ReceivedData> {
+        self.wait_for(timeout, RudpCore::poll_received)
+    }
+
+    /// Take received user data without waiting
+    pub fn try_recv(&self) -> Option<ReceivedData> {
+        self.shared.lock().poll_received()
+    }
+
+    /// Wait up to `timeout` for a complete message; `None` if none was reassembled
+    pub fn recv_message_timeout(&self, timeout: Duration) -> Option<ReceivedMessage> {
+        self.wait_for(timeout, RudpCore::poll_message)
+    }
+
+    /// Run `f` on the protocol core, for statistics and configuration
+    ///
+    /// Datagrams queued by `f` are sent before returning.
+    pub fn with_core<R>(&self, f: impl FnOnce(&mut RudpCore) -> R) -> R {
+        let mut core = self.shared.lock();
+        let result = f(&mut core);
+        let _ = self.shared.flush(&mut core);
+        result
+    }
+
+    /// Stop the maintenance thread and send close packets to all peers
+    ///
+    /// Also done on drop.
+    pub fn close(&mut self) {
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        self.shared.running.store(false, Ordering::Release);
+        let _ = worker.join();
+        let mut core = self.shared.lock();
+        let _ = self.shared.flush(&mut core);
+        core.close();
+        let _ = self.shared.flush(&mut core);
+    }
+
+    fn wait_for<T>(&self, timeout: Duration, mut poll: impl FnMut(&mut RudpCore) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut core = self.shared.lock();
+        loop {
+            if let Some(item) = poll(&mut core) {
+                return Some(item);
+            }
+            let remaining = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero())?;
+            core = self.shared.readable.wait_timeout(core, remaining).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+}
+
+impl Drop for Rudpbase {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, RudpCore> {
+        self.core.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Maintenance loop: run timers, then block on the socket until the next timer
+    fn run(&self) {
+        while self.running.load(Ordering::Acquire) {
+            let (buffer, wait) = {
+                let mut core = self.lock();
+                let now = Instant::now();
+                core.handle_timeout(now);
+                let _ = self.flush(&mut core);
+                let wait = core.poll_timeout().map_or(MAX_WAIT, |at| at.saturating_duration_since(now));
+                (core.get_buffer(), wait.clamp(MIN_WAIT, MAX_WAIT))
+            };
+
+            // The lock is not held while blocked, so sends proceed on other threads
+            let result = buffer.map_err(Some).and_then(|mut buffer| {
+                self.socket.set_read_timeout(Some(wait)).map_err(|e| Some(e.into()))?;
+                match self.socket.recv_from(buffer.raw_buffer_mut()) {
+                    Ok((len, from)) => Ok((buffer, len, from)),
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Err(None),
+                    Err(e) => Err(Some(e.into())),
+                }
+            });
+
+            let mut core = self.lock();
+            match result {
+                Ok((buffer, len, from)) => core.handle_buffer(buffer, len, from, Instant::now()),
+                Err(None) => continue,
+                Err(Some(e)) => {
+                    core.push_received(ReceivedData {
+                        from: "0.0.0.0:0".parse().unwrap(),
+                        seq: None,
+                        result: Err(e),
+                    });
+                    // Do not spin on a persistent error
+                    drop(core);
+                    thread::sleep(wait);
+                    self.readable.notify_all();
+                    continue;
+                }
+            }
+            if core.has_received() || core.has_messages() {
+                self.readable.notify_all();
+            }
+        }
+    }
+
+    /// Send every datagram the core has queued, returning the first error
+    fn flush(&self, core: &mut RudpCore) -> Result<(), RudpError> {
+        let mut result = Ok(());
+        for (data, target) in core.queued_transmits() {
+            if let Err(e) = self.socket.send_to(data, target) {
+                result = result.and(Err(e.into()));
+            }
+        }
+        core.clear_transmits();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(rudp: &Rudpbase, data: &[u8]) -> PooledBuffer {
+        let mut buffer = rudp.get_buffer().unwrap();
+        buffer.data_mut()[..data.len()].copy_from_slice(data);
+        buffer.set_data_len(data.len()).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_blocking_exchange() {
+        let a = Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let b = Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        a.send(payload(&a, b"hello"), b_addr).unwrap();
+        let received = b.recv_timeout(Duration::from_secs(2)).expect("no data received");
+        assert_eq!(received.from, a_addr);
+        assert_eq!(received.result.unwrap().data(), b"hello");
+
+        // The maintenance threads send and process the ACK without any tick()
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while a.with_core(|core| core.unacked_packets(b_addr)) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(a.with_core(|core| core.unacked_packets(b_addr)), 0);
+        assert!(a.try_recv().is_none());
+    }
+
+    #[test]
+    fn test_message_across_threads() {
+        let a = Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let b = Arc::new(Rudpbase::new("127.0.0.1:0".parse().unwrap()).unwrap());
+        let b_addr = b.local_addr().unwrap();
+
+        let receiver = {
+            let b = Arc::clone(&b);
+            thread::spawn(move || b.recv_message_timeout(Duration::from_secs(2)))
+        };
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        a.send_message(&data, b_addr).unwrap();
+
+        let message = receiver.join().unwrap().expect("no message received");
+        assert_eq!(message.data, data);
+    }
+}
@@ -0,0 +1,235 @@
+//! Background receive task
+//!
+//! [`spawn`] moves a [`Rudpbase`] into a task that reads the socket, handles control
+//! packets and timers, and acknowledges data as soon as it arrives. Received data
+//! and complete messages are handed over through bounded channels, taken with
+//! [`RudpReceiver::recv`] and [`RudpReceiver::recv_message`]. An application that
+//! is slow to process what it receives therefore does not delay acknowledgments
+//! and inflate its peers' RTT estimates.
+//!
+//! When a channel is full, received data waits in the instance's receive queue,
+//! bounded by `LimitsConfig::max_queued_packets` and `max_queued_messages`; beyond
+//! that, new data packets are left unacknowledged and retransmitted by the peer.
+//! Sends go through [`RudpSender`]. The task runs until every [`RudpSender`] and the
+//! [`RudpReceiver`] are dropped, then closes the instance.
+//!
+//! ```rust,no_run
+//! use rudpbase::task::{self, TaskOptions};
+//! use rudpbase::Rudpbase;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+//!     let (sender, mut receiver) = task::spawn(rudp, TaskOptions::default());
+//!     while let Some(received) = receiver.recv().await {
+//!         let buffer = received.result?;
+//!         // Slow processing here does not hold back ACKs
+//!         sender.send(buffer.data(), received.from).await?;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::core::Rudpbase;
+use crate::engine::ReceivedData;
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+
+/// Default number of received packets, and of messages, waiting in the channels
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Options of a receive task
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskOptions {
+    /// Received packets waiting for [`RudpReceiver::recv`], and messages waiting for
+    /// [`RudpReceiver::recv_message`]
+    pub channel_capacity: usize,
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        Self {
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+}
+
+/// Work handed to the driver task
+enum Command {
+    Send {
+        target: SocketAddr,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<u32, RudpError>>,
+    },
+    SendMessage {
+        target: SocketAddr,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), RudpError>>,
+    },
+}
+
+/// Start a receive task on `rudp`
+///
+/// Must be called within a tokio runtime; the driver task is spawned on it.
+pub fn spawn(rudp: Rudpbase, options: TaskOptions) -> (RudpSender, RudpReceiver) {
+    let (commands_tx, commands) = mpsc::unbounded_channel();
+    let (data_tx, data) = mpsc::channel(options.channel_capacity.max(1));
+    let (messages_tx, messages) = mpsc::channel(options.channel_capacity.max(1));
+    let driver = Driver {
+        rudp,
+        commands,
+        data: data_tx,
+        messages: messages_tx,
+    };
+    tokio::spawn(driver.run());
+    (
+        RudpSender { commands: commands_tx.clone() },
+        RudpReceiver {
+            data,
+            messages,
+            _commands: commands_tx,
+        },
+    )
+}
+
+/// Sends through a receive task; cheap to clone
+#[derive(Clone)]
+pub struct RudpSender {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl RudpSender {
+    /// Send `data` reliably to `target` in one packet, returning its sequence number
+    ///
+    /// Fails like [`Rudpbase::send`], and with `RudpError::Closing` once the task has
+    /// stopped.
+    pub async fn send(&self, data: &[u8], target: SocketAddr) -> Result<u32, RudpError> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::Send { target, data: data.to_vec(), reply })
+            .map_err(|_| RudpError::Closing)?;
+        result.await.unwrap_or(Err(RudpError::Closing))
+    }
+
+    /// Send `data` reliably to `target` as one message of any size
+    ///
+    /// Fails like [`Rudpbase::send_message`], and with `RudpError::Closing` once the
+    /// task has stopped.
+    pub async fn send_message(&self, data: &[u8], target: SocketAddr) -> Result<(), RudpError> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Command::SendMessage { target, data: data.to_vec(), reply })
+            .map_err(|_| RudpError::Closing)?;
+        result.await.unwrap_or(Err(RudpError::Closing))
+    }
+}
+
+/// Data and messages received by a receive task
+pub struct RudpReceiver {
+    data: mpsc::Receiver<ReceivedData>,
+    messages: mpsc::Receiver<ReceivedMessage>,
+    /// Keeps the driver running while data can still be taken
+    _commands: mpsc::UnboundedSender<Command>,
+}
+
+impl RudpReceiver {
+    /// Wait for the next data packet or receive error, as returned by
+    /// [`Rudpbase::recv`]; `None` once the task has stopped
+    pub async fn recv(&mut self) -> Option<ReceivedData> {
+        self.data.recv().await
+    }
+
+    /// Wait for the next complete message; `None` once the task has stopped
+    pub async fn recv_message(&mut self) -> Option<ReceivedMessage> {
+        self.messages.recv().await
+    }
+}
+
+/// Background task that owns the instance
+struct Driver {
+    rudp: Rudpbase,
+    commands: mpsc::UnboundedReceiver<Command>,
+    data: mpsc::Sender<ReceivedData>,
+    messages: mpsc::Sender<ReceivedMessage>,
+}
+
+impl Driver {
+    async fn run(mut self) {
+        loop {
+            self.hand_over();
+            let data_waiting = self.rudp.has_received(None) && !self.data.is_closed();
+            let messages_waiting = self.rudp.has_messages() && !self.messages.is_closed();
+            let command = tokio::select! {
+                biased;
+                command = self.commands.recv() => match command {
+                    Some(command) => Some(command),
+                    None => break,
+                },
+                // A full channel got room; the next hand_over() fills it
+                _ = self.data.reserve(), if data_waiting => None,
+                _ = self.messages.reserve(), if messages_waiting => None,
+                () = self.rudp.drive() => None,
+            };
+            if let Some(command) = command {
+                self.handle_command(command).await;
+            }
+        }
+        self.rudp.close().await;
+    }
+
+    /// Move received data and messages into the channels while they have room;
+    /// what nobody will take is dropped so it does not hold pool buffers
+    fn hand_over(&mut self) {
+        while self.rudp.has_received(None) {
+            match self.data.try_reserve() {
+                Ok(permit) => {
+                    if let Some(received) = self.rudp.poll_received() {
+                        permit.send(received);
+                    }
+                }
+                Err(TrySendError::Full(())) => break,
+                Err(TrySendError::Closed(())) => {
+                    self.rudp.poll_received();
+                }
+            }
+        }
+        while self.rudp.has_messages() {
+            match self.messages.try_reserve() {
+                Ok(permit) => {
+                    if let Some(message) = self.rudp.poll_message() {
+                        permit.send(message);
+                    }
+                }
+                Err(TrySendError::Full(())) => break,
+                Err(TrySendError::Closed(())) => {
+                    self.rudp.poll_message();
+                }
+            }
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Send { target, data, reply } => {
+                let result = self.send(&data, target).await;
+                let _ = reply.send(result);
+            }
+            Command::SendMessage { target, data, reply } => {
+                let result = self.rudp.send_message(&data, target).await;
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    async fn send(&mut self, data: &[u8], target: SocketAddr) -> Result<u32, RudpError> {
+        let mut buffer = self.rudp.get_buffer_for(data.len())?;
+        buffer.data_mut()[..data.len()].copy_from_slice(data);
+        buffer.set_data_len(data.len())?;
+        self.rudp.send(buffer, target).await
+    }
+}
@@ -0,0 +1,446 @@
+//! TCP fallback for networks that block UDP
+//!
+//! [`TcpTransport`] carries rudpbase datagrams over TCP connections, each one framed
+//! with a 2-byte length prefix, so the packet format and everything above the
+//! [`Transport`] stay the same. [`FallbackTransport`] sends over UDP and switches a
+//! peer to TCP when it has not answered over UDP within
+//! [`FallbackConfig::udp_timeout`], or as soon as the peer reaches us over TCP:
+//!
+//! ```rust,no_run
+//! use rudpbase::tcp::{FallbackConfig, FallbackTransport};
+//! use rudpbase::{RudpConfig, Rudpbase};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let transport = FallbackTransport::bind("0.0.0.0:8080".parse()?, FallbackConfig::default()).await?;
+//!     let mut rudp = Rudpbase::with_transport(transport, RudpConfig::default()).await?;
+//!     rudp.send_message(b"hello", "203.0.113.5:8080".parse()?).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The TCP listener uses the same port number as the UDP socket, and a peer's TCP
+//! connections are keyed by the address of its UDP socket: each connection starts
+//! with a hello frame naming the port the connecting side listens on. Reliability
+//! and retransmission still come from rudpbase, which costs some duplicate work over
+//! TCP but keeps a single protocol. Connections are plain TCP; encryption is left to
+//! the application or to a custom [`Transport`].
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+
+use crate::config::SocketConfig;
+use crate::socket;
+use crate::transport::Transport;
+
+/// Datagrams queued per connection or in the receive queue before further ones are
+/// dropped
+pub const TCP_QUEUE_CAPACITY: usize = 4096;
+
+/// First frame of every connection: this magic followed by the sender's port
+const HELLO: &[u8; 4] = b"RUDP";
+
+/// Time an accepted connection has to send its hello frame
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings of a [`FallbackTransport`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallbackConfig {
+    /// Time a peer may leave UDP unanswered before datagrams to it also go over TCP
+    pub udp_timeout: Duration,
+    /// Most TCP connections open at once; further ones are refused
+    pub max_connections: usize,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            udp_timeout: Duration::from_secs(2),
+            max_connections: 1024,
+        }
+    }
+}
+
+/// Receive queue shared by all connections
+#[derive(Default)]
+struct Inbox {
+    datagrams: VecDeque<(Vec<u8>, SocketAddr)>,
+    waker: Option<Waker>,
+}
+
+/// One TCP connection to a peer
+struct Connection {
+    id: u64,
+    sender: mpsc::Sender<Vec<u8>>,
+    /// Set once the connection is up and the hello frame is out
+    established: Arc<AtomicBool>,
+    task: AbortHandle,
+}
+
+struct Shared {
+    local_port: u16,
+    max_connections: usize,
+    inbox: Mutex<Inbox>,
+    /// Open and connecting connections by peer; simultaneous connects leave a peer
+    /// with more than one
+    connections: Mutex<HashMap<SocketAddr, Vec<Connection>>>,
+    next_id: AtomicU64,
+    /// Set when the transport is dropped; handshakes still running register nothing
+    closed: AtomicBool,
+}
+
+impl Shared {
+    fn deliver(&self, datagram: Vec<u8>, from: SocketAddr) {
+        let mut inbox = self.inbox.lock().unwrap();
+        if inbox.datagrams.len() >= TCP_QUEUE_CAPACITY {
+            return;
+        }
+        inbox.datagrams.push_back((datagram, from));
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Record a new connection to `peer` served by the task `run` spawns, unless the
+    /// connection limit is reached
+    fn register<F>(self: &Arc<Self>, peer: SocketAddr, established: bool, run: F) -> Option<mpsc::Sender<Vec<u8>>>
+    where
+        F: FnOnce(Arc<Self>, u64, mpsc::Receiver<Vec<u8>>, Arc<AtomicBool>) -> tokio::task::JoinHandle<()>,
+    {
+        let mut connections = self.connections.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) || connections.values().map(Vec::len).sum::<usize>() >= self.max_connections {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(TCP_QUEUE_CAPACITY);
+        let established = Arc::new(AtomicBool::new(established));
+        // The task removes itself under this lock, so it is registered before that
+        let task = run(Arc::clone(self), id, receiver, Arc::clone(&established)).abort_handle();
+        connections.entry(peer).or_default().push(Connection {
+            id,
+            sender: sender.clone(),
+            established,
+            task,
+        });
+        Some(sender)
+    }
+
+    fn remove(&self, peer: SocketAddr, id: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Entry::Occupied(mut entry) = connections.entry(peer) {
+            entry.get_mut().retain(|connection| connection.id != id);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// Datagram transport over TCP connections
+///
+/// Sending to a peer without a connection opens one to the same address over TCP;
+/// datagrams queue while it connects and are dropped, like UDP datagrams, if it
+/// fails or the queue is full. Incoming connections are accepted on the local
+/// address. Must be used inside a tokio runtime.
+pub struct TcpTransport {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    accept: AbortHandle,
+}
+
+impl TcpTransport {
+    /// Listen for connections on `addr`, with at most `max_connections` open at once
+    pub async fn bind(addr: SocketAddr, max_connections: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            local_port: local_addr.port(),
+            max_connections,
+            inbox: Mutex::new(Inbox::default()),
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        });
+        let accept = tokio::spawn(accept_loop(listener, Arc::clone(&shared))).abort_handle();
+        Ok(Self { local_addr, shared, accept })
+    }
+
+    /// Whether an established connection to `peer` is open
+    pub fn is_connected(&self, peer: SocketAddr) -> bool {
+        self.shared
+            .connections
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .is_some_and(|connections| connections.iter().any(|connection| connection.established.load(Ordering::Relaxed)))
+    }
+
+    /// Number of open and connecting connections
+    pub fn connection_count(&self) -> usize {
+        self.shared.connections.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    fn send(&self, datagram: &[u8], target: SocketAddr) {
+        let sender = {
+            let connections = self.shared.connections.lock().unwrap();
+            connections.get(&target).and_then(|connections| {
+                connections
+                    .iter()
+                    .find(|connection| connection.established.load(Ordering::Relaxed))
+                    .or_else(|| connections.first())
+                    .map(|connection| connection.sender.clone())
+            })
+        };
+        let sender = sender.or_else(|| {
+            self.shared.register(target, false, |shared, id, receiver, established| {
+                tokio::spawn(connect(shared, target, id, receiver, established))
+            })
+        });
+        if let Some(sender) = sender {
+            // A full queue drops the datagram, as a congested UDP path would
+            let _ = sender.try_send(datagram.to_vec());
+        }
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.shared.closed.store(true, Ordering::Relaxed);
+        for connection in self.shared.connections.lock().unwrap().drain().flat_map(|(_, connections)| connections) {
+            connection.task.abort();
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn poll_send_to(&self, _cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        if buf.len() > u16::MAX as usize {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidInput, "datagram too large for a TCP frame")));
+        }
+        self.send(buf, target);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut inbox = self.shared.inbox.lock().unwrap();
+        match inbox.datagrams.pop_front() {
+            Some((datagram, from)) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Poll::Ready(Ok((len, from)))
+            }
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        let Ok((stream, remote)) = listener.accept().await else {
+            // Out of file descriptors and the like; retry shortly instead of spinning
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            continue;
+        };
+        tokio::spawn(accept(stream, remote, Arc::clone(&shared)));
+    }
+}
+
+/// Read the hello frame of an accepted connection and start serving it
+async fn accept(mut stream: TcpStream, remote: SocketAddr, shared: Arc<Shared>) {
+    let Ok(Ok(hello)) = tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut stream)).await else {
+        return;
+    };
+    let (Some(magic), Some(port)) = (hello.get(..HELLO.len()), hello.get(HELLO.len()..)) else {
+        return;
+    };
+    let Ok(port) = <[u8; 2]>::try_from(port) else {
+        return;
+    };
+    if magic != HELLO {
+        return;
+    }
+    let peer = SocketAddr::new(remote.ip(), u16::from_be_bytes(port));
+    shared.register(peer, true, |shared, id, receiver, _| {
+        tokio::spawn(async move {
+            let _ = serve(stream, peer, &shared, receiver).await;
+            shared.remove(peer, id);
+        })
+    });
+}
+
+/// Open a connection to `peer` and serve it
+async fn connect(shared: Arc<Shared>, peer: SocketAddr, id: u64, receiver: mpsc::Receiver<Vec<u8>>, established: Arc<AtomicBool>) {
+    let result = async {
+        let mut stream = TcpStream::connect(peer).await?;
+        let mut hello = HELLO.to_vec();
+        hello.extend_from_slice(&shared.local_port.to_be_bytes());
+        write_frame(&mut stream, &hello).await?;
+        established.store(true, Ordering::Relaxed);
+        serve(stream, peer, &shared, receiver).await
+    };
+    let _ = result.await;
+    shared.remove(peer, id);
+}
+
+/// Pass frames in both directions until either fails or the transport is dropped
+async fn serve(stream: TcpStream, peer: SocketAddr, shared: &Shared, mut outgoing: mpsc::Receiver<Vec<u8>>) -> io::Result<()> {
+    let _ = stream.set_nodelay(true);
+    let (mut reader, mut writer) = stream.into_split();
+    let read = async {
+        loop {
+            let datagram = read_frame(&mut reader).await?;
+            shared.deliver(datagram, peer);
+        }
+    };
+    let write = async {
+        while let Some(datagram) = outgoing.recv().await {
+            write_frame(&mut writer, &datagram).await?;
+        }
+        Ok(())
+    };
+    tokio::select! {
+        result = read => result,
+        result = write => result,
+    }
+}
+
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let len = reader.read_u16().await? as usize;
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> io::Result<()> {
+    // One write per frame, so the length and the data leave in the same segment
+    let mut frame = Vec::with_capacity(2 + data.len());
+    frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    frame.extend_from_slice(data);
+    writer.write_all(&frame).await
+}
+
+/// UDP reachability of one peer
+struct UdpPath {
+    first_sent: Instant,
+    answered: bool,
+}
+
+/// UDP transport that falls back to TCP per peer
+///
+/// Datagrams go over UDP until the peer is found unreachable there: nothing has
+/// arrived from it over UDP `udp_timeout` after the first datagram was sent to it.
+/// From then on they also go over TCP, and only over TCP once the connection is
+/// established. A peer that connects to us over TCP is answered over TCP. Batched
+/// I/O and UDP offload are not available through this transport.
+pub struct FallbackTransport {
+    udp: UdpSocket,
+    tcp: TcpTransport,
+    config: FallbackConfig,
+    /// Peers we sent to over UDP, bounded by `max_connections`
+    paths: Mutex<HashMap<SocketAddr, UdpPath>>,
+    /// Alternates which transport is polled first, so neither starves the other
+    tcp_first: AtomicBool,
+}
+
+impl FallbackTransport {
+    /// Bind a UDP socket and a TCP listener on the same address
+    ///
+    /// With port 0 the TCP listener takes the port the system gave the UDP socket.
+    pub async fn bind(addr: SocketAddr, config: FallbackConfig) -> io::Result<Self> {
+        let udp = socket::bind(addr, &SocketConfig::default())?;
+        let tcp = TcpTransport::bind(udp.local_addr()?, config.max_connections).await?;
+        Ok(Self {
+            udp,
+            tcp,
+            config,
+            paths: Mutex::new(HashMap::new()),
+            tcp_first: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether datagrams to `peer` currently go over TCP
+    pub fn uses_tcp(&self, peer: SocketAddr) -> bool {
+        self.tcp.is_connected(peer)
+    }
+
+    /// The TCP side of the transport
+    pub fn tcp(&self) -> &TcpTransport {
+        &self.tcp
+    }
+
+    /// Whether `target` has left UDP unanswered for too long
+    fn udp_failed(&self, target: SocketAddr) -> bool {
+        let now = Instant::now();
+        let mut paths = self.paths.lock().unwrap();
+        if !paths.contains_key(&target) && paths.len() >= self.config.max_connections {
+            // Peers that answered are relearned on their next datagram
+            paths.retain(|_, path| !path.answered);
+            if paths.len() >= self.config.max_connections {
+                return false;
+            }
+        }
+        let path = paths.entry(target).or_insert(UdpPath { first_sent: now, answered: false });
+        !path.answered && now.saturating_duration_since(path.first_sent) >= self.config.udp_timeout
+    }
+
+    fn poll_udp(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let result = Transport::poll_recv_from(&self.udp, cx, buf);
+        if let Poll::Ready(Ok((_, from))) = &result {
+            if let Some(path) = self.paths.lock().unwrap().get_mut(from) {
+                path.answered = true;
+            }
+        }
+        result
+    }
+}
+
+impl Transport for FallbackTransport {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        if self.tcp.is_connected(target) {
+            return self.tcp.poll_send_to(cx, buf, target);
+        }
+        if self.udp_failed(target) {
+            // Keep UDP going while the connection is set up
+            let _ = self.tcp.poll_send_to(cx, buf, target);
+        }
+        Transport::poll_send_to(&self.udp, cx, buf, target)
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        // Both are polled when nothing is ready, so both wakers are registered
+        if self.tcp_first.fetch_xor(true, Ordering::Relaxed) {
+            if let Poll::Ready(result) = self.tcp.poll_recv_from(cx, buf) {
+                return Poll::Ready(result);
+            }
+            self.poll_udp(cx, buf)
+        } else {
+            if let Poll::Ready(result) = self.poll_udp(cx, buf) {
+                return Poll::Ready(result);
+            }
+            self.tcp.poll_recv_from(cx, buf)
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+}
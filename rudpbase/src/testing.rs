@@ -0,0 +1,1018 @@
+//! Scripted network scenarios for adversarial testing
+//!
+//! A [`Scenario`] is a sequence of phases, each applying [`Conditions`] (loss,
+//! latency, jitter, duplication or a full partition) to the link between two
+//! in-memory protocol cores for a given time. [`Scenario::run`] replays it on a
+//! virtual clock: the sender sends a numbered packet every traffic interval while the
+//! phases last, then the link is cleared and both sides run until every packet is
+//! settled or the settle period is over. The [`Outcome`] lists each broken
+//! delivery or statistics invariant as a [`Violation`].
+//!
+//! Nothing waits in real time, so a scenario lasting minutes runs in milliseconds,
+//! and a scenario with the same seed always makes the same decisions.
+//!
+//! A [`BottleneckScenario`] instead runs bulk [`Flow`]s through one [`Bottleneck`]
+//! link of limited rate and queue, optionally dropping early with [`Red`], to compare
+//! how congestion control configurations use and share it.
+//!
+//! ```rust
+//! use rudpbase::testing::{Bottleneck, BottleneckScenario, Flow, Scenario};
+//! use rudpbase::RudpConfig;
+//! use std::time::Duration;
+//!
+//! let outcome = Scenario::new()
+//!     .loss(0.3, Duration::from_secs(5))
+//!     .latency(Duration::from_millis(200), Duration::from_secs(5))
+//!     .partition(Duration::from_secs(2))
+//!     .run(RudpConfig::default())
+//!     .unwrap();
+//! outcome.assert_invariants();
+//!
+//! let outcome = BottleneckScenario::new(Bottleneck::new(1_000_000, 64 * 1024, Duration::from_millis(20)))
+//!     .flow(Flow::new(RudpConfig::default()))
+//!     .flow(Flow::new(RudpConfig::default()))
+//!     .run()
+//!     .unwrap();
+//! println!("utilization {:.2}, fairness {:.2}", outcome.utilization(), outcome.fairness());
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::RudpConfig;
+use crate::delivery::DeliveryHandle;
+use crate::engine::RudpCore;
+use crate::error::{ConnectionError, RudpError};
+use crate::rng::SplitMix64;
+use crate::stats::ConnectionStats;
+
+/// Smallest step of the virtual clock, so timers that are already due cannot stall it
+const MIN_STEP: Duration = Duration::from_millis(1);
+
+/// Impairments of the link during one phase, applied to both directions
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Conditions {
+    /// Probability in `[0, 1]` that a datagram is dropped
+    pub loss: f64,
+    /// One-way delay of every datagram
+    pub latency: Duration,
+    /// Extra delay drawn uniformly from `[0, jitter]` per datagram, which reorders them
+    pub jitter: Duration,
+    /// Probability in `[0, 1]` that a datagram is delivered twice
+    pub duplicate: f64,
+    /// Drop every datagram
+    pub partitioned: bool,
+}
+
+/// Conditions holding for a fixed time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Phase {
+    pub duration: Duration,
+    pub conditions: Conditions,
+}
+
+/// Network conditions over time, and the traffic sent through them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    phases: Vec<Phase>,
+    send_interval: Duration,
+    payload_size: usize,
+    settle: Duration,
+    seed: u64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scenario {
+    /// No phases, a 64-byte packet every 50ms and up to 60s to settle
+    pub fn new() -> Self {
+        Self {
+            phases: Vec::new(),
+            send_interval: Duration::from_millis(50),
+            payload_size: 64,
+            settle: Duration::from_secs(60),
+            seed: 0,
+        }
+    }
+
+    /// Append a phase with arbitrary conditions
+    pub fn phase(mut self, duration: Duration, conditions: Conditions) -> Self {
+        self.phases.push(Phase { duration, conditions });
+        self
+    }
+
+    /// Append a phase of a perfect link
+    pub fn clear(self, duration: Duration) -> Self {
+        self.phase(duration, Conditions::default())
+    }
+
+    /// Append a phase dropping each datagram with probability `loss`
+    pub fn loss(self, loss: f64, duration: Duration) -> Self {
+        self.phase(duration, Conditions { loss, ..Conditions::default() })
+    }
+
+    /// Append a phase delaying every datagram by `latency`
+    pub fn latency(self, latency: Duration, duration: Duration) -> Self {
+        self.phase(duration, Conditions { latency, ..Conditions::default() })
+    }
+
+    /// Append a phase in which nothing gets through
+    pub fn partition(self, duration: Duration) -> Self {
+        self.phase(duration, Conditions { partitioned: true, ..Conditions::default() })
+    }
+
+    /// Send a packet of `payload_size` bytes every `interval` while the phases last
+    ///
+    /// `payload_size` is at least 4, for the packet number, and must fit in one packet.
+    pub fn traffic(mut self, interval: Duration, payload_size: usize) -> Self {
+        self.send_interval = interval;
+        self.payload_size = payload_size.max(4);
+        self
+    }
+
+    /// Longest time to run on a perfect link after the last phase
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Seed of the loss, jitter and duplication decisions
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Phases in order
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    /// Total length of the phases
+    pub fn duration(&self) -> Duration {
+        self.phases.iter().map(|phase| phase.duration).sum()
+    }
+
+    /// Replay the scenario between two cores created with `config`
+    pub fn run(&self, config: RudpConfig) -> Result<Outcome, RudpError> {
+        Harness::new(self, config.clone(), config)?.run()
+    }
+
+    /// Replay the scenario with separate sender and receiver configurations
+    pub fn run_with(&self, sender_config: RudpConfig, receiver_config: RudpConfig) -> Result<Outcome, RudpError> {
+        Harness::new(self, sender_config, receiver_config)?.run()
+    }
+
+    /// Conditions `elapsed` after the start; a perfect link after the last phase
+    fn conditions_at(&self, elapsed: Duration) -> Conditions {
+        let mut end = Duration::ZERO;
+        for phase in &self.phases {
+            end += phase.duration;
+            if elapsed < end {
+                return phase.conditions;
+            }
+        }
+        Conditions::default()
+    }
+}
+
+/// Datagrams handled by the simulated link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Datagrams put on the link by either side
+    pub sent: u64,
+    /// Datagrams dropped by loss or a partition
+    pub dropped: u64,
+    /// Extra copies delivered
+    pub duplicated: u64,
+}
+
+/// A broken invariant
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A packet reached the receiver more than once
+    Duplicated { seq: u32, count: usize },
+    /// A packet reached the receiver with other contents than were sent
+    Corrupted { seq: u32 },
+    /// The receiver got a packet the sender never sent
+    Unexpected { seq: Option<u32> },
+    /// The sender was told the packet was acknowledged, but it never arrived
+    AckedNotDelivered { seq: u32 },
+    /// The packet was neither acknowledged nor given up on by the end of the run
+    Unsettled { seq: u32 },
+    /// A statistics counter disagrees with the traffic the harness observed
+    Stats { counter: &'static str, expected: u64, actual: u64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Duplicated { seq, count } => write!(f, "packet {} delivered {} times", seq, count),
+            Violation::Corrupted { seq } => write!(f, "packet {} delivered with wrong contents", seq),
+            Violation::Unexpected { seq: Some(seq) } => write!(f, "packet {} delivered but never sent", seq),
+            Violation::Unexpected { seq: None } => write!(f, "packet without sequence number delivered"),
+            Violation::AckedNotDelivered { seq } => write!(f, "packet {} acknowledged but never delivered", seq),
+            Violation::Unsettled { seq } => write!(f, "packet {} still unacknowledged", seq),
+            Violation::Stats { counter, expected, actual } => {
+                write!(f, "{} is {}, expected {}", counter, actual, expected)
+            }
+        }
+    }
+}
+
+/// Result of replaying a scenario
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// Packets accepted by `send_tracked()`
+    pub sent: u64,
+    /// Sends rejected, e.g. because the send queue was full
+    pub rejected: u64,
+    /// Distinct packets that reached the receiver
+    pub delivered: u64,
+    /// Packets acknowledged to the sender
+    pub acked: u64,
+    /// Packets the sender gave up on
+    pub failed: u64,
+    /// Whether either side declared the connection dead during the run, which resets
+    /// its statistics; the statistics invariants are only checked if not
+    pub connection_lost: bool,
+    /// Virtual time from the start until the run ended
+    pub elapsed: Duration,
+    pub link: LinkStats,
+    /// Sender's statistics of the receiver at the end, if it still had a connection
+    pub sender_stats: Option<ConnectionStats>,
+    /// Receiver's statistics of the sender at the end, if it still had a connection
+    pub receiver_stats: Option<ConnectionStats>,
+    pub violations: Vec<Violation>,
+}
+
+impl Outcome {
+    /// Whether every invariant held
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Panic listing every broken invariant
+    #[track_caller]
+    pub fn assert_invariants(&self) {
+        if self.is_ok() {
+            return;
+        }
+        let violations: Vec<String> = self.violations.iter().map(ToString::to_string).collect();
+        panic!("{} invariant violations:\n  {}", violations.len(), violations.join("\n  "));
+    }
+}
+
+/// A packet sent by the harness and what became of it
+struct SentPacket {
+    payload: Vec<u8>,
+    handle: DeliveryHandle,
+    result: Option<Result<(), RudpError>>,
+    deliveries: usize,
+    corrupted: bool,
+}
+
+/// A datagram on the simulated link
+struct InFlight {
+    arrival: Instant,
+    from: SocketAddr,
+    to: SocketAddr,
+    contents: Vec<u8>,
+}
+
+/// Remove the datagrams whose arrival time has come, in arrival order
+fn take_due(in_flight: &mut Vec<InFlight>, now: Instant) -> Vec<InFlight> {
+    let mut due = Vec::new();
+    let mut index = 0;
+    while index < in_flight.len() {
+        if in_flight[index].arrival <= now {
+            due.push(in_flight.swap_remove(index));
+        } else {
+            index += 1;
+        }
+    }
+    due.sort_by_key(|datagram| datagram.arrival);
+    due
+}
+
+/// Two cores connected through a link following a scenario
+struct Harness<'a> {
+    scenario: &'a Scenario,
+    sender: RudpCore,
+    receiver: RudpCore,
+    sender_addr: SocketAddr,
+    receiver_addr: SocketAddr,
+    start: Instant,
+    now: Instant,
+    rng: SplitMix64,
+    in_flight: Vec<InFlight>,
+    packets: HashMap<u32, SentPacket>,
+    unexpected: Vec<Option<u32>>,
+    rejected: u64,
+    connection_lost: bool,
+    link: LinkStats,
+}
+
+impl<'a> Harness<'a> {
+    fn new(scenario: &'a Scenario, sender_config: RudpConfig, receiver_config: RudpConfig) -> Result<Self, RudpError> {
+        let start = Instant::now();
+        Ok(Self {
+            scenario,
+            sender: RudpCore::new(sender_config)?,
+            receiver: RudpCore::new(receiver_config)?,
+            sender_addr: "10.0.0.1:1".parse().unwrap(),
+            receiver_addr: "10.0.0.2:1".parse().unwrap(),
+            start,
+            now: start,
+            rng: SplitMix64(scenario.seed),
+            in_flight: Vec::new(),
+            packets: HashMap::new(),
+            unexpected: Vec::new(),
+            rejected: 0,
+            connection_lost: false,
+            link: LinkStats::default(),
+        })
+    }
+
+    fn run(mut self) -> Result<Outcome, RudpError> {
+        let traffic_end = self.start + self.scenario.duration();
+        let deadline = traffic_end + self.scenario.settle;
+        let mut next_send = self.start;
+        loop {
+            self.deliver_due();
+            if self.now >= next_send && self.now < traffic_end {
+                self.send_next()?;
+                next_send += self.scenario.send_interval.max(MIN_STEP);
+            }
+            self.sender.handle_timeout(self.now);
+            self.receiver.handle_timeout(self.now);
+            self.transmit();
+            self.collect();
+
+            let settled = self.packets.values().all(|packet| packet.result.is_some());
+            if self.now >= deadline || (self.now >= traffic_end && settled && self.in_flight.is_empty()) {
+                break;
+            }
+            let next = [
+                self.in_flight.iter().map(|datagram| datagram.arrival).min(),
+                self.sender.poll_timeout(),
+                self.receiver.poll_timeout(),
+                (next_send < traffic_end).then_some(next_send),
+                Some(self.next_phase_boundary()),
+                Some(deadline),
+            ];
+            let next = next.into_iter().flatten().min().unwrap_or(deadline);
+            self.now = next.max(self.now + MIN_STEP);
+        }
+        Ok(self.outcome())
+    }
+
+    /// Start of the next phase, or the end of the last one
+    fn next_phase_boundary(&self) -> Instant {
+        let mut boundary = self.start;
+        for phase in self.scenario.phases() {
+            boundary += phase.duration;
+            if boundary > self.now {
+                return boundary;
+            }
+        }
+        boundary
+    }
+
+    /// Send the next numbered packet from the sender
+    fn send_next(&mut self) -> Result<(), RudpError> {
+        let number = self.packets.len() as u32 + self.rejected as u32;
+        let payload: Vec<u8> = number
+            .to_be_bytes()
+            .into_iter()
+            .chain((0..self.scenario.payload_size - 4).map(|index| (number as usize + index) as u8))
+            .collect();
+        let mut buffer = self.sender.get_buffer_for(payload.len())?;
+        buffer.data_mut()[..payload.len()].copy_from_slice(&payload);
+        buffer.set_data_len(payload.len())?;
+        match self.sender.send_tracked(buffer, self.receiver_addr, self.now) {
+            Ok(handle) => {
+                let packet = SentPacket { payload, handle, result: None, deliveries: 0, corrupted: false };
+                self.packets.insert(packet.handle.seq(), packet);
+            }
+            Err(_) => self.rejected += 1,
+        }
+        Ok(())
+    }
+
+    /// Hand datagrams whose arrival time has come to their destination
+    fn deliver_due(&mut self) {
+        for datagram in take_due(&mut self.in_flight, self.now) {
+            let to = if datagram.to == self.receiver_addr { &mut self.receiver } else { &mut self.sender };
+            to.handle_datagram(&datagram.contents, datagram.from, self.now);
+        }
+    }
+
+    /// Put the datagrams both sides want to send on the link
+    fn transmit(&mut self) {
+        let conditions = self.scenario.conditions_at(self.now.duration_since(self.start));
+        let mut outgoing = Vec::new();
+        while let Some(transmit) = self.sender.poll_transmit() {
+            outgoing.push((self.sender_addr, transmit.destination, transmit.contents));
+        }
+        while let Some(transmit) = self.receiver.poll_transmit() {
+            outgoing.push((self.receiver_addr, transmit.destination, transmit.contents));
+        }
+        for (from, to, contents) in outgoing {
+            self.link.sent += 1;
+            if conditions.partitioned || self.rng.chance(conditions.loss) {
+                self.link.dropped += 1;
+                continue;
+            }
+            if self.rng.chance(conditions.duplicate) {
+                self.link.duplicated += 1;
+                let arrival = self.arrival(&conditions);
+                self.in_flight.push(InFlight { arrival, from, to, contents: contents.clone() });
+            }
+            let arrival = self.arrival(&conditions);
+            self.in_flight.push(InFlight { arrival, from, to, contents });
+        }
+    }
+
+    fn arrival(&mut self, conditions: &Conditions) -> Instant {
+        self.now + conditions.latency + conditions.jitter.mul_f64(self.rng.next_f64())
+    }
+
+    /// Record delivered packets, settled handles and lost connections
+    fn collect(&mut self) {
+        while let Some(received) = self.receiver.poll_received() {
+            match received.result {
+                Ok(buffer) => match received.seq.and_then(|seq| self.packets.get_mut(&seq)) {
+                    Some(packet) => {
+                        packet.deliveries += 1;
+                        packet.corrupted |= buffer.data() != packet.payload.as_slice();
+                    }
+                    None => self.unexpected.push(received.seq),
+                },
+                Err(error) => self.connection_lost |= is_dead(&error),
+            }
+        }
+        while let Some(received) = self.sender.poll_received() {
+            if let Err(error) = received.result {
+                self.connection_lost |= is_dead(&error);
+            }
+        }
+        for packet in self.packets.values_mut().filter(|packet| packet.result.is_none()) {
+            packet.result = packet.handle.try_result();
+        }
+    }
+
+    fn outcome(self) -> Outcome {
+        let mut violations = Vec::new();
+        let mut seqs: Vec<u32> = self.packets.keys().copied().collect();
+        seqs.sort_unstable();
+        let (mut delivered, mut acked, mut failed) = (0, 0, 0);
+        let (mut delivered_bytes, mut acked_bytes, mut sent_bytes) = (0u64, 0u64, 0u64);
+        for seq in seqs {
+            let packet = &self.packets[&seq];
+            sent_bytes += packet.payload.len() as u64;
+            if packet.deliveries > 0 {
+                delivered += 1;
+                delivered_bytes += packet.payload.len() as u64;
+            }
+            if packet.deliveries > 1 {
+                violations.push(Violation::Duplicated { seq, count: packet.deliveries });
+            }
+            if packet.corrupted {
+                violations.push(Violation::Corrupted { seq });
+            }
+            match &packet.result {
+                Some(Ok(())) => {
+                    acked += 1;
+                    acked_bytes += packet.payload.len() as u64;
+                    if packet.deliveries == 0 {
+                        violations.push(Violation::AckedNotDelivered { seq });
+                    }
+                }
+                Some(Err(_)) => failed += 1,
+                None => violations.push(Violation::Unsettled { seq }),
+            }
+        }
+        violations.extend(self.unexpected.iter().map(|seq| Violation::Unexpected { seq: *seq }));
+
+        let sender_stats = self.sender.get_stats(self.receiver_addr);
+        let receiver_stats = self.receiver.get_stats(self.sender_addr);
+        if !self.connection_lost {
+            let mut check = |counter, expected, actual: Option<u64>| {
+                let actual = actual.unwrap_or(0);
+                if actual != expected {
+                    violations.push(Violation::Stats { counter, expected, actual });
+                }
+            };
+            check("sender bytes_sent", sent_bytes, sender_stats.as_ref().map(|stats| stats.bytes_sent));
+            check("sender bytes_acked", acked_bytes, sender_stats.as_ref().map(|stats| stats.bytes_acked));
+            check("receiver bytes_received", delivered_bytes, receiver_stats.as_ref().map(|stats| stats.bytes_received));
+        }
+
+        Outcome {
+            sent: self.packets.len() as u64,
+            rejected: self.rejected,
+            delivered,
+            acked,
+            failed,
+            connection_lost: self.connection_lost,
+            elapsed: self.now.duration_since(self.start),
+            link: self.link,
+            sender_stats,
+            receiver_stats,
+            violations,
+        }
+    }
+}
+
+/// Random early detection on a [`Bottleneck`] queue
+///
+/// Arriving datagrams are dropped with a probability growing linearly from 0 to
+/// `max_probability` as the average queue grows from `min_threshold` to
+/// `max_threshold` bytes, and always above it, so senders see loss before the queue
+/// overflows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Red {
+    /// Average queue in bytes below which nothing is dropped early
+    pub min_threshold: usize,
+    /// Average queue in bytes from which every arrival is dropped
+    pub max_threshold: usize,
+    /// Drop probability just below `max_threshold`
+    pub max_probability: f64,
+    /// Weight of each sample in the average queue
+    pub weight: f64,
+}
+
+impl Red {
+    /// Thresholds at a quarter and three quarters of a queue of `queue_limit` bytes,
+    /// with an average following the queue over a few dozen datagrams
+    pub fn for_queue(queue_limit: usize) -> Self {
+        Self {
+            min_threshold: queue_limit / 4,
+            max_threshold: queue_limit * 3 / 4,
+            max_probability: 0.1,
+            weight: 0.02,
+        }
+    }
+
+    fn drop_probability(&self, average: f64) -> f64 {
+        if average < self.min_threshold as f64 {
+            0.0
+        } else if average >= self.max_threshold as f64 {
+            1.0
+        } else {
+            let range = (self.max_threshold - self.min_threshold).max(1) as f64;
+            self.max_probability * (average - self.min_threshold as f64) / range
+        }
+    }
+}
+
+/// A link of limited rate in front of a FIFO queue
+///
+/// Datagrams from the senders of a [`BottleneckScenario`] are serialized onto the link
+/// one at a time at `rate`; those arriving while the queue holds `queue_limit` bytes
+/// are dropped. Acknowledgements travel back on an uncongested path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bottleneck {
+    /// Link rate in bytes per second
+    pub rate: u64,
+    /// Queue capacity in bytes, including the datagram being transmitted
+    pub queue_limit: usize,
+    /// Early drops, or only drops of a full queue if `None`
+    pub red: Option<Red>,
+    /// One-way propagation delay, in both directions
+    pub delay: Duration,
+}
+
+impl Bottleneck {
+    /// A drop-tail link
+    pub fn new(rate: u64, queue_limit: usize, delay: Duration) -> Self {
+        Self { rate, queue_limit, red: None, delay }
+    }
+
+    /// Drop early with [`Red::for_queue`]
+    pub fn with_red(mut self) -> Self {
+        self.red = Some(Red::for_queue(self.queue_limit));
+        self
+    }
+
+    /// Time to put `bytes` on the link
+    fn serialization(&self, bytes: usize) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / self.rate.max(1) as f64)
+    }
+}
+
+/// A bulk transfer through the bottleneck, sending as fast as its congestion window allows
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub config: RudpConfig,
+    /// When the flow starts sending, from the start of the run
+    pub start: Duration,
+    pub payload_size: usize,
+}
+
+impl Flow {
+    /// A flow of 1024-byte packets starting immediately
+    pub fn new(config: RudpConfig) -> Self {
+        Self { config, start: Duration::ZERO, payload_size: 1024 }
+    }
+
+    /// Start sending `start` after the beginning of the run
+    pub fn starting_at(mut self, start: Duration) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn with_payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+}
+
+/// Flows competing for one bottleneck link
+///
+/// Each flow has its own sender and receiver core; all data crosses the bottleneck,
+/// so the flows' congestion control decides how they share it.
+#[derive(Debug, Clone)]
+pub struct BottleneckScenario {
+    bottleneck: Bottleneck,
+    flows: Vec<Flow>,
+    duration: Duration,
+    seed: u64,
+}
+
+impl BottleneckScenario {
+    /// No flows, running for 10s
+    pub fn new(bottleneck: Bottleneck) -> Self {
+        Self { bottleneck, flows: Vec::new(), duration: Duration::from_secs(10), seed: 0 }
+    }
+
+    pub fn flow(mut self, flow: Flow) -> Self {
+        self.flows.push(flow);
+        self
+    }
+
+    /// How long the flows send
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Seed of the early drop decisions
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn run(&self) -> Result<BottleneckOutcome, RudpError> {
+        BottleneckHarness::new(self)?.run()
+    }
+}
+
+/// Datagrams handled by the bottleneck
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BottleneckStats {
+    /// Datagrams put on the link
+    pub forwarded: u64,
+    /// Bytes put on the link, including headers and retransmissions
+    pub forwarded_bytes: u64,
+    /// Datagrams dropped because the queue was full
+    pub tail_drops: u64,
+    /// Datagrams dropped early
+    pub red_drops: u64,
+    /// Largest queue in bytes
+    pub max_queue: usize,
+}
+
+/// What one flow achieved
+#[derive(Debug, Clone)]
+pub struct FlowOutcome {
+    /// Payload bytes that reached the receiver
+    pub delivered_bytes: u64,
+    /// Delivered payload bytes per second from the flow's start to the end of the run
+    pub throughput: f64,
+    /// Sender's statistics of the receiver at the end
+    pub stats: Option<ConnectionStats>,
+}
+
+/// Result of a bottleneck scenario
+#[derive(Debug, Clone)]
+pub struct BottleneckOutcome {
+    /// In the order the flows were added
+    pub flows: Vec<FlowOutcome>,
+    pub link: BottleneckStats,
+    /// Link rate in bytes per second
+    pub rate: u64,
+    pub duration: Duration,
+}
+
+impl BottleneckOutcome {
+    /// Delivered payload bytes per second of all flows
+    pub fn throughput(&self) -> f64 {
+        let delivered: u64 = self.flows.iter().map(|flow| flow.delivered_bytes).sum();
+        delivered as f64 / self.duration.as_secs_f64()
+    }
+
+    /// Share of the link rate carrying datagrams, including headers and retransmissions
+    pub fn utilization(&self) -> f64 {
+        self.link.forwarded_bytes as f64 / (self.rate as f64 * self.duration.as_secs_f64())
+    }
+
+    /// Jain's fairness index of the flows' throughputs: 1 for equal shares, down to
+    /// `1 / flows` for one flow taking everything
+    pub fn fairness(&self) -> f64 {
+        let sum: f64 = self.flows.iter().map(|flow| flow.throughput).sum();
+        let squares: f64 = self.flows.iter().map(|flow| flow.throughput * flow.throughput).sum();
+        if squares == 0.0 {
+            return 1.0;
+        }
+        sum * sum / (self.flows.len() as f64 * squares)
+    }
+}
+
+/// FIFO queue in front of the bottleneck link
+struct BottleneckQueue {
+    bottleneck: Bottleneck,
+    /// Departure time and size of each queued datagram
+    queued: VecDeque<(Instant, usize)>,
+    queued_bytes: usize,
+    /// When the link finishes the last queued datagram
+    busy_until: Instant,
+    /// Average queue in bytes, for early drops
+    average: f64,
+    rng: SplitMix64,
+    stats: BottleneckStats,
+}
+
+impl BottleneckQueue {
+    fn new(bottleneck: Bottleneck, seed: u64, now: Instant) -> Self {
+        Self {
+            bottleneck,
+            queued: VecDeque::new(),
+            queued_bytes: 0,
+            busy_until: now,
+            average: 0.0,
+            rng: SplitMix64(seed),
+            stats: BottleneckStats::default(),
+        }
+    }
+
+    /// Queue a datagram of `size` bytes arriving at `now`; returns when it has crossed
+    /// the link, or `None` if it was dropped
+    fn enqueue(&mut self, size: usize, now: Instant) -> Option<Instant> {
+        while let Some(&(departure, bytes)) = self.queued.front() {
+            if departure > now {
+                break;
+            }
+            self.queued.pop_front();
+            self.queued_bytes -= bytes;
+        }
+        if let Some(red) = self.bottleneck.red {
+            if self.queued_bytes == 0 {
+                // Decay the average as if datagrams of this size had found the link idle
+                let idle = now.saturating_duration_since(self.busy_until);
+                let samples = idle.as_secs_f64() / self.bottleneck.serialization(size).as_secs_f64();
+                self.average *= (1.0 - red.weight).powf(samples);
+            }
+            self.average += red.weight * (self.queued_bytes as f64 - self.average);
+            if self.rng.chance(red.drop_probability(self.average)) {
+                self.stats.red_drops += 1;
+                return None;
+            }
+        }
+        if self.queued_bytes + size > self.bottleneck.queue_limit {
+            self.stats.tail_drops += 1;
+            return None;
+        }
+        let departure = self.busy_until.max(now) + self.bottleneck.serialization(size);
+        self.busy_until = departure;
+        self.queued.push_back((departure, size));
+        self.queued_bytes += size;
+        self.stats.max_queue = self.stats.max_queue.max(self.queued_bytes);
+        self.stats.forwarded += 1;
+        self.stats.forwarded_bytes += size as u64;
+        Some(departure + self.bottleneck.delay)
+    }
+}
+
+/// One flow's pair of cores
+struct FlowState {
+    sender: RudpCore,
+    receiver: RudpCore,
+    sender_addr: SocketAddr,
+    receiver_addr: SocketAddr,
+    start: Instant,
+    payload_size: usize,
+    delivered_bytes: u64,
+}
+
+/// Flows connected through a bottleneck
+struct BottleneckHarness<'a> {
+    scenario: &'a BottleneckScenario,
+    flows: Vec<FlowState>,
+    queue: BottleneckQueue,
+    start: Instant,
+    now: Instant,
+    in_flight: Vec<InFlight>,
+}
+
+impl<'a> BottleneckHarness<'a> {
+    fn new(scenario: &'a BottleneckScenario) -> Result<Self, RudpError> {
+        let start = Instant::now();
+        let mut flows = Vec::new();
+        for (index, flow) in scenario.flows.iter().enumerate() {
+            let host = index as u8 + 1;
+            flows.push(FlowState {
+                sender: RudpCore::new(flow.config.clone())?,
+                receiver: RudpCore::new(flow.config.clone())?,
+                sender_addr: SocketAddr::from(([10, 0, 1, host], 1)),
+                receiver_addr: SocketAddr::from(([10, 0, 2, host], 1)),
+                start: start + flow.start,
+                payload_size: flow.payload_size,
+                delivered_bytes: 0,
+            });
+        }
+        Ok(Self {
+            scenario,
+            flows,
+            queue: BottleneckQueue::new(scenario.bottleneck, scenario.seed, start),
+            start,
+            now: start,
+            in_flight: Vec::new(),
+        })
+    }
+
+    fn run(mut self) -> Result<BottleneckOutcome, RudpError> {
+        let end = self.start + self.scenario.duration;
+        while self.now < end {
+            self.deliver_due();
+            self.send()?;
+            for flow in &mut self.flows {
+                flow.sender.handle_timeout(self.now);
+                flow.receiver.handle_timeout(self.now);
+            }
+            self.transmit();
+            self.collect();
+
+            let mut next = end;
+            for flow in &self.flows {
+                let timeouts = [flow.sender.poll_timeout(), flow.receiver.poll_timeout(), (flow.start > self.now).then_some(flow.start)];
+                next = timeouts.into_iter().flatten().fold(next, Instant::min);
+            }
+            next = self.in_flight.iter().map(|datagram| datagram.arrival).fold(next, Instant::min);
+            self.now = next.max(self.now + MIN_STEP);
+        }
+        Ok(self.outcome())
+    }
+
+    /// Fill the congestion window of every flow that has started
+    fn send(&mut self) -> Result<(), RudpError> {
+        for flow in self.flows.iter_mut().filter(|flow| flow.start <= self.now) {
+            loop {
+                let mut buffer = flow.sender.get_buffer_for(flow.payload_size)?;
+                buffer.set_data_len(flow.payload_size)?;
+                match flow.sender.try_send(buffer, flow.receiver_addr, self.now) {
+                    Ok(_) => {}
+                    Err(RudpError::CongestionWindowFull) => break,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand datagrams whose arrival time has come to their destination
+    fn deliver_due(&mut self) {
+        for datagram in take_due(&mut self.in_flight, self.now) {
+            for flow in &mut self.flows {
+                if datagram.to == flow.receiver_addr {
+                    flow.receiver.handle_datagram(&datagram.contents, datagram.from, self.now);
+                } else if datagram.to == flow.sender_addr {
+                    flow.sender.handle_datagram(&datagram.contents, datagram.from, self.now);
+                }
+            }
+        }
+    }
+
+    /// Queue data at the bottleneck and send acknowledgements straight back
+    fn transmit(&mut self) {
+        for flow in &mut self.flows {
+            while let Some(transmit) = flow.sender.poll_transmit() {
+                if let Some(arrival) = self.queue.enqueue(transmit.contents.len(), self.now) {
+                    self.in_flight.push(InFlight { arrival, from: flow.sender_addr, to: transmit.destination, contents: transmit.contents });
+                }
+            }
+            while let Some(transmit) = flow.receiver.poll_transmit() {
+                let arrival = self.now + self.scenario.bottleneck.delay;
+                self.in_flight.push(InFlight { arrival, from: flow.receiver_addr, to: transmit.destination, contents: transmit.contents });
+            }
+        }
+    }
+
+    fn collect(&mut self) {
+        for flow in &mut self.flows {
+            while let Some(received) = flow.receiver.poll_received() {
+                if let Ok(buffer) = received.result {
+                    flow.delivered_bytes += buffer.data_len() as u64;
+                }
+            }
+            while flow.sender.poll_received().is_some() {}
+        }
+    }
+
+    fn outcome(self) -> BottleneckOutcome {
+        let end = self.now;
+        let flows = self
+            .flows
+            .iter()
+            .map(|flow| {
+                let active = end.saturating_duration_since(flow.start).as_secs_f64();
+                FlowOutcome {
+                    delivered_bytes: flow.delivered_bytes,
+                    throughput: if active > 0.0 { flow.delivered_bytes as f64 / active } else { 0.0 },
+                    stats: flow.sender.get_stats(flow.receiver_addr),
+                }
+            })
+            .collect();
+        BottleneckOutcome {
+            flows,
+            link: self.queue.stats,
+            rate: self.scenario.bottleneck.rate,
+            duration: self.scenario.duration,
+        }
+    }
+}
+
+fn is_dead(error: &RudpError) -> bool {
+    matches!(error, RudpError::Connection(ConnectionError::Dead { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phases_apply_in_order() {
+        let scenario = Scenario::new()
+            .loss(0.3, Duration::from_secs(5))
+            .latency(Duration::from_millis(200), Duration::from_secs(5))
+            .partition(Duration::from_secs(1));
+        assert_eq!(scenario.duration(), Duration::from_secs(11));
+        assert_eq!(scenario.conditions_at(Duration::from_secs(1)).loss, 0.3);
+        assert_eq!(scenario.conditions_at(Duration::from_secs(5)).latency, Duration::from_millis(200));
+        assert!(scenario.conditions_at(Duration::from_millis(10_500)).partitioned);
+        assert_eq!(scenario.conditions_at(Duration::from_secs(11)), Conditions::default());
+    }
+
+    #[test]
+    fn test_clear_link_delivers_everything() {
+        let outcome = Scenario::new().clear(Duration::from_secs(1)).run(RudpConfig::default()).unwrap();
+        outcome.assert_invariants();
+        assert_eq!(outcome.sent, 20);
+        assert_eq!(outcome.delivered, 20);
+        assert_eq!(outcome.acked, 20);
+        assert_eq!(outcome.link.dropped, 0);
+        assert!(outcome.elapsed < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_same_seed_same_run() {
+        let scenario = Scenario::new().loss(0.2, Duration::from_secs(2)).seed(3);
+        let first = scenario.run(RudpConfig::default()).unwrap();
+        let second = scenario.run(RudpConfig::default()).unwrap();
+        first.assert_invariants();
+        assert!(first.link.dropped > 0);
+        assert_eq!(first.link, second.link);
+        assert_eq!(first.elapsed, second.elapsed);
+    }
+
+    #[test]
+    fn test_bottleneck_queue_serializes_and_drops() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut queue = BottleneckQueue::new(Bottleneck::new(1000, 1000, Duration::from_millis(10)), 0, start);
+        assert_eq!(queue.enqueue(500, start), Some(at(510)));
+        assert_eq!(queue.enqueue(500, start), Some(at(1010)));
+        // Full until the first datagram has left
+        assert_eq!(queue.enqueue(100, at(100)), None);
+        assert_eq!(queue.enqueue(100, at(500)), Some(at(1110)));
+        // An idle link sends right away
+        assert_eq!(queue.enqueue(100, at(2000)), Some(at(2110)));
+        assert_eq!(queue.stats.forwarded, 4);
+        assert_eq!(queue.stats.tail_drops, 1);
+        assert_eq!(queue.stats.max_queue, 1000);
+    }
+
+    #[test]
+    fn test_red_drop_probability() {
+        let red = Red::for_queue(4000);
+        assert_eq!(red.drop_probability(500.0), 0.0);
+        assert!((red.drop_probability(2000.0) - 0.05).abs() < 1e-9);
+        assert_eq!(red.drop_probability(3000.0), 1.0);
+    }
+}
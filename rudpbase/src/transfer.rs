@@ -0,0 +1,378 @@
+//! Stream transfer over the message API
+//!
+//! [`send_stream`] copies an [`AsyncRead`] source to a peer running [`recv_stream`],
+//! which writes it to an [`AsyncWrite`] sink. Data travels in chunks sent with
+//! [`Rudpbase::send_message`]; the sender keeps at most `window` chunks ahead of what
+//! the receiver has written, so a slow sink slows the sender instead of piling up
+//! memory. The receiver announces how many bytes it already has when the transfer
+//! starts, and the sender skips that much of its source, so an interrupted transfer
+//! resumes by starting again with the partial output.
+//!
+//! Both helpers drive the instance (`tick()` and receiving) until the transfer is
+//! done. Messages that do not belong to the transfer are kept for `recv_message()`.
+//!
+//! ```rust,no_run
+//! use rudpbase::transfer::{self, TransferOptions};
+//! use rudpbase::Rudpbase;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let mut rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+//!     let source = std::io::Cursor::new(vec![0u8; 10 * 1024 * 1024]);
+//!     let options = TransferOptions::default();
+//!     transfer::send_stream(&mut rudp, source, "127.0.0.1:8081".parse()?, &options, |progress| {
+//!         println!("{} bytes acknowledged", progress.transferred);
+//!     })
+//!     .await?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+use crate::core::Rudpbase;
+use crate::error::RudpError;
+use crate::message::ReceivedMessage;
+
+/// First byte of every transfer message
+const TRANSFER_MAGIC: u8 = 0xF7;
+
+/// Transfer message header size: magic(1) + kind(1) + transfer_id(4) + offset(8)
+const TRANSFER_HEADER_SIZE: usize = 14;
+
+/// Default bytes per chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Default chunks in flight ahead of the receiver
+pub const DEFAULT_WINDOW: usize = 8;
+
+/// Default time without any message from the peer before a transfer fails
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time `recv_stream` waits for its final acknowledgement to be confirmed
+pub const DEFAULT_LINGER: Duration = Duration::from_secs(1);
+
+/// Options of a stream transfer
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferOptions {
+    /// Bytes per chunk; must fit `max_message_size` with a 14-byte header
+    pub chunk_size: usize,
+    /// Chunks the sender may have in flight ahead of the receiver's written offset
+    pub window: usize,
+    /// Time without any message from the peer before the transfer fails with
+    /// `RudpError::Timeout`
+    pub timeout: Duration,
+    /// Total size of the source, passed through to progress reports
+    pub total_len: Option<u64>,
+    /// How long `recv_stream` keeps retransmitting its final acknowledgement
+    /// before returning
+    pub linger: Duration,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            window: DEFAULT_WINDOW,
+            timeout: DEFAULT_TRANSFER_TIMEOUT,
+            total_len: None,
+            linger: DEFAULT_LINGER,
+        }
+    }
+}
+
+/// Progress of a stream transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Bytes written by the receiver, including bytes it had before resuming
+    pub transferred: u64,
+    /// Total size, if known
+    pub total: Option<u64>,
+}
+
+/// Result of a completed receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedStream {
+    /// Sender address
+    pub from: SocketAddr,
+    /// Final length of the stream, including bytes held before resuming
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Sender opens a transfer
+    Start = 0,
+    /// Data at `offset`
+    Chunk = 1,
+    /// From the sender: source exhausted, `offset` is the final length.
+    /// From the receiver: all of it is written and flushed
+    End = 2,
+    /// Receiver has written everything before `offset`
+    Ack = 3,
+}
+
+/// A decoded transfer message
+struct Frame<'a> {
+    kind: Kind,
+    id: u32,
+    offset: u64,
+    data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    fn encode(kind: Kind, id: u32, offset: u64, data: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(TRANSFER_HEADER_SIZE + data.len());
+        message.push(TRANSFER_MAGIC);
+        message.push(kind as u8);
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&offset.to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    fn decode(message: &'a [u8]) -> Option<Self> {
+        if message.len() < TRANSFER_HEADER_SIZE || message[0] != TRANSFER_MAGIC {
+            return None;
+        }
+        let kind = match message[1] {
+            0 => Kind::Start,
+            1 => Kind::Chunk,
+            2 => Kind::End,
+            3 => Kind::Ack,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            id: u32::from_be_bytes(message[2..6].try_into().ok()?),
+            offset: u64::from_be_bytes(message[6..14].try_into().ok()?),
+            data: &message[TRANSFER_HEADER_SIZE..],
+        })
+    }
+}
+
+/// Drives an instance while waiting for transfer messages, setting aside
+/// non-transfer messages and enforcing the inactivity timeout
+struct Driver<'r> {
+    rudp: &'r mut Rudpbase,
+    unrelated: Vec<ReceivedMessage>,
+    timeout: Duration,
+    last_heard: Instant,
+}
+
+impl<'r> Driver<'r> {
+    fn new(rudp: &'r mut Rudpbase, timeout: Duration) -> Self {
+        Self {
+            rudp,
+            unrelated: Vec::new(),
+            timeout,
+            last_heard: Instant::now(),
+        }
+    }
+
+    /// Wait for the next transfer message accepted by `filter`
+    async fn next(&mut self, filter: impl Fn(&Frame<'_>, SocketAddr) -> bool) -> Result<(Vec<u8>, SocketAddr), RudpError> {
+        loop {
+            if self.last_heard.elapsed() >= self.timeout {
+                return Err(RudpError::Timeout);
+            }
+            self.rudp.tick().await;
+            let Some(message) = self.rudp.recv_message().await else {
+                continue;
+            };
+            match Frame::decode(&message.data) {
+                Some(frame) if filter(&frame, message.from) => {
+                    self.last_heard = Instant::now();
+                    return Ok((message.data, message.from));
+                }
+                // Leftovers of another transfer
+                Some(_) => {}
+                None => self.unrelated.push(message),
+            }
+        }
+    }
+
+    /// Keep ticking until everything sent to `peer` is acknowledged or `limit` passes
+    ///
+    /// The peer may stop reading as soon as it has what it needs, so this gives up
+    /// quietly; later `tick()` calls keep retransmitting.
+    async fn linger(&mut self, peer: SocketAddr, limit: Duration) {
+        let deadline = Instant::now() + limit;
+        while self.rudp.pending_count(peer) > 0 && Instant::now() < deadline {
+            self.rudp.tick().await;
+            if let Some(message) = self.rudp.recv_message().await {
+                if Frame::decode(&message.data).is_none() {
+                    self.unrelated.push(message);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Driver<'_> {
+    fn drop(&mut self) {
+        self.rudp.requeue_messages(std::mem::take(&mut self.unrelated));
+    }
+}
+
+/// Send everything `reader` produces to a peer running [`recv_stream`]
+///
+/// Returns the final length of the stream once the receiver has written all of it.
+/// `progress` is called whenever the receiver reports a new written offset.
+pub async fn send_stream<R: AsyncRead + Unpin>(
+    rudp: &mut Rudpbase,
+    mut reader: R,
+    target: SocketAddr,
+    options: &TransferOptions,
+    mut progress: impl FnMut(TransferProgress),
+) -> Result<u64, RudpError> {
+    if options.chunk_size == 0 || options.window == 0 || options.chunk_size + TRANSFER_HEADER_SIZE > rudp.config().max_message_size {
+        return Err(RudpError::InvalidConfig {
+            message: "chunk_size and window must be non-zero and chunks must fit max_message_size".to_string(),
+        });
+    }
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.subsec_nanos() ^ elapsed.as_secs() as u32);
+    let mut driver = Driver::new(rudp, options.timeout);
+    let is_reply = move |frame: &Frame<'_>, from: SocketAddr| {
+        from == target && frame.id == id && matches!(frame.kind, Kind::Ack | Kind::End)
+    };
+
+    // The receiver answers with the length it already holds
+    driver.rudp.send_message(&Frame::encode(Kind::Start, id, 0, &[]), target).await?;
+    let (ack, _) = driver.next(is_reply).await?;
+    let resume = Frame::decode(&ack).map_or(0, |frame| frame.offset);
+    let skipped = tokio::io::copy(&mut (&mut reader).take(resume), &mut tokio::io::sink()).await?;
+    if skipped < resume {
+        return Err(RudpError::Protocol {
+            message: format!("receiver holds {} bytes but the source has only {}", resume, skipped),
+        });
+    }
+
+    let report = |acked: u64| TransferProgress { transferred: acked, total: options.total_len };
+    progress(report(resume));
+    let window = (options.window * options.chunk_size) as u64;
+    let mut chunk = vec![0u8; options.chunk_size];
+    let mut sent = resume;
+    let mut acked = resume;
+    let mut finished = false;
+
+    loop {
+        while !finished && sent - acked < window {
+            let len = read_full(&mut reader, &mut chunk).await?;
+            if len == 0 {
+                finished = true;
+                driver.rudp.send_message(&Frame::encode(Kind::End, id, sent, &[]), target).await?;
+                break;
+            }
+            driver.rudp.send_message(&Frame::encode(Kind::Chunk, id, sent, &chunk[..len]), target).await?;
+            sent += len as u64;
+        }
+
+        let (reply, _) = driver.next(is_reply).await?;
+        let Some(frame) = Frame::decode(&reply) else { continue };
+        if frame.offset > acked {
+            acked = frame.offset.min(sent);
+            progress(report(acked));
+        }
+        if frame.kind == Kind::End {
+            if !finished || frame.offset != sent {
+                return Err(RudpError::Protocol {
+                    message: format!("receiver ended the stream at {} bytes, {} were sent", frame.offset, sent),
+                });
+            }
+            return Ok(sent);
+        }
+    }
+}
+
+/// Receive one stream sent with [`send_stream`] into `writer`
+///
+/// `start_offset` is the number of bytes `writer` already holds from an earlier,
+/// interrupted transfer of the same stream; the sender skips them. `progress` is
+/// called after each write. Before returning it waits up to `options.linger` for the
+/// sender to confirm the final reply.
+pub async fn recv_stream<W: AsyncWrite + Unpin>(
+    rudp: &mut Rudpbase,
+    mut writer: W,
+    start_offset: u64,
+    options: &TransferOptions,
+    mut progress: impl FnMut(TransferProgress),
+) -> Result<ReceivedStream, RudpError> {
+    // No inactivity limit until a sender shows up
+    let mut driver = Driver::new(rudp, Duration::MAX);
+    let (start, from) = driver.next(|frame, _| frame.kind == Kind::Start).await?;
+    let id = Frame::decode(&start).map_or(0, |frame| frame.id);
+    driver.timeout = options.timeout;
+    driver.rudp.send_message(&Frame::encode(Kind::Ack, id, start_offset, &[]), from).await?;
+
+    let mut written = start_offset;
+    let mut end = None;
+    let mut pending: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+    progress(TransferProgress { transferred: written, total: end });
+
+    while end != Some(written) {
+        let (message, _) = driver
+            .next(move |frame, sender| sender == from && frame.id == id && matches!(frame.kind, Kind::Chunk | Kind::End))
+            .await?;
+        let Some(frame) = Frame::decode(&message) else { continue };
+        match frame.kind {
+            Kind::End => end = Some(frame.offset),
+            _ if frame.offset >= written => {
+                pending.insert(frame.offset, frame.data.to_vec());
+            }
+            _ => continue,
+        }
+
+        // Write every chunk that is now contiguous
+        let before = written;
+        while let Some(data) = pending.remove(&written) {
+            writer.write_all(&data).await?;
+            written += data.len() as u64;
+        }
+        if written > before {
+            driver.rudp.send_message(&Frame::encode(Kind::Ack, id, written, &[]), from).await?;
+            progress(TransferProgress { transferred: written, total: end });
+        }
+    }
+    writer.flush().await?;
+    driver.rudp.send_message(&Frame::encode(Kind::End, id, written, &[]), from).await?;
+
+    // Give the final reply a chance to reach the sender
+    driver.linger(from, options.linger).await;
+    Ok(ReceivedStream { from, len: written })
+}
+
+/// Fill `buf` from `reader`, stopping early only at the end of the stream
+async fn read_full<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            len => filled += len,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let message = Frame::encode(Kind::Chunk, 7, 1 << 40, b"data");
+        let frame = Frame::decode(&message).unwrap();
+        assert_eq!(frame.kind, Kind::Chunk);
+        assert_eq!(frame.id, 7);
+        assert_eq!(frame.offset, 1 << 40);
+        assert_eq!(frame.data, b"data");
+
+        assert!(Frame::decode(b"not a transfer message").is_none());
+        assert!(Frame::decode(&message[..TRANSFER_HEADER_SIZE - 1]).is_none());
+    }
+}
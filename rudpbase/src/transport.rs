@@ -0,0 +1,114 @@
+//! Pluggable datagram transport
+//!
+//! [`Rudpbase`](crate::Rudpbase) moves datagrams through a [`Transport`]. The default
+//! is a tokio [`UdpSocket`]; other implementations can plug in mock networks for
+//! deterministic tests, userspace network stacks or encrypted tunnels with
+//! [`Rudpbase::with_transport`](crate::Rudpbase::with_transport).
+//!
+//! The trait is poll-based so it stays object safe and does not tie implementations
+//! to a particular async runtime.
+
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::task::{ready, Context, Poll, Waker};
+
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+
+/// A datagram transport
+///
+/// Datagrams are sent and received whole: `poll_recv_from` fills `buf` with exactly
+/// one datagram, truncating it if `buf` is too small.
+///
+/// # Example
+/// A transport that drops every other outgoing datagram, for loss testing:
+/// ```rust
+/// use rudpbase::Transport;
+/// use std::io;
+/// use std::net::SocketAddr;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::task::{Context, Poll};
+/// use tokio::net::UdpSocket;
+///
+/// struct Lossy {
+///     socket: UdpSocket,
+///     drop_next: AtomicBool,
+/// }
+///
+/// impl Transport for Lossy {
+///     fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+///         if self.drop_next.fetch_xor(true, Ordering::Relaxed) {
+///             return Poll::Ready(Ok(buf.len()));
+///         }
+///         self.socket.poll_send_to(cx, buf, target)
+///     }
+///
+///     fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+///         Transport::poll_recv_from(&self.socket, cx, buf)
+///     }
+///
+///     fn local_addr(&self) -> io::Result<SocketAddr> {
+///         self.socket.local_addr()
+///     }
+/// }
+/// ```
+pub trait Transport: Send + Sync + 'static {
+    /// Attempt to send one datagram to `target`
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>>;
+
+    /// Attempt to receive one datagram, returning its length and sender
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>>;
+
+    /// Local address of the transport
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The OS socket behind this transport, if any
+    ///
+    /// Batched I/O, UDP offload, the io_uring backend and per-peer source addresses
+    /// work directly on the socket and are only available when this returns `Some`.
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+}
+
+impl Transport for UdpSocket {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut read_buf = ReadBuf::new(buf);
+        let from = ready!(UdpSocket::poll_recv_from(self, cx, &mut read_buf))?;
+        Poll::Ready(Ok((read_buf.filled().len(), from)))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self)
+    }
+}
+
+/// Send one datagram
+pub(crate) async fn send_to(transport: &dyn Transport, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+    poll_fn(|cx| transport.poll_send_to(cx, buf, target)).await
+}
+
+/// Wait for one datagram
+pub(crate) async fn recv_from(transport: &dyn Transport, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    poll_fn(|cx| transport.poll_recv_from(cx, buf)).await
+}
+
+/// Receive one datagram without waiting, returning `WouldBlock` if none is available
+pub(crate) fn try_recv_from(transport: &dyn Transport, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    if let Some(socket) = transport.udp_socket() {
+        return socket.try_recv_from(buf);
+    }
+    match transport.poll_recv_from(&mut Context::from_waker(Waker::noop()), buf) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+    }
+}
@@ -0,0 +1,116 @@
+//! Running instances inside a turmoil simulation
+//!
+//! [turmoil](https://docs.rs/turmoil) runs several hosts in one process on a simulated
+//! network and clock, and can hold, partition and repair the links between them, so
+//! multi-node tests of retransmission and failover run reproducibly and without real
+//! waits. [`TurmoilTransport`] carries an instance's datagrams over a turmoil UDP
+//! socket, and [`bind`] creates an instance on one with a [`TokioClock`], so protocol
+//! timers follow the simulated time.
+//!
+//! ```rust,no_run
+//! use rudpbase::RudpConfig;
+//! use std::net::{Ipv4Addr, SocketAddr};
+//! use std::time::Duration;
+//!
+//! let mut sim = turmoil::Builder::new().build();
+//! sim.host("server", || async {
+//!     let mut rudp = rudpbase::turmoil::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 9000)), RudpConfig::default()).await?;
+//!     loop {
+//!         let received = rudp.recv().await;
+//!         println!("{} bytes from {}", received.result?.data_len(), received.from);
+//!     }
+//! });
+//! sim.client("client", async {
+//!     let mut rudp = rudpbase::turmoil::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 9000)), RudpConfig::default()).await?;
+//!     let server = SocketAddr::new(turmoil::lookup("server"), 9000);
+//!     let mut buffer = rudp.get_buffer()?;
+//!     buffer.set_data_len(10)?;
+//!     rudp.send(buffer, server).await?;
+//!     // Receiving drives retransmissions until the packet is acknowledged
+//!     while rudp.pending_count(server) > 0 {
+//!         rudp.recv_timeout(Duration::from_millis(10)).await;
+//!     }
+//!     Ok(())
+//! });
+//! sim.run().unwrap();
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use ::turmoil::net::UdpSocket;
+
+use crate::clock::TokioClock;
+use crate::config::RudpConfig;
+use crate::core::Rudpbase;
+use crate::error::RudpError;
+use crate::transport::Transport;
+
+type Readable = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// Transport over a turmoil UDP socket
+///
+/// Must be used from inside a turmoil host or client.
+pub struct TurmoilTransport {
+    socket: Arc<UdpSocket>,
+    /// Pending wait for the socket to become readable
+    readable: Mutex<Option<Readable>>,
+}
+
+impl TurmoilTransport {
+    /// Bind a turmoil UDP socket on the current host
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self::new(UdpSocket::bind(addr).await?))
+    }
+
+    /// Wrap a bound socket
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket: Arc::new(socket),
+            readable: Mutex::new(None),
+        }
+    }
+}
+
+impl Transport for TurmoilTransport {
+    fn poll_send_to(&self, _cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<io::Result<usize>> {
+        // Turmoil sockets have no backpressure
+        Poll::Ready(self.socket.try_send_to(buf, target))
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut readable = self.readable.lock().unwrap();
+        loop {
+            // The wait holds the socket's receive lock, so it is finished before reading
+            if readable.is_none() {
+                match self.socket.try_recv_from(buf) {
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return Poll::Ready(result),
+                }
+                let socket = Arc::clone(&self.socket);
+                *readable = Some(Box::pin(async move { socket.readable().await }));
+            }
+            let wait = readable.as_mut().expect("wait created above");
+            let result = std::task::ready!(wait.as_mut().poll(cx));
+            *readable = None;
+            result?;
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+/// Create an instance on a turmoil UDP socket bound to `addr` on the current host,
+/// with its timers on the simulated clock
+pub async fn bind(addr: SocketAddr, config: RudpConfig) -> Result<Rudpbase, RudpError> {
+    let transport = TurmoilTransport::bind(addr).await?;
+    let mut rudp = Rudpbase::with_transport(transport, config).await?;
+    rudp.set_clock(TokioClock);
+    Ok(rudp)
+}
@@ -0,0 +1,203 @@
+//! Typed values over Rudpbase
+//!
+//! [`TypedChannel`] sends and receives serde values instead of raw byte buffers. Each
+//! value is encoded with postcard straight into a pooled buffer and sent as one
+//! reliable packet, so it must fit in a single buffer; larger values fail with
+//! `RudpError::BufferTooLarge` before anything is sent. Packets that do not decode
+//! as `T` are returned as `RudpError::Serialization` rather than dropped.
+//!
+//! [`encode`] and [`decode`] are the codec on their own, for the blocking and
+//! smol/async-std adapters or when several value types share one instance.
+//!
+//! ```rust,no_run
+//! use rudpbase::typed::TypedChannel;
+//! use rudpbase::Rudpbase;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Position { x: f32, y: f32 }
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let rudp = Rudpbase::new("127.0.0.1:8080".parse()?).await?;
+//!     let mut channel = TypedChannel::<Position>::new(rudp);
+//!     channel.send(&Position { x: 1.0, y: 2.0 }, "127.0.0.1:8081".parse()?).await?;
+//!     loop {
+//!         let received = channel.recv().await;
+//!         let position = received.result?;
+//!         println!("{} is at ({}, {})", received.from, position.x, position.y);
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "tokio")]
+use std::marker::PhantomData;
+#[cfg(feature = "tokio")]
+use std::net::SocketAddr;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::buffer_pool::PooledBuffer;
+#[cfg(feature = "tokio")]
+use crate::core::Rudpbase;
+#[cfg(feature = "tokio")]
+use crate::delivery::DeliveryHandle;
+use crate::error::RudpError;
+
+/// Encode `value` into `buffer` and set its data length
+pub fn encode<T: Serialize + ?Sized>(value: &T, buffer: &mut PooledBuffer) -> Result<(), RudpError> {
+    let max = buffer.data_mut().len();
+    let len = match postcard::to_slice(value, buffer.data_mut()) {
+        Ok(encoded) => encoded.len(),
+        Err(postcard::Error::SerializeBufferFull) => {
+            let size = postcard::serialize_with_flavor(value, postcard::ser_flavors::Size::default()).map_err(serialization_error)?;
+            return Err(RudpError::BufferTooLarge { size, max });
+        }
+        Err(e) => return Err(serialization_error(e)),
+    };
+    buffer.set_data_len(len)
+}
+
+/// Decode a value from a received payload
+///
+/// Trailing bytes after the value are rejected.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, RudpError> {
+    match postcard::take_from_bytes(data).map_err(serialization_error)? {
+        (value, []) => Ok(value),
+        (_, rest) => Err(RudpError::Serialization {
+            message: format!("{} trailing bytes after value", rest.len()),
+        }),
+    }
+}
+
+fn serialization_error(e: postcard::Error) -> RudpError {
+    RudpError::Serialization { message: e.to_string() }
+}
+
+/// A received value
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TypedReceived<T> {
+    /// Sender address
+    pub from: SocketAddr,
+    /// Sequence number of the packet; `None` for receive errors
+    pub seq: Option<u32>,
+    /// The decoded value, the receive error or the decoding error
+    pub result: Result<T, RudpError>,
+}
+
+/// Rudpbase instance that carries values of type `T`
+///
+/// Wraps a [`Rudpbase`]; use [`get_ref`](Self::get_ref) and [`get_mut`](Self::get_mut)
+/// for statistics and configuration.
+#[cfg(feature = "tokio")]
+pub struct TypedChannel<T> {
+    rudp: Rudpbase,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Serialize + DeserializeOwned> TypedChannel<T> {
+    /// Carry values of type `T` over `rudp`
+    pub fn new(rudp: Rudpbase) -> Self {
+        Self { rudp, _marker: PhantomData }
+    }
+
+    /// Send a value reliably, returning its sequence number
+    pub async fn send(&mut self, value: &T, target: SocketAddr) -> Result<u32, RudpError> {
+        let buffer = self.encode(value)?;
+        self.rudp.send(buffer, target).await
+    }
+
+    /// Send a value and track its delivery; see [`DeliveryHandle`]
+    pub async fn send_tracked(&mut self, value: &T, target: SocketAddr) -> Result<DeliveryHandle, RudpError> {
+        let buffer = self.encode(value)?;
+        self.rudp.send_tracked(buffer, target).await
+    }
+
+    /// Receive and decode a value, waiting until one arrives like [`Rudpbase::recv`]
+    pub async fn recv(&mut self) -> TypedReceived<T> {
+        let received = self.rudp.recv().await;
+        TypedReceived {
+            from: received.from,
+            seq: received.seq,
+            result: received.result.and_then(|buffer| decode(buffer.data())),
+        }
+    }
+
+    /// Handle retransmissions, ACKs and keep-alive; see [`Rudpbase::tick`]
+    pub async fn tick(&mut self) {
+        self.rudp.tick().await;
+    }
+
+    /// The wrapped instance
+    pub fn get_ref(&self) -> &Rudpbase {
+        &self.rudp
+    }
+
+    /// The wrapped instance, mutably
+    pub fn get_mut(&mut self) -> &mut Rudpbase {
+        &mut self.rudp
+    }
+
+    /// Unwrap the instance
+    pub fn into_inner(self) -> Rudpbase {
+        self.rudp
+    }
+
+    fn encode(&self, value: &T) -> Result<PooledBuffer, RudpError> {
+        let mut buffer = self.rudp.get_buffer()?;
+        encode(value, &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_pool::SharedBufferPool;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Update {
+        id: u32,
+        name: String,
+        values: Vec<i64>,
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let pool = SharedBufferPool::new(1);
+        let mut buffer = pool.get_write_buffer().unwrap();
+        let update = Update { id: 7, name: "sensor".to_string(), values: vec![-1, 0, 300] };
+        encode(&update, &mut buffer).unwrap();
+        // varint id, length-prefixed name, zigzag varint values
+        assert_eq!(buffer.data_len(), 1 + 7 + 1 + 4);
+        assert_eq!(decode::<Update>(buffer.data()).unwrap(), update);
+
+        // Truncated or padded payloads and other types are rejected
+        let data = buffer.data();
+        assert!(matches!(decode::<Update>(&data[..data.len() - 1]), Err(RudpError::Serialization { .. })));
+        let mut padded = data.to_vec();
+        padded.push(0);
+        assert!(matches!(decode::<Update>(&padded), Err(RudpError::Serialization { .. })));
+        assert!(matches!(decode::<String>(data), Err(RudpError::Serialization { .. })));
+    }
+
+    #[test]
+    fn test_encode_too_large() {
+        let pool = SharedBufferPool::new(1);
+        let mut buffer = pool.get_write_buffer().unwrap();
+        let max = buffer.data_mut().len();
+        let value = vec![1u8; max];
+        match encode(&value, &mut buffer) {
+            Err(RudpError::BufferTooLarge { size, max: limit }) => {
+                assert_eq!(limit, max);
+                // Two-byte varint length prefix
+                assert_eq!(size, max + 2);
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! io_uring socket backend
+//!
+//! Every receive slot keeps a `RECVMSG` operation posted on the ring, so incoming
+//! datagrams are copied out of the kernel without a system call per packet, and a
+//! batch of outgoing datagrams is submitted as `SENDMSG` operations with a single
+//! `io_uring_enter`. Completions are signalled through an eventfd watched by tokio,
+//! so the backend runs on the ordinary tokio runtime.
+//!
+//! Slot memory is taken from the shared buffer pool and stays posted to the ring for
+//! the lifetime of the backend.
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub(crate) use imp::UringDriver;
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+pub(crate) use stub::UringDriver;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod imp {
+    use std::io;
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::task::{ready, Context, Poll};
+
+    use io_uring::{opcode, squeue, types, IoUring};
+    use socket2::SockAddr;
+    use tokio::io::unix::AsyncFd;
+    use tokio::net::UdpSocket;
+
+    use crate::buffer_pool::{PooledBuffer, SharedBufferPool};
+    use crate::error::RudpError;
+
+    /// user_data bit marking send completions
+    const SEND_TAG: u64 = 1 << 32;
+    /// user_data of cancellation requests, whose completions are ignored
+    const CANCEL_TAG: u64 = 1 << 33;
+
+    /// Message header of one slot; boxed so the kernel-visible pointers stay valid
+    struct MsgSlot {
+        addr: libc::sockaddr_storage,
+        iovec: libc::iovec,
+        msghdr: libc::msghdr,
+    }
+
+    impl MsgSlot {
+        fn new(buffer: &mut PooledBuffer) -> Box<Self> {
+            // SAFETY: sockaddr_storage and msghdr are plain old data; all-zero is valid
+            let mut slot = Box::new(Self {
+                addr: unsafe { mem::zeroed() },
+                iovec: libc::iovec {
+                    iov_base: buffer.raw_buffer_mut().as_mut_ptr().cast(),
+                    iov_len: buffer.raw_buffer_mut().len(),
+                },
+                msghdr: unsafe { mem::zeroed() },
+            });
+            slot.msghdr.msg_name = (&mut slot.addr as *mut libc::sockaddr_storage).cast();
+            slot.msghdr.msg_iov = &mut slot.iovec;
+            slot.msghdr.msg_iovlen = 1;
+            slot
+        }
+    }
+
+    pub(crate) struct UringDriver {
+        ring: IoUring,
+        eventfd: AsyncFd<OwnedFd>,
+        socket_fd: RawFd,
+        recv_slots: Vec<(PooledBuffer, Box<MsgSlot>)>,
+        send_slots: Vec<(PooledBuffer, Box<MsgSlot>)>,
+        free_sends: Vec<usize>,
+        /// Receive slots handed out by the last `try_recv`, re-posted on the next call
+        consumed: Vec<usize>,
+        /// Completed receives not yet handed out: (slot, length, source)
+        completed: Vec<(usize, usize, SocketAddr)>,
+        /// Receives returned by the last `try_recv`
+        received: Vec<(usize, usize, SocketAddr)>,
+        recv_error: Option<io::Error>,
+        send_error: Option<io::Error>,
+        /// Operations submitted to the kernel and not yet completed
+        outstanding: usize,
+    }
+
+    // SAFETY: the raw pointers inside the slots only refer to memory owned by the
+    // driver itself, and `&self` methods only read the eventfd
+    unsafe impl Send for UringDriver {}
+    unsafe impl Sync for UringDriver {}
+
+    impl UringDriver {
+        /// Create a ring for `socket` with `slots` receive and send slots
+        pub(crate) fn new(socket: &UdpSocket, pool: &SharedBufferPool, slots: usize) -> Result<Self, RudpError> {
+            let ring = IoUring::new((slots * 2).next_power_of_two() as u32)?;
+
+            // SAFETY: eventfd has no memory-safety preconditions
+            let raw_eventfd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+            if raw_eventfd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // SAFETY: raw_eventfd is a freshly created descriptor owned by nobody else
+            let eventfd = unsafe { OwnedFd::from_raw_fd(raw_eventfd) };
+            ring.submitter().register_eventfd(eventfd.as_raw_fd())?;
+
+            let new_slot = || -> Result<(PooledBuffer, Box<MsgSlot>), RudpError> {
+                let mut buffer = pool.get_write_buffer()?;
+                let slot = MsgSlot::new(&mut buffer);
+                Ok((buffer, slot))
+            };
+            let recv_slots = (0..slots).map(|_| new_slot()).collect::<Result<Vec<_>, _>>()?;
+            let send_slots = (0..slots).map(|_| new_slot()).collect::<Result<Vec<_>, _>>()?;
+
+            let mut driver = Self {
+                ring,
+                eventfd: AsyncFd::with_interest(eventfd, tokio::io::Interest::READABLE)?,
+                socket_fd: socket.as_raw_fd(),
+                recv_slots,
+                send_slots,
+                free_sends: (0..slots).rev().collect(),
+                consumed: (0..slots).collect(),
+                completed: Vec::with_capacity(slots),
+                received: Vec::with_capacity(slots),
+                recv_error: None,
+                send_error: None,
+                outstanding: 0,
+            };
+            driver.post_consumed()?;
+            driver.ring.submit()?;
+            Ok(driver)
+        }
+
+        /// Datagrams returned by the last `try_recv` call
+        pub(crate) fn datagrams(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+            self.received
+                .iter()
+                .map(|&(slot, len, from)| (&self.recv_slots[slot].0.raw_buffer()[..len], from))
+        }
+
+        /// Wait until a receive completes
+        pub(crate) async fn recv(&mut self) -> io::Result<usize> {
+            loop {
+                match self.try_recv() {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.readable().await?,
+                    result => return result,
+                }
+            }
+        }
+
+        /// Hand out completed receives without waiting
+        ///
+        /// Returns `WouldBlock` if no datagram has arrived since the last call.
+        pub(crate) fn try_recv(&mut self) -> io::Result<usize> {
+            self.post_consumed()?;
+            self.ring.submit()?;
+            self.reap();
+
+            self.received.clear();
+            self.received.append(&mut self.completed);
+            self.consumed.extend(self.received.iter().map(|&(slot, _, _)| slot));
+
+            if !self.received.is_empty() {
+                Ok(self.received.len())
+            } else if let Some(e) = self.recv_error.take() {
+                Err(e)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        /// Wait until the ring signals a completion
+        pub(crate) async fn readable(&self) -> io::Result<()> {
+            std::future::poll_fn(|cx| self.poll_readable(cx)).await
+        }
+
+        /// Poll form of [`readable`](Self::readable)
+        pub(crate) fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.eventfd.poll_read_ready(cx))?;
+                let mut counter = 0u64;
+                // SAFETY: counter is valid for writes of 8 bytes
+                let result = unsafe {
+                    libc::read(self.eventfd.as_raw_fd(), (&mut counter as *mut u64).cast(), mem::size_of::<u64>())
+                };
+                if result >= 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::WouldBlock {
+                    return Poll::Ready(Err(error));
+                }
+                guard.clear_ready();
+            }
+        }
+
+        /// Queue all datagrams as SENDMSG operations and submit them at once
+        ///
+        /// Datagrams are copied into send slots, so the caller's memory is not borrowed
+        /// past this call. Errors reported by completed sends are returned by a later call.
+        pub(crate) fn send_batch(&mut self, datagrams: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+            for (data, target) in datagrams {
+                let index = self.free_send_slot()?;
+                let (buffer, slot) = &mut self.send_slots[index];
+                let raw = buffer.raw_buffer_mut();
+                if data.len() > raw.len() {
+                    self.free_sends.push(index);
+                    self.send_error.get_or_insert_with(|| io::ErrorKind::InvalidInput.into());
+                    continue;
+                }
+                raw[..data.len()].copy_from_slice(data);
+                slot.iovec.iov_len = data.len();
+                let addr = SockAddr::from(*target);
+                slot.msghdr.msg_namelen = addr.len();
+                slot.addr = addr.as_storage();
+
+                let entry = opcode::SendMsg::new(types::Fd(self.socket_fd), &slot.msghdr)
+                    .build()
+                    .user_data(SEND_TAG | index as u64);
+                self.push(&entry)?;
+            }
+            self.ring.submit()?;
+            self.reap();
+
+            self.send_error.take().map_or(Ok(()), Err)
+        }
+
+        fn free_send_slot(&mut self) -> io::Result<usize> {
+            loop {
+                if let Some(index) = self.free_sends.pop() {
+                    return Ok(index);
+                }
+                self.ring.submit_and_wait(1)?;
+                self.reap();
+            }
+        }
+
+        /// Re-post receive operations for slots whose data has been consumed
+        fn post_consumed(&mut self) -> io::Result<()> {
+            while let Some(index) = self.consumed.pop() {
+                let slot = &mut self.recv_slots[index].1;
+                slot.msghdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                slot.msghdr.msg_flags = 0;
+                let entry = opcode::RecvMsg::new(types::Fd(self.socket_fd), &mut slot.msghdr)
+                    .build()
+                    .user_data(index as u64);
+                self.push(&entry)?;
+            }
+            Ok(())
+        }
+
+        fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+            // SAFETY: every entry points into a boxed slot that outlives the operation;
+            // Drop waits for all outstanding operations before the slots are freed
+            while unsafe { self.ring.submission().push(entry) }.is_err() {
+                self.ring.submit()?;
+            }
+            if entry.get_user_data() != CANCEL_TAG {
+                self.outstanding += 1;
+            }
+            Ok(())
+        }
+
+        /// Process all available completions
+        fn reap(&mut self) {
+            for cqe in self.ring.completion() {
+                let user_data = cqe.user_data();
+                if user_data == CANCEL_TAG {
+                    continue;
+                }
+                self.outstanding -= 1;
+
+                let result = cqe.result();
+                if user_data & SEND_TAG != 0 {
+                    self.free_sends.push((user_data & !SEND_TAG) as usize);
+                    if result < 0 {
+                        self.send_error.get_or_insert_with(|| io::Error::from_raw_os_error(-result));
+                    }
+                    continue;
+                }
+
+                let index = user_data as usize;
+                let slot = &self.recv_slots[index].1;
+                // SAFETY: the kernel filled in `msg_namelen` bytes of the address storage
+                let from = (result >= 0).then(|| unsafe { SockAddr::new(slot.addr, slot.msghdr.msg_namelen) }.as_socket()).flatten();
+                match from {
+                    Some(from) => self.completed.push((index, result as usize, from)),
+                    None => {
+                        if result < 0 {
+                            self.recv_error.get_or_insert_with(|| io::Error::from_raw_os_error(-result));
+                        }
+                        self.consumed.push(index);
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for UringDriver {
+        /// Cancel posted receives and wait for every operation, so the kernel never
+        /// writes into slot memory after it has been returned to the pool
+        fn drop(&mut self) {
+            for index in 0..self.recv_slots.len() as u64 {
+                let entry = opcode::AsyncCancel::new(index).build().user_data(CANCEL_TAG);
+                if self.push(&entry).is_err() {
+                    break;
+                }
+            }
+            while self.outstanding > 0 {
+                if self.ring.submit_and_wait(1).is_err() {
+                    // The ring is unusable; leak the slots rather than risk the
+                    // kernel writing into freed memory
+                    mem::forget(mem::take(&mut self.recv_slots));
+                    mem::forget(mem::take(&mut self.send_slots));
+                    return;
+                }
+                self.reap();
+            }
+        }
+    }
+}
+
+/// Placeholder when the `io-uring` feature is disabled; `RudpConfig::validate` rejects
+/// the io_uring backend, so it is never constructed
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+mod stub {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::task::{Context, Poll};
+
+    use tokio::net::UdpSocket;
+
+    use crate::buffer_pool::SharedBufferPool;
+    use crate::error::RudpError;
+
+    pub(crate) enum UringDriver {}
+
+    impl UringDriver {
+        pub(crate) fn new(_socket: &UdpSocket, _pool: &SharedBufferPool, _slots: usize) -> Result<Self, RudpError> {
+            Err(RudpError::InvalidConfig {
+                message: "io_uring backend requires the io-uring feature on Linux".to_string(),
+            })
+        }
+
+        pub(crate) fn datagrams(&self) -> std::iter::Empty<(&[u8], SocketAddr)> {
+            match *self {}
+        }
+
+        pub(crate) async fn recv(&mut self) -> io::Result<usize> {
+            match *self {}
+        }
+
+        pub(crate) fn try_recv(&mut self) -> io::Result<usize> {
+            match *self {}
+        }
+
+        pub(crate) async fn readable(&self) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub(crate) fn poll_readable(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            match *self {}
+        }
+
+        pub(crate) fn send_batch(&mut self, _datagrams: &[(&[u8], SocketAddr)]) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Duplicate detection for received data packets
+//!
+//! Each peer gets a [`ReceiveWindow`]: the sequence number below which everything is
+//! settled, plus a fixed bitmap of the [`WINDOW_SIZE`] sequence numbers after it.
+//! Memory per peer is constant no matter how many packets arrive, and comparisons
+//! use serial-number arithmetic, so the window keeps working when sequence numbers
+//! wrap from `u32::MAX` to 0.
+//!
+//! A packet further ahead than the window slides it forward. Sequence numbers that
+//! fall behind the window this way are treated as already received, so a packet
+//! must not still be retransmitted after more than `WINDOW_SIZE` newer sequence
+//! numbers from the same peer.
+
+/// Sequence numbers tracked at and after the window base
+pub(crate) const WINDOW_SIZE: u32 = 4096;
+
+pub(crate) const WORDS: usize = WINDOW_SIZE as usize / 64;
+
+/// Sequence numbers received from one peer
+#[derive(Debug, Clone)]
+pub(crate) struct ReceiveWindow {
+    /// Every sequence number before `base` has been received or left the window
+    base: u32,
+    /// Bit `seq % WINDOW_SIZE` is set for each received `seq` in `base..base + WINDOW_SIZE`
+    bits: Box<[u64; WORDS]>,
+}
+
+impl ReceiveWindow {
+    /// Window for a peer whose first packet carries `first_seq`
+    ///
+    /// The window ends at `first_seq`, so earlier packets that are still in flight
+    /// or being retransmitted are accepted when they arrive.
+    pub(crate) fn new(first_seq: u32) -> Self {
+        Self {
+            base: first_seq.wrapping_sub(WINDOW_SIZE - 1),
+            bits: Box::new([0; WORDS]),
+        }
+    }
+
+    /// Window restored from the `base` and `bits` of an exported one
+    pub(crate) fn from_parts(base: u32, bits: [u64; WORDS]) -> Self {
+        Self { base, bits: Box::new(bits) }
+    }
+
+    pub(crate) fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub(crate) fn bits(&self) -> &[u64; WORDS] {
+        &self.bits
+    }
+
+    /// Whether `seq` was already received, without recording it
+    pub(crate) fn contains(&self, seq: u32) -> bool {
+        let offset = seq.wrapping_sub(self.base);
+        offset > u32::MAX / 2 || (offset < WINDOW_SIZE && self.contains_in_window(seq))
+    }
+
+    /// Record `seq`; returns false if it was already received
+    pub(crate) fn insert(&mut self, seq: u32) -> bool {
+        let offset = seq.wrapping_sub(self.base);
+        if offset > u32::MAX / 2 {
+            // Before the window
+            return false;
+        }
+        if offset >= WINDOW_SIZE {
+            self.advance_to(seq.wrapping_sub(WINDOW_SIZE - 1));
+        }
+        if self.contains_in_window(seq) {
+            return false;
+        }
+        self.set(seq, true);
+        while self.contains_in_window(self.base) {
+            self.set(self.base, false);
+            self.base = self.base.wrapping_add(1);
+        }
+        true
+    }
+
+    /// Move the base to `new_base`, forgetting everything before it
+    fn advance_to(&mut self, new_base: u32) {
+        let distance = new_base.wrapping_sub(self.base);
+        if distance >= WINDOW_SIZE {
+            self.bits.fill(0);
+        } else {
+            for i in 0..distance {
+                self.set(self.base.wrapping_add(i), false);
+            }
+        }
+        self.base = new_base;
+    }
+
+    fn contains_in_window(&self, seq: u32) -> bool {
+        let slot = (seq % WINDOW_SIZE) as usize;
+        self.bits[slot / 64] & (1 << (slot % 64)) != 0
+    }
+
+    fn set(&mut self, seq: u32, received: bool) {
+        let slot = (seq % WINDOW_SIZE) as usize;
+        if received {
+            self.bits[slot / 64] |= 1 << (slot % 64);
+        } else {
+            self.bits[slot / 64] &= !(1 << (slot % 64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_and_reordering() {
+        let mut window = ReceiveWindow::new(0);
+        assert!(window.insert(0));
+        assert!(!window.insert(0));
+        assert!(window.insert(3));
+        assert!(window.insert(1));
+        assert!(!window.insert(3));
+        assert!(window.insert(2));
+        assert!(!window.insert(1));
+        assert!(!window.insert(2));
+        assert!(window.contains(3));
+        assert!(!window.contains(4));
+        assert!(window.insert(4));
+    }
+
+    #[test]
+    fn test_packets_before_first_are_accepted() {
+        // Packets 0..5 were lost or reordered behind packet 5
+        let mut window = ReceiveWindow::new(5);
+        assert!(window.insert(5));
+        for seq in 0..5 {
+            assert!(window.insert(seq));
+        }
+        assert!(!window.insert(2));
+    }
+
+    #[test]
+    fn test_slides_forward() {
+        let mut window = ReceiveWindow::new(0);
+        assert!(window.insert(0));
+        // A gap left by a lost control packet does not stop the window
+        for seq in 2..3 * WINDOW_SIZE {
+            assert!(window.insert(seq));
+        }
+        assert!(!window.insert(3 * WINDOW_SIZE - 1));
+        // Within the window: still accepted once, then a duplicate
+        assert!(window.insert(3 * WINDOW_SIZE + 10));
+        assert!(window.insert(3 * WINDOW_SIZE + 9));
+        assert!(!window.insert(3 * WINDOW_SIZE + 9));
+        // Fell behind the window
+        assert!(!window.insert(1));
+        assert!(!window.insert(WINDOW_SIZE));
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let start = u32::MAX - 100;
+        let mut window = ReceiveWindow::new(start);
+        for i in 0..200u32 {
+            assert!(window.insert(start.wrapping_add(i)), "seq {}", start.wrapping_add(i));
+        }
+        for i in 0..200u32 {
+            assert!(!window.insert(start.wrapping_add(i)));
+        }
+        // The window slides across the wrap like anywhere else
+        for i in 200..2 * WINDOW_SIZE {
+            assert!(window.insert(start.wrapping_add(i)));
+        }
+        assert!(!window.insert(u32::MAX));
+        assert!(!window.insert(0));
+    }
+
+    #[test]
+    fn test_large_jump_clears_window() {
+        let mut window = ReceiveWindow::new(0);
+        for seq in 0..10 {
+            window.insert(seq);
+        }
+        let far = 10 * WINDOW_SIZE;
+        assert!(window.insert(far));
+        for seq in far - WINDOW_SIZE + 1..far {
+            assert!(window.insert(seq));
+        }
+        assert!(!window.insert(9));
+    }
+}
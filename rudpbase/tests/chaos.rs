@@ -0,0 +1,100 @@
+//! Delivery and statistics invariants under scripted network impairments
+
+use std::time::Duration;
+
+use rudpbase::testing::{Bottleneck, BottleneckScenario, Conditions, Flow, Scenario};
+use rudpbase::RudpConfig;
+
+#[test]
+fn test_loss_then_latency_then_partition() {
+    let outcome = Scenario::new()
+        .loss(0.3, Duration::from_secs(5))
+        .latency(Duration::from_millis(200), Duration::from_secs(5))
+        .partition(Duration::from_secs(2))
+        .seed(1)
+        .run(RudpConfig::default())
+        .unwrap();
+    outcome.assert_invariants();
+    assert_eq!(outcome.delivered, outcome.sent);
+    assert!(outcome.link.dropped > 0);
+    assert!(!outcome.connection_lost);
+}
+
+#[test]
+fn test_jitter_and_duplication() {
+    let conditions = Conditions {
+        latency: Duration::from_millis(30),
+        jitter: Duration::from_millis(60),
+        duplicate: 0.2,
+        loss: 0.05,
+        ..Conditions::default()
+    };
+    let outcome = Scenario::new()
+        .phase(Duration::from_secs(10), conditions)
+        .traffic(Duration::from_millis(5), 512)
+        .seed(2)
+        .run(RudpConfig::default())
+        .unwrap();
+    outcome.assert_invariants();
+    assert_eq!(outcome.delivered, outcome.sent);
+    assert!(outcome.link.duplicated > 0);
+}
+
+#[test]
+fn test_heavy_loss_bursts() {
+    let mut scenario = Scenario::new().seed(3);
+    for _ in 0..5 {
+        scenario = scenario.loss(0.8, Duration::from_millis(500)).clear(Duration::from_secs(1));
+    }
+    let outcome = scenario.run(RudpConfig::default()).unwrap();
+    outcome.assert_invariants();
+    assert_eq!(outcome.acked + outcome.failed, outcome.sent);
+}
+
+#[test]
+fn test_partition_outlasting_retransmissions() {
+    let config = RudpConfig::default();
+    let partition = config.keepalive.dead_peer_detection_time() + Duration::from_secs(5);
+    let outcome = Scenario::new()
+        .clear(Duration::from_secs(1))
+        .partition(partition)
+        .clear(Duration::from_secs(1))
+        .seed(4)
+        .run(config)
+        .unwrap();
+    outcome.assert_invariants();
+    assert!(outcome.failed > 0);
+    assert_eq!(outcome.acked + outcome.failed, outcome.sent);
+}
+
+#[test]
+fn test_identical_flows_fill_drop_tail_bottleneck() {
+    let bottleneck = Bottleneck::new(1_000_000, 64 * 1024, Duration::from_millis(20));
+    let outcome = BottleneckScenario::new(bottleneck)
+        .flow(Flow::new(RudpConfig::default()))
+        .flow(Flow::new(RudpConfig::default()))
+        .duration(Duration::from_secs(20))
+        .run()
+        .unwrap();
+    assert!(outcome.utilization() > 0.9, "utilization {}", outcome.utilization());
+    assert!(outcome.fairness() > 0.75, "fairness {}", outcome.fairness());
+    assert!(outcome.link.tail_drops > 0);
+    assert!(outcome.link.max_queue <= 64 * 1024);
+    assert!(outcome.flows.iter().all(|flow| flow.delivered_bytes > 0));
+}
+
+#[test]
+fn test_red_drops_before_queue_fills() {
+    let bottleneck = Bottleneck::new(1_000_000, 64 * 1024, Duration::from_millis(20)).with_red();
+    let scenario = BottleneckScenario::new(bottleneck)
+        .flow(Flow::new(RudpConfig::default()))
+        .flow(Flow::new(RudpConfig::default()).starting_at(Duration::from_secs(1)))
+        .duration(Duration::from_secs(10))
+        .seed(1);
+    let outcome = scenario.run().unwrap();
+    assert!(outcome.link.red_drops > outcome.link.tail_drops);
+    assert!(outcome.flows.iter().all(|flow| flow.delivered_bytes > 0));
+    let again = scenario.run().unwrap();
+    assert_eq!(outcome.link, again.link);
+    assert_eq!(outcome.flows[0].delivered_bytes, again.flows[0].delivered_bytes);
+}
@@ -1,6 +1,16 @@
-use rudpbase::Rudpbase;
+use rudpbase::{Clock, Rudpbase, Transport};
+use std::collections::VecDeque;
+use std::io;
 use std::net::SocketAddr;
-use tokio::time::{sleep, Duration};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::time::{sleep, timeout, Duration};
+
+/// Take received data, waiting at most 1ms
+async fn recv_now(rudp: &mut Rudpbase) -> Option<rudpbase::ReceivedData> {
+    rudp.recv_timeout(Duration::from_millis(1)).await
+}
 
 #[tokio::test]
 async fn test_basic_send_receive() {
@@ -27,7 +37,7 @@ async fn test_basic_send_receive() {
     for _ in 0..100 {
         receiver.tick().await;
         
-        if let Some(received) = receiver.recv().await {
+        if let Some(received) = recv_now(&mut receiver).await {
             match received.result {
                 Ok(buffer) => {
                     assert_eq!(buffer.data(), test_data);
@@ -48,6 +58,7 @@ async fn test_basic_send_receive() {
 }
 
 #[tokio::test]
+#[allow(unused_variables, unused_mut)]
 async fn test_large_message() {
     let addr1: SocketAddr = "127.0.0.1:9003".parse().unwrap();
     let addr2: SocketAddr = "127.0.0.1:9004".parse().unwrap();
@@ -98,7 +109,7 @@ async fn test_multiple_messages() {
     for _ in 0..1000 { // Timeout after 1000 iterations
         receiver.tick().await;
         
-        while let Some(_received) = receiver.recv().await {
+        while let Some(_received) = recv_now(&mut receiver).await {
             received_count += 1;
             if received_count >= message_count {
                 break;
@@ -144,7 +155,7 @@ async fn test_bidirectional_communication() {
     for _ in 0..100 {
         node1.tick().await;
         
-        if let Some(received) = node1.recv().await {
+        if let Some(received) = recv_now(&mut node1).await {
             match received.result {
                 Ok(buffer) => {
                     assert_eq!(buffer.data(), message2);
@@ -166,7 +177,7 @@ async fn test_bidirectional_communication() {
     for _ in 0..100 {
         node2.tick().await;
         
-        if let Some(received) = node2.recv().await {
+        if let Some(received) = recv_now(&mut node2).await {
             match received.result {
                 Ok(buffer) => {
                     assert_eq!(buffer.data(), message1);
@@ -210,7 +221,7 @@ async fn test_connection_statistics() {
     // Receive messages
     for _ in 0..100 {
         receiver.tick().await;
-        if let Some(_received) = receiver.recv().await {
+        if let Some(_received) = recv_now(&mut receiver).await {
             // Message received
         }
         sleep(Duration::from_millis(1)).await;
@@ -223,6 +234,7 @@ async fn test_connection_statistics() {
 }
 
 #[tokio::test]
+#[allow(unused_comparisons, clippy::absurd_extreme_comparisons)]
 async fn test_buffer_pool_stats() {
     let addr1: SocketAddr = "127.0.0.1:9011".parse().unwrap();
     let rudp = Rudpbase::new(addr1).await.unwrap();
@@ -234,4 +246,2278 @@ async fn test_buffer_pool_stats() {
     assert!(stats.total_allocations >= 0, "Total allocations should be non-negative");
     assert!(stats.pool_hits >= 0, "Pool hits should be non-negative");
     assert!(stats.pool_misses >= 0, "Pool misses should be non-negative");
-} 
\ No newline at end of file
+} 
+/// Build a STUN binding success response carrying XOR-MAPPED-ADDRESS for an IPv4 source
+fn stun_binding_response(request: &[u8], mapped: SocketAddr) -> Vec<u8> {
+    let cookie = rudpbase::stun::MAGIC_COOKIE.to_be_bytes();
+    let ip = match mapped.ip() {
+        std::net::IpAddr::V4(ip) => ip.octets(),
+        std::net::IpAddr::V6(_) => panic!("IPv4 only"),
+    };
+
+    let mut response = vec![0x01, 0x01, 0x00, 0x0C];
+    response.extend_from_slice(&request[4..20]); // cookie + transaction id
+    response.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+    response.extend_from_slice(&(mapped.port() ^ 0x2112).to_be_bytes());
+    response.extend(ip.iter().zip(cookie.iter()).map(|(a, b)| a ^ b));
+    response
+}
+
+#[tokio::test]
+async fn test_discover_public_addr() {
+    let addr: SocketAddr = "127.0.0.1:9012".parse().unwrap();
+    let stun_addr: SocketAddr = "127.0.0.1:9013".parse().unwrap();
+    let peer_addr: SocketAddr = "127.0.0.1:9014".parse().unwrap();
+
+    let mut rudp = Rudpbase::new(addr).await.unwrap();
+    let mut peer = Rudpbase::new(peer_addr).await.unwrap();
+    let stun_server = tokio::net::UdpSocket::bind(stun_addr).await.unwrap();
+
+    // Data that arrives while discovery is in progress must not be lost
+    let mut buffer = peer.get_buffer().unwrap();
+    let test_data = b"during discovery";
+    buffer.data_mut()[..test_data.len()].copy_from_slice(test_data);
+    buffer.set_data_len(test_data.len()).unwrap();
+    peer.send(buffer, addr).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        let (len, from) = stun_server.recv_from(&mut buf).await.unwrap();
+        let response = stun_binding_response(&buf[..len], from);
+        stun_server.send_to(&response, from).await.unwrap();
+    });
+
+    let public_addr = rudp.discover_public_addr(stun_addr).await.unwrap();
+    assert_eq!(public_addr, addr);
+
+    let mut received_message = false;
+    for _ in 0..100 {
+        if let Some(received) = recv_now(&mut rudp).await {
+            assert_eq!(received.result.unwrap().data(), test_data);
+            assert_eq!(received.from, peer_addr);
+            received_message = true;
+            break;
+        }
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    assert!(received_message, "Data received during discovery was lost");
+}
+
+#[tokio::test]
+async fn test_fast_dead_peer_detection() {
+    let addr: SocketAddr = "127.0.0.1:9015".parse().unwrap();
+    let silent_addr: SocketAddr = "127.0.0.1:9016".parse().unwrap();
+    let live_addr: SocketAddr = "127.0.0.1:9017".parse().unwrap();
+
+    let mut rudp = Rudpbase::new(addr).await.unwrap();
+    let mut live_peer = Rudpbase::new(live_addr).await.unwrap();
+    // Bound but never answers
+    let _silent = tokio::net::UdpSocket::bind(silent_addr).await.unwrap();
+
+    let keepalive = rudpbase::KeepAliveConfig {
+        idle_timeout: Duration::from_millis(100),
+        ping_interval: Duration::from_millis(50),
+        max_ping_failures: 2,
+        ..rudpbase::KeepAliveConfig::default()
+    };
+    rudp.set_keepalive_config(keepalive);
+
+    for target in [silent_addr, live_addr] {
+        let mut buffer = rudp.get_buffer().unwrap();
+        buffer.data_mut()[..4].copy_from_slice(b"ping");
+        buffer.set_data_len(4).unwrap();
+        rudp.send(buffer, target).await.unwrap();
+    }
+
+    // Well past idle_timeout + max_ping_failures * ping_interval
+    for _ in 0..100 {
+        rudp.tick().await;
+        let _ = recv_now(&mut rudp).await;
+        live_peer.tick().await;
+        let _ = recv_now(&mut live_peer).await;
+        sleep(Duration::from_millis(5)).await;
+    }
+
+    assert_eq!(rudp.connection_status(silent_addr), rudpbase::ConnectionStatus::Dead);
+    assert_ne!(rudp.connection_status(live_addr), rudpbase::ConnectionStatus::Dead);
+}
+
+#[tokio::test]
+async fn test_with_config() {
+    let addr1: SocketAddr = "127.0.0.1:9018".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9019".parse().unwrap();
+
+    let invalid = rudpbase::RudpConfig::new().with_initial_cwnd(0);
+    assert!(matches!(
+        Rudpbase::with_config(addr1, invalid).await,
+        Err(rudpbase::RudpError::InvalidConfig { .. })
+    ));
+
+    let security = rudpbase::SecurityConfig {
+        salt: b"private-mesh".to_vec(),
+        verify: true,
+    };
+    let config = rudpbase::RudpConfig::new()
+        .with_max_payload_size(64)
+        .with_security(security);
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+
+    // Payloads above the configured limit are rejected up front
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.set_data_len(65).unwrap();
+    assert!(matches!(
+        sender.send(buffer, addr2).await,
+        Err(rudpbase::RudpError::BufferTooLarge { size: 65, max: 64 })
+    ));
+
+    // Peers sharing the custom salt talk to each other
+    let mut buffer = sender.get_buffer().unwrap();
+    let test_data = b"salted hello";
+    buffer.data_mut()[..test_data.len()].copy_from_slice(test_data);
+    buffer.set_data_len(test_data.len()).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+
+    let mut received_message = false;
+    for _ in 0..100 {
+        if let Some(received) = recv_now(&mut receiver).await {
+            assert_eq!(received.result.unwrap().data(), test_data);
+            received_message = true;
+            break;
+        }
+        sleep(Duration::from_millis(1)).await;
+    }
+    assert!(received_message, "Message was not received");
+}
+
+#[tokio::test]
+async fn test_update_config_applies_to_live_connections() {
+    let addr: SocketAddr = "127.0.0.1:9020".parse().unwrap();
+    let silent_addr: SocketAddr = "127.0.0.1:9021".parse().unwrap();
+
+    let mut rudp = Rudpbase::new(addr).await.unwrap();
+    let _silent = tokio::net::UdpSocket::bind(silent_addr).await.unwrap();
+
+    let mut buffer = rudp.get_buffer().unwrap();
+    buffer.set_data_len(8).unwrap();
+    rudp.send(buffer, silent_addr).await.unwrap();
+
+    let invalid = rudp.config().clone().with_max_cwnd(0);
+    assert!(rudp.update_config(invalid).is_err());
+
+    let mut config = rudp.config().clone();
+    config.keepalive.idle_timeout = Duration::from_millis(50);
+    config.keepalive.ping_interval = Duration::from_millis(50);
+    config.keepalive.max_ping_failures = 1;
+    config.initial_cwnd = 4;
+    config.max_cwnd = 4;
+    rudp.update_config(config).unwrap();
+
+    let info = rudp.congestion_info(silent_addr).unwrap();
+    assert!(info.congestion_window <= 4);
+
+    for _ in 0..60 {
+        rudp.tick().await;
+        sleep(Duration::from_millis(5)).await;
+    }
+
+    assert_eq!(rudp.connection_status(silent_addr), rudpbase::ConnectionStatus::Dead);
+}
+
+#[derive(Clone, Default)]
+struct RecordingHandler {
+    delivery_failures: std::sync::Arc<std::sync::Mutex<Vec<(SocketAddr, u32)>>>,
+    auth_failures: std::sync::Arc<std::sync::Mutex<Vec<SocketAddr>>>,
+}
+
+impl rudpbase::EventHandler for RecordingHandler {
+    fn on_delivery_failed(&self, addr: SocketAddr, seq: u32) {
+        self.delivery_failures.lock().unwrap().push((addr, seq));
+    }
+
+    fn on_auth_failure(&self, addr: SocketAddr) {
+        self.auth_failures.lock().unwrap().push(addr);
+    }
+}
+
+#[tokio::test]
+async fn test_event_handler_callbacks() {
+    let addr: SocketAddr = "127.0.0.1:9022".parse().unwrap();
+    let silent_addr: SocketAddr = "127.0.0.1:9023".parse().unwrap();
+
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(10), Duration::from_millis(20))
+        .with_initial_rto(Duration::from_millis(10))
+        .with_max_retries(1);
+    let mut rudp = Rudpbase::with_config(addr, config).await.unwrap();
+    let handler = RecordingHandler::default();
+    rudp.set_event_handler(handler.clone());
+
+    // A forged packet with a bad security code
+    let silent = tokio::net::UdpSocket::bind(silent_addr).await.unwrap();
+    let mut forged = vec![2u8];
+    forged.extend_from_slice(&0xDEADBEEFu32.to_be_bytes());
+    forged.extend_from_slice(&1u32.to_be_bytes());
+    forged.extend_from_slice(b"forged");
+    silent.send_to(&forged, addr).await.unwrap();
+
+    let mut buffer = rudp.get_buffer().unwrap();
+    buffer.set_data_len(4).unwrap();
+    rudp.send(buffer, silent_addr).await.unwrap();
+
+    let mut security_error = false;
+    for _ in 0..50 {
+        rudp.tick().await;
+        if let Some(received) = recv_now(&mut rudp).await {
+            security_error |= matches!(received.result, Err(rudpbase::RudpError::Security));
+        }
+        sleep(Duration::from_millis(2)).await;
+    }
+
+    assert!(security_error, "Forged packet was not rejected");
+    assert_eq!(*handler.auth_failures.lock().unwrap(), vec![silent_addr]);
+    assert_eq!(*handler.delivery_failures.lock().unwrap(), vec![(silent_addr, 0)]);
+}
+
+#[tokio::test]
+async fn test_qlog_events() {
+    let addr1: SocketAddr = "127.0.0.1:9024".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9025".parse().unwrap();
+
+    let mut sender = Rudpbase::new(addr1).await.unwrap();
+    let mut receiver = Rudpbase::new(addr2).await.unwrap();
+
+    let records = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let collected = records.clone();
+    sender.set_qlog_sink(move |record: &rudpbase::qlog::QlogRecord| {
+        collected.lock().unwrap().push(record.clone());
+    });
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"qlog!");
+    buffer.set_data_len(5).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+
+    for _ in 0..50 {
+        receiver.tick().await;
+        let _ = recv_now(&mut receiver).await;
+        let _ = recv_now(&mut sender).await;
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    let records = records.lock().unwrap();
+    let names: Vec<&str> = records.iter().map(|r| r.event.name()).collect();
+    assert_eq!(names[0], "transport:packet_sent");
+    assert!(names.contains(&"transport:packet_received"));
+    assert!(names.contains(&"recovery:packet_acked"));
+    assert!(names.contains(&"recovery:metrics_updated"));
+    assert!(records.iter().all(|r| r.peer == addr2));
+    assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+}
+
+#[tokio::test]
+async fn test_global_stats() {
+    let addr: SocketAddr = "127.0.0.1:9026".parse().unwrap();
+    let peer1: SocketAddr = "127.0.0.1:9027".parse().unwrap();
+    let peer2: SocketAddr = "127.0.0.1:9028".parse().unwrap();
+
+    let mut sender = Rudpbase::new(addr).await.unwrap();
+    let mut receiver1 = Rudpbase::new(peer1).await.unwrap();
+    let mut receiver2 = Rudpbase::new(peer2).await.unwrap();
+
+    for (target, len) in [(peer1, 10), (peer2, 20), (peer2, 30)] {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.set_data_len(len).unwrap();
+        sender.send(buffer, target).await.unwrap();
+    }
+
+    let global = sender.global_stats().unwrap();
+    assert_eq!(global.active_connections, 2);
+    assert_eq!(global.packets_sent, 3);
+    assert_eq!(global.bytes_sent, 60);
+    assert_eq!(global.pending_packets, 3);
+
+    let mut peers: Vec<(SocketAddr, u64)> = sender.iter_stats().map(|(a, s)| (a, s.bytes_sent)).collect();
+    peers.sort();
+    assert_eq!(peers, vec![(peer1, 10), (peer2, 50)]);
+
+    for _ in 0..50 {
+        let _ = recv_now(&mut receiver1).await;
+        let _ = recv_now(&mut receiver2).await;
+        receiver1.tick().await;
+        receiver2.tick().await;
+        let _ = recv_now(&mut sender).await;
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+    assert_eq!(receiver2.global_stats().unwrap().bytes_received, 50);
+}
+
+#[tokio::test]
+async fn test_batched_io() {
+    let addr1: SocketAddr = "127.0.0.1:9029".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9030".parse().unwrap();
+    let config = rudpbase::RudpConfig::new().with_io_batch_size(16);
+
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+
+    for i in 0..8u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+    }
+
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        while let Some(data) = recv_now(&mut receiver).await {
+            received.push(data.result.unwrap().data()[0]);
+        }
+        receiver.tick().await;
+        let _ = recv_now(&mut sender).await;
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    received.sort();
+    assert_eq!(received, (0..8).collect::<Vec<u8>>());
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+#[tokio::test]
+async fn test_udp_offload_burst() {
+    let addr1: SocketAddr = "127.0.0.1:9031".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9032".parse().unwrap();
+    let config = rudpbase::RudpConfig::new()
+        .with_io_batch_size(32)
+        .with_udp_offload(true);
+
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+
+    // A burst of MTU-sized packets to one peer, flushed as a single batch
+    for i in 0..10u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut().fill(i);
+        buffer.set_data_len(rudpbase::buffer_pool::MAX_PAYLOAD_SIZE).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+    }
+    sender.flush().await;
+
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        while let Some(data) = recv_now(&mut receiver).await {
+            let buffer = data.result.unwrap();
+            assert_eq!(buffer.data_len(), rudpbase::buffer_pool::MAX_PAYLOAD_SIZE);
+            assert!(buffer.data().iter().all(|&b| b == buffer.data()[0]));
+            received.push(buffer.data()[0]);
+        }
+        receiver.tick().await;
+        let _ = recv_now(&mut sender).await;
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    received.sort();
+    assert_eq!(received, (0..10).collect::<Vec<u8>>());
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+#[tokio::test]
+async fn test_recv_batch() {
+    let addr1: SocketAddr = "127.0.0.1:9033".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9034".parse().unwrap();
+
+    let mut sender = Rudpbase::new(addr1).await.unwrap();
+    let mut receiver = Rudpbase::new(addr2).await.unwrap();
+
+    for i in 0..10u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+    }
+    sleep(Duration::from_millis(20)).await;
+
+    // All datagrams are already readable: one call drains up to `max`
+    let first = receiver.recv_batch(4).await;
+    assert_eq!(first.len(), 4);
+
+    let rest = receiver.recv_batch(64).await;
+    assert_eq!(rest.len(), 6);
+
+    let mut received: Vec<u8> = first.into_iter().chain(rest)
+        .map(|data| data.result.unwrap().data()[0])
+        .collect();
+    received.sort();
+    assert_eq!(received, (0..10).collect::<Vec<u8>>());
+
+    assert!(receiver.recv_batch(64).await.is_empty());
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[tokio::test]
+async fn test_io_uring_backend() {
+    let addr1: SocketAddr = "127.0.0.1:9035".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9036".parse().unwrap();
+    let config = rudpbase::RudpConfig::new()
+        .with_initial_cwnd(64)
+        .with_io_batch_size(16)
+        .with_io_backend(rudpbase::IoBackend::IoUring);
+
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+
+    for i in 0..40u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+    }
+    sender.flush().await;
+
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        for data in receiver.recv_batch(64).await {
+            received.push(data.result.unwrap().data()[0]);
+        }
+        receiver.tick().await;
+        let _ = recv_now(&mut sender).await;
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    received.sort();
+    assert_eq!(received, (0..40).collect::<Vec<u8>>());
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_peer_source_addr() {
+    let sender_addr: SocketAddr = "0.0.0.0:9037".parse().unwrap();
+    let receiver_addr: SocketAddr = "127.0.0.1:9038".parse().unwrap();
+
+    let mut sender = Rudpbase::new(sender_addr).await.unwrap();
+    let mut receiver = Rudpbase::new(receiver_addr).await.unwrap();
+
+    // IPv4 source for an IPv6 peer is rejected
+    assert!(sender.set_peer_source_addr("[::1]:9038".parse().unwrap(), "127.0.0.2".parse().unwrap()).is_err());
+    // A socket bound to a specific address cannot pick its source
+    assert!(receiver.set_peer_source_addr(sender_addr, "127.0.0.2".parse().unwrap()).is_err());
+
+    sender.set_peer_source_addr(receiver_addr, "127.0.0.2".parse().unwrap()).unwrap();
+    assert_eq!(sender.peer_source_addr(receiver_addr), Some("127.0.0.2".parse().unwrap()));
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"hello");
+    buffer.set_data_len(5).unwrap();
+    sender.send(buffer, receiver_addr).await.unwrap();
+
+    let mut received = None;
+    for _ in 0..100 {
+        received = recv_now(&mut receiver).await;
+        if received.is_some() {
+            break;
+        }
+        sleep(Duration::from_millis(1)).await;
+    }
+    let received = received.expect("message was not received");
+    assert_eq!(received.from, "127.0.0.2:9037".parse::<SocketAddr>().unwrap());
+    assert_eq!(received.result.unwrap().data(), b"hello");
+}
+
+/// In-memory datagram queue of one endpoint
+#[derive(Default)]
+struct Inbox {
+    datagrams: VecDeque<(Vec<u8>, SocketAddr)>,
+    waker: Option<Waker>,
+}
+
+/// Transport over in-memory queues that drops the first `drop_sends` datagrams
+struct MemoryTransport {
+    addr: SocketAddr,
+    inbox: Arc<Mutex<Inbox>>,
+    peer_inbox: Arc<Mutex<Inbox>>,
+    drop_sends: AtomicUsize,
+}
+
+impl Transport for MemoryTransport {
+    fn poll_send_to(&self, _cx: &mut Context<'_>, buf: &[u8], _target: SocketAddr) -> Poll<io::Result<usize>> {
+        let dropped = self.drop_sends
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        if !dropped {
+            let mut inbox = self.peer_inbox.lock().unwrap();
+            inbox.datagrams.push_back((buf.to_vec(), self.addr));
+            if let Some(waker) = inbox.waker.take() {
+                waker.wake();
+            }
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut inbox = self.inbox.lock().unwrap();
+        match inbox.datagrams.pop_front() {
+            Some((data, from)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Poll::Ready(Ok((len, from)))
+            }
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+#[tokio::test]
+async fn test_custom_transport_retransmission() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let inbox1 = Arc::new(Mutex::new(Inbox::default()));
+    let inbox2 = Arc::new(Mutex::new(Inbox::default()));
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_secs(1))
+        .with_initial_rto(Duration::from_millis(20));
+
+    // The first data packet is lost on the way to the receiver
+    let sender_transport = MemoryTransport {
+        addr: addr1,
+        inbox: inbox1.clone(),
+        peer_inbox: inbox2.clone(),
+        drop_sends: AtomicUsize::new(1),
+    };
+    let receiver_transport = MemoryTransport {
+        addr: addr2,
+        inbox: inbox2,
+        peer_inbox: inbox1,
+        drop_sends: AtomicUsize::new(0),
+    };
+    let mut sender = Rudpbase::with_transport(sender_transport, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(receiver_transport, config.clone()).await.unwrap();
+    assert_eq!(sender.local_addr().unwrap(), addr1);
+
+    // Batched I/O needs a real socket
+    assert!(sender.update_config(config.with_io_batch_size(8)).is_err());
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..4].copy_from_slice(b"lost");
+    buffer.set_data_len(4).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+    assert!(recv_now(&mut receiver).await.is_none());
+
+    sleep(Duration::from_millis(30)).await;
+    sender.tick().await;
+
+    let received = recv_now(&mut receiver).await.expect("retransmission was not received");
+    assert_eq!(received.from, addr1);
+    assert_eq!(received.result.unwrap().data(), b"lost");
+    assert_eq!(sender.get_stats(addr2).unwrap().retransmissions, 1);
+}
+
+#[tokio::test]
+async fn test_recv_waits_for_readiness() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let inbox1 = Arc::new(Mutex::new(Inbox::default()));
+    let inbox2 = Arc::new(Mutex::new(Inbox::default()));
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_secs(1))
+        .with_initial_rto(Duration::from_millis(20));
+
+    // The first data packet is lost on the way to the receiver
+    let sender_transport = MemoryTransport {
+        addr: addr1,
+        inbox: inbox1.clone(),
+        peer_inbox: inbox2.clone(),
+        drop_sends: AtomicUsize::new(1),
+    };
+    let receiver_transport = MemoryTransport {
+        addr: addr2,
+        inbox: inbox2,
+        peer_inbox: inbox1,
+        drop_sends: AtomicUsize::new(0),
+    };
+    let mut sender = Rudpbase::with_transport(sender_transport, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(receiver_transport, config).await.unwrap();
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..4].copy_from_slice(b"lost");
+    buffer.set_data_len(4).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+
+    // Nothing has arrived, so readable() keeps waiting instead of returning
+    tokio::select! {
+        _ = receiver.readable() => panic!("the only packet was lost"),
+        _ = sleep(Duration::from_millis(5)) => {}
+    }
+
+    // Waiting to receive runs the sender's retransmission timer without tick()
+    let received = tokio::select! {
+        received = std::future::poll_fn(|cx| receiver.poll_recv(cx)) => received,
+        _ = sender.recv() => panic!("the sender receives no data"),
+    };
+    assert_eq!(received.from, addr1);
+    assert_eq!(received.result.unwrap().data(), b"lost");
+    assert_eq!(sender.get_stats(addr2).unwrap().retransmissions, 1);
+
+    // The acknowledgment is sent and processed while both sides wait
+    let _ = timeout(Duration::from_millis(50), async { tokio::join!(receiver.recv(), sender.recv()) }).await;
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+#[tokio::test]
+async fn test_recv_from_buffers_other_peers() {
+    let client_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let server_addr: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let other_addr: SocketAddr = "10.0.0.3:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let bind = |addr| Rudpbase::with_transport(network.bind(addr).unwrap(), rudpbase::RudpConfig::default());
+    let mut client = bind(client_addr).await.unwrap();
+    let mut server = bind(server_addr).await.unwrap();
+    let mut other = bind(other_addr).await.unwrap();
+    assert!(client.recv_timeout(Duration::from_millis(5)).await.is_none());
+
+    let send = |rudp: &Rudpbase, data: &[u8]| {
+        let mut buffer = rudp.get_buffer().unwrap();
+        buffer.data_mut()[..data.len()].copy_from_slice(data);
+        buffer.set_data_len(data.len()).unwrap();
+        buffer
+    };
+    other.send(send(&other, b"unrelated"), client_addr).await.unwrap();
+    server.send(send(&server, b"response"), client_addr).await.unwrap();
+
+    // The response is taken out of order; the other peer's packet waits for recv()
+    let response = client.recv_from(server_addr).await;
+    assert_eq!(response.result.unwrap().data(), b"response");
+    let unrelated = client.recv_timeout(Duration::from_millis(5)).await.expect("buffered packet lost");
+    assert_eq!(unrelated.from, other_addr);
+    assert_eq!(unrelated.result.unwrap().data(), b"unrelated");
+    assert!(client.recv_timeout(Duration::from_millis(5)).await.is_none());
+}
+
+#[tokio::test]
+async fn test_loopback_many_exchanges() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    for i in 0..2000u32 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[..4].copy_from_slice(&i.to_be_bytes());
+        buffer.set_data_len(4).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+
+        let received = recv_now(&mut receiver).await.expect("datagram delivered immediately");
+        assert_eq!(received.from, addr1);
+        assert_eq!(received.result.unwrap().data(), &i.to_be_bytes());
+
+        // Flush the acknowledgment and process it so the congestion window never fills
+        receiver.tick().await;
+        assert!(recv_now(&mut sender).await.is_none());
+    }
+
+    let stats = sender.get_stats(addr2).unwrap();
+    assert_eq!(stats.packets_sent, 2000);
+    assert_eq!(stats.retransmissions, 0);
+}
+
+#[cfg(feature = "bytes")]
+#[tokio::test]
+async fn test_send_and_receive_bytes() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let payload = bytes::Bytes::from_static(b"shared payload");
+    sender.send_bytes(payload.clone(), addr2).await.unwrap();
+
+    let received = recv_now(&mut receiver).await.unwrap();
+    let data: bytes::Bytes = received.result.unwrap().into();
+    assert_eq!(data, payload);
+
+    receiver.tick().await;
+    assert!(recv_now(&mut sender).await.is_none());
+    assert_eq!(sender.get_stats(addr2).unwrap().bytes_acked, payload.len() as u64);
+
+    let too_large = bytes::Bytes::from(vec![0u8; sender.config().max_payload_size + 1]);
+    assert!(matches!(
+        sender.send_bytes(too_large, addr2).await,
+        Err(rudpbase::RudpError::BufferTooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_send_vectored() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let header = 7u16.to_be_bytes();
+    let parts = [io::IoSlice::new(&header), io::IoSlice::new(b"body"), io::IoSlice::new(b""), io::IoSlice::new(b"!")];
+    sender.send_vectored(&parts, addr2).await.unwrap();
+
+    let received = recv_now(&mut receiver).await.unwrap();
+    assert_eq!(received.result.unwrap().data(), b"\x00\x07body!");
+
+    let max = sender.config().max_payload_size;
+    let half = vec![0u8; max / 2 + 1];
+    assert!(matches!(
+        sender.send_vectored(&[io::IoSlice::new(&half), io::IoSlice::new(&half)], addr2).await,
+        Err(rudpbase::RudpError::BufferTooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_large_message_over_lossy_sim() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let lossy = |transport, seed| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        loss: 0.1,
+        reorder: 0.2,
+        reorder_window: Duration::from_millis(2),
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    });
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(lossy(a, 1), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(lossy(b, 2), config).await.unwrap();
+
+    // Far more fragments than the initial congestion window holds
+    let message: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    sender.send_message(&message, addr2).await.unwrap();
+    sender.send_message(b"small", addr2).await.unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0..2000 {
+        if let Some(message) = receiver.recv_message().await {
+            assert_eq!(message.from, addr1);
+            received.push(message.data);
+        }
+        if received.len() == 2 {
+            break;
+        }
+        receiver.tick().await;
+        sender.tick().await;
+        while recv_now(&mut sender).await.is_some() {}
+    }
+    received.sort_by_key(Vec::len);
+    assert_eq!(received, vec![b"small".to_vec(), message]);
+    assert!(recv_now(&mut receiver).await.is_none());
+
+    let too_large = vec![0u8; sender.config().max_message_size + 1];
+    assert!(matches!(
+        sender.send_message(&too_large, addr2).await,
+        Err(rudpbase::RudpError::BufferTooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_from_pre_bound_sockets() {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    // A socket2 socket configured by the application before binding
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+    socket.set_reuse_address(true).unwrap();
+    socket.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+    let mut receiver = Rudpbase::from_std(socket, rudpbase::RudpConfig::default()).await.unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let sender_addr = socket.local_addr().unwrap();
+    let mut sender = Rudpbase::from_socket(socket, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"bound");
+    buffer.set_data_len(5).unwrap();
+    sender.send(buffer, receiver_addr).await.unwrap();
+
+    let received = timeout(Duration::from_secs(1), receiver.recv()).await.unwrap();
+    assert_eq!(received.from, sender_addr);
+    assert_eq!(received.result.unwrap().data(), b"bound");
+}
+
+#[tokio::test]
+async fn test_instances_share_buffer_pool() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let pool = rudpbase::SharedBufferPool::with_limits(8, 64, rudpbase::buffer_pool::DEFAULT_BUFFER_SIZE);
+    let config = rudpbase::RudpConfig::default();
+    let mut sender = Rudpbase::with_transport_and_pool(a, config.clone(), pool.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport_and_pool(b, config.clone(), pool.clone()).await.unwrap();
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"hello");
+    buffer.set_data_len(5).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+    assert_eq!(receiver.get_buffer_pool_stats().unwrap().free_count, 7);
+
+    let received = recv_now(&mut receiver).await.unwrap();
+    assert_eq!(received.result.unwrap().data(), b"hello");
+    receiver.tick().await;
+    assert!(recv_now(&mut sender).await.is_none());
+    // Every payload buffer is back, next to the small one the acknowledgment used
+    let stats = pool.stats().unwrap();
+    assert_eq!(stats.free_by_class, [1, 0, 8, 0]);
+
+    // The pool's buffers must fit the configured payload size
+    let small_pool = rudpbase::SharedBufferPool::with_limits(0, 64, 256);
+    let (c, _d) = rudpbase::LoopbackTransport::pair("10.0.0.3:1000".parse().unwrap(), "10.0.0.4:1000".parse().unwrap());
+    assert!(matches!(
+        Rudpbase::with_transport_and_pool(c, config, small_pool).await,
+        Err(rudpbase::RudpError::InvalidConfig { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_delivery_over_lossy_sim() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let impairments = |seed| rudpbase::SimConfig {
+        loss: 0.2,
+        delay: rudpbase::Delay::Uniform { min: Duration::from_millis(1), max: Duration::from_millis(3) },
+        duplicate: 0.05,
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    };
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20))
+        .with_max_retries(20);
+
+    let mut sender = Rudpbase::with_transport(rudpbase::SimTransport::new(a, impairments(1)), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(rudpbase::SimTransport::new(b, impairments(2)), config).await.unwrap();
+
+    let total = 100u32;
+    let mut next = 0u32;
+    let mut received = std::collections::BTreeSet::new();
+    for _ in 0..5000 {
+        while next < total {
+            let mut buffer = sender.get_buffer().unwrap();
+            buffer.data_mut()[..4].copy_from_slice(&next.to_be_bytes());
+            buffer.set_data_len(4).unwrap();
+            match sender.send(buffer, addr2).await {
+                Ok(_) => next += 1,
+                Err(rudpbase::RudpError::CongestionWindowFull) => break,
+                Err(e) => panic!("send failed: {}", e),
+            }
+        }
+
+        while let Some(data) = recv_now(&mut receiver).await {
+            let payload = data.result.unwrap();
+            received.insert(u32::from_be_bytes(payload.data()[..4].try_into().unwrap()));
+        }
+        receiver.tick().await;
+        while recv_now(&mut sender).await.is_some() {}
+        sender.tick().await;
+
+        if received.len() == total as usize {
+            break;
+        }
+    }
+
+    assert_eq!(received.len(), total as usize, "not every message survived the lossy link");
+    assert!(sender.get_stats(addr2).unwrap().retransmissions > 0);
+}
+
+#[tokio::test]
+async fn test_delivery_over_corrupting_sim() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let impairments = |seed| rudpbase::SimConfig {
+        corrupt: 0.1,
+        truncate: 0.05,
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    };
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20))
+        .with_max_retries(20)
+        .with_payload_checksum(true);
+
+    let sender_transport = rudpbase::SimTransport::new(a, impairments(5));
+    let receiver_transport = rudpbase::SimTransport::new(b, impairments(6));
+    let mut sender = Rudpbase::with_transport(sender_transport, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(receiver_transport, config).await.unwrap();
+
+    let payload = |i: u32| -> Vec<u8> { (0..64u32).map(|j| (i * 7 + j) as u8).collect() };
+    let total = 100u32;
+    let mut next = 0u32;
+    let mut received = std::collections::BTreeSet::new();
+    let mut errors = 0;
+    for _ in 0..5000 {
+        while next < total {
+            let data = payload(next);
+            let mut buffer = sender.get_buffer().unwrap();
+            buffer.data_mut()[..4].copy_from_slice(&next.to_be_bytes());
+            buffer.data_mut()[4..data.len()].copy_from_slice(&data[4..]);
+            buffer.set_data_len(data.len()).unwrap();
+            match sender.send(buffer, addr2).await {
+                Ok(_) => next += 1,
+                Err(rudpbase::RudpError::CongestionWindowFull) => break,
+                Err(e) => panic!("send failed: {}", e),
+            }
+        }
+
+        while let Some(data) = recv_now(&mut receiver).await {
+            // Packets failing verification are reported, not delivered
+            let Ok(buffer) = data.result else {
+                errors += 1;
+                continue;
+            };
+            let i = u32::from_be_bytes(buffer.data()[..4].try_into().unwrap());
+            assert_eq!(buffer.data()[4..], payload(i)[4..], "corrupted payload delivered");
+            received.insert(i);
+        }
+        receiver.tick().await;
+        while recv_now(&mut sender).await.is_some() {}
+        sender.tick().await;
+
+        if received.len() == total as usize {
+            break;
+        }
+    }
+
+    assert_eq!(received.len(), total as usize, "not every message survived the corrupting link");
+    let invalid = receiver.global_stats().unwrap().invalid_packets;
+    assert!(errors > 0);
+    assert!(invalid.total() > 0);
+    assert!(invalid.checksum_failures > 0);
+}
+
+#[tokio::test]
+async fn test_rtt_samples_skip_retransmissions_on_lossy_sim() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    // 50ms round trip, but the initial RTO fires after 20ms
+    let impairments = |seed| rudpbase::SimConfig {
+        loss: 0.2,
+        delay: rudpbase::Delay::Fixed(Duration::from_millis(25)),
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    };
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(500))
+        .with_initial_rto(Duration::from_millis(20))
+        .with_max_retries(20);
+
+    let mut sender = Rudpbase::with_transport(rudpbase::SimTransport::new(a, impairments(3)), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(rudpbase::SimTransport::new(b, impairments(4)), config).await.unwrap();
+
+    let total = 30u32;
+    for i in 0..total {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[..4].copy_from_slice(&i.to_be_bytes());
+        buffer.set_data_len(4).unwrap();
+        sender.send(buffer, addr2).await.unwrap();
+    }
+
+    let mut received = 0;
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while sender.pending_count(addr2) > 0 && tokio::time::Instant::now() < deadline {
+        while recv_now(&mut receiver).await.is_some() {
+            received += 1;
+        }
+        receiver.tick().await;
+        while recv_now(&mut sender).await.is_some() {}
+        sender.tick().await;
+    }
+    assert_eq!(sender.pending_count(addr2), 0, "not every packet was acknowledged");
+    assert!(received > 0);
+    assert!(sender.get_stats(addr2).unwrap().retransmissions > 0);
+
+    // Samples from retransmitted packets would measure from the last copy and come
+    // out shorter than the link's round trip
+    let rtt_stats = sender.rtt_stats(addr2).unwrap();
+    let shortest = rtt_stats.rtt_histogram.percentile(0.0).expect("no RTT sample taken");
+    assert!(shortest >= Duration::from_millis(45), "RTT sample of {:?} below the round trip", shortest);
+    assert!(rtt_stats.rto >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_mock_clock_backoff_and_keepalive() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+    // The peer is a bare transport that never answers, so every datagram stays queued
+    let (a, peer) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut rudp = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let clock = rudpbase::MockClock::new();
+    rudp.set_clock(clock.clone());
+
+    let mut buffer = rudp.get_buffer().unwrap();
+    buffer.data_mut()[0] = 1;
+    buffer.set_data_len(1).unwrap();
+    rudp.send(buffer, addr2).await.unwrap();
+    assert_eq!(peer.queued(), 1);
+
+    // Initial RTO is 200ms, doubled after every retransmission
+    clock.advance(Duration::from_millis(199));
+    rudp.tick().await;
+    assert_eq!(peer.queued(), 1);
+    clock.advance(Duration::from_millis(1));
+    rudp.tick().await;
+    assert_eq!(peer.queued(), 2);
+
+    clock.advance(Duration::from_millis(399));
+    rudp.tick().await;
+    assert_eq!(peer.queued(), 2);
+    clock.advance(Duration::from_millis(1));
+    rudp.tick().await;
+    assert_eq!(peer.queued(), 3);
+    assert_eq!(rudp.get_stats(addr2).unwrap().retransmissions, 2);
+
+    // Keep-alive: ping once idle (since the send at t=0) for longer than
+    // idle_timeout, dead after repeated ping failures
+    let keepalive = rudpbase::KeepAliveConfig {
+        idle_timeout: Duration::from_secs(1),
+        ping_interval: Duration::from_millis(500),
+        max_ping_failures: 2,
+        max_retries: 0,
+    };
+    rudp.set_peer_keepalive_config(addr2, keepalive);
+    clock.advance(Duration::from_millis(400));
+    rudp.tick().await;
+    assert_ne!(rudp.connection_status(addr2), rudpbase::ConnectionStatus::Probing);
+
+    clock.advance(Duration::from_millis(1));
+    rudp.tick().await;
+    assert_eq!(rudp.connection_status(addr2), rudpbase::ConnectionStatus::Probing);
+
+    clock.advance(Duration::from_millis(501));
+    rudp.tick().await;
+    clock.advance(Duration::from_millis(501));
+    rudp.tick().await;
+    assert_eq!(rudp.connection_status(addr2), rudpbase::ConnectionStatus::Dead);
+}
+
+#[cfg(feature = "transfer")]
+#[tokio::test]
+async fn test_stream_transfer_with_progress_and_resume() {
+    use rudpbase::transfer::{self, TransferOptions};
+
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let lossy = |transport, seed| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        loss: 0.05,
+        reorder: 0.2,
+        reorder_window: Duration::from_millis(2),
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    });
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(lossy(a, 3), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(lossy(b, 4), config).await.unwrap();
+
+    let source: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let options = TransferOptions {
+        chunk_size: 4096,
+        window: 4,
+        timeout: Duration::from_secs(10),
+        total_len: Some(source.len() as u64),
+        ..TransferOptions::default()
+    };
+
+    let mut reports = Vec::new();
+    let mut output = Vec::new();
+    let (sent, received) = tokio::join!(
+        transfer::send_stream(&mut sender, &source[..], addr2, &options, |progress| reports.push(progress)),
+        transfer::recv_stream(&mut receiver, &mut output, 0, &options, |_| {}),
+    );
+    assert_eq!(sent.unwrap(), source.len() as u64);
+    let received = received.unwrap();
+    assert_eq!((received.from, received.len), (addr1, source.len() as u64));
+    assert_eq!(output, source);
+    assert!(reports.windows(2).all(|pair| pair[0].transferred < pair[1].transferred));
+    assert_eq!(reports.last().unwrap().transferred, source.len() as u64);
+    assert_eq!(reports.last().unwrap().total, Some(source.len() as u64));
+
+    // Resume from a partial copy: only the missing tail is sent. A message sent
+    // alongside the transfer is kept for recv_message()
+    sender.send_message(b"aside", addr2).await.unwrap();
+    let mut partial = source[..150_000].to_vec();
+    let mut first_report = None;
+    let (sent, received) = tokio::join!(
+        transfer::send_stream(&mut sender, &source[..], addr2, &options, |progress| {
+            first_report.get_or_insert(progress.transferred);
+        }),
+        transfer::recv_stream(&mut receiver, &mut partial, 150_000, &options, |_| {}),
+    );
+    assert_eq!(sent.unwrap(), source.len() as u64);
+    assert_eq!(received.unwrap().len, source.len() as u64);
+    assert_eq!(partial, source);
+    assert_eq!(first_report, Some(150_000));
+
+    let mut message = None;
+    for _ in 0..2000 {
+        message = receiver.recv_message().await;
+        if message.is_some() {
+            break;
+        }
+        sender.tick().await;
+        receiver.tick().await;
+    }
+    assert_eq!(message.unwrap().data, b"aside");
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[tokio::test]
+async fn test_compression_negotiated_per_peer() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let addr3: SocketAddr = "10.0.0.3:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let compressed = rudpbase::RudpConfig::new().with_compression(Some(rudpbase::CompressionConfig::default()));
+    let mut sender = Rudpbase::with_transport(network.bind(addr1).unwrap(), compressed.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(network.bind(addr2).unwrap(), compressed).await.unwrap();
+    let mut plain = Rudpbase::with_transport(network.bind(addr3).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+
+    let payload: Vec<u8> = b"compressible ".iter().copied().cycle().take(1000).collect();
+    let send = |rudp: &Rudpbase| {
+        let mut buffer = rudp.get_buffer().unwrap();
+        buffer.data_mut()[..payload.len()].copy_from_slice(&payload);
+        buffer.set_data_len(payload.len()).unwrap();
+        buffer
+    };
+
+    // The first packet to each peer goes out before the ping exchange completes
+    for target in [addr2, addr3] {
+        sender.send(send(&sender), target).await.unwrap();
+    }
+    for peer in [&mut receiver, &mut plain] {
+        assert_eq!(recv_data(peer).await, payload);
+    }
+    assert_eq!(sender.get_stats(addr2).unwrap().bytes_sent, payload.len() as u64);
+    for _ in 0..4 {
+        recv_now(&mut sender).await;
+    }
+
+    // Negotiated: only the peer that offers compression gets compressed packets
+    for target in [addr2, addr3] {
+        sender.send(send(&sender), target).await.unwrap();
+    }
+    sender.send_message(&payload, addr2).await.unwrap();
+    assert_eq!(recv_data(&mut receiver).await, payload);
+    assert_eq!(receiver.recv_message().await.unwrap().data, payload);
+    assert_eq!(recv_data(&mut plain).await, payload);
+    assert!(sender.get_stats(addr2).unwrap().bytes_sent < 2 * payload.len() as u64);
+    assert_eq!(sender.get_stats(addr3).unwrap().bytes_sent, 2 * payload.len() as u64);
+
+    // Payloads shared between packets are compressed as well
+    assert!(sender.send_to_all(send(&sender), &[addr2, addr3]).await.unwrap().iter().all(Result::is_ok));
+    #[cfg(feature = "bytes")]
+    sender.send_bytes(bytes::Bytes::from(payload.clone()), addr2).await.unwrap();
+    let shared = if cfg!(feature = "bytes") { 2 } else { 1 };
+    for _ in 0..shared {
+        assert_eq!(recv_data(&mut receiver).await, payload);
+    }
+    assert_eq!(recv_data(&mut plain).await, payload);
+    assert!(sender.get_stats(addr2).unwrap().bytes_sent < (2 + shared) * payload.len() as u64 / 2);
+    assert_eq!(sender.get_stats(addr3).unwrap().bytes_sent, 3 * payload.len() as u64);
+
+    // Everything was acknowledged
+    receiver.tick().await;
+    plain.tick().await;
+    for _ in 0..4 {
+        recv_now(&mut sender).await;
+    }
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+/// Receive the next data packet, skipping control packets
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+async fn recv_data(rudp: &mut Rudpbase) -> Vec<u8> {
+    for _ in 0..10 {
+        if let Some(received) = recv_now(rudp).await {
+            return received.result.unwrap().data().to_vec();
+        }
+    }
+    panic!("no data received");
+}
+
+#[tokio::test]
+async fn test_receive_packet_with_extension_section() {
+    use rudpbase::protocol::{Extension, RawPacket, EXTENSION_TIMESTAMP};
+    use rudpbase::PacketType;
+    use rudpbase::Transport;
+
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (raw, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    // A newer peer attaches an extension this version does not interpret
+    let mut extensions = Vec::new();
+    Extension { kind: EXTENSION_TIMESTAMP, value: &[0, 0, 0, 42] }.encode(&mut extensions).unwrap();
+    let mut packet = RawPacket {
+        packet_type: PacketType::Data,
+        security_code: 0,
+        seq: 1,
+        extensions,
+        data: b"payload".to_vec(),
+    };
+    packet.security_code = rudpbase::SecurityCode::calculate(PacketType::Data, packet.seq, &packet.body());
+    let datagram = packet.serialize();
+    std::future::poll_fn(|cx| raw.poll_send_to(cx, &datagram, addr2)).await.unwrap();
+
+    let received = recv_now(&mut receiver).await.unwrap();
+    assert_eq!(received.from, addr1);
+    assert_eq!(received.result.unwrap().data(), b"payload");
+}
+
+#[tokio::test]
+async fn test_close_graceful_flushes_pending_data() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let lossy = |transport, seed| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        loss: 0.1,
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    });
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(lossy(a, 5), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(lossy(b, 6), config).await.unwrap();
+
+    // More fragments than the congestion window holds are still queued at close time
+    let message: Vec<u8> = (0..50_000u32).map(|i| i as u8).collect();
+    sender.send_message(&message, addr2).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let mut received = Vec::new();
+    let (result, ()) = tokio::join!(
+        async {
+            let result = sender.close_graceful(Duration::from_secs(10)).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                receiver.tick().await;
+                if let Some(message) = receiver.recv_message().await {
+                    received.push(message.data);
+                }
+            }
+        },
+    );
+    result.unwrap();
+    assert_eq!(received, vec![message]);
+    assert!(sender.get_stats(addr2).is_none());
+
+    // The instance accepts data again once closed
+    sender.send_message(b"again", addr2).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_close_graceful_times_out_on_silent_peer() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, _silent) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"hello");
+    buffer.set_data_len(5).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+
+    let started = std::time::Instant::now();
+    assert!(matches!(
+        sender.close_graceful(Duration::from_millis(100)).await,
+        Err(rudpbase::RudpError::Timeout)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+}
+
+#[tokio::test]
+async fn test_wait_acked_resolves_once_peer_acknowledged() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let lossy = |transport, seed| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        loss: 0.2,
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    });
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(lossy(a, 7), config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(lossy(b, 8), config).await.unwrap();
+
+    let message: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+    sender.send_message(&message, addr2).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let mut received = Vec::new();
+    let (result, ()) = tokio::join!(
+        async {
+            let result = sender.wait_acked(addr2).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                receiver.tick().await;
+                if let Some(message) = receiver.recv_message().await {
+                    received.push(message.data);
+                }
+            }
+        },
+    );
+    result.unwrap();
+    assert_eq!(received, vec![message]);
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+    // Nothing is outstanding anywhere, so the global variant returns immediately
+    sender.wait_all_acked().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_acked_fails_when_delivery_fails() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, _silent) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let config = rudpbase::RudpConfig::new()
+        .with_max_retries(2)
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(50))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(a, config).await.unwrap();
+
+    sender.send_message(b"lost", addr2).await.unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), sender.wait_all_acked()).await.unwrap();
+    assert!(matches!(
+        result,
+        Err(rudpbase::RudpError::Connection(rudpbase::ConnectionError::MaxRetriesExceeded { addr })) if addr == addr2
+    ));
+}
+
+#[tokio::test]
+async fn test_ping_measures_round_trip() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let delayed = |transport| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        delay: rudpbase::Delay::Fixed(Duration::from_millis(25)),
+        ..rudpbase::SimConfig::default()
+    });
+    let mut sender = Rudpbase::with_transport(delayed(a), rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(delayed(b), rudpbase::RudpConfig::default()).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let (result, ()) = tokio::join!(
+        async {
+            let result = sender.ping(addr2).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                receiver.tick().await;
+                recv_now(&mut receiver).await;
+            }
+        },
+    );
+    let rtt = result.unwrap();
+    assert!(rtt >= Duration::from_millis(50), "rtt {:?}", rtt);
+    assert!(rtt < Duration::from_secs(1), "rtt {:?}", rtt);
+}
+
+#[tokio::test]
+async fn test_connect_primes_rtt_before_first_send() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let impairments = |transport, seed| rudpbase::SimTransport::new(transport, rudpbase::SimConfig {
+        loss: 0.2,
+        delay: rudpbase::Delay::Fixed(Duration::from_millis(15)),
+        seed: Some(seed),
+        ..rudpbase::SimConfig::default()
+    });
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_secs(2))
+        .with_keepalive(rudpbase::KeepAliveConfig {
+            ping_interval: Duration::from_millis(100),
+            ..rudpbase::KeepAliveConfig::default()
+        });
+    let mut client = Rudpbase::with_transport(impairments(a, 7), config.clone()).await.unwrap();
+    let mut server = Rudpbase::with_transport(impairments(b, 8), config).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let (result, ()) = tokio::join!(
+        async {
+            let result = client.connect(addr2).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                server.tick().await;
+                recv_now(&mut server).await;
+            }
+        },
+    );
+    let srtt = result.unwrap();
+    assert!(srtt >= Duration::from_millis(30), "srtt {:?}", srtt);
+    assert_eq!(client.connection_status(addr2), rudpbase::ConnectionStatus::Alive);
+    let rtt_stats = client.rtt_stats(addr2).unwrap();
+    assert!(rtt_stats.rtt_histogram.count() >= 3);
+    // The first data packet no longer waits out the 200ms initial RTO
+    assert!(rtt_stats.rto < rudpbase::config::DEFAULT_INITIAL_RTO, "rto {:?}", rtt_stats.rto);
+    assert!(client.congestion_info(addr2).is_some());
+}
+
+#[tokio::test]
+async fn test_connect_any_keeps_first_answering_address() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let unreachable: SocketAddr = "10.0.0.9:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut client = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut server = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let started = std::time::Instant::now();
+    let done = std::cell::Cell::new(false);
+    let (result, ()) = tokio::join!(
+        async {
+            let result = client.connect_any(&[unreachable, addr2]).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                server.tick().await;
+                recv_now(&mut server).await;
+            }
+        },
+    );
+    let (winner, _rtt) = result.unwrap();
+    assert_eq!(winner, addr2);
+    // The second address was tried once the first stayed silent for a while
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    assert_eq!(client.connection_status(addr2), rudpbase::ConnectionStatus::Alive);
+    assert!(client.rtt_stats(unreachable).is_none());
+}
+
+#[tokio::test]
+async fn test_connect_host_resolves_name() {
+    let mut server = Rudpbase::new("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let mut client = Rudpbase::new("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let (result, ()) = tokio::join!(
+        async {
+            let result = client.connect_host(&format!("localhost:{}", server_addr.port())).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                server.tick().await;
+                recv_now(&mut server).await;
+            }
+        },
+    );
+    // An IPv4 socket only tries the IPv4 addresses of the name
+    assert_eq!(result.unwrap().0, server_addr);
+    assert!(client.connect_host("localhost").await.is_err());
+}
+
+#[tokio::test]
+async fn test_ping_times_out_on_silent_peer() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, _silent) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let config = rudpbase::RudpConfig::new().with_keepalive(rudpbase::KeepAliveConfig {
+        ping_interval: Duration::from_millis(100),
+        ..rudpbase::KeepAliveConfig::default()
+    });
+    let mut sender = Rudpbase::with_transport(a, config).await.unwrap();
+
+    let started = std::time::Instant::now();
+    let result = timeout(Duration::from_secs(5), sender.ping(addr2)).await.unwrap();
+    assert!(matches!(result, Err(rudpbase::RudpError::Timeout)));
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_send_tracked_resolves_per_packet() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let config = rudpbase::RudpConfig::new()
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(200))
+        .with_initial_rto(Duration::from_millis(20));
+    let lossy = rudpbase::SimTransport::new(a, rudpbase::SimConfig {
+        loss: 0.3,
+        seed: Some(9),
+        ..rudpbase::SimConfig::default()
+    });
+    let mut sender = Rudpbase::with_transport(lossy, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, config).await.unwrap();
+
+    let mut handles = Vec::new();
+    for i in 0..5u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        handles.push(sender.send_tracked(buffer, addr2).await.unwrap());
+    }
+    let seqs: Vec<u32> = handles.iter().map(|handle| handle.seq()).collect();
+    assert_eq!(seqs, (0..5).collect::<Vec<u32>>());
+    assert!(handles.iter().all(|handle| handle.target() == addr2));
+
+    let done = std::cell::Cell::new(false);
+    let (results, ()) = tokio::join!(
+        async {
+            let mut results = Vec::new();
+            for handle in handles {
+                results.push(handle.await);
+            }
+            done.set(true);
+            results
+        },
+        async {
+            while !done.get() {
+                sender.tick().await;
+                let _ = recv_now(&mut sender).await;
+                receiver.tick().await;
+                let _ = recv_now(&mut receiver).await;
+            }
+        },
+    );
+    assert!(results.iter().all(Result::is_ok), "{:?}", results);
+}
+
+#[tokio::test]
+async fn test_send_tracked_reports_failed_delivery() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, _silent) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let config = rudpbase::RudpConfig::new()
+        .with_max_retries(2)
+        .with_rto_bounds(Duration::from_millis(20), Duration::from_millis(50))
+        .with_initial_rto(Duration::from_millis(20));
+    let mut sender = Rudpbase::with_transport(a, config).await.unwrap();
+
+    let make_buffer = |sender: &Rudpbase| {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[..4].copy_from_slice(b"lost");
+        buffer.set_data_len(4).unwrap();
+        buffer
+    };
+    let buffer = make_buffer(&sender);
+    let mut lost = sender.send_tracked(buffer, addr2).await.unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            tokio::select! {
+                result = &mut lost => break result,
+                _ = sender.tick() => {}
+            }
+            let _ = recv_now(&mut sender).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        result,
+        Err(rudpbase::RudpError::Connection(rudpbase::ConnectionError::MaxRetriesExceeded { addr })) if addr == addr2
+    ));
+
+    // Closing the instance settles handles that are still waiting
+    let buffer = make_buffer(&sender);
+    let pending = sender.send_tracked(buffer, addr2).await.unwrap();
+    sender.close().await;
+    assert!(matches!(
+        pending.await,
+        Err(rudpbase::RudpError::Connection(rudpbase::ConnectionError::Closed))
+    ));
+}
+
+#[tokio::test]
+async fn test_received_data_carries_sender_seq() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let mut sent = Vec::new();
+    for i in 0..3u8 {
+        let mut buffer = sender.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        sent.push((sender.send(buffer, addr2).await.unwrap(), i));
+    }
+    let slices = [std::io::IoSlice::new(b"vec"), std::io::IoSlice::new(b"tored")];
+    let vectored_seq = sender.send_vectored(&slices, addr2).await.unwrap();
+    assert_eq!(vectored_seq, 3);
+
+    let mut received = Vec::new();
+    for _ in 0..10 {
+        if let Some(data) = recv_now(&mut receiver).await {
+            let buffer = data.result.unwrap();
+            received.push((data.seq.unwrap(), buffer.data()[0]));
+        }
+    }
+    assert_eq!(received.len(), 4);
+    assert!(sent.iter().all(|entry| received.contains(entry)));
+    assert!(received.contains(&(vectored_seq, b'v')));
+}
+
+#[tokio::test]
+async fn test_send_with_deadline_abandons_retransmission() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, peer) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut rudp = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let clock = rudpbase::MockClock::new();
+    rudp.set_clock(clock.clone());
+
+    let handler = RecordingHandler::default();
+    rudp.set_event_handler(handler.clone());
+
+    let mut buffer = rudp.get_buffer().unwrap();
+    buffer.data_mut()[0] = 1;
+    buffer.set_data_len(1).unwrap();
+    let mut stale = rudp.send_with_deadline(buffer, addr2, clock.now() + Duration::from_millis(100)).await.unwrap();
+    let mut buffer = rudp.get_buffer().unwrap();
+    buffer.data_mut()[0] = 2;
+    buffer.set_data_len(1).unwrap();
+    let mut tracked = rudp.send_tracked(buffer, addr2).await.unwrap();
+
+    clock.advance(Duration::from_millis(99));
+    rudp.tick().await;
+    assert!(tokio::time::timeout(Duration::from_millis(1), &mut stale).await.is_err());
+
+    // The deadline passes before the first 200ms RTO, so the packet is never retransmitted
+    clock.advance(Duration::from_millis(1));
+    rudp.tick().await;
+    assert!(matches!(stale.await, Err(rudpbase::RudpError::Timeout)));
+    assert_eq!(*handler.delivery_failures.lock().unwrap(), vec![(addr2, 0)]);
+    assert_eq!(rudp.global_stats().unwrap().pending_packets, 1);
+
+    clock.advance(Duration::from_millis(100));
+    rudp.tick().await;
+    assert_eq!(peer.queued(), 3);
+    assert!(tokio::time::timeout(Duration::from_millis(1), &mut tracked).await.is_err());
+}
+
+#[tokio::test]
+async fn test_send_to_all_delivers_to_each_peer() {
+    let server_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let config = rudpbase::RudpConfig::new().with_initial_cwnd(1).with_max_payload_size(64);
+    let mut server = Rudpbase::with_transport(network.bind(server_addr).unwrap(), config).await.unwrap();
+    let mut players = Vec::new();
+    let mut addrs = Vec::new();
+    for i in 2..5 {
+        let addr: SocketAddr = format!("10.0.0.{}:1000", i).parse().unwrap();
+        players.push(Rudpbase::with_transport(network.bind(addr).unwrap(), rudpbase::RudpConfig::default()).await.unwrap());
+        addrs.push(addr);
+    }
+
+    // The last player's congestion window is already full
+    let mut buffer = server.get_buffer().unwrap();
+    buffer.set_data_len(1).unwrap();
+    server.send(buffer, addrs[2]).await.unwrap();
+
+    let mut buffer = server.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"state");
+    buffer.set_data_len(5).unwrap();
+    let results = server.send_to_all(buffer, &addrs).await.unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap(), &0);
+    assert_eq!(results[1].as_ref().unwrap(), &0);
+    // Queued behind the full window like `send`
+    assert_eq!(results[2].as_ref().unwrap(), &1);
+
+    for player in &mut players[..2] {
+        let received = recv_now(player).await.unwrap();
+        assert_eq!(received.from, server_addr);
+        assert_eq!(received.seq, Some(0));
+        assert_eq!(received.result.unwrap().data(), b"state");
+    }
+    assert_eq!(recv_now(&mut players[2]).await.unwrap().seq, Some(0));
+    // The acknowledgement opens the window for the queued packet
+    assert!(recv_now(&mut server).await.is_none());
+    let received = recv_now(&mut players[2]).await.unwrap();
+    assert_eq!(received.seq, Some(1));
+    assert_eq!(received.result.unwrap().data(), b"state");
+    assert!(server.send_to_all(server.get_buffer().unwrap(), &[]).await.unwrap().is_empty());
+
+    let mut too_large = server.get_buffer().unwrap();
+    too_large.set_data_len(65).unwrap();
+    let results = server.send_to_all(too_large, &addrs).await.unwrap();
+    assert!(results.iter().all(|result| matches!(result, Err(rudpbase::RudpError::BufferTooLarge { size: 65, max: 64 }))));
+}
+
+#[tokio::test]
+async fn test_send_unreliable_is_not_acknowledged() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..8].copy_from_slice(b"discover");
+    buffer.set_data_len(8).unwrap();
+    sender.send_unreliable(buffer, addr2).await.unwrap();
+
+    let received = recv_now(&mut receiver).await.unwrap();
+    assert_eq!(received.from, addr1);
+    assert_eq!(received.seq, None);
+    assert_eq!(received.result.unwrap().data(), b"discover");
+
+    // Nothing is kept for retransmission and the receiver sends no acknowledgment
+    assert_eq!(sender.global_stats().unwrap().pending_packets, 0);
+    receiver.tick().await;
+    assert!(recv_now(&mut sender).await.is_none());
+    assert!(receiver.get_stats(addr1).is_none());
+
+    // Multicast needs an OS socket
+    assert!(matches!(
+        sender.join_multicast_v4("239.255.0.1".parse().unwrap(), std::net::Ipv4Addr::UNSPECIFIED),
+        Err(rudpbase::RudpError::InvalidConfig { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_multicast_group_receives_unreliable_datagrams() {
+    let group: std::net::Ipv4Addr = "239.255.42.99".parse().unwrap();
+    let mut member = Rudpbase::new("0.0.0.0:9071".parse().unwrap()).await.unwrap();
+    member.join_multicast_v4(group, std::net::Ipv4Addr::LOCALHOST).unwrap();
+    let mut announcer = Rudpbase::new("127.0.0.1:9072".parse().unwrap()).await.unwrap();
+
+    let mut buffer = announcer.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"hello");
+    buffer.set_data_len(5).unwrap();
+    announcer.send_unreliable(buffer, SocketAddr::new(group.into(), 9071)).await.unwrap();
+
+    let mut received = None;
+    for _ in 0..50 {
+        if let Some(data) = recv_now(&mut member).await {
+            received = Some(data);
+            break;
+        }
+    }
+    let received = received.expect("multicast datagram not received");
+    assert_eq!(received.seq, None);
+    assert_eq!(received.result.unwrap().data(), b"hello");
+    member.leave_multicast_v4(group, std::net::Ipv4Addr::LOCALHOST).unwrap();
+}
+
+#[tokio::test]
+async fn test_relay_forwards_between_registered_peers() {
+    use rudpbase::relay::{Relay, RelayTransport};
+
+    let network = rudpbase::LoopbackNetwork::new();
+    let relay_addr: SocketAddr = "10.0.0.9:3478".parse().unwrap();
+    let mut relay = Relay::with_transport(network.bind(relay_addr).unwrap());
+    let relay_task = tokio::spawn(async move {
+        let _ = relay.run().await;
+    });
+
+    // The addresses the peers use for each other are unreachable; only the relay connects them
+    let alice_candidate: SocketAddr = "192.0.2.1:9000".parse().unwrap();
+    let bob_candidate: SocketAddr = "192.0.2.2:9000".parse().unwrap();
+    let alice_transport = RelayTransport::new(network.bind("10.0.0.1:9000".parse().unwrap()).unwrap(), 1);
+    let bob_transport = RelayTransport::new(network.bind("10.0.0.2:9000".parse().unwrap()).unwrap(), 2);
+    let alice_routes = alice_transport.routes();
+    let bob_routes = bob_transport.routes();
+    alice_routes.add(bob_candidate, relay_addr, 2);
+    bob_routes.add(alice_candidate, relay_addr, 1);
+    alice_routes.register(relay_addr).await.unwrap();
+    bob_routes.register(relay_addr).await.unwrap();
+    assert_eq!(alice_routes.route(bob_candidate), Some((relay_addr, 2)));
+
+    let mut alice = Rudpbase::with_transport(alice_transport, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut bob = Rudpbase::with_transport(bob_transport, rudpbase::RudpConfig::default()).await.unwrap();
+    tokio::task::yield_now().await;
+
+    let mut buffer = alice.get_buffer().unwrap();
+    buffer.data_mut()[..7].copy_from_slice(b"relayed");
+    buffer.set_data_len(7).unwrap();
+    let seq = alice.send(buffer, bob_candidate).await.unwrap();
+
+    let mut received = None;
+    for _ in 0..50 {
+        tokio::task::yield_now().await;
+        if let Some(data) = recv_now(&mut bob).await {
+            received = Some(data);
+            break;
+        }
+    }
+    let received = received.expect("relayed packet not received");
+    assert_eq!(received.from, alice_candidate);
+    assert_eq!(received.seq, Some(seq));
+    assert_eq!(received.result.unwrap().data(), b"relayed");
+
+    // The acknowledgment travels back through the relay as well
+    bob.tick().await;
+    for _ in 0..50 {
+        tokio::task::yield_now().await;
+        recv_now(&mut alice).await;
+        if alice.global_stats().unwrap().pending_packets == 0 {
+            break;
+        }
+    }
+    assert_eq!(alice.global_stats().unwrap().pending_packets, 0);
+    relay_task.abort();
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn test_typed_channel_roundtrip() {
+    use rudpbase::typed::TypedChannel;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Command {
+        Move { x: i32, y: i32 },
+        Say(String),
+    }
+
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let sender = Rudpbase::with_transport(network.bind(addr1).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let receiver = Rudpbase::with_transport(network.bind(addr2).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let mut sender = TypedChannel::<Command>::new(sender);
+    let mut receiver = TypedChannel::<Command>::new(receiver);
+
+    let commands = [Command::Move { x: -3, y: 12 }, Command::Say("hello".to_string())];
+    let mut seqs = Vec::new();
+    for command in &commands {
+        seqs.push(sender.send(command, addr2).await.unwrap());
+    }
+    for (command, seq) in commands.iter().zip(seqs) {
+        let received = receiver.recv().await;
+        assert_eq!(received.from, addr1);
+        assert_eq!(received.seq, Some(seq));
+        assert_eq!(&received.result.unwrap(), command);
+    }
+
+    // A raw packet that is not a Command surfaces as a decoding error
+    let mut buffer = sender.get_ref().get_buffer().unwrap();
+    buffer.data_mut()[0] = 0xff;
+    buffer.set_data_len(1).unwrap();
+    sender.get_mut().send(buffer, addr2).await.unwrap();
+    let received = receiver.recv().await;
+    assert!(matches!(received.result, Err(rudpbase::RudpError::Serialization { .. })));
+
+    // Values that do not fit in one buffer are rejected before sending
+    let oversized = Command::Say("x".repeat(2000));
+    assert!(matches!(sender.send(&oversized, addr2).await, Err(rudpbase::RudpError::BufferTooLarge { .. })));
+}
+
+#[cfg(feature = "rpc")]
+#[tokio::test]
+async fn test_rpc_concurrent_calls() {
+    use rudpbase::rpc::{self, RpcOptions};
+
+    let server_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let client_addr: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let server = Rudpbase::with_transport(network.bind(server_addr).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let client = Rudpbase::with_transport(network.bind(client_addr).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+
+    // Echo requests back reversed, reject "drop" and leave "ignore" unanswered
+    let (_, mut requests) = rpc::spawn(server, RpcOptions::default());
+    let server_task = tokio::spawn(async move {
+        let mut ignored = Vec::new();
+        while let Some(request) = requests.next().await {
+            match &request.data[..] {
+                b"drop" => drop(request),
+                b"ignore" => ignored.push(request),
+                data => {
+                    let reply: Vec<u8> = data.iter().rev().copied().collect();
+                    request.respond(&reply);
+                }
+            }
+        }
+    });
+    let (client, _) = rpc::spawn(client, RpcOptions::default());
+
+    // Calls complete independently, in any order
+    let large: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+    let (a, b, c) = tokio::join!(
+        client.call(server_addr, b"abc"),
+        client.call(server_addr, &large),
+        client.call(server_addr, b"xyz"),
+    );
+    assert_eq!(a.unwrap(), b"cba");
+    assert_eq!(b.unwrap(), large.iter().rev().copied().collect::<Vec<u8>>());
+    assert_eq!(c.unwrap(), b"zyx");
+
+    assert!(matches!(client.call(server_addr, b"drop").await, Err(rudpbase::RudpError::Protocol { .. })));
+    let started = std::time::Instant::now();
+    let result = client.call_with_timeout(server_addr, b"ignore", Duration::from_millis(100)).await;
+    assert!(matches!(result, Err(rudpbase::RudpError::Timeout)));
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    // The endpoint still serves calls after a timeout
+    assert_eq!(client.call(server_addr, b"ok").await.unwrap(), b"ko");
+    server_task.abort();
+}
+
+#[cfg(feature = "task")]
+#[tokio::test]
+async fn test_receive_task_acknowledges_before_data_is_taken() {
+    use rudpbase::task::{self, TaskOptions};
+
+    let server_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let client_addr: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let server = Rudpbase::with_transport(network.bind(server_addr).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let mut client = Rudpbase::with_transport(network.bind(client_addr).unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let (sender, mut receiver) = task::spawn(server, TaskOptions { channel_capacity: 2 });
+
+    // More packets than the channel holds are all acknowledged while nobody receives
+    for i in 0..5u8 {
+        let mut buffer = client.get_buffer().unwrap();
+        buffer.data_mut()[0] = i;
+        buffer.set_data_len(1).unwrap();
+        client.send(buffer, server_addr).await.unwrap();
+    }
+    timeout(Duration::from_secs(2), client.wait_acked(server_addr)).await.unwrap().unwrap();
+    for i in 0..5u8 {
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.from, client_addr);
+        assert_eq!(received.result.unwrap().data(), [i]);
+    }
+
+    // Sends and messages go both ways
+    sender.send(b"reply", client_addr).await.unwrap();
+    let reply = client.recv_timeout(Duration::from_secs(1)).await.unwrap();
+    assert_eq!(reply.result.unwrap().data(), b"reply");
+    let large = vec![7u8; 5000];
+    client.send_message(&large, server_addr).await.unwrap();
+    let message = timeout(Duration::from_secs(2), receiver.recv_message()).await.unwrap().unwrap();
+    assert_eq!(message.data, large);
+
+    // The task keeps running for the sender after the receiver is dropped
+    drop(receiver);
+    sender.send(b"still running", client_addr).await.unwrap();
+    let later = client.recv_timeout(Duration::from_secs(1)).await.unwrap();
+    assert_eq!(later.result.unwrap().data(), b"still running");
+}
+
+#[cfg(feature = "pubsub")]
+#[tokio::test]
+async fn test_pubsub_fan_out() {
+    use rudpbase::pubsub::PubSub;
+
+    let publisher_addr: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let addr3: SocketAddr = "10.0.0.3:1000".parse().unwrap();
+    let network = rudpbase::LoopbackNetwork::new();
+    let bind = |addr| Rudpbase::with_transport(network.bind(addr).unwrap(), rudpbase::RudpConfig::default());
+    let mut publisher = PubSub::new(bind(publisher_addr).await.unwrap());
+    let mut first = PubSub::new(bind(addr2).await.unwrap());
+    let mut second = PubSub::new(bind(addr3).await.unwrap());
+
+    first.subscribe(publisher_addr, "news").await.unwrap();
+    first.subscribe(publisher_addr, "weather").await.unwrap();
+    second.subscribe(publisher_addr, "news").await.unwrap();
+    for _ in 0..3 {
+        assert!(publisher.recv().await.is_none());
+    }
+    let mut news: Vec<SocketAddr> = publisher.subscribers("news").collect();
+    news.sort();
+    assert_eq!(news, vec![addr2, addr3]);
+
+    // Each publication reaches exactly the subscribers of its topic
+    assert_eq!(publisher.publish("news", b"headline").await.unwrap(), 2);
+    assert_eq!(publisher.publish("weather", b"sunny").await.unwrap(), 1);
+    assert_eq!(publisher.publish("sports", b"score").await.unwrap(), 0);
+    let expected = |topic: &str, data: &[u8]| rudpbase::pubsub::Publication { from: publisher_addr, topic: topic.to_string(), data: data.to_vec() };
+    assert_eq!(first.recv().await, Some(expected("news", b"headline")));
+    assert_eq!(first.recv().await, Some(expected("weather", b"sunny")));
+    assert_eq!(second.recv().await, Some(expected("news", b"headline")));
+    assert_eq!(second.recv().await, None);
+
+    first.unsubscribe(publisher_addr, "weather").await.unwrap();
+    assert!(publisher.recv().await.is_none());
+    assert_eq!(publisher.publish("weather", b"rain").await.unwrap(), 0);
+
+    // A subscriber that closes loses its subscriptions
+    second.get_mut().close().await;
+    assert!(publisher.recv().await.is_none());
+    publisher.tick().await;
+    assert_eq!(publisher.subscribers("news").collect::<Vec<_>>(), vec![addr2]);
+    assert_eq!(publisher.publish("news", b"update").await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_rebind_keeps_connection() {
+    let client_addr: SocketAddr = "127.0.0.1:9073".parse().unwrap();
+    let server_addr: SocketAddr = "127.0.0.1:9074".parse().unwrap();
+    let mut client = Rudpbase::new(client_addr).await.unwrap();
+    let mut server = Rudpbase::new(server_addr).await.unwrap();
+
+    let done = std::cell::Cell::new(false);
+    let (result, ()) = tokio::join!(
+        async {
+            let result = client.connect(server_addr).await;
+            done.set(true);
+            result
+        },
+        async {
+            while !done.get() {
+                server.tick().await;
+                recv_now(&mut server).await;
+            }
+        },
+    );
+    result.unwrap();
+
+    let moved = client.rebind("127.0.0.1:9075".parse().unwrap()).await.unwrap();
+    assert_eq!(moved, "127.0.0.1:9075".parse::<SocketAddr>().unwrap());
+    assert_eq!(client.local_addr().unwrap(), moved);
+
+    let mut buffer = client.get_buffer().unwrap();
+    buffer.data_mut()[..7].copy_from_slice(b"request");
+    buffer.set_data_len(7).unwrap();
+    client.send(buffer, server_addr).await.unwrap();
+    let mut from = None;
+    for _ in 0..200 {
+        server.tick().await;
+        if let Some(received) = recv_now(&mut server).await {
+            assert_eq!(received.result.unwrap().data(), b"request");
+            from = Some(received.from);
+            break;
+        }
+    }
+    assert_eq!(from, Some(moved));
+
+    // The server answers the new address with the state of the old one
+    let mut buffer = server.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"reply");
+    buffer.set_data_len(5).unwrap();
+    server.send(buffer, moved).await.unwrap();
+    let mut replied = false;
+    for _ in 0..200 {
+        client.tick().await;
+        server.tick().await;
+        if let Some(received) = recv_now(&mut client).await {
+            assert_eq!(received.from, server_addr);
+            assert_eq!(received.result.unwrap().data(), b"reply");
+            replied = true;
+            break;
+        }
+    }
+    assert!(replied, "reply was not received");
+    assert!(server.get_stats(client_addr).is_none());
+    assert!(server.wait_acked(moved).await.is_ok());
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_tcp_transport_carries_packets() {
+    use rudpbase::tcp::TcpTransport;
+
+    let addr1: SocketAddr = "127.0.0.1:9076".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9077".parse().unwrap();
+    let mut alice = Rudpbase::with_transport(TcpTransport::bind(addr1, 16).await.unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let mut bob = Rudpbase::with_transport(TcpTransport::bind(addr2, 16).await.unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+
+    let message = vec![7u8; 5000];
+    alice.send_message(&message, addr2).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            alice.tick().await;
+            bob.tick().await;
+            if let Some(message) = bob.recv_message().await {
+                return message;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    // The sender is known by its listening address, not the connection's source port
+    assert_eq!(received.from, addr1);
+    assert_eq!(received.data, message);
+
+    // Acknowledgments travel back over the connection bob accepted
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while alice.pending_count(addr2) > 0 {
+            bob.tick().await;
+            alice.tick().await;
+            recv_now(&mut alice).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "tcp")]
+#[tokio::test]
+async fn test_fallback_reaches_tcp_only_peer() {
+    use rudpbase::tcp::{FallbackConfig, FallbackTransport, TcpTransport};
+
+    let client_addr: SocketAddr = "127.0.0.1:9078".parse().unwrap();
+    let server_addr: SocketAddr = "127.0.0.1:9079".parse().unwrap();
+    // The server is only reachable over TCP, as if UDP were blocked
+    let mut server = Rudpbase::with_transport(TcpTransport::bind(server_addr, 16).await.unwrap(), rudpbase::RudpConfig::default()).await.unwrap();
+    let fallback = FallbackTransport::bind(client_addr, FallbackConfig {
+        udp_timeout: Duration::from_millis(100),
+        ..FallbackConfig::default()
+    })
+    .await
+    .unwrap();
+    let config = rudpbase::RudpConfig::new().with_rto_bounds(Duration::from_millis(50), Duration::from_secs(1));
+    let mut client = Rudpbase::with_transport(fallback, config).await.unwrap();
+
+    let mut buffer = client.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"hello");
+    buffer.set_data_len(5).unwrap();
+    client.send(buffer, server_addr).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            client.tick().await;
+            server.tick().await;
+            if let Some(received) = recv_now(&mut server).await {
+                return received;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received.from, client_addr);
+    assert_eq!(received.result.unwrap().data(), b"hello");
+
+    // The answer comes back over the connection the client opened
+    let mut buffer = server.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"world");
+    buffer.set_data_len(5).unwrap();
+    server.send(buffer, client_addr).await.unwrap();
+    let reply = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            client.tick().await;
+            server.tick().await;
+            if let Some(received) = recv_now(&mut client).await {
+                return received;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(reply.from, server_addr);
+    assert_eq!(reply.result.unwrap().data(), b"world");
+}
+
+#[tokio::test]
+async fn test_send_after_waits_for_delay() {
+    let addr1: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    let addr2: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+    let (a, b) = rudpbase::LoopbackTransport::pair(addr1, addr2);
+    let mut sender = Rudpbase::with_transport(a, rudpbase::RudpConfig::default()).await.unwrap();
+    let mut receiver = Rudpbase::with_transport(b, rudpbase::RudpConfig::default()).await.unwrap();
+
+    let start = std::time::Instant::now();
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.data_mut()[..5].copy_from_slice(b"later");
+    buffer.set_data_len(5).unwrap();
+    sender.send_after(buffer, addr2, Duration::from_millis(50)).unwrap();
+    assert_eq!(sender.pending_count(addr2), 1);
+
+    let received = loop {
+        let _ = recv_now(&mut sender).await;
+        if let Some(received) = recv_now(&mut receiver).await {
+            break received;
+        }
+        assert!(start.elapsed() < Duration::from_secs(2), "scheduled data never arrived");
+    };
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(received.result.unwrap().data(), b"later");
+}
+
+#[tokio::test]
+async fn test_full_size_payload_with_extensions_over_udp() {
+    let addr1: SocketAddr = "127.0.0.1:9080".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9081".parse().unwrap();
+    let config = rudpbase::RudpConfig::new().with_timestamps(true).with_payload_checksum(true);
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+
+    // The extension section comes out of the payload, not on top of the datagram
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.set_data_len(rudpbase::buffer_pool::MAX_PAYLOAD_SIZE).unwrap();
+    match sender.send(buffer, addr2).await {
+        Err(rudpbase::RudpError::BufferTooLarge { max, .. }) => assert_eq!(max, sender.max_payload_for(addr2)),
+        other => panic!("full-size payload accepted: {:?}", other.map(|_| ())),
+    }
+
+    let payload: Vec<u8> = (0..sender.max_payload_for(addr2)).map(|i| i as u8).collect();
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.write_bytes(&payload).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let _ = recv_now(&mut sender).await;
+            if let Some(received) = recv_now(&mut receiver).await {
+                return received;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received.result.unwrap().data(), &payload[..]);
+}
+
+#[tokio::test]
+async fn test_largest_padding_bucket_over_udp() {
+    let addr1: SocketAddr = "127.0.0.1:9082".parse().unwrap();
+    let addr2: SocketAddr = "127.0.0.1:9083".parse().unwrap();
+
+    // Receivers would truncate datagrams padded beyond what they read
+    let oversized = rudpbase::RudpConfig::new().with_padding(Some(rudpbase::PaddingConfig::new(vec![2048])));
+    assert!(matches!(Rudpbase::with_config(addr1, oversized).await, Err(rudpbase::RudpError::InvalidConfig { .. })));
+
+    let largest = rudpbase::buffer_pool::DEFAULT_BUFFER_SIZE;
+    let config = rudpbase::RudpConfig::new().with_padding(Some(rudpbase::PaddingConfig::new(vec![largest])));
+    let mut sender = Rudpbase::with_config(addr1, config.clone()).await.unwrap();
+    let mut receiver = Rudpbase::with_config(addr2, config).await.unwrap();
+    let mut buffer = sender.get_buffer().unwrap();
+    buffer.write_bytes(&[7; 100]).unwrap();
+    sender.send(buffer, addr2).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let _ = recv_now(&mut sender).await;
+            if let Some(received) = recv_now(&mut receiver).await {
+                return received;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received.result.unwrap().data(), &[7; 100][..]);
+}
@@ -0,0 +1,85 @@
+//! Heap allocations made by `RudpCore::handle_timeout`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rudpbase::{RudpConfig, RudpCore};
+
+/// Counts the allocations of the current thread, so tests running in parallel do
+/// not disturb each other
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn send(from: &mut RudpCore, data: &[u8], target: SocketAddr, now: Instant) {
+    let mut buffer = from.get_buffer_for(data.len()).unwrap();
+    buffer.data_mut()[..data.len()].copy_from_slice(data);
+    buffer.set_data_len(data.len()).unwrap();
+    from.send(buffer, target, now).unwrap();
+}
+
+fn deliver(from: &mut RudpCore, from_addr: SocketAddr, to: &mut RudpCore, now: Instant) {
+    while let Some(transmit) = from.poll_transmit() {
+        to.handle_datagram(&transmit.contents, from_addr, now);
+    }
+    while to.poll_received().is_some() {}
+}
+
+#[test]
+fn test_ticks_reuse_ack_scratch_space() {
+    let a_addr: SocketAddr = "127.0.0.1:10001".parse().unwrap();
+    let b_addr: SocketAddr = "127.0.0.1:10002".parse().unwrap();
+    let now = Instant::now();
+    let mut a = RudpCore::new(RudpConfig::default()).unwrap();
+    let mut b = RudpCore::new(RudpConfig::default()).unwrap();
+
+    // Warm up: the first acknowledgments size the scratch space and leave their
+    // buffers in the pool
+    for round in 0..3u32 {
+        let at = now + Duration::from_millis(round.into());
+        send(&mut a, b"warm up", b_addr, at);
+        deliver(&mut a, a_addr, &mut b, at);
+        b.handle_timeout(at);
+        deliver(&mut b, b_addr, &mut a, at);
+    }
+
+    // An idle tick allocates nothing
+    let idle = now + Duration::from_millis(5);
+    assert_eq!(allocations_during(|| b.handle_timeout(idle)), 0);
+    assert!(b.poll_transmit().is_none());
+
+    // A tick acknowledging data writes the acknowledgment into a pooled buffer
+    send(&mut a, b"data", b_addr, idle);
+    deliver(&mut a, a_addr, &mut b, idle);
+    assert_eq!(allocations_during(|| b.handle_timeout(idle)), 0);
+    assert!(b.poll_transmit().is_some());
+}
@@ -0,0 +1,118 @@
+//! Multi-node tests on turmoil's simulated network and clock
+
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rudpbase::RudpConfig;
+
+const PORT: u16 = 9000;
+
+fn any_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT))
+}
+
+/// A server recording the first four bytes of every payload it receives
+fn serve(sim: &mut turmoil::Sim<'_>, name: &str, received: Arc<Mutex<BTreeSet<u32>>>) {
+    sim.host(name, move || {
+        let received = Arc::clone(&received);
+        async move {
+            let mut rudp = rudpbase::turmoil::bind(any_addr(), RudpConfig::default()).await?;
+            loop {
+                if let Ok(buffer) = rudp.recv().await.result {
+                    received.lock().unwrap().insert(u32::from_be_bytes(buffer.data()[..4].try_into()?));
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn test_retransmission_across_partition() -> turmoil::Result {
+    let mut sim = turmoil::Builder::new().simulation_duration(Duration::from_secs(60)).build();
+    let received = Arc::new(Mutex::new(BTreeSet::new()));
+    serve(&mut sim, "server", Arc::clone(&received));
+
+    sim.client("client", async {
+        let mut rudp = rudpbase::turmoil::bind(any_addr(), RudpConfig::default()).await?;
+        let server = SocketAddr::new(turmoil::lookup("server"), PORT);
+        for i in 0..20u32 {
+            let mut buffer = rudp.get_buffer()?;
+            buffer.data_mut()[..4].copy_from_slice(&i.to_be_bytes());
+            buffer.set_data_len(4)?;
+            rudp.send(buffer, server).await?;
+            rudp.recv_timeout(Duration::from_millis(100)).await;
+        }
+        while rudp.pending_count(server) > 0 {
+            rudp.recv_timeout(Duration::from_millis(10)).await;
+        }
+        assert!(rudp.get_stats(server).unwrap().retransmissions > 0);
+        Ok(())
+    });
+
+    // Cut the link while the client is sending, then restore it
+    let mut partitioned = false;
+    while !sim.step()? {
+        let elapsed = sim.elapsed();
+        if !partitioned && elapsed >= Duration::from_millis(500) && elapsed < Duration::from_millis(1500) {
+            sim.partition("client", "server");
+            partitioned = true;
+        } else if partitioned && elapsed >= Duration::from_millis(1500) {
+            sim.repair("client", "server");
+            partitioned = false;
+        }
+    }
+    assert_eq!(received.lock().unwrap().len(), 20);
+    Ok(())
+}
+
+#[test]
+fn test_failover_to_second_server() -> turmoil::Result {
+    let mut sim = turmoil::Builder::new().simulation_duration(Duration::from_secs(60)).build();
+    let primary = Arc::new(Mutex::new(BTreeSet::new()));
+    let backup = Arc::new(Mutex::new(BTreeSet::new()));
+    serve(&mut sim, "primary", Arc::clone(&primary));
+    serve(&mut sim, "backup", Arc::clone(&backup));
+
+    // Packets the primary never acknowledges are given up on and resent to the backup
+    sim.client("client", async {
+        let config = RudpConfig::default().with_max_retries(3);
+        let mut rudp = rudpbase::turmoil::bind(any_addr(), config).await?;
+        let primary = SocketAddr::new(turmoil::lookup("primary"), PORT);
+        let backup = SocketAddr::new(turmoil::lookup("backup"), PORT);
+        let mut handles = Vec::new();
+        for i in 0..5u32 {
+            let mut buffer = rudp.get_buffer()?;
+            buffer.data_mut()[..4].copy_from_slice(&i.to_be_bytes());
+            buffer.set_data_len(4)?;
+            handles.push((i, rudp.send_tracked(buffer, primary).await?));
+        }
+        let mut failed = Vec::new();
+        while failed.len() < handles.len() {
+            rudp.recv_timeout(Duration::from_millis(10)).await;
+            for (i, handle) in &mut handles {
+                if let Some(result) = handle.try_result() {
+                    assert!(result.is_err());
+                    failed.push(*i);
+                }
+            }
+        }
+        for i in failed {
+            let mut buffer = rudp.get_buffer()?;
+            buffer.data_mut()[..4].copy_from_slice(&i.to_be_bytes());
+            buffer.set_data_len(4)?;
+            rudp.send(buffer, backup).await?;
+        }
+        while rudp.pending_count(backup) > 0 {
+            rudp.recv_timeout(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    });
+    sim.partition("client", "primary");
+
+    sim.run()?;
+    assert!(primary.lock().unwrap().is_empty());
+    assert_eq!(backup.lock().unwrap().len(), 5);
+    Ok(())
+}